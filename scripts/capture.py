@@ -154,7 +154,9 @@ CREATE TABLE IF NOT EXISTS transactions (
     gas_used     INTEGER NOT NULL,
     gas_price    TEXT    NOT NULL,
     input        BLOB    NOT NULL,
-    status       INTEGER NOT NULL
+    status       INTEGER NOT NULL,
+    logs_bloom   BLOB,
+    nonce        INTEGER
 );
 CREATE INDEX IF NOT EXISTS idx_tx_block ON transactions(block_number);
 CREATE INDEX IF NOT EXISTS idx_tx_from  ON transactions(from_addr);
@@ -269,6 +271,9 @@ def write_block(conn: sqlite3.Connection, block, receipts: list, traces: list):
 
             gas_used = hex_to_int(receipt.get("gasUsed", 0))
             status   = hex_to_int(receipt.get("status", 1))
+            bloom    = receipt.get("logsBloom")
+            if isinstance(bloom, str):
+                bloom = bytes.fromhex(bloom.removeprefix("0x"))
 
             gas_price = hex_to_int(tx.get("effectiveGasPrice") or tx.get("gasPrice") or 0)
 
@@ -289,8 +294,8 @@ def write_block(conn: sqlite3.Connection, block, receipts: list, traces: list):
             conn.execute(
                 "INSERT OR IGNORE INTO transactions "
                 "(hash, block_number, tx_index, from_addr, to_addr, value, "
-                " gas_used, gas_price, input, status) "
-                "VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                " gas_used, gas_price, input, status, logs_bloom, nonce) "
+                "VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
                 (
                     tx_hash,
                     block_number,
@@ -302,6 +307,8 @@ def write_block(conn: sqlite3.Connection, block, receipts: list, traces: list):
                     str(gas_price),
                     input_data,
                     status,
+                    bytes(bloom) if bloom is not None else None,
+                    hex_to_int(tx["nonce"]) if "nonce" in tx else None,
                 ),
             )
 
@@ -359,7 +366,7 @@ def export_parquet(db_path: str, out_dir: Path):
         "address", "topic0", "topic1", "topic2", "topic3",
         "tx_hash", "trace_json",
     }
-    BINARY_COLS = {"input", "data"}
+    BINARY_COLS = {"input", "data", "logs_bloom"}
     # Everything else is int64
 
     conn = sqlite3.connect(db_path)
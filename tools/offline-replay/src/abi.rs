@@ -0,0 +1,86 @@
+//! Contract ABIs given on the command line (`--abi`), to name what the
+//! built-in signature lists don't: a JSON file, or a directory searched for
+//! them, each a plain ABI array or a Foundry or Hardhat artifact with an
+//! `abi` key. Only functions and errors are read.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use eyre::{Context, Result};
+use serde_json::Value;
+
+use crate::decode;
+
+/// A function or error of an ABI.
+pub struct Entry {
+    pub name: String,
+    /// Parameter types as a signature lists them, tuples spelled out.
+    pub inputs: Vec<String>,
+}
+
+impl Entry {
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.inputs.join(","))
+    }
+
+    /// The 4-byte selector, as 0x-hex.
+    pub fn selector(&self) -> String {
+        decode::topic(&self.signature())[..10].to_string()
+    }
+}
+
+/// The entries of kind `kind` ("function" or "error") in the ABIs of
+/// `paths`, by selector.
+pub fn entries(paths: &[impl AsRef<Path>], kind: &str) -> Result<HashMap<String, Entry>> {
+    let mut found = HashMap::new();
+    for path in paths {
+        read(path.as_ref(), kind, &mut found)?;
+    }
+    Ok(found)
+}
+
+fn read(path: &Path, kind: &str, found: &mut HashMap<String, Entry>) -> Result<()> {
+    if path.is_dir() {
+        let entries =
+            std::fs::read_dir(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() || path.extension().is_some_and(|e| e == "json") {
+                read(&path, kind, found)?;
+            }
+        }
+        return Ok(());
+    }
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    let json: Value =
+        serde_json::from_str(&text).with_context(|| format!("Bad JSON in {}", path.display()))?;
+    let abi = match &json {
+        Value::Array(items) => items.as_slice(),
+        _ => json["abi"].as_array().map(Vec::as_slice).unwrap_or_default(),
+    };
+    for item in abi.iter().filter(|i| i["type"] == kind) {
+        let Some(name) = item["name"].as_str() else { continue };
+        let inputs = item["inputs"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let entry = Entry { name: name.to_string(), inputs: types(inputs) };
+        found.insert(entry.selector(), entry);
+    }
+    Ok(())
+}
+
+fn types(params: &[Value]) -> Vec<String> {
+    params
+        .iter()
+        .map(|p| {
+            let ty = p["type"].as_str().unwrap_or_default();
+            match ty.strip_prefix("tuple") {
+                Some(dims) => {
+                    let components =
+                        p["components"].as_array().map(Vec::as_slice).unwrap_or_default();
+                    format!("({}){dims}", types(components).join(","))
+                }
+                None => ty.to_string(),
+            }
+        })
+        .collect()
+}
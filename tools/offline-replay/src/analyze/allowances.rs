@@ -0,0 +1,179 @@
+//! Outstanding ERC-20 allowances per (token, owner, spender) at a block,
+//! from `approvals`, into an `allowances` table.
+//!
+//! An allowance is the amount of the last Approval logged for it at or
+//! before `--at-block`; those set back to zero are left out. `unlimited`
+//! marks type(uint256).max, which most tokens never decrease. Spending an
+//! allowance with transferFrom only shows up where the token logs an
+//! Approval for it (OpenZeppelin before 5.0 does; DAI doesn't), so an
+//! allowance may be less than its row says, never more.
+//!
+//! Rows are keyed by (at_block, token, owner, spender); a rerun replaces the
+//! rows of its block. `--spender` answers "who still has approvals to the
+//! exploited contract".
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use alloy_primitives::U256;
+use clap::Args;
+use eyre::Result;
+use rusqlite::{params, Connection};
+use tracing::info;
+
+use crate::decode;
+use crate::normalize::{read_amount, Normalize};
+use crate::open_existing;
+
+const ALLOWANCES_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS allowances (
+    at_block       INTEGER NOT NULL,
+    token          TEXT    NOT NULL,
+    owner          TEXT    NOT NULL,
+    spender        TEXT    NOT NULL,
+    amount         TEXT    NOT NULL,
+    unlimited      INTEGER NOT NULL,
+    approved_block INTEGER NOT NULL,
+    tx_hash        TEXT    NOT NULL,
+    PRIMARY KEY (at_block, token, owner, spender)
+);
+CREATE INDEX IF NOT EXISTS idx_allowances_spender ON allowances(spender);
+";
+
+#[derive(Args, Debug)]
+pub struct AllowancesArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Allowances after this block [default: the last block loaded]
+    #[arg(long)]
+    at_block: Option<i64>,
+
+    /// Only these spenders (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    spender: Vec<String>,
+
+    /// Only these tokens (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    token: Vec<String>,
+
+    /// Only these owners (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    owner: Vec<String>,
+
+    /// Write the allowances to this CSV file instead of the `allowances` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+struct Allowance {
+    amount: U256,
+    block_number: i64,
+    tx_hash: String,
+}
+
+pub fn run(args: AllowancesArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    decode::approvals(&conn)?;
+    let norm = Normalize::from_meta(&conn)?;
+    let at_block = match args.at_block {
+        Some(block) => block,
+        None => conn
+            .query_row("SELECT MAX(number) FROM blocks", [], |r| r.get::<_, Option<i64>>(0))?
+            .ok_or_else(|| eyre::eyre!("no blocks loaded; give --at-block"))?,
+    };
+
+    let allowances = allowances(&conn, &args, at_block)?;
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "at_block",
+                "token",
+                "owner",
+                "spender",
+                "amount",
+                "unlimited",
+                "approved_block",
+                "tx_hash",
+            ])?;
+            for ((token, owner, spender), a) in &allowances {
+                w.write_record([
+                    at_block.to_string(),
+                    token.clone(),
+                    owner.clone(),
+                    spender.clone(),
+                    a.amount.to_string(),
+                    ((a.amount == U256::MAX) as u8).to_string(),
+                    a.block_number.to_string(),
+                    a.tx_hash.clone(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(ALLOWANCES_SCHEMA)?;
+            conn.execute_batch("BEGIN;")?;
+            conn.execute("DELETE FROM allowances WHERE at_block = ?1", [at_block])?;
+            let mut stmt = conn.prepare(
+                "INSERT INTO allowances
+                 (at_block, token, owner, spender, amount, unlimited, approved_block, tx_hash)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8)",
+            )?;
+            for ((token, owner, spender), a) in &allowances {
+                stmt.execute(params![
+                    at_block,
+                    norm.address("token", token)?,
+                    norm.address("owner", owner)?,
+                    norm.address("spender", spender)?,
+                    a.amount.to_string(),
+                    a.amount == U256::MAX,
+                    a.block_number,
+                    a.tx_hash,
+                ])?;
+            }
+            drop(stmt);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("allowances".to_string(), |p| p.display().to_string());
+    let unlimited = allowances.values().filter(|a| a.amount == U256::MAX).count();
+    info!(out, at_block, allowances = allowances.len(), unlimited, "wrote allowances");
+    Ok(())
+}
+
+/// The last approval up to `at_block` by (token, owner, spender), lowercase,
+/// without the zero ones.
+fn allowances(
+    conn: &Connection,
+    args: &AllowancesArgs,
+    at_block: i64,
+) -> Result<BTreeMap<(String, String, String), Allowance>> {
+    let lower = |v: &[String]| v.iter().map(|a| a.to_ascii_lowercase()).collect::<Vec<_>>();
+    let (tokens, owners, spenders) = (lower(&args.token), lower(&args.owner), lower(&args.spender));
+    let wanted =
+        |filter: &[String], address: &String| filter.is_empty() || filter.contains(address);
+
+    let mut stmt = conn.prepare(
+        "SELECT lower(token), lower(owner), lower(spender), amount, block_number, tx_hash
+         FROM approvals
+         WHERE block_number <= ?1
+         ORDER BY block_number, log_index",
+    )?;
+    let mut rows = stmt.query([at_block])?;
+    let mut allowances = BTreeMap::new();
+    while let Some(r) = rows.next()? {
+        let key: (String, String, String) = (r.get(0)?, r.get(1)?, r.get(2)?);
+        if !wanted(&tokens, &key.0) || !wanted(&owners, &key.1) || !wanted(&spenders, &key.2) {
+            continue;
+        }
+        let allowance = Allowance {
+            amount: read_amount(r.get_ref(3)?)?,
+            block_number: r.get(4)?,
+            tx_hash: r.get(5)?,
+        };
+        allowances.insert(key, allowance);
+    }
+    allowances.retain(|_, a| !a.amount.is_zero());
+    Ok(allowances)
+}
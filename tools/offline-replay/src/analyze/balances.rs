@@ -0,0 +1,198 @@
+//! ERC-20 balances per holder over time, folded from `erc20_transfers` into a
+//! `token_balances` table (one row per block that changed a balance).
+//!
+//! Balances are summed from the start of the dataset, so they are changes
+//! over the loaded range (signed, decimal, in the token's base unit) unless
+//! the range starts at the token's deployment. The zero address, which mints
+//! come from and burns go to, is not tracked as a holder.
+//!
+//! `--snapshot` checks the final balances against a CSV with `holder` and
+//! `balance` columns (and `token`, when more than one token is analysed),
+//! such as a block explorer's holder export.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::{I256, U256};
+use clap::Args;
+use eyre::{eyre, Result};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::decode;
+use crate::normalize::read_amount;
+use crate::open_existing;
+
+const BALANCES_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS token_balances (
+    token        TEXT    NOT NULL,
+    holder       TEXT    NOT NULL,
+    block_number INTEGER NOT NULL,
+    balance      TEXT    NOT NULL,
+    PRIMARY KEY (token, holder, block_number)
+);
+";
+
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+#[derive(Args, Debug)]
+pub struct BalancesArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Tokens to reconstruct (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',', required = true)]
+    token: Vec<String>,
+
+    /// Only these holders (default: every holder)
+    #[arg(long, value_delimiter = ',')]
+    holder: Vec<String>,
+
+    /// Write the series to this CSV file instead of the `token_balances` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+
+    /// CSV of final balances (holder, balance[, token]) to check the result against
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotRow {
+    #[serde(default)]
+    token: Option<String>,
+    holder: String,
+    balance: String,
+}
+
+/// Balance after each block that changed it, by (token, holder, block).
+type Series = BTreeMap<(String, String, i64), I256>;
+
+/// Final balance by (token, holder).
+type Balances = HashMap<(String, String), I256>;
+
+pub fn run(args: BalancesArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    decode::erc20_transfers(&conn)?;
+    let tokens: Vec<String> = args.token.iter().map(|t| t.to_ascii_lowercase()).collect();
+    let holders: Vec<String> = args.holder.iter().map(|h| h.to_ascii_lowercase()).collect();
+
+    let (series, last) = fold(&conn, &tokens, &holders)?;
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record(["token", "holder", "block_number", "balance"])?;
+            for ((token, holder, block), balance) in &series {
+                w.write_record([token, holder, &block.to_string(), &balance.to_string()])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(BALANCES_SCHEMA)?;
+            conn.execute_batch("BEGIN;")?;
+            for token in &tokens {
+                if holders.is_empty() {
+                    conn.execute("DELETE FROM token_balances WHERE token = ?1", [token])?;
+                }
+                for holder in &holders {
+                    conn.execute(
+                        "DELETE FROM token_balances WHERE token = ?1 AND holder = ?2",
+                        [token, holder],
+                    )?;
+                }
+            }
+            let mut stmt = conn.prepare(
+                "INSERT INTO token_balances (token, holder, block_number, balance)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for ((token, holder, block), balance) in &series {
+                stmt.execute(params![token, holder, block, balance.to_string()])?;
+            }
+            drop(stmt);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("token_balances".to_string(), |p| p.display().to_string());
+    info!(out, holders = last.len(), rows = series.len(), "wrote balances");
+
+    if let Some(path) = &args.snapshot {
+        validate(path, &tokens, &holders, &last)?;
+    }
+    Ok(())
+}
+
+/// The balance series, and each (token, holder)'s final balance.
+fn fold(
+    conn: &Connection,
+    tokens: &[String],
+    holders: &[String],
+) -> Result<(Series, Balances)> {
+    let mut stmt = conn.prepare(
+        "SELECT lower(token), lower(from_addr), lower(to_addr), block_number, amount
+         FROM erc20_transfers
+         ORDER BY block_number, log_index",
+    )?;
+    let mut rows = stmt.query([])?;
+
+    let mut series = Series::new();
+    let mut balances = Balances::new();
+    while let Some(r) = rows.next()? {
+        let token: String = r.get(0)?;
+        if !tokens.contains(&token) {
+            continue;
+        }
+        let block: i64 = r.get(3)?;
+        let amount = I256::try_from(read_amount(r.get_ref(4)?)?).unwrap_or(I256::MAX);
+        for (holder, delta) in [(r.get::<_, String>(1)?, -amount), (r.get(2)?, amount)] {
+            if holder == ZERO_ADDRESS || !(holders.is_empty() || holders.contains(&holder)) {
+                continue;
+            }
+            let balance = balances.entry((token.clone(), holder.clone())).or_default();
+            *balance = balance.saturating_add(delta);
+            series.insert((token.clone(), holder, block), *balance);
+        }
+    }
+    Ok((series, balances))
+}
+
+/// Compare final balances with a snapshot; fails if any differ.
+fn validate(
+    path: &Path,
+    tokens: &[String],
+    holders: &[String],
+    last: &Balances,
+) -> Result<()> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let (mut checked, mut mismatched) = (0, 0);
+    for (i, row) in reader.deserialize().enumerate() {
+        let row: SnapshotRow = row.map_err(|e| eyre!("{} row {}: {e}", path.display(), i + 1))?;
+        let token = match (row.token, tokens) {
+            (Some(t), _) => t.trim().to_ascii_lowercase(),
+            (None, [only]) => only.clone(),
+            (None, _) => eyre::bail!(
+                "{} has no token column, but more than one --token is analysed",
+                path.display()
+            ),
+        };
+        let holder = row.holder.trim().to_ascii_lowercase();
+        if !tokens.contains(&token) || !(holders.is_empty() || holders.contains(&holder)) {
+            continue;
+        }
+        let expected: U256 = row.balance.trim().parse().map_err(|_| {
+            eyre!("{} row {}: {:?} is not a balance", path.display(), i + 1, row.balance)
+        })?;
+        let expected = I256::try_from(expected).unwrap_or(I256::MAX);
+        let actual = last.get(&(token.clone(), holder.clone())).copied().unwrap_or_default();
+        checked += 1;
+        if actual != expected {
+            mismatched += 1;
+            warn!(%token, %holder, %expected, %actual, "balance differs from snapshot");
+        }
+    }
+    info!(snapshot = %path.display(), checked, mismatched, "checked balances");
+    if mismatched > 0 {
+        eyre::bail!("{mismatched} of {checked} balances differ from {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,273 @@
+//! Block builder and proposer revenue over the dataset range, from each
+//! block's fee recipient (`blocks.miner`), into `builder_blocks` and
+//! `builder_revenue`.
+//!
+//! Per block: the fee recipient, the priority fees its transactions paid it
+//! (what they paid per gas above the base fee, times their gas), the base
+//! fee burned (base fee times the block's gas, NULL before London), and
+//! the proposer payment: under MEV-Boost the builder is the fee recipient
+//! and pays the proposer in the block's last transaction, so a transaction
+//! from the fee recipient that ends the block and carries value is taken
+//! as one, its recipient as the `proposer`. Payments through a contract or
+//! `coinbase.transfer` tips sent to the builder aren't visible without
+//! traces.
+//!
+//! Per fee recipient, the same summed up: blocks, first and last block,
+//! transactions, priority fees, burned, payments, and `margin`, priority
+//! fees less payments (negative where a builder paid the proposer more than
+//! the block's fees, as bidding builders do). Amounts are decimal wei. A
+//! rerun replaces both tables.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use alloy_primitives::U256;
+use clap::Args;
+use eyre::Result;
+use rusqlite::params;
+use tracing::{info, warn};
+
+use crate::normalize::{read_amount, read_key, Normalize};
+use crate::open_existing;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS builder_blocks (
+    block_number     INTEGER PRIMARY KEY,
+    fee_recipient    TEXT    NOT NULL,
+    txs              INTEGER NOT NULL,
+    priority_fees    TEXT    NOT NULL,
+    burned           TEXT,
+    proposer         TEXT,
+    proposer_payment TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_builder_blocks_recipient ON builder_blocks(fee_recipient);
+CREATE TABLE IF NOT EXISTS builder_revenue (
+    fee_recipient     TEXT    PRIMARY KEY,
+    blocks            INTEGER NOT NULL,
+    first_block       INTEGER NOT NULL,
+    last_block        INTEGER NOT NULL,
+    txs               INTEGER NOT NULL,
+    priority_fees     TEXT    NOT NULL,
+    burned            TEXT    NOT NULL,
+    proposer_payments TEXT    NOT NULL,
+    margin            TEXT    NOT NULL
+);
+";
+
+#[derive(Args, Debug)]
+pub struct BuildersArgs {
+    /// SQLite database produced by offline-replay from blocks with a miner column
+    db: PathBuf,
+
+    /// Only blocks at or above this one
+    #[arg(long)]
+    from_block: Option<i64>,
+
+    /// Only blocks at or below this one
+    #[arg(long)]
+    to_block: Option<i64>,
+
+    /// Write the per-recipient report to this CSV file instead of the tables
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+struct Block {
+    number: i64,
+    fee_recipient: String,
+    base_fee: Option<U256>,
+    gas_used: i64,
+    txs: i64,
+    priority_fees: U256,
+    /// The last transaction so far, if the fee recipient sent it with value:
+    /// (to, value).
+    payment: Option<(Option<String>, U256)>,
+}
+
+#[derive(Default)]
+struct Revenue {
+    blocks: i64,
+    first_block: i64,
+    last_block: i64,
+    txs: i64,
+    priority_fees: U256,
+    burned: U256,
+    payments: U256,
+}
+
+impl Revenue {
+    /// Priority fees less payments, signed.
+    fn margin(&self) -> String {
+        if self.priority_fees >= self.payments {
+            (self.priority_fees - self.payments).to_string()
+        } else {
+            format!("-{}", self.payments - self.priority_fees)
+        }
+    }
+}
+
+pub fn run(args: BuildersArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let norm = Normalize::from_meta(&conn)?;
+    let (from, to) = (args.from_block.unwrap_or(i64::MIN), args.to_block.unwrap_or(i64::MAX));
+
+    let mut blocks: BTreeMap<i64, Block> = BTreeMap::new();
+    let mut unknown = 0u64;
+    let mut stmt = conn.prepare(
+        "SELECT number, miner, base_fee, gas_used FROM blocks
+         WHERE is_canonical = 1 AND number BETWEEN ?1 AND ?2",
+    )?;
+    let mut rows = stmt.query(params![from, to])?;
+    while let Some(r) = rows.next()? {
+        let number: i64 = r.get(0)?;
+        let Some(fee_recipient) = read_key(r.get_ref(1)?)? else {
+            unknown += 1;
+            continue;
+        };
+        let base_fee = match r.get_ref(2)? {
+            rusqlite::types::ValueRef::Null => None,
+            v => Some(read_amount(v)?),
+        };
+        blocks.insert(number, Block {
+            number,
+            fee_recipient: fee_recipient.to_ascii_lowercase(),
+            base_fee,
+            gas_used: r.get(3)?,
+            txs: 0,
+            priority_fees: U256::ZERO,
+            payment: None,
+        });
+    }
+    drop(rows);
+    drop(stmt);
+    if blocks.is_empty() {
+        eyre::bail!("no blocks with a fee recipient in the range; the blocks need a miner column");
+    }
+    if unknown > 0 {
+        warn!(blocks = unknown, "left out blocks without a fee recipient (miner)");
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT block_number, from_addr, to_addr, value, gas_used, gas_price FROM transactions
+         WHERE block_number BETWEEN ?1 AND ?2 ORDER BY block_number, tx_index",
+    )?;
+    let mut rows = stmt.query(params![from, to])?;
+    while let Some(r) = rows.next()? {
+        let Some(block) = blocks.get_mut(&r.get::<_, i64>(0)?) else { continue };
+        let from_addr = read_key(r.get_ref(1)?)?.unwrap_or_default().to_ascii_lowercase();
+        let value = read_amount(r.get_ref(3)?)?;
+        let gas = U256::from(r.get::<_, i64>(4)?.max(0));
+        let price = read_amount(r.get_ref(5)?)?;
+        let tip = price.saturating_sub(block.base_fee.unwrap_or_default());
+        block.txs += 1;
+        block.priority_fees += tip * gas;
+        block.payment = if from_addr == block.fee_recipient && !value.is_zero() {
+            Some((read_key(r.get_ref(2)?)?, value))
+        } else {
+            None
+        };
+    }
+    drop(rows);
+    drop(stmt);
+
+    let mut revenue: BTreeMap<&str, Revenue> = BTreeMap::new();
+    for b in blocks.values() {
+        let r = revenue.entry(&b.fee_recipient).or_insert_with(|| Revenue {
+            first_block: b.number,
+            ..Default::default()
+        });
+        r.blocks += 1;
+        r.last_block = b.number;
+        r.txs += b.txs;
+        r.priority_fees += b.priority_fees;
+        r.burned += burned(b).unwrap_or_default();
+        r.payments += b.payment.as_ref().map_or(U256::ZERO, |(_, v)| *v);
+    }
+    let mut report: Vec<(&str, &Revenue)> = revenue.iter().map(|(k, v)| (*k, v)).collect();
+    report.sort_by(|a, b| b.1.priority_fees.cmp(&a.1.priority_fees).then(a.0.cmp(b.0)));
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "fee_recipient",
+                "blocks",
+                "first_block",
+                "last_block",
+                "txs",
+                "priority_fees",
+                "burned",
+                "proposer_payments",
+                "margin",
+            ])?;
+            for (recipient, r) in &report {
+                w.write_record([
+                    recipient.to_string(),
+                    r.blocks.to_string(),
+                    r.first_block.to_string(),
+                    r.last_block.to_string(),
+                    r.txs.to_string(),
+                    r.priority_fees.to_string(),
+                    r.burned.to_string(),
+                    r.payments.to_string(),
+                    r.margin(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM builder_blocks; DELETE FROM builder_revenue;")?;
+            let mut insert = conn.prepare(
+                "INSERT INTO builder_blocks
+                 (block_number, fee_recipient, txs, priority_fees, burned, proposer,
+                  proposer_payment)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7)",
+            )?;
+            for b in blocks.values() {
+                let (proposer, payment) = match &b.payment {
+                    Some((to, value)) => (to.as_deref(), Some(value.to_string())),
+                    None => (None, None),
+                };
+                insert.execute(params![
+                    b.number,
+                    norm.address("fee_recipient", &b.fee_recipient)?,
+                    b.txs,
+                    b.priority_fees.to_string(),
+                    burned(b).map(|v| v.to_string()),
+                    proposer.map(|p| norm.address("proposer", p)).transpose()?,
+                    payment,
+                ])?;
+            }
+            let mut insert = conn.prepare(
+                "INSERT INTO builder_revenue
+                 (fee_recipient, blocks, first_block, last_block, txs, priority_fees, burned,
+                  proposer_payments, margin)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9)",
+            )?;
+            for (recipient, r) in &report {
+                insert.execute(params![
+                    norm.address("fee_recipient", recipient)?,
+                    r.blocks,
+                    r.first_block,
+                    r.last_block,
+                    r.txs,
+                    r.priority_fees.to_string(),
+                    r.burned.to_string(),
+                    r.payments.to_string(),
+                    r.margin(),
+                ])?;
+            }
+            drop(insert);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("builder_revenue".to_string(), |p| p.display().to_string());
+    info!(out, blocks = blocks.len(), recipients = report.len(), "wrote builder revenue");
+    Ok(())
+}
+
+/// The base fee `b` burned, after London.
+fn burned(b: &Block) -> Option<U256> {
+    b.base_fee.map(|fee| fee * U256::from(b.gas_used.max(0)))
+}
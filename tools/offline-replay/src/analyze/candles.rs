@@ -0,0 +1,206 @@
+//! OHLCV candles per pool, from `dex_swaps`.
+//!
+//! A swap's price is token1 per token0 in raw units (amount1 / amount0, not
+//! adjusted for decimals), and each candle covers `--interval` seconds of
+//! block time, aligned to the Unix epoch. Volumes are the token0 and token1
+//! amounts that went through the pool, in base units.
+//!
+//! Candles are written to a `candles` table keyed by (pool, interval_secs,
+//! bucket_start), replacing rows from an earlier run, or with `--out` to CSV.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use alloy_primitives::U256;
+use clap::Args;
+use eyre::{eyre, Result};
+use rusqlite::{params, Connection};
+use tracing::info;
+
+use crate::decode;
+use crate::normalize::{read_amount, Normalize};
+use crate::open_existing;
+
+const CANDLES_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS candles (
+    pool          TEXT    NOT NULL,
+    interval_secs INTEGER NOT NULL,
+    bucket_start  INTEGER NOT NULL,
+    open          REAL    NOT NULL,
+    high          REAL    NOT NULL,
+    low           REAL    NOT NULL,
+    close         REAL    NOT NULL,
+    volume0       TEXT    NOT NULL,
+    volume1       TEXT    NOT NULL,
+    swaps         INTEGER NOT NULL,
+    PRIMARY KEY (pool, interval_secs, bucket_start)
+);
+";
+
+#[derive(Args, Debug)]
+pub struct CandlesArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Candle width: seconds, or a number with s/m/h/d (e.g. 5m, 1h)
+    #[arg(long, default_value = "1h", value_parser = parse_interval)]
+    interval: i64,
+
+    /// Only these pools (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    pool: Vec<String>,
+
+    /// Write the candles to this CSV file instead of the `candles` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+
+    /// Only swaps at or above this block
+    #[arg(long)]
+    from_block: Option<i64>,
+
+    /// Only swaps at or below this block
+    #[arg(long)]
+    to_block: Option<i64>,
+}
+
+struct Candle {
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume0: U256,
+    volume1: U256,
+    swaps: u64,
+}
+
+pub fn run(args: CandlesArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    decode::dex_swaps(&conn)?;
+    let norm = Normalize::from_meta(&conn)?;
+
+    let candles = candles(&conn, &args)?;
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "pool", "interval_secs", "bucket_start", "open", "high", "low", "close",
+                "volume0", "volume1", "swaps",
+            ])?;
+            for ((pool, bucket), c) in &candles {
+                w.write_record([
+                    pool.clone(),
+                    args.interval.to_string(),
+                    bucket.to_string(),
+                    c.open.to_string(),
+                    c.high.to_string(),
+                    c.low.to_string(),
+                    c.close.to_string(),
+                    c.volume0.to_string(),
+                    c.volume1.to_string(),
+                    c.swaps.to_string(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(CANDLES_SCHEMA)?;
+            conn.execute_batch("BEGIN;")?;
+            let mut stmt = conn.prepare(
+                "INSERT OR REPLACE INTO candles
+                 (pool, interval_secs, bucket_start, open, high, low, close, volume0, volume1, swaps)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10)",
+            )?;
+            for ((pool, bucket), c) in &candles {
+                stmt.execute(params![
+                    pool,
+                    args.interval,
+                    bucket,
+                    c.open,
+                    c.high,
+                    c.low,
+                    c.close,
+                    norm.encode(c.volume0),
+                    norm.encode(c.volume1),
+                    c.swaps,
+                ])?;
+            }
+            drop(stmt);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("candles table".to_string(), |p| p.display().to_string());
+    info!(out, interval = args.interval, candles = candles.len(), "wrote candles");
+    Ok(())
+}
+
+/// Candles keyed by (pool, bucket start), from swaps in block order.
+fn candles(conn: &Connection, args: &CandlesArgs) -> Result<BTreeMap<(String, i64), Candle>> {
+    let pools: Vec<String> = args.pool.iter().map(|p| p.to_ascii_lowercase()).collect();
+    let mut stmt = conn.prepare(
+        "SELECT lower(s.pool), b.timestamp, s.amount0_in, s.amount1_in, s.amount0_out, s.amount1_out
+         FROM dex_swaps s
+         JOIN blocks b ON b.number = s.block_number AND b.is_canonical = 1
+         WHERE s.block_number BETWEEN ?1 AND ?2
+         ORDER BY s.block_number, s.log_index",
+    )?;
+    let mut rows = stmt.query(params![
+        args.from_block.unwrap_or(i64::MIN),
+        args.to_block.unwrap_or(i64::MAX),
+    ])?;
+
+    let mut candles = BTreeMap::new();
+    while let Some(r) = rows.next()? {
+        let pool: String = r.get(0)?;
+        if !pools.is_empty() && !pools.contains(&pool) {
+            continue;
+        }
+        let timestamp: i64 = r.get(1)?;
+        let volume0 = read_amount(r.get_ref(2)?)?.saturating_add(read_amount(r.get_ref(4)?)?);
+        let volume1 = read_amount(r.get_ref(3)?)?.saturating_add(read_amount(r.get_ref(5)?)?);
+        if volume0.is_zero() || volume1.is_zero() {
+            continue; // no price to take from it
+        }
+        let price = f64::from(volume1) / f64::from(volume0);
+        let bucket = timestamp - timestamp.rem_euclid(args.interval);
+        candles
+            .entry((pool, bucket))
+            .and_modify(|c: &mut Candle| {
+                c.high = c.high.max(price);
+                c.low = c.low.min(price);
+                c.close = price;
+                c.volume0 = c.volume0.saturating_add(volume0);
+                c.volume1 = c.volume1.saturating_add(volume1);
+                c.swaps += 1;
+            })
+            .or_insert(Candle {
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume0,
+                volume1,
+                swaps: 1,
+            });
+    }
+    Ok(candles)
+}
+
+/// "300", "90s", "5m", "1h" or "1d", in seconds.
+fn parse_interval(s: &str) -> Result<i64> {
+    let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return Err(eyre!("unknown unit {unit:?} (use s, m, h or d)")),
+    };
+    let n: i64 = n.parse().map_err(|_| eyre!("expected a number of seconds, or e.g. 5m"))?;
+    if n <= 0 {
+        return Err(eyre!("the interval must be positive"));
+    }
+    Ok(n * unit)
+}
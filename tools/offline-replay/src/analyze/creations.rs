@@ -0,0 +1,263 @@
+//! Contract creations, from the call trees of the `traces` table (see
+//! traces.rs) and from deployment transactions, into `contract_creations`.
+//!
+//! With traces, every CREATE and CREATE2 frame that succeeded is a row, so
+//! factory deployments are listed along with top-level ones: the new
+//! contract's `address`, the `deployer` whose code created it (the factory,
+//! or the sender for a deployment transaction), the `kind`, the frame's
+//! position in the trace and its depth, and `init_code_hash`, the keccak of
+//! the init code. A transaction without a trace contributes only its
+//! top-level deployment, whose address follows from the sender's nonce.
+//!
+//! CREATE2's `salt` can't be read back from the address, only checked: it
+//! is recovered when the factory was passed it as a word of its calldata
+//! (as createPair-style factories that hash their arguments aren't), and is
+//! NULL otherwise. Failed frames create nothing and are skipped with their
+//! subcalls. A rerun replaces the table.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use alloy_primitives::{hex, keccak256, Address, B256};
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::{params, Connection};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use super::traces::{self, has_traces};
+use crate::normalize::{read_key, Normalize};
+use crate::open_existing;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS contract_creations (
+    address        TEXT    NOT NULL,
+    block_number   INTEGER NOT NULL,
+    tx_hash        TEXT    NOT NULL,
+    frame          INTEGER NOT NULL,
+    depth          INTEGER NOT NULL,
+    kind           TEXT    NOT NULL,
+    deployer       TEXT    NOT NULL,
+    salt           TEXT,
+    init_code_hash TEXT    NOT NULL,
+    PRIMARY KEY (tx_hash, frame)
+);
+CREATE INDEX IF NOT EXISTS idx_contract_creations_address ON contract_creations(address);
+CREATE INDEX IF NOT EXISTS idx_contract_creations_deployer ON contract_creations(deployer);
+";
+
+#[derive(Args, Debug)]
+pub struct CreationsArgs {
+    /// SQLite database produced by offline-replay, with or without a `traces` table
+    db: PathBuf,
+
+    /// Write the creations to this CSV file instead of the table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+struct Creation {
+    address: String,
+    block_number: i64,
+    tx_hash: String,
+    frame: usize,
+    depth: usize,
+    kind: String,
+    deployer: String,
+    salt: Option<String>,
+    init_code_hash: String,
+}
+
+pub fn run(args: CreationsArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let norm = Normalize::from_meta(&conn)?;
+
+    let mut found = Vec::new();
+    let mut traced = HashSet::new();
+    if has_traces(&conn)? {
+        let mut stmt = conn.prepare("SELECT block_number, tx_hash, trace_json FROM traces")?;
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            let block: i64 = r.get(0)?;
+            let tx: String = r.get(1)?;
+            let trace: Value = serde_json::from_str(&r.get::<_, String>(2)?)
+                .with_context(|| format!("Bad trace_json for {tx}"))?;
+            let tx_found = found.len();
+            creations(&trace, &mut found)
+                .with_context(|| format!("Bad trace_json for {tx}"))?;
+            for c in &mut found[tx_found..] {
+                c.block_number = block;
+                c.tx_hash.clone_from(&tx);
+            }
+            traced.insert(tx.to_ascii_lowercase());
+        }
+    } else {
+        warn!("no traces table; listing top-level deployments only");
+    }
+    found.extend(deployments(&conn, &norm, &traced)?);
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "address",
+                "block_number",
+                "tx_hash",
+                "frame",
+                "depth",
+                "kind",
+                "deployer",
+                "salt",
+                "init_code_hash",
+            ])?;
+            for c in &found {
+                w.write_record([
+                    c.address.clone(),
+                    c.block_number.to_string(),
+                    c.tx_hash.clone(),
+                    c.frame.to_string(),
+                    c.depth.to_string(),
+                    c.kind.clone(),
+                    c.deployer.clone(),
+                    c.salt.clone().unwrap_or_default(),
+                    c.init_code_hash.clone(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM contract_creations;")?;
+            let mut insert = conn.prepare(
+                "INSERT INTO contract_creations
+                 (address, block_number, tx_hash, frame, depth, kind, deployer, salt,
+                  init_code_hash)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9)",
+            )?;
+            for c in &found {
+                insert.execute(params![
+                    norm.address("address", &c.address)?,
+                    c.block_number,
+                    c.tx_hash,
+                    c.frame as i64,
+                    c.depth as i64,
+                    c.kind,
+                    norm.address("deployer", &c.deployer)?,
+                    c.salt,
+                    c.init_code_hash,
+                ])?;
+            }
+            drop(insert);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out =
+        args.out.as_ref().map_or("contract_creations".to_string(), |p| p.display().to_string());
+    let factory = found.iter().filter(|c| c.depth > 0).count();
+    let salted = found.iter().filter(|c| c.salt.is_some()).count();
+    info!(out, creations = found.len(), factory, salted, "wrote contract creations");
+    Ok(())
+}
+
+/// Add each successful creation of a callTracer trace to `found`, for the
+/// caller to fill in its block and transaction.
+fn creations(trace: &Value, found: &mut Vec<Creation>) -> Result<()> {
+    traces::walk(trace, &mut |f| {
+        let kind = f.call["type"].as_str().unwrap_or_default().to_ascii_uppercase();
+        if f.failed || !matches!(kind.as_str(), "CREATE" | "CREATE2") {
+            return Ok(());
+        }
+        let number = f.number;
+        let address = |key: &str| -> Result<Address> {
+            let s = f.call[key].as_str().unwrap_or_default();
+            s.parse().map_err(|_| eyre::eyre!("{kind} frame {number}: bad {key} {s:?}"))
+        };
+        let (deployer, to) = (address("from")?, address("to")?);
+        let init_code = bytes(&f.call["input"])?;
+        let hash = keccak256(&init_code);
+        let salt = match (kind.as_str(), f.parent) {
+            ("CREATE2", Some(parent)) => salt(&bytes(&parent["input"])?, deployer, hash, to),
+            _ => None,
+        };
+        found.push(Creation {
+            address: format!("0x{}", hex::encode(to)),
+            block_number: 0,
+            tx_hash: String::new(),
+            frame: number,
+            depth: f.depth,
+            kind,
+            deployer: format!("0x{}", hex::encode(deployer)),
+            salt: salt.map(|s| s.to_string()),
+            init_code_hash: hash.to_string(),
+        });
+        Ok(())
+    })
+}
+
+fn bytes(v: &Value) -> Result<Vec<u8>> {
+    let s = v.as_str().unwrap_or("0x");
+    hex::decode(s).map_err(|_| eyre::eyre!("bad hex {s:?}"))
+}
+
+/// The word of the factory's calldata that, as the salt, gives `address`:
+/// the words after a selector, or from the start for deployers such as the
+/// deterministic deployment proxy that take the salt and init code raw.
+fn salt(
+    calldata: &[u8],
+    deployer: Address,
+    init_code_hash: B256,
+    address: Address,
+) -> Option<B256> {
+    let words = |from: usize| calldata.get(from..).unwrap_or_default().chunks_exact(32);
+    words(4)
+        .chain(words(0))
+        .map(B256::from_slice)
+        .find(|&salt| deployer.create2(salt, init_code_hash) == address)
+}
+
+/// Successful deployment transactions whose trace isn't in `traced`, with
+/// the address from the sender and nonce. Transactions loaded without a
+/// nonce are left out, with a warning.
+fn deployments(
+    conn: &Connection,
+    norm: &Normalize,
+    traced: &HashSet<String>,
+) -> Result<Vec<Creation>> {
+    let mut stmt = conn.prepare(
+        "SELECT hash, block_number, from_addr, nonce, input FROM transactions
+         WHERE to_addr IS NULL AND status = 1
+         ORDER BY block_number, tx_index",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut found = Vec::new();
+    let mut no_nonce = 0;
+    while let Some(r) = rows.next()? {
+        let tx = read_key(r.get_ref(0)?)?.unwrap_or_default();
+        if traced.contains(&tx.to_ascii_lowercase()) {
+            continue;
+        }
+        let Some(nonce) = r.get::<_, Option<i64>>(3)? else {
+            no_nonce += 1;
+            continue;
+        };
+        let from = read_key(r.get_ref(2)?)?.unwrap_or_default();
+        let sender: Address =
+            from.parse().map_err(|_| eyre::eyre!("{tx}: bad from_addr {from:?}"))?;
+        let init_code = norm.read_blob(r.get(4)?)?;
+        found.push(Creation {
+            address: format!("0x{}", hex::encode(sender.create(nonce as u64))),
+            block_number: r.get(1)?,
+            tx_hash: tx,
+            frame: 0,
+            depth: 0,
+            kind: "CREATE".to_string(),
+            deployer: format!("0x{}", hex::encode(sender)),
+            salt: None,
+            init_code_hash: keccak256(&init_code).to_string(),
+        });
+    }
+    if no_nonce > 0 {
+        warn!(transactions = no_nonce, "deployments without a nonce left out");
+    }
+    Ok(found)
+}
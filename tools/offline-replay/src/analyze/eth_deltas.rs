@@ -0,0 +1,119 @@
+//! Native ETH balance changes per address and block, from a `traces` table,
+//! into `eth_balance_deltas`.
+//!
+//! Transactions only show the top-level call's value; ETH moved by internal
+//! calls, contract creations and self-destructs is only visible in traces.
+//! The loader doesn't produce them: they come from the `traces` table that
+//! scripts/capture.py writes, one row per transaction with its callTracer
+//! output (`block_number, tx_hash, trace_json`), copied or attached into the
+//! database.
+//!
+//! Calls that failed, and everything under them, move nothing; neither do
+//! delegate and static calls. `fees` is the sender's `fee_wei`; delta =
+//! received - sent - fees (signed decimal, in wei), so the fee recipient's
+//! side and block rewards are not included.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use alloy_primitives::{I256, U256};
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::params;
+use serde_json::Value;
+use tracing::info;
+
+use super::traces::{self, check_traces};
+use crate::normalize::{read_amount, read_key, Normalize};
+use crate::open_existing;
+
+const DELTAS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS eth_balance_deltas (
+    block_number INTEGER NOT NULL,
+    address      TEXT    NOT NULL,
+    received     TEXT    NOT NULL,
+    sent         TEXT    NOT NULL,
+    fees         TEXT    NOT NULL,
+    delta        TEXT    NOT NULL,
+    PRIMARY KEY (block_number, address)
+);
+";
+
+#[derive(Args, Debug)]
+pub struct EthDeltasArgs {
+    /// SQLite database with a `traces` table, as written by scripts/capture.py
+    db: PathBuf,
+}
+
+#[derive(Default)]
+struct Delta {
+    received: U256,
+    sent: U256,
+    fees: U256,
+}
+
+impl Delta {
+    fn net(&self) -> I256 {
+        let signed = |v: U256| I256::try_from(v).unwrap_or(I256::MAX);
+        signed(self.received).saturating_sub(signed(self.sent)).saturating_sub(signed(self.fees))
+    }
+}
+
+pub fn run(args: EthDeltasArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    check_traces(&conn)?;
+    let norm = Normalize::from_meta(&conn)?;
+
+    let mut deltas: BTreeMap<(i64, String), Delta> = BTreeMap::new();
+    let mut stmt = conn.prepare("SELECT block_number, tx_hash, trace_json FROM traces")?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let block: i64 = r.get(0)?;
+        let tx: String = r.get(1)?;
+        let trace: Value = serde_json::from_str(&r.get::<_, String>(2)?)
+            .with_context(|| format!("Bad trace_json for {tx}"))?;
+        traces::transfers(&trace, &mut |t| {
+            let d = deltas.entry((block, t.from)).or_default();
+            d.sent = d.sent.saturating_add(t.value);
+            let d = deltas.entry((block, t.to)).or_default();
+            d.received = d.received.saturating_add(t.value);
+        })
+        .with_context(|| format!("Bad trace_json for {tx}"))?;
+    }
+    drop(rows);
+
+    let mut stmt = conn.prepare(
+        "SELECT block_number, from_addr, fee_wei FROM transactions WHERE fee_wei IS NOT NULL",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let fee = read_amount(r.get_ref(2)?)?;
+        if !fee.is_zero() {
+            let from = read_key(r.get_ref(1)?)?.unwrap_or_default().to_ascii_lowercase();
+            let d = deltas.entry((r.get(0)?, from)).or_default();
+            d.fees = d.fees.saturating_add(fee);
+        }
+    }
+    drop(rows);
+
+    conn.execute_batch(DELTAS_SCHEMA)?;
+    conn.execute_batch("BEGIN; DELETE FROM eth_balance_deltas;")?;
+    let mut insert = conn.prepare(
+        "INSERT INTO eth_balance_deltas (block_number, address, received, sent, fees, delta)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for ((block, address), d) in &deltas {
+        insert.execute(params![
+            block,
+            norm.address("address", address)?,
+            norm.encode(d.received),
+            norm.encode(d.sent),
+            norm.encode(d.fees),
+            d.net().to_string(),
+        ])?;
+    }
+    drop(insert);
+    conn.execute_batch("COMMIT;")?;
+    info!(db = %args.db.display(), rows = deltas.len(), "wrote eth_balance_deltas");
+    Ok(())
+}
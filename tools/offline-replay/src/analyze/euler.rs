@@ -0,0 +1,315 @@
+//! The Euler Finance exploit of March 2023, told step by step: every Euler
+//! event of a transaction, in log order, as a row of `euler_steps` with a
+//! sentence describing it.
+//!
+//! The attacker's transactions took an Aave flash loan, deposited it, used
+//! `mint` to borrow ten times over against eTokens, then donated eTokens to
+//! the reserves with `donateToReserves` — leaving the account with more debt
+//! than collateral — and had a second contract liquidate it at the maximum
+//! discount. A transaction is flagged `exploit` when an account donates and
+//! is then liquidated within it.
+//!
+//! The steps come from the tables of the `euler` decoders (and the Aave flash
+//! loans, with the `aave` decoders), which are caught up first; see
+//! protocols/builtin.rs. By default only transactions with a donation or a
+//! liquidation are written; `--tx` picks transactions and `--all` takes
+//! every one with an Euler event. Amounts are in base units of the token.
+//! Addresses in the sentences are replaced by their name in `labels` when
+//! there is one. A rerun replaces the table.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use alloy_primitives::U256;
+use clap::Args;
+use eyre::Result;
+use rusqlite::{params, Connection, Row};
+use tracing::info;
+
+use crate::normalize::read_amount;
+use crate::open_existing;
+use crate::protocols::{self, Registry};
+
+const STEPS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS euler_steps (
+    tx_hash      TEXT    NOT NULL,
+    step         INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    log_index    INTEGER NOT NULL,
+    action       TEXT    NOT NULL,
+    account      TEXT    NOT NULL,
+    counterparty TEXT,
+    underlying   TEXT,
+    amount       TEXT    NOT NULL,
+    exploit      INTEGER NOT NULL,
+    detail       TEXT    NOT NULL,
+    PRIMARY KEY (tx_hash, step)
+);
+";
+
+#[derive(Args, Debug)]
+pub struct EulerArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Only these transactions (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',', conflicts_with = "all")]
+    tx: Vec<String>,
+
+    /// Every transaction with an Euler event, not only those with a donation
+    /// or a liquidation
+    #[arg(long)]
+    all: bool,
+
+    /// Write the steps to this CSV file instead of the `euler_steps` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+struct Step {
+    block_number: i64,
+    log_index: i64,
+    action: &'static str,
+    account: String,
+    counterparty: Option<String>,
+    underlying: Option<String>,
+    amount: U256,
+    detail: String,
+}
+
+pub fn run(args: EulerArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let flash_loans = Registry::builtin()?.protocols().contains("aave");
+    protocols::catch_up(&conn, if flash_loans { &["euler", "aave"] } else { &["euler"] })?;
+
+    let names = labels(&conn)?;
+    let mut txs: BTreeMap<String, Vec<Step>> = BTreeMap::new();
+    for (tx, step) in steps(&conn, flash_loans, &names)? {
+        txs.entry(tx).or_default().push(step);
+    }
+    let wanted: Vec<String> = args.tx.iter().map(|t| t.to_ascii_lowercase()).collect();
+    txs.retain(|tx, steps| {
+        // A flash loan alone isn't an Euler transaction.
+        steps.iter().any(|s| s.action != "flash_loan")
+            && if !wanted.is_empty() {
+                wanted.contains(&tx.to_ascii_lowercase())
+            } else {
+                args.all || steps.iter().any(|s| matches!(s.action, "donate" | "liquidation"))
+            }
+    });
+    for steps in txs.values_mut() {
+        steps.sort_by_key(|s| (s.block_number, s.log_index));
+    }
+
+    let mut rows = Vec::new();
+    let mut exploits = 0;
+    for (tx, steps) in &txs {
+        let exploit = exploit(steps);
+        exploits += exploit as usize;
+        rows.extend(steps.iter().enumerate().map(|(i, s)| (tx, i, s, exploit)));
+    }
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "tx_hash",
+                "step",
+                "block_number",
+                "log_index",
+                "action",
+                "account",
+                "counterparty",
+                "underlying",
+                "amount",
+                "exploit",
+                "detail",
+            ])?;
+            for (tx, i, s, exploit) in &rows {
+                w.write_record([
+                    tx.to_string(),
+                    i.to_string(),
+                    s.block_number.to_string(),
+                    s.log_index.to_string(),
+                    s.action.to_string(),
+                    s.account.clone(),
+                    s.counterparty.clone().unwrap_or_default(),
+                    s.underlying.clone().unwrap_or_default(),
+                    s.amount.to_string(),
+                    (*exploit as u8).to_string(),
+                    s.detail.clone(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(STEPS_SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM euler_steps;")?;
+            let mut stmt = conn.prepare(
+                "INSERT INTO euler_steps
+                 (tx_hash, step, block_number, log_index, action, account, counterparty,
+                  underlying, amount, exploit, detail)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11)",
+            )?;
+            for (tx, i, s, exploit) in &rows {
+                stmt.execute(params![
+                    tx,
+                    *i as i64,
+                    s.block_number,
+                    s.log_index,
+                    s.action,
+                    s.account,
+                    s.counterparty,
+                    s.underlying,
+                    s.amount.to_string(),
+                    exploit,
+                    s.detail,
+                ])?;
+            }
+            drop(stmt);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("euler_steps".to_string(), |p| p.display().to_string());
+    info!(out, transactions = txs.len(), steps = rows.len(), exploits, "wrote Euler steps");
+    Ok(())
+}
+
+/// Every step in the decoded tables, with its transaction; `names` are used
+/// for the addresses in the sentences.
+fn steps(
+    conn: &Connection,
+    flash_loans: bool,
+    names: &HashMap<String, String>,
+) -> Result<Vec<(String, Step)>> {
+    let name = |r: &Row<'_>, i: usize| -> Result<String> {
+        let address: String = r.get(i)?;
+        Ok(names.get(&address.to_ascii_lowercase()).cloned().unwrap_or(address))
+    };
+    let amount = |r: &Row<'_>, i: usize| read_amount(r.get_ref(i)?);
+    let step = |r: &Row<'_>, action, detail: String| -> Result<Step> {
+        Ok(Step {
+            block_number: r.get(1)?,
+            log_index: r.get(2)?,
+            action,
+            account: r.get(3)?,
+            counterparty: None,
+            underlying: r.get(4)?,
+            amount: amount(r, 5)?,
+            detail,
+        })
+    };
+    let mut steps = Vec::new();
+    let mut read = |sql: &str, f: &dyn Fn(&Row<'_>) -> Result<Step>| -> Result<()> {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            steps.push((r.get::<_, String>(0)?, f(r)?));
+        }
+        Ok(())
+    };
+
+    for (table, action, verb) in [
+        ("euler_deposits", "deposit", "deposits"),
+        ("euler_withdrawals", "withdraw", "withdraws"),
+        ("euler_borrows", "borrow", "borrows"),
+        ("euler_repays", "repay", "repays"),
+    ] {
+        read(
+            &format!(
+                "SELECT tx_hash, block_number, log_index, account, underlying, amount FROM {table}"
+            ),
+            &|r| {
+                let detail = format!("{} {verb} {} {}", name(r, 3)?, amount(r, 5)?, name(r, 4)?);
+                step(r, action, detail)
+            },
+        )?;
+    }
+    read(
+        "SELECT tx_hash, block_number, log_index, account, NULL, amount FROM euler_mints",
+        &|r| {
+            let detail =
+                format!("{} mints {} eTokens with as much new debt", name(r, 3)?, amount(r, 5)?);
+            step(r, "mint", detail)
+        },
+    )?;
+    read(
+        "SELECT tx_hash, block_number, log_index, account, NULL, amount FROM euler_donations",
+        &|r| {
+            let detail =
+                format!("{} donates {} eTokens to the reserves", name(r, 3)?, amount(r, 5)?);
+            step(r, "donate", detail)
+        },
+    )?;
+    read(
+        "SELECT tx_hash, block_number, log_index, liquidator, underlying, repay, violator,
+                collateral, yield, discount
+         FROM euler_liquidations",
+        &|r| {
+            // 1e18 is 100%.
+            let bps = amount(r, 9)? * U256::from(10_000) / U256::from(10u64.pow(18));
+            let bps = bps.saturating_to::<u64>();
+            let detail = format!(
+                "{} liquidates {}: repays {} {} for {} {} at a {}.{:02}% discount",
+                name(r, 3)?,
+                name(r, 6)?,
+                amount(r, 5)?,
+                name(r, 4)?,
+                amount(r, 8)?,
+                name(r, 7)?,
+                bps / 100,
+                bps % 100
+            );
+            let mut s = step(r, "liquidation", detail)?;
+            s.counterparty = Some(r.get(6)?);
+            Ok(s)
+        },
+    )?;
+    if flash_loans {
+        read(
+            "SELECT tx_hash, block_number, log_index, target, asset, amount, premium
+             FROM aave_v2_flash_loans",
+            &|r| {
+                let detail = format!(
+                    "{} flash-borrows {} {} from Aave (premium {})",
+                    name(r, 3)?,
+                    amount(r, 5)?,
+                    name(r, 4)?,
+                    amount(r, 6)?
+                );
+                step(r, "flash_loan", detail)
+            },
+        )?;
+    }
+    Ok(steps)
+}
+
+/// An account donates, and is then liquidated in the same transaction.
+fn exploit(steps: &[Step]) -> bool {
+    steps.iter().enumerate().any(|(i, donation)| {
+        donation.action == "donate"
+            && steps[i + 1..].iter().any(|s| {
+                s.action == "liquidation"
+                    && s.counterparty
+                        .as_deref()
+                        .is_some_and(|v| v.eq_ignore_ascii_case(&donation.account))
+            })
+    })
+}
+
+/// Address -> name, from `labels` when the database has it.
+fn labels(conn: &Connection) -> Result<HashMap<String, String>> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'labels'",
+        [],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        return Ok(HashMap::new());
+    }
+    let mut stmt = conn.prepare("SELECT address, name FROM labels")?;
+    let names = stmt
+        .query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(names)
+}
@@ -0,0 +1,350 @@
+//! Flash loans, recognized per lender from what its events look like when
+//! it lends and is repaid in the same transaction, into a `flashloans`
+//! table:
+//!
+//!   aave_v2     LendingPool FlashLoan
+//!   balancer    Vault FlashLoan
+//!   uniswap_v3  pool Flash, a row per token lent
+//!   uniswap_v2  a flash swap repaid in the token it took: a Swap with both
+//!               an in and an out amount of the same token
+//!   dydx        a SoloMargin withdrawal of a market followed by a deposit of
+//!               at least as much by the same account
+//!
+//! A row has the lender contract, the `borrower` (the receiver of the
+//! tokens, or the dYdX account), the `asset`, the `amount` lent and the
+//! `fee` repaid on top, in base units. Uniswap events don't name their
+//! tokens, so the asset is that of the transfer out of the pool with the
+//! amount, and NULL without one.
+//!
+//! `swaps` counts the other DEX swaps of the transaction, and `large_swap`
+//! is 1 when one of them moved at least `--large-swap` percent of the loan
+//! in the borrowed asset — the borrowed funds were put through a pool, as
+//! in a price manipulation. Lenders whose decoders aren't compiled in are
+//! skipped. A rerun replaces the table.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use alloy_primitives::U256;
+use clap::Args;
+use eyre::Result;
+use rusqlite::{params, Connection, Row};
+use tracing::info;
+
+use crate::decode;
+use crate::normalize::read_amount;
+use crate::open_existing;
+use crate::protocols::{self, Registry};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS flashloans (
+    tx_hash      TEXT    NOT NULL,
+    block_number INTEGER NOT NULL,
+    log_index    INTEGER NOT NULL,
+    provider     TEXT    NOT NULL,
+    lender       TEXT    NOT NULL,
+    borrower     TEXT    NOT NULL,
+    asset        TEXT,
+    amount       TEXT    NOT NULL,
+    fee          TEXT,
+    swaps        INTEGER NOT NULL,
+    large_swap   INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_flashloans_tx ON flashloans(tx_hash);
+";
+
+/// The dYdX markets' tokens, by market id.
+const DYDX_MARKETS: [&str; 4] = [
+    "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+    "0x89d24a6b4ccb1b6faa2625fe562bdd9a23260359",
+    "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+    "0x6b175474e89094c44da98b954eedeac495271d0f",
+];
+
+#[derive(Args, Debug)]
+pub struct FlashloansArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// A swap of at least this percentage of a loan, in its asset, is large
+    #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u64).range(1..))]
+    large_swap: u64,
+
+    /// Write the loans to this CSV file instead of the `flashloans` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+struct Loan {
+    tx_hash: String,
+    block_number: i64,
+    log_index: i64,
+    provider: &'static str,
+    lender: String,
+    borrower: String,
+    asset: Option<String>,
+    amount: U256,
+    fee: Option<U256>,
+    swaps: usize,
+    large_swap: bool,
+}
+
+/// What the analysis reads of a transaction's transfers and swaps.
+#[derive(Default)]
+struct Tx {
+    /// (token, from, to, amount), lowercase.
+    transfers: Vec<(String, String, String, U256)>,
+    /// Lowercase.
+    swap_pools: Vec<String>,
+}
+
+pub fn run(args: FlashloansArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let registry = Registry::builtin()?;
+    let compiled = registry.protocols();
+    let lenders: Vec<&str> = ["aave", "balancer", "uniswap", "dydx"]
+        .into_iter()
+        .filter(|p| compiled.contains(p))
+        .collect();
+    protocols::catch_up(&conn, &lenders)?;
+    decode::erc20_transfers(&conn)?;
+    decode::dex_swaps(&conn)?;
+
+    let mut loans = loans(&conn, &lenders)?;
+    let hashes: HashSet<String> = loans.iter().map(|l| l.tx_hash.clone()).collect();
+    let txs = txs(&conn, &hashes)?;
+    let empty = Tx::default();
+    for loan in &mut loans {
+        let tx = txs.get(&loan.tx_hash).unwrap_or(&empty);
+        let lender = loan.lender.to_ascii_lowercase();
+        if loan.asset.is_none() {
+            loan.asset = tx
+                .transfers
+                .iter()
+                .find(|(_, from, _, amount)| *from == lender && *amount == loan.amount)
+                .map(|(token, ..)| token.clone());
+        }
+        let pools: HashSet<&str> =
+            tx.swap_pools.iter().map(String::as_str).filter(|p| *p != lender).collect();
+        loan.swaps = tx.swap_pools.iter().filter(|p| **p != lender).count();
+        let threshold = loan.amount.saturating_mul(U256::from(args.large_swap));
+        loan.large_swap = loan.asset.as_deref().is_some_and(|asset| {
+            let asset = asset.to_ascii_lowercase();
+            tx.transfers.iter().any(|(token, from, to, amount)| {
+                *token == asset
+                    && (pools.contains(from.as_str()) || pools.contains(to.as_str()))
+                    && amount.saturating_mul(U256::from(100)) >= threshold
+            })
+        });
+    }
+    loans.sort_by_key(|l| (l.block_number, l.log_index));
+
+    let fee = |l: &Loan| l.fee.map(|f| f.to_string());
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "tx_hash",
+                "block_number",
+                "log_index",
+                "provider",
+                "lender",
+                "borrower",
+                "asset",
+                "amount",
+                "fee",
+                "swaps",
+                "large_swap",
+            ])?;
+            for l in &loans {
+                w.write_record([
+                    l.tx_hash.clone(),
+                    l.block_number.to_string(),
+                    l.log_index.to_string(),
+                    l.provider.to_string(),
+                    l.lender.clone(),
+                    l.borrower.clone(),
+                    l.asset.clone().unwrap_or_default(),
+                    l.amount.to_string(),
+                    fee(l).unwrap_or_default(),
+                    l.swaps.to_string(),
+                    (l.large_swap as u8).to_string(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM flashloans;")?;
+            let mut stmt = conn.prepare(
+                "INSERT INTO flashloans
+                 (tx_hash, block_number, log_index, provider, lender, borrower, asset, amount,
+                  fee, swaps, large_swap)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11)",
+            )?;
+            for l in &loans {
+                stmt.execute(params![
+                    l.tx_hash,
+                    l.block_number,
+                    l.log_index,
+                    l.provider,
+                    l.lender,
+                    l.borrower,
+                    l.asset,
+                    l.amount.to_string(),
+                    fee(l),
+                    l.swaps as i64,
+                    l.large_swap,
+                ])?;
+            }
+            drop(stmt);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("flashloans".to_string(), |p| p.display().to_string());
+    let large = loans.iter().filter(|l| l.large_swap).count();
+    info!(out, loans = loans.len(), transactions = hashes.len(), large, "wrote flash loans");
+    Ok(())
+}
+
+/// Every flash loan of the `lenders` protocols.
+fn loans(conn: &Connection, lenders: &[&str]) -> Result<Vec<Loan>> {
+    let amount = |r: &Row<'_>, i: usize| read_amount(r.get_ref(i)?);
+    let loan = |r: &Row<'_>, provider, asset, amount, fee| -> Result<Loan> {
+        Ok(Loan {
+            tx_hash: r.get(0)?,
+            block_number: r.get(1)?,
+            log_index: r.get(2)?,
+            provider,
+            lender: r.get(3)?,
+            borrower: r.get(4)?,
+            asset,
+            amount,
+            fee,
+            swaps: 0,
+            large_swap: false,
+        })
+    };
+    let mut loans = Vec::new();
+    let mut read = |sql: &str, f: &mut dyn FnMut(&Row<'_>, &mut Vec<Loan>) -> Result<()>| {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            f(r, &mut loans)?;
+        }
+        eyre::Ok(())
+    };
+
+    if lenders.contains(&"aave") {
+        read(
+            "SELECT tx_hash, block_number, log_index, contract, target, asset, amount, premium
+             FROM aave_v2_flash_loans",
+            &mut |r, loans| {
+                let (asset, fee) = (Some(r.get(5)?), Some(amount(r, 7)?));
+                loans.push(loan(r, "aave_v2", asset, amount(r, 6)?, fee)?);
+                Ok(())
+            },
+        )?;
+    }
+    if lenders.contains(&"balancer") {
+        read(
+            "SELECT tx_hash, block_number, log_index, contract, recipient, token, amount, fee_amount
+             FROM balancer_flashloans",
+            &mut |r, loans| {
+                let (asset, fee) = (Some(r.get(5)?), Some(amount(r, 7)?));
+                loans.push(loan(r, "balancer", asset, amount(r, 6)?, fee)?);
+                Ok(())
+            },
+        )?;
+    }
+    if lenders.contains(&"uniswap") {
+        read(
+            "SELECT tx_hash, block_number, log_index, contract, recipient, amount0, amount1,
+                    paid0, paid1
+             FROM uniswap_v3_flashes",
+            &mut |r, loans| {
+                for (lent, paid) in [(5, 7), (6, 8)] {
+                    let lent = amount(r, lent)?;
+                    if !lent.is_zero() {
+                        loans.push(loan(r, "uniswap_v3", None, lent, Some(amount(r, paid)?))?);
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        read(
+            "SELECT tx_hash, block_number, log_index, contract, \"to\", amount0_in, amount1_in,
+                    amount0_out, amount1_out
+             FROM uniswap_v2_swaps",
+            &mut |r, loans| {
+                for (paid, lent) in [(5, 7), (6, 8)] {
+                    let (paid, lent) = (amount(r, paid)?, amount(r, lent)?);
+                    if !paid.is_zero() && !lent.is_zero() {
+                        let fee = Some(paid.saturating_sub(lent));
+                        loans.push(loan(r, "uniswap_v2", None, lent, fee)?);
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+    if lenders.contains(&"dydx") {
+        // (tx, account, market) -> withdrawals not yet paid back, in order.
+        let mut open: HashMap<(String, String, i64), Vec<Loan>> = HashMap::new();
+        read(
+            "SELECT tx_hash, block_number, log_index, contract, account_owner, kind, market, amount
+             FROM dydx_transfers
+             ORDER BY block_number, log_index",
+            &mut |r, loans| {
+                let market: i64 = r.get(6)?;
+                let key = (r.get(0)?, r.get::<_, String>(4)?.to_ascii_lowercase(), market);
+                let moved = amount(r, 7)?;
+                if r.get::<_, String>(5)? == "withdraw" {
+                    let asset = usize::try_from(market)
+                        .ok()
+                        .and_then(|m| DYDX_MARKETS.get(m))
+                        .map(|a| a.to_string());
+                    open.entry(key).or_default().push(loan(r, "dydx", asset, moved, None)?);
+                } else if let Some(withdrawals) = open.get_mut(&key) {
+                    if let Some(i) = withdrawals.iter().position(|w| moved >= w.amount) {
+                        let mut w = withdrawals.remove(i);
+                        w.fee = Some(moved - w.amount);
+                        loans.push(w);
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+    Ok(loans)
+}
+
+/// The transfers and swaps of `hashes`, by transaction.
+fn txs(conn: &Connection, hashes: &HashSet<String>) -> Result<HashMap<String, Tx>> {
+    let mut txs: HashMap<String, Tx> = HashMap::new();
+    if hashes.is_empty() {
+        return Ok(txs);
+    }
+    let mut stmt =
+        conn.prepare("SELECT tx_hash, token, from_addr, to_addr, amount FROM erc20_transfers")?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let hash: String = r.get(0)?;
+        if !hashes.contains(&hash) {
+            continue;
+        }
+        let lower =
+            |i: usize| -> Result<String> { Ok(r.get::<_, String>(i)?.to_ascii_lowercase()) };
+        let transfer = (lower(1)?, lower(2)?, lower(3)?, read_amount(r.get_ref(4)?)?);
+        txs.entry(hash).or_default().transfers.push(transfer);
+    }
+    let mut stmt = conn.prepare("SELECT tx_hash, lower(pool) FROM dex_swaps")?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let hash: String = r.get(0)?;
+        if hashes.contains(&hash) {
+            txs.entry(hash).or_default().swap_pools.push(r.get(1)?);
+        }
+    }
+    Ok(txs)
+}
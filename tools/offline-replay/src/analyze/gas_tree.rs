@@ -0,0 +1,197 @@
+//! One transaction's call tree from the `traces` table (see traces.rs) with
+//! the gas each frame used, printed as a tree, and with `--out` written as
+//! JSON, so where a transaction's gas went can be read without tracing it
+//! again on a node.
+//!
+//! Each frame shows its number (pre-order, as the other trace analyses
+//! count), call type, target and function, the gas it used with its
+//! subcalls (callTracer's `gasUsed`), and `self`: that less what its
+//! subcalls used, which is the gas spent in the frame's own code and on
+//! calling out. The share is of the transaction's gas. The transaction's own
+//! frame includes the intrinsic gas and is net of refunds, as the receipt
+//! is. Failed frames are shown with their error, since the gas they burned
+//! was paid for.
+//!
+//! `--min-gas N` leaves frames that used less than N out of the printed
+//! tree, counting them on their parent's line; the JSON has every frame.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+
+use super::traces::{calls, check_traces, failed};
+use crate::{open_existing, rpc, views};
+
+#[derive(Args, Debug)]
+pub struct GasTreeArgs {
+    /// SQLite database with a `traces` table, as written by scripts/capture.py
+    db: PathBuf,
+
+    /// Hash of the transaction
+    #[arg(long)]
+    tx: String,
+
+    /// Leave frames that used less gas than this out of the printed tree
+    #[arg(long, default_value_t = 0)]
+    min_gas: u64,
+
+    /// Name functions from `function_signatures` and the common ones
+    #[arg(long)]
+    names: bool,
+
+    /// Also write the tree to this JSON file
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Tree {
+    tx_hash: String,
+    block_number: i64,
+    gas_used: u64,
+    root: Frame,
+}
+
+#[derive(Serialize)]
+struct Frame {
+    frame: usize,
+    depth: usize,
+    kind: String,
+    from: String,
+    to: String,
+    /// 0x for a plain transfer.
+    selector: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function: Option<String>,
+    /// What the frame was given.
+    gas: u64,
+    /// What it used, its subcalls included.
+    gas_used: u64,
+    /// What it used less its subcalls' `gas_used`.
+    self_gas: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    calls: Vec<Frame>,
+}
+
+pub fn run(args: GasTreeArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    check_traces(&conn)?;
+    let tx = args.tx.to_ascii_lowercase();
+    let found: Option<(i64, String, String)> = conn
+        .query_row(
+            "SELECT block_number, tx_hash, trace_json FROM traces WHERE lower(tx_hash) = ?1",
+            [&tx],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )
+        .optional()?;
+    let Some((block_number, tx_hash, json)) = found else {
+        eyre::bail!("no trace of {tx} in the traces table");
+    };
+    let trace: Value =
+        serde_json::from_str(&json).with_context(|| format!("Bad trace_json for {tx_hash}"))?;
+    let names = if args.names { Some(views::function_names(&conn)?) } else { None };
+
+    let root = frame(&trace, 0, &mut 0, names.as_ref())?;
+    let tree = Tree { tx_hash, block_number, gas_used: root.gas_used, root };
+
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "{} in block {}: {} gas", tree.tx_hash, tree.block_number, tree.gas_used)?;
+    print(&mut out, &tree.root, tree.gas_used, args.min_gas, "", "")?;
+
+    if let Some(path) = &args.out {
+        let json = serde_json::to_string_pretty(&tree)?;
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("Cannot write {}", path.display()))?;
+        info!(out = %path.display(), frames = count(&tree.root), "wrote gas tree");
+    }
+    Ok(())
+}
+
+/// A callTracer frame and its subcalls, numbering frames in pre-order from
+/// `next`.
+fn frame(
+    v: &Value,
+    depth: usize,
+    next: &mut usize,
+    names: Option<&HashMap<String, String>>,
+) -> Result<Frame> {
+    let number = *next;
+    *next += 1;
+    let gas = |key: &str| -> Result<u64> {
+        match &v[key] {
+            Value::Null => Ok(0),
+            g => Ok(rpc::quantity(g)? as u64),
+        }
+    };
+    let address = |key: &str| v[key].as_str().unwrap_or_default().to_ascii_lowercase();
+    let input = v["input"].as_str().unwrap_or_default().to_ascii_lowercase();
+    let selector = input.get(..10).unwrap_or("0x").to_string();
+    let mut subcalls = Vec::new();
+    for call in calls(v) {
+        subcalls.push(frame(call, depth + 1, next, names)?);
+    }
+    let gas_used = gas("gasUsed")?;
+    let below: u64 = subcalls.iter().map(|f| f.gas_used).sum();
+    Ok(Frame {
+        frame: number,
+        depth,
+        kind: v["type"].as_str().unwrap_or("CALL").to_ascii_uppercase(),
+        from: address("from"),
+        to: address("to"),
+        function: names.and_then(|n| n.get(&selector)).cloned(),
+        selector,
+        gas: gas("gas")?,
+        gas_used,
+        self_gas: gas_used.saturating_sub(below),
+        error: failed(v).then(|| v["error"].as_str().unwrap_or("failed").to_string()),
+        calls: subcalls,
+    })
+}
+
+/// Print `f` and the subcalls that used at least `min_gas`, `lead` before
+/// its own line and `indent` before its subcalls'.
+fn print(
+    out: &mut impl Write,
+    f: &Frame,
+    total: u64,
+    min_gas: u64,
+    lead: &str,
+    indent: &str,
+) -> Result<()> {
+    let share = if total == 0 { 0.0 } else { f.gas_used as f64 * 100.0 / total as f64 };
+    let call = f.function.as_deref().unwrap_or(&f.selector);
+    write!(
+        out,
+        "{lead}[{}] {} {} {call}  {} gas (self {}, {share:.1}%)",
+        f.frame, f.kind, f.to, f.gas_used, f.self_gas
+    )?;
+    if let Some(error) = &f.error {
+        write!(out, "  reverted: {error}")?;
+    }
+    let (shown, hidden): (Vec<&Frame>, Vec<&Frame>) =
+        f.calls.iter().partition(|c| c.gas_used >= min_gas);
+    if !hidden.is_empty() {
+        let gas: u64 = hidden.iter().map(|c| c.gas_used).sum();
+        write!(out, "  +{} calls under {min_gas} gas ({gas} gas)", hidden.len())?;
+    }
+    writeln!(out)?;
+    for (i, call) in shown.iter().enumerate() {
+        let last = i + 1 == shown.len();
+        let (branch, rest) = if last { ("└─ ", "   ") } else { ("├─ ", "│  ") };
+        print(out, call, total, min_gas, &format!("{indent}{branch}"), &format!("{indent}{rest}"))?;
+    }
+    Ok(())
+}
+
+/// The frames of a tree, itself included.
+fn count(f: &Frame) -> usize {
+    1 + f.calls.iter().map(count).sum::<usize>()
+}
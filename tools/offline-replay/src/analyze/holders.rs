@@ -0,0 +1,187 @@
+//! An ERC-20 token's holders and their balances at a block, folded from
+//! `erc20_transfers` into a `token_holders` table, for airdrops and
+//! compensation after an incident.
+//!
+//! Balances are summed from the start of the dataset, as in balances.rs, so
+//! they are exact only when the dataset starts at the token's deployment;
+//! otherwise `--opening` gives the balances before its first block (a CSV
+//! with `holder` and `balance` columns, such as a block explorer's holder
+//! export) to start from. Holders left with nothing are left out, and so is
+//! the zero address. A negative balance means the holder's opening balance
+//! is missing, and is warned about. `share` is the holder's fraction of the
+//! total held (by holders with a positive balance, whose shares add up to
+//! 1), as a REAL.
+//!
+//! Rows are keyed by (token, at_block, holder); a rerun replaces the
+//! snapshot of its token and block.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::{I256, U256};
+use clap::Args;
+use eyre::{eyre, Result};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::decode;
+use crate::normalize::read_amount;
+use crate::open_existing;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS token_holders (
+    token    TEXT    NOT NULL,
+    at_block INTEGER NOT NULL,
+    holder   TEXT    NOT NULL,
+    balance  TEXT    NOT NULL,
+    share    REAL,
+    PRIMARY KEY (token, at_block, holder)
+);
+";
+
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+#[derive(Args, Debug)]
+pub struct HoldersArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// The token
+    #[arg(long)]
+    token: String,
+
+    /// Balances after this block [default: the last block loaded]
+    #[arg(long)]
+    at_block: Option<i64>,
+
+    /// CSV of balances before the dataset's first block (holder, balance)
+    #[arg(long)]
+    opening: Option<PathBuf>,
+
+    /// Write the snapshot to this CSV file instead of the `token_holders` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct OpeningRow {
+    holder: String,
+    balance: String,
+}
+
+pub fn run(args: HoldersArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    decode::erc20_transfers(&conn)?;
+    let token = args.token.to_ascii_lowercase();
+    let at_block = match args.at_block {
+        Some(block) => block,
+        None => conn
+            .query_row("SELECT MAX(number) FROM blocks", [], |r| r.get::<_, Option<i64>>(0))?
+            .ok_or_else(|| eyre!("no blocks loaded; give --at-block"))?,
+    };
+
+    let mut balances = match &args.opening {
+        Some(path) => opening(path)?,
+        None => BTreeMap::new(),
+    };
+    fold(&conn, &token, at_block, &mut balances)?;
+    balances.retain(|_, b| !b.is_zero());
+    let negative = balances.values().filter(|b| b.is_negative()).count();
+    if negative > 0 {
+        warn!(
+            holders = negative,
+            "negative balances; the dataset starts after the token's deployment, \
+             so give their balances before it with --opening"
+        );
+    }
+    let total = balances
+        .values()
+        .filter(|b| b.is_positive())
+        .fold(U256::ZERO, |total, b| total.saturating_add(b.into_raw()));
+    let share = |b: &I256| b.is_positive().then(|| f64::from(b.into_raw()) / f64::from(total));
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record(["token", "at_block", "holder", "balance", "share"])?;
+            for (holder, balance) in &balances {
+                w.write_record([
+                    token.clone(),
+                    at_block.to_string(),
+                    holder.clone(),
+                    balance.to_string(),
+                    share(balance).map(|s| s.to_string()).unwrap_or_default(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN;")?;
+            conn.execute(
+                "DELETE FROM token_holders WHERE token = ?1 AND at_block = ?2",
+                params![token, at_block],
+            )?;
+            let mut stmt = conn.prepare(
+                "INSERT INTO token_holders (token, at_block, holder, balance, share)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (holder, balance) in &balances {
+                stmt.execute(params![
+                    token,
+                    at_block,
+                    holder,
+                    balance.to_string(),
+                    share(balance)
+                ])?;
+            }
+            drop(stmt);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("token_holders".to_string(), |p| p.display().to_string());
+    info!(out, token, at_block, holders = balances.len(), total = %total, "wrote holders");
+    Ok(())
+}
+
+/// Add the token's transfers up to `at_block` to `balances`, by holder.
+fn fold(
+    conn: &Connection,
+    token: &str,
+    at_block: i64,
+    balances: &mut BTreeMap<String, I256>,
+) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT lower(from_addr), lower(to_addr), amount FROM erc20_transfers
+         WHERE lower(token) = ?1 AND block_number <= ?2
+         ORDER BY block_number, log_index",
+    )?;
+    let mut rows = stmt.query(params![token, at_block])?;
+    while let Some(r) = rows.next()? {
+        let amount = I256::try_from(read_amount(r.get_ref(2)?)?).unwrap_or(I256::MAX);
+        for (holder, delta) in [(r.get::<_, String>(0)?, -amount), (r.get(1)?, amount)] {
+            if holder == ZERO_ADDRESS {
+                continue;
+            }
+            let balance = balances.entry(holder).or_default();
+            *balance = balance.saturating_add(delta);
+        }
+    }
+    Ok(())
+}
+
+/// The balances of an `--opening` CSV, by lowercase holder.
+fn opening(path: &Path) -> Result<BTreeMap<String, I256>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut balances = BTreeMap::new();
+    for (i, row) in reader.deserialize().enumerate() {
+        let row: OpeningRow = row.map_err(|e| eyre!("{} row {}: {e}", path.display(), i + 1))?;
+        let balance: U256 = row.balance.trim().parse().map_err(|_| {
+            eyre!("{} row {}: {:?} is not a balance", path.display(), i + 1, row.balance)
+        })?;
+        let balance = I256::try_from(balance).unwrap_or(I256::MAX);
+        balances.insert(row.holder.trim().to_ascii_lowercase(), balance);
+    }
+    Ok(balances)
+}
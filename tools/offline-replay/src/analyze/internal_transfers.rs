@@ -0,0 +1,122 @@
+//! ETH moved frame by frame, from the call trees of the `traces` table (see
+//! traces.rs), into `internal_transfers`.
+//!
+//! One row per frame that carried value: its position in the trace
+//! (`frame`, pre-order, as in `reentrancy_candidates`), its call `depth` (0
+//! for the transaction's own call), `kind`, `from_addr`, `to_addr` and
+//! `value` in wei. The same frames as eth_deltas.rs sums per block, so the
+//! ETH flow through a contract is a query rather than a walk over traces.
+//! A rerun replaces the table.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::params;
+use serde_json::Value;
+use tracing::info;
+
+use super::traces::{self, check_traces, Transfer};
+use crate::normalize::Normalize;
+use crate::open_existing;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS internal_transfers (
+    tx_hash      TEXT    NOT NULL,
+    block_number INTEGER NOT NULL,
+    frame        INTEGER NOT NULL,
+    depth        INTEGER NOT NULL,
+    kind         TEXT    NOT NULL,
+    from_addr    TEXT    NOT NULL,
+    to_addr      TEXT    NOT NULL,
+    value        TEXT    NOT NULL,
+    PRIMARY KEY (tx_hash, frame)
+);
+CREATE INDEX IF NOT EXISTS idx_internal_transfers_from ON internal_transfers(from_addr);
+CREATE INDEX IF NOT EXISTS idx_internal_transfers_to ON internal_transfers(to_addr);
+";
+
+#[derive(Args, Debug)]
+pub struct InternalTransfersArgs {
+    /// SQLite database with a `traces` table, as written by scripts/capture.py
+    db: PathBuf,
+
+    /// Write the transfers to this CSV file instead of the table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+pub fn run(args: InternalTransfersArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    check_traces(&conn)?;
+    let norm = Normalize::from_meta(&conn)?;
+
+    let mut found: Vec<(i64, String, Transfer)> = Vec::new();
+    let mut stmt = conn.prepare("SELECT block_number, tx_hash, trace_json FROM traces")?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let block: i64 = r.get(0)?;
+        let tx: String = r.get(1)?;
+        let trace: Value = serde_json::from_str(&r.get::<_, String>(2)?)
+            .with_context(|| format!("Bad trace_json for {tx}"))?;
+        traces::transfers(&trace, &mut |t| found.push((block, tx.clone(), t)))
+            .with_context(|| format!("Bad trace_json for {tx}"))?;
+    }
+    drop(rows);
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "tx_hash",
+                "block_number",
+                "frame",
+                "depth",
+                "kind",
+                "from_addr",
+                "to_addr",
+                "value",
+            ])?;
+            for (block, tx, t) in &found {
+                w.write_record([
+                    tx.clone(),
+                    block.to_string(),
+                    t.frame.to_string(),
+                    t.depth.to_string(),
+                    t.kind.clone(),
+                    t.from.clone(),
+                    t.to.clone(),
+                    t.value.to_string(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM internal_transfers;")?;
+            let mut insert = conn.prepare(
+                "INSERT INTO internal_transfers
+                 (tx_hash, block_number, frame, depth, kind, from_addr, to_addr, value)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8)",
+            )?;
+            for (block, tx, t) in &found {
+                insert.execute(params![
+                    tx,
+                    block,
+                    t.frame as i64,
+                    t.depth as i64,
+                    t.kind,
+                    norm.address("from_addr", &t.from)?,
+                    norm.address("to_addr", &t.to)?,
+                    t.value.to_string(),
+                ])?;
+            }
+            drop(insert);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out =
+        args.out.as_ref().map_or("internal_transfers".to_string(), |p| p.display().to_string());
+    info!(out, transfers = found.len(), "wrote internal transfers");
+    Ok(())
+}
@@ -0,0 +1,150 @@
+//! `analyze` — tables derived from a loaded database for incident analysis.
+//!
+//!   analyze candles     per-pool OHLCV candles from DEX swaps
+//!   analyze tvl         per-asset TVL of a lending protocol over the range
+//!   analyze balances    ERC-20 balances per holder over time
+//!   analyze holders     an ERC-20 token's holders at a block
+//!   analyze allowances  outstanding ERC-20 allowances at a block
+//!   analyze eth-deltas  native ETH balance changes per block, from traces
+//!   analyze internal-transfers
+//!                       each call that moved ETH, from traces
+//!   analyze creations   contract deployments, factory ones included when
+//!                       there are traces
+//!   analyze flashloans  flash loans per lender, and the swaps they paid for
+//!   analyze reentrancy  calls back into a contract before it returns, from
+//!                       traces
+//!   analyze gas-tree    one transaction's call tree with the gas of each
+//!                       frame, from traces
+//!   analyze revert-reasons
+//!                       why failed calls failed, from traces
+//!   analyze storage     slot writes from state_diffs, decoded with solc
+//!                       storage layouts
+//!   analyze overlap     addresses active in this and another database
+//!   analyze builders    fee recipients' priority fees, burned base fee and
+//!                       proposer payments
+//!   analyze withdrawal-flows
+//!                       what validator withdrawal addresses did after
+//!                       their withdrawals
+//!   analyze euler       the Euler exploit's steps per transaction (`euler`
+//!                       feature)
+//!   analyze v3-pool     a Uniswap V3 pool's liquidity by tick at a block
+//!                       (`uniswap` feature)
+//!
+//! Each analysis decodes the events it needs (see decode.rs and protocols/)
+//! before reading them, so it can be run straight after a load.
+
+use clap::{Args, Subcommand};
+use eyre::Result;
+
+mod allowances;
+mod balances;
+mod builders;
+mod candles;
+mod creations;
+mod eth_deltas;
+#[cfg(feature = "euler")]
+mod euler;
+mod flashloans;
+mod gas_tree;
+mod holders;
+mod internal_transfers;
+mod overlap;
+mod reentrancy;
+mod revert_reasons;
+mod slots;
+mod storage;
+pub(crate) mod traces;
+mod tvl;
+#[cfg(feature = "uniswap")]
+mod v3_pool;
+mod withdrawal_flows;
+
+#[derive(Args, Debug)]
+pub struct AnalyzeArgs {
+    #[command(subcommand)]
+    command: AnalyzeCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum AnalyzeCommand {
+    /// Per-pool OHLCV candles from Uniswap V2/V3-style swaps
+    Candles(candles::CandlesArgs),
+
+    /// Per-asset TVL from a lending protocol's deposit/withdraw/borrow/repay events
+    Tvl(tvl::TvlArgs),
+
+    /// Per-block ERC-20 balances of every (or the given) holder
+    Balances(balances::BalancesArgs),
+
+    /// An ERC-20 token's holders and their balances at a block
+    Holders(holders::HoldersArgs),
+
+    /// Outstanding ERC-20 allowances per token, owner and spender at a block
+    Allowances(allowances::AllowancesArgs),
+
+    /// Per-block native ETH balance changes, internal transfers included (needs traces)
+    EthDeltas(eth_deltas::EthDeltasArgs),
+
+    /// Every call that moved ETH, with its depth, sender and recipient (needs traces)
+    InternalTransfers(internal_transfers::InternalTransfersArgs),
+
+    /// Contracts created, by transactions and (with traces) by factories via CREATE/CREATE2
+    Creations(creations::CreationsArgs),
+
+    /// Flash loans of Aave, Balancer, Uniswap and dYdX, and whether swaps moved the funds
+    Flashloans(flashloans::FlashloansArgs),
+
+    /// Transactions in which a contract is called back before its outer call returns (needs traces)
+    Reentrancy(reentrancy::ReentrancyArgs),
+
+    /// A transaction's call tree with the gas each frame used, printed and as JSON (needs traces)
+    GasTree(gas_tree::GasTreeArgs),
+
+    /// Decoded revert reasons of failed calls, custom errors named from ABIs (reasons need traces)
+    RevertReasons(revert_reasons::RevertReasonsArgs),
+
+    /// Storage slot writes per contract from state_diffs, decoded with a solc storage layout
+    Storage(storage::StorageArgs),
+
+    /// Addresses active in both of two databases, with their transactions and first/last seen
+    Overlap(overlap::OverlapArgs),
+
+    /// Priority fees, burned base fee and proposer payments per block fee recipient
+    Builders(builders::BuildersArgs),
+
+    /// Transactions and token transfers out of validator withdrawal addresses after their withdrawals
+    WithdrawalFlows(withdrawal_flows::WithdrawalFlowsArgs),
+
+    /// The Euler exploit step by step: flash loan, mint, donation, liquidation
+    #[cfg(feature = "euler")]
+    Euler(euler::EulerArgs),
+
+    /// A Uniswap V3 pool's liquidity by tick at a block, from its mints and burns
+    #[cfg(feature = "uniswap")]
+    V3Pool(v3_pool::V3PoolArgs),
+}
+
+pub fn run(args: AnalyzeArgs) -> Result<()> {
+    match args.command {
+        AnalyzeCommand::Candles(args) => candles::run(args),
+        AnalyzeCommand::Tvl(args) => tvl::run(args),
+        AnalyzeCommand::Balances(args) => balances::run(args),
+        AnalyzeCommand::Holders(args) => holders::run(args),
+        AnalyzeCommand::Allowances(args) => allowances::run(args),
+        AnalyzeCommand::EthDeltas(args) => eth_deltas::run(args),
+        AnalyzeCommand::InternalTransfers(args) => internal_transfers::run(args),
+        AnalyzeCommand::Creations(args) => creations::run(args),
+        AnalyzeCommand::Flashloans(args) => flashloans::run(args),
+        AnalyzeCommand::Reentrancy(args) => reentrancy::run(args),
+        AnalyzeCommand::GasTree(args) => gas_tree::run(args),
+        AnalyzeCommand::RevertReasons(args) => revert_reasons::run(args),
+        AnalyzeCommand::Storage(args) => storage::run(args),
+        AnalyzeCommand::Overlap(args) => overlap::run(args),
+        AnalyzeCommand::Builders(args) => builders::run(args),
+        AnalyzeCommand::WithdrawalFlows(args) => withdrawal_flows::run(args),
+        #[cfg(feature = "euler")]
+        AnalyzeCommand::Euler(args) => euler::run(args),
+        #[cfg(feature = "uniswap")]
+        AnalyzeCommand::V3Pool(args) => v3_pool::run(args),
+    }
+}
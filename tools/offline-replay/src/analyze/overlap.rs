@@ -0,0 +1,220 @@
+//! Addresses active in two databases, for linking an attacker across
+//! incidents, into `address_overlap` of the first one.
+//!
+//! An address is active in a database where it sent a transaction, or with
+//! `--senders` left out, received one too. Each address active in both is
+//! a row with, per database, its transactions and the blocks (and block
+//! timestamps) it was first and last seen in; the `other_` columns are the
+//! second database's. Routers, WETH and other popular contracts show up in
+//! any two datasets that overlap in time, so `--senders` and `--min-txs`
+//! are the usual way to get down to the accounts worth a look.
+//!
+//! Rows are keyed by (other, address), `other` being the second database's
+//! path; a rerun replaces the rows of its database.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::{params, Connection};
+use tracing::info;
+
+use crate::normalize::{read_key, Normalize};
+use crate::open_existing;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS address_overlap (
+    other             TEXT    NOT NULL,
+    address           TEXT    NOT NULL,
+    txs               INTEGER NOT NULL,
+    first_block       INTEGER NOT NULL,
+    last_block        INTEGER NOT NULL,
+    first_seen        INTEGER,
+    last_seen         INTEGER,
+    other_txs         INTEGER NOT NULL,
+    other_first_block INTEGER NOT NULL,
+    other_last_block  INTEGER NOT NULL,
+    other_first_seen  INTEGER,
+    other_last_seen   INTEGER,
+    PRIMARY KEY (other, address)
+);
+";
+
+#[derive(Args, Debug)]
+pub struct OverlapArgs {
+    /// SQLite database produced by offline-replay; the table goes here
+    db: PathBuf,
+
+    /// The database to compare it with
+    other: PathBuf,
+
+    /// Only count transactions an address sent, not those it received
+    #[arg(long)]
+    senders: bool,
+
+    /// Leave out addresses with fewer transactions than this in either database
+    #[arg(long, default_value_t = 1)]
+    min_txs: i64,
+
+    /// Write the addresses to this CSV file instead of the `address_overlap` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+/// An address's transactions in one database.
+#[derive(Clone, Copy)]
+struct Activity {
+    txs: i64,
+    first_block: i64,
+    last_block: i64,
+    first_seen: Option<i64>,
+    last_seen: Option<i64>,
+}
+
+impl Activity {
+    fn merge(&mut self, other: Activity) {
+        self.txs += other.txs;
+        self.first_block = self.first_block.min(other.first_block);
+        self.last_block = self.last_block.max(other.last_block);
+        self.first_seen = min(self.first_seen, other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+}
+
+pub fn run(args: OverlapArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let other_conn = open_existing(&args.other)?;
+    let other = std::fs::canonicalize(&args.other)
+        .with_context(|| format!("Cannot resolve {}", args.other.display()))?
+        .display()
+        .to_string();
+    let norm = Normalize::from_meta(&conn)?;
+
+    let ours = activity(&conn, args.senders)?;
+    let theirs = activity(&other_conn, args.senders)?;
+    let mut shared: Vec<(&String, &Activity, &Activity)> = ours
+        .iter()
+        .filter_map(|(address, a)| theirs.get(address).map(|b| (address, a, b)))
+        .filter(|(_, a, b)| a.txs >= args.min_txs && b.txs >= args.min_txs)
+        .collect();
+    shared.sort_by(|x, y| (y.1.txs + y.2.txs).cmp(&(x.1.txs + x.2.txs)).then(x.0.cmp(y.0)));
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "other",
+                "address",
+                "txs",
+                "first_block",
+                "last_block",
+                "first_seen",
+                "last_seen",
+                "other_txs",
+                "other_first_block",
+                "other_last_block",
+                "other_first_seen",
+                "other_last_seen",
+            ])?;
+            let opt = |v: Option<i64>| v.map_or(String::new(), |v| v.to_string());
+            for (address, a, b) in &shared {
+                w.write_record([
+                    other.clone(),
+                    address.to_string(),
+                    a.txs.to_string(),
+                    a.first_block.to_string(),
+                    a.last_block.to_string(),
+                    opt(a.first_seen),
+                    opt(a.last_seen),
+                    b.txs.to_string(),
+                    b.first_block.to_string(),
+                    b.last_block.to_string(),
+                    opt(b.first_seen),
+                    opt(b.last_seen),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN;")?;
+            conn.execute("DELETE FROM address_overlap WHERE other = ?1", [&other])?;
+            let mut stmt = conn.prepare(
+                "INSERT INTO address_overlap
+                 (other, address, txs, first_block, last_block, first_seen, last_seen,
+                  other_txs, other_first_block, other_last_block, other_first_seen,
+                  other_last_seen)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12)",
+            )?;
+            for (address, a, b) in &shared {
+                stmt.execute(params![
+                    other,
+                    norm.address("address", address)?,
+                    a.txs,
+                    a.first_block,
+                    a.last_block,
+                    a.first_seen,
+                    a.last_seen,
+                    b.txs,
+                    b.first_block,
+                    b.last_block,
+                    b.first_seen,
+                    b.last_seen,
+                ])?;
+            }
+            drop(stmt);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("address_overlap".to_string(), |p| p.display().to_string());
+    info!(
+        out,
+        other,
+        addresses = ours.len(),
+        other_addresses = theirs.len(),
+        shared = shared.len(),
+        "wrote address overlap"
+    );
+    Ok(())
+}
+
+/// Each active address's transactions, by lowercase address.
+fn activity(conn: &Connection, senders: bool) -> Result<HashMap<String, Activity>> {
+    let received = if senders {
+        ""
+    } else {
+        "UNION ALL
+         SELECT to_addr, block_number FROM transactions
+         WHERE to_addr IS NOT NULL AND to_addr <> from_addr"
+    };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT t.address, COUNT(*), MIN(t.block_number), MAX(t.block_number),
+                MIN(b.timestamp), MAX(b.timestamp)
+         FROM (SELECT from_addr AS address, block_number FROM transactions {received}) t
+         LEFT JOIN blocks b ON b.number = t.block_number AND b.is_canonical = 1
+         GROUP BY t.address"
+    ))?;
+    let mut rows = stmt.query([])?;
+    let mut found: HashMap<String, Activity> = HashMap::new();
+    while let Some(r) = rows.next()? {
+        let Some(address) = read_key(r.get_ref(0)?)? else { continue };
+        let a = Activity {
+            txs: r.get(1)?,
+            first_block: r.get(2)?,
+            last_block: r.get(3)?,
+            first_seen: r.get(4)?,
+            last_seen: r.get(5)?,
+        };
+        // Without --address-case, an address may be stored in more than one case.
+        found.entry(address.to_ascii_lowercase()).and_modify(|seen| seen.merge(a)).or_insert(a);
+    }
+    Ok(found)
+}
+
+fn min(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        _ => a.or(b),
+    }
+}
@@ -0,0 +1,213 @@
+//! Reentrancy candidates, from the call trees of the `traces` table (see
+//! eth_deltas.rs), into `reentrancy_candidates`.
+//!
+//! A frame runs in the storage of its `to`, or of its caller for a delegate
+//! call. A frame re-enters a contract when it runs in that contract's
+//! storage while an outer frame of the same contract, with another contract
+//! in between, has not returned yet — the shape of the DAO and of most
+//! reentrancy exploits since. Each such frame is a row:
+//!
+//!   contract       the contract re-entered
+//!   frame          the re-entering frame's position in the trace (pre-order)
+//!   depth          its call depth; `outer_depth` that of the outer frame
+//!   kind           its call type; a STATICCALL is read-only reentrancy
+//!   selector       the function it calls (0x for a plain transfer)
+//!   same_function  1 when the outer frame called the same function
+//!   path           the calls from the outer frame down to it, as
+//!                  `KIND to selector` joined by ` > `
+//!
+//! Failed frames are skipped with their subcalls, as they changed nothing.
+//! A call back into a contract is not always an attack — callbacks such as
+//! flash loans and ERC-777 hooks look the same — so these are candidates to
+//! read, not findings. A rerun replaces the table.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::params;
+use serde_json::Value;
+use tracing::info;
+
+use super::traces::{self, check_traces};
+use crate::open_existing;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS reentrancy_candidates (
+    tx_hash       TEXT    NOT NULL,
+    block_number  INTEGER NOT NULL,
+    frame         INTEGER NOT NULL,
+    contract      TEXT    NOT NULL,
+    outer_depth   INTEGER NOT NULL,
+    depth         INTEGER NOT NULL,
+    kind          TEXT    NOT NULL,
+    selector      TEXT    NOT NULL,
+    same_function INTEGER NOT NULL,
+    path          TEXT    NOT NULL,
+    PRIMARY KEY (tx_hash, frame)
+);
+CREATE INDEX IF NOT EXISTS idx_reentrancy_contract ON reentrancy_candidates(contract);
+";
+
+#[derive(Args, Debug)]
+pub struct ReentrancyArgs {
+    /// SQLite database with a `traces` table, as written by scripts/capture.py
+    db: PathBuf,
+
+    /// Write the candidates to this CSV file instead of the table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+/// An unreturned frame on the way down to the one being walked.
+struct Hop {
+    kind: String,
+    to: String,
+    /// Whose storage it runs in.
+    context: String,
+    selector: String,
+}
+
+struct Candidate {
+    frame: usize,
+    contract: String,
+    outer_depth: usize,
+    depth: usize,
+    kind: String,
+    selector: String,
+    same_function: bool,
+    path: String,
+}
+
+pub fn run(args: ReentrancyArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    check_traces(&conn)?;
+
+    let mut found = Vec::new();
+    let mut stmt = conn.prepare("SELECT block_number, tx_hash, trace_json FROM traces")?;
+    let mut rows = stmt.query([])?;
+    let mut txs = 0;
+    while let Some(r) = rows.next()? {
+        let block: i64 = r.get(0)?;
+        let tx: String = r.get(1)?;
+        let trace: Value = serde_json::from_str(&r.get::<_, String>(2)?)
+            .with_context(|| format!("Bad trace_json for {tx}"))?;
+        let candidates = candidates(&trace)?;
+        txs += !candidates.is_empty() as usize;
+        found.extend(candidates.into_iter().map(|c| (block, tx.clone(), c)));
+    }
+    drop(rows);
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "tx_hash",
+                "block_number",
+                "frame",
+                "contract",
+                "outer_depth",
+                "depth",
+                "kind",
+                "selector",
+                "same_function",
+                "path",
+            ])?;
+            for (block, tx, c) in &found {
+                w.write_record([
+                    tx.clone(),
+                    block.to_string(),
+                    c.frame.to_string(),
+                    c.contract.clone(),
+                    c.outer_depth.to_string(),
+                    c.depth.to_string(),
+                    c.kind.clone(),
+                    c.selector.clone(),
+                    (c.same_function as u8).to_string(),
+                    c.path.clone(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM reentrancy_candidates;")?;
+            let mut insert = conn.prepare(
+                "INSERT INTO reentrancy_candidates
+                 (tx_hash, block_number, frame, contract, outer_depth, depth, kind, selector,
+                  same_function, path)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10)",
+            )?;
+            for (block, tx, c) in &found {
+                insert.execute(params![
+                    tx,
+                    block,
+                    c.frame as i64,
+                    c.contract,
+                    c.outer_depth as i64,
+                    c.depth as i64,
+                    c.kind,
+                    c.selector,
+                    c.same_function,
+                    c.path,
+                ])?;
+            }
+            drop(insert);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out =
+        args.out.as_ref().map_or("reentrancy_candidates".to_string(), |p| p.display().to_string());
+    info!(out, candidates = found.len(), transactions = txs, "wrote reentrancy candidates");
+    Ok(())
+}
+
+/// The reentrancy candidates of a callTracer trace.
+fn candidates(trace: &Value) -> Result<Vec<Candidate>> {
+    // The frames from the transaction's call down to the one visited.
+    let mut stack: Vec<Hop> = Vec::new();
+    let mut found = Vec::new();
+    traces::walk(trace, &mut |f| {
+        if f.failed {
+            return Ok(());
+        }
+        stack.truncate(f.depth);
+        let address = |key: &str| f.call[key].as_str().unwrap_or_default().to_ascii_lowercase();
+        let kind = f.call["type"].as_str().unwrap_or("CALL").to_ascii_uppercase();
+        let to = address("to");
+        let context = match kind.as_str() {
+            "DELEGATECALL" | "CALLCODE" => {
+                stack.last().map_or_else(|| address("from"), |p| p.context.clone())
+            }
+            _ => to.clone(),
+        };
+        let input = f.call["input"].as_str().unwrap_or_default().to_ascii_lowercase();
+        let selector = input.get(..10).unwrap_or("0x").to_string();
+
+        // Entering a contract from another one, while an outer frame of it is
+        // still running.
+        let entered = stack.last().is_some_and(|parent| parent.context != context);
+        if entered {
+            if let Some(outer) = stack.iter().position(|h| h.context == context) {
+                let mut path: Vec<String> = stack[outer..]
+                    .iter()
+                    .map(|h| format!("{} {} {}", h.kind, h.to, h.selector))
+                    .collect();
+                path.push(format!("{kind} {to} {selector}"));
+                found.push(Candidate {
+                    frame: f.number,
+                    contract: context.clone(),
+                    outer_depth: outer,
+                    depth: stack.len(),
+                    kind: kind.clone(),
+                    same_function: stack[outer].selector == selector,
+                    selector: selector.clone(),
+                    path: path.join(" > "),
+                });
+            }
+        }
+        stack.push(Hop { kind, to, context, selector });
+        Ok(())
+    })?;
+    Ok(found)
+}
@@ -0,0 +1,209 @@
+//! Why calls failed, from the call trees of the `traces` table (see
+//! traces.rs) and the transactions' receipt status, into `revert_reasons`.
+//!
+//! Each failed frame of a trace is a row: its number (pre-order, as the
+//! other trace analyses count), depth and `address`, `caught` when its
+//! caller went on (a try/catch, or a low-level call whose result was
+//! checked or not), and the reason its revert data gives, decoded as
+//! revert.rs describes, with `--abi` naming custom errors. A frame that
+//! halted rather than reverted (out of gas, an invalid opcode) is of kind
+//! `halt`, with the trace's error as the reason. `data` keeps the revert
+//! data as 0x-hex, for errors no ABI names.
+//!
+//! Receipts only say that a transaction failed, so a failed transaction
+//! without a trace is a row of kind `untraced` for its own call (frame 0),
+//! with no reason. `v_tx_with_block` shows frame 0's kind and reason next
+//! to each transaction. A rerun replaces the table.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use alloy_primitives::hex;
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::params;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use super::traces::{self, failed, has_traces};
+use crate::abi::{self, Entry};
+use crate::normalize::read_key;
+use crate::schema::REVERT_REASONS_SCHEMA;
+use crate::{open_existing, revert, rpc, views};
+
+#[derive(Args, Debug)]
+pub struct RevertReasonsArgs {
+    /// SQLite database produced by offline-replay, with a `traces` table for reasons
+    db: PathBuf,
+
+    /// ABI JSON file, or a directory of them, to name custom errors (repeatable)
+    #[arg(long)]
+    abi: Vec<PathBuf>,
+
+    /// Write the reasons to this CSV file instead of the table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+struct Failure {
+    tx_hash: String,
+    block_number: i64,
+    frame: usize,
+    depth: usize,
+    address: Option<String>,
+    caught: bool,
+    kind: &'static str,
+    reason: Option<String>,
+    data: Option<String>,
+}
+
+pub fn run(args: RevertReasonsArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let errors = abi::entries(&args.abi, "error")?;
+
+    let mut found = Vec::new();
+    let mut traced = HashSet::new();
+    if has_traces(&conn)? {
+        let mut stmt = conn.prepare("SELECT block_number, tx_hash, trace_json FROM traces")?;
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            let block: i64 = r.get(0)?;
+            let tx = r.get::<_, String>(1)?.to_ascii_lowercase();
+            let trace: Value = serde_json::from_str(&r.get::<_, String>(2)?)
+                .with_context(|| format!("Bad trace_json for {tx}"))?;
+            found.extend(failures(&trace, &errors)?.into_iter().map(|mut f| {
+                f.tx_hash.clone_from(&tx);
+                f.block_number = block;
+                f
+            }));
+            traced.insert(tx);
+        }
+    } else {
+        warn!("no traces table; failed transactions are listed without reasons");
+    }
+
+    let mut untraced = 0;
+    let mut stmt = conn.prepare("SELECT hash, block_number FROM transactions WHERE status = 0")?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let Some(tx) = read_key(r.get_ref(0)?)?.map(|h| h.to_ascii_lowercase()) else { continue };
+        if traced.contains(&tx) {
+            continue;
+        }
+        untraced += 1;
+        found.push(Failure {
+            tx_hash: tx,
+            block_number: r.get(1)?,
+            frame: 0,
+            depth: 0,
+            address: None,
+            caught: false,
+            kind: "untraced",
+            reason: None,
+            data: None,
+        });
+    }
+    drop(rows);
+    drop(stmt);
+    found.sort_by(|a, b| {
+        (a.block_number, &a.tx_hash, a.frame).cmp(&(b.block_number, &b.tx_hash, b.frame))
+    });
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "tx_hash",
+                "block_number",
+                "frame",
+                "depth",
+                "address",
+                "caught",
+                "kind",
+                "reason",
+                "data",
+            ])?;
+            for f in &found {
+                w.write_record([
+                    f.tx_hash.clone(),
+                    f.block_number.to_string(),
+                    f.frame.to_string(),
+                    f.depth.to_string(),
+                    f.address.clone().unwrap_or_default(),
+                    (f.caught as u8).to_string(),
+                    f.kind.to_string(),
+                    f.reason.clone().unwrap_or_default(),
+                    f.data.clone().unwrap_or_default(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(REVERT_REASONS_SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM revert_reasons;")?;
+            let mut insert = conn.prepare(
+                "INSERT INTO revert_reasons
+                 (tx_hash, block_number, frame, depth, address, caught, kind, reason, data)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9)",
+            )?;
+            for f in &found {
+                insert.execute(params![
+                    f.tx_hash,
+                    f.block_number,
+                    f.frame as i64,
+                    f.depth as i64,
+                    f.address,
+                    f.caught,
+                    f.kind,
+                    f.reason,
+                    f.data,
+                ])?;
+            }
+            drop(insert);
+            conn.execute_batch("COMMIT;")?;
+            views::refresh(&conn)?;
+        }
+    }
+    let out = args.out.as_ref().map_or("revert_reasons".to_string(), |p| p.display().to_string());
+    info!(out, failures = found.len(), untraced, "wrote revert reasons");
+    Ok(())
+}
+
+/// The frames of a callTracer trace that failed themselves, for the caller
+/// to fill in their block and transaction.
+fn failures(trace: &Value, errors: &HashMap<String, Entry>) -> Result<Vec<Failure>> {
+    let mut found = Vec::new();
+    traces::walk(trace, &mut |f| {
+        let frame = f.call;
+        if !failed(frame) {
+            return Ok(());
+        }
+        let error = frame["error"].as_str().unwrap_or_default();
+        let output = match &frame["output"] {
+            Value::Null => Vec::new(),
+            v => rpc::data(v)?,
+        };
+        let (kind, reason) = if error != "execution reverted" && !error.is_empty() {
+            ("halt", Some(error.to_string()))
+        } else if let (true, Some(reason)) = (output.is_empty(), frame["revertReason"].as_str()) {
+            // Some nodes decode Error(string) and drop the data.
+            ("error", Some(reason.to_string()))
+        } else {
+            let decoded = revert::decode(&output, errors);
+            (decoded.kind, decoded.reason)
+        };
+        found.push(Failure {
+            tx_hash: String::new(),
+            block_number: 0,
+            frame: f.number,
+            depth: f.depth,
+            address: frame["to"].as_str().map(str::to_ascii_lowercase),
+            caught: f.parent.is_some_and(|p| !failed(p)),
+            kind,
+            reason,
+            data: (!output.is_empty()).then(|| hex::encode_prefixed(&output)),
+        });
+        Ok(())
+    })?;
+    Ok(found)
+}
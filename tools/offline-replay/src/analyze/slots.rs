@@ -0,0 +1,350 @@
+//! Where Solidity keeps a contract's variables, from the storage layout solc
+//! writes (`solc --storage-layout`, or `storageLayout` in the standard JSON
+//! output selection), so a written slot can be named and its value decoded:
+//!
+//!   value types      packed into a slot from its low-order end, `offset`
+//!                    bytes in
+//!   structs, static  members or elements from the variable's slot on
+//!   arrays
+//!   mapping m        m[k] at keccak256(pad32(k) ++ slot)
+//!   dynamic array a  the length at the slot, a[i] from keccak256(slot) on
+//!   bytes, string    in the slot itself when under 32 bytes, the length
+//!                    (times 2, plus 1 when longer) otherwise
+//!
+//! A mapping slot is a hash, so its key can't be read back from it: keys
+//! are tried from candidates (32-byte words), mappings nested up to
+//! [`MAX_DEPTH`] deep.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use alloy_primitives::{hex, keccak256, Address, B256, I256, U256};
+use eyre::{eyre, Context, Result};
+use serde::Deserialize;
+
+/// Mappings within mappings tried, counting the outermost.
+pub const MAX_DEPTH: usize = 3;
+
+/// Elements of a dynamic array looked for past its data slot.
+const MAX_ELEMENTS: u64 = 1 << 32;
+
+pub struct Layout {
+    storage: Vec<Member>,
+    types: HashMap<String, TypeDef>,
+}
+
+#[derive(Deserialize)]
+struct RawLayout {
+    storage: Vec<Member>,
+    #[serde(default)]
+    types: Option<HashMap<String, TypeDef>>,
+}
+
+#[derive(Deserialize)]
+struct Member {
+    label: String,
+    offset: usize,
+    /// Decimal, relative to the enclosing struct or the contract.
+    slot: String,
+    #[serde(rename = "type")]
+    ty: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TypeDef {
+    encoding: String,
+    label: String,
+    number_of_bytes: String,
+    key: Option<String>,
+    value: Option<String>,
+    base: Option<String>,
+    members: Option<Vec<Member>>,
+}
+
+/// A value-type variable (or part of one) stored in a slot.
+#[derive(Clone)]
+pub struct Variable {
+    /// As it would be written in Solidity: `balanceOf[0x…]`, `reserves.r0`.
+    pub path: String,
+    /// The Solidity type, as solc labels it.
+    pub label: String,
+    offset: usize,
+    bytes: usize,
+    encoding: Encoding,
+}
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Uint,
+    Int,
+    Address,
+    Bool,
+    FixedBytes,
+    String,
+    Bytes,
+}
+
+impl Layout {
+    /// A storage layout file: solc's `storageLayout` object, or a JSON
+    /// document with one under that key.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        let mut json: serde_json::Value = serde_json::from_str(&text)
+            .with_context(|| format!("Cannot parse {}", path.display()))?;
+        if let Some(inner) = json.get_mut("storageLayout") {
+            json = inner.take();
+        }
+        let raw: RawLayout = serde_json::from_value(json).with_context(|| {
+            format!("{}: not a solc storage layout (storage and types)", path.display())
+        })?;
+        let layout = Self { storage: raw.storage, types: raw.types.unwrap_or_default() };
+        for member in &layout.storage {
+            layout.type_def(&member.ty).with_context(|| path.display().to_string())?;
+            parse_slot(&member.slot).with_context(|| path.display().to_string())?;
+        }
+        Ok(layout)
+    }
+
+    /// The variables stored in `slot`, trying `keys` for mapping keys.
+    pub fn resolve(&self, slot: U256, keys: &[B256]) -> Vec<Variable> {
+        let mut found = Vec::new();
+        self.members(&self.storage, U256::ZERO, "", slot, keys, 0, &mut found);
+        found
+    }
+
+    fn type_def(&self, ty: &str) -> Result<&TypeDef> {
+        self.types.get(ty).ok_or_else(|| eyre!("storage layout has no type {ty}"))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn members(
+        &self,
+        members: &[Member],
+        base: U256,
+        prefix: &str,
+        target: U256,
+        keys: &[B256],
+        depth: usize,
+        found: &mut Vec<Variable>,
+    ) {
+        for m in members {
+            let Ok(slot) = parse_slot(&m.slot) else { continue };
+            let path = format!("{prefix}{}", m.label);
+            self.place(&m.ty, base + slot, m.offset, &path, target, keys, depth, found);
+        }
+    }
+
+    /// Add the parts of a `ty` at `slot` (and `offset` within it) that are
+    /// stored in `target`.
+    #[allow(clippy::too_many_arguments)]
+    fn place(
+        &self,
+        ty: &str,
+        slot: U256,
+        offset: usize,
+        path: &str,
+        target: U256,
+        keys: &[B256],
+        depth: usize,
+        found: &mut Vec<Variable>,
+    ) {
+        let Ok(def) = self.type_def(ty) else { return };
+        let bytes: usize = def.number_of_bytes.parse().unwrap_or(32);
+        match def.encoding.as_str() {
+            "mapping" => {
+                let (Some(key), Some(value)) = (&def.key, &def.value) else { return };
+                if depth >= MAX_DEPTH || !self.is_value_type(key) {
+                    return;
+                }
+                // Slots a value of the type takes up, if all of it is in place.
+                let span = self.type_def(value).ok().filter(|_| self.in_place(value)).map(|d| {
+                    U256::from(d.number_of_bytes.parse::<usize>().unwrap_or(32).div_ceil(32))
+                });
+                for k in keys {
+                    let at = U256::from_be_bytes(
+                        keccak256([k.as_slice(), &slot.to_be_bytes::<32>()].concat()).0,
+                    );
+                    if span.is_some_and(|span| target < at || target - at >= span) {
+                        continue;
+                    }
+                    let path = format!("{path}[{}]", self.show_key(key, k));
+                    self.place(value, at, 0, &path, target, keys, depth + 1, found);
+                }
+            }
+            "dynamic_array" => {
+                if target == slot {
+                    found.push(Variable {
+                        path: format!("{path}.length"),
+                        label: "uint256".to_string(),
+                        offset: 0,
+                        bytes: 32,
+                        encoding: Encoding::Uint,
+                    });
+                    return;
+                }
+                let Some(base) = &def.base else { return };
+                let start = U256::from_be_bytes(keccak256(slot.to_be_bytes::<32>()).0);
+                self.elements(base, start, MAX_ELEMENTS, path, target, keys, depth, found);
+            }
+            "bytes" => {
+                if target == slot {
+                    found.push(self.leaf(def, path, 0, 32));
+                }
+            }
+            _ => {
+                if let Some(members) = &def.members {
+                    let slots = U256::from(bytes.div_ceil(32));
+                    if !self.in_place(ty) || (target >= slot && target - slot < slots) {
+                        let prefix = format!("{path}.");
+                        self.members(members, slot, &prefix, target, keys, depth, found);
+                    }
+                } else if let Some(base) = &def.base {
+                    // A static array, `t_array(<base>)<len>_storage`.
+                    let Some(len) = ty
+                        .rsplit_once(')')
+                        .and_then(|(_, rest)| rest.split('_').next()?.parse::<u64>().ok())
+                    else {
+                        return;
+                    };
+                    self.elements(base, slot, len, path, target, keys, depth, found);
+                } else if target == slot {
+                    found.push(self.leaf(def, path, offset, bytes));
+                }
+            }
+        }
+    }
+
+    /// The elements of an array of `len` `ty`s from `start` that are stored
+    /// in `target`: one, or those packed into it.
+    #[allow(clippy::too_many_arguments)]
+    fn elements(
+        &self,
+        ty: &str,
+        start: U256,
+        len: u64,
+        path: &str,
+        target: U256,
+        keys: &[B256],
+        depth: usize,
+        found: &mut Vec<Variable>,
+    ) {
+        let Ok(def) = self.type_def(ty) else { return };
+        if target < start {
+            return;
+        }
+        let size: usize = def.number_of_bytes.parse().unwrap_or(32);
+        let distance = target - start;
+        if size >= 32 {
+            let slots = U256::from(size.div_ceil(32));
+            let index = distance / slots;
+            if index < U256::from(len) {
+                let at = start + index * slots;
+                let path = format!("{path}[{index}]");
+                self.place(ty, at, 0, &path, target, keys, depth, found);
+            }
+        } else {
+            let per_slot = 32 / size;
+            if distance >= U256::from(len.div_ceil(per_slot as u64)) {
+                return;
+            }
+            let first = distance.to::<u64>() * per_slot as u64;
+            for i in 0..per_slot {
+                let index = first + i as u64;
+                if index >= len {
+                    break;
+                }
+                let path = format!("{path}[{index}]");
+                self.place(ty, target, i * size, &path, target, keys, depth, found);
+            }
+        }
+    }
+
+    fn leaf(&self, def: &TypeDef, path: &str, offset: usize, bytes: usize) -> Variable {
+        let label = def.label.as_str();
+        let encoding = if def.encoding == "bytes" {
+            if label == "string" {
+                Encoding::String
+            } else {
+                Encoding::Bytes
+            }
+        } else if label == "address" || label == "address payable" || label.starts_with("contract ")
+        {
+            Encoding::Address
+        } else if label == "bool" {
+            Encoding::Bool
+        } else if label.starts_with("int") {
+            Encoding::Int
+        } else if label.starts_with("bytes") {
+            Encoding::FixedBytes
+        } else {
+            // uintN, enums and anything else read as a number.
+            Encoding::Uint
+        };
+        Variable { path: path.to_string(), label: label.to_string(), offset, bytes, encoding }
+    }
+
+    /// Whether all of a `ty` is stored in its own slots, with no mapping or
+    /// dynamic array in it.
+    fn in_place(&self, ty: &str) -> bool {
+        let Ok(def) = self.type_def(ty) else { return false };
+        def.encoding == "inplace"
+            && def.members.iter().flatten().all(|m| self.in_place(&m.ty))
+            && def.base.as_deref().map_or(true, |base| self.in_place(base))
+    }
+
+    /// Whether mapping keys of type `ty` are hashed as one padded word.
+    fn is_value_type(&self, ty: &str) -> bool {
+        self.type_def(ty).is_ok_and(|d| d.encoding == "inplace" && d.members.is_none())
+    }
+
+    fn show_key(&self, ty: &str, key: &B256) -> String {
+        let Ok(def) = self.type_def(ty) else { return key.to_string() };
+        let size = def.number_of_bytes.parse::<usize>().unwrap_or(32).min(32);
+        let key_type = self.leaf(def, "", 0, size);
+        match key_type.encoding {
+            // bytesN keys are left-aligned in the word, the rest right-aligned.
+            Encoding::FixedBytes => format!("0x{}", hex::encode(&key[..size])),
+            _ => key_type.decode(U256::from_be_bytes(key.0)),
+        }
+    }
+}
+
+impl Variable {
+    /// The variable's value in a slot holding `word`.
+    pub fn decode(&self, word: U256) -> String {
+        let bytes = word.to_be_bytes::<32>();
+        let end = 32 - self.offset.min(32);
+        let field = &bytes[end.saturating_sub(self.bytes)..end];
+        let number = U256::from_be_slice(field);
+        match self.encoding {
+            Encoding::Uint => number.to_string(),
+            Encoding::Int => {
+                let bits = field.len() * 8;
+                let negative = bits > 0 && number.bit(bits - 1);
+                let value =
+                    if negative && bits < 256 { number | (U256::MAX << bits) } else { number };
+                I256::from_raw(value).to_string()
+            }
+            Encoding::Address => format!("{:#x}", Address::from_word(B256::from(number))),
+            Encoding::Bool => (!number.is_zero()).to_string(),
+            Encoding::FixedBytes => format!("0x{}", hex::encode(field)),
+            Encoding::String | Encoding::Bytes => {
+                if bytes[31] & 1 == 1 {
+                    let len = (word - U256::from(1)) / U256::from(2);
+                    return format!("({len} bytes, stored from keccak256(slot))");
+                }
+                let content = &bytes[..(bytes[31] / 2).min(31) as usize];
+                match self.encoding {
+                    Encoding::String => format!("{:?}", String::from_utf8_lossy(content)),
+                    _ => format!("0x{}", hex::encode(content)),
+                }
+            }
+        }
+    }
+}
+
+fn parse_slot(s: &str) -> Result<U256> {
+    s.parse().map_err(|_| eyre!("bad slot {s:?} in storage layout"))
+}
@@ -0,0 +1,315 @@
+//! Storage history from `state_diffs` (see state_snapshot.rs), into a
+//! `storage` table: one row per slot write, with the value before it
+//! (`previous`, NULL for the first write in the dataset) and after it as
+//! 32-byte words.
+//!
+//! With `--layout ADDRESS=FILE`, the contract's writes are decoded with its
+//! solc storage layout (see slots.rs): `variable` names what was written
+//! (`totalSupply`, `balanceOf[0x…]`, `allowance[0x…][0x…]`, `pool.reserve0`),
+//! `type` is its Solidity type and `decoded` the new value. A slot packing
+//! several variables gets a row for each one the write changed. Mapping keys
+//! are looked for among the words of the transaction that wrote the slot —
+//! its sender, recipient and calldata, and its logs' addresses, topics and
+//! data — and the contract's own address; a slot keyed by something else
+//! is left with a NULL `variable`.
+//!
+//! A rerun replaces the table.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use alloy_primitives::{Address, B256, U256};
+use clap::Args;
+use eyre::{eyre, Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::info;
+
+use super::slots::{Layout, Variable};
+use crate::normalize::{read_amount, read_key, Normalize};
+use crate::open_existing;
+use crate::state_snapshot::check_diffs;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS storage (
+    address      TEXT    NOT NULL,
+    slot         TEXT    NOT NULL,
+    block_number INTEGER NOT NULL,
+    tx_index     INTEGER NOT NULL,
+    previous     TEXT,
+    value        TEXT    NOT NULL,
+    variable     TEXT,
+    type         TEXT,
+    decoded      TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_storage_slot ON storage(address, slot, block_number);
+CREATE INDEX IF NOT EXISTS idx_storage_variable ON storage(address, variable);
+";
+
+const COLUMNS: [&str; 9] = [
+    "address",
+    "slot",
+    "block_number",
+    "tx_index",
+    "previous",
+    "value",
+    "variable",
+    "type",
+    "decoded",
+];
+
+#[derive(Args, Debug)]
+pub struct StorageArgs {
+    /// SQLite database with a `state_diffs` table (see `export state`)
+    db: PathBuf,
+
+    /// Only these contracts (repeatable or comma-separated) [default: all]
+    #[arg(long, value_delimiter = ',')]
+    address: Vec<Address>,
+
+    /// A contract's storage layout, as solc writes it with --storage-layout
+    /// (repeatable)
+    #[arg(long, value_name = "ADDRESS=FILE", value_parser = parse_layout)]
+    layout: Vec<(Address, PathBuf)>,
+
+    /// Write the history to this CSV file instead of the table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+fn parse_layout(s: &str) -> Result<(Address, PathBuf), String> {
+    let (address, file) =
+        s.split_once('=').ok_or_else(|| format!("{s:?}: expected ADDRESS=FILE"))?;
+    let address = address.parse().map_err(|_| format!("{address:?} is not an address"))?;
+    Ok((address, PathBuf::from(file)))
+}
+
+/// One write, in `state_diffs` order within its slot.
+struct Write {
+    block: i64,
+    tx_index: i64,
+    value: U256,
+}
+
+struct Row {
+    address: String,
+    slot: String,
+    block: i64,
+    tx_index: i64,
+    previous: Option<String>,
+    value: String,
+    /// Path, type and decoded value, where the layout names the slot.
+    variable: Option<(String, String, String)>,
+}
+
+pub fn run(args: StorageArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    check_diffs(&conn)?;
+    let norm = Normalize::from_meta(&conn)?;
+    let layouts: HashMap<Address, Layout> = args
+        .layout
+        .iter()
+        .map(|(address, file)| Ok((*address, Layout::load(file)?)))
+        .collect::<Result<_>>()?;
+
+    let mut slots: BTreeMap<(Address, U256), Vec<Write>> = BTreeMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT address, slot, value, block_number, tx_index FROM state_diffs
+         WHERE field = 'storage' ORDER BY block_number, tx_index",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let address = read_key(r.get_ref(0)?)?.unwrap_or_default();
+        let address: Address =
+            address.parse().map_err(|_| eyre!("state_diffs: {address:?} is not an address"))?;
+        if !args.address.is_empty() && !args.address.contains(&address) {
+            continue;
+        }
+        let context = || format!("state_diffs storage of {address:#x}");
+        let slot = read_amount(r.get_ref(1)?).with_context(context)?;
+        let value = read_amount(r.get_ref(2)?).with_context(context)?;
+        slots.entry((address, slot)).or_default().push(Write {
+            block: r.get(3)?,
+            tx_index: r.get(4)?,
+            value,
+        });
+    }
+    drop(rows);
+    drop(stmt);
+
+    let mut keys = TxKeys { conn: &conn, norm: &norm, cache: HashMap::new() };
+    let mut out: Vec<Row> = Vec::new();
+    let (mut decoded, mut undecoded) = (0usize, 0usize);
+    for ((address, slot), writes) in &slots {
+        let variables = match layouts.get(address) {
+            Some(layout) => {
+                let mut variables = Vec::new();
+                // One transaction's words are enough where the slot's key is.
+                for w in writes {
+                    variables = layout.resolve(*slot, &keys.of(*address, w.block, w.tx_index)?);
+                    if !variables.is_empty() {
+                        break;
+                    }
+                }
+                if variables.is_empty() {
+                    undecoded += 1;
+                } else {
+                    decoded += 1;
+                }
+                variables
+            }
+            None => Vec::new(),
+        };
+        let address = norm.address("address", &format!("{address:#x}"))?.into_owned();
+        let mut previous: Option<U256> = None;
+        for w in writes {
+            let row = |variable: Option<&Variable>| Row {
+                address: address.clone(),
+                slot: format!("{slot:#066x}"),
+                block: w.block,
+                tx_index: w.tx_index,
+                previous: previous.map(|p| format!("{p:#066x}")),
+                value: format!("{:#066x}", w.value),
+                variable: variable.map(|v| (v.path.clone(), v.label.clone(), v.decode(w.value))),
+            };
+            let changed: Vec<&Variable> = variables
+                .iter()
+                .filter(|v| previous.map_or(true, |p| v.decode(p) != v.decode(w.value)))
+                .collect();
+            match (variables.is_empty(), changed.is_empty()) {
+                (true, _) => out.push(row(None)),
+                (false, true) => out.extend(variables.iter().map(|v| row(Some(v)))),
+                (false, false) => out.extend(changed.into_iter().map(|v| row(Some(v)))),
+            }
+            previous = Some(w.value);
+        }
+    }
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record(COLUMNS)?;
+            for row in &out {
+                let (variable, ty, decoded) = row.variable.clone().unwrap_or_default();
+                w.write_record([
+                    row.address.clone(),
+                    row.slot.clone(),
+                    row.block.to_string(),
+                    row.tx_index.to_string(),
+                    row.previous.clone().unwrap_or_default(),
+                    row.value.clone(),
+                    variable,
+                    ty,
+                    decoded,
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM storage;")?;
+            let mut insert = conn.prepare(
+                "INSERT INTO storage
+                 (address, slot, block_number, tx_index, previous, value, variable, type, decoded)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9)",
+            )?;
+            for row in &out {
+                let variable = row.variable.as_ref();
+                insert.execute(params![
+                    row.address,
+                    row.slot,
+                    row.block,
+                    row.tx_index,
+                    row.previous,
+                    row.value,
+                    variable.map(|v| &v.0),
+                    variable.map(|v| &v.1),
+                    variable.map(|v| &v.2),
+                ])?;
+            }
+            drop(insert);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let dest = args.out.as_ref().map_or("storage".to_string(), |p| p.display().to_string());
+    info!(
+        out = dest,
+        writes = out.len(),
+        slots = slots.len(),
+        decoded,
+        undecoded,
+        "wrote storage history"
+    );
+    Ok(())
+}
+
+/// The candidate mapping keys of each transaction, read once.
+struct TxKeys<'a> {
+    conn: &'a Connection,
+    norm: &'a Normalize,
+    cache: HashMap<(i64, i64), Vec<B256>>,
+}
+
+impl TxKeys<'_> {
+    /// The 32-byte words of the transaction at (`block`, `tx_index`), and
+    /// `contract`'s address.
+    fn of(&mut self, contract: Address, block: i64, tx_index: i64) -> Result<Vec<B256>> {
+        if !self.cache.contains_key(&(block, tx_index)) {
+            let words = self.read(block, tx_index)?;
+            self.cache.insert((block, tx_index), words);
+        }
+        let mut words = self.cache[&(block, tx_index)].clone();
+        if !words.contains(&contract.into_word()) {
+            words.push(contract.into_word());
+        }
+        Ok(words)
+    }
+
+    fn read(&self, block: i64, tx_index: i64) -> Result<Vec<B256>> {
+        let mut words = Vec::new();
+        let address = |v: Option<String>, words: &mut Vec<B256>| {
+            if let Some(a) = v.and_then(|a| a.parse::<Address>().ok()) {
+                words.push(a.into_word());
+            }
+        };
+        let tx = self
+            .conn
+            .query_row(
+                "SELECT hash, from_addr, to_addr, input FROM transactions
+                 WHERE block_number = ?1 AND tx_index = ?2",
+                [block, tx_index],
+                |r| {
+                    Ok((
+                        r.get::<_, Value>(0)?,
+                        read_key(r.get_ref(1)?),
+                        read_key(r.get_ref(2)?),
+                        r.get::<_, Vec<u8>>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((hash, from, to, input)) = tx else { return Ok(words) };
+        address(from?, &mut words);
+        address(to?, &mut words);
+        let input = self.norm.read_blob(input)?;
+        words.extend(input.get(4..).unwrap_or_default().chunks_exact(32).map(B256::from_slice));
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT address, topic1, topic2, topic3, data FROM logs WHERE tx_hash = ?1",
+        )?;
+        let mut rows = stmt.query([hash])?;
+        while let Some(r) = rows.next()? {
+            address(read_key(r.get_ref(0)?)?, &mut words);
+            for i in 1..=3 {
+                if let Some(topic) = read_key(r.get_ref(i)?)?.and_then(|t| t.parse().ok()) {
+                    words.push(topic);
+                }
+            }
+            let data = self.norm.read_blob(r.get::<_, Option<Vec<u8>>>(4)?.unwrap_or_default())?;
+            words.extend(data.chunks_exact(32).map(B256::from_slice));
+        }
+        words.sort();
+        words.dedup();
+        Ok(words)
+    }
+}
@@ -0,0 +1,127 @@
+//! The `traces` table that scripts/capture.py writes, one row per
+//! transaction with its callTracer output (`block_number, tx_hash,
+//! trace_json`), copied or attached into the database; the loader doesn't
+//! produce it.
+//!
+//! Frames are numbered in pre-order from 0, the transaction's own call,
+//! counting failed frames and their subcalls so that a frame's number is the
+//! same whichever analysis reports it.
+
+use alloy_primitives::U256;
+use eyre::Result;
+use rusqlite::Connection;
+use serde_json::Value;
+
+use crate::rpc;
+
+const TRACE_COLUMNS: &[&str] = &["block_number", "tx_hash", "trace_json"];
+
+/// ETH moved by a frame.
+pub struct Transfer {
+    pub frame: usize,
+    pub depth: usize,
+    /// CALL, CREATE, CREATE2 or SELFDESTRUCT, as the tracer names it.
+    pub kind: String,
+    /// Lowercase.
+    pub from: String,
+    pub to: String,
+    pub value: U256,
+}
+
+/// Fail with the expected layout if `traces` is missing or lacks a column.
+pub fn check_traces(conn: &Connection) -> Result<()> {
+    let columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('traces')")?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    if columns.is_empty() {
+        eyre::bail!(
+            "no traces table; copy in the one scripts/capture.py writes (columns {})",
+            TRACE_COLUMNS.join(", ")
+        );
+    }
+    let missing: Vec<&str> =
+        TRACE_COLUMNS.iter().copied().filter(|c| !columns.iter().any(|have| have == c)).collect();
+    if !missing.is_empty() {
+        eyre::bail!("traces table is missing columns: {}", missing.join(", "));
+    }
+    Ok(())
+}
+
+/// Whether the database has a `traces` table, checking its columns if so.
+pub fn has_traces(conn: &Connection) -> Result<bool> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'traces'",
+        [],
+        |r| r.get(0),
+    )?;
+    if exists {
+        check_traces(conn)?;
+    }
+    Ok(exists)
+}
+
+/// A frame of a trace, as `walk` visits it.
+pub struct Frame<'a> {
+    pub call: &'a Value,
+    /// The frame that made the call; None for the transaction's own.
+    pub parent: Option<&'a Value>,
+    pub number: usize,
+    pub depth: usize,
+    /// Whether it failed, or a frame above it did.
+    pub failed: bool,
+}
+
+/// Visit each frame of a trace in pre-order, failed ones and their subcalls
+/// included, so analyses that skip them still number the rest alike.
+pub fn walk(trace: &Value, visit: &mut impl FnMut(&Frame) -> Result<()>) -> Result<()> {
+    fn frame<'a>(
+        call: &'a Value,
+        parent: Option<&'a Value>,
+        depth: usize,
+        under_failed: bool,
+        next: &mut usize,
+        visit: &mut impl FnMut(&Frame) -> Result<()>,
+    ) -> Result<()> {
+        let failed = under_failed || failed(call);
+        visit(&Frame { call, parent, number: *next, depth, failed })?;
+        *next += 1;
+        for sub in calls(call) {
+            frame(sub, Some(call), depth + 1, failed, next, visit)?;
+        }
+        Ok(())
+    }
+    frame(trace, None, 0, false, &mut 0, visit)
+}
+
+/// Call `transfer` for each value-moving frame of a trace, in order.
+/// Failed frames move nothing, and neither does anything under them; nor do
+/// delegate and static calls, or a contract paying itself.
+pub fn transfers(trace: &Value, transfer: &mut impl FnMut(Transfer)) -> Result<()> {
+    walk(trace, &mut |f| {
+        let kind = f.call["type"].as_str().unwrap_or_default().to_ascii_uppercase();
+        if f.failed || matches!(kind.as_str(), "DELEGATECALL" | "STATICCALL") {
+            return Ok(());
+        }
+        let address = |key: &str| f.call[key].as_str().map(str::to_ascii_lowercase);
+        let value = match &f.call["value"] {
+            Value::Null => U256::ZERO,
+            v => U256::from(rpc::quantity(v)?),
+        };
+        if let (Some(from), Some(to)) = (address("from"), address("to")) {
+            if !value.is_zero() && from != to {
+                let (frame, depth) = (f.number, f.depth);
+                transfer(Transfer { frame, depth, kind, from, to, value });
+            }
+        }
+        Ok(())
+    })
+}
+
+pub fn failed(frame: &Value) -> bool {
+    frame.get("error").is_some_and(|e| !e.is_null())
+}
+
+pub fn calls(frame: &Value) -> &[Value] {
+    frame["calls"].as_array().map(Vec::as_slice).unwrap_or_default()
+}
@@ -0,0 +1,316 @@
+//! TVL per asset for a lending protocol, from its deposit, withdraw, borrow
+//! and repay events, into a `tvl_timeseries` table.
+//!
+//! A protocol is described by which events mean what and where each event
+//! keeps its asset and amount. `euler`, `aave-v2` and `compound-v2` are built
+//! in; `--spec` takes the same description as a TOML file:
+//!
+//! ```toml
+//! name = "euler"
+//! contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]  # optional
+//!
+//! [[events]]
+//! action = "deposit"            # deposit | withdraw | borrow | repay
+//! signature = "Deposit(address,address,uint256)"
+//! asset = "topic1"              # topic1..3, data0.., or "address" (the emitter)
+//! amount = "data0"
+//! ```
+//!
+//! supplied = deposits - withdrawals and borrowed = borrows - repays, summed
+//! from the start of the dataset, so they are changes over the loaded range
+//! rather than absolute levels (signed, decimal, in the asset's base unit);
+//! tvl = supplied - borrowed. There is a row for every block that changed an
+//! asset's figures. Liquidations are not counted.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use alloy_primitives::{hex, I256, U256};
+use clap::{Args, ValueEnum};
+use eyre::{eyre, Context, Result};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::decode;
+use crate::normalize::{read_key, Normalize};
+use crate::open_existing;
+
+const TVL_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS tvl_timeseries (
+    protocol     TEXT    NOT NULL,
+    asset        TEXT    NOT NULL,
+    block_number INTEGER NOT NULL,
+    timestamp    INTEGER NOT NULL,
+    supplied     TEXT    NOT NULL,
+    borrowed     TEXT    NOT NULL,
+    tvl          TEXT    NOT NULL,
+    PRIMARY KEY (protocol, asset, block_number)
+);
+";
+
+const EULER: &str = r#"
+name = "euler"
+contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]
+events = [
+    { action = "deposit",  signature = "Deposit(address,address,uint256)",  asset = "topic1", amount = "data0" },
+    { action = "withdraw", signature = "Withdraw(address,address,uint256)", asset = "topic1", amount = "data0" },
+    { action = "borrow",   signature = "Borrow(address,address,uint256)",   asset = "topic1", amount = "data0" },
+    { action = "repay",    signature = "Repay(address,address,uint256)",    asset = "topic1", amount = "data0" },
+]
+"#;
+
+const AAVE_V2: &str = r#"
+name = "aave-v2"
+contracts = ["0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9"]
+events = [
+    { action = "deposit",  signature = "Deposit(address,address,address,uint256,uint16)",                 asset = "topic1", amount = "data1" },
+    { action = "withdraw", signature = "Withdraw(address,address,address,uint256)",                      asset = "topic1", amount = "data0" },
+    { action = "borrow",   signature = "Borrow(address,address,address,uint256,uint256,uint256,uint16)", asset = "topic1", amount = "data1" },
+    { action = "repay",    signature = "Repay(address,address,address,uint256)",                         asset = "topic1", amount = "data0" },
+]
+"#;
+
+/// cTokens emit these themselves, so the asset is the cToken.
+const COMPOUND_V2: &str = r#"
+name = "compound-v2"
+events = [
+    { action = "deposit",  signature = "Mint(address,uint256,uint256)",                     asset = "address", amount = "data1" },
+    { action = "withdraw", signature = "Redeem(address,uint256,uint256)",                   asset = "address", amount = "data1" },
+    { action = "borrow",   signature = "Borrow(address,uint256,uint256,uint256)",           asset = "address", amount = "data1" },
+    { action = "repay",    signature = "RepayBorrow(address,address,uint256,uint256,uint256)", asset = "address", amount = "data2" },
+]
+"#;
+
+#[derive(Args, Debug)]
+pub struct TvlArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Built-in protocol description
+    #[arg(long, value_enum, required_unless_present = "spec", conflicts_with = "spec")]
+    protocol: Option<Protocol>,
+
+    /// TOML protocol description (see tvl.rs for the format)
+    #[arg(long)]
+    spec: Option<PathBuf>,
+
+    /// Write the series to this CSV file instead of the `tvl_timeseries` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Protocol {
+    Euler,
+    #[value(name = "aave-v2")]
+    AaveV2,
+    #[value(name = "compound-v2")]
+    CompoundV2,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Spec {
+    name: String,
+    #[serde(default)]
+    contracts: Vec<String>,
+    events: Vec<EventSpec>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct EventSpec {
+    action: Action,
+    signature: String,
+    asset: Field,
+    amount: Field,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Deposit,
+    Withdraw,
+    Borrow,
+    Repay,
+}
+
+/// Where in a log a value is found.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+enum Field {
+    Address,
+    Topic(usize),
+    Data(usize),
+}
+
+impl FromStr for Field {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let index = |n: &str| n.parse::<usize>().map_err(|_| eyre!("bad field {s:?}"));
+        if s == "address" {
+            Ok(Field::Address)
+        } else if let Some(n) = s.strip_prefix("topic") {
+            match index(n)? {
+                n @ 1..=3 => Ok(Field::Topic(n)),
+                _ => Err(eyre!("{s:?}: only topic1..topic3 hold event arguments")),
+            }
+        } else if let Some(n) = s.strip_prefix("data") {
+            Ok(Field::Data(index(n)?))
+        } else {
+            Err(eyre!("bad field {s:?} (expected address, topicN or dataN)"))
+        }
+    }
+}
+
+impl TryFrom<String> for Field {
+    type Error = eyre::Report;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl Field {
+    /// The 32-byte word this field names, from a log's topics and data.
+    fn word(self, address: &str, topics: &[Option<String>; 3], data: &[u8]) -> Option<[u8; 32]> {
+        let mut word = [0u8; 32];
+        match self {
+            Field::Address => {
+                let bytes = hex::decode(address).ok()?;
+                word[12..].copy_from_slice(bytes.get(..20)?);
+            }
+            Field::Topic(n) => {
+                let bytes = hex::decode(topics[n - 1].as_deref()?).ok()?;
+                word.copy_from_slice(bytes.get(..32)?);
+            }
+            Field::Data(n) => word.copy_from_slice(data.get(n * 32..(n + 1) * 32)?),
+        }
+        Some(word)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Position {
+    supplied: I256,
+    borrowed: I256,
+}
+
+pub fn run(args: TvlArgs) -> Result<()> {
+    let spec: Spec = match (&args.spec, args.protocol) {
+        (Some(path), _) => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Cannot read {}", path.display()))?;
+            toml::from_str(&text).with_context(|| format!("Invalid spec {}", path.display()))?
+        }
+        (None, Some(p)) => toml::from_str(match p {
+            Protocol::Euler => EULER,
+            Protocol::AaveV2 => AAVE_V2,
+            Protocol::CompoundV2 => COMPOUND_V2,
+        })?,
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+    let conn = open_existing(&args.db)?;
+    let series = series(&conn, &spec)?;
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "protocol", "asset", "block_number", "timestamp", "supplied", "borrowed", "tvl",
+            ])?;
+            for ((asset, block), (timestamp, p)) in &series {
+                w.write_record([
+                    spec.name.clone(),
+                    asset.clone(),
+                    block.to_string(),
+                    timestamp.to_string(),
+                    p.supplied.to_string(),
+                    p.borrowed.to_string(),
+                    (p.supplied.saturating_sub(p.borrowed)).to_string(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(TVL_SCHEMA)?;
+            conn.execute_batch("BEGIN;")?;
+            conn.execute("DELETE FROM tvl_timeseries WHERE protocol = ?1", [&spec.name])?;
+            let mut stmt = conn.prepare(
+                "INSERT INTO tvl_timeseries
+                 (protocol, asset, block_number, timestamp, supplied, borrowed, tvl)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7)",
+            )?;
+            for ((asset, block), (timestamp, p)) in &series {
+                stmt.execute(params![
+                    spec.name,
+                    asset,
+                    block,
+                    timestamp,
+                    p.supplied.to_string(),
+                    p.borrowed.to_string(),
+                    p.supplied.saturating_sub(p.borrowed).to_string(),
+                ])?;
+            }
+            drop(stmt);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("tvl_timeseries".to_string(), |p| p.display().to_string());
+    info!(protocol = spec.name, out, rows = series.len(), "wrote TVL series");
+    Ok(())
+}
+
+/// Running totals keyed by (asset, block), with the block's timestamp, one
+/// entry per block that changed the asset.
+fn series(conn: &Connection, spec: &Spec) -> Result<BTreeMap<(String, i64), (i64, Position)>> {
+    let events: BTreeMap<String, &EventSpec> =
+        spec.events.iter().map(|e| (decode::topic(&e.signature), e)).collect();
+    let contracts: Vec<String> = spec.contracts.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let norm = Normalize::from_meta(conn)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT l.block_number, b.timestamp, l.address, l.topic0, l.topic1, l.topic2,
+                l.topic3, l.data
+         FROM logs l
+         JOIN blocks b ON b.number = l.block_number AND b.is_canonical = 1
+         ORDER BY l.block_number, l.log_index",
+    )?;
+    let mut rows = stmt.query([])?;
+
+    let mut totals: BTreeMap<String, Position> = BTreeMap::new();
+    let mut series = BTreeMap::new();
+    while let Some(r) = rows.next()? {
+        let Some(event) = read_key(r.get_ref(3)?)?.and_then(|t| events.get(&t).copied()) else {
+            continue;
+        };
+        let address = read_key(r.get_ref(2)?)?.unwrap_or_default().to_ascii_lowercase();
+        if !contracts.is_empty() && !contracts.contains(&address) {
+            continue;
+        }
+        let topics = [read_key(r.get_ref(4)?)?, read_key(r.get_ref(5)?)?, read_key(r.get_ref(6)?)?];
+        let data = norm.read_blob(r.get::<_, Option<Vec<u8>>>(7)?.unwrap_or_default())?;
+        let (Some(asset), Some(amount)) = (
+            event.asset.word(&address, &topics, &data),
+            event.amount.word(&address, &topics, &data),
+        ) else {
+            continue;
+        };
+        let asset = format!("0x{}", hex::encode(&asset[12..]));
+        let amount = I256::try_from(U256::from_be_bytes(amount)).unwrap_or(I256::MAX);
+
+        let p = totals.entry(asset.clone()).or_default();
+        match event.action {
+            Action::Deposit => p.supplied = p.supplied.saturating_add(amount),
+            Action::Withdraw => p.supplied = p.supplied.saturating_sub(amount),
+            Action::Borrow => p.borrowed = p.borrowed.saturating_add(amount),
+            Action::Repay => p.borrowed = p.borrowed.saturating_sub(amount),
+        }
+        let (block, timestamp): (i64, i64) = (r.get(0)?, r.get(1)?);
+        series.insert((asset, block), (timestamp, *p));
+    }
+    Ok(series)
+}
@@ -0,0 +1,256 @@
+//! A Uniswap V3 pool's liquidity by tick at a block, folded from its Mint
+//! and Burn events, with the price and tick of its last swap (or of its
+//! Initialize, before the first one).
+//!
+//! Each position adds its liquidity at `tick_lower` and takes it away at
+//! `tick_upper`, as the pool itself keeps it: a tick's `liquidity_net` is
+//! what crossing it upwards adds to the in-range liquidity, its
+//! `liquidity_gross` all the liquidity referencing it, and
+//! `liquidity_active` the in-range liquidity while the price is between it
+//! and the next tick up. Ticks whose positions were all burned are left out.
+//!
+//! The ticks are written to `v3_pool_ticks` and the pool's state to
+//! `v3_pool_state`, both keyed by (pool, at_block) and replaced by a rerun,
+//! or the ticks with `--out` to CSV. Amounts are decimal. The events come
+//! from the `uniswap` decoders, which are caught up first.
+//!
+//! The snapshot is only as complete as the logs: positions minted before
+//! the dataset starts are missing, and a warning says so when the last
+//! swap's in-range `liquidity` differs from the ticks' figure at its tick.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{eyre, Result};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection};
+use tracing::{info, warn};
+
+use crate::normalize::read_amount;
+use crate::open_existing;
+use crate::protocols;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS v3_pool_ticks (
+    pool             TEXT    NOT NULL,
+    at_block         INTEGER NOT NULL,
+    tick             INTEGER NOT NULL,
+    liquidity_net    TEXT    NOT NULL,
+    liquidity_gross  TEXT    NOT NULL,
+    liquidity_active TEXT    NOT NULL,
+    PRIMARY KEY (pool, at_block, tick)
+);
+
+CREATE TABLE IF NOT EXISTS v3_pool_state (
+    pool           TEXT    NOT NULL,
+    at_block       INTEGER NOT NULL,
+    price_block    INTEGER,
+    sqrt_price_x96 TEXT,
+    tick           INTEGER,
+    liquidity      TEXT,
+    ticks          INTEGER NOT NULL,
+    PRIMARY KEY (pool, at_block)
+);
+";
+
+#[derive(Args, Debug)]
+pub struct V3PoolArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// The pool's address
+    #[arg(long)]
+    pool: String,
+
+    /// The snapshot is of the pool after this block
+    #[arg(long)]
+    at_block: i64,
+
+    /// Write the ticks to this CSV file instead of the `v3_pool_ticks` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Default)]
+struct Tick {
+    net: i128,
+    gross: u128,
+}
+
+/// The pool after its last swap, or its initialization when it has none.
+struct State {
+    block_number: i64,
+    sqrt_price_x96: String,
+    tick: i64,
+    /// None after an initialization.
+    liquidity: Option<u128>,
+}
+
+pub fn run(args: V3PoolArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    protocols::catch_up(&conn, &["uniswap"])?;
+    let pool = args.pool.to_ascii_lowercase();
+
+    let ticks = ticks(&conn, &pool, args.at_block)?;
+    let state = state(&conn, &pool, args.at_block)?;
+    let mut active = 0i128;
+    let mut rows = Vec::with_capacity(ticks.len());
+    for (&tick, t) in &ticks {
+        active += t.net;
+        rows.push((tick, t, active));
+    }
+    if let Some(s) = &state {
+        let at_tick = rows.iter().take_while(|(tick, ..)| *tick <= s.tick).last();
+        let from_ticks = at_tick.map_or(0, |(.., active)| *active);
+        if let Some(liquidity) = s.liquidity.filter(|&l| i128::try_from(l) != Ok(from_ticks)) {
+            warn!(
+                swap_liquidity = %liquidity,
+                tick_liquidity = %from_ticks,
+                "the last swap's liquidity differs from the ticks'; \
+                 likely positions minted before the dataset starts are missing"
+            );
+        }
+    }
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "pool",
+                "at_block",
+                "tick",
+                "liquidity_net",
+                "liquidity_gross",
+                "liquidity_active",
+            ])?;
+            for (tick, t, active) in &rows {
+                w.write_record([
+                    pool.clone(),
+                    args.at_block.to_string(),
+                    tick.to_string(),
+                    t.net.to_string(),
+                    t.gross.to_string(),
+                    active.to_string(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN;")?;
+            for table in ["v3_pool_ticks", "v3_pool_state"] {
+                conn.execute(
+                    &format!("DELETE FROM {table} WHERE pool = ?1 AND at_block = ?2"),
+                    params![pool, args.at_block],
+                )?;
+            }
+            let mut stmt = conn.prepare(
+                "INSERT INTO v3_pool_ticks
+                 (pool, at_block, tick, liquidity_net, liquidity_gross, liquidity_active)
+                 VALUES (?1,?2,?3,?4,?5,?6)",
+            )?;
+            for (tick, t, active) in &rows {
+                stmt.execute(params![
+                    pool,
+                    args.at_block,
+                    tick,
+                    t.net.to_string(),
+                    t.gross.to_string(),
+                    active.to_string(),
+                ])?;
+            }
+            drop(stmt);
+            conn.execute(
+                "INSERT INTO v3_pool_state
+                 (pool, at_block, price_block, sqrt_price_x96, tick, liquidity, ticks)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7)",
+                params![
+                    pool,
+                    args.at_block,
+                    state.as_ref().map(|s| s.block_number),
+                    state.as_ref().map(|s| s.sqrt_price_x96.clone()),
+                    state.as_ref().map(|s| s.tick),
+                    state.as_ref().and_then(|s| s.liquidity).map(|l| l.to_string()),
+                    rows.len() as i64,
+                ],
+            )?;
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out = args.out.as_ref().map_or("v3_pool_ticks".to_string(), |p| p.display().to_string());
+    info!(
+        out,
+        pool,
+        at_block = args.at_block,
+        ticks = rows.len(),
+        tick = state.as_ref().map(|s| s.tick),
+        sqrt_price_x96 = state.as_ref().map(|s| s.sqrt_price_x96.as_str()),
+        "wrote V3 pool snapshot"
+    );
+    Ok(())
+}
+
+/// Tick -> liquidity, from the pool's mints and burns up to `at_block`.
+fn ticks(conn: &Connection, pool: &str, at_block: i64) -> Result<BTreeMap<i64, Tick>> {
+    let mut stmt = conn.prepare(
+        "SELECT 1, tick_lower, tick_upper, amount, block_number, log_index
+         FROM uniswap_v3_mints WHERE lower(contract) = ?1 AND block_number <= ?2
+         UNION ALL
+         SELECT 0, tick_lower, tick_upper, amount, block_number, log_index
+         FROM uniswap_v3_burns WHERE lower(contract) = ?1 AND block_number <= ?2
+         ORDER BY block_number, log_index",
+    )?;
+    let mut rows = stmt.query(params![pool, at_block])?;
+    let mut ticks: BTreeMap<i64, Tick> = BTreeMap::new();
+    while let Some(r) = rows.next()? {
+        let mint: bool = r.get(0)?;
+        let (lower, upper): (i64, i64) = (r.get(1)?, r.get(2)?);
+        let block: i64 = r.get(4)?;
+        // uint128, but a pool's liquidity stays far below 2^127.
+        let amount = i128::try_from(read_amount(r.get_ref(3)?)?)
+            .map_err(|_| eyre!("liquidity over 127 bits at block {block}"))?;
+        let signed = if mint { amount } else { -amount };
+        for (tick, net) in [(lower, signed), (upper, -signed)] {
+            let t = ticks.entry(tick).or_default();
+            t.net = t.net.checked_add(net).ok_or_else(|| eyre!("overflow at block {block}"))?;
+            // A burn of liquidity minted before the dataset starts saturates.
+            t.gross = if mint {
+                t.gross.saturating_add(amount as u128)
+            } else {
+                t.gross.saturating_sub(amount as u128)
+            };
+        }
+    }
+    ticks.retain(|_, t| t.gross != 0 || t.net != 0);
+    Ok(ticks)
+}
+
+/// The pool after its last swap up to `at_block`, or after its
+/// initialization when it has none.
+fn state(conn: &Connection, pool: &str, at_block: i64) -> Result<Option<State>> {
+    for (table, liquidity) in
+        [("uniswap_v3_swaps", "liquidity"), ("uniswap_v3_initializations", "NULL")]
+    {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT block_number, sqrt_price_x96, tick, {liquidity} FROM {table}
+             WHERE lower(contract) = ?1 AND block_number <= ?2
+             ORDER BY block_number DESC, log_index DESC LIMIT 1"
+        ))?;
+        let mut rows = stmt.query(params![pool, at_block])?;
+        let Some(r) = rows.next()? else { continue };
+        let liquidity = match r.get_ref(3)? {
+            ValueRef::Null => None,
+            v => Some(
+                u128::try_from(read_amount(v)?).map_err(|_| eyre!("liquidity over 128 bits"))?,
+            ),
+        };
+        return Ok(Some(State {
+            block_number: r.get(0)?,
+            sqrt_price_x96: read_amount(r.get_ref(1)?)?.to_string(),
+            tick: r.get(2)?,
+            liquidity,
+        }));
+    }
+    Ok(None)
+}
@@ -0,0 +1,240 @@
+//! What validator withdrawal addresses did with the dataset window after
+//! their withdrawals, for staking-provider incident forensics, into
+//! `withdrawal_flows`.
+//!
+//! Withdrawals come from the `withdrawals` table a dataset with a
+//! withdrawals.parquet loads. Each transaction an address sent (and that
+//! succeeded) in a block after its first withdrawal is a row of kind `tx`,
+//! and each ERC-20 transfer out of it one of kind `erc20` with the token as
+//! `asset`; `amount` is the transaction's value or the tokens moved. A
+//! block credits its withdrawals after its transactions, so the withdrawals
+//! of earlier blocks are summed up next to the row: `withdrawals`,
+//! `withdrawn_gwei`, and `last_withdrawal_block` with the `blocks_after`
+//! it. Activity before an address's first withdrawal in the window isn't
+//! listed, so a wider window finds more of it.
+//!
+//! Addresses are matched as stored, so datasets from exporters that
+//! disagree on case need `--address-case`. A rerun replaces the table.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::Result;
+use rusqlite::{params, Connection};
+use tracing::info;
+
+use crate::decode;
+use crate::normalize::{self, read_amount, read_key, Normalize};
+use crate::open_existing;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS withdrawal_flows (
+    address               TEXT    NOT NULL,
+    block_number          INTEGER NOT NULL,
+    tx_hash               TEXT    NOT NULL,
+    log_index             INTEGER,
+    kind                  TEXT    NOT NULL,
+    to_addr               TEXT,
+    asset                 TEXT,
+    amount                TEXT    NOT NULL,
+    withdrawals           INTEGER NOT NULL,
+    withdrawn_gwei        INTEGER NOT NULL,
+    last_withdrawal_block INTEGER NOT NULL,
+    blocks_after          INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_withdrawal_flows_address ON withdrawal_flows(address, block_number);
+CREATE INDEX IF NOT EXISTS idx_withdrawal_flows_to ON withdrawal_flows(to_addr);
+";
+
+#[derive(Args, Debug)]
+pub struct WithdrawalFlowsArgs {
+    /// SQLite database produced by offline-replay from a dataset with withdrawals
+    db: PathBuf,
+
+    /// Only these withdrawal addresses (repeatable)
+    #[arg(long)]
+    address: Vec<String>,
+
+    /// Only activity within this many blocks of the address's last withdrawal
+    #[arg(long)]
+    within: Option<i64>,
+
+    /// Write the flows to this CSV file instead of the `withdrawal_flows` table
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+/// One transaction or transfer out of a withdrawal address.
+struct Flow {
+    address: String,
+    block_number: i64,
+    tx_hash: String,
+    log_index: Option<i64>,
+    kind: &'static str,
+    to_addr: Option<String>,
+    asset: Option<String>,
+    amount: String,
+    withdrawals: usize,
+    withdrawn_gwei: i64,
+    last_withdrawal_block: i64,
+}
+
+pub fn run(args: WithdrawalFlowsArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let has_withdrawals: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'withdrawals')",
+        [],
+        |r| r.get(0),
+    )?;
+    if !has_withdrawals {
+        eyre::bail!("no withdrawals table; load a dataset with a withdrawals.parquet");
+    }
+    decode::erc20_transfers(&conn)?;
+    // erc20_transfers holds addresses as text, with --binary-keys too.
+    normalize::functions(&conn)?;
+    let norm = Normalize::from_meta(&conn)?;
+
+    let only: Vec<String> = args.address.iter().map(|a| a.to_ascii_lowercase()).collect();
+    let withdrawals = withdrawals(&conn, &only)?;
+    let mut flows = Vec::new();
+    for (kind, sql) in [
+        (
+            "tx",
+            "SELECT from_addr, block_number, hash, NULL, to_addr, NULL, value FROM transactions
+             WHERE status = 1 AND from_addr IN (SELECT address FROM withdrawals)",
+        ),
+        (
+            "erc20",
+            "SELECT from_addr, block_number, tx_hash, log_index, to_addr, token, amount
+             FROM erc20_transfers WHERE from_addr IN (SELECT hexkey(address) FROM withdrawals)",
+        ),
+    ] {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            let key = |i| -> Result<Option<String>> {
+                Ok(read_key(r.get_ref(i)?)?.map(|k| k.to_ascii_lowercase()))
+            };
+            let Some(address) = key(0)? else { continue };
+            let Some(before) = withdrawals.get(&address) else { continue };
+            let block_number: i64 = r.get(1)?;
+            // Withdrawals are sorted by block, and credited after a block's
+            // transactions: those of earlier blocks.
+            let n = before.partition_point(|(block, _)| *block < block_number);
+            if n == 0 {
+                continue;
+            }
+            let last_withdrawal_block = before[n - 1].0;
+            if args.within.is_some_and(|w| block_number - last_withdrawal_block > w) {
+                continue;
+            }
+            flows.push(Flow {
+                address,
+                block_number,
+                tx_hash: key(2)?.unwrap_or_default(),
+                log_index: r.get(3)?,
+                kind,
+                to_addr: key(4)?,
+                asset: key(5)?,
+                amount: read_amount(r.get_ref(6)?)?.to_string(),
+                withdrawals: n,
+                withdrawn_gwei: before[..n].iter().map(|(_, gwei)| gwei).sum(),
+                last_withdrawal_block,
+            });
+        }
+    }
+    flows.sort_by(|a, b| {
+        (&a.address, a.block_number, &a.tx_hash, a.log_index)
+            .cmp(&(&b.address, b.block_number, &b.tx_hash, b.log_index))
+    });
+
+    match &args.out {
+        Some(path) => {
+            let mut w = csv::Writer::from_path(path)?;
+            w.write_record([
+                "address",
+                "block_number",
+                "tx_hash",
+                "log_index",
+                "kind",
+                "to_addr",
+                "asset",
+                "amount",
+                "withdrawals",
+                "withdrawn_gwei",
+                "last_withdrawal_block",
+                "blocks_after",
+            ])?;
+            for f in &flows {
+                w.write_record([
+                    f.address.clone(),
+                    f.block_number.to_string(),
+                    f.tx_hash.clone(),
+                    f.log_index.map_or(String::new(), |i| i.to_string()),
+                    f.kind.to_string(),
+                    f.to_addr.clone().unwrap_or_default(),
+                    f.asset.clone().unwrap_or_default(),
+                    f.amount.clone(),
+                    f.withdrawals.to_string(),
+                    f.withdrawn_gwei.to_string(),
+                    f.last_withdrawal_block.to_string(),
+                    (f.block_number - f.last_withdrawal_block).to_string(),
+                ])?;
+            }
+            w.flush()?;
+        }
+        None => {
+            conn.execute_batch(SCHEMA)?;
+            conn.execute_batch("BEGIN; DELETE FROM withdrawal_flows;")?;
+            let mut insert = conn.prepare(
+                "INSERT INTO withdrawal_flows
+                 (address, block_number, tx_hash, log_index, kind, to_addr, asset, amount,
+                  withdrawals, withdrawn_gwei, last_withdrawal_block, blocks_after)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12)",
+            )?;
+            for f in &flows {
+                insert.execute(params![
+                    norm.address("address", &f.address)?,
+                    f.block_number,
+                    f.tx_hash,
+                    f.log_index,
+                    f.kind,
+                    f.to_addr.as_deref().map(|a| norm.address("to_addr", a)).transpose()?,
+                    f.asset.as_deref().map(|a| norm.address("asset", a)).transpose()?,
+                    f.amount,
+                    f.withdrawals as i64,
+                    f.withdrawn_gwei,
+                    f.last_withdrawal_block,
+                    f.block_number - f.last_withdrawal_block,
+                ])?;
+            }
+            drop(insert);
+            conn.execute_batch("COMMIT;")?;
+        }
+    }
+    let out =
+        args.out.as_ref().map_or("withdrawal_flows".to_string(), |p| p.display().to_string());
+    info!(out, addresses = withdrawals.len(), flows = flows.len(), "wrote withdrawal flows");
+    Ok(())
+}
+
+/// Each withdrawal address's withdrawals as (block, Gwei), by block, keyed
+/// by lowercase address; only those of `only` when it isn't empty.
+fn withdrawals(conn: &Connection, only: &[String]) -> Result<HashMap<String, Vec<(i64, i64)>>> {
+    let mut stmt = conn.prepare(
+        "SELECT address, block_number, amount FROM withdrawals
+         ORDER BY block_number, withdrawal_index",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut found: HashMap<String, Vec<(i64, i64)>> = HashMap::new();
+    while let Some(r) = rows.next()? {
+        let Some(address) = read_key(r.get_ref(0)?)? else { continue };
+        let address = address.to_ascii_lowercase();
+        if !only.is_empty() && !only.contains(&address) {
+            continue;
+        }
+        found.entry(address).or_default().push((r.get(1)?, r.get(2)?));
+    }
+    Ok(found)
+}
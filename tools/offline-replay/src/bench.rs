@@ -0,0 +1,225 @@
+//! `bench` — load one dataset under several loader configurations and
+//! report throughput and database size, so loader regressions show up as
+//! numbers that can be compared across releases.
+//!
+//!   default        the plain load
+//!   bulk-pragmas   --preset bulk (no journal on disk, no fsync)
+//!   in-memory      --out :memory: --vacuum-into <file>
+//!   blob32         --numeric-encoding blob32
+//!   light          --light (no calldata or log data)
+//!   compress       --compress (zstd calldata and log data)
+//!   binary-keys    --binary-keys (hashes, addresses and topics as BLOBs)
+//!   topics-table   --topics-table
+//!   strict         --strict (STRICT tables, transactions WITHOUT ROWID)
+//!   no-read-ahead  --queue-size 0 (decode on the inserting thread)
+//!   read-threads   --read-threads 4 (decode row groups on four threads)
+//!
+//! Without --data, a synthetic dataset is generated into the work directory
+//! first, from `generate`'s options (--blocks, --txs-per-block, ...), so the
+//! same options always bench the same data. Each configuration is loaded
+//! --runs times into a fresh database and the fastest run reported.
+//! Throughput counts the rows loaded into blocks, transactions and logs.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::{Args, Parser};
+use eyre::{Context, Result};
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::generate;
+use crate::Cli;
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Dataset to load (default: a generated one)
+    #[arg(long)]
+    data: Option<PathBuf>,
+
+    /// The dataset to generate without --data
+    #[command(flatten)]
+    spec: generate::Spec,
+
+    /// Loads per configuration; the fastest is reported
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    runs: u32,
+
+    /// Only these configurations (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    config: Vec<String>,
+
+    /// Directory for the generated dataset and the databases (default: a
+    /// temporary directory, removed afterwards)
+    #[arg(long)]
+    work_dir: Option<PathBuf>,
+
+    /// Print one JSON object per configuration instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+struct Config {
+    name: &'static str,
+    flags: &'static [&'static str],
+    in_memory: bool,
+}
+
+const CONFIGS: &[Config] = &[
+    Config { name: "default", flags: &[], in_memory: false },
+    Config { name: "bulk-pragmas", flags: &["--preset", "bulk"], in_memory: false },
+    Config { name: "in-memory", flags: &[], in_memory: true },
+    Config { name: "blob32", flags: &["--numeric-encoding", "blob32"], in_memory: false },
+    Config { name: "light", flags: &["--light"], in_memory: false },
+    Config { name: "compress", flags: &["--compress"], in_memory: false },
+    Config { name: "binary-keys", flags: &["--binary-keys"], in_memory: false },
+    Config { name: "topics-table", flags: &["--topics-table"], in_memory: false },
+    Config { name: "strict", flags: &["--strict"], in_memory: false },
+    Config { name: "no-read-ahead", flags: &["--queue-size", "0"], in_memory: false },
+    Config { name: "read-threads", flags: &["--read-threads", "4"], in_memory: false },
+];
+
+struct Measurement {
+    config: &'static str,
+    rows: i64,
+    best: Duration,
+    db_bytes: u64,
+}
+
+pub fn run(args: BenchArgs) -> Result<()> {
+    for name in &args.config {
+        if !CONFIGS.iter().any(|c| c.name == name) {
+            let known: Vec<&str> = CONFIGS.iter().map(|c| c.name).collect();
+            eyre::bail!("unknown configuration {name:?} (known: {})", known.join(", "));
+        }
+    }
+    let configs = CONFIGS
+        .iter()
+        .filter(|c| args.config.is_empty() || args.config.iter().any(|n| n == c.name));
+
+    let temporary = args.work_dir.is_none();
+    let work = args.work_dir.clone().unwrap_or_else(|| {
+        std::env::temp_dir().join(format!("offline-replay-bench-{}", std::process::id()))
+    });
+    std::fs::create_dir_all(&work).with_context(|| format!("Cannot create {}", work.display()))?;
+
+    let result = (|| -> Result<Vec<Measurement>> {
+        let data = match &args.data {
+            Some(data) => data.clone(),
+            None => {
+                let dir = work.join("dataset");
+                let m = generate::write(&dir, &args.spec)?;
+                let rows: u64 = m.tables.values().map(|t| t.rows).sum();
+                info!(dir = %dir.display(), blocks = args.spec.blocks, rows, "generated dataset");
+                dir
+            }
+        };
+        configs.map(|config| measure(config, &data, &work, args.runs)).collect()
+    })();
+    if temporary {
+        let _ = std::fs::remove_dir_all(&work);
+    }
+    let measurements = result?;
+
+    if args.json {
+        for m in &measurements {
+            let line = serde_json::json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "config": m.config,
+                "rows": m.rows,
+                "seconds": m.best.as_secs_f64(),
+                "rows_per_sec": rows_per_sec(m),
+                "db_bytes": m.db_bytes,
+            });
+            println!("{line}");
+        }
+    } else {
+        println!(
+            "{:<14} {:>10} {:>9} {:>12} {:>10}",
+            "config", "rows", "seconds", "rows/s", "db MiB"
+        );
+        for m in &measurements {
+            println!(
+                "{:<14} {:>10} {:>9.3} {:>12.0} {:>10.1}",
+                m.config,
+                m.rows,
+                m.best.as_secs_f64(),
+                rows_per_sec(m),
+                m.db_bytes as f64 / (1024.0 * 1024.0)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Load `data` `runs` times with `config`, each into a new database.
+fn measure(config: &'static Config, data: &Path, work: &Path, runs: u32) -> Result<Measurement> {
+    let db = work.join(format!("{}.db", config.name));
+    let mut best = Duration::MAX;
+    let mut rows = 0;
+    let mut db_bytes = 0;
+    for run in 1..=runs {
+        remove_db(&db)?;
+        let mut argv: Vec<&std::ffi::OsStr> =
+            vec!["offline-replay".as_ref(), "--data".as_ref(), data.as_ref()];
+        if config.in_memory {
+            argv.extend([
+                "--out".as_ref(),
+                ":memory:".as_ref(),
+                "--vacuum-into".as_ref(),
+                db.as_os_str(),
+            ]);
+        } else {
+            argv.extend(["--out".as_ref(), db.as_os_str()]);
+        }
+        argv.extend(config.flags.iter().map(std::ffi::OsStr::new));
+        let load = Cli::try_parse_from(argv)?.load;
+
+        let started = Instant::now();
+        crate::load(load).with_context(|| format!("Loading with {}", config.name))?;
+        let elapsed = started.elapsed();
+        best = best.min(elapsed);
+
+        let conn = Connection::open(&db)?;
+        rows = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM blocks) + (SELECT COUNT(*) FROM transactions)
+                    + (SELECT COUNT(*) FROM logs)",
+            [],
+            |r| r.get(0),
+        )?;
+        drop(conn);
+        db_bytes = db_size(&db)?;
+        info!(config = config.name, run, seconds = elapsed.as_secs_f64(), "loaded");
+    }
+    remove_db(&db)?;
+    Ok(Measurement { config: config.name, rows, best, db_bytes })
+}
+
+fn rows_per_sec(m: &Measurement) -> f64 {
+    m.rows as f64 / m.best.as_secs_f64().max(f64::EPSILON)
+}
+
+/// The database's size on disk, counting a WAL file left beside it.
+fn db_size(db: &Path) -> Result<u64> {
+    let mut bytes = std::fs::metadata(db)?.len();
+    if let Ok(wal) = std::fs::metadata(sidecar(db, "-wal")) {
+        bytes += wal.len();
+    }
+    Ok(bytes)
+}
+
+fn remove_db(db: &Path) -> Result<()> {
+    for path in [db.to_path_buf(), sidecar(db, "-wal"), sidecar(db, "-shm")] {
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Cannot remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn sidecar(db: &Path, suffix: &str) -> PathBuf {
+    let mut name = db.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
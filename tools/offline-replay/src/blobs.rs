@@ -0,0 +1,54 @@
+//! EIP-4844 blob sidecars, for rollup data-availability analyses over
+//! Dencun-era datasets.
+//!
+//! A dataset may carry a `blobs.parquet` (or part files), one row per blob a
+//! type-3 transaction carried; without one the table is not created. Each
+//! row is linked to its transaction by `tx_hash` and keyed by its position
+//! `blob_index` in the transaction's `blob_versioned_hashes`, with
+//! `block_number` (and `block_hash`, see reorg.rs) for the block.
+//!
+//! `kzg_commitment` and `kzg_proof` are 48-byte 0x-hex strings, kept as text
+//! with `--binary-keys` too. The blob itself is either in `data`, its 128 KiB
+//! stored like calldata (compressed with `--compress`, which blobs padded
+//! with zeros take well to), or referred to by `data_ref`, wherever the
+//! exporter kept it (a path or URL), since the beacon node prunes sidecars
+//! after about 18 days; a file may have both or neither. A missing
+//! `versioned_hash` is computed from the commitment, and one that doesn't
+//! match its commitment fails the load.
+
+use alloy_primitives::hex;
+use eyre::{eyre, Result};
+use sha2::{Digest, Sha256};
+
+/// The first byte of a versioned hash of a KZG commitment.
+const VERSIONED_HASH_VERSION_KZG: u8 = 0x01;
+
+/// The versioned hash of a 0x-hex KZG `commitment`, as 0x-hex.
+pub fn versioned_hash(commitment: &str) -> Result<String> {
+    let bytes = hex::decode(commitment)
+        .ok()
+        .filter(|b| b.len() == 48)
+        .ok_or_else(|| eyre!("kzg_commitment: {commitment:?} is not 48 bytes of hex"))?;
+    let mut hash: [u8; 32] = Sha256::digest(bytes).into();
+    hash[0] = VERSIONED_HASH_VERSION_KZG;
+    Ok(hex::encode_prefixed(hash))
+}
+
+/// A row's versioned hash: the file's, checked against `commitment` when
+/// it has both, or else computed from it.
+pub fn checked(tx_hash: &str, versioned: Option<&str>, commitment: Option<&str>) -> Result<String> {
+    match (versioned, commitment) {
+        (Some(v), Some(c)) => {
+            let computed = versioned_hash(c)?;
+            if !v.eq_ignore_ascii_case(&computed) {
+                eyre::bail!(
+                    "blob of {tx_hash}: versioned_hash {v} doesn't match its kzg_commitment ({computed})"
+                );
+            }
+            Ok(v.to_string())
+        }
+        (Some(v), None) => Ok(v.to_string()),
+        (None, Some(c)) => versioned_hash(c),
+        (None, None) => eyre::bail!("blob of {tx_hash}: no versioned_hash or kzg_commitment"),
+    }
+}
@@ -0,0 +1,453 @@
+//! `call` — an eth_call run offline: the call executes in revm against the
+//! state after `--at-block` as the database has it (state_diffs and
+//! contracts, see state_snapshot.rs), so "what would balanceOf have
+//! returned at block N" needs no archive node:
+//!
+//!   offline-replay call euler.db --to 0x… --at-block 16817995 \
+//!     --sig "balanceOf(address)(uint256)" 0x…
+//!
+//! `--sig` is the function signature with its return types in a second
+//! pair of parentheses; the arguments after it are encoded as those types
+//! and the output decoded and printed one value per line. Or `--data` gives
+//! the calldata as hex, and the output is printed as hex.
+//!
+//! The block environment (number, timestamp, gas limit, base fee, the
+//! hashes BLOCKHASH returns) comes from the blocks table; the call is made
+//! with a zero gas price and no balance check, as eth_call is. Accounts and
+//! slots are read where the execution touches them; any the tables don't
+//! have at the block are fetched with `--rpc`, or without it read as zero
+//! and counted in a warning, which makes the result only as good as the
+//! capture. The hardfork is mainnet's at the block unless `--hardfork`
+//! names one. Needs a build with `--features simulate`.
+
+use std::path::PathBuf;
+
+use alloy_primitives::{Address, U256};
+use clap::Args;
+
+#[derive(Args, Debug)]
+#[cfg_attr(not(feature = "simulate"), allow(dead_code))]
+pub struct CallArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Arguments for --sig, one per parameter
+    #[arg(requires = "sig", allow_negative_numbers = true)]
+    args: Vec<String>,
+
+    /// Contract to call
+    #[arg(long)]
+    to: Address,
+
+    /// Function and return types, e.g. "balanceOf(address)(uint256)"
+    #[arg(long, conflicts_with = "data", required_unless_present = "data")]
+    sig: Option<String>,
+
+    /// Calldata as 0x-hex, instead of --sig and arguments
+    #[arg(long)]
+    data: Option<String>,
+
+    /// Run against the state after this block (default: the last one)
+    #[arg(long)]
+    at_block: Option<i64>,
+
+    /// Caller address
+    #[arg(long, default_value_t = Address::ZERO)]
+    from: Address,
+
+    /// Wei sent with the call
+    #[arg(long, default_value_t = U256::ZERO)]
+    value: U256,
+
+    /// Gas limit (default: the block's)
+    #[arg(long)]
+    gas: Option<u64>,
+
+    /// Hardfork rules to run under, e.g. London, Shanghai or Cancun
+    /// (default: mainnet's at the block)
+    #[arg(long)]
+    hardfork: Option<String>,
+
+    /// Archive node JSON-RPC endpoint for the state the tables don't have
+    #[arg(long)]
+    rpc: Option<String>,
+
+    /// Attempts per RPC call before giving up
+    #[arg(long, default_value_t = 8, requires = "rpc")]
+    retries: u32,
+
+    /// Base delay between retries in milliseconds (doubles on HTTP 429)
+    #[arg(long, default_value_t = 2000, requires = "rpc")]
+    retry_delay_ms: u64,
+}
+
+#[cfg(not(feature = "simulate"))]
+pub fn run(_args: CallArgs) -> eyre::Result<()> {
+    eyre::bail!("`call` needs a build with `--features simulate`")
+}
+
+#[cfg(feature = "simulate")]
+pub use evm::run;
+
+#[cfg(feature = "simulate")]
+mod evm {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use alloy_dyn_abi::{DynSolType, DynSolValue};
+    use alloy_primitives::{hex, keccak256, Address, Bytes, B256, U256};
+    use eyre::{eyre, Context, Result};
+    use revm::primitives::{
+        AccountInfo, Bytecode, ExecutionResult, Output, SpecId, TxKind, KECCAK_EMPTY,
+    };
+    use revm::{Database, Evm};
+    use rusqlite::{Connection, OptionalExtension};
+    use tracing::{info, warn};
+
+    use super::CallArgs;
+    use crate::normalize::read_amount;
+    use crate::open_existing;
+    use crate::revert;
+    use crate::rpc::RpcClient;
+    use crate::state_snapshot::{self, Account, Tables};
+
+    /// Mainnet's hardforks by first block, latest first.
+    const MAINNET: [(i64, SpecId); 14] = [
+        (19_426_587, SpecId::CANCUN),
+        (17_034_870, SpecId::SHANGHAI),
+        (15_537_394, SpecId::MERGE),
+        (15_050_000, SpecId::GRAY_GLACIER),
+        (13_773_000, SpecId::ARROW_GLACIER),
+        (12_965_000, SpecId::LONDON),
+        (12_244_000, SpecId::BERLIN),
+        (9_200_000, SpecId::MUIR_GLACIER),
+        (9_069_000, SpecId::ISTANBUL),
+        (7_280_000, SpecId::PETERSBURG),
+        (4_370_000, SpecId::BYZANTIUM),
+        (2_675_000, SpecId::SPURIOUS_DRAGON),
+        (2_463_000, SpecId::TANGERINE),
+        (1_150_000, SpecId::HOMESTEAD),
+    ];
+
+    pub fn run(args: CallArgs) -> Result<()> {
+        let conn = open_existing(&args.db)?;
+        let block = match args.at_block {
+            Some(block) => block,
+            None => conn
+                .query_row("SELECT MAX(number) FROM blocks", [], |r| r.get::<_, Option<i64>>(0))?
+                .ok_or_else(|| eyre!("{} has no blocks", args.db.display()))?,
+        };
+        state_snapshot::check_block(&conn, &args.db, block)?;
+        let spec = match &args.hardfork {
+            Some(name) => match SpecId::from(name.as_str()) {
+                SpecId::LATEST if name != "Latest" => {
+                    eyre::bail!("unknown hardfork {name:?} (e.g. London, Shanghai, Cancun)")
+                }
+                spec => spec,
+            },
+            None => MAINNET
+                .iter()
+                .find(|(first, _)| block >= *first)
+                .map_or(SpecId::FRONTIER, |&(_, spec)| spec),
+        };
+        let (timestamp, gas_limit, base_fee) = conn
+            .query_row(
+                "SELECT timestamp, gas_limit, base_fee FROM blocks
+                 WHERE number = ?1 AND is_canonical = 1",
+                [block],
+                |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, read_amount(r.get_ref(2)?).ok())),
+            )
+            .optional()?
+            .ok_or_else(|| eyre!("block {block} is not in {}", args.db.display()))?;
+
+        let (sig, data) = match (&args.sig, &args.data) {
+            (Some(sig), _) => {
+                let sig = Signature::parse(sig)?;
+                let data = sig.encode(&args.args)?;
+                (Some(sig), data)
+            }
+            (None, Some(data)) => (None, hex::decode(data).context("--data is not hex")?),
+            (None, None) => unreachable!("clap requires one"),
+        };
+
+        let db = StateDb {
+            tables: Tables::open(&conn, args.rpc.is_some())?,
+            conn: &conn,
+            block,
+            client: args.rpc.as_deref().map(|url| {
+                RpcClient::new(url, args.retries, Duration::from_millis(args.retry_delay_ms))
+            }),
+            accounts: HashMap::new(),
+            fetched: 0,
+            unknown: 0,
+        };
+        let mut evm = Evm::builder()
+            .with_db(db)
+            .with_spec_id(spec)
+            .modify_cfg_env(|cfg| {
+                cfg.disable_balance_check = true;
+                cfg.disable_base_fee = true;
+                cfg.disable_block_gas_limit = true;
+                cfg.disable_eip3607 = true;
+            })
+            .modify_block_env(|env| {
+                env.number = U256::from(block);
+                env.timestamp = U256::from(timestamp);
+                env.gas_limit = U256::from(gas_limit);
+                env.basefee = base_fee.unwrap_or_default();
+                env.prevrandao = Some(B256::ZERO);
+            })
+            .modify_tx_env(|tx| {
+                tx.caller = args.from;
+                tx.transact_to = TxKind::Call(args.to);
+                tx.data = Bytes::from(data);
+                tx.value = args.value;
+                tx.gas_limit = args.gas.unwrap_or(gas_limit as u64);
+                tx.gas_price = U256::ZERO;
+                tx.nonce = None;
+            })
+            .build();
+        let result = evm.transact().map_err(|e| eyre!("call failed: {e}"))?.result;
+        let db = evm.db();
+        if db.unknown > 0 {
+            warn!(
+                values = db.unknown,
+                "state the call read was not in the tables at the block and was taken as zero; \
+                 --rpc fetches it"
+            );
+        }
+        info!(block, hardfork = ?spec, fetched = db.fetched, gas_used = result.gas_used(), "called");
+
+        match result {
+            ExecutionResult::Success { output, .. } => {
+                let output = match output {
+                    Output::Call(bytes) => bytes,
+                    Output::Create(bytes, _) => bytes,
+                };
+                match sig.filter(|s| !s.outputs.is_empty()) {
+                    Some(sig) => {
+                        for value in sig.decode(&output)? {
+                            println!("{}", show(&value));
+                        }
+                    }
+                    None => println!("0x{}", hex::encode(&output)),
+                }
+                Ok(())
+            }
+            ExecutionResult::Revert { output, .. } => {
+                eyre::bail!("call reverted: {}", revert_reason(&output))
+            }
+            ExecutionResult::Halt { reason, .. } => eyre::bail!("call halted: {reason:?}"),
+        }
+    }
+
+    /// revm's view of the database: accounts and slots after `block`, read
+    /// on first use.
+    struct StateDb<'a> {
+        conn: &'a Connection,
+        tables: Tables,
+        block: i64,
+        client: Option<RpcClient>,
+        accounts: HashMap<Address, Account>,
+        /// Values fetched over RPC.
+        fetched: usize,
+        /// Values neither the tables nor RPC had, read as zero.
+        unknown: usize,
+    }
+
+    impl StateDb<'_> {
+        fn account(&mut self, address: Address) -> Result<&mut Account> {
+            if !self.accounts.contains_key(&address) {
+                let mut account = self.tables.account(self.conn, &address, self.block)?;
+                match &self.client {
+                    Some(client) => {
+                        self.fetched +=
+                            state_snapshot::fetch(client, &address, self.block, &mut account)?
+                    }
+                    None => {
+                        self.unknown += [
+                            account.balance.is_none(),
+                            account.nonce.is_none(),
+                            account.code.is_none(),
+                        ]
+                        .iter()
+                        .filter(|&&u| u)
+                        .count()
+                    }
+                }
+                self.accounts.insert(address, account);
+            }
+            Ok(self.accounts.get_mut(&address).expect("just inserted"))
+        }
+    }
+
+    impl Database for StateDb<'_> {
+        type Error = eyre::Report;
+
+        fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>> {
+            let account = self.account(address)?;
+            if account.balance.is_none() && account.nonce.is_none() && account.code.is_none() {
+                return Ok(None);
+            }
+            let code = account.code.clone().unwrap_or_default();
+            let hash = if code.is_empty() { KECCAK_EMPTY } else { keccak256(&code) };
+            Ok(Some(AccountInfo::new(
+                account.balance.unwrap_or_default(),
+                account.nonce.unwrap_or(0),
+                hash,
+                Bytecode::new_raw(code.into()),
+            )))
+        }
+
+        fn code_by_hash(&mut self, _code_hash: B256) -> Result<Bytecode> {
+            // basic() always hands over the code with the account.
+            Ok(Bytecode::new())
+        }
+
+        fn storage(&mut self, address: Address, index: U256) -> Result<U256> {
+            let block = self.block;
+            // Accounts are read before their storage, which fills in the
+            // slots written after the block when there is RPC.
+            self.account(address)?;
+            let account = self.accounts.get_mut(&address).expect("read above");
+            if let Some(value) = account.storage.get(&index) {
+                return Ok(*value);
+            }
+            let value = match &self.client {
+                Some(client) => {
+                    self.fetched += 1;
+                    state_snapshot::fetch_slot(client, &address, index, block)?
+                }
+                None => {
+                    self.unknown += 1;
+                    U256::ZERO
+                }
+            };
+            account.storage.insert(index, value);
+            Ok(value)
+        }
+
+        fn block_hash(&mut self, number: u64) -> Result<B256> {
+            let hash: Option<String> = self
+                .conn
+                .query_row(
+                    "SELECT hash FROM blocks WHERE number = ?1 AND is_canonical = 1",
+                    [number as i64],
+                    |r| r.get(0),
+                )
+                .optional()?;
+            match hash {
+                Some(hash) => hash.parse().with_context(|| format!("block {number}: bad hash")),
+                None => {
+                    self.unknown += 1;
+                    Ok(B256::ZERO)
+                }
+            }
+        }
+    }
+
+    /// A `--sig` such as "balanceOf(address)(uint256)".
+    struct Signature {
+        /// The canonical "name(type,...)" the selector is hashed from.
+        canonical: String,
+        inputs: Vec<DynSolType>,
+        outputs: Vec<DynSolType>,
+    }
+
+    impl Signature {
+        fn parse(sig: &str) -> Result<Self> {
+            let sig = sig.trim();
+            let bad = || eyre!("--sig {sig:?}: expected name(types)(return types)");
+            let open = sig.find('(').ok_or_else(bad)?;
+            let name = sig[..open].trim();
+            // The end of the parameter list: its matching parenthesis.
+            let mut depth = 0;
+            let close = sig[open..]
+                .char_indices()
+                .find_map(|(i, c)| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    (depth == 0).then_some(open + i)
+                })
+                .ok_or_else(bad)?;
+            let tuple = |s: &str| -> Result<Vec<DynSolType>> {
+                match DynSolType::parse(s).with_context(|| format!("--sig {sig:?}"))? {
+                    DynSolType::Tuple(types) => Ok(types),
+                    _ => Err(bad()),
+                }
+            };
+            let inputs = tuple(&sig[open..=close])?;
+            let rest = sig[close + 1..].trim();
+            let outputs = if rest.is_empty() { Vec::new() } else { tuple(rest)? };
+            if name.is_empty() {
+                return Err(bad());
+            }
+            let types: Vec<String> =
+                inputs.iter().map(|t| t.sol_type_name().into_owned()).collect();
+            Ok(Self { canonical: format!("{name}({})", types.join(",")), inputs, outputs })
+        }
+
+        fn encode(&self, args: &[String]) -> Result<Vec<u8>> {
+            if args.len() != self.inputs.len() {
+                eyre::bail!(
+                    "{} takes {} argument(s), {} given",
+                    self.canonical,
+                    self.inputs.len(),
+                    args.len()
+                );
+            }
+            let values = self
+                .inputs
+                .iter()
+                .zip(args)
+                .map(|(ty, arg)| {
+                    ty.coerce_str(arg).with_context(|| format!("{arg:?} is not a {}", ty))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok([&keccak256(&self.canonical)[..4], &DynSolValue::Tuple(values).abi_encode_params()]
+                .concat())
+        }
+
+        fn decode(&self, output: &[u8]) -> Result<Vec<DynSolValue>> {
+            let decoded = DynSolType::Tuple(self.outputs.clone())
+                .abi_decode_params(output)
+                .with_context(|| {
+                    format!("cannot decode output 0x{} as the return types", hex::encode(output))
+                })?;
+            match decoded {
+                DynSolValue::Tuple(values) => Ok(values),
+                value => Ok(vec![value]),
+            }
+        }
+    }
+
+    /// A value as cast prints it: numbers in decimal, bytes as 0x-hex.
+    fn show(value: &DynSolValue) -> String {
+        let list = |values: &[DynSolValue]| values.iter().map(show).collect::<Vec<_>>().join(", ");
+        match value {
+            DynSolValue::Bool(b) => b.to_string(),
+            DynSolValue::Int(i, _) => i.to_string(),
+            DynSolValue::Uint(u, _) => u.to_string(),
+            DynSolValue::Address(a) => a.to_checksum(None),
+            DynSolValue::FixedBytes(word, size) => format!("0x{}", hex::encode(&word[..*size])),
+            DynSolValue::Bytes(b) => format!("0x{}", hex::encode(b)),
+            DynSolValue::String(s) => format!("{s:?}"),
+            DynSolValue::Function(f) => format!("0x{}", hex::encode(f.as_slice())),
+            DynSolValue::Array(values) | DynSolValue::FixedArray(values) => {
+                format!("[{}]", list(values))
+            }
+            DynSolValue::Tuple(values) => format!("({})", list(values)),
+        }
+    }
+
+    /// The reason in revert data (see revert.rs), or the data itself.
+    fn revert_reason(output: &[u8]) -> String {
+        match revert::decode(output, &HashMap::new()) {
+            r if r.kind == "unknown" => format!("0x{}", hex::encode(output)),
+            r => r.to_string(),
+        }
+    }
+}
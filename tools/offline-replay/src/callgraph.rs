@@ -0,0 +1,220 @@
+//! `export callgraph` — one transaction's calls from the `traces` table (see
+//! analyze/traces.rs) as a graph of the contracts involved, the figure
+//! every post-mortem opens with:
+//!
+//!   dot   Graphviz DOT: a node per address, named from `labels` and
+//!         `ens_names`, and an edge per call numbered in order
+//!   json  the same nodes and edges, for other renderers
+//!
+//! An edge is labelled with its frame number (pre-order, as the trace
+//! analyses count) and function, with the ETH it moved if any. Delegate
+//! calls are dashed, static calls dotted, and failed calls red, their
+//! subcalls included. Functions are named from the ABIs of `--abi` (see
+//! abi.rs), then from `function_signatures` and the common ones `--views`
+//! seeds; the rest show as selectors. `--max-depth` leaves out calls deeper
+//! than it, to keep a large exploit readable.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::PathBuf;
+
+use alloy_primitives::U256;
+use clap::{Args, ValueEnum};
+use eyre::{Context, Result};
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+
+use crate::analyze::traces::{self, check_traces};
+use crate::export::{dot_escape, names, write_file};
+use crate::{abi, open_existing, rpc, views};
+
+#[derive(Args, Debug)]
+pub struct CallgraphArgs {
+    /// SQLite database with a `traces` table, as written by scripts/capture.py
+    db: PathBuf,
+
+    /// Hash of the transaction
+    #[arg(long)]
+    tx: String,
+
+    /// Output file
+    #[arg(short, long)]
+    out: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = CallgraphFormat::Dot)]
+    format: CallgraphFormat,
+
+    /// ABI JSON file, or a directory of them, to name functions (repeatable)
+    #[arg(long)]
+    abi: Vec<PathBuf>,
+
+    /// Leave out calls deeper than this (the transaction's own call is 0)
+    #[arg(long)]
+    max_depth: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum CallgraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// Nodes and edges as JSON
+    Json,
+}
+
+#[derive(Serialize)]
+struct Graph {
+    tx_hash: String,
+    block_number: i64,
+    nodes: Vec<Node>,
+    edges: Vec<Call>,
+}
+
+#[derive(Serialize)]
+struct Node {
+    address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Call {
+    frame: usize,
+    depth: usize,
+    kind: String,
+    from: String,
+    to: String,
+    /// 0x for a plain transfer.
+    selector: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function: Option<String>,
+    /// Wei, decimal.
+    value: String,
+    gas_used: u64,
+    /// Whether the call, or one it was made by, failed.
+    failed: bool,
+}
+
+pub fn run(args: CallgraphArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    check_traces(&conn)?;
+    let tx = args.tx.to_ascii_lowercase();
+    let found: Option<(i64, String, String)> = conn
+        .query_row(
+            "SELECT block_number, tx_hash, trace_json FROM traces WHERE lower(tx_hash) = ?1",
+            [&tx],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )
+        .optional()?;
+    let Some((block_number, tx_hash, json)) = found else {
+        eyre::bail!("no trace of {tx} in the traces table");
+    };
+    let trace: Value =
+        serde_json::from_str(&json).with_context(|| format!("Bad trace_json for {tx_hash}"))?;
+
+    let mut functions = views::function_names(&conn)?;
+    functions.extend(abi::entries(&args.abi, "function")?.into_iter().map(|(s, f)| (s, f.name)));
+    let edges = edges(&trace, args.max_depth, &functions)?;
+
+    let labels = names(&conn)?;
+    let mut nodes: BTreeMap<&str, Option<String>> = BTreeMap::new();
+    for e in &edges {
+        for address in [&e.from, &e.to] {
+            nodes.entry(address).or_insert_with(|| labels.get(address.as_str()).cloned());
+        }
+    }
+    let nodes = nodes
+        .into_iter()
+        .map(|(address, name)| Node { address: address.to_string(), name })
+        .collect();
+    let graph = Graph { tx_hash, block_number, nodes, edges };
+
+    match args.format {
+        CallgraphFormat::Dot => write_file(&args.out, |w| dot(w, &graph))?,
+        CallgraphFormat::Json => write_file(&args.out, |w| {
+            serde_json::to_writer_pretty(&mut *w, &graph)?;
+            writeln!(w)?;
+            Ok(())
+        })?,
+    }
+    info!(
+        out = %args.out.display(),
+        nodes = graph.nodes.len(),
+        calls = graph.edges.len(),
+        "wrote call graph"
+    );
+    Ok(())
+}
+
+/// The calls of a callTracer trace; those below `max_depth` are left out,
+/// keeping their frame numbers.
+fn edges(
+    trace: &Value,
+    max_depth: Option<usize>,
+    functions: &HashMap<String, String>,
+) -> Result<Vec<Call>> {
+    let mut edges = Vec::new();
+    traces::walk(trace, &mut |f| {
+        if max_depth.is_some_and(|max| f.depth > max) {
+            return Ok(());
+        }
+        let address = |key: &str| f.call[key].as_str().unwrap_or_default().to_ascii_lowercase();
+        let input = f.call["input"].as_str().unwrap_or_default().to_ascii_lowercase();
+        let selector = input.get(..10).unwrap_or("0x").to_string();
+        let value = match &f.call["value"] {
+            Value::Null => U256::ZERO,
+            v => U256::from(rpc::quantity(v)?),
+        };
+        let gas_used = match &f.call["gasUsed"] {
+            Value::Null => 0,
+            g => rpc::quantity(g)? as u64,
+        };
+        edges.push(Call {
+            frame: f.number,
+            depth: f.depth,
+            kind: f.call["type"].as_str().unwrap_or("CALL").to_ascii_uppercase(),
+            from: address("from"),
+            to: address("to"),
+            function: functions.get(&selector).cloned(),
+            selector,
+            value: value.to_string(),
+            gas_used,
+            failed: f.failed,
+        });
+        Ok(())
+    })?;
+    Ok(edges)
+}
+
+fn dot(w: &mut dyn Write, graph: &Graph) -> Result<()> {
+    writeln!(w, "digraph call_graph {{")?;
+    writeln!(w, "  label=\"{} (block {})\";", graph.tx_hash, graph.block_number)?;
+    writeln!(w, "  node [shape=box];")?;
+    for node in &graph.nodes {
+        let label = match &node.name {
+            Some(name) => format!("{}\\n{}", dot_escape(name), node.address),
+            None => node.address.clone(),
+        };
+        writeln!(w, "  \"{}\" [label=\"{label}\"];", node.address)?;
+    }
+    for e in &graph.edges {
+        let function = e.function.as_deref().unwrap_or(&e.selector);
+        let mut label = format!("{}. {}", e.frame, dot_escape(function));
+        if e.value != "0" {
+            label.push_str(&format!("\\n{} wei", e.value));
+        }
+        let mut attrs = vec![format!("label=\"{label}\"")];
+        match e.kind.as_str() {
+            "DELEGATECALL" | "CALLCODE" => attrs.push("style=dashed".to_string()),
+            "STATICCALL" => attrs.push("style=dotted".to_string()),
+            _ => {}
+        }
+        if e.failed {
+            attrs.push("color=red".to_string());
+        }
+        writeln!(w, "  \"{}\" -> \"{}\" [{}];", e.from, e.to, attrs.join(", "))?;
+    }
+    writeln!(w, "}}")?;
+    Ok(())
+}
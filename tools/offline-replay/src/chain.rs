@@ -0,0 +1,820 @@
+//! `--chain`: which chain a dataset is from, for the fields an L2 adds to
+//! Ethereum's (loaded as extra columns of `blocks` and `transactions`
+//! instead of being dropped as unknown) and what is known of the chain.
+//!
+//! `ethereum`, `op-stack` and `arbitrum` name a kind of chain, by its
+//! columns, and say nothing more of it. The presets are chains of one kind
+//! with an expected chain id, average block time and system addresses:
+//!
+//!   mainnet, sepolia                Ethereum; 12s
+//!   polygon, bsc, gnosis, avalanche Ethereum's columns; 2s, 1.5s, 5s, 2s
+//!   optimism, base                  op-stack; 2s
+//!   arbitrum-one, arbitrum-nova     arbitrum; 0.25s on One
+//!
+//! (BSC's blocks were 3s until 2025, and 0.75s since its Maxwell fork.)
+//! A preset is checked against the dataset as it loads:
+//!
+//!   - the manifest's `chain_id` (written by `fetch`), and a `chain_id`
+//!     column of transactions (cryo has one), must be the preset's: a
+//!     dataset of another chain fails the load
+//!   - the blocks' average spacing more than three times off the preset's
+//!     block time is warned about
+//!
+//! `fetch --chain` checks the node's eth_chainId the same way, and finds
+//! the blocks of `--from-date`/`--to-date` with the block time; `labels
+//! --chain` labels the system addresses: precompiles, system contracts and
+//! predeploys.
+//!
+//! For any kind but `ethereum`, a file's column of another kind is warned
+//! about, and so is one of the kind's own columns the dataset lacks where
+//! it changes what is loaded (`l1_fee`, `gas_used_for_l1`,
+//! `l1_block_number`).
+//!
+//! `op-stack` (Optimism, Base and the other OP Stack chains) adds to
+//! transactions:
+//!
+//!   tx_type            the EIP-2718 type; 126 (0x7e) for deposits
+//!   source_hash        what a deposit was derived from (its L1 log, or the
+//!                      L1 block for the first transaction of each block)
+//!   mint               wei a deposit minted on L2
+//!   is_system_tx       1 for system transactions: the L1 attributes deposit
+//!                      that opens every block, or a deposit the file flags
+//!                      (isSystemTx, before Regolith); 0 otherwise
+//!   l1_fee             wei charged for the L1 data of the transaction
+//!   l1_gas_used        the L1 gas its data was charged for
+//!   l1_gas_price       the L1 base fee that fee was priced at, and
+//!   l1_blob_base_fee   the L1 blob base fee (from Ecotone on)
+//!   l1_fee_scalar      the fee scalar, as the receipt has it (before Ecotone)
+//!
+//! and `fee_wei` includes `l1_fee`, so it is what the sender paid.
+//!
+//! `arbitrum` (Arbitrum One and Nova, and Orbit chains) adds to blocks
+//! `l1_block_number`, the L1 block the sequencer was at (`number` is the L2
+//! block), and the outbox's `send_count` and `send_root`, and to
+//! transactions:
+//!
+//!   tx_type                 the EIP-2718 type: 100 (0x64) for ETH deposits,
+//!                           104 (0x68) for retryable redeems, 105 (0x69)
+//!                           for retryable ticket submissions, 106 (0x6a)
+//!                           for ArbOS internal transactions
+//!   gas_used_for_l1         the part of `gas_used` that paid for L1 calldata
+//!   l1_block_number         the L1 block the transaction was sequenced at
+//!   request_id              the L1 inbox message of a deposit or submission
+//!   ticket_id               the retryable a redeem ran: the hash of the
+//!                           submission that created it
+//!   refund_to, beneficiary  who gets excess gas and fees back, and who may
+//!                           cancel the ticket
+//!   deposit_value           wei a submission brought from L1
+//!   l1_base_fee             the L1 base fee it was priced at
+//!   max_submission_fee      what it offered for the ticket's storage
+//!   max_refund              the most a redeem refunds, and
+//!   submission_fee_refund   the part of that which is submission fee
+//!
+//! Arbitrum's `gas_used` already counts the L1 component, so `fee_wei` is
+//! `gas_used * gas_price` there, as on Ethereum.
+//!
+//! All of them are optional in the files: a missing column loads as NULL
+//! (0 for `is_system_tx`), and a differently named one can be `--map`ped,
+//! e.g. `--map type=tx_type`.
+//!
+//! The chain is recorded in `_meta` like the value encodings: appending
+//! with another `--chain` is refused.
+
+use arrow::array::{Array, Int64Array, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use clap::ValueEnum;
+use eyre::{eyre, Result};
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::error::DatasetError;
+use crate::migrate;
+use crate::normalize::Normalize;
+use crate::schema::{col, strict_types, ColumnSpec, Kind, Table};
+use crate::{col_i64_opt, col_str_opt, opt_i64, opt_str};
+
+/// Sender of the L1 attributes deposit, the system transaction at the start
+/// of every OP Stack block.
+const L1_ATTRIBUTES_DEPOSITOR: &str = "0xdeaddeaddeaddeaddeaddeaddeaddeaddead0001";
+
+/// The EIP-2718 type of an OP Stack deposit transaction.
+const DEPOSIT_TX_TYPE: i64 = 0x7e;
+
+/// Blocks whose average spacing is further than this factor from the preset's
+/// block time is warned about.
+const BLOCK_TIME_TOLERANCE: f64 = 3.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Chain {
+    /// Any chain with Ethereum's transaction fields
+    #[default]
+    Ethereum,
+    /// Any OP Stack rollup: deposit transactions and L1 data fees
+    OpStack,
+    /// Any Arbitrum rollup: retryable tickets, L1 gas and L1 block numbers
+    Arbitrum,
+    /// Ethereum mainnet (chain 1)
+    Mainnet,
+    /// The Sepolia testnet (chain 11155111)
+    Sepolia,
+    /// Polygon PoS (chain 137)
+    Polygon,
+    /// BNB Smart Chain (chain 56)
+    Bsc,
+    /// Gnosis Chain (chain 100)
+    Gnosis,
+    /// Avalanche C-Chain (chain 43114)
+    Avalanche,
+    /// OP Mainnet (chain 10)
+    Optimism,
+    /// Base (chain 8453)
+    Base,
+    /// Arbitrum One (chain 42161)
+    ArbitrumOne,
+    /// Arbitrum Nova (chain 42170)
+    ArbitrumNova,
+}
+
+/// What a preset expects of its chain.
+struct Preset {
+    id: u64,
+    /// Average block time in milliseconds, where it is regular.
+    block_time_ms: Option<u64>,
+    /// The chain's own system addresses, on top of its kind's.
+    system: &'static [(&'static str, &'static str)],
+}
+
+/// The precompiles every EVM chain has.
+const PRECOMPILES: &[(&str, &str)] = &[
+    ("0x0000000000000000000000000000000000000001", "ecrecover"),
+    ("0x0000000000000000000000000000000000000002", "sha256"),
+    ("0x0000000000000000000000000000000000000003", "ripemd160"),
+    ("0x0000000000000000000000000000000000000004", "identity"),
+    ("0x0000000000000000000000000000000000000005", "modexp"),
+    ("0x0000000000000000000000000000000000000006", "ecAdd"),
+    ("0x0000000000000000000000000000000000000007", "ecMul"),
+    ("0x0000000000000000000000000000000000000008", "ecPairing"),
+    ("0x0000000000000000000000000000000000000009", "blake2f"),
+    ("0x000000000000000000000000000000000000000a", "point evaluation"),
+];
+
+/// EIP-4788's contract, deployed on Ethereum with Dencun and on OP Stack
+/// chains with Ecotone.
+const BEACON_ROOTS: (&str, &str) = ("0x000f3df6d732807ef1319fb7b8bb8522d0beac02", "beacon roots");
+
+/// Ethereum's system contracts and Prague's BLS12-381 precompiles.
+const ETHEREUM_SYSTEM: &[(&str, &str)] = &[
+    ("0x000000000000000000000000000000000000000b", "bls12 g1add"),
+    ("0x000000000000000000000000000000000000000c", "bls12 g1msm"),
+    ("0x000000000000000000000000000000000000000d", "bls12 g2add"),
+    ("0x000000000000000000000000000000000000000e", "bls12 g2msm"),
+    ("0x000000000000000000000000000000000000000f", "bls12 pairing check"),
+    ("0x0000000000000000000000000000000000000010", "bls12 map fp to g1"),
+    ("0x0000000000000000000000000000000000000011", "bls12 map fp2 to g2"),
+    BEACON_ROOTS,
+    ("0x0000f90827f1c53a10cb7a02335b175320002935", "history storage"),
+];
+
+const MAINNET_SYSTEM: &[(&str, &str)] =
+    &[("0x00000000219ab540356cbb839cbe05303d7705fa", "beacon deposit contract")];
+
+const SEPOLIA_SYSTEM: &[(&str, &str)] =
+    &[("0x7f02c3e3c98b133055b8b348b2ac625669ed295d", "beacon deposit contract")];
+
+const POLYGON_SYSTEM: &[(&str, &str)] = &[
+    ("0x0000000000000000000000000000000000001000", "validator set"),
+    ("0x0000000000000000000000000000000000001001", "state receiver"),
+    ("0x0000000000000000000000000000000000001010", "POL token"),
+];
+
+const BSC_SYSTEM: &[(&str, &str)] = &[
+    ("0x0000000000000000000000000000000000001000", "validator set"),
+    ("0x0000000000000000000000000000000000001001", "slash indicator"),
+    ("0x0000000000000000000000000000000000001002", "system reward"),
+    ("0x0000000000000000000000000000000000001004", "token hub"),
+    ("0x0000000000000000000000000000000000001007", "gov hub"),
+    ("0x0000000000000000000000000000000000002000", "cross chain"),
+    ("0x0000000000000000000000000000000000002002", "stake hub"),
+];
+
+const AVALANCHE_SYSTEM: &[(&str, &str)] = &[
+    ("0x0100000000000000000000000000000000000000", "native asset balance"),
+    ("0x0100000000000000000000000000000000000001", "native asset call"),
+    ("0x0200000000000000000000000000000000000005", "warp messenger"),
+];
+
+const OP_STACK_SYSTEM: &[(&str, &str)] = &[
+    (L1_ATTRIBUTES_DEPOSITOR, "L1 attributes depositor"),
+    ("0x4200000000000000000000000000000000000006", "WETH"),
+    ("0x4200000000000000000000000000000000000007", "L2 cross domain messenger"),
+    ("0x420000000000000000000000000000000000000f", "gas price oracle"),
+    ("0x4200000000000000000000000000000000000010", "L2 standard bridge"),
+    ("0x4200000000000000000000000000000000000011", "sequencer fee vault"),
+    ("0x4200000000000000000000000000000000000012", "mintable ERC20 factory"),
+    ("0x4200000000000000000000000000000000000014", "L2 ERC721 bridge"),
+    ("0x4200000000000000000000000000000000000015", "L1 block"),
+    ("0x4200000000000000000000000000000000000016", "L2 to L1 message passer"),
+    ("0x4200000000000000000000000000000000000019", "base fee vault"),
+    ("0x420000000000000000000000000000000000001a", "L1 fee vault"),
+    BEACON_ROOTS,
+    ("0x0000000000000000000000000000000000000100", "p256verify"),
+];
+
+const OPTIMISM_SYSTEM: &[(&str, &str)] =
+    &[("0x4200000000000000000000000000000000000042", "OP token")];
+
+const ARBITRUM_SYSTEM: &[(&str, &str)] = &[
+    ("0x0000000000000000000000000000000000000064", "ArbSys"),
+    ("0x0000000000000000000000000000000000000065", "ArbInfo"),
+    ("0x0000000000000000000000000000000000000066", "ArbAddressTable"),
+    ("0x0000000000000000000000000000000000000068", "ArbFunctionTable"),
+    ("0x000000000000000000000000000000000000006b", "ArbOwnerPublic"),
+    ("0x000000000000000000000000000000000000006c", "ArbGasInfo"),
+    ("0x000000000000000000000000000000000000006d", "ArbAggregator"),
+    ("0x000000000000000000000000000000000000006e", "ArbRetryableTx"),
+    ("0x000000000000000000000000000000000000006f", "ArbStatistics"),
+    ("0x0000000000000000000000000000000000000070", "ArbOwner"),
+    ("0x0000000000000000000000000000000000000071", "ArbWasm"),
+    ("0x0000000000000000000000000000000000000072", "ArbWasmCache"),
+    ("0x00000000000000000000000000000000000000c8", "NodeInterface"),
+    ("0x00000000000000000000000000000000000a4b05", "ArbOS"),
+];
+
+/// How an extra column is stored.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stored {
+    Integer,
+    /// A uint256, encoded like the other amounts.
+    Amount,
+    /// A hash or address, like the other keys.
+    Key,
+    Text,
+    /// OP Stack's `is_system_tx`, also set for the L1 attributes deposit.
+    SystemTx,
+}
+
+struct Extra {
+    spec: ColumnSpec,
+    /// The column's type as SCHEMA would write it (see [`strict_types`]).
+    sql: &'static str,
+    stored: Stored,
+}
+
+const fn extra(name: &'static str, stored: Stored) -> Extra {
+    let (kind, sql) = match stored {
+        Stored::Integer => (Kind::Int64, "INTEGER"),
+        Stored::Amount => (Kind::Utf8, "AMOUNT"),
+        Stored::Key => (Kind::Utf8, "HEX"),
+        Stored::Text => (Kind::Utf8, "TEXT"),
+        Stored::SystemTx => (Kind::Int64, "INTEGER NOT NULL DEFAULT 0"),
+    };
+    Extra { spec: col(name, kind, true), sql, stored }
+}
+
+const OP_STACK_TRANSACTIONS: &[Extra] = &[
+    extra("tx_type", Stored::Integer),
+    extra("source_hash", Stored::Key),
+    extra("mint", Stored::Amount),
+    extra("is_system_tx", Stored::SystemTx),
+    extra("l1_fee", Stored::Amount),
+    extra("l1_gas_used", Stored::Integer),
+    extra("l1_gas_price", Stored::Amount),
+    extra("l1_blob_base_fee", Stored::Amount),
+    extra("l1_fee_scalar", Stored::Text),
+];
+
+const ARBITRUM_BLOCKS: &[Extra] = &[
+    extra("l1_block_number", Stored::Integer),
+    extra("send_count", Stored::Integer),
+    extra("send_root", Stored::Key),
+];
+
+const ARBITRUM_TRANSACTIONS: &[Extra] = &[
+    extra("tx_type", Stored::Integer),
+    extra("gas_used_for_l1", Stored::Integer),
+    extra("l1_block_number", Stored::Integer),
+    extra("request_id", Stored::Key),
+    extra("ticket_id", Stored::Key),
+    extra("refund_to", Stored::Key),
+    extra("beneficiary", Stored::Key),
+    extra("deposit_value", Stored::Amount),
+    extra("l1_base_fee", Stored::Amount),
+    extra("max_submission_fee", Stored::Amount),
+    extra("max_refund", Stored::Amount),
+    extra("submission_fee_refund", Stored::Amount),
+];
+
+impl Chain {
+    pub const ALL: [Chain; 13] = [
+        Chain::Ethereum,
+        Chain::OpStack,
+        Chain::Arbitrum,
+        Chain::Mainnet,
+        Chain::Sepolia,
+        Chain::Polygon,
+        Chain::Bsc,
+        Chain::Gnosis,
+        Chain::Avalanche,
+        Chain::Optimism,
+        Chain::Base,
+        Chain::ArbitrumOne,
+        Chain::ArbitrumNova,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Chain::Ethereum => "ethereum",
+            Chain::OpStack => "op-stack",
+            Chain::Arbitrum => "arbitrum",
+            Chain::Mainnet => "mainnet",
+            Chain::Sepolia => "sepolia",
+            Chain::Polygon => "polygon",
+            Chain::Bsc => "bsc",
+            Chain::Gnosis => "gnosis",
+            Chain::Avalanche => "avalanche",
+            Chain::Optimism => "optimism",
+            Chain::Base => "base",
+            Chain::ArbitrumOne => "arbitrum-one",
+            Chain::ArbitrumNova => "arbitrum-nova",
+        }
+    }
+
+    /// The kind of chain this is: `ethereum`, `op-stack` or `arbitrum`.
+    pub fn kind(self) -> Chain {
+        match self {
+            Chain::OpStack | Chain::Optimism | Chain::Base => Chain::OpStack,
+            Chain::Arbitrum | Chain::ArbitrumOne | Chain::ArbitrumNova => Chain::Arbitrum,
+            _ => Chain::Ethereum,
+        }
+    }
+
+    fn preset(self) -> Option<Preset> {
+        let preset = |id, block_time_ms, system| Some(Preset { id, block_time_ms, system });
+        match self {
+            Chain::Ethereum | Chain::OpStack | Chain::Arbitrum => None,
+            Chain::Mainnet => preset(1, Some(12_000), MAINNET_SYSTEM),
+            Chain::Sepolia => preset(11_155_111, Some(12_000), SEPOLIA_SYSTEM),
+            Chain::Polygon => preset(137, Some(2_000), POLYGON_SYSTEM),
+            Chain::Bsc => preset(56, Some(1_500), BSC_SYSTEM),
+            Chain::Gnosis => preset(100, Some(5_000), &[]),
+            Chain::Avalanche => preset(43_114, Some(2_000), AVALANCHE_SYSTEM),
+            Chain::Optimism => preset(10, Some(2_000), OPTIMISM_SYSTEM),
+            Chain::Base => preset(8_453, Some(2_000), &[]),
+            // Nova makes a block per batch of transactions, at no set pace.
+            Chain::ArbitrumOne => preset(42_161, Some(250), &[]),
+            Chain::ArbitrumNova => preset(42_170, None, &[]),
+        }
+    }
+
+    /// The chain id a preset expects.
+    pub fn id(self) -> Option<u64> {
+        self.preset().map(|p| p.id)
+    }
+
+    /// A preset's average block time, in seconds.
+    pub fn block_time(self) -> Option<f64> {
+        self.preset().and_then(|p| p.block_time_ms).map(|ms| ms as f64 / 1000.0)
+    }
+
+    /// The precompiles, system contracts and predeploys of the chain, with
+    /// their names.
+    pub fn system_addresses(self) -> Vec<(&'static str, &'static str)> {
+        let mut addresses = PRECOMPILES.to_vec();
+        match self {
+            Chain::Mainnet | Chain::Sepolia => addresses.extend(ETHEREUM_SYSTEM),
+            _ => {}
+        }
+        match self.kind() {
+            Chain::OpStack => addresses.extend(OP_STACK_SYSTEM),
+            Chain::Arbitrum => addresses.extend(ARBITRUM_SYSTEM),
+            _ => {}
+        }
+        addresses.extend(self.preset().map_or(&[][..], |p| p.system));
+        addresses
+    }
+
+    /// Fail unless a dataset of chain `id` (from its manifest or a
+    /// `chain_id` column) is this preset's chain.
+    pub fn check_id(self, id: u64, what: &str) -> Result<()> {
+        match self.id() {
+            Some(expected) if expected != id => Err(eyre!(
+                "{what} is chain {id}{}, but --chain {} is chain {expected}",
+                Chain::by_id(id).map(|c| format!(" ({})", c.name())).unwrap_or_default(),
+                self.name()
+            )),
+            Some(_) => Ok(()),
+            None => {
+                if let Some(preset) = Chain::by_id(id).filter(|c| c.kind() != self.kind()) {
+                    warn!(
+                        chain_id = id,
+                        "{what} is {}; pass --chain {} to load its columns",
+                        preset.name(),
+                        preset.name()
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The preset of chain `id`.
+    pub fn by_id(id: u64) -> Option<Chain> {
+        Chain::ALL.into_iter().find(|c| c.id() == Some(id))
+    }
+
+    /// Fail if a `table` batch has a `chain_id` column (as cryo writes for
+    /// transactions) with another chain than the preset's. Legacy
+    /// transactions from before EIP-155 have none, or 0.
+    pub fn check_batch(self, table: Table, batch: &RecordBatch) -> Result<()> {
+        let (Some(_), Some(column)) = (self.id(), batch.column_by_name("chain_id")) else {
+            return Ok(());
+        };
+        let Ok(ids) = cast(column, &DataType::UInt64) else { return Ok(()) };
+        let ids = ids.as_any().downcast_ref::<UInt64Array>().expect("cast to UInt64");
+        match ids.iter().flatten().find(|&id| id != 0) {
+            Some(id) => self.check_id(id, &format!("{}'s chain_id column", table.name())),
+            None => Ok(()),
+        }
+    }
+
+    /// Warn if `blocks` blocks over `seconds` are spaced further from the
+    /// preset's block time than [`BLOCK_TIME_TOLERANCE`].
+    pub fn check_block_time(self, blocks: i64, seconds: i64) {
+        let Some(expected) = self.block_time() else { return };
+        if blocks < 2 {
+            return;
+        }
+        let observed = seconds as f64 / blocks as f64;
+        if observed * BLOCK_TIME_TOLERANCE < expected || observed > expected * BLOCK_TIME_TOLERANCE
+        {
+            warn!(
+                observed = format!("{observed:.2}s"),
+                expected = format!("{expected}s"),
+                "blocks are spaced unlike --chain {}'s; is the dataset from another chain?",
+                self.name()
+            );
+        }
+    }
+
+    /// The chain kind other than this one's that reads `column` of `table`.
+    pub fn other_kind(self, table: Table, column: &str) -> Option<Chain> {
+        [Chain::OpStack, Chain::Arbitrum].into_iter().find(|&k| {
+            k != self.kind()
+                && !self.column_names(table).any(|c| c == column)
+                && k.column_names(table).any(|c| c == column)
+        })
+    }
+
+    /// Whether a dataset without `column` of `table` loads less than the
+    /// chain has: the fee or L1 block a kind's columns add.
+    pub fn expects(self, table: Table, column: &str) -> bool {
+        let expected: &[&str] = match (self.kind(), table) {
+            (Chain::OpStack, Table::Transactions) => &["l1_fee"],
+            (Chain::Arbitrum, Table::Blocks) => &["l1_block_number"],
+            (Chain::Arbitrum, Table::Transactions) => &["gas_used_for_l1"],
+            _ => &[],
+        };
+        expected.contains(&column)
+    }
+
+    fn extra(self, table: Table) -> &'static [Extra] {
+        match (self.kind(), table) {
+            (Chain::OpStack, Table::Transactions) => OP_STACK_TRANSACTIONS,
+            (Chain::Arbitrum, Table::Blocks) => ARBITRUM_BLOCKS,
+            (Chain::Arbitrum, Table::Transactions) => ARBITRUM_TRANSACTIONS,
+            _ => &[],
+        }
+    }
+
+    /// Every column the loader reads for `table` on this chain.
+    pub fn columns(self, table: Table) -> impl Iterator<Item = &'static ColumnSpec> {
+        table.columns().iter().chain(self.extra(table).iter().map(|e| &e.spec))
+    }
+
+    /// The columns this chain adds to `table`, in the order
+    /// [`ChainColumns::values`] gives them.
+    pub fn column_names(self, table: Table) -> impl Iterator<Item = &'static str> {
+        self.extra(table).iter().map(|e| e.spec.name)
+    }
+
+    /// Whether `column` holds a uint256 on some chain.
+    pub fn is_amount(column: &str) -> bool {
+        Chain::ALL.iter().any(|c| {
+            Table::ALL.iter().any(|&t| {
+                c.extra(t).iter().any(|e| e.stored == Stored::Amount && e.spec.name == column)
+            })
+        })
+    }
+
+    /// Add the chain's columns to the tables of `conn` that don't have them
+    /// yet, typed like the rest of a `--strict` database if it is one.
+    pub fn add_columns(self, conn: &Connection, norm: &Normalize) -> Result<()> {
+        let strict = migrate::is_strict(conn)?;
+        for table in Table::ALL {
+            for e in self.extra(table) {
+                let name = e.spec.name;
+                let present: bool = conn.query_row(
+                    "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+                    [table.name(), name],
+                    |r| r.get(0),
+                )?;
+                if present {
+                    continue;
+                }
+                let ty = if strict {
+                    strict_types(norm, e.sql)
+                } else {
+                    e.sql.replace("AMOUNT", "TEXT").replace("HEX", "TEXT")
+                };
+                conn.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN {name} {ty};",
+                    table.name()
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// This chain's columns of a `table` batch.
+    pub fn read<'a>(
+        self,
+        table: Table,
+        batch: &'a RecordBatch,
+    ) -> Result<ChainColumns<'a>, DatasetError> {
+        let mut columns = Vec::new();
+        for e in self.extra(table) {
+            let name = e.spec.name;
+            let column = match e.spec.kind {
+                Kind::Int64 => Column::Int(col_i64_opt(batch, name)?),
+                _ => Column::Str(col_str_opt(batch, name)?),
+            };
+            columns.push((e, column));
+        }
+        Ok(ChainColumns { columns })
+    }
+}
+
+enum Column<'a> {
+    Int(Option<&'a Int64Array>),
+    Str(Option<&'a StringArray>),
+}
+
+pub struct ChainColumns<'a> {
+    columns: Vec<(&'static Extra, Column<'a>)>,
+}
+
+impl ChainColumns<'_> {
+    /// Row `i`'s values of the chain's columns, as stored; `from_addr` is
+    /// the sender of a transaction.
+    pub fn values(
+        &self,
+        i: usize,
+        norm: &Normalize,
+        from_addr: Option<&str>,
+    ) -> Result<Vec<Value>> {
+        let mut values = Vec::with_capacity(self.columns.len());
+        for (e, column) in &self.columns {
+            let name = e.spec.name;
+            values.push(match (e.stored, column) {
+                (Stored::SystemTx, Column::Int(flag)) => {
+                    let deposit = self.integer("tx_type", i).map_or(true, |t| t == DEPOSIT_TX_TYPE);
+                    let system = opt_i64(*flag, i).is_some_and(|s| s != 0)
+                        || (deposit
+                            && from_addr
+                                .is_some_and(|f| f.eq_ignore_ascii_case(L1_ATTRIBUTES_DEPOSITOR)));
+                    Value::Integer(system as i64)
+                }
+                (_, Column::Int(arr)) => opt_i64(*arr, i).map_or(Value::Null, Value::Integer),
+                (stored, Column::Str(arr)) => match (stored, opt_str(*arr, i)) {
+                    (_, None) => Value::Null,
+                    (Stored::Amount, Some(s)) => norm.amount(name, s)?,
+                    (Stored::Key, Some(s)) => norm.key_value(name, s)?,
+                    (_, Some(s)) => Value::Text(s.to_string()),
+                },
+            });
+        }
+        Ok(values)
+    }
+
+    /// What row `i` paid on top of `gas_used * gas_price`: its L1 fee, on
+    /// an OP Stack chain.
+    pub fn l1_fee(&self, i: usize) -> Option<&str> {
+        self.columns.iter().find_map(|(e, column)| match column {
+            Column::Str(arr) if e.spec.name == "l1_fee" => opt_str(*arr, i),
+            _ => None,
+        })
+    }
+
+    fn integer(&self, name: &str, i: usize) -> Option<i64> {
+        self.columns.iter().find_map(|(e, column)| match column {
+            Column::Int(arr) if e.spec.name == name => opt_i64(*arr, i),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use arrow::array::ArrayRef;
+    use arrow::datatypes::{Field, Schema};
+
+    use super::*;
+    use crate::open_existing;
+    use crate::testing::{block, count, load_into, rewrite, tx, write, Scratch};
+
+    /// A dataset of one block with two transactions, the first sent by
+    /// `first_from`, with `columns` added to the transactions file.
+    fn dataset(dir: &Path, first_from: &str, columns: Vec<(&str, ArrayRef)>) {
+        let hash = |n: u64| format!("0x{n:064x}");
+        let mut first = tx(&hash(0xa), 1, 0);
+        first.from_addr = first_from.to_string();
+        write(dir, vec![block(1, &hash(1), &hash(0))], vec![first, tx(&hash(0xb), 1, 1)], vec![]);
+        add(&dir.join(Table::Transactions.file()), columns);
+    }
+
+    /// Add `columns` to the Parquet file at `path`.
+    fn add(path: &Path, columns: Vec<(&str, ArrayRef)>) {
+        rewrite(path, |batch| {
+            let schema = batch.schema();
+            let mut fields: Vec<Field> =
+                schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+            let mut arrays = batch.columns().to_vec();
+            for (name, array) in columns {
+                fields.push(Field::new(name, array.data_type().clone(), true));
+                arrays.push(array);
+            }
+            RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).unwrap()
+        });
+    }
+
+    /// The text of `column` of the transaction at `tx_index`.
+    fn column(conn: &Connection, column: &str, tx_index: i64) -> Option<String> {
+        conn.query_row(
+            &format!("SELECT CAST({column} AS TEXT) FROM transactions WHERE tx_index = ?1"),
+            [tx_index],
+            |r| r.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn op_stack_deposits_and_l1_fees() {
+        let dir = Scratch::new("chain-op");
+        let data = dir.join("data");
+        let deposit = format!("0x{:064x}", 0xd);
+        dataset(
+            &data,
+            L1_ATTRIBUTES_DEPOSITOR,
+            vec![
+                ("tx_type", Arc::new(Int64Array::from(vec![DEPOSIT_TX_TYPE, 2]))),
+                ("source_hash", Arc::new(StringArray::from(vec![Some(deposit.as_str()), None]))),
+                ("mint", Arc::new(StringArray::from(vec![Some("0"), None]))),
+                ("l1_fee", Arc::new(StringArray::from(vec![None, Some("5")]))),
+            ],
+        );
+        let db = dir.join("op.db");
+        load_into(&data, &db, "--chain op-stack").unwrap();
+        let conn = open_existing(&db).unwrap();
+        assert_eq!(column(&conn, "is_system_tx", 0).as_deref(), Some("1"));
+        assert_eq!(column(&conn, "is_system_tx", 1).as_deref(), Some("0"));
+        assert_eq!(column(&conn, "source_hash", 0), Some(deposit));
+        assert_eq!(column(&conn, "mint", 0).as_deref(), Some("0"));
+        assert_eq!(column(&conn, "l1_gas_used", 1), None);
+        // fee_wei is gas_used * gas_price and the L1 fee.
+        assert_eq!(column(&conn, "fee_wei", 0).as_deref(), Some("21000000000000"));
+        assert_eq!(column(&conn, "fee_wei", 1).as_deref(), Some("21000000000005"));
+
+        // Loaded as Ethereum, the columns are left out; appended to as
+        // Ethereum, an op-stack database refuses.
+        let plain = dir.join("plain.db");
+        load_into(&data, &plain, "").unwrap();
+        let l1_fee = "SELECT COUNT(*) FROM pragma_table_info('transactions') WHERE name = 'l1_fee'";
+        assert_eq!(count(&open_existing(&plain).unwrap(), l1_fee), 0);
+        let e = load_into(&data, &db, "--mode append").unwrap_err();
+        assert!(format!("{e:#}").contains("op-stack"), "{e:#}");
+    }
+
+    #[test]
+    fn arbitrum_l1_blocks_and_retryables() {
+        let dir = Scratch::new("chain-arbitrum");
+        let data = dir.join("data");
+        let ticket = format!("0x{:064x}", 0x7);
+        let refund_to = format!("0x{:040x}", 0x5);
+        dataset(
+            &data,
+            &format!("0x{:040x}", 1),
+            vec![
+                ("tx_type", Arc::new(Int64Array::from(vec![0x68, 2]))),
+                ("gas_used_for_l1", Arc::new(Int64Array::from(vec![None, Some(400)]))),
+                ("ticket_id", Arc::new(StringArray::from(vec![Some(ticket.as_str()), None]))),
+                ("refund_to", Arc::new(StringArray::from(vec![Some(refund_to.as_str()), None]))),
+                ("max_refund", Arc::new(StringArray::from(vec![Some("9"), None]))),
+            ],
+        );
+        add(
+            &data.join(Table::Blocks.file()),
+            vec![
+                ("l1_block_number", Arc::new(Int64Array::from(vec![17_000_000]))),
+                ("send_count", Arc::new(Int64Array::from(vec![3]))),
+            ],
+        );
+        let db = dir.join("arbitrum.db");
+        load_into(&data, &db, "--chain arbitrum-one").unwrap();
+        // A second load finds the columns there.
+        load_into(&data, &db, "--chain arbitrum-one --mode append").unwrap();
+        let conn = open_existing(&db).unwrap();
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM transactions"), 2);
+        let l1_block = "SELECT l1_block_number FROM blocks WHERE send_count = 3";
+        assert_eq!(count(&conn, l1_block), 17_000_000);
+        let send_root = "SELECT COUNT(*) FROM blocks WHERE send_root IS NULL";
+        assert_eq!(count(&conn, send_root), 1);
+        assert_eq!(column(&conn, "tx_type", 0).as_deref(), Some("104"));
+        assert_eq!(column(&conn, "ticket_id", 0), Some(ticket));
+        assert_eq!(column(&conn, "refund_to", 0), Some(refund_to));
+        assert_eq!(column(&conn, "max_refund", 0).as_deref(), Some("9"));
+        assert_eq!(column(&conn, "gas_used_for_l1", 1).as_deref(), Some("400"));
+        // gas_used already counts the L1 part.
+        assert_eq!(column(&conn, "fee_wei", 1).as_deref(), Some("21000000000000"));
+    }
+
+    #[test]
+    fn columns_of_another_kind() {
+        let tx = Table::Transactions;
+        assert_eq!(Chain::Ethereum.other_kind(tx, "l1_fee"), Some(Chain::OpStack));
+        assert_eq!(Chain::Base.other_kind(tx, "gas_used_for_l1"), Some(Chain::Arbitrum));
+        assert_eq!(Chain::Ethereum.other_kind(Table::Blocks, "send_root"), Some(Chain::Arbitrum));
+        // Both kinds have tx_type, and a kind's own columns are its own.
+        assert_eq!(Chain::Arbitrum.other_kind(tx, "tx_type"), None);
+        assert_eq!(Chain::ArbitrumNova.other_kind(tx, "request_id"), None);
+        assert_eq!(Chain::OpStack.other_kind(tx, "value"), None);
+        assert!(Chain::ArbitrumOne.expects(tx, "gas_used_for_l1"));
+        assert!(!Chain::Ethereum.expects(tx, "gas_used_for_l1"));
+    }
+
+    #[test]
+    fn preset_chain_ids() {
+        let cases = [
+            (Chain::Base, 8_453, None),
+            (Chain::Base, 10, Some("is chain 10 (optimism), but --chain base is chain 8453")),
+            (Chain::Mainnet, 11_155_111, Some("is chain 11155111 (sepolia), but --chain mainnet")),
+            (Chain::Polygon, 5, Some("the dataset is chain 5, but --chain polygon is chain 137")),
+            // Kinds have no chain id to check.
+            (Chain::OpStack, 10, None),
+            (Chain::Ethereum, 42_161, None),
+        ];
+        for (chain, id, error) in cases {
+            let checked = chain.check_id(id, "the dataset");
+            match error {
+                None => assert!(checked.is_ok(), "{} {id}: {checked:?}", chain.name()),
+                Some(error) => assert!(
+                    checked.as_ref().is_err_and(|e| e.to_string().contains(error)),
+                    "{} {id}: {checked:?}",
+                    chain.name()
+                ),
+            }
+        }
+        assert_eq!(Chain::by_id(42_161), Some(Chain::ArbitrumOne));
+        assert_eq!(Chain::by_id(5), None);
+    }
+
+    #[test]
+    fn chain_id_columns() {
+        let batch = |ids: Vec<Option<u64>>| {
+            let ids: ArrayRef = Arc::new(UInt64Array::from(ids));
+            RecordBatch::try_from_iter([("chain_id", ids)]).unwrap()
+        };
+        let tx = Table::Transactions;
+        // Legacy transactions have none, or 0.
+        let mainnet = batch(vec![None, Some(0), Some(1)]);
+        assert!(Chain::Mainnet.check_batch(tx, &mainnet).is_ok());
+        assert!(Chain::Ethereum.check_batch(tx, &mainnet).is_ok());
+        let e = Chain::Polygon.check_batch(tx, &mainnet).unwrap_err();
+        assert!(e.to_string().contains("transactions's chain_id column is chain 1"), "{e}");
+        assert!(Chain::Polygon.check_batch(tx, &batch(vec![None, Some(0)])).is_ok());
+    }
+
+    #[test]
+    fn loads_check_the_manifest_chain_id() {
+        let dir = Scratch::new("chain-id");
+        let data = dir.join("data");
+        dataset(&data, &format!("0x{:040x}", 1), vec![]);
+        let manifest = data.join(crate::manifest::MANIFEST_FILE);
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&manifest).unwrap()).unwrap();
+        json["chain_id"] = 1.into();
+        std::fs::write(&manifest, serde_json::to_vec(&json).unwrap()).unwrap();
+
+        load_into(&data, &dir.join("mainnet.db"), "--chain mainnet").unwrap();
+        let e = load_into(&data, &dir.join("base.db"), "--chain base").unwrap_err();
+        assert!(format!("{e:#}").contains("is chain 1 (mainnet), but --chain base"), "{e:#}");
+        assert!(!dir.join("base.db").exists());
+    }
+}
@@ -0,0 +1,473 @@
+//! Reading datasets from other exporters (cryo, ethereum-etl), whose files
+//! have the same data under other column names, or a few columns more or
+//! less.
+//!
+//! `--map FROM=TO` renames the file's column FROM to the loader's TO before
+//! anything else looks at it (`--map transaction_hash=tx_hash`); qualified
+//! as `--map logs.transaction_hash=tx_hash` it only applies to that table.
+//! An unqualified mapping applies to each table that has a TO column.
+//!
+//! Each file's columns are then checked once it's opened:
+//!
+//!   - a required column that is missing fails the load, listing the file's
+//!     columns so the right --map is easy to see
+//!   - a missing optional column (base_fee, to_addr, input, logs_bloom,
+//!     nonce, topics, data) loads as NULL; without `is_canonical` and
+//!     `block_hash` every row is taken as canonical (see reorg.rs)
+//!   - columns the loader doesn't know are ignored, with a warning; an L2's
+//!     own columns are only read with its `--chain`, and a `chain_id`
+//!     column is checked against a `--chain` preset's
+//!
+//! The notes are logged once per table and column, not for every part file.
+//!
+//! Columns of another Arrow type than the loader's are coerced as they are
+//! read, where that loses nothing:
+//!
+//!   - integer columns: any signed or unsigned integer, Decimal128 with
+//!     scale 0, or Boolean as 0 and 1; a value that doesn't fit an Int64
+//!     fails the load
+//!   - hashes, addresses and topics: LargeUtf8, or Binary, LargeBinary and
+//!     FixedSizeBinary bytes, written as 0x-hex
+//!   - amounts (value, gas_price, base_fee, and the L2 fees and mint of
+//!     chain.rs): also any integer, or Decimal128
+//!     and Decimal256 with scale 0, written as decimal
+//!   - input and data: LargeBinary, FixedSizeBinary, or 0x-hex text
+//!
+//! Any other type fails the load with exit code 4.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use alloy_primitives::hex;
+use arrow::array::{Array, ArrayRef, BinaryArray, StringArray};
+use arrow::compute::{cast_with_options, CastOptions};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use eyre::{Context, Result};
+use tracing::{debug, info, warn};
+
+use crate::chain::Chain;
+use crate::error::DatasetError;
+use crate::schema::{ColumnSpec, Kind, Table, REORG_COLUMNS};
+
+/// Text columns holding a uint256, which may come as a number instead (and
+/// those of chain.rs).
+const AMOUNTS: [&str; 3] = ["value", "gas_price", "base_fee"];
+
+/// One `--map [TABLE.]FROM=TO`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rename {
+    table: Option<Table>,
+    from: String,
+    to: String,
+}
+
+/// Parse `--map [TABLE.]FROM=TO`; TO must be a column the loader reads
+/// (on some `--chain`).
+pub fn parse(s: &str) -> Result<Rename, String> {
+    let (from, to) = s.split_once('=').ok_or_else(|| format!("expected FROM=TO, got {s:?}"))?;
+    let (from, to) = (from.trim(), to.trim());
+    let (table, from) = match from.split_once('.') {
+        Some((table, from)) => {
+            let table = Table::named(table)
+                .ok_or_else(|| format!("unknown table {table:?} in {s:?}"))?;
+            (Some(table), from)
+        }
+        None => (None, from),
+    };
+    if from.is_empty() {
+        return Err(format!("expected FROM=TO, got {s:?}"));
+    }
+    let every = Table::ALL.into_iter().chain(Table::OPTIONAL).collect();
+    let tables: Vec<Table> = table.map_or(every, |t| vec![t]);
+    let known = |t: &Table| Chain::ALL.iter().any(|c| c.columns(*t).any(|c| c.name == to));
+    if !tables.iter().any(known) {
+        let place = table.map_or("the dataset".to_string(), |t| t.name().to_string());
+        return Err(format!("{to:?} is not a column of {place}"));
+    }
+    Ok(Rename { table, from: from.to_string(), to: to.to_string() })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ColumnMap {
+    renames: Vec<Rename>,
+    /// Whose columns the loader reads.
+    chain: Chain,
+    /// `table.column` notes already logged.
+    noted: Arc<Mutex<HashSet<String>>>,
+}
+
+impl ColumnMap {
+    pub fn new(renames: Vec<Rename>, chain: Chain) -> Self {
+        Self { renames, chain, noted: Arc::default() }
+    }
+
+    /// The loader's columns of `table`.
+    pub fn columns(&self, table: Table) -> impl Iterator<Item = &'static ColumnSpec> {
+        self.chain.columns(table)
+    }
+
+    /// The mappings that apply to `table`, as (file column, loader column).
+    pub(crate) fn for_table(&self, table: Table) -> impl Iterator<Item = (&str, &str)> {
+        self.renames
+            .iter()
+            .filter(move |r| {
+                r.table.map_or(true, |t| t == table)
+                    && self.columns(table).any(|c| c.name == r.to)
+            })
+            .map(|r| (r.from.as_str(), r.to.as_str()))
+    }
+
+    /// `skip` (loader names) with the file's names for mapped columns added,
+    /// so a mapped column is projected away too.
+    pub fn skip(&self, table: Table, skip: &[String]) -> Vec<String> {
+        let mut names = skip.to_vec();
+        for (from, to) in self.for_table(table) {
+            if skip.iter().any(|s| s == to) {
+                names.push(from.to_string());
+            }
+        }
+        names
+    }
+
+    /// `schema` with the columns mapped for `table` renamed.
+    pub fn rename_schema(&self, table: Table, schema: &Schema) -> Result<SchemaRef> {
+        let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+        for (from, to) in self.for_table(table) {
+            let Some(i) = fields.iter().position(|f| f.name() == from) else { continue };
+            if fields.iter().any(|f| f.name() == to) {
+                eyre::bail!(
+                    "--map {from}={to}: the {} file already has a {to} column",
+                    table.name()
+                );
+            }
+            fields[i] = fields[i].clone().with_name(to);
+        }
+        Ok(Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())))
+    }
+
+    /// Check a file's columns (already renamed) against the loader's.
+    /// `skip` columns aren't expected to be there.
+    pub fn check(&self, table: Table, schema: &Schema, skip: &[String]) -> Result<()> {
+        for spec in self.columns(table).filter(|c| !skip.iter().any(|s| s == c.name)) {
+            if let Ok(field) = schema.field_with_name(spec.name) {
+                let found = field.data_type();
+                let expected = spec.kind.data_type();
+                if *found == expected {
+                    continue;
+                }
+                if !coercible(spec, found) {
+                    return Err(DatasetError::TypeMismatch {
+                        column: spec.name.to_string(),
+                        expected,
+                        found: found.clone(),
+                    }
+                    .into());
+                }
+                if self.first_note(table, spec.name) {
+                    debug!(table = table.name(), column = spec.name, %found, %expected, "coercing column");
+                }
+                continue;
+            }
+            if !spec.nullable {
+                let columns: Vec<&str> =
+                    schema.fields().iter().map(|f| f.name().as_str()).collect();
+                return Err(eyre::Report::new(DatasetError::MissingColumn {
+                    column: spec.name.to_string(),
+                }))
+                .wrap_err(format!(
+                    "The file's columns are {}; if one of them holds {}, add --map NAME={}",
+                    columns.join(", "),
+                    spec.name,
+                    spec.name
+                ));
+            }
+            if !self.first_note(table, spec.name) {
+                continue;
+            }
+            if REORG_COLUMNS.contains(&spec.name) {
+                debug!(
+                    table = table.name(),
+                    column = spec.name,
+                    "column not in the file; rows taken as canonical"
+                );
+            } else if self.chain.expects(table, spec.name) {
+                warn!(
+                    table = table.name(),
+                    column = spec.name,
+                    "column not in the file, though --chain {} has it; loading NULL",
+                    self.chain.name()
+                );
+            } else {
+                info!(
+                    table = table.name(),
+                    column = spec.name,
+                    "column not in the file; loading NULL"
+                );
+            }
+        }
+        for field in schema.fields() {
+            let name = field.name();
+            if !skip.contains(name)
+                && !self.columns(table).any(|c| c.name == name)
+                && self.first_note(table, name)
+            {
+                match self.chain.other_kind(table, name) {
+                    Some(kind) => warn!(
+                        table = table.name(),
+                        column = %name,
+                        "column of --chain {} datasets; ignored",
+                        kind.name()
+                    ),
+                    None if name == "chain_id" && self.chain.id().is_some() => debug!(
+                        table = table.name(),
+                        "chain_id checked against --chain {}",
+                        self.chain.name()
+                    ),
+                    None => warn!(
+                        table = table.name(),
+                        column = %name,
+                        "column not used by the loader; ignored"
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The name of loader column `column` in a `table` file with `schema`.
+    pub fn file_name<'a>(&'a self, table: Table, column: &'a str, schema: &Schema) -> &'a str {
+        self.for_table(table)
+            .find(|(from, to)| *to == column && schema.field_with_name(from).is_ok())
+            .map_or(column, |(from, _)| from)
+    }
+
+    /// `batch` renamed and coerced for `table`: what the loader reads.
+    pub fn convert(&self, table: Table, batch: RecordBatch) -> Result<RecordBatch> {
+        let batch = self.rename(table, batch)?;
+        self.chain.check_batch(table, &batch)?;
+        coerce(self.columns(table), batch)
+    }
+
+    /// `batch` with the columns mapped for `table` renamed.
+    pub fn rename(&self, table: Table, batch: RecordBatch) -> Result<RecordBatch> {
+        if self.for_table(table).next().is_none() {
+            return Ok(batch);
+        }
+        let schema = self.rename_schema(table, &batch.schema())?;
+        RecordBatch::try_new(schema, batch.columns().to_vec()).context("Renaming columns")
+    }
+
+    fn first_note(&self, table: Table, column: &str) -> bool {
+        let mut noted = self.noted.lock().unwrap_or_else(|e| e.into_inner());
+        noted.insert(format!("{}.{column}", table.name()))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Type coercion
+// ---------------------------------------------------------------------------
+
+/// `batch` (already renamed) with the loader `specs` of another type than
+/// the loader's coerced to it.
+fn coerce<'a>(
+    specs: impl Iterator<Item = &'a ColumnSpec>,
+    batch: RecordBatch,
+) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+    let mut columns = batch.columns().to_vec();
+    let mut changed = false;
+    for spec in specs {
+        let Some(i) = fields.iter().position(|f| f.name() == spec.name) else { continue };
+        let expected = spec.kind.data_type();
+        if *fields[i].data_type() == expected {
+            continue;
+        }
+        columns[i] = coerce_column(spec, &columns[i]).with_context(|| {
+            format!("Reading column {} ({}) as {expected}", spec.name, fields[i].data_type())
+        })?;
+        fields[i] = fields[i].clone().with_data_type(expected);
+        changed = true;
+    }
+    if !changed {
+        return Ok(batch);
+    }
+    let schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+    RecordBatch::try_new(schema, columns).context("Coercing columns")
+}
+
+/// Whether a column of type `found` can be read as `spec`'s kind.
+pub fn coercible(spec: &ColumnSpec, found: &DataType) -> bool {
+    use DataType::*;
+    let integer = found.is_integer() || matches!(found, Decimal128(_, 0));
+    let bytes = matches!(found, Binary | LargeBinary | FixedSizeBinary(_));
+    match spec.kind {
+        Kind::Int64 => integer || matches!(found, Boolean),
+        Kind::Utf8 if AMOUNTS.contains(&spec.name) || Chain::is_amount(spec.name) => {
+            matches!(found, LargeUtf8 | Decimal256(_, 0)) || integer || bytes
+        }
+        Kind::Utf8 => matches!(found, LargeUtf8) || bytes,
+        Kind::Binary => matches!(found, Utf8 | LargeUtf8) || bytes,
+    }
+}
+
+fn coerce_column(spec: &ColumnSpec, array: &ArrayRef) -> Result<ArrayRef> {
+    let found = array.data_type();
+    if !coercible(spec, found) {
+        return Err(DatasetError::TypeMismatch {
+            column: spec.name.to_string(),
+            expected: spec.kind.data_type(),
+            found: found.clone(),
+        }
+        .into());
+    }
+    // Without `safe`, a value that doesn't fit is an error rather than a null.
+    let strict = CastOptions { safe: false, ..Default::default() };
+    let bytes =
+        matches!(found, DataType::Binary | DataType::LargeBinary | DataType::FixedSizeBinary(_));
+    Ok(match spec.kind {
+        Kind::Utf8 if bytes => {
+            let bytes = cast_with_options(array, &DataType::Binary, &strict)?;
+            let bytes = bytes.as_any().downcast_ref::<BinaryArray>().expect("cast to Binary");
+            let hex: StringArray =
+                bytes.iter().map(|b| b.map(|b| format!("0x{}", hex::encode(b)))).collect();
+            Arc::new(hex)
+        }
+        Kind::Binary if matches!(found, DataType::Utf8 | DataType::LargeUtf8) => {
+            let text = cast_with_options(array, &DataType::Utf8, &strict)?;
+            let text = text.as_any().downcast_ref::<StringArray>().expect("cast to Utf8");
+            let bytes = text
+                .iter()
+                .map(|t| {
+                    t.map(|t| hex::decode(t).map_err(|e| eyre::eyre!("not 0x-hex text: {e}")))
+                        .transpose()
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Arc::new(BinaryArray::from_iter(bytes))
+        }
+        kind => cast_with_options(array, &kind.data_type(), &strict)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{
+        BooleanArray, Decimal256Array, FixedSizeBinaryArray, Int64Array, LargeBinaryArray,
+        UInt32Array, UInt64Array,
+    };
+    use arrow::datatypes::i256;
+
+    /// The loader's column `name`, on whichever chain has it.
+    fn spec(name: &str) -> &'static ColumnSpec {
+        Chain::ALL
+            .iter()
+            .flat_map(|c| Table::ALL.into_iter().flat_map(move |t| c.columns(t)))
+            .find(|c| c.name == name)
+            .unwrap_or_else(|| panic!("no column {name}"))
+    }
+
+    #[test]
+    fn coercible_types() {
+        use DataType::*;
+        let cases = [
+            // Integers.
+            ("gas_used", Int32, true),
+            ("gas_used", UInt64, true),
+            ("gas_used", Decimal128(38, 0), true),
+            ("gas_used", Boolean, true),
+            ("gas_used", Decimal128(38, 2), false),
+            ("gas_used", Float64, false),
+            ("gas_used", Utf8, false),
+            // Amounts, of Ethereum's and of an L2's.
+            ("value", LargeUtf8, true),
+            ("value", UInt64, true),
+            ("value", Decimal256(76, 0), true),
+            ("value", Binary, true),
+            ("value", Decimal256(76, 18), false),
+            ("value", Float64, false),
+            ("l1_fee", Decimal128(38, 0), true),
+            ("l1_fee", Float32, false),
+            // Hashes, addresses and topics.
+            ("hash", LargeUtf8, true),
+            ("hash", FixedSizeBinary(32), true),
+            ("hash", LargeBinary, true),
+            ("hash", Int64, false),
+            ("hash", Decimal256(76, 0), false),
+            // Bytes.
+            ("input", Utf8, true),
+            ("input", LargeUtf8, true),
+            ("input", LargeBinary, true),
+            ("input", FixedSizeBinary(4), true),
+            ("input", Int64, false),
+        ];
+        for (column, found, accepted) in cases {
+            assert_eq!(coercible(spec(column), &found), accepted, "{column} as {found}");
+        }
+    }
+
+    #[test]
+    fn coerced_values() {
+        let array = |a: &dyn Array| arrow::array::make_array(a.to_data());
+        let text = |s: &[Option<&str>]| array(&StringArray::from(s.to_vec()));
+        let cases: Vec<(&str, ArrayRef, Option<ArrayRef>)> = vec![
+            (
+                "gas_used",
+                array(&UInt32Array::from(vec![Some(1), None])),
+                Some(array(&Int64Array::from(vec![Some(1), None]))),
+            ),
+            (
+                "gas_used",
+                array(&BooleanArray::from(vec![true, false])),
+                Some(array(&Int64Array::from(vec![1, 0]))),
+            ),
+            // Too large for an Int64.
+            ("gas_used", array(&UInt64Array::from(vec![u64::MAX])), None),
+            (
+                "value",
+                array(&UInt64Array::from(vec![u64::MAX])),
+                Some(text(&[Some("18446744073709551615")])),
+            ),
+            (
+                "value",
+                array(
+                    &Decimal256Array::from(vec![i256::from_i128(i128::MAX) * i256::from(4)])
+                        .with_precision_and_scale(76, 0)
+                        .unwrap(),
+                ),
+                Some(text(&[Some("680564733841876926926749214863536422908")])),
+            ),
+            (
+                "hash",
+                array(&FixedSizeBinaryArray::try_from_sparse_iter_with_size(
+                    vec![Some([0xab, 0xcd]), None].into_iter(),
+                    2,
+                )
+                .unwrap()),
+                Some(text(&[Some("0xabcd"), None])),
+            ),
+            (
+                "input",
+                text(&[Some("0x0102"), Some(""), None]),
+                Some(array(&BinaryArray::from(vec![Some(&[1u8, 2][..]), Some(&[]), None]))),
+            ),
+            ("input", text(&[Some("0xzz")]), None),
+            (
+                "data",
+                array(&LargeBinaryArray::from(vec![&[7u8][..]])),
+                Some(array(&BinaryArray::from(vec![&[7u8][..]]))),
+            ),
+            // Not coercible at all.
+            ("hash", array(&Int64Array::from(vec![1])), None),
+        ];
+        for (column, input, expected) in cases {
+            let found = input.data_type().clone();
+            let coerced = coerce_column(spec(column), &input);
+            match expected {
+                Some(expected) => assert_eq!(&coerced.unwrap(), &expected, "{column} from {found}"),
+                None => assert!(coerced.is_err(), "{column} from {found}: {coerced:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! `--config job.toml` — a load job described in a file, so the whole job spec
+//! can be kept in version control instead of a long shell command.
+//!
+//! ```toml
+//! [source]
+//! data = "./euler-finance"      # directory, URL, or "-" together with `table`
+//! only = ["blocks", "logs"]     # or skip = ["transactions"]; default: all tables
+//! watch = false
+//! poll_interval = 10
+//! batch_size = 8192             # rows per record batch
+//! queue_size = 4                # batches decoded ahead of the inserts
+//! read_threads = 4              # threads decoding row groups in parallel
+//! max_memory = "4GiB"           # memory ceiling for decoded batches
+//! skip_columns = ["input", "data"]   # nullable columns not to read at all
+//! chain = "optimism"            # ethereum | op-stack | arbitrum, or a preset: see chain.rs
+//! map = { from_address = "from_addr", "logs.transaction_hash" = "tx_hash" }
+//! prices = "prices.csv"         # token/USD prices for v_transfers_usd
+//!
+//! [sink]
+//! out = "ethereum.db"           # "-" for a SQL dump on stdout
+//! mode = "overwrite"            # create | append | overwrite
+//! commit_every = 1000000        # rows per transaction; resumable with append
+//! vacuum_into = "compact.db"    # optional compacted copy after the load
+//! optimize = "analyze"          # analyze | vacuum, after the load
+//! shard_blocks = 100000         # one database per range; `out` is then a directory
+//! numeric_encoding = "blob32"   # decimal | hex | blob32
+//! address_case = "lower"        # lower | checksum
+//! topics_table = true           # also write log_topics(log_id, position, topic)
+//! compress = true               # zstd-compress calldata and log data
+//! binary_keys = true            # hashes, addresses and topics as BLOBs
+//! log_key = "full"              # full | compact | none: columns unique in logs
+//! on_conflict = "replace"       # ignore | replace | fail, or { logs = "fail", ... }
+//! reorg_policy = "keep"         # keep | drop | prefer-latest: blocks reorged out
+//! strict = true                 # STRICT tables, transactions WITHOUT ROWID
+//! default_indexes = false       # leave out the indexes on block_number, address, ...
+//! indexes = ["logs(address, topic0, block_number)"]
+//! views = true                  # v_tx_with_block, v_logs_named, v_daily_activity
+//! views_only = true             # a DuckDB database of views over the files
+//! preset = "bulk"               # bulk | safe connection pragmas
+//! report = "report.json"        # timings and sizes of the load
+//! metrics_addr = "0.0.0.0:9100" # Prometheus metrics while loading
+//!
+//! [filter]
+//! from_block = 16817996
+//! to_block   = 16818100
+//! # or from_date = "2023-03-13T00:00:00Z", to_date = "2023-03-14T00:00:00Z"
+//! addresses  = ["0xe025e3ca2be02316033184551d4d3aa22024d9dc"]
+//!
+//! [pragmas]                     # applied in order, after the preset's
+//! cache_size = -262144
+//! synchronous = "OFF"
+//! ```
+//!
+//! Relative paths are resolved against the directory of the config file.
+//! Flags given on the command line take precedence over the file.
+
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use eyre::{Context, Result};
+use serde::Deserialize;
+
+use crate::chain::Chain;
+use crate::compat;
+use crate::conflict;
+use crate::filter;
+use crate::indexes;
+use crate::memory;
+use crate::normalize::{AddressCase, NumericEncoding};
+use crate::optimize;
+use crate::pragmas;
+use crate::reorg::ReorgPolicy;
+use crate::schema::{LogKey, Table};
+use crate::{LoadArgs, Mode};
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Job {
+    source: Source,
+    sink: Sink,
+    filter: Filter,
+    pragmas: toml::Table,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Source {
+    data: Option<PathBuf>,
+    table: Option<Table>,
+    only: Option<Vec<Table>>,
+    skip: Option<Vec<Table>>,
+    watch: Option<bool>,
+    poll_interval: Option<u64>,
+    batch_size: Option<u64>,
+    #[serde(alias = "read_ahead")]
+    queue_size: Option<usize>,
+    read_threads: Option<u64>,
+    max_memory: Option<String>,
+    skip_columns: Option<Vec<String>>,
+    chain: Option<Chain>,
+    map: toml::Table,
+    prices: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Sink {
+    out: Option<PathBuf>,
+    mode: Option<Mode>,
+    commit_every: Option<u64>,
+    vacuum_into: Option<PathBuf>,
+    optimize: Option<optimize::Level>,
+    shard_blocks: Option<u64>,
+    numeric_encoding: Option<NumericEncoding>,
+    address_case: Option<AddressCase>,
+    topics_table: Option<bool>,
+    compress: Option<bool>,
+    binary_keys: Option<bool>,
+    log_key: Option<LogKey>,
+    on_conflict: Option<toml::Value>,
+    reorg_policy: Option<ReorgPolicy>,
+    strict: Option<bool>,
+    default_indexes: Option<bool>,
+    indexes: Option<Vec<String>>,
+    preset: Option<pragmas::Preset>,
+    report: Option<PathBuf>,
+    metrics_addr: Option<String>,
+    views: Option<bool>,
+    views_only: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Filter {
+    from_block: Option<i64>,
+    to_block: Option<i64>,
+    from_date: Option<String>,
+    to_date: Option<String>,
+    addresses: Option<Vec<String>>,
+}
+
+impl Job {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        let mut job: Job =
+            toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        job.source.data = job.source.data.map(|p| resolve(base, p));
+        job.source.prices = job.source.prices.map(|p| resolve(base, p));
+        job.sink.out = job.sink.out.map(|p| resolve(base, p));
+        job.sink.vacuum_into = job.sink.vacuum_into.map(|p| resolve(base, p));
+        job.sink.report = job.sink.report.map(|p| resolve(base, p));
+        Ok(job)
+    }
+
+    /// Fill in every field of `args` that wasn't given on the command line.
+    pub fn apply(self, args: &mut LoadArgs, matches: &ArgMatches) -> Result<()> {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+        fn set<T>(unset: bool, field: &mut T, value: Option<T>) {
+            if let (true, Some(value)) = (unset, value) {
+                *field = value;
+            }
+        }
+        set(unset("data"), &mut args.data, self.source.data);
+        set(unset("out"), &mut args.out, self.sink.out);
+        set(unset("mode"), &mut args.mode, self.sink.mode.map(Some));
+        set(unset("commit_every"), &mut args.commit_every, self.sink.commit_every.map(Some));
+        set(unset("vacuum_into"), &mut args.vacuum_into, self.sink.vacuum_into.map(Some));
+        set(unset("optimize"), &mut args.optimize, self.sink.optimize.map(Some));
+        set(unset("shard_blocks"), &mut args.shard_blocks, self.sink.shard_blocks.map(Some));
+        set(unset("numeric_encoding"), &mut args.numeric_encoding, self.sink.numeric_encoding);
+        set(unset("address_case"), &mut args.address_case, self.sink.address_case.map(Some));
+        set(unset("topics_table"), &mut args.topics_table, self.sink.topics_table);
+        set(unset("compress"), &mut args.compress, self.sink.compress);
+        set(unset("binary_keys"), &mut args.binary_keys, self.sink.binary_keys);
+        set(unset("strict"), &mut args.strict, self.sink.strict);
+        set(unset("log_key"), &mut args.log_key, self.sink.log_key);
+        let no_default_index = self.sink.default_indexes.map(|d| !d);
+        set(unset("no_default_index"), &mut args.no_default_index, no_default_index);
+        if let (true, Some(indexes)) = (unset("index"), self.sink.indexes) {
+            args.index = indexes
+                .iter()
+                .map(|s| indexes::parse(s).map_err(|e| eyre::eyre!("indexes: {e}")))
+                .collect::<Result<_>>()?;
+        }
+        if let (true, Some(value)) = (unset("on_conflict"), self.sink.on_conflict) {
+            args.on_conflict = on_conflict(&value)?;
+        }
+        set(unset("reorg_policy"), &mut args.reorg_policy, self.sink.reorg_policy);
+        set(unset("views"), &mut args.views, self.sink.views);
+        set(unset("views_only"), &mut args.views_only, self.sink.views_only);
+        set(unset("table"), &mut args.table, self.source.table.map(Some));
+        set(unset("only"), &mut args.only, self.source.only);
+        set(unset("skip_tables"), &mut args.skip_tables, self.source.skip);
+        set(unset("watch"), &mut args.watch, self.source.watch);
+        set(unset("poll_interval"), &mut args.poll_interval, self.source.poll_interval);
+        set(unset("batch_size"), &mut args.batch_size, self.source.batch_size);
+        set(unset("queue_size"), &mut args.queue_size, self.source.queue_size);
+        set(unset("read_threads"), &mut args.read_threads, self.source.read_threads);
+        if let (true, Some(size)) = (unset("max_memory"), &self.source.max_memory) {
+            args.max_memory =
+                Some(memory::parse_size(size).map_err(|e| eyre::eyre!("max_memory: {e}"))?);
+        }
+        set(unset("prices"), &mut args.prices, self.source.prices.map(Some));
+        set(unset("from_block"), &mut args.from_block, self.filter.from_block.map(Some));
+        set(unset("to_block"), &mut args.to_block, self.filter.to_block.map(Some));
+        let date = |key: &str, value: Option<String>| {
+            value
+                .map(|s| filter::parse_date(&s).map(Some).map_err(|e| eyre::eyre!("{key}: {e}")))
+                .transpose()
+        };
+        set(unset("from_date"), &mut args.from_date, date("from_date", self.filter.from_date)?);
+        set(unset("to_date"), &mut args.to_date, date("to_date", self.filter.to_date)?);
+        set(unset("address"), &mut args.address, self.filter.addresses);
+        if unset("light") {
+            set(unset("skip_columns"), &mut args.skip_columns, self.source.skip_columns);
+        }
+        set(unset("chain"), &mut args.chain, self.source.chain);
+
+        set(unset("preset"), &mut args.preset, self.sink.preset.map(Some));
+        set(unset("report"), &mut args.report, self.sink.report.map(Some));
+        set(unset("metrics_addr"), &mut args.metrics_addr, self.sink.metrics_addr.map(Some));
+        // Before the command line's --pragma, so those win.
+        let mut pragmas = Vec::new();
+        for (key, value) in self.pragmas {
+            let value = pragma_value(&key, &value)?;
+            pragmas.push((key, value));
+        }
+        args.pragmas.splice(0..0, pragmas);
+        // After the command line's --map, so those win for the same column.
+        for (from, to) in self.source.map {
+            let to = to.as_str().ok_or_else(|| eyre::eyre!("map.{from}: expected a column name"))?;
+            let rename = compat::parse(&format!("{from}={to}")).map_err(|e| eyre::eyre!(e))?;
+            args.map.push(rename);
+        }
+        Ok(())
+    }
+}
+
+/// Relative local paths are taken relative to the config file; URLs, "-"
+/// and ":memory:" are left alone.
+fn resolve(base: &Path, path: PathBuf) -> PathBuf {
+    let s = path.to_string_lossy();
+    if path.is_absolute() || s == "-" || s == ":memory:" || s.contains("://") {
+        path
+    } else {
+        base.join(path)
+    }
+}
+
+/// `on_conflict = "replace"` for every table, or `{ TABLE = "ACTION" }`.
+fn on_conflict(value: &toml::Value) -> Result<Vec<conflict::Setting>> {
+    let settings = match value {
+        toml::Value::String(s) => vec![s.clone()],
+        toml::Value::Table(tables) => tables
+            .iter()
+            .map(|(table, action)| match action.as_str() {
+                Some(action) => Ok(format!("{table}={action}")),
+                None => eyre::bail!("on_conflict.{table}: expected ignore, replace or fail"),
+            })
+            .collect::<Result<_>>()?,
+        other => eyre::bail!("on_conflict: expected a string or a table, got {other}"),
+    };
+    settings
+        .iter()
+        .map(|s| conflict::parse(s).map_err(|e| eyre::eyre!("on_conflict: {e}")))
+        .collect()
+}
+
+/// Render a TOML pragma value as SQL.
+fn pragma_value(key: &str, value: &toml::Value) -> Result<String> {
+    pragmas::check_name(key).map_err(|e| eyre::eyre!(e))?;
+    Ok(match value {
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Boolean(b) => if *b { "ON" } else { "OFF" }.to_string(),
+        toml::Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+        other => eyre::bail!("pragma {key}: unsupported value {other}"),
+    })
+}
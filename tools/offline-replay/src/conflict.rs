@@ -0,0 +1,123 @@
+//! `--on-conflict [TABLE=]ignore|replace|fail` — what an insert does with a
+//! row whose key is already in the table, as when a corrected dataset is
+//! loaded over an existing database with `--mode append`:
+//!
+//!   ignore   keep the row that is there (the default)
+//!   replace  overwrite its columns with the new row's; columns the loader
+//!            derives (transactions.log_count) are kept, and a replaced log
+//!            keeps its `id`
+//!   fail     end the load with an error, rolling back the file
+//!
+//! Without a table it applies to every table; later settings win, so
+//! `--on-conflict replace --on-conflict logs=fail` replaces blocks and
+//! transactions only. Blocks are keyed by number and hash, and a canonical
+//! block also by number alone (see reorg.rs), transactions by hash, logs
+//! by the `--log-key` columns, ommers by their nephew block and position
+//! in it, withdrawals by index and blobs by transaction and position in it;
+//! with `--log-key none` a log never conflicts, and loading the same logs
+//! again appends them again.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::schema::Table;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Conflict {
+    #[default]
+    Ignore,
+    Replace,
+    Fail,
+}
+
+/// One `--on-conflict [TABLE=]ACTION`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Setting {
+    table: Option<Table>,
+    action: Conflict,
+}
+
+/// Parse `--on-conflict [TABLE=]ACTION`.
+pub fn parse(s: &str) -> Result<Setting, String> {
+    let (table, action) = match s.split_once('=') {
+        Some((table, action)) => {
+            let table = Table::named(table.trim())
+                .ok_or_else(|| format!("unknown table {table:?} in {s:?}"))?;
+            (Some(table), action)
+        }
+        None => (None, s),
+    };
+    let action = Conflict::from_str(action.trim(), true)
+        .map_err(|_| format!("expected ignore, replace or fail, got {action:?}"))?;
+    Ok(Setting { table, action })
+}
+
+/// The conflict action of each table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OnConflict {
+    blocks: Conflict,
+    transactions: Conflict,
+    logs: Conflict,
+    ommers: Conflict,
+    withdrawals: Conflict,
+    blobs: Conflict,
+}
+
+impl OnConflict {
+    /// The defaults with `settings` applied in order.
+    pub fn new(settings: &[Setting]) -> Self {
+        let mut on_conflict = Self::default();
+        for setting in settings {
+            let every = Table::ALL.into_iter().chain(Table::OPTIONAL).collect();
+            for table in setting.table.map_or(every, |t| vec![t]) {
+                *on_conflict.get_mut(table) = setting.action;
+            }
+        }
+        on_conflict
+    }
+
+    pub fn get(&self, table: Table) -> Conflict {
+        match table {
+            Table::Blocks => self.blocks,
+            Table::Transactions => self.transactions,
+            Table::Logs => self.logs,
+            Table::Ommers => self.ommers,
+            Table::Withdrawals => self.withdrawals,
+            Table::Blobs => self.blobs,
+        }
+    }
+
+    fn get_mut(&mut self, table: Table) -> &mut Conflict {
+        match table {
+            Table::Blocks => &mut self.blocks,
+            Table::Transactions => &mut self.transactions,
+            Table::Logs => &mut self.logs,
+            Table::Ommers => &mut self.ommers,
+            Table::Withdrawals => &mut self.withdrawals,
+            Table::Blobs => &mut self.blobs,
+        }
+    }
+
+    /// `INSERT INTO table (columns) VALUES (?1, ...)` with `table`'s conflict
+    /// clause. Replace is an upsert rather than `INSERT OR REPLACE`, which
+    /// would delete the old row and with it the columns not inserted here.
+    pub fn insert(&self, table: Table, columns: &[&str]) -> String {
+        let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+        let insert = match self.get(table) {
+            Conflict::Ignore => "INSERT OR IGNORE",
+            Conflict::Replace | Conflict::Fail => "INSERT",
+        };
+        let mut sql = format!(
+            "{insert} INTO {} ({}) VALUES ({})",
+            table.name(),
+            columns.join(", "),
+            placeholders.join(",")
+        );
+        if self.get(table) == Conflict::Replace {
+            let set: Vec<String> = columns.iter().map(|c| format!("{c} = excluded.{c}")).collect();
+            sql.push_str(&format!(" ON CONFLICT DO UPDATE SET {}", set.join(", ")));
+        }
+        sql
+    }
+}
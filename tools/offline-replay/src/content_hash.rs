@@ -0,0 +1,138 @@
+//! A content hash of a database, for checking that two independently built
+//! ones hold the same thing (`--emit-hash` after a load, or `hash <db>`).
+//!
+//! The hash is a SHA-256 over a canonical form of the database rather than
+//! its file, whose page layout differs between otherwise identical builds:
+//! every table, index, view and trigger by type and name with its SQL, and
+//! each table's rows with their rowids, in rowid order (primary key order
+//! for WITHOUT ROWID tables, key order for `_meta`). Values are hashed with
+//! their storage class, so 1 and '1' differ. Left out are SQLite's own
+//! tables (`sqlite_stat1` from ANALYZE, `sqlite_sequence`),
+//! `_loaded_files`, which records where the files were read from, and the
+//! `_meta` keys that record where and when the load ran (`source`,
+//! `loaded_at`).
+//!
+//! A load writes the same rows with the same rowids given the same files
+//! and flags, and takes `loaded_at` from SOURCE_DATE_EPOCH when set, so two
+//! builds of a dataset hash alike on any machine. The tool version and
+//! every setting recorded in `_meta` are hashed: a different version or
+//! --numeric-encoding gives a different hash.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::Result;
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+
+use crate::open_existing;
+
+/// `_meta` keys describing the load rather than the data.
+const VOLATILE_META: &[&str] = &["source", "loaded_at"];
+
+#[derive(Args, Debug)]
+pub struct HashArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+}
+
+pub fn run(args: HashArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    println!("{}", hash(&conn)?);
+    Ok(())
+}
+
+/// The database's content hash, as lowercase hex.
+pub fn hash(conn: &Connection) -> Result<String> {
+    let mut h = Sha256::new();
+    let objects: Vec<(String, String, Option<String>)> = conn
+        .prepare(
+            "SELECT type, name, sql FROM sqlite_master
+             WHERE name NOT LIKE 'sqlite_%' AND name <> '_loaded_files'
+             ORDER BY type, name",
+        )?
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    for (kind, name, sql) in &objects {
+        text(&mut h, kind);
+        text(&mut h, name);
+        text(&mut h, sql.as_deref().unwrap_or_default());
+        if kind != "table" {
+            continue;
+        }
+        let sql = sql.as_deref().unwrap_or_default();
+        // _meta's rowids follow the order settings were last written in.
+        let query = if name == "_meta" {
+            "SELECT key, value FROM _meta ORDER BY key".to_string()
+        } else if sql.to_ascii_uppercase().contains("WITHOUT ROWID") {
+            let columns: usize =
+                conn.query_row("SELECT COUNT(*) FROM pragma_table_info(?1)", [name], |r| r.get(0))?;
+            let order = (1..=columns).map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+            format!("SELECT * FROM \"{name}\" ORDER BY {order}")
+        } else {
+            format!("SELECT rowid, * FROM \"{name}\" ORDER BY rowid")
+        };
+        let mut stmt = conn.prepare(&query)?;
+        let width = stmt.column_count();
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            if name == "_meta" {
+                if let ValueRef::Text(key) = r.get_ref(0)? {
+                    if VOLATILE_META.iter().any(|k| k.as_bytes() == key) {
+                        continue;
+                    }
+                }
+            }
+            h.update(b"r");
+            for i in 0..width {
+                value(&mut h, r.get_ref(i)?);
+            }
+        }
+    }
+    Ok(format!("{:x}", h.finalize()))
+}
+
+fn text(h: &mut Sha256, s: &str) {
+    value(h, ValueRef::Text(s.as_bytes()));
+}
+
+/// A value, tagged with its storage class and (for text and blobs) length.
+fn value(h: &mut Sha256, v: ValueRef<'_>) {
+    match v {
+        ValueRef::Null => h.update(b"n"),
+        ValueRef::Integer(i) => {
+            h.update(b"i");
+            h.update(i.to_be_bytes());
+        }
+        ValueRef::Real(f) => {
+            h.update(b"f");
+            h.update(f.to_bits().to_be_bytes());
+        }
+        ValueRef::Text(b) | ValueRef::Blob(b) => {
+            h.update(if matches!(v, ValueRef::Text(_)) { b"t" } else { b"b" });
+            h.update((b.len() as u64).to_be_bytes());
+            h.update(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{generate, load_into, Scratch};
+
+    #[test]
+    fn loads_of_the_same_files_hash_alike() {
+        let dir = Scratch::new("hash");
+        let data = dir.join("data");
+        generate(&data, 20);
+        let load_and_hash = |db: &str, flags: &str| {
+            load_into(&data, &dir.join(db), flags).unwrap();
+            hash(&open_existing(&dir.join(db)).unwrap()).unwrap()
+        };
+        let first = load_and_hash("a.db", "");
+        assert_eq!(first, load_and_hash("b.db", ""));
+        assert_ne!(first, load_and_hash("hex.db", "--numeric-encoding hex"));
+    }
+}
@@ -0,0 +1,306 @@
+//! Writing compliant Parquet datasets (blocks / transactions / logs).
+//!
+//! Column names and types match what scripts/capture.py exports and what the
+//! loader expects: hashes, addresses and uint256 amounts as decimal/hex Utf8,
+//! calldata and log data as Binary, everything else Int64.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, BinaryArray, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use eyre::{Context, Result};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use crate::manifest::{BlockRange, Manifest, TableEntry};
+
+/// Rows buffered per table before a record batch is flushed.
+const FLUSH_ROWS: usize = 8192;
+
+pub struct BlockRow {
+    pub number: i64,
+    pub hash: String,
+    pub parent_hash: String,
+    pub timestamp: i64,
+    pub gas_used: i64,
+    pub gas_limit: i64,
+    pub base_fee: Option<String>,
+    pub tx_count: i64,
+    /// The fee recipient.
+    pub miner: Option<String>,
+}
+
+pub struct TxRow {
+    pub hash: String,
+    pub block_number: i64,
+    pub tx_index: i64,
+    pub from_addr: String,
+    pub to_addr: Option<String>,
+    pub value: String,
+    pub gas_used: i64,
+    pub gas_price: String,
+    pub input: Vec<u8>,
+    pub status: i64,
+    pub logs_bloom: Option<Vec<u8>>,
+    pub nonce: i64,
+}
+
+pub struct LogRow {
+    pub block_number: i64,
+    pub tx_hash: String,
+    pub log_index: i64,
+    pub address: String,
+    pub topics: [Option<String>; 4],
+    pub data: Vec<u8>,
+}
+
+// ---------------------------------------------------------------------------
+// Schemas
+// ---------------------------------------------------------------------------
+
+fn utf8(name: &str, nullable: bool) -> Field {
+    Field::new(name, DataType::Utf8, nullable)
+}
+
+fn int64(name: &str) -> Field {
+    Field::new(name, DataType::Int64, false)
+}
+
+fn binary(name: &str, nullable: bool) -> Field {
+    Field::new(name, DataType::Binary, nullable)
+}
+
+pub fn blocks_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        int64("number"),
+        utf8("hash", false),
+        utf8("parent_hash", false),
+        int64("timestamp"),
+        int64("gas_used"),
+        int64("gas_limit"),
+        utf8("base_fee", true),
+        int64("tx_count"),
+        utf8("miner", true),
+    ]))
+}
+
+pub fn transactions_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        utf8("hash", false),
+        int64("block_number"),
+        int64("tx_index"),
+        utf8("from_addr", false),
+        utf8("to_addr", true),
+        utf8("value", false),
+        int64("gas_used"),
+        utf8("gas_price", false),
+        binary("input", false),
+        int64("status"),
+        binary("logs_bloom", true),
+        int64("nonce"),
+    ]))
+}
+
+pub fn logs_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        int64("block_number"),
+        utf8("tx_hash", false),
+        int64("log_index"),
+        utf8("address", false),
+        utf8("topic0", true),
+        utf8("topic1", true),
+        utf8("topic2", true),
+        utf8("topic3", true),
+        binary("data", true),
+    ]))
+}
+
+// ---------------------------------------------------------------------------
+// Row → RecordBatch
+// ---------------------------------------------------------------------------
+
+fn i64s<T>(rows: &[T], f: impl Fn(&T) -> i64) -> ArrayRef {
+    Arc::new(Int64Array::from_iter_values(rows.iter().map(f)))
+}
+
+fn strs<'a, T>(rows: &'a [T], f: impl Fn(&'a T) -> Option<&'a str>) -> ArrayRef {
+    Arc::new(StringArray::from_iter(rows.iter().map(f)))
+}
+
+fn bins<'a, T>(rows: &'a [T], f: impl Fn(&'a T) -> Option<&'a [u8]>) -> ArrayRef {
+    Arc::new(BinaryArray::from_iter(rows.iter().map(f)))
+}
+
+fn blocks_batch(rows: &[BlockRow]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(blocks_schema(), vec![
+        i64s(rows, |r| r.number),
+        strs(rows, |r| Some(&r.hash)),
+        strs(rows, |r| Some(&r.parent_hash)),
+        i64s(rows, |r| r.timestamp),
+        i64s(rows, |r| r.gas_used),
+        i64s(rows, |r| r.gas_limit),
+        strs(rows, |r| r.base_fee.as_deref()),
+        i64s(rows, |r| r.tx_count),
+        strs(rows, |r| r.miner.as_deref()),
+    ])?)
+}
+
+fn transactions_batch(rows: &[TxRow]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(transactions_schema(), vec![
+        strs(rows, |r| Some(&r.hash)),
+        i64s(rows, |r| r.block_number),
+        i64s(rows, |r| r.tx_index),
+        strs(rows, |r| Some(&r.from_addr)),
+        strs(rows, |r| r.to_addr.as_deref()),
+        strs(rows, |r| Some(&r.value)),
+        i64s(rows, |r| r.gas_used),
+        strs(rows, |r| Some(&r.gas_price)),
+        bins(rows, |r| Some(&r.input)),
+        i64s(rows, |r| r.status),
+        bins(rows, |r| r.logs_bloom.as_deref()),
+        i64s(rows, |r| r.nonce),
+    ])?)
+}
+
+fn logs_batch(rows: &[LogRow]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(logs_schema(), vec![
+        i64s(rows, |r| r.block_number),
+        strs(rows, |r| Some(&r.tx_hash)),
+        i64s(rows, |r| r.log_index),
+        strs(rows, |r| Some(&r.address)),
+        strs(rows, |r| r.topics[0].as_deref()),
+        strs(rows, |r| r.topics[1].as_deref()),
+        strs(rows, |r| r.topics[2].as_deref()),
+        strs(rows, |r| r.topics[3].as_deref()),
+        bins(rows, |r| Some(&r.data)),
+    ])?)
+}
+
+// ---------------------------------------------------------------------------
+// Writer
+// ---------------------------------------------------------------------------
+
+struct TableWriter<T> {
+    writer: ArrowWriter<std::fs::File>,
+    buf: Vec<T>,
+    rows: u64,
+    to_batch: fn(&[T]) -> Result<RecordBatch>,
+}
+
+impl<T> TableWriter<T> {
+    fn create(
+        path: &Path,
+        schema: SchemaRef,
+        to_batch: fn(&[T]) -> Result<RecordBatch>,
+        row_group_size: Option<usize>,
+    ) -> Result<Self> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Cannot create {}", path.display()))?;
+        let mut props =
+            WriterProperties::builder().set_compression(Compression::ZSTD(ZstdLevel::default()));
+        if let Some(rows) = row_group_size {
+            props = props.set_max_row_group_size(rows);
+        }
+        let props = props.build();
+        let writer = ArrowWriter::try_new(file, schema, Some(props))?;
+        Ok(Self { writer, buf: Vec::with_capacity(FLUSH_ROWS), rows: 0, to_batch })
+    }
+
+    fn push(&mut self, row: T) -> Result<()> {
+        self.buf.push(row);
+        if self.buf.len() >= FLUSH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.buf.is_empty() {
+            self.writer.write(&(self.to_batch)(&self.buf)?)?;
+            self.rows += self.buf.len() as u64;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<u64> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(self.rows)
+    }
+}
+
+/// Streams rows into blocks.parquet / transactions.parquet / logs.parquet
+/// and writes a manifest.json on [`DatasetWriter::finish`].
+pub struct DatasetWriter {
+    dir: std::path::PathBuf,
+    blocks: TableWriter<BlockRow>,
+    transactions: TableWriter<TxRow>,
+    logs: TableWriter<LogRow>,
+    chain_id: Option<u64>,
+}
+
+impl DatasetWriter {
+    /// Row groups hold up to `row_group_size` rows (the Parquet writer's
+    /// default, about a million, when None).
+    pub fn create(dir: &Path, row_group_size: Option<usize>) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            blocks: TableWriter::create(
+                &dir.join("blocks.parquet"),
+                blocks_schema(),
+                blocks_batch,
+                row_group_size,
+            )?,
+            transactions: TableWriter::create(
+                &dir.join("transactions.parquet"),
+                transactions_schema(),
+                transactions_batch,
+                row_group_size,
+            )?,
+            logs: TableWriter::create(
+                &dir.join("logs.parquet"),
+                logs_schema(),
+                logs_batch,
+                row_group_size,
+            )?,
+            chain_id: None,
+        })
+    }
+
+    pub fn push_block(&mut self, row: BlockRow) -> Result<()> {
+        self.blocks.push(row)
+    }
+
+    pub fn push_tx(&mut self, row: TxRow) -> Result<()> {
+        self.transactions.push(row)
+    }
+
+    pub fn push_log(&mut self, row: LogRow) -> Result<()> {
+        self.logs.push(row)
+    }
+
+    /// Record the chain the rows are from in the manifest.
+    pub fn set_chain_id(&mut self, id: u64) {
+        self.chain_id = Some(id);
+    }
+
+    pub fn finish(self, block_range: BlockRange) -> Result<Manifest> {
+        let mut m = Manifest::new(block_range);
+        m.chain_id = self.chain_id;
+        for (table, file, rows) in [
+            ("blocks", "blocks.parquet", self.blocks.close()?),
+            ("transactions", "transactions.parquet", self.transactions.close()?),
+            ("logs", "logs.parquet", self.logs.close()?),
+        ] {
+            m.tables.insert(table.to_string(), TableEntry { file: file.to_string(), rows });
+        }
+        m.write(&self.dir)?;
+        Ok(m)
+    }
+}
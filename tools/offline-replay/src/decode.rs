@@ -0,0 +1,392 @@
+//! Decoded event tables, derived from `logs` by the analysis and export
+//! commands that need them:
+//!
+//!   erc20_transfers(log_id, block_number, tx_hash, log_index, token,
+//!                   from_addr, to_addr, amount)
+//!   approvals(log_id, block_number, tx_hash, log_index, token, owner,
+//!             spender, amount)
+//!   dex_swaps(log_id, block_number, tx_hash, log_index, pool, kind, sender,
+//!             recipient, amount0_in, amount1_in, amount0_out, amount1_out)
+//!
+//! `dex_swaps` covers Uniswap V2-style pairs (and their forks) and V3-style
+//! pools, from the pool's side: `_in` amounts were paid into the pool, `_out`
+//! amounts paid out of it.
+//!
+//! A table is created on first use and caught up incrementally after that:
+//! `_meta` records the last log id decoded into it (`decoded.<table>`), so
+//! logs appended later are picked up without re-decoding the rest. Amounts
+//! and addresses follow the database's --numeric-encoding and --address-case;
+//! hashes and addresses are 0x-hex text even with --binary-keys.
+
+use alloy_primitives::{hex, keccak256, I256, U256};
+use eyre::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::debug;
+
+use crate::migrate::set_meta;
+use crate::normalize::{read_key, Normalize};
+
+const ERC20_TRANSFERS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS erc20_transfers (
+    log_id       INTEGER PRIMARY KEY REFERENCES logs(id),
+    block_number INTEGER NOT NULL,
+    tx_hash      TEXT    NOT NULL,
+    log_index    INTEGER NOT NULL,
+    token        TEXT    NOT NULL,
+    from_addr    TEXT    NOT NULL,
+    to_addr      TEXT    NOT NULL,
+    amount       TEXT    NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_erc20_token ON erc20_transfers(token, block_number);
+CREATE INDEX IF NOT EXISTS idx_erc20_from  ON erc20_transfers(from_addr);
+CREATE INDEX IF NOT EXISTS idx_erc20_to    ON erc20_transfers(to_addr);
+";
+
+/// Bring `erc20_transfers` up to date with `logs`. Returns the rows added.
+///
+/// ERC-721 shares the Transfer signature but indexes the token id as a
+/// fourth topic; those logs are left out.
+pub fn erc20_transfers(conn: &Connection) -> Result<u64> {
+    conn.execute_batch(ERC20_TRANSFERS_SCHEMA)?;
+    let norm = Normalize::from_meta(conn)?;
+    let topic0 = topic("Transfer(address,address,uint256)");
+
+    conn.execute_batch("BEGIN;")?;
+    let mut added = 0;
+    catch_up(conn, "erc20_transfers", |after| {
+        let mut stmt = conn.prepare(
+            "SELECT id, block_number, tx_hash, log_index, address, topic1, topic2, data
+             FROM logs
+             WHERE id > ?1 AND topic0 = ?2 AND topic2 IS NOT NULL AND topic3 IS NULL",
+        )?;
+        let mut rows = stmt.query(params![after, norm.key("topic0", &topic0)?])?;
+        while let Some(r) = rows.next()? {
+            let data: Option<Vec<u8>> = r.get(7)?;
+            let data = data.map(|d| norm.read_blob(d)).transpose()?;
+            let Some(data) = data.filter(|d| d.len() == 32) else { continue };
+            let (Some(from), Some(to)) = (topic_address(&key(r, 5)?), topic_address(&key(r, 6)?))
+            else {
+                continue;
+            };
+            conn.prepare_cached(
+                "INSERT OR IGNORE INTO erc20_transfers
+                 (log_id, block_number, tx_hash, log_index, token, from_addr, to_addr, amount)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8)",
+            )?
+            .execute(params![
+                r.get::<_, i64>(0)?,
+                r.get::<_, i64>(1)?,
+                key(r, 2)?,
+                r.get::<_, i64>(3)?,
+                norm.address("address", &key(r, 4)?)?,
+                norm.address("topic1", &from)?,
+                norm.address("topic2", &to)?,
+                norm.encode(U256::from_be_slice(&data)),
+            ])?;
+            added += 1;
+        }
+        Ok(())
+    })?;
+    conn.execute_batch("COMMIT;")?;
+    debug!(table = "erc20_transfers", added, "decoded");
+    Ok(added)
+}
+
+const APPROVALS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS approvals (
+    log_id       INTEGER PRIMARY KEY REFERENCES logs(id),
+    block_number INTEGER NOT NULL,
+    tx_hash      TEXT    NOT NULL,
+    log_index    INTEGER NOT NULL,
+    token        TEXT    NOT NULL,
+    owner        TEXT    NOT NULL,
+    spender      TEXT    NOT NULL,
+    amount       TEXT    NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_approvals_token   ON approvals(token, block_number);
+CREATE INDEX IF NOT EXISTS idx_approvals_spender ON approvals(spender);
+";
+
+/// Bring `approvals` up to date with `logs`. Returns the rows added.
+///
+/// ERC-721 approvals index the token id as a third topic; those logs are
+/// left out, like its transfers.
+pub fn approvals(conn: &Connection) -> Result<u64> {
+    conn.execute_batch(APPROVALS_SCHEMA)?;
+    let norm = Normalize::from_meta(conn)?;
+    let topic0 = topic("Approval(address,address,uint256)");
+
+    conn.execute_batch("BEGIN;")?;
+    let mut added = 0;
+    catch_up(conn, "approvals", |after| {
+        let mut stmt = conn.prepare(
+            "SELECT id, block_number, tx_hash, log_index, address, topic1, topic2, data
+             FROM logs
+             WHERE id > ?1 AND topic0 = ?2 AND topic2 IS NOT NULL AND topic3 IS NULL",
+        )?;
+        let mut rows = stmt.query(params![after, norm.key("topic0", &topic0)?])?;
+        while let Some(r) = rows.next()? {
+            let data: Option<Vec<u8>> = r.get(7)?;
+            let data = data.map(|d| norm.read_blob(d)).transpose()?;
+            let Some(data) = data.filter(|d| d.len() == 32) else { continue };
+            let (Some(owner), Some(spender)) =
+                (topic_address(&key(r, 5)?), topic_address(&key(r, 6)?))
+            else {
+                continue;
+            };
+            conn.prepare_cached(
+                "INSERT OR IGNORE INTO approvals
+                 (log_id, block_number, tx_hash, log_index, token, owner, spender, amount)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8)",
+            )?
+            .execute(params![
+                r.get::<_, i64>(0)?,
+                r.get::<_, i64>(1)?,
+                key(r, 2)?,
+                r.get::<_, i64>(3)?,
+                norm.address("address", &key(r, 4)?)?,
+                norm.address("topic1", &owner)?,
+                norm.address("topic2", &spender)?,
+                norm.encode(U256::from_be_slice(&data)),
+            ])?;
+            added += 1;
+        }
+        Ok(())
+    })?;
+    conn.execute_batch("COMMIT;")?;
+    debug!(table = "approvals", added, "decoded");
+    Ok(added)
+}
+
+const DEX_SWAPS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS dex_swaps (
+    log_id       INTEGER PRIMARY KEY REFERENCES logs(id),
+    block_number INTEGER NOT NULL,
+    tx_hash      TEXT    NOT NULL,
+    log_index    INTEGER NOT NULL,
+    pool         TEXT    NOT NULL,
+    kind         TEXT    NOT NULL,
+    sender       TEXT    NOT NULL,
+    recipient    TEXT    NOT NULL,
+    amount0_in   TEXT    NOT NULL,
+    amount1_in   TEXT    NOT NULL,
+    amount0_out  TEXT    NOT NULL,
+    amount1_out  TEXT    NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_dex_swaps_pool ON dex_swaps(pool, block_number);
+";
+
+/// Bring `dex_swaps` up to date with `logs`. Returns the rows added.
+pub fn dex_swaps(conn: &Connection) -> Result<u64> {
+    conn.execute_batch(DEX_SWAPS_SCHEMA)?;
+    let norm = Normalize::from_meta(conn)?;
+    let v2 = topic("Swap(address,uint256,uint256,uint256,uint256,address)");
+    let v3 = topic("Swap(address,address,int256,int256,uint160,uint128,int24)");
+
+    conn.execute_batch("BEGIN;")?;
+    let mut added = 0;
+    catch_up(conn, "dex_swaps", |after| {
+        let mut stmt = conn.prepare(
+            "SELECT id, block_number, tx_hash, log_index, address, topic0, topic1, topic2, data
+             FROM logs
+             WHERE id > ?1 AND topic0 IN (?2, ?3) AND topic2 IS NOT NULL",
+        )?;
+        let mut rows =
+            stmt.query(params![after, norm.key("topic0", &v2)?, norm.key("topic0", &v3)?])?;
+        while let Some(r) = rows.next()? {
+            let data = norm.read_blob(r.get::<_, Option<Vec<u8>>>(8)?.unwrap_or_default())?;
+            let word = |i: usize| U256::from_be_slice(&data[i * 32..(i + 1) * 32]);
+            let is_v2 = key(r, 5)? == v2;
+            let (kind, [in0, in1, out0, out1]) = match (is_v2, data.len()) {
+                (true, 128) => ("v2", [word(0), word(1), word(2), word(3)]),
+                (false, 160) => {
+                    // Signed from the pool's side: positive was paid in.
+                    let split = |v: I256| {
+                        if v.is_negative() {
+                            (U256::ZERO, v.unsigned_abs())
+                        } else {
+                            (v.into_raw(), U256::ZERO)
+                        }
+                    };
+                    let (in0, out0) = split(I256::from_raw(word(0)));
+                    let (in1, out1) = split(I256::from_raw(word(1)));
+                    ("v3", [in0, in1, out0, out1])
+                }
+                _ => continue,
+            };
+            let (Some(sender), Some(recipient)) =
+                (topic_address(&key(r, 6)?), topic_address(&key(r, 7)?))
+            else {
+                continue;
+            };
+            conn.prepare_cached(
+                "INSERT OR IGNORE INTO dex_swaps
+                 (log_id, block_number, tx_hash, log_index, pool, kind, sender, recipient,
+                  amount0_in, amount1_in, amount0_out, amount1_out)
+                 VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12)",
+            )?
+            .execute(params![
+                r.get::<_, i64>(0)?,
+                r.get::<_, i64>(1)?,
+                key(r, 2)?,
+                r.get::<_, i64>(3)?,
+                norm.address("address", &key(r, 4)?)?,
+                kind,
+                norm.address("topic1", &sender)?,
+                norm.address("topic2", &recipient)?,
+                norm.encode(in0),
+                norm.encode(in1),
+                norm.encode(out0),
+                norm.encode(out1),
+            ])?;
+            added += 1;
+        }
+        Ok(())
+    })?;
+    conn.execute_batch("COMMIT;")?;
+    debug!(table = "dex_swaps", added, "decoded");
+    Ok(added)
+}
+
+/// A non-null hash, address or topic column of `r` as 0x-hex text.
+pub(crate) fn key(r: &rusqlite::Row<'_>, i: usize) -> Result<String> {
+    read_key(r.get_ref(i)?)?.ok_or_else(|| eyre::eyre!("column {i} is NULL"))
+}
+
+/// Run `decode` over the logs after the table's high-water mark, then move
+/// the mark to the newest log.
+pub(crate) fn catch_up(
+    conn: &Connection,
+    table: &str,
+    decode: impl FnOnce(i64) -> Result<()>,
+) -> Result<()> {
+    let key = format!("decoded.{table}");
+    let after: i64 = conn
+        .query_row("SELECT value FROM _meta WHERE key = ?1", [&key], |r| r.get::<_, String>(0))
+        .optional()?
+        .map_or(Ok(0), |v| v.parse())?;
+    decode(after)?;
+    let last: Option<i64> = conn.query_row("SELECT MAX(id) FROM logs", [], |r| r.get(0))?;
+    set_meta(conn, &key, &last.unwrap_or(after).to_string())?;
+    Ok(())
+}
+
+/// topic0 of an event signature, as stored in `logs`.
+pub fn topic(signature: &str) -> String {
+    format!("0x{}", hex::encode(keccak256(signature)))
+}
+
+/// The address in an indexed address topic (left-padded to 32 bytes).
+pub fn topic_address(topic: &str) -> Option<String> {
+    let hex = topic.strip_prefix("0x")?;
+    (hex.len() == 64 && hex[..24].bytes().all(|b| b == b'0'))
+        .then(|| format!("0x{}", &hex[24..]))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::migrate;
+
+    const TOKEN: &str = "0x00000000000000000000000000000000000000aa";
+
+    /// An empty database to decode logs in, without foreign keys so that
+    /// logs need no transactions.
+    pub(crate) fn db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate::init(&conn, None).unwrap();
+        conn.execute_batch("PRAGMA foreign_keys=OFF;").unwrap();
+        conn
+    }
+
+    /// Add a log of `address` to block 1, numbered after the others.
+    pub(crate) fn log(conn: &Connection, address: &str, topics: &[&str], data: &[u8]) {
+        let topic = |i: usize| topics.get(i).copied();
+        conn.execute(
+            "INSERT INTO logs
+             (block_number, tx_hash, log_index, address, topic0, topic1, topic2, topic3, data)
+             VALUES (1, '0x01', (SELECT COUNT(*) FROM logs), ?1, ?2, ?3, ?4, ?5, ?6)",
+            params![address, topic(0), topic(1), topic(2), topic(3), data],
+        )
+        .unwrap();
+    }
+
+    /// An address or number as a 32-byte topic.
+    pub(crate) fn padded(value: &str) -> String {
+        format!("0x{:0>64}", &value[2..])
+    }
+
+    /// A value as an ABI word.
+    pub(crate) fn word(value: U256) -> [u8; 32] {
+        value.to_be_bytes()
+    }
+
+    #[test]
+    fn topics() {
+        assert_eq!(
+            topic("Transfer(address,address,uint256)"),
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+        let address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+        assert_eq!(topic_address(&padded(address)).as_deref(), Some(address));
+        assert_eq!(topic_address(&format!("0x1{}", &padded(address)[3..])), None);
+        assert_eq!(topic_address(address), None);
+    }
+
+    #[test]
+    fn erc20_transfers_skip_erc721_and_catch_up() {
+        let conn = db();
+        let transfer = topic("Transfer(address,address,uint256)");
+        let (from, to) = (padded("0x01"), padded("0x02"));
+        let amount = word(U256::from(10).pow(U256::from(18)));
+        log(&conn, TOKEN, &[&transfer, &from, &to], &amount);
+        // ERC-721: the token id as a fourth topic, no data.
+        log(&conn, TOKEN, &[&transfer, &from, &to, &padded("0x07")], &[]);
+        assert_eq!(erc20_transfers(&conn).unwrap(), 1);
+        let row: (i64, String, String, String, String) = conn
+            .query_row(
+                "SELECT log_index, token, from_addr, to_addr, amount FROM erc20_transfers",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)),
+            )
+            .unwrap();
+        let expected = (
+            0,
+            TOKEN.to_string(),
+            format!("0x{:0>40}", "1"),
+            format!("0x{:0>40}", "2"),
+            "1000000000000000000".to_string(),
+        );
+        assert_eq!(row, expected);
+
+        assert_eq!(erc20_transfers(&conn).unwrap(), 0);
+        log(&conn, TOKEN, &[&transfer, &from, &to], &amount);
+        assert_eq!(erc20_transfers(&conn).unwrap(), 1);
+    }
+
+    #[test]
+    fn approvals_skip_erc721() {
+        let conn = db();
+        let approval = topic("Approval(address,address,uint256)");
+        let (owner, spender) = (padded("0x0b"), padded("0x0c"));
+        log(&conn, TOKEN, &[&approval, &owner, &spender], &word(U256::MAX));
+        // ERC-721: the token id as the third topic.
+        log(&conn, TOKEN, &[&approval, &owner, &spender, &padded("0x07")], &[]);
+        // A Transfer isn't an approval.
+        let transfer = topic("Transfer(address,address,uint256)");
+        log(&conn, TOKEN, &[&transfer, &owner, &spender], &word(U256::from(1)));
+        assert_eq!(approvals(&conn).unwrap(), 1);
+        let row: (String, String, String, String) = conn
+            .query_row("SELECT token, owner, spender, amount FROM approvals", [], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))
+            })
+            .unwrap();
+        let expected = (
+            TOKEN.to_string(),
+            format!("0x{:0>40}", "b"),
+            format!("0x{:0>40}", "c"),
+            U256::MAX.to_string(),
+        );
+        assert_eq!(row, expected);
+    }
+}
@@ -0,0 +1,99 @@
+//! `dedupe <db>` — remove the duplicate logs that loading the same logs more
+//! than once leaves in a database without a log key, and add the key
+//! (`idx_log_key`, see [`LogKey`]) so later loads can't add them again.
+//!
+//! Of the logs with the same key, the first loaded (lowest `id`) is kept.
+//! The log_topics, erc20_transfers, approvals and dex_swaps rows of the
+//! others go with them, and `transactions.log_count` is recounted.
+//! Everything happens in one transaction, so an interrupted run leaves the
+//! database as it was.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::migrate::{self, set_meta};
+use crate::open_existing;
+use crate::schema::LogKey;
+
+/// Tables with a row per log, keyed by `log_id`.
+const LOG_TABLES: [&str; 4] = ["log_topics", "erc20_transfers", "approvals", "dex_swaps"];
+
+#[derive(Args, Debug)]
+pub struct DedupeArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// The columns that identify a log: full (block_number, tx_hash,
+    /// log_index) or compact (block_number, log_index)
+    #[arg(long, value_enum, default_value_t = LogKey::Full)]
+    log_key: LogKey,
+}
+
+pub fn run(args: DedupeArgs) -> Result<()> {
+    if args.log_key == LogKey::None {
+        eyre::bail!("--log-key none has no columns to deduplicate by");
+    }
+    let conn = open_existing(&args.db)?;
+    match migrate::version(&conn)? {
+        Some(v) if v == migrate::SCHEMA_VERSION => {}
+        Some(v) if v < migrate::SCHEMA_VERSION => eyre::bail!(
+            "{} has schema version {v}; run `offline-replay migrate` on it first",
+            args.db.display()
+        ),
+        _ => {
+            eyre::bail!("{} is not a database of this version of offline-replay", args.db.display())
+        }
+    }
+
+    conn.execute_batch("BEGIN;")?;
+    match dedupe(&conn, args.log_key) {
+        Ok(removed) => {
+            conn.execute_batch("COMMIT;")?;
+            info!(db = %args.db.display(), removed, log_key = args.log_key.name(), "deduplicated logs");
+            Ok(())
+        }
+        Err(e) => {
+            conn.execute_batch("ROLLBACK;")?;
+            Err(e.wrap_err(format!("Deduplicating {}", args.db.display())))
+        }
+    }
+}
+
+/// Delete the duplicates of `key` and index it; returns the logs removed.
+fn dedupe(conn: &Connection, key: LogKey) -> Result<usize> {
+    conn.execute_batch(&format!(
+        "CREATE TEMP TABLE _dupes AS
+         SELECT id FROM logs WHERE id NOT IN (SELECT MIN(id) FROM logs GROUP BY {});",
+        key.columns().join(", ")
+    ))?;
+    for table in LOG_TABLES {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |r| r.get(0),
+        )?;
+        if exists {
+            conn.execute(
+                &format!("DELETE FROM {table} WHERE log_id IN (SELECT id FROM _dupes)"),
+                [],
+            )?;
+        }
+    }
+    let removed = conn.execute("DELETE FROM logs WHERE id IN (SELECT id FROM _dupes)", [])?;
+    if removed > 0 {
+        // Only transactions that lost logs change, and each still has one.
+        conn.execute_batch(
+            "UPDATE transactions SET log_count = c.n
+             FROM (SELECT tx_hash, COUNT(*) AS n FROM logs GROUP BY tx_hash) AS c
+             WHERE c.tx_hash = transactions.hash;",
+        )?;
+    }
+    conn.execute_batch("DROP TABLE _dupes; DROP INDEX IF EXISTS idx_log_key;")?;
+    key.create_index(conn).context("Adding the log key")?;
+    set_meta(conn, "log_key", key.name())?;
+    Ok(removed)
+}
@@ -0,0 +1,281 @@
+//! `--dry-run` — check a dataset against what the loader expects, without
+//! writing anything.
+//!
+//! Only Parquet footers and the first record batch of each file are read:
+//! column names and types come from the Arrow schema, nulls in required
+//! columns from row-group statistics, and the size estimate from the SQLite
+//! record sizes of the sampled rows, scaled up to each file's row count.
+
+use std::io::Write;
+
+use arrow::array::{Array, BinaryArray, Int64Array, StringArray};
+use arrow::record_batch::RecordBatch;
+use eyre::Result;
+use rusqlite::Connection;
+
+use crate::compat;
+use crate::indexes::Indexes;
+use crate::schema::{ColumnSpec, Kind, Table, SCHEMA};
+use crate::source::{DataSource, ReadOptions};
+
+/// Per-cell b-tree overhead: payload length and rowid varints plus the
+/// 2-byte cell pointer. Pages are assumed ~90% full.
+const TABLE_CELL_OVERHEAD: f64 = 7.0;
+const INDEX_CELL_OVERHEAD: f64 = 4.0;
+const PAGE_FILL: f64 = 0.9;
+
+/// Running totals for the size estimate, in bytes.
+#[derive(Default)]
+struct Estimate {
+    tables: f64,
+    indexes: f64,
+}
+
+pub fn run(
+    source: &DataSource,
+    tables: &[Table],
+    read: &ReadOptions,
+    indexes: &Indexes,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let skip = &read.skip;
+    let indexes = index_columns(indexes)?;
+    let mut problems = 0usize;
+    let mut estimate = Estimate::default();
+
+    writeln!(out, "Dry run: nothing will be written.")?;
+    if !skip.is_empty() {
+        writeln!(out, "Skipping columns: {}", skip.join(", "))?;
+    }
+
+    for &table in tables {
+        let files = source.list(table.name())?;
+        if files.is_empty() {
+            writeln!(out, "\n{}", source.display(table.file()))?;
+            writeln!(out, "  ! missing file")?;
+            problems += 1;
+            continue;
+        }
+        for file in &files {
+            problems += check_file(source, table, file, read, &indexes, &mut estimate, out)?;
+        }
+    }
+
+    writeln!(
+        out,
+        "\nEstimated database size: ~{} (tables {}, indexes {})",
+        human(estimate.tables + estimate.indexes),
+        human(estimate.tables),
+        human(estimate.indexes),
+    )?;
+
+    if problems > 0 {
+        eyre::bail!("{problems} problem(s) found");
+    }
+    writeln!(out, "No problems found.")?;
+    Ok(())
+}
+
+/// Check one file, print its findings and add it to the size estimate.
+/// Returns the number of problems found.
+fn check_file(
+    source: &DataSource,
+    table: Table,
+    file: &str,
+    read: &ReadOptions,
+    indexes: &[(Table, Vec<&'static ColumnSpec>)],
+    estimate: &mut Estimate,
+    out: &mut dyn Write,
+) -> Result<usize> {
+    let meta = match source.meta(file) {
+        Ok(meta) => meta,
+        Err(e) => {
+            writeln!(out, "\n{}", source.display(file))?;
+            writeln!(out, "  ! unreadable: {e:#}")?;
+            return Ok(1);
+        }
+    };
+    let rows = meta.metadata.file_metadata().num_rows();
+    writeln!(out, "\n{}  {rows} rows", source.display(file))?;
+    let (skip, map) = (&read.skip, &read.map);
+    let schema = match map.rename_schema(table, &meta.schema) {
+        Ok(schema) => schema,
+        Err(e) => {
+            writeln!(out, "  ! {e:#}")?;
+            return Ok(1);
+        }
+    };
+
+    let mut problems = 0usize;
+    let mut missing_stats = false;
+    for spec in map.columns(table).filter(|c| !skip.iter().any(|s| s == c.name)) {
+        let Ok(field) = schema.field_with_name(spec.name) else {
+            if spec.nullable {
+                writeln!(out, "  - column {} not in the file (loads as NULL)", spec.name)?;
+            } else {
+                writeln!(out, "  ! missing column: {} (--map can rename one)", spec.name)?;
+                problems += 1;
+            }
+            continue;
+        };
+        let expected = spec.kind.data_type();
+        if field.data_type() != &expected && compat::coercible(spec, field.data_type()) {
+            writeln!(out, "  - column {}: {} (read as {expected})", spec.name, field.data_type())?;
+        } else if field.data_type() != &expected {
+            writeln!(
+                out,
+                "  ! column {}: expected {expected}, found {}",
+                spec.name,
+                field.data_type()
+            )?;
+            problems += 1;
+            continue;
+        }
+        if spec.nullable {
+            continue;
+        }
+        match null_count(&meta.metadata, map.file_name(table, spec.name, &meta.schema)) {
+            Some(0) => {}
+            Some(n) => {
+                writeln!(out, "  ! column {}: {n} null(s), but a value is required", spec.name)?;
+                problems += 1;
+            }
+            None => missing_stats = true,
+        }
+    }
+
+    for field in schema.fields() {
+        if skip.contains(field.name()) {
+            writeln!(out, "  - column {} skipped", field.name())?;
+        } else if !map.columns(table).any(|c| c.name == field.name()) {
+            writeln!(out, "  - extra column {} (ignored)", field.name())?;
+        }
+    }
+    if missing_stats {
+        writeln!(out, "  - no null-count statistics; required columns not checked for nulls")?;
+    }
+
+    if problems == 0 {
+        writeln!(out, "  ok")?;
+        let sample = match source.open(file, &map.skip(table, skip))?.batches.next() {
+            Some(batch) => Some(map.convert(table, batch?)?),
+            None => None,
+        };
+        if let Some(batch) = sample.filter(|b| b.num_rows() > 0) {
+            let scale = rows as f64 / PAGE_FILL;
+            let row_bytes = record_bytes(&batch, table.columns()) + TABLE_CELL_OVERHEAD;
+            estimate.tables += row_bytes * scale;
+            for (_, columns) in indexes.iter().filter(|(t, _)| *t == table) {
+                // Index records carry the rowid after the indexed columns.
+                let entry = record_bytes(&batch, columns) + 4.0 + INDEX_CELL_OVERHEAD;
+                estimate.indexes += entry * scale;
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Total nulls in `column` across row groups, or None if any row group has no
+/// statistics for it.
+pub(crate) fn null_count(metadata: &parquet::file::metadata::ParquetMetaData, column: &str) -> Option<u64> {
+    let mut total = 0;
+    for rg in metadata.row_groups() {
+        let chunk = rg.columns().iter().find(|c| c.column_path().string() == column)?;
+        total += chunk.statistics()?.null_count_opt()?;
+    }
+    Some(total)
+}
+
+/// Indexed columns of each dataset table, read back from SCHEMA and the
+/// `indexes` themselves so the estimate follows whatever indexes the loader
+/// creates. Includes the implicit index behind a TEXT PRIMARY KEY.
+fn index_columns(indexes: &Indexes) -> Result<Vec<(Table, Vec<&'static ColumnSpec>)>> {
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch(SCHEMA)?;
+    indexes.create(&conn)?;
+
+    let mut result = Vec::new();
+    for table in Table::ALL {
+        let names: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_index_list(?1)")?
+            .query_map([table.name()], |r| r.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        for index in names {
+            let columns: Vec<String> = conn
+                .prepare("SELECT name FROM pragma_index_info(?1) ORDER BY seqno")?
+                .query_map([&index], |r| r.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            let specs = columns
+                .iter()
+                .filter_map(|c| table.columns().iter().find(|s| s.name == c))
+                .collect();
+            result.push((table, specs));
+        }
+    }
+    Ok(result)
+}
+
+/// Average SQLite record size (header + body) of `columns` over the batch.
+fn record_bytes(batch: &RecordBatch, columns: &[impl std::borrow::Borrow<ColumnSpec>]) -> f64 {
+    let mut total = 0usize;
+    for spec in columns {
+        let spec = spec.borrow();
+        let Some(array) = batch.column_by_name(spec.name) else { continue };
+        for i in 0..batch.num_rows() {
+            let (serial_type, body) = value_size(array.as_ref(), spec.kind, i);
+            total += varint_len(serial_type) + body;
+        }
+    }
+    // One byte for the header-length varint.
+    1.0 + total as f64 / batch.num_rows() as f64
+}
+
+/// SQLite serial type and body size of one value.
+fn value_size(array: &dyn Array, kind: Kind, i: usize) -> (u64, usize) {
+    if array.is_null(i) {
+        return (0, 0);
+    }
+    match kind {
+        Kind::Int64 => {
+            let v = array.as_any().downcast_ref::<Int64Array>().unwrap().value(i);
+            match v {
+                0 => (8, 0),
+                1 => (9, 0),
+                v if i8::try_from(v).is_ok() => (1, 1),
+                v if i16::try_from(v).is_ok() => (2, 2),
+                v if (-(1 << 23)..1 << 23).contains(&v) => (3, 3),
+                v if i32::try_from(v).is_ok() => (4, 4),
+                v if (-(1 << 47)..1 << 47).contains(&v) => (5, 6),
+                _ => (6, 8),
+            }
+        }
+        Kind::Utf8 => {
+            let len = array.as_any().downcast_ref::<StringArray>().unwrap().value(i).len();
+            (len as u64 * 2 + 13, len)
+        }
+        Kind::Binary => {
+            let len = array.as_any().downcast_ref::<BinaryArray>().unwrap().value(i).len();
+            (len as u64 * 2 + 12, len)
+        }
+    }
+}
+
+fn varint_len(v: u64) -> usize {
+    let bits = 64 - v.leading_zeros() as usize;
+    bits.div_ceil(7).clamp(1, 9)
+}
+
+fn human(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{value:.0} {}", UNITS[unit])
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
@@ -0,0 +1,127 @@
+//! `--views-only` — a DuckDB database of views over the dataset's
+//! Parquet files, in place of copying them into SQLite:
+//!
+//!   blocks, transactions, logs  `read_parquet` over each table's files,
+//!                               with --skip-columns, --map and the block
+//!                               range applied
+//!   v_tx_with_block, ...        the `--views` helper views, with the
+//!                               labels, ens_names and signature tables
+//!                               they join (see views.rs)
+//!
+//! Nothing is read but the file list, so the "load" takes no time; every
+//! query scans the files instead. The views name the files by absolute path
+//! (or URL, for a remote dataset, which DuckDB reads with httpfs), so the
+//! dataset has to stay where it is.
+//!
+//! The tool doesn't link DuckDB: it writes the statements and runs them
+//! with the `duckdb` CLI, failing if it isn't on PATH. With an --out ending
+//! in `.sql` it writes the script for `duckdb <db> < script` instead.
+
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use eyre::{Context, Result};
+use tracing::info;
+
+use crate::schema::Table;
+use crate::source::{DataSource, ReadOptions};
+use crate::views;
+
+/// The tables' views and the helper views, as a DuckDB script.
+pub fn script(
+    source: &DataSource,
+    tables: &[Table],
+    read: &ReadOptions,
+    from_block: Option<i64>,
+    to_block: Option<i64>,
+) -> Result<String> {
+    let mut sql = String::from("-- Written by offline-replay --views-only\n\n");
+    for &table in tables {
+        let files = source
+            .list(table.name())?
+            .iter()
+            .map(|f| location(source, f).map(|l| quote(&l)))
+            .collect::<Result<Vec<_>>>()?;
+        if files.is_empty() {
+            eyre::bail!("Missing file: {}", source.display(table.file()));
+        }
+        let mut star = "*".to_string();
+        let renames: Vec<String> =
+            read.map.for_table(table).map(|(from, to)| format!("\"{from}\" AS \"{to}\"")).collect();
+        let skip: Vec<String> = read
+            .skip
+            .iter()
+            .filter(|s| table.columns().iter().any(|c| c.name == s.as_str()))
+            .map(|s| format!("\"{s}\""))
+            .collect();
+        if !skip.is_empty() {
+            star += &format!(" EXCLUDE ({})", skip.join(", "));
+        }
+        if !renames.is_empty() {
+            star += &format!(" RENAME ({})", renames.join(", "));
+        }
+        let number = if table == Table::Blocks { "number" } else { "block_number" };
+        let range: Vec<String> = [
+            from_block.map(|b| format!("{number} >= {b}")),
+            to_block.map(|b| format!("{number} <= {b}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let filter = if range.is_empty() {
+            String::new()
+        } else {
+            format!("\nWHERE {}", range.join(" AND "))
+        };
+        sql += &format!(
+            "CREATE OR REPLACE VIEW {} AS\nSELECT {star} FROM read_parquet([{}]){filter};\n\n",
+            table.name(),
+            files.join(", ")
+        );
+    }
+    sql += &views::duckdb();
+    Ok(sql)
+}
+
+/// Run `sql` against the DuckDB database `out`, or write it out as a script.
+pub fn write(sql: &str, out: &Path) -> Result<()> {
+    if out.extension().is_some_and(|e| e == "sql") {
+        std::fs::write(out, sql).with_context(|| format!("Cannot write {}", out.display()))?;
+        info!(out = %out.display(), "wrote DuckDB script; run it with `duckdb <db> < script`");
+        return Ok(());
+    }
+    let mut child = match Command::new("duckdb").arg(out).stdin(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => eyre::bail!(
+            "no duckdb CLI on PATH to create {}; install it, or pass an --out ending in `.sql` \
+             for the script",
+            out.display()
+        ),
+        Err(e) => return Err(e).context("Cannot run duckdb"),
+    };
+    child.stdin.take().expect("piped stdin").write_all(sql.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        eyre::bail!("duckdb exited with {status} creating {}", out.display());
+    }
+    info!(out = %out.display(), "wrote DuckDB views");
+    Ok(())
+}
+
+/// Where DuckDB finds `file`: its absolute path, or its URL.
+fn location(source: &DataSource, file: &str) -> Result<String> {
+    match source {
+        DataSource::Local(dir) => {
+            let path = dir.join(file);
+            let path = std::fs::canonicalize(&path)
+                .with_context(|| format!("Cannot resolve {}", path.display()))?;
+            Ok(path.display().to_string())
+        }
+        _ => Ok(source.display(file)),
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
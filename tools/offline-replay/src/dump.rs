@@ -0,0 +1,135 @@
+//! SQL text dump of a database, in the spirit of sqlite3's `.dump`.
+//!
+//! Differences that make dumps composable when piped into an existing DB:
+//!   - CREATE TABLE / CREATE INDEX use IF NOT EXISTS
+//!   - AUTOINCREMENT surrogate keys (logs.id) are left out, so the target
+//!     database assigns fresh ids instead of colliding with its own rows
+//!   - bookkeeping tables (`_meta`, `_loaded_files`) describe this database,
+//!     not the data, and are not dumped
+//!   - log_topics refers to logs by id, so instead of its rows the dump
+//!     rebuilds it from the topic columns of logs
+
+use std::io::Write;
+
+use eyre::Result;
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+
+use crate::schema::TOPICS_BACKFILL;
+
+pub fn dump(conn: &Connection, w: &mut impl Write) -> Result<()> {
+    writeln!(w, "PRAGMA foreign_keys=OFF;")?;
+    writeln!(w, "BEGIN TRANSACTION;")?;
+
+    let tables: Vec<(String, String)> = conn
+        .prepare(
+            "SELECT name, sql FROM sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND substr(name, 1, 1) <> '_'
+             ORDER BY rowid",
+        )?
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    for (name, sql) in &tables {
+        writeln!(w, "{};", if_not_exists(sql, "CREATE TABLE "))?;
+        if name == "log_topics" {
+            writeln!(w, "{}", TOPICS_BACKFILL.trim())?;
+            continue;
+        }
+        dump_rows(conn, name, sql, w)?;
+    }
+
+    let indexes: Vec<String> = conn
+        .prepare(
+            "SELECT sql FROM sqlite_master
+             WHERE type = 'index' AND sql IS NOT NULL ORDER BY rowid",
+        )?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for sql in &indexes {
+        let prefix =
+            if sql.starts_with("CREATE UNIQUE") { "CREATE UNIQUE INDEX " } else { "CREATE INDEX " };
+        writeln!(w, "{};", if_not_exists(sql, prefix))?;
+    }
+
+    let views: Vec<String> = conn
+        .prepare("SELECT sql FROM sqlite_master WHERE type = 'view' ORDER BY rowid")?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for sql in &views {
+        writeln!(w, "{};", if_not_exists(sql, "CREATE VIEW "))?;
+    }
+
+    writeln!(w, "COMMIT;")?;
+    w.flush()?;
+    Ok(())
+}
+
+fn if_not_exists(sql: &str, prefix: &str) -> String {
+    match sql.strip_prefix(prefix) {
+        Some(rest) => format!("{prefix}IF NOT EXISTS {rest}"),
+        None => sql.to_string(),
+    }
+}
+
+fn dump_rows(conn: &Connection, table: &str, sql: &str, w: &mut impl Write) -> Result<()> {
+    let autoincrement = sql.to_ascii_uppercase().contains("AUTOINCREMENT");
+    let columns: Vec<String> = conn
+        .prepare(&format!("SELECT name, pk FROM pragma_table_info('{table}') ORDER BY cid"))?
+        .query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)?)))?
+        .filter_map(|c| match c {
+            Ok((_, 1)) if autoincrement => None,
+            other => Some(other.map(|(name, _)| name)),
+        })
+        .collect::<rusqlite::Result<_>>()?;
+
+    let column_list = columns
+        .iter()
+        .map(|c| format!("\"{c}\""))
+        .collect::<Vec<_>>()
+        .join(",");
+    // WITHOUT ROWID tables (--strict's transactions) have no rowid; they
+    // read in primary key order anyway.
+    let without_rowid = sql.to_ascii_uppercase().contains("WITHOUT ROWID");
+    let order = if without_rowid { "" } else { " ORDER BY rowid" };
+    let mut stmt = conn.prepare(&format!("SELECT {column_list} FROM \"{table}\"{order}"))?;
+    let mut rows = stmt.query([])?;
+
+    while let Some(row) = rows.next()? {
+        write!(w, "INSERT INTO \"{table}\"({column_list}) VALUES(")?;
+        for i in 0..columns.len() {
+            if i > 0 {
+                w.write_all(b",")?;
+            }
+            write_value(w, row.get_ref(i)?)?;
+        }
+        writeln!(w, ");")?;
+    }
+    Ok(())
+}
+
+fn write_value(w: &mut impl Write, v: ValueRef) -> std::io::Result<()> {
+    match v {
+        ValueRef::Null => w.write_all(b"NULL"),
+        ValueRef::Integer(i) => write!(w, "{i}"),
+        ValueRef::Real(f) => write!(w, "{f:?}"),
+        ValueRef::Text(t) => {
+            w.write_all(b"'")?;
+            for &b in t {
+                if b == b'\'' {
+                    w.write_all(b"''")?;
+                } else {
+                    w.write_all(&[b])?;
+                }
+            }
+            w.write_all(b"'")
+        }
+        ValueRef::Blob(b) => {
+            w.write_all(b"X'")?;
+            for byte in b {
+                write!(w, "{byte:02x}")?;
+            }
+            w.write_all(b"'")
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! `ens` — resolve the addresses in a database to their primary ENS names,
+//! into an `ens_names` table.
+//!
+//! With `--rpc`, each address's reverse record (`<addr>.addr.reverse`) is
+//! looked up through the mainnet ENS registry and kept only if the name
+//! resolves forward to the same address, since anyone can set a reverse
+//! record claiming any name. With `--snapshot`, names come from a CSV or JSON
+//! file of address/name pairs in the `labels` formats instead.
+//!
+//! Addresses already in `ens_names` are skipped, so an interrupted run can be
+//! resumed and a later run only resolves newly loaded addresses. The `--views`
+//! views show these names where `labels` has none.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use alloy_primitives::{hex, keccak256, Address, B256};
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::params;
+use tracing::info;
+
+use crate::labels;
+use crate::normalize;
+use crate::open_existing;
+use crate::progress::progress_bar;
+use crate::rpc::RpcClient;
+use crate::schema::ENS_SCHEMA;
+use crate::views;
+
+/// The ENS registry, at the same address since 2020.
+const ENS_REGISTRY: &str = "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+
+#[derive(Args, Debug)]
+pub struct EnsArgs {
+    /// SQLite database whose addresses are resolved
+    db: PathBuf,
+
+    /// Mainnet JSON-RPC endpoint to resolve names against
+    #[arg(long, required_unless_present = "snapshot", conflicts_with = "snapshot")]
+    rpc: Option<String>,
+
+    /// CSV or JSON file of address/name pairs to take names from instead
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+
+    /// Block to resolve at (default: latest)
+    #[arg(long, requires = "rpc")]
+    block: Option<i64>,
+
+    /// Attempts per RPC call before giving up
+    #[arg(long, default_value_t = 8)]
+    retries: u32,
+
+    /// Base delay between retries in milliseconds (doubles on HTTP 429)
+    #[arg(long, default_value_t = 2000)]
+    retry_delay_ms: u64,
+}
+
+pub fn run(args: EnsArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    conn.execute_batch(ENS_SCHEMA)?;
+    views::refresh(&conn)?;
+    normalize::functions(&conn)?;
+
+    let addresses: Vec<String> = conn
+        .prepare(
+            "SELECT DISTINCT lower(hexkey(a)) FROM (
+                 SELECT from_addr AS a FROM transactions
+                 UNION SELECT to_addr FROM transactions WHERE to_addr IS NOT NULL
+                 UNION SELECT address FROM logs
+             ) WHERE lower(hexkey(a)) NOT IN (SELECT address FROM ens_names)",
+        )?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    info!(addresses = addresses.len(), "addresses to resolve");
+
+    const SQL: &str = "INSERT OR REPLACE INTO ens_names (address, name, source, resolved_at)
+                       VALUES (?1, ?2, ?3, strftime('%s', 'now'))";
+    let mut pb = progress_bar(addresses.len() as u64, "ens");
+    let mut named = 0;
+
+    if let Some(path) = &args.snapshot {
+        let names: HashMap<String, String> = labels::read(path)
+            .with_context(|| format!("Reading {}", path.display()))?
+            .into_iter()
+            .map(|l| (l.address.trim().to_ascii_lowercase(), l.name))
+            .collect();
+        let source = format!("snapshot:{}", path.display());
+        conn.execute_batch("BEGIN;")?;
+        for address in &addresses {
+            if let Some(name) = names.get(address) {
+                conn.prepare_cached(SQL)?.execute(params![address, name, source])?;
+                named += 1;
+            }
+            pb.inc(1);
+        }
+        conn.execute_batch("COMMIT;")?;
+    } else if let Some(url) = &args.rpc {
+        let client = RpcClient::new(url, args.retries, Duration::from_millis(args.retry_delay_ms));
+        let source = match args.block {
+            Some(b) => format!("rpc@{b}"),
+            None => "rpc@latest".to_string(),
+        };
+        // One row per address as it resolves, so an interrupted run keeps them.
+        for address in &addresses {
+            let addr: Address = address.parse().with_context(|| format!("Bad address {address}"))?;
+            let name = primary_name(&client, addr, args.block)
+                .with_context(|| format!("Resolving {address}"))?;
+            named += name.is_some() as usize;
+            conn.prepare_cached(SQL)?.execute(params![address, name, source])?;
+            pb.inc(1);
+        }
+    }
+
+    pb.finish_with_message(format!("ens ✓ ({named})"));
+    info!(db = %args.db.display(), resolved = addresses.len(), named, "done");
+    Ok(())
+}
+
+/// The verified primary name of `addr`: its reverse record, if that name
+/// resolves back to `addr`.
+fn primary_name(client: &RpcClient, addr: Address, block: Option<i64>) -> Result<Option<String>> {
+    let reverse = namehash(&format!("{}.addr.reverse", hex::encode(addr)));
+    let Some(reverse_resolver) = resolver(client, reverse, block)? else { return Ok(None) };
+    let out = client.eth_call(&reverse_resolver, &call("name(bytes32)", reverse), block)?;
+    let Some(name) = decode_string(&out).filter(|n| !n.is_empty()) else { return Ok(None) };
+
+    let node = namehash(&name);
+    let Some(forward_resolver) = resolver(client, node, block)? else { return Ok(None) };
+    let out = client.eth_call(&forward_resolver, &call("addr(bytes32)", node), block)?;
+    Ok((decode_address(&out) == Some(addr)).then_some(name))
+}
+
+/// Resolver contract registered for `node`, if any.
+fn resolver(client: &RpcClient, node: B256, block: Option<i64>) -> Result<Option<String>> {
+    let out = client.eth_call(ENS_REGISTRY, &call("resolver(bytes32)", node), block)?;
+    Ok(decode_address(&out)
+        .filter(|a| !a.is_zero())
+        .map(|a| a.to_checksum(None)))
+}
+
+/// ENS namehash (EIP-137).
+fn namehash(name: &str) -> B256 {
+    let mut node = B256::ZERO;
+    for label in name.rsplit('.').filter(|l| !l.is_empty()) {
+        node = keccak256([node.as_slice(), keccak256(label).as_slice()].concat());
+    }
+    node
+}
+
+/// Calldata for a `f(bytes32)` function.
+fn call(signature: &str, arg: B256) -> Vec<u8> {
+    [&keccak256(signature)[..4], arg.as_slice()].concat()
+}
+
+fn decode_address(out: &[u8]) -> Option<Address> {
+    (out.len() >= 32).then(|| Address::from_slice(&out[12..32]))
+}
+
+/// Decode an ABI-encoded `string` return value.
+fn decode_string(out: &[u8]) -> Option<String> {
+    let word = |at: usize| -> Option<usize> {
+        let w = out.get(at..at + 32)?;
+        w[..24].iter().all(|&b| b == 0).then(|| u64::from_be_bytes(w[24..].try_into().unwrap()) as usize)
+    };
+    let offset = word(0)?;
+    let len = word(offset)?;
+    let bytes = out.get(offset + 32..offset + 32 + len)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
@@ -0,0 +1,43 @@
+//! Errors about a dataset's shape, kept apart from the rest so scripts can
+//! tell "this file isn't in the expected format" from other failures by the
+//! exit code:
+//!
+//!   1   any other error
+//!   2   invalid command line (from clap)
+//!   3   a required column is missing
+//!   4   a column has another Arrow type than expected
+//!
+//! A loader error also says which file, and which rows and row group of it,
+//! were being loaded.
+
+use std::fmt;
+
+use arrow::datatypes::DataType;
+
+#[derive(Debug)]
+pub enum DatasetError {
+    MissingColumn { column: String },
+    TypeMismatch { column: String, expected: DataType, found: DataType },
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingColumn { column } => write!(f, "missing column `{column}`"),
+            Self::TypeMismatch { column, expected, found } => {
+                write!(f, "column `{column}` is {found}, expected {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+/// The process exit code for an error that ended a command.
+pub fn exit_code(e: &eyre::Report) -> u8 {
+    match e.chain().find_map(|e| e.downcast_ref::<DatasetError>()) {
+        Some(DatasetError::MissingColumn { .. }) => 3,
+        Some(DatasetError::TypeMismatch { .. }) => 4,
+        None => 1,
+    }
+}
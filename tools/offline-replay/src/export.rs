@@ -0,0 +1,607 @@
+//! `export` — write a loaded database out in formats other tools read.
+//!
+//!   export graph   address-level value-flow graph (GraphML, Neo4j CSV, DOT)
+//!   export table   a table or query result as an Arrow IPC or Parquet file
+//!   export report  a block range as a self-contained HTML page (see
+//!                  incident_report.rs)
+//!   export state   accounts at a block as an anvil/revm state file (see
+//!                  state_snapshot.rs)
+//!   export callgraph
+//!                  one transaction's calls as a DOT or JSON graph (see
+//!                  callgraph.rs)
+//!
+//! The graph has one node per address and one edge per (from, to, asset),
+//! weighted by the total amount moved and the number of transfers. ETH edges
+//! come from successful transactions' `value`; token edges from
+//! `erc20_transfers`, which is decoded from the logs first if needed. Amounts
+//! are in the asset's base unit (wei, or the token's smallest unit).
+//!
+//! Table exports keep each column's type: integers as Int64, reals as
+//! Float64, text as Utf8 and blobs as Binary, taken from the values of the
+//! first batch of rows (SQLite columns have no fixed type). Amounts stay as
+//! the database stores them — decimal or hex text, or 32-byte big-endian
+//! binary with blob32 — so none are rounded through floating point the way
+//! CSV readers do with numbers over 2^53. A column of mixed types is written
+//! as text. `--compress`ed calldata and log data (`input`, `data`) are
+//! decompressed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use alloy_primitives::{hex, U256};
+use arrow::array::{ArrayRef, BinaryBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use clap::{Args, Subcommand, ValueEnum};
+use eyre::{eyre, Context, Result};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use tracing::info;
+
+use crate::callgraph;
+use crate::decode;
+use crate::incident_report;
+use crate::normalize::{read_amount, read_key, Normalize};
+use crate::open_existing;
+use crate::state_snapshot;
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    #[command(subcommand)]
+    command: ExportCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ExportCommand {
+    /// Address-level value-flow graph of ETH and ERC-20 transfers
+    Graph(GraphArgs),
+
+    /// A table or query result as an Arrow IPC (Feather) or Parquet file
+    Table(TableArgs),
+
+    /// An HTML page of a block range's events, value flows and counterparties
+    Report(incident_report::ReportArgs),
+
+    /// Balances, nonces, code and storage of some accounts at a block, for anvil or revm
+    State(state_snapshot::StateArgs),
+
+    /// A transaction's calls as a DOT or JSON graph of the contracts involved (needs traces)
+    Callgraph(callgraph::CallgraphArgs),
+}
+
+#[derive(Args, Debug)]
+struct GraphArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Output file (a directory for --format neo4j)
+    #[arg(short, long)]
+    out: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = GraphFormat::Graphml)]
+    format: GraphFormat,
+
+    /// Only these assets: token addresses, or "eth" (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    asset: Vec<String>,
+
+    /// Only transfers at or above this block
+    #[arg(long)]
+    from_block: Option<i64>,
+
+    /// Only transfers at or below this block
+    #[arg(long)]
+    to_block: Option<i64>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum GraphFormat {
+    /// GraphML, for Gephi, yEd, networkx
+    Graphml,
+    /// nodes.csv + edges.csv for neo4j-admin import
+    Neo4j,
+    /// Graphviz DOT
+    Dot,
+}
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("rows").required(true).args(["table", "query"])))]
+struct TableArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Table or view to export
+    table: Option<String>,
+
+    /// Export the result of this SELECT instead
+    #[arg(long)]
+    query: Option<String>,
+
+    /// Output file
+    #[arg(short, long)]
+    out: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = TableFormat::Arrow)]
+    format: TableFormat,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub(crate) enum TableFormat {
+    /// Arrow IPC file (Feather v2), for polars, pandas, DuckDB
+    Arrow,
+    /// Parquet, zstd-compressed
+    Parquet,
+}
+
+pub fn run(args: ExportArgs) -> Result<()> {
+    match args.command {
+        ExportCommand::Graph(args) => graph(args),
+        ExportCommand::Table(args) => table(args),
+        ExportCommand::Report(args) => incident_report::run(args),
+        ExportCommand::State(args) => state_snapshot::run(args),
+        ExportCommand::Callgraph(args) => callgraph::run(args),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Value-flow graph
+// ---------------------------------------------------------------------------
+
+#[derive(Default)]
+pub(crate) struct Edge {
+    pub amount: U256,
+    pub count: u64,
+}
+
+/// Edges keyed by (from, to, asset), addresses and assets lowercase.
+pub(crate) type Edges = BTreeMap<(String, String, String), Edge>;
+
+fn graph(args: GraphArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    decode::erc20_transfers(&conn)?;
+    let norm = Normalize::from_meta(&conn)?;
+
+    let assets: Vec<String> = args.asset.iter().map(|a| a.to_ascii_lowercase()).collect();
+    let edges = edges(
+        &conn,
+        &assets,
+        args.from_block.unwrap_or(i64::MIN),
+        args.to_block.unwrap_or(i64::MAX),
+    )?;
+
+    let names = names(&conn)?;
+    match args.format {
+        GraphFormat::Graphml => write_file(&args.out, |w| graphml(w, &edges, &names, &norm))?,
+        GraphFormat::Dot => write_file(&args.out, |w| dot(w, &edges, &names, &norm))?,
+        GraphFormat::Neo4j => neo4j(&args.out, &edges, &names, &norm)?,
+    }
+    info!(out = %args.out.display(), nodes = nodes(&edges).len(), edges = edges.len(), "wrote graph");
+    Ok(())
+}
+
+/// The value flows of `assets` (every one when empty) between blocks `from`
+/// and `to`, `erc20_transfers` having been decoded.
+pub(crate) fn edges(conn: &Connection, assets: &[String], from: i64, to: i64) -> Result<Edges> {
+    let wanted = |asset: &str| assets.is_empty() || assets.iter().any(|a| a == asset);
+    let mut edges = Edges::new();
+    let mut add = |from: String, to: String, asset: &str, amount: U256| {
+        let e = edges
+            .entry((from.to_ascii_lowercase(), to.to_ascii_lowercase(), asset.to_string()))
+            .or_default();
+        e.amount = e.amount.saturating_add(amount);
+        e.count += 1;
+    };
+
+    if wanted("eth") {
+        let mut stmt = conn.prepare(
+            "SELECT from_addr, to_addr, value FROM transactions
+             WHERE status = 1 AND to_addr IS NOT NULL AND block_number BETWEEN ?1 AND ?2",
+        )?;
+        let mut rows = stmt.query(params![from, to])?;
+        while let Some(r) = rows.next()? {
+            let amount = read_amount(r.get_ref(2)?)?;
+            if let (false, Some(from), Some(to)) =
+                (amount.is_zero(), read_key(r.get_ref(0)?)?, read_key(r.get_ref(1)?)?)
+            {
+                add(from, to, "eth", amount);
+            }
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT from_addr, to_addr, lower(token), amount FROM erc20_transfers
+         WHERE block_number BETWEEN ?1 AND ?2",
+    )?;
+    let mut rows = stmt.query(params![from, to])?;
+    while let Some(r) = rows.next()? {
+        let token: String = r.get(2)?;
+        if wanted(&token) {
+            add(r.get(0)?, r.get(1)?, &token, read_amount(r.get_ref(3)?)?);
+        }
+    }
+    drop(rows);
+    Ok(edges)
+}
+
+/// Every address on an edge, in order.
+fn nodes(edges: &Edges) -> Vec<&str> {
+    let mut nodes: Vec<&str> =
+        edges.keys().flat_map(|(from, to, _)| [from.as_str(), to.as_str()]).collect();
+    nodes.sort_unstable();
+    nodes.dedup();
+    nodes
+}
+
+/// Names from `labels`, falling back to `ens_names`, where those tables exist.
+pub(crate) fn names(conn: &Connection) -> Result<HashMap<String, String>> {
+    let mut names = HashMap::new();
+    for table in ["ens_names", "labels"] {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |r| r.get(0),
+        )?;
+        if exists {
+            let mut stmt =
+                conn.prepare(&format!("SELECT address, name FROM {table} WHERE name IS NOT NULL"))?;
+            let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+            for row in rows {
+                let (address, name): (String, String) = row?;
+                names.insert(address, name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// An address as written out: in the database's --address-case, if it has one.
+fn display(norm: &Normalize, address: &str) -> String {
+    norm.address("address", address).map_or_else(|_| address.to_string(), |a| a.into_owned())
+}
+
+pub(crate) fn write_file(path: &Path, f: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Cannot create {}", path.display()))?;
+    let mut w = BufWriter::new(file);
+    f(&mut w)?;
+    w.flush()?;
+    Ok(())
+}
+
+fn graphml(
+    w: &mut dyn Write,
+    edges: &Edges,
+    names: &HashMap<String, String>,
+    norm: &Normalize,
+) -> Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(w, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(w, r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#)?;
+    writeln!(w, r#"  <key id="asset" for="edge" attr.name="asset" attr.type="string"/>"#)?;
+    writeln!(w, r#"  <key id="amount" for="edge" attr.name="amount" attr.type="string"/>"#)?;
+    writeln!(w, r#"  <key id="count" for="edge" attr.name="count" attr.type="long"/>"#)?;
+    writeln!(w, r#"  <graph id="value-flow" edgedefault="directed">"#)?;
+    for node in nodes(edges) {
+        write!(w, r#"    <node id="{}">"#, display(norm, node))?;
+        if let Some(name) = names.get(node) {
+            write!(w, r#"<data key="name">{}</data>"#, xml_escape(name))?;
+        }
+        writeln!(w, "</node>")?;
+    }
+    for ((from, to, asset), e) in edges {
+        writeln!(
+            w,
+            r#"    <edge source="{}" target="{}"><data key="asset">{}</data><data key="amount">{}</data><data key="count">{}</data></edge>"#,
+            display(norm, from),
+            display(norm, to),
+            display_asset(norm, asset),
+            e.amount,
+            e.count
+        )?;
+    }
+    writeln!(w, "  </graph>")?;
+    writeln!(w, "</graphml>")?;
+    Ok(())
+}
+
+fn dot(
+    w: &mut dyn Write,
+    edges: &Edges,
+    names: &HashMap<String, String>,
+    norm: &Normalize,
+) -> Result<()> {
+    writeln!(w, "digraph value_flow {{")?;
+    for node in nodes(edges) {
+        let label = names.get(node).cloned().unwrap_or_else(|| display(norm, node));
+        writeln!(w, "  \"{}\" [label=\"{}\"];", display(norm, node), dot_escape(&label))?;
+    }
+    for ((from, to, asset), e) in edges {
+        writeln!(
+            w,
+            "  \"{}\" -> \"{}\" [label=\"{} {}\", asset=\"{}\", amount=\"{}\", count={}];",
+            display(norm, from),
+            display(norm, to),
+            e.amount,
+            display_asset(norm, asset),
+            display_asset(norm, asset),
+            e.amount,
+            e.count
+        )?;
+    }
+    writeln!(w, "}}")?;
+    Ok(())
+}
+
+fn neo4j(
+    dir: &Path,
+    edges: &Edges,
+    names: &HashMap<String, String>,
+    norm: &Normalize,
+) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+
+    let mut nodes_csv = csv::Writer::from_path(dir.join("nodes.csv"))?;
+    nodes_csv.write_record(["address:ID", "name", ":LABEL"])?;
+    for node in nodes(edges) {
+        let name = names.get(node).map_or("", String::as_str);
+        nodes_csv.write_record([display(norm, node).as_str(), name, "Address"])?;
+    }
+    nodes_csv.flush()?;
+
+    let mut edges_csv = csv::Writer::from_path(dir.join("edges.csv"))?;
+    edges_csv.write_record([":START_ID", ":END_ID", "asset", "amount", "count:long", ":TYPE"])?;
+    for ((from, to, asset), e) in edges {
+        edges_csv.write_record([
+            display(norm, from),
+            display(norm, to),
+            display_asset(norm, asset),
+            e.amount.to_string(),
+            e.count.to_string(),
+            "TRANSFERRED".to_string(),
+        ])?;
+    }
+    edges_csv.flush()?;
+    Ok(())
+}
+
+fn display_asset(norm: &Normalize, asset: &str) -> String {
+    if asset == "eth" { "ETH".to_string() } else { display(norm, asset) }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub(crate) fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// ---------------------------------------------------------------------------
+// Tables and query results
+// ---------------------------------------------------------------------------
+
+/// Rows per record batch; the first batch also decides the column types.
+const BATCH_ROWS: usize = 8192;
+
+enum TableWriter {
+    Arrow(FileWriter<std::fs::File>),
+    Parquet(ArrowWriter<std::fs::File>),
+}
+
+impl TableWriter {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            TableWriter::Arrow(w) => w.write(batch)?,
+            TableWriter::Parquet(w) => w.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            TableWriter::Arrow(mut w) => w.finish()?,
+            TableWriter::Parquet(w) => {
+                w.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn table(args: TableArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let norm = Normalize::from_meta(&conn)?;
+    let sql = match (&args.table, &args.query) {
+        (Some(table), _) => {
+            let exists: bool = conn.query_row(
+                "SELECT COUNT(*) > 0 FROM sqlite_master
+                 WHERE type IN ('table', 'view') AND name = ?1",
+                [table],
+                |r| r.get(0),
+            )?;
+            if !exists {
+                eyre::bail!("{} has no table or view {table:?}", args.db.display());
+            }
+            format!("SELECT * FROM \"{}\"", table.replace('"', "\"\""))
+        }
+        (None, Some(query)) => query.clone(),
+        (None, None) => unreachable!("clap requires a table or --query"),
+    };
+    let total = write_query(&conn, &norm, &sql, &args.out, args.format)?;
+    info!(out = %args.out.display(), rows = total, "wrote table");
+    Ok(())
+}
+
+/// Write the rows of `sql` to `out`. Returns how many there were.
+pub(crate) fn write_query(
+    conn: &Connection,
+    norm: &Normalize,
+    sql: &str,
+    out: &Path,
+    format: TableFormat,
+) -> Result<u64> {
+    let mut stmt = conn.prepare(sql)?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let compressed: Vec<bool> =
+        names.iter().map(|n| norm.compress && matches!(n.as_str(), "input" | "data")).collect();
+    let mut rows = stmt.query([])?;
+
+    let mut chunk: Vec<Vec<Value>> = Vec::with_capacity(BATCH_ROWS);
+    let mut schema: Option<SchemaRef> = None;
+    let mut writer: Option<TableWriter> = None;
+    let mut total = 0u64;
+    loop {
+        let row = rows.next()?;
+        if let Some(row) = row {
+            chunk.push((0..names.len()).map(|i| row.get(i)).collect::<rusqlite::Result<_>>()?);
+            if chunk.len() < BATCH_ROWS {
+                continue;
+            }
+        }
+        let schema = schema.get_or_insert_with(|| {
+            let fields: Vec<Field> = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Field::new(name, infer(chunk.iter().map(|r| &r[i])), true))
+                .collect();
+            Arc::new(Schema::new(fields))
+        });
+        let writer = match &mut writer {
+            Some(w) => w,
+            None => writer.insert(create_writer(out, format, schema)?),
+        };
+        if !chunk.is_empty() {
+            let batch = to_batch(schema, &chunk, &compressed, norm, total)?;
+            writer.write(&batch)?;
+            total += chunk.len() as u64;
+            chunk.clear();
+        }
+        if row.is_none() {
+            break;
+        }
+    }
+    writer.expect("created with the first batch").finish()?;
+    Ok(total)
+}
+
+fn create_writer(path: &Path, format: TableFormat, schema: &SchemaRef) -> Result<TableWriter> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Cannot create {}", path.display()))?;
+    Ok(match format {
+        TableFormat::Arrow => TableWriter::Arrow(FileWriter::try_new(file, schema)?),
+        TableFormat::Parquet => {
+            let props = WriterProperties::builder()
+                .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                .build();
+            TableWriter::Parquet(ArrowWriter::try_new(file, schema.clone(), Some(props))?)
+        }
+    })
+}
+
+/// The Arrow type for a column holding `values`: that of its values if they
+/// agree (integers and reals together are Float64), text otherwise.
+fn infer<'a>(values: impl Iterator<Item = &'a Value>) -> DataType {
+    let mut inferred = None;
+    for v in values {
+        let t = match v {
+            Value::Null => continue,
+            Value::Integer(_) => DataType::Int64,
+            Value::Real(_) => DataType::Float64,
+            Value::Text(_) => DataType::Utf8,
+            Value::Blob(_) => DataType::Binary,
+        };
+        inferred = match (inferred, t) {
+            (None, t) => Some(t),
+            (Some(a), b) if a == b => Some(a),
+            (Some(DataType::Int64 | DataType::Float64), DataType::Int64 | DataType::Float64) => {
+                Some(DataType::Float64)
+            }
+            _ => return DataType::Utf8,
+        };
+    }
+    inferred.unwrap_or(DataType::Utf8)
+}
+
+/// `rows` as a batch of `schema`; `offset` is the number of rows before them,
+/// for messages.
+fn to_batch(
+    schema: &SchemaRef,
+    rows: &[Vec<Value>],
+    compressed: &[bool],
+    norm: &Normalize,
+    offset: u64,
+) -> Result<RecordBatch> {
+    let mut columns: Vec<ArrayRef> = Vec::with_capacity(schema.fields().len());
+    for (i, field) in schema.fields().iter().enumerate() {
+        let values = rows.iter().map(|r| &r[i]);
+        let mismatch = |n: usize, v: &Value| {
+            eyre!(
+                "column {:?}, row {}: a {:?} value in a {} column; CAST the column in --query",
+                field.name(),
+                offset + n as u64 + 1,
+                v.data_type(),
+                field.data_type()
+            )
+        };
+        let array: ArrayRef = match field.data_type() {
+            DataType::Int64 => {
+                let mut b = Int64Builder::with_capacity(rows.len());
+                for (n, v) in values.enumerate() {
+                    match v {
+                        Value::Null => b.append_null(),
+                        Value::Integer(x) => b.append_value(*x),
+                        other => return Err(mismatch(n, other)),
+                    }
+                }
+                Arc::new(b.finish())
+            }
+            DataType::Float64 => {
+                let mut b = Float64Builder::with_capacity(rows.len());
+                for (n, v) in values.enumerate() {
+                    match v {
+                        Value::Null => b.append_null(),
+                        Value::Integer(x) => b.append_value(*x as f64),
+                        Value::Real(x) => b.append_value(*x),
+                        other => return Err(mismatch(n, other)),
+                    }
+                }
+                Arc::new(b.finish())
+            }
+            DataType::Binary => {
+                let mut b = BinaryBuilder::with_capacity(rows.len(), 0);
+                for (n, v) in values.enumerate() {
+                    match v {
+                        Value::Null => b.append_null(),
+                        Value::Blob(x) if compressed[i] => {
+                            b.append_value(norm.read_blob(x.clone())?)
+                        }
+                        Value::Blob(x) => b.append_value(x),
+                        other => return Err(mismatch(n, other)),
+                    }
+                }
+                Arc::new(b.finish())
+            }
+            _ => {
+                let mut b = StringBuilder::with_capacity(rows.len(), 0);
+                for v in values {
+                    match v {
+                        Value::Null => b.append_null(),
+                        Value::Integer(x) => b.append_value(x.to_string()),
+                        Value::Real(x) => b.append_value(x.to_string()),
+                        Value::Text(x) => b.append_value(x),
+                        Value::Blob(x) => b.append_value(format!("0x{}", hex::encode(x))),
+                    }
+                }
+                Arc::new(b.finish())
+            }
+        };
+        columns.push(array);
+    }
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
@@ -0,0 +1,339 @@
+//! `fetch` — build a Parquet dataset straight from an archive JSON-RPC node.
+//!
+//! Per block: eth_getBlockByNumber (full transactions) + eth_getBlockReceipts.
+//! Blocks are fetched concurrently in windows and written in block order, so
+//! the output is identical regardless of --concurrency.
+//!
+//! The node's eth_chainId goes into the manifest, and must be the one of a
+//! `--chain` preset (see chain.rs). `--from-date` and `--to-date` stand for
+//! the first block at or after one and the last before the other, found by
+//! their timestamps: a first guess from the chain head and the preset's
+//! block time, then interpolating between the blocks either side.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use clap::Args;
+use eyre::{eyre, Context, Result};
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+use crate::chain::Chain;
+use crate::dataset::{BlockRow, DatasetWriter, LogRow, TxRow};
+use crate::filter;
+use crate::manifest::BlockRange;
+use crate::progress::progress_bar;
+use crate::rpc::{self, RpcClient};
+
+#[derive(Args, Debug)]
+pub struct FetchArgs {
+    /// Archive node JSON-RPC endpoint
+    #[arg(long, env = "RPC_URL")]
+    rpc: String,
+
+    /// First block (inclusive)
+    #[arg(long, required_unless_present = "from_date", conflicts_with = "from_date")]
+    start: Option<i64>,
+
+    /// Last block (inclusive)
+    #[arg(long, required_unless_present = "to_date", conflicts_with = "to_date")]
+    end: Option<i64>,
+
+    /// Start at the first block at or after this date/time (ISO 8601, or Unix
+    /// seconds)
+    #[arg(long, value_parser = filter::parse_date)]
+    from_date: Option<i64>,
+
+    /// End at the last block before this date/time
+    #[arg(long, value_parser = filter::parse_date)]
+    to_date: Option<i64>,
+
+    /// The chain the node serves: a preset's chain id is checked and its
+    /// block time speeds up finding dates
+    #[arg(long, value_enum, default_value_t = Chain::Ethereum)]
+    chain: Chain,
+
+    /// Output dataset directory
+    #[arg(short, long, default_value = ".")]
+    out: PathBuf,
+
+    /// Blocks fetched in parallel
+    #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    concurrency: u32,
+
+    /// Attempts per RPC call before giving up
+    #[arg(long, default_value_t = 8)]
+    retries: u32,
+
+    /// Base delay between retries in milliseconds (doubles on HTTP 429)
+    #[arg(long, default_value_t = 2000)]
+    retry_delay_ms: u64,
+}
+
+/// A block plus its receipts, converted to dataset rows.
+struct FetchedBlock {
+    block: BlockRow,
+    txs: Vec<TxRow>,
+    logs: Vec<LogRow>,
+}
+
+pub fn run(args: FetchArgs) -> Result<()> {
+    let client =
+        RpcClient::new(&args.rpc, args.retries, Duration::from_millis(args.retry_delay_ms));
+    let chain_id = u64::try_from(rpc::quantity(&client.call("eth_chainId", json!([]))?)?)
+        .map_err(|_| eyre!("eth_chainId out of range"))?;
+    args.chain.check_id(chain_id, &format!("{} (eth_chainId)", args.rpc))?;
+
+    let (start, end) = match (args.from_date, args.to_date) {
+        (None, None) => (
+            args.start.expect("--start or --from-date"),
+            args.end.expect("--end or --to-date"),
+        ),
+        (from_date, to_date) => {
+            if let (Some(from), Some(to)) = (from_date, to_date) {
+                if from >= to {
+                    eyre::bail!("--from-date must be before --to-date");
+                }
+            }
+            let mut dates = Dates::new(&client, args.chain.block_time())?;
+            let start = match from_date {
+                Some(t) => dates.first_at(t)?,
+                None => args.start.expect("--start or --from-date"),
+            };
+            let end = match to_date {
+                Some(t) => dates.first_at(t)? - 1,
+                None => args.end.expect("--end or --to-date"),
+            };
+            info!(start, end, calls = dates.calls, "resolved dates to blocks");
+            (start, end)
+        }
+    };
+    if start > end {
+        eyre::bail!("--start ({start}) must not exceed --end ({end})");
+    }
+
+    let mut writer = DatasetWriter::create(&args.out, None)?;
+    writer.set_chain_id(chain_id);
+
+    let total = (end - start + 1) as u64;
+    let mut pb = progress_bar(total, "blocks");
+    let window = args.concurrency as i64 * 8;
+
+    let mut from = start;
+    while from <= end {
+        let to = (from + window - 1).min(end);
+        for fetched in fetch_window(&client, from, to, args.concurrency)? {
+            writer.push_block(fetched.block)?;
+            for tx in fetched.txs {
+                writer.push_tx(tx)?;
+            }
+            for log in fetched.logs {
+                writer.push_log(log)?;
+            }
+        }
+        pb.inc((to - from + 1) as u64);
+        from = to + 1;
+    }
+    pb.finish_with_message("blocks ✓");
+
+    let m = writer.finish(BlockRange { start, end })?;
+
+    for (table, t) in &m.tables {
+        info!(table, rows = t.rows, "fetched");
+    }
+    info!(out = %args.out.display(), "done");
+    Ok(())
+}
+
+/// Block timestamps, read from the node as a date's block is searched for.
+struct Dates<'a> {
+    client: &'a RpcClient,
+    block_time: Option<f64>,
+    /// The chain head, as (number, timestamp).
+    head: (i64, i64),
+    calls: u32,
+}
+
+impl<'a> Dates<'a> {
+    fn new(client: &'a RpcClient, block_time: Option<f64>) -> Result<Self> {
+        let number = rpc::quantity_i64(&client.call("eth_blockNumber", json!([]))?)?;
+        let mut dates = Self { client, block_time, head: (number, 0), calls: 1 };
+        dates.head.1 = dates.timestamp(number)?;
+        Ok(dates)
+    }
+
+    fn timestamp(&mut self, number: i64) -> Result<i64> {
+        self.calls += 1;
+        let block =
+            self.client.call("eth_getBlockByNumber", json!([format!("0x{number:x}"), false]))?;
+        if block.is_null() {
+            eyre::bail!("block {number} not found");
+        }
+        rpc::quantity_i64(&block["timestamp"])
+    }
+
+    /// The first block with a timestamp at or after `t`; the block after
+    /// the head if none has one yet.
+    fn first_at(&mut self, t: i64) -> Result<i64> {
+        let mut hi = self.head;
+        if hi.1 < t {
+            return Ok(hi.0 + 1);
+        }
+        let mut lo = (0, self.timestamp(0)?);
+        if lo.1 >= t {
+            return Ok(0);
+        }
+        // lo is before t and hi at or after it. The first guess counts back
+        // from the head; later ones interpolate, or bisect when that
+        // doesn't at least halve the range.
+        let mut guess = match self.block_time {
+            Some(bt) => hi.0 - ((hi.1 - t) as f64 / bt).ceil() as i64,
+            None => (lo.0 + hi.0) / 2,
+        };
+        while hi.0 - lo.0 > 1 {
+            let before = hi.0 - lo.0;
+            let n = guess.clamp(lo.0 + 1, hi.0 - 1);
+            let time = self.timestamp(n)?;
+            if time >= t {
+                hi = (n, time);
+            } else {
+                lo = (n, time);
+            }
+            guess = if (hi.0 - lo.0) * 2 > before {
+                (lo.0 + hi.0) / 2
+            } else {
+                lo.0 + ((t - lo.1) as i128 * (hi.0 - lo.0) as i128 / (hi.1 - lo.1) as i128) as i64
+            };
+        }
+        Ok(hi.0)
+    }
+}
+
+/// Fetch blocks `from..=to` with `concurrency` workers, returned in block order.
+fn fetch_window(
+    client: &RpcClient,
+    from: i64,
+    to: i64,
+    concurrency: u32,
+) -> Result<Vec<FetchedBlock>> {
+    let next = AtomicI64::new(from);
+    let results: Mutex<BTreeMap<i64, FetchedBlock>> = Mutex::new(BTreeMap::new());
+
+    std::thread::scope(|s| {
+        let workers: Vec<_> = (0..concurrency)
+            .map(|_| {
+                s.spawn(|| -> Result<()> {
+                    loop {
+                        let n = next.fetch_add(1, Ordering::Relaxed);
+                        if n > to {
+                            return Ok(());
+                        }
+                        let fetched = fetch_block(client, n)
+                            .with_context(|| format!("Fetching block {n}"))?;
+                        debug!(block = n, txs = fetched.txs.len(), logs = fetched.logs.len(), "fetched block");
+                        results.lock().unwrap().insert(n, fetched);
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|w| w.join().map_err(|_| eyre!("fetch worker panicked"))?)
+    })?;
+
+    Ok(results.into_inner().unwrap().into_values().collect())
+}
+
+fn fetch_block(client: &RpcClient, number: i64) -> Result<FetchedBlock> {
+    let tag = format!("0x{number:x}");
+    let block = client.call("eth_getBlockByNumber", json!([tag, true]))?;
+    if block.is_null() {
+        eyre::bail!("block not found");
+    }
+    let receipts = client.call("eth_getBlockReceipts", json!([tag]))?;
+
+    let mut receipt_by_hash: BTreeMap<String, &Value> = BTreeMap::new();
+    for r in receipts.as_array().map(Vec::as_slice).unwrap_or_default() {
+        receipt_by_hash.insert(lower(&r["transactionHash"])?, r);
+    }
+
+    let transactions = block["transactions"].as_array().cloned().unwrap_or_default();
+    let block_row = BlockRow {
+        number,
+        hash: lower(&block["hash"])?,
+        parent_hash: lower(&block["parentHash"])?,
+        timestamp: rpc::quantity_i64(&block["timestamp"])?,
+        gas_used: rpc::quantity_i64(&block["gasUsed"])?,
+        gas_limit: rpc::quantity_i64(&block["gasLimit"])?,
+        base_fee: match &block["baseFeePerGas"] {
+            Value::Null => None,
+            v => Some(rpc::quantity(v)?.to_string()),
+        },
+        tx_count: transactions.len() as i64,
+        miner: Some(lower(&block["miner"])?),
+    };
+
+    let mut txs = Vec::with_capacity(transactions.len());
+    let mut logs = Vec::new();
+    for (tx_index, tx) in transactions.iter().enumerate() {
+        let hash = lower(&tx["hash"])?;
+        let receipt = receipt_by_hash
+            .get(&hash)
+            .ok_or_else(|| eyre!("no receipt for transaction {hash}"))?;
+
+        let gas_price = match &receipt["effectiveGasPrice"] {
+            Value::Null => rpc::quantity(&tx["gasPrice"])?,
+            v => rpc::quantity(v)?,
+        };
+        // Pre-Byzantium receipts carry a state root instead of a status.
+        let status = match &receipt["status"] {
+            Value::Null => 1,
+            v => rpc::quantity_i64(v)?,
+        };
+
+        txs.push(TxRow {
+            hash: hash.clone(),
+            block_number: number,
+            tx_index: tx_index as i64,
+            from_addr: lower(&tx["from"])?,
+            to_addr: match &tx["to"] {
+                Value::Null => None,
+                v => Some(lower(v)?),
+            },
+            value: rpc::quantity(&tx["value"])?.to_string(),
+            gas_used: rpc::quantity_i64(&receipt["gasUsed"])?,
+            gas_price: gas_price.to_string(),
+            input: rpc::data(&tx["input"])?,
+            status,
+            logs_bloom: match &receipt["logsBloom"] {
+                Value::Null => None,
+                v => Some(rpc::data(v)?),
+            },
+            nonce: rpc::quantity_i64(&tx["nonce"])?,
+        });
+
+        for log in receipt["logs"].as_array().map(Vec::as_slice).unwrap_or_default() {
+            let topics = log["topics"].as_array().map(Vec::as_slice).unwrap_or_default();
+            let topic = |i: usize| topics.get(i).map(lower).transpose();
+            logs.push(LogRow {
+                block_number: number,
+                tx_hash: hash.clone(),
+                log_index: rpc::quantity_i64(&log["logIndex"])?,
+                address: lower(&log["address"])?,
+                topics: [topic(0)?, topic(1)?, topic(2)?, topic(3)?],
+                data: rpc::data(&log["data"])?,
+            });
+        }
+    }
+
+    Ok(FetchedBlock { block: block_row, txs, logs })
+}
+
+fn lower(v: &Value) -> Result<String> {
+    v.as_str()
+        .map(str::to_lowercase)
+        .ok_or_else(|| eyre!("expected hex string, got {v}"))
+}
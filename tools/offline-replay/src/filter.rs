@@ -0,0 +1,129 @@
+//! Row filters applied while loading: a block range and a set of addresses.
+//!
+//! The address filter keeps logs emitted by one of the addresses, and the
+//! transactions that either touch an address directly (from/to) or emitted one
+//! of those logs — so every kept log still has its transaction. Those hashes
+//! come from a pre-pass over the logs files ([`Filter::scan_logs`]).
+//!
+//! `--from-date` and `--to-date` are turned into a block range by another
+//! pre-pass, over the blocks files' timestamps ([`block_range`]): the first
+//! block at or after `--from-date` up to the last one before `--to-date`.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use eyre::Result;
+
+use crate::prices;
+use crate::source::TableReader;
+use crate::{col_i64, col_str};
+
+#[derive(Default, Debug)]
+pub struct Filter {
+    pub from_block: Option<i64>,
+    pub to_block: Option<i64>,
+    /// Lowercased; None keeps every address.
+    pub addresses: Option<HashSet<String>>,
+    /// Transactions that emitted a matching log.
+    tx_hashes: HashSet<String>,
+    /// Lowercased hashes of the blocks reorged out, whose transactions and
+    /// logs are left out (see reorg.rs).
+    pub orphans: HashSet<String>,
+}
+
+impl Filter {
+    pub fn new(from_block: Option<i64>, to_block: Option<i64>, addresses: &[String]) -> Self {
+        let addresses = (!addresses.is_empty())
+            .then(|| addresses.iter().map(|a| a.to_ascii_lowercase()).collect());
+        Self { from_block, to_block, addresses, tx_hashes: HashSet::new(), orphans: HashSet::new() }
+    }
+
+    pub fn block(&self, number: i64) -> bool {
+        self.from_block.map_or(true, |from| number >= from)
+            && self.to_block.map_or(true, |to| number <= to)
+    }
+
+    pub fn transaction(&self, block: i64, hash: &str, from: &str, to: Option<&str>) -> bool {
+        if !self.block(block) {
+            return false;
+        }
+        let Some(addresses) = &self.addresses else { return true };
+        self.tx_hashes.contains(&hash.to_ascii_lowercase())
+            || addresses.contains(&from.to_ascii_lowercase())
+            || to.is_some_and(|to| addresses.contains(&to.to_ascii_lowercase()))
+    }
+
+    pub fn log(&self, block: i64, address: &str) -> bool {
+        self.block(block)
+            && self.addresses.as_ref().map_or(true, |a| a.contains(&address.to_ascii_lowercase()))
+    }
+
+    /// Whether a row of the block `block_hash`, where the file says which,
+    /// is from a canonical block.
+    pub fn canonical(&self, block_hash: Option<&str>) -> bool {
+        block_hash.map_or(true, |hash| !self.orphans.contains(&hash.to_ascii_lowercase()))
+    }
+
+    /// Remember the transactions behind the logs this filter keeps.
+    pub fn scan_logs(&mut self, table: TableReader) -> Result<()> {
+        if self.addresses.is_none() {
+            return Ok(());
+        }
+        for batch in table.batches {
+            let batch = batch?;
+            let block_number = col_i64(&batch, "block_number")?;
+            let tx_hash = col_str(&batch, "tx_hash")?;
+            let address = col_str(&batch, "address")?;
+            for i in 0..batch.num_rows() {
+                if self.log(block_number.value(i), address.value(i)) {
+                    self.tx_hashes.insert(tx_hash.value(i).to_ascii_lowercase());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse `--from-date`/`--to-date`: an ISO 8601 date/time, UTC unless it
+/// has an offset, or Unix seconds.
+pub fn parse_date(s: &str) -> Result<i64, String> {
+    prices::timestamp(s.trim())
+        .ok_or_else(|| format!("expected a date/time such as 2023-03-13T00:00:00Z, got {s:?}"))
+}
+
+/// The blocks with `from <= timestamp < to`, as (first, last) block numbers;
+/// None when no block is in that time range.
+pub fn block_range(
+    tables: impl IntoIterator<Item = Result<TableReader>>,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Option<(i64, i64)>> {
+    let mut range: Option<(i64, i64)> = None;
+    for table in tables {
+        for batch in table?.batches {
+            let batch = batch?;
+            let number = col_i64(&batch, "number")?;
+            let timestamp = col_i64(&batch, "timestamp")?;
+            for i in 0..batch.num_rows() {
+                let t = timestamp.value(i);
+                if from.is_some_and(|from| t < from) || to.is_some_and(|to| t >= to) {
+                    continue;
+                }
+                let n = number.value(i);
+                range = Some(range.map_or((n, n), |(first, last)| (first.min(n), last.max(n))));
+            }
+        }
+    }
+    Ok(range)
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bound = |b: Option<i64>| b.map_or_else(|| "*".to_string(), |b| b.to_string());
+        write!(f, "blocks {}..{}", bound(self.from_block), bound(self.to_block))?;
+        if let Some(addresses) = &self.addresses {
+            write!(f, ", {} address(es)", addresses.len())?;
+        }
+        Ok(())
+    }
+}
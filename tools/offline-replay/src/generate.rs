@@ -0,0 +1,408 @@
+//! `generate` — write a synthetic dataset in the loader's format, for testing
+//! loaders, decoders and downstream pipelines without real chain data.
+//!
+//! Transactions come from a fixed pool of senders. A share of them
+//! (--call-share) call a contract and emit a Poisson-distributed number of
+//! logs (--logs-per-call), each one of a few well-known events:
+//!
+//!   transfer     ERC-20 Transfer(address,address,uint256), from a token
+//!   approval     ERC-20 Approval(address,address,uint256), from a token
+//!   swap         Uniswap V2 Swap(address,uint256,uint256,uint256,uint256,address)
+//!   sync         Uniswap V2 Sync(uint112,uint112), from a pair
+//!   deposit      WETH Deposit(address,uint256)
+//!   withdrawal   WETH Withdrawal(address,uint256)
+//!
+//! The rest are plain ETH transfers; one call in fifty reverts and logs
+//! nothing. Nonces, gas totals, tx counts and logs blooms are consistent with
+//! each other, so `verify` finds nothing to report, and transfers decode
+//! into `erc20_transfers` and swaps into `dex_swaps`. Calldata is the called
+//! method's selector and two argument words, not a faithful encoding. The
+//! rows depend only on the options: the same options always write the same
+//! data.
+
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::{hex, keccak256, U256};
+use clap::{Args, ValueEnum};
+use eyre::Result;
+use tracing::info;
+
+use crate::dataset::{BlockRow, DatasetWriter, LogRow, TxRow};
+use crate::decode;
+use crate::manifest::{BlockRange, Manifest};
+use crate::progress::progress_bar;
+use crate::verify::bloom;
+
+/// Addresses sending transactions.
+const SENDERS: u64 = 500;
+/// Addresses receiving ETH and tokens.
+const HOLDERS: u64 = 2000;
+/// Fee recipients of the blocks, in turn.
+const BUILDERS: u64 = 4;
+/// ERC-20 token contracts.
+const TOKENS: u64 = 16;
+/// Uniswap V2-style pairs.
+const PAIRS: u64 = 8;
+
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    /// Output dataset directory
+    #[arg(short, long)]
+    out: PathBuf,
+
+    #[command(flatten)]
+    spec: Spec,
+}
+
+/// What to generate (shared with `bench`, which generates its dataset).
+#[derive(Args, Debug)]
+pub struct Spec {
+    /// Blocks to generate
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub blocks: u64,
+
+    /// Number of the first block
+    #[arg(long, default_value_t = 17_000_000)]
+    pub start_block: i64,
+
+    /// Mean transactions per block
+    #[arg(long, default_value_t = 150)]
+    pub txs_per_block: u64,
+
+    /// How the transaction count varies from block to block
+    #[arg(long, value_enum, default_value_t = TxDistribution::Uniform)]
+    pub tx_distribution: TxDistribution,
+
+    /// Share of transactions that call a contract; the rest move ETH
+    #[arg(long, default_value_t = 0.6)]
+    pub call_share: f64,
+
+    /// Mean logs per successful contract call
+    #[arg(long, default_value_t = 1.0)]
+    pub logs_per_call: f64,
+
+    /// Events to emit (comma-separated; default: all)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub events: Vec<Event>,
+
+    /// Seed for the generator; change it for a different dataset of the same shape
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+
+    /// Rows per Parquet row group [default: the writer's, about a million];
+    /// smaller ones give --read-threads more to share out
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub row_group_size: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TxDistribution {
+    /// Exactly --txs-per-block in every block
+    Fixed,
+    /// Uniform between 0 and twice --txs-per-block
+    Uniform,
+    /// Poisson with mean --txs-per-block
+    Poisson,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Event {
+    Transfer,
+    Approval,
+    Swap,
+    Sync,
+    Deposit,
+    Withdrawal,
+}
+
+impl Event {
+    const ALL: [Event; 6] = [
+        Event::Transfer,
+        Event::Approval,
+        Event::Swap,
+        Event::Sync,
+        Event::Deposit,
+        Event::Withdrawal,
+    ];
+
+    fn signature(self) -> &'static str {
+        match self {
+            Event::Transfer => "Transfer(address,address,uint256)",
+            Event::Approval => "Approval(address,address,uint256)",
+            Event::Swap => "Swap(address,uint256,uint256,uint256,uint256,address)",
+            Event::Sync => "Sync(uint112,uint112)",
+            Event::Deposit => "Deposit(address,uint256)",
+            Event::Withdrawal => "Withdrawal(address,uint256)",
+        }
+    }
+
+    /// The method a transaction calls to emit the event.
+    fn method(self) -> &'static str {
+        match self {
+            Event::Transfer => "transfer(address,uint256)",
+            Event::Approval => "approve(address,uint256)",
+            Event::Swap | Event::Sync => "swap(uint256,uint256,address,bytes)",
+            Event::Deposit => "deposit()",
+            Event::Withdrawal => "withdraw(uint256)",
+        }
+    }
+
+    /// How often the event is picked, relative to the others.
+    fn weight(self) -> u64 {
+        match self {
+            Event::Transfer => 5,
+            Event::Approval | Event::Swap | Event::Sync => 2,
+            Event::Deposit | Event::Withdrawal => 1,
+        }
+    }
+
+    /// A contract that emits the event.
+    fn contract(self, rng: &mut Rng) -> String {
+        match self {
+            Event::Transfer | Event::Approval => address(b"token", rng.below(TOKENS)),
+            Event::Swap | Event::Sync => address(b"pair", rng.below(PAIRS)),
+            Event::Deposit | Event::Withdrawal => address(b"weth", 0),
+        }
+    }
+}
+
+pub fn run(args: GenerateArgs) -> Result<()> {
+    let m = write(&args.out, &args.spec)?;
+    for (table, t) in &m.tables {
+        info!(table, rows = t.rows, "generated");
+    }
+    info!(out = %args.out.display(), "done");
+    Ok(())
+}
+
+/// Write a dataset for `spec` to `dir`.
+pub fn write(dir: &Path, spec: &Spec) -> Result<Manifest> {
+    if !(0.0..=1.0).contains(&spec.call_share) {
+        eyre::bail!("--call-share must be between 0 and 1, not {}", spec.call_share);
+    }
+    if !spec.logs_per_call.is_finite() || spec.logs_per_call < 0.0 {
+        eyre::bail!("--logs-per-call must be a non-negative number, not {}", spec.logs_per_call);
+    }
+    let events: &[Event] = if spec.events.is_empty() { &Event::ALL } else { &spec.events };
+    let total_weight: u64 = events.iter().map(|e| e.weight()).sum();
+    let pick = |rng: &mut Rng| {
+        let mut n = rng.below(total_weight);
+        for &event in events {
+            if n < event.weight() {
+                return event;
+            }
+            n -= event.weight();
+        }
+        unreachable!("n is below the total weight")
+    };
+
+    let mut rng = Rng(spec.seed);
+    let mut writer = DatasetWriter::create(dir, spec.row_group_size.map(|r| r as usize))?;
+    let mut nonces = vec![0i64; SENDERS as usize];
+    let mut parent_hash = hash(b"block", spec.start_block - 1, 0);
+    let mut pb = progress_bar(spec.blocks, "blocks");
+
+    for number in spec.start_block..spec.start_block + spec.blocks as i64 {
+        let base_fee = 15_000_000_000 + rng.below(10_000_000_000);
+        let tx_count = match spec.tx_distribution {
+            TxDistribution::Fixed => spec.txs_per_block,
+            TxDistribution::Uniform => rng.below(2 * spec.txs_per_block + 1),
+            TxDistribution::Poisson => rng.poisson(spec.txs_per_block as f64),
+        };
+        let mut gas_used = 0;
+        let mut log_index = 0;
+
+        for tx_index in 0..tx_count as i64 {
+            let tx_hash = hash(b"tx", number, tx_index);
+            let sender = rng.below(SENDERS);
+            let from_addr = address(b"sender", sender);
+            let nonce = nonces[sender as usize];
+            nonces[sender as usize] += 1;
+            let gas_price = base_fee + 1_000_000_000 + rng.below(2_000_000_000);
+            let mut logs_bloom = [0u8; 256];
+
+            let tx = if rng.unit() < spec.call_share {
+                let status = i64::from(rng.below(50) != 0);
+                let logs = if status == 1 { rng.poisson(spec.logs_per_call) } else { 0 };
+                let first = pick(&mut rng);
+                let target = first.contract(&mut rng);
+                let holder = address(b"holder", rng.below(HOLDERS));
+                let amount = rng.amount();
+
+                for i in 0..logs {
+                    let event = if i == 0 { first } else { pick(&mut rng) };
+                    let emitter = if i == 0 { target.clone() } else { event.contract(&mut rng) };
+                    let amount = if i == 0 { amount } else { rng.amount() };
+                    let (topics, data) = encode(event, &from_addr, &holder, amount, &mut rng);
+                    bloom::accrue(&mut logs_bloom, &hex::decode(&emitter)?);
+                    for topic in topics.iter().flatten() {
+                        bloom::accrue(&mut logs_bloom, &hex::decode(topic)?);
+                    }
+                    writer.push_log(LogRow {
+                        block_number: number,
+                        tx_hash: tx_hash.clone(),
+                        log_index,
+                        address: emitter,
+                        topics,
+                        data,
+                    })?;
+                    log_index += 1;
+                }
+
+                let mut input = keccak256(first.method())[..4].to_vec();
+                input.extend(hex::decode(word(&holder))?);
+                input.extend(amount.to_be_bytes::<32>());
+                let value = if first == Event::Deposit { amount } else { U256::ZERO };
+                TxRow {
+                    hash: tx_hash,
+                    block_number: number,
+                    tx_index,
+                    from_addr,
+                    to_addr: Some(target),
+                    value: value.to_string(),
+                    gas_used: 30_000 + 12_000 * logs as i64 + rng.below(20_000) as i64,
+                    gas_price: gas_price.to_string(),
+                    input,
+                    status,
+                    logs_bloom: Some(logs_bloom.to_vec()),
+                    nonce,
+                }
+            } else {
+                TxRow {
+                    hash: tx_hash,
+                    block_number: number,
+                    tx_index,
+                    from_addr,
+                    to_addr: Some(address(b"holder", rng.below(HOLDERS))),
+                    value: rng.amount().to_string(),
+                    gas_used: 21_000,
+                    gas_price: gas_price.to_string(),
+                    input: Vec::new(),
+                    status: 1,
+                    logs_bloom: Some(logs_bloom.to_vec()),
+                    nonce,
+                }
+            };
+            gas_used += tx.gas_used;
+            writer.push_tx(tx)?;
+        }
+
+        let block_hash = hash(b"block", number, 0);
+        writer.push_block(BlockRow {
+            number,
+            hash: block_hash.clone(),
+            parent_hash: std::mem::replace(&mut parent_hash, block_hash),
+            timestamp: 1_700_000_000 + 12 * (number - spec.start_block),
+            gas_used,
+            // Raised for very full blocks, so they stay valid.
+            gas_limit: gas_used.max(30_000_000),
+            base_fee: Some(base_fee.to_string()),
+            tx_count: tx_count as i64,
+            miner: Some(address(b"builder", number as u64 % BUILDERS)),
+        })?;
+        pb.inc(1);
+    }
+    pb.finish_with_message("blocks ✓");
+
+    let end = spec.start_block + spec.blocks as i64 - 1;
+    writer.finish(BlockRange { start: spec.start_block, end })
+}
+
+/// Topics and data of one `event` in a transaction from `from` to `holder`.
+fn encode(
+    event: Event,
+    from: &str,
+    holder: &str,
+    amount: U256,
+    rng: &mut Rng,
+) -> ([Option<String>; 4], Vec<u8>) {
+    let topic0 = Some(decode::topic(event.signature()));
+    let words =
+        |values: &[U256]| -> Vec<u8> { values.iter().flat_map(|v| v.to_be_bytes::<32>()).collect() };
+    match event {
+        Event::Transfer => ([topic0, Some(word(from)), Some(word(holder)), None], words(&[amount])),
+        Event::Approval => {
+            let spender = address(b"pair", rng.below(PAIRS));
+            ([topic0, Some(word(from)), Some(word(&spender)), None], words(&[amount]))
+        }
+        Event::Swap => {
+            let router = address(b"router", 0);
+            let out = rng.amount();
+            let amounts = if rng.below(2) == 0 {
+                [amount, U256::ZERO, U256::ZERO, out]
+            } else {
+                [U256::ZERO, amount, out, U256::ZERO]
+            };
+            ([topic0, Some(word(&router)), Some(word(holder)), None], words(&amounts))
+        }
+        Event::Sync => {
+            let reserves = [rng.amount() * U256::from(1000), rng.amount() * U256::from(1000)];
+            ([topic0, None, None, None], words(&reserves))
+        }
+        Event::Deposit | Event::Withdrawal => {
+            ([topic0, Some(word(from)), None, None], words(&[amount]))
+        }
+    }
+}
+
+/// A 32-byte hash standing in for a block or transaction hash.
+fn hash(kind: &[u8], number: i64, index: i64) -> String {
+    let preimage = [kind, &number.to_be_bytes(), &index.to_be_bytes()].concat();
+    format!("0x{}", hex::encode(keccak256(preimage)))
+}
+
+/// The `n`th address of a kind (sender, holder, token, pair).
+fn address(kind: &[u8], n: u64) -> String {
+    let preimage = [kind, &n.to_be_bytes()].concat();
+    format!("0x{}", hex::encode(&keccak256(preimage)[12..]))
+}
+
+/// An address as an indexed topic, left-padded to 32 bytes.
+fn word(address: &str) -> String {
+    format!("0x{:0>64}", &address[2..])
+}
+
+/// SplitMix64: small, fast, and the same sequence everywhere.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    /// Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Poisson-distributed with the given mean (Knuth's method, in steps
+    /// small enough that e^-step doesn't underflow).
+    fn poisson(&mut self, mean: f64) -> u64 {
+        let mut n = 0;
+        let mut left = mean;
+        while left > 0.0 {
+            let step = left.min(500.0);
+            left -= step;
+            let limit = (-step).exp();
+            let mut p = self.unit();
+            while p > limit {
+                n += 1;
+                p *= self.unit();
+            }
+        }
+        n
+    }
+
+    /// A token amount or wei value, up to about 1.8e25.
+    fn amount(&mut self) -> U256 {
+        U256::from(self.next()) * U256::from(1_000_000)
+    }
+}
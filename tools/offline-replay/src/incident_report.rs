@@ -0,0 +1,513 @@
+//! `export report` — a self-contained HTML page of what happened in a block
+//! range, for readers without any tooling: a browser opens it offline, with
+//! no scripts and nothing fetched.
+//!
+//! The page has
+//!
+//!   summary         the range's blocks, times, transactions and logs
+//!   addresses       those of `--addresses`, with their names and activity
+//!   timeline        transactions and logs in block order, each log with its
+//!                   event name and, if a `decode` table holds it, its
+//!                   decoded fields
+//!   value flows     ETH and ERC-20 amounts moved between two addresses (see
+//!                   export.rs), largest transfer counts first
+//!   counterparties  the addresses that moved value to or from those of
+//!                   `--addresses`, most transfers first
+//!
+//! With `--addresses` (a labels CSV or JSON file, see labels.rs, or a text
+//! file of one address per line with an optional name after it) the timeline
+//! keeps the transactions from or to them and the logs they emit or are a
+//! topic of, and the flows those with them on either end; without it, the
+//! whole range. Names come from that file, then `labels` and `ens_names`.
+//! Amounts are in base units. Decoded fields only show for tables already
+//! decoded, so run `decode` first to have them.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::{hex, Address};
+use clap::Args;
+use eyre::{eyre, Context, Result};
+use rusqlite::{params, Connection};
+use tracing::info;
+
+use crate::normalize::{read_amount, read_key, Normalize};
+use crate::protocols::Registry;
+use crate::{decode, export, labels, open_existing, query, views};
+
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Output HTML file
+    #[arg(short, long)]
+    out: PathBuf,
+
+    /// Blocks to report on, as A..B [default: every block loaded]
+    #[arg(long, value_parser = parse_range)]
+    block_range: Option<(i64, i64)>,
+
+    /// Addresses of interest: a labels CSV/JSON file, or one address per line
+    #[arg(long)]
+    addresses: Option<PathBuf>,
+
+    /// Timeline entries to show at most
+    #[arg(long, default_value_t = 2000)]
+    max_events: usize,
+
+    /// Value flows and counterparties to show at most
+    #[arg(long, default_value_t = 50)]
+    top: usize,
+}
+
+/// A timeline entry: a transaction, or one of its logs.
+struct Entry {
+    block_number: i64,
+    tx_index: i64,
+    /// -1 for the transaction itself.
+    log_index: i64,
+    tx_hash: String,
+    kind: String,
+    address: String,
+    details: String,
+}
+
+fn parse_range(s: &str) -> Result<(i64, i64), String> {
+    let (a, b) = s.split_once("..").ok_or_else(|| format!("expected A..B, got {s:?}"))?;
+    let block = |v: &str| v.trim().parse::<i64>().map_err(|_| format!("{v:?} is not a block"));
+    let (a, b) = (block(a)?, block(b)?);
+    if a > b {
+        return Err(format!("{s}: the range ends before it starts"));
+    }
+    Ok((a, b))
+}
+
+pub fn run(args: ReportArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    decode::erc20_transfers(&conn)?;
+    let norm = Normalize::from_meta(&conn)?;
+    let (start, end) = match args.block_range {
+        Some(range) => range,
+        None => conn.query_row("SELECT MIN(number), MAX(number) FROM blocks", [], |r| {
+            Ok((r.get::<_, Option<i64>>(0)?.unwrap_or(0), r.get::<_, Option<i64>>(1)?.unwrap_or(0)))
+        })?,
+    };
+
+    let mut names = export::names(&conn)?;
+    let interest = match &args.addresses {
+        Some(path) => {
+            let addresses =
+                addresses(path).with_context(|| format!("Reading {}", path.display()))?;
+            let set: Vec<String> = addresses.iter().map(|(a, _)| a.clone()).collect();
+            names.extend(addresses.into_iter().filter_map(|(a, n)| Some((a, n?))));
+            set
+        }
+        None => Vec::new(),
+    };
+    let wanted: HashSet<&str> = interest.iter().map(String::as_str).collect();
+    let of_interest = |address: &str| wanted.is_empty() || wanted.contains(address);
+
+    let (timeline, truncated) = timeline(&conn, &norm, start, end, &of_interest, args.max_events)?;
+    let mut flows: Vec<_> = export::edges(&conn, &[], start, end)?
+        .into_iter()
+        .filter(|((from, to, _), _)| of_interest(from) || of_interest(to))
+        .collect();
+    flows.sort_by(|(a_key, a), (b_key, b)| b.count.cmp(&a.count).then_with(|| a_key.cmp(b_key)));
+    let counterparties = counterparties(&flows, &wanted);
+
+    let mut html = String::new();
+    let title = format!("Blocks {start}..{end}");
+    write!(html, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n")?;
+    writeln!(html, "<title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>", escape(&title))?;
+    writeln!(html, "<h1>{}</h1>", escape(&title))?;
+    summary(&mut html, &conn, &args.db, start, end)?;
+
+    let name = |address: &str| names.get(address).map_or("", String::as_str).to_string();
+    if !interest.is_empty() {
+        let mut rows = Vec::new();
+        for address in &interest {
+            let (sent, received): (i64, i64) = conn.query_row(
+                "SELECT COUNT(*) FILTER (WHERE from_addr = ?1), COUNT(*) FILTER (WHERE to_addr = ?1)
+                 FROM transactions WHERE block_number BETWEEN ?2 AND ?3",
+                params![norm.key_value("from_addr", address)?, start, end],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )?;
+            let emitted: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM logs WHERE address = ?1 AND block_number BETWEEN ?2 AND ?3",
+                params![norm.key_value("address", address)?, start, end],
+                |r| r.get(0),
+            )?;
+            rows.push(vec![
+                code(address),
+                escape(&name(address)),
+                sent.to_string(),
+                received.to_string(),
+                emitted.to_string(),
+            ]);
+        }
+        section(
+            &mut html,
+            "Addresses",
+            &["address", "name", "transactions sent", "received", "logs emitted"],
+            &rows,
+        )?;
+    }
+
+    let rows: Vec<Vec<String>> = timeline
+        .iter()
+        .map(|e| {
+            vec![
+                e.block_number.to_string(),
+                if e.log_index < 0 { String::new() } else { e.log_index.to_string() },
+                code(&e.tx_hash),
+                escape(&e.kind),
+                address_cell(&e.address, &name(&e.address)),
+                escape(&e.details),
+            ]
+        })
+        .collect();
+    section(
+        &mut html,
+        "Timeline",
+        &["block", "log", "transaction", "what", "address", "details"],
+        &rows,
+    )?;
+    if truncated {
+        writeln!(
+            html,
+            "<p class=\"note\">Only the first {} entries are shown.</p>",
+            args.max_events
+        )?;
+    }
+
+    let rows: Vec<Vec<String>> = flows
+        .iter()
+        .take(args.top)
+        .map(|((from, to, asset), e)| {
+            vec![
+                address_cell(from, &name(from)),
+                address_cell(to, &name(to)),
+                asset_cell(asset, &name(asset)),
+                e.amount.to_string(),
+                e.count.to_string(),
+            ]
+        })
+        .collect();
+    section(&mut html, "Value flows", &["from", "to", "asset", "amount", "transfers"], &rows)?;
+
+    let rows: Vec<Vec<String>> = counterparties
+        .iter()
+        .take(args.top)
+        .map(|(address, c)| {
+            vec![
+                address_cell(address, &name(address)),
+                c.sent.to_string(),
+                c.received.to_string(),
+                c.assets.len().to_string(),
+            ]
+        })
+        .collect();
+    section(
+        &mut html,
+        "Top counterparties",
+        &["address", "transfers to it", "transfers from it", "assets"],
+        &rows,
+    )?;
+    writeln!(html, "</body>\n</html>")?;
+
+    export::write_file(&args.out, |w| Ok(w.write_all(html.as_bytes())?))?;
+    info!(
+        out = %args.out.display(),
+        start,
+        end,
+        timeline = timeline.len(),
+        flows = flows.len(),
+        "wrote report"
+    );
+    Ok(())
+}
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; font-size: 0.9em; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; vertical-align: top; }
+th { background: #f0f0f0; }
+tr:nth-child(even) td { background: #fafafa; }
+code { font-size: 0.95em; }
+.name { color: #555; }
+.note { color: #777; font-style: italic; }
+";
+
+/// The addresses of an `--addresses` file, lowercase, with their names.
+pub(crate) fn addresses(path: &Path) -> Result<Vec<(String, Option<String>)>> {
+    let parse = |s: &str| -> Result<String> {
+        let addr: Address = s.trim().parse().map_err(|_| eyre!("{s:?} is not an address"))?;
+        Ok(format!("0x{}", hex::encode(addr)))
+    };
+    if matches!(path.extension().and_then(|e| e.to_str()), Some("csv" | "json")) {
+        return labels::read(path)?
+            .into_iter()
+            .map(|l| Ok((parse(&l.address)?, Some(l.name))))
+            .collect();
+    }
+    let text = std::fs::read_to_string(path)?;
+    let mut addresses = Vec::new();
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let (address, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let name = name.trim();
+        addresses.push((parse(address)?, (!name.is_empty()).then(|| name.to_string())));
+    }
+    Ok(addresses)
+}
+
+fn summary(html: &mut String, conn: &Connection, db: &Path, start: i64, end: i64) -> Result<()> {
+    let (blocks, first, last): (i64, Option<i64>, Option<i64>) = conn.query_row(
+        "SELECT COUNT(*), MIN(timestamp), MAX(timestamp) FROM blocks
+         WHERE number BETWEEN ?1 AND ?2 AND is_canonical = 1",
+        [start, end],
+        |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+    )?;
+    let count = |table: &str| -> Result<i64> {
+        Ok(conn.query_row(
+            &format!("SELECT COUNT(*) FROM {table} WHERE block_number BETWEEN ?1 AND ?2"),
+            [start, end],
+            |r| r.get(0),
+        )?)
+    };
+    let time = |t: Option<i64>| -> Result<String> {
+        Ok(match t {
+            Some(t) => conn.query_row("SELECT datetime(?1, 'unixepoch')", [t], |r| r.get(0))?,
+            None => String::new(),
+        })
+    };
+    let rows = vec![
+        vec!["database".into(), code(&db.display().to_string())],
+        vec!["blocks".into(), format!("{start}..{end} ({blocks} loaded)")],
+        vec!["from".into(), format!("{} UTC", time(first)?)],
+        vec!["to".into(), format!("{} UTC", time(last)?)],
+        vec!["transactions".into(), count("transactions")?.to_string()],
+        vec!["logs".into(), count("logs")?.to_string()],
+        vec!["ERC-20 transfers".into(), count("erc20_transfers")?.to_string()],
+    ];
+    section(html, "Summary", &["", ""], &rows)
+}
+
+/// Transactions and logs of interest in block order, up to `max` of them,
+/// and whether there were more.
+fn timeline(
+    conn: &Connection,
+    norm: &Normalize,
+    start: i64,
+    end: i64,
+    of_interest: &dyn Fn(&str) -> bool,
+    max: usize,
+) -> Result<(Vec<Entry>, bool)> {
+    let functions = views::function_names(conn)?;
+    let events = views::event_names(conn)?;
+    let decoded = decoded(conn, start, end)?;
+
+    let mut entries = Vec::new();
+    let mut tx_index = HashMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT hash, block_number, tx_index, from_addr, to_addr, value, input, status
+         FROM transactions WHERE block_number BETWEEN ?1 AND ?2",
+    )?;
+    let mut rows = stmt.query([start, end])?;
+    while let Some(r) = rows.next()? {
+        let hash = read_key(r.get_ref(0)?)?.unwrap_or_default().to_ascii_lowercase();
+        let index: i64 = r.get(2)?;
+        tx_index.insert(hash.clone(), index);
+        let from = read_key(r.get_ref(3)?)?.unwrap_or_default().to_ascii_lowercase();
+        let to = read_key(r.get_ref(4)?)?.map(|a| a.to_ascii_lowercase());
+        if !of_interest(&from) && !to.as_deref().is_some_and(of_interest) {
+            continue;
+        }
+        let input = norm.read_blob(r.get(6)?)?;
+        let call = match (&to, input.get(..4)) {
+            (None, _) => "create".to_string(),
+            (Some(_), None) => "transfer".to_string(),
+            (Some(_), Some(selector)) => {
+                let selector = format!("0x{}", hex::encode(selector));
+                functions.get(&selector).cloned().unwrap_or(selector)
+            }
+        };
+        let value = read_amount(r.get_ref(5)?)?;
+        let mut details = format!("from {from}");
+        if !value.is_zero() {
+            write!(details, ", {value} wei")?;
+        }
+        if r.get::<_, i64>(7)? == 0 {
+            details.push_str(", failed");
+        }
+        entries.push(Entry {
+            block_number: r.get(1)?,
+            tx_index: index,
+            log_index: -1,
+            tx_hash: hash,
+            kind: format!("tx {call}"),
+            address: to.unwrap_or_default(),
+            details,
+        });
+    }
+    drop(rows);
+
+    let mut stmt = conn.prepare(
+        "SELECT id, block_number, tx_hash, log_index, address, topic0, topic1, topic2, topic3
+         FROM logs WHERE block_number BETWEEN ?1 AND ?2",
+    )?;
+    let mut rows = stmt.query([start, end])?;
+    while let Some(r) = rows.next()? {
+        let address = read_key(r.get_ref(4)?)?.unwrap_or_default().to_ascii_lowercase();
+        let topics: Vec<String> = (6..9)
+            .filter_map(|i| read_key(r.get_ref(i).ok()?).ok().flatten())
+            .filter_map(|t| decode::topic_address(&t.to_ascii_lowercase()))
+            .collect();
+        if !of_interest(&address) && !topics.iter().any(|t| of_interest(t)) {
+            continue;
+        }
+        let id: i64 = r.get(0)?;
+        let topic0 = read_key(r.get_ref(5)?)?.map(|t| t.to_ascii_lowercase());
+        let event = topic0.as_ref().and_then(|t| events.get(t)).cloned();
+        let (kind, details) = match decoded.get(&id) {
+            Some((table, fields)) => (event.unwrap_or_else(|| table.clone()), fields.clone()),
+            None => (event.unwrap_or_else(|| topic0.unwrap_or_default()), String::new()),
+        };
+        let tx_hash = read_key(r.get_ref(2)?)?.unwrap_or_default().to_ascii_lowercase();
+        entries.push(Entry {
+            block_number: r.get(1)?,
+            tx_index: tx_index.get(&tx_hash).copied().unwrap_or(i64::MAX),
+            log_index: r.get(3)?,
+            tx_hash,
+            kind,
+            address,
+            details,
+        });
+    }
+    entries.sort_by_key(|e| (e.block_number, e.tx_index, e.log_index));
+    let truncated = entries.len() > max;
+    entries.truncate(max);
+    Ok((entries, truncated))
+}
+
+/// The decoded fields of the logs in the range, by log id, from the tables
+/// of the built-in decoders and of erc20_transfers that exist: the table,
+/// and its columns after the common ones as `name=value, ...`.
+fn decoded(conn: &Connection, start: i64, end: i64) -> Result<HashMap<i64, (String, String)>> {
+    let registry = Registry::builtin()?;
+    let mut tables: Vec<(String, Vec<String>)> = registry
+        .select(&[])?
+        .iter()
+        .map(|d| (d.table().to_string(), d.columns().iter().map(|c| c.name.clone()).collect()))
+        .collect();
+    let transfer = ["token", "from_addr", "to_addr", "amount"];
+    tables.push(("erc20_transfers".into(), transfer.map(String::from).to_vec()));
+
+    let mut decoded = HashMap::new();
+    for (table, columns) in tables {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [&table],
+            |r| r.get(0),
+        )?;
+        if !exists {
+            continue;
+        }
+        let mut stmt = conn.prepare(&format!(
+            "SELECT log_id, {} FROM {table} WHERE block_number BETWEEN ?1 AND ?2",
+            columns.iter().map(|c| format!("\"{c}\"")).collect::<Vec<_>>().join(", ")
+        ))?;
+        let mut rows = stmt.query([start, end])?;
+        while let Some(r) = rows.next()? {
+            let mut fields = Vec::new();
+            for (i, column) in columns.iter().enumerate() {
+                fields.push(format!("{column}={}", query::text(r.get_ref(i + 1)?)));
+            }
+            decoded.insert(r.get(0)?, (table.clone(), fields.join(", ")));
+        }
+    }
+    Ok(decoded)
+}
+
+#[derive(Default)]
+struct Counterparty {
+    /// Transfers from an address of interest to it.
+    sent: u64,
+    received: u64,
+    assets: HashSet<String>,
+}
+
+/// The other ends of `flows` with an address of interest, by transfers; with
+/// none, every address.
+fn counterparties(
+    flows: &[((String, String, String), export::Edge)],
+    wanted: &HashSet<&str>,
+) -> Vec<(String, Counterparty)> {
+    let mut counterparties: BTreeMap<String, Counterparty> = BTreeMap::new();
+    for ((from, to, asset), e) in flows {
+        for (other, ours, incoming) in [(to, from, true), (from, to, false)] {
+            if !wanted.is_empty()
+                && (!wanted.contains(ours.as_str()) || wanted.contains(other.as_str()))
+            {
+                continue;
+            }
+            let c = counterparties.entry(other.clone()).or_default();
+            if incoming {
+                c.sent += e.count;
+            } else {
+                c.received += e.count;
+            }
+            c.assets.insert(asset.clone());
+        }
+    }
+    let mut ranked: Vec<_> = counterparties.into_iter().collect();
+    ranked.sort_by(|(a_key, a), (b_key, b)| {
+        (b.sent + b.received).cmp(&(a.sent + a.received)).then_with(|| a_key.cmp(b_key))
+    });
+    ranked
+}
+
+/// A heading and a table of already escaped cells.
+fn section(html: &mut String, title: &str, columns: &[&str], rows: &[Vec<String>]) -> Result<()> {
+    writeln!(html, "<h2>{}</h2>", escape(title))?;
+    if rows.is_empty() {
+        writeln!(html, "<p class=\"note\">None.</p>")?;
+        return Ok(());
+    }
+    writeln!(html, "<table>")?;
+    if columns.iter().any(|c| !c.is_empty()) {
+        let header: String = columns.iter().map(|c| format!("<th>{}</th>", escape(c))).collect();
+        writeln!(html, "<tr>{header}</tr>")?;
+    }
+    for row in rows {
+        let cells: String = row.iter().map(|c| format!("<td>{c}</td>")).collect();
+        writeln!(html, "<tr>{cells}</tr>")?;
+    }
+    writeln!(html, "</table>")?;
+    Ok(())
+}
+
+fn address_cell(address: &str, name: &str) -> String {
+    if name.is_empty() {
+        code(address)
+    } else {
+        format!("{} <span class=\"name\">{}</span>", code(address), escape(name))
+    }
+}
+
+fn asset_cell(asset: &str, name: &str) -> String {
+    if asset == "eth" {
+        "ETH".to_string()
+    } else {
+        address_cell(asset, name)
+    }
+}
+
+fn code(s: &str) -> String {
+    format!("<code>{}</code>", escape(s))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,83 @@
+//! `--index 'logs(address, topic0, block_number)'` and `--no-default-index`
+//! — which secondary indexes the output database gets.
+//!
+//! Every database gets [`DEFAULT_INDEXES`] unless `--no-default-index` is
+//! given; each one costs load time and disk space, which a database that is
+//! only ever scanned, or queried one way, doesn't need to pay. `--index`
+//! adds an index of its own over one or more columns of blocks,
+//! transactions or logs, named `idx_<table>_<columns>`; a composite index
+//! serves queries that filter on its leading columns, such as an event of
+//! one contract over a block range.
+//!
+//! Indexes are created with the schema, before rows are loaded, and never
+//! dropped: appending without `--no-default-index` adds the defaults to a
+//! database that lacks them, and an index that exists is left alone. Primary
+//! keys and the log key (`idx_log_key`) exist either way.
+
+use eyre::{Context, Result};
+use rusqlite::Connection;
+
+use crate::schema::{Table, DEFAULT_INDEXES};
+
+/// One `--index TABLE(COL, ...)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Index {
+    table: Table,
+    columns: Vec<String>,
+}
+
+impl Index {
+    pub fn name(&self) -> String {
+        format!("idx_{}_{}", self.table.name(), self.columns.join("_"))
+    }
+
+    fn sql(&self) -> String {
+        format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {}({});",
+            self.name(),
+            self.table.name(),
+            self.columns.join(", ")
+        )
+    }
+}
+
+/// Parse `--index TABLE(COL, ...)`.
+pub fn parse(s: &str) -> Result<Index, String> {
+    let (table, columns) = s
+        .trim()
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or_else(|| format!("expected TABLE(COLUMN, ...), got {s:?}"))?;
+    let table = Table::ALL
+        .into_iter()
+        .find(|t| t.name() == table.trim())
+        .ok_or_else(|| format!("unknown table {:?} in {s:?}", table.trim()))?;
+    let columns: Vec<String> = columns.split(',').map(|c| c.trim().to_string()).collect();
+    for column in &columns {
+        if column.is_empty() || !column.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("{column:?} is not a column name, in {s:?}"));
+        }
+    }
+    Ok(Index { table, columns })
+}
+
+/// The indexes a load asks for.
+#[derive(Clone, Debug, Default)]
+pub struct Indexes {
+    pub no_default: bool,
+    pub extra: Vec<Index>,
+}
+
+impl Indexes {
+    /// Create the indexes `conn` doesn't have yet.
+    pub fn create(&self, conn: &Connection) -> Result<()> {
+        if !self.no_default {
+            conn.execute_batch(DEFAULT_INDEXES)?;
+        }
+        for index in &self.extra {
+            conn.execute_batch(&index.sql())
+                .with_context(|| format!("Cannot create index {}", index.name()))?;
+        }
+        Ok(())
+    }
+}
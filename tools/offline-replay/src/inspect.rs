@@ -0,0 +1,348 @@
+//! `inspect` — each Parquet file's schema next to the one the loader
+//! expects, for dataset producers to fix before anyone attempts a load.
+//!
+//! For every file of every table it prints the file's columns (Arrow type,
+//! whether the column is nullable, and its null count from the row-group
+//! statistics) against the loader's, with one verdict per column:
+//!
+//!   ok             same type, and nulls only where the loader allows them
+//!   read as T      another type the loader converts without loss (compat.rs)
+//!   nullable       required by the loader and nullable in the file, though
+//!                  without nulls (or without statistics to say)
+//!   ! type         a type the loader can't read: the load exits with code 4
+//!   ! nulls        nulls in a column the loader requires
+//!   ! missing      a required column the file doesn't have: exit code 3
+//!   missing        an optional column the file doesn't have; loads as NULL
+//!   extra          a column the loader doesn't read
+//!
+//! Then, per table, the columns whose type or nullability differs between
+//! its files, the usual sign of part files written by two versions of an
+//! exporter. Only footers are read. `--map` renames columns as the loader
+//! would; `--json` prints the same report as one JSON document. The command
+//! exits non-zero if any `!` verdict was found.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use arrow::datatypes::Schema;
+use clap::Args;
+use eyre::Result;
+use serde::Serialize;
+
+use crate::chain::Chain;
+use crate::compat::{self, ColumnMap};
+use crate::dry_run::null_count;
+use crate::schema::Table;
+use crate::source::DataSource;
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    /// Dataset directory to inspect (or a URL with the `remote` feature)
+    #[arg(short, long, default_value = ".")]
+    data: PathBuf,
+
+    /// Rename a file column before comparing, as the loader's --map does
+    #[arg(long, value_name = "[TABLE.]FROM=TO", value_parser = compat::parse)]
+    map: Vec<compat::Rename>,
+
+    /// Compare against the loader's columns for this chain, as --chain does
+    #[arg(long, value_enum, default_value_t = Chain::Ethereum)]
+    chain: Chain,
+
+    /// Print the report as one JSON document
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Report {
+    files: Vec<FileReport>,
+    drift: Vec<Drift>,
+    problems: usize,
+}
+
+#[derive(Serialize)]
+struct FileReport {
+    table: &'static str,
+    file: String,
+    rows: i64,
+    row_groups: usize,
+    columns: Vec<ColumnReport>,
+}
+
+#[derive(Serialize)]
+struct ColumnReport {
+    /// The loader's name, after --map.
+    name: String,
+    /// The file's name, where --map renamed it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_name: Option<String>,
+    /// The file's Arrow type, if it has the column.
+    #[serde(rename = "type")]
+    data_type: Option<String>,
+    nullable: Option<bool>,
+    nulls: Option<u64>,
+    expected_type: Option<String>,
+    required: Option<bool>,
+    verdict: Verdict,
+}
+
+#[derive(Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Verdict {
+    Ok,
+    Coerced,
+    Nullable,
+    WrongType,
+    Nulls,
+    MissingRequired,
+    Missing,
+    Extra,
+}
+
+impl Verdict {
+    fn is_problem(self) -> bool {
+        matches!(self, Verdict::WrongType | Verdict::Nulls | Verdict::MissingRequired)
+    }
+}
+
+/// A column of one table whose type or nullability differs between files.
+#[derive(Serialize)]
+struct Drift {
+    table: &'static str,
+    column: String,
+    /// Each variant ("Int64, nullable", or "absent") and the files with it.
+    variants: BTreeMap<String, Vec<String>>,
+}
+
+pub fn run(args: InspectArgs) -> Result<()> {
+    let source = DataSource::new(&args.data)?;
+    let map = ColumnMap::new(args.map, args.chain);
+    let mut report = Report { files: Vec::new(), drift: Vec::new(), problems: 0 };
+    for table in Table::ALL {
+        let start = report.files.len();
+        for file in source.list(table.name())? {
+            let meta = source.meta(&file)?;
+            let schema = map.rename_schema(table, &meta.schema)?;
+            let columns = columns(table, &schema, &meta.schema, &map, &meta.metadata);
+            report.problems += columns.iter().filter(|c| c.verdict.is_problem()).count();
+            report.files.push(FileReport {
+                table: table.name(),
+                file: source.display(&file),
+                rows: meta.metadata.file_metadata().num_rows(),
+                row_groups: meta.metadata.num_row_groups(),
+                columns,
+            });
+        }
+        report.drift.extend(drift(table, &report.files[start..]));
+    }
+
+    let mut out = std::io::stdout().lock();
+    if args.json {
+        serde_json::to_writer_pretty(&mut out, &report)?;
+        writeln!(out)?;
+    } else {
+        print(&report, &mut out)?;
+    }
+    if report.problems > 0 {
+        eyre::bail!("{} problem(s) found", report.problems);
+    }
+    Ok(())
+}
+
+/// The loader's columns of `table` against those of a file whose schema is
+/// `schema` after --map (`original` before it), then the file's extra ones.
+fn columns(
+    table: Table,
+    schema: &Schema,
+    original: &Schema,
+    map: &ColumnMap,
+    metadata: &parquet::file::metadata::ParquetMetaData,
+) -> Vec<ColumnReport> {
+    let mut columns = Vec::new();
+    for spec in map.columns(table) {
+        let expected = spec.kind.data_type();
+        let file_name = map.file_name(table, spec.name, original);
+        let mut column = ColumnReport {
+            name: spec.name.to_string(),
+            file_name: (file_name != spec.name).then(|| file_name.to_string()),
+            data_type: None,
+            nullable: None,
+            nulls: None,
+            expected_type: Some(expected.to_string()),
+            required: Some(!spec.nullable),
+            verdict: if spec.nullable { Verdict::Missing } else { Verdict::MissingRequired },
+        };
+        if let Ok(field) = schema.field_with_name(spec.name) {
+            let nulls = null_count(metadata, file_name);
+            column.data_type = Some(field.data_type().to_string());
+            column.nullable = Some(field.is_nullable());
+            column.nulls = nulls;
+            column.verdict =
+                if *field.data_type() != expected && !compat::coercible(spec, field.data_type()) {
+                    Verdict::WrongType
+                } else if !spec.nullable && nulls.is_some_and(|n| n > 0) {
+                    Verdict::Nulls
+                } else if *field.data_type() != expected {
+                    Verdict::Coerced
+                } else if !spec.nullable && field.is_nullable() {
+                    Verdict::Nullable
+                } else {
+                    Verdict::Ok
+                };
+        }
+        columns.push(column);
+    }
+    for field in schema.fields() {
+        if map.columns(table).any(|c| c.name == field.name()) {
+            continue;
+        }
+        columns.push(ColumnReport {
+            name: field.name().clone(),
+            file_name: None,
+            data_type: Some(field.data_type().to_string()),
+            nullable: Some(field.is_nullable()),
+            nulls: null_count(metadata, field.name()),
+            expected_type: None,
+            required: None,
+            verdict: Verdict::Extra,
+        });
+    }
+    columns
+}
+
+/// The columns of `files` (all of one table) that don't look alike in all
+/// of them.
+fn drift(table: Table, files: &[FileReport]) -> Vec<Drift> {
+    let mut seen: BTreeMap<&str, BTreeMap<String, Vec<String>>> = BTreeMap::new();
+    for file in files {
+        for column in &file.columns {
+            let variant = match (&column.data_type, column.nullable) {
+                (Some(t), Some(true)) => format!("{t}, nullable"),
+                (Some(t), _) => t.clone(),
+                (None, _) => "absent".to_string(),
+            };
+            seen.entry(&column.name)
+                .or_default()
+                .entry(variant)
+                .or_default()
+                .push(file.file.clone());
+        }
+    }
+    seen.into_iter()
+        .filter_map(|(column, mut variants)| {
+            // A column only some files have is absent from the others.
+            let present: usize = variants.values().map(Vec::len).sum();
+            if present < files.len() {
+                let absent = files
+                    .iter()
+                    .filter(|f| !f.columns.iter().any(|c| c.name == column))
+                    .map(|f| f.file.clone());
+                variants.entry("absent".to_string()).or_default().extend(absent);
+            }
+            (variants.len() > 1).then(|| Drift {
+                table: table.name(),
+                column: column.to_string(),
+                variants,
+            })
+        })
+        .collect()
+}
+
+fn print(report: &Report, out: &mut dyn Write) -> Result<()> {
+    for file in &report.files {
+        writeln!(out, "{}  {} rows, {} row group(s)", file.file, file.rows, file.row_groups)?;
+        let names: Vec<String> = file
+            .columns
+            .iter()
+            .map(|c| match &c.file_name {
+                Some(from) => format!("{from} -> {}", c.name),
+                None => c.name.clone(),
+            })
+            .collect();
+        let types: Vec<String> = file
+            .columns
+            .iter()
+            .map(|c| match (&c.data_type, c.nullable) {
+                (Some(t), Some(true)) => format!("{t}?"),
+                (Some(t), _) => t.clone(),
+                (None, _) => "-".to_string(),
+            })
+            .collect();
+        let name_width = names.iter().map(String::len).max().unwrap_or(0);
+        let nulls: Vec<String> = file
+            .columns
+            .iter()
+            .map(|c| match (c.data_type.is_some(), c.nulls) {
+                (false, _) => String::new(),
+                (true, Some(n)) => format!("{n} null(s)"),
+                (true, None) => "nulls unknown".to_string(),
+            })
+            .collect();
+        let type_width = types.iter().map(String::len).max().unwrap_or(0);
+        let nulls_width = nulls.iter().map(String::len).max().unwrap_or(0);
+        for (((c, name), data_type), nulls) in
+            file.columns.iter().zip(&names).zip(&types).zip(&nulls)
+        {
+            writeln!(
+                out,
+                "  {name:name_width$}  {data_type:type_width$}  {nulls:nulls_width$}  {}",
+                verdict(c)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    if !report.drift.is_empty() {
+        writeln!(out, "Schema drift between part files:")?;
+        for d in &report.drift {
+            writeln!(out, "  {}.{}", d.table, d.column)?;
+            for (variant, files) in &d.variants {
+                let more = if files.len() > 3 {
+                    format!(" and {} more", files.len() - 3)
+                } else {
+                    String::new()
+                };
+                let shown: Vec<&str> = files.iter().take(3).map(String::as_str).collect();
+                writeln!(out, "    {variant}: {}{more}", shown.join(", "))?;
+            }
+        }
+        writeln!(out)?;
+    }
+
+    let count = |v: Verdict| {
+        report.files.iter().flat_map(|f| &f.columns).filter(|c| c.verdict == v).count()
+    };
+    writeln!(
+        out,
+        "{} file(s): {} incompatible type(s), {} required column(s) missing, {} with nulls, \
+         {} converted, {} nullable but required, {} optional missing, {} extra; \
+         {} column(s) drifting",
+        report.files.len(),
+        count(Verdict::WrongType),
+        count(Verdict::MissingRequired),
+        count(Verdict::Nulls),
+        count(Verdict::Coerced),
+        count(Verdict::Nullable),
+        count(Verdict::Missing),
+        count(Verdict::Extra),
+        report.drift.len(),
+    )?;
+    Ok(())
+}
+
+fn verdict(c: &ColumnReport) -> String {
+    let expected = c.expected_type.as_deref().unwrap_or_default();
+    match c.verdict {
+        Verdict::Ok => "ok".to_string(),
+        Verdict::Coerced => format!("read as {expected}"),
+        Verdict::Nullable => "nullable, but the loader requires a value".to_string(),
+        Verdict::WrongType => format!("! type: expected {expected}"),
+        Verdict::Nulls => "! nulls: the loader requires a value".to_string(),
+        Verdict::MissingRequired => {
+            format!("! missing: a required {expected} column (--map can rename one)")
+        }
+        Verdict::Missing => format!("missing: {expected}, loads as NULL"),
+        Verdict::Extra => "extra: not read".to_string(),
+    }
+}
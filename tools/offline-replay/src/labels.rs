@@ -0,0 +1,127 @@
+//! `labels` — import human-readable address labels (exchanges, exploiters,
+//! protocol contracts) into a `labels` table.
+//!
+//! Accepted files, picked by extension:
+//!
+//!   .csv   header row with `address`, `name` and optionally `category`
+//!   .json  [{"address": ..., "name": ..., "category": ...}, ...]
+//!          or {"0xabc...": "name", ...}
+//!
+//! `--chain` also labels a chain's precompiles, system contracts and
+//! predeploys (see chain.rs), with category `system`.
+//!
+//! Addresses are stored lowercase; importing an address again replaces its
+//! label. The `--views` views join the table (from_label, to_label,
+//! address_label), and are refreshed by an import if the database has them.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::{hex, Address};
+use clap::Args;
+use eyre::{eyre, Context, Result};
+use rusqlite::params;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::chain::Chain;
+use crate::open_existing;
+use crate::schema::LABELS_SCHEMA;
+use crate::views;
+
+#[derive(Args, Debug)]
+pub struct LabelsArgs {
+    /// SQLite database to add the labels to
+    db: PathBuf,
+
+    /// CSV or JSON label files
+    #[arg(required_unless_present = "chain")]
+    files: Vec<PathBuf>,
+
+    /// Also label the system addresses of this chain
+    #[arg(long, value_enum)]
+    chain: Option<Chain>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Label {
+    pub address: String,
+    pub name: String,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonLabels {
+    List(Vec<Label>),
+    Map(BTreeMap<String, String>),
+}
+
+pub fn run(args: LabelsArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    conn.execute_batch(LABELS_SCHEMA)?;
+
+    conn.execute_batch("BEGIN;")?;
+    let mut total = 0;
+    if let Some(chain) = args.chain {
+        let mut stmt = conn.prepare_cached(
+            "INSERT OR REPLACE INTO labels (address, name, category, source)
+             VALUES (?1, ?2, 'system', ?3)",
+        )?;
+        let system = chain.system_addresses();
+        for (address, name) in &system {
+            stmt.execute(params![address, name, format!("--chain {}", chain.name())])?;
+        }
+        info!(chain = chain.name(), labels = system.len(), "labelled system addresses");
+        total += system.len();
+    }
+    for file in &args.files {
+        let labels = read(file).with_context(|| format!("Reading {}", file.display()))?;
+        let mut stmt = conn.prepare_cached(
+            "INSERT OR REPLACE INTO labels (address, name, category, source) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (i, label) in labels.iter().enumerate() {
+            let addr: Address = label.address.trim().parse().map_err(|_| {
+                eyre!("{} row {}: {:?} is not an address", file.display(), i + 1, label.address)
+            })?;
+            stmt.execute(params![
+                format!("0x{}", hex::encode(addr)),
+                label.name,
+                label.category,
+                file.file_name().map(|n| n.to_string_lossy().into_owned()),
+            ])?;
+        }
+        info!(file = %file.display(), labels = labels.len(), "imported labels");
+        total += labels.len();
+    }
+
+    views::refresh(&conn)?;
+    conn.execute_batch("COMMIT;")?;
+    info!(db = %args.db.display(), labels = total, "done");
+    Ok(())
+}
+
+/// Read a CSV or JSON label file (see the module docs for the formats).
+pub(crate) fn read(path: &Path) -> Result<Vec<Label>> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("csv") => {
+            let mut reader = csv::Reader::from_path(path)?;
+            reader
+                .deserialize()
+                .collect::<Result<_, _>>()
+                .map_err(|e| eyre!("{e}"))
+        }
+        Some("json") => {
+            let text = std::fs::read_to_string(path)?;
+            Ok(match serde_json::from_str(&text)? {
+                JsonLabels::List(labels) => labels,
+                JsonLabels::Map(map) => map
+                    .into_iter()
+                    .map(|(address, name)| Label { address, name, category: None })
+                    .collect(),
+            })
+        }
+        _ => eyre::bail!("expected a .csv or .json file"),
+    }
+}
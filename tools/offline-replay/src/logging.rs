@@ -0,0 +1,40 @@
+//! Log output via `tracing`. Logs always go to stderr so stdout stays free for
+//! data (SQL dumps with `--out -`, dry-run reports).
+//!
+//! Verbosity: `--quiet` → warnings only, default → info, `-v` → debug,
+//! `-vv` → trace. RUST_LOG, when set, overrides all of these.
+//! `--log-format json` emits one JSON object per event for log collectors.
+
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+pub fn init(verbose: u8, quiet: bool, format: LogFormat) {
+    let level = match (quiet, verbose) {
+        (true, _) => "warn",
+        (false, 0) => "info",
+        (false, 1) => "debug",
+        (false, _) => "trace",
+    };
+    // Dependencies stay at warn unless RUST_LOG asks for more.
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,offline_replay={level}")));
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false);
+    match format {
+        LogFormat::Text => builder.without_time().init(),
+        LogFormat::Json => builder.json().flatten_event(true).init(),
+    }
+}
@@ -2,24 +2,155 @@
 //!
 //! Usage:
 //!   offline-replay --data ./euler-finance --out ethereum.db
+//!   offline-replay --data ./euler-finance --dry-run
 //!
 //! The --data directory must contain:
 //!   blocks.parquet
 //!   transactions.parquet
 //!   logs.parquet
 //!
+//! Each table may instead (or additionally) be split into part files under
+//! <table>/*.parquet; `--watch` keeps appending new part files as they appear.
+//!
 //! Output: a SQLite database with the same schema, ready to query with sqlite3.
+//!
+//! A whole load job (paths, filters, pragmas) can be kept in a TOML file and
+//! run with `--config job.toml`; see config.rs for the format.
+//!
+//! L2 datasets: `--chain op-stack` also loads deposit transactions and L1
+//! fees into `transactions`, `--chain arbitrum` retryable tickets, L1 gas and
+//! L1 block numbers (see chain.rs). A preset (`--chain mainnet`, `polygon`,
+//! `bsc`, `optimism`, `arbitrum-one`, ...) also checks that the dataset is
+//! from that chain.
+//!
+//! Pipes: `--data - --table logs` reads one table's Parquet from stdin, and
+//! `--out -` writes a SQL dump to stdout instead of a database file.
+//!
+//! Subcommands:
+//!   offline-replay split --data <dir|db> --out <dir> --blocks N
+//!   offline-replay fetch --rpc <url> --start A --end B --out <dir>
+//!   offline-replay fetch --rpc <url> --chain <preset> --from-date D1 --to-date D2 --out <dir>
+//!   offline-replay sample --data <dir> --out <dir> --every N | --random N
+//!   offline-replay redact --data <dir> --out <dir> --key <secret> [--keep <addr>...]
+//!   offline-replay migrate <db>
+//!   offline-replay dedupe <db> [--log-key full|compact]
+//!   offline-replay optimize <db> [--vacuum]
+//!   offline-replay query <db> <sql> [--attach <db> [AS <alias>]...]
+//!   offline-replay shell <db>
+//!   offline-replay labels <db> <labels.csv|json>... [--chain <preset>]
+//!   offline-replay ens <db> --rpc <url> | --snapshot <file>
+//!   offline-replay export graph <db> --out <file> --format graphml|neo4j|dot
+//!   offline-replay export table <db> <table> | --query <sql> --out <file> [--format arrow|parquet]
+//!   offline-replay export report <db> --out <file> [--block-range A..B] [--addresses <file>]
+//!   offline-replay export state <db> --at-block N --addresses <file> --out <file> [--rpc <url>]
+//!   offline-replay analyze candles <db> --interval 1h [--out candles.csv]
+//!   offline-replay analyze tvl <db> --protocol euler|aave-v2|compound-v2 | --spec <toml>
+//!   offline-replay analyze balances <db> --token <addr> [--holder <addr>] [--snapshot <csv>]
+//!   offline-replay analyze holders <db> --token <addr> [--at-block N] [--opening <csv>]
+//!   offline-replay analyze eth-deltas <db>
+//!   offline-replay analyze storage <db> [--address <addr>] [--layout <addr>=<layout.json>...]
+//!   offline-replay analyze overlap <db> <other-db> [--senders] [--min-txs N]
+//!   offline-replay analyze euler <db> [--tx <hash>... | --all] [--out steps.csv]
+//!   offline-replay stats selectors|events <db> [--by count|gas] [--names]
+//!   offline-replay decode <db> [--protocol uniswap,aave,curve] [--decoders <toml>...]
+//!   offline-replay call <db> --to <addr> --sig <sig> [args...] | --data <hex> [--at-block N] [--rpc <url>]
+//!   offline-replay inspect --data <dir> [--map FROM=TO...] [--json]
+//!   offline-replay verify gas|bloom|nonces <db>
+//!   offline-replay generate --out <dir> --blocks N [--txs-per-block N] [--events ...]
+//!   offline-replay bench [--data <dir> | --blocks N] [--runs N] [--json]
 
-use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use arrow::array::{
-    Array, BinaryArray, Int64Array, StringArray,
+    Array, ArrayRef, BinaryArray, Int64Array, StringArray,
 };
-use clap::Parser;
+use arrow::datatypes::DataType;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use eyre::{Context, Result};
-use indicatif::{ProgressBar, ProgressStyle};
-use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
-use rusqlite::{params, Connection};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, ToSql};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+mod abi;
+mod analyze;
+mod bench;
+mod blobs;
+mod call;
+mod callgraph;
+mod chain;
+mod compat;
+mod config;
+mod conflict;
+mod content_hash;
+mod dataset;
+mod decode;
+mod dedupe;
+mod dry_run;
+mod duckdb_views;
+mod dump;
+mod ens;
+mod error;
+mod export;
+mod fetch;
+mod generate;
+mod filter;
+mod incident_report;
+mod indexes;
+mod inspect;
+mod labels;
+mod logging;
+mod manifest;
+mod memory;
+mod metrics;
+mod migrate;
+mod normalize;
+mod ommers;
+mod optimize;
+mod output;
+mod pragmas;
+mod prices;
+mod progress;
+mod protocols;
+mod query;
+mod redact;
+mod reorg;
+mod report;
+mod revert;
+mod rpc;
+mod sample;
+mod schema;
+mod segments;
+mod shell;
+mod state_snapshot;
+mod source;
+mod split;
+mod stats;
+#[cfg(test)]
+mod testing;
+mod verify;
+mod views;
+
+use chain::Chain;
+use compat::ColumnMap;
+use conflict::{Conflict, OnConflict};
+use error::DatasetError;
+use filter::Filter;
+use indexes::Indexes;
+use logging::LogFormat;
+use normalize::{AddressCase, Normalize, NumericEncoding};
+use output::{Output, Shards};
+use progress::{file_bar, ProgressMode};
+use reorg::ReorgPolicy;
+use report::Report;
+use schema::{LogKey, Table};
+use source::{Batches, DataSource, ReadOptions, TableReader};
 
 // ---------------------------------------------------------------------------
 // CLI
@@ -30,275 +161,923 @@ use rusqlite::{params, Connection};
     name = "offline-replay",
     about = "Load a DeFi Replay Kit Parquet dataset into a queryable SQLite database"
 )]
-struct Args {
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Progress output on stderr: interactive bar, or JSON lines for CI
+    #[arg(long, value_enum, default_value_t = ProgressMode::Bar, global = true)]
+    progress: ProgressMode,
+
+    /// More log output (-v debug, -vv trace)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only log warnings and errors, and hide progress bars
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log line format on stderr
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    #[command(flatten)]
+    load: LoadArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Carve a Parquet dataset or SQLite DB into N-block chunks, each with a manifest
+    Split(split::SplitArgs),
+
+    /// Build a Parquet dataset for a block range from an archive JSON-RPC endpoint
+    Fetch(fetch::FetchArgs),
+
+    /// Extract every Nth block, or a random sample of blocks, into a small dataset
+    Sample(sample::SampleArgs),
+
+    /// Pseudonymize addresses and strip calldata, for datasets shared externally
+    Redact(redact::RedactArgs),
+
+    /// Upgrade a database written by an older version to the current schema
+    Migrate(migrate::MigrateArgs),
+
+    /// Remove duplicate logs from a database and add a log key against new ones
+    Dedupe(dedupe::DedupeArgs),
+
+    /// ANALYZE (and optionally VACUUM) a database, reporting its size before and after
+    Optimize(optimize::OptimizeArgs),
+
+    /// Run a SQL statement, optionally joining across attached databases
+    Query(query::QueryArgs),
+
+    /// Interactive SQL prompt with .block, .tx and .logs shortcuts
+    Shell(shell::ShellArgs),
+
+    /// Import address labels from CSV or JSON files into a database
+    Labels(labels::LabelsArgs),
+
+    /// Resolve the addresses in a database to ENS names (via RPC or a snapshot)
+    Ens(ens::EnsArgs),
+
+    /// Write a database out as a value-flow graph, or tables as Arrow/Parquet
+    Export(export::ExportArgs),
+
+    /// Derive candles, TVL and balance histories from a database
+    Analyze(analyze::AnalyzeArgs),
+
+    /// Rank function selectors and events by count and gas, for a first look at a dataset
+    Stats(stats::StatsArgs),
+
+    /// Decode protocol events (built-in ones, or described in TOML) into a table each
+    Decode(protocols::DecodeArgs),
+
+    /// Print a database's content hash, to check two builds of a dataset match
+    Hash(content_hash::HashArgs),
+
+    /// Run an eth_call offline against the database's state at a block (`--features simulate`)
+    Call(call::CallArgs),
+
+    /// Compare each Parquet file's schema with the loader's, and between part files
+    Inspect(inspect::InspectArgs),
+
+    /// Check a database for internal inconsistencies (gas, blooms, nonces)
+    Verify(verify::VerifyArgs),
+
+    /// Write a synthetic Parquet dataset of configurable size and shape
+    Generate(generate::GenerateArgs),
+
+    /// Time loads of a sample dataset under several configurations
+    Bench(bench::BenchArgs),
+}
+
+#[derive(Args, Debug)]
+struct LoadArgs {
+    /// TOML job file with source, sink, filter and pragma settings;
+    /// command-line flags override it
+    #[arg(long)]
+    config: Option<PathBuf>,
+
     /// Directory containing blocks.parquet, transactions.parquet, logs.parquet
+    /// (an s3://, gs://, https:// URL with the `remote` feature, or "-" for stdin)
     #[arg(short, long, default_value = ".")]
     data: PathBuf,
 
-    /// Output SQLite database path
+    /// Output SQLite database path ("-" writes a SQL dump to stdout,
+    /// ":memory:" loads into RAM only)
     #[arg(short, long, default_value = "ethereum.db")]
     out: PathBuf,
-}
 
-// ---------------------------------------------------------------------------
-// Schema
-// ---------------------------------------------------------------------------
+    /// Write one database per N-block range into the --out directory, plus
+    /// a shards.json index mapping block ranges to files
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    shard_blocks: Option<u64>,
+
+    /// After loading, write a compacted copy of the database here (VACUUM
+    /// INTO); with --out :memory: the load never touches disk until then
+    #[arg(long)]
+    vacuum_into: Option<PathBuf>,
+
+    /// After loading, ANALYZE for the query planner; `--optimize vacuum`
+    /// also VACUUMs. Runs before --vacuum-into
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "analyze")]
+    optimize: Option<optimize::Level>,
+
+    /// What to do if the output database exists: create (fail), append,
+    /// overwrite. Defaults to create, or append with --watch
+    #[arg(long, value_enum)]
+    mode: Option<Mode>,
+
+    /// What to do with rows whose key is already in the table: ignore,
+    /// replace or fail; `TABLE=ACTION` for one table (repeatable) [default:
+    /// ignore]
+    #[arg(long, value_name = "[TABLE=]ACTION", value_parser = conflict::parse)]
+    on_conflict: Vec<conflict::Setting>,
 
-const SCHEMA: &str = "
-PRAGMA journal_mode=WAL;
-PRAGMA synchronous=NORMAL;
-PRAGMA cache_size=-65536;
-
-CREATE TABLE IF NOT EXISTS blocks (
-    number      INTEGER PRIMARY KEY,
-    hash        TEXT    NOT NULL,
-    parent_hash TEXT    NOT NULL,
-    timestamp   INTEGER NOT NULL,
-    gas_used    INTEGER NOT NULL,
-    gas_limit   INTEGER NOT NULL,
-    base_fee    TEXT,
-    tx_count    INTEGER NOT NULL
-);
-
-CREATE TABLE IF NOT EXISTS transactions (
-    hash         TEXT    PRIMARY KEY,
-    block_number INTEGER NOT NULL REFERENCES blocks(number),
-    tx_index     INTEGER NOT NULL,
-    from_addr    TEXT    NOT NULL,
-    to_addr      TEXT,
-    value        TEXT    NOT NULL,
-    gas_used     INTEGER NOT NULL,
-    gas_price    TEXT    NOT NULL,
-    input        BLOB    NOT NULL,
-    status       INTEGER NOT NULL
-);
-CREATE INDEX IF NOT EXISTS idx_tx_block ON transactions(block_number);
-CREATE INDEX IF NOT EXISTS idx_tx_from  ON transactions(from_addr);
-CREATE INDEX IF NOT EXISTS idx_tx_to    ON transactions(to_addr);
-
-CREATE TABLE IF NOT EXISTS logs (
-    id           INTEGER PRIMARY KEY AUTOINCREMENT,
-    block_number INTEGER NOT NULL REFERENCES blocks(number),
-    tx_hash      TEXT    NOT NULL REFERENCES transactions(hash),
-    log_index    INTEGER NOT NULL,
-    address      TEXT    NOT NULL,
-    topic0       TEXT,
-    topic1       TEXT,
-    topic2       TEXT,
-    topic3       TEXT,
-    data         BLOB
-);
-CREATE INDEX IF NOT EXISTS idx_log_block   ON logs(block_number);
-CREATE INDEX IF NOT EXISTS idx_log_address ON logs(address);
-CREATE INDEX IF NOT EXISTS idx_log_topic0  ON logs(topic0);
-";
+    /// What to do with blocks reorged out of the chain: keep them with
+    /// is_canonical = 0, drop them, or prefer-latest, taking the last block
+    /// read of a number as canonical (see reorg.rs)
+    #[arg(long, value_enum, default_value_t = ReorgPolicy::Drop)]
+    reorg_policy: ReorgPolicy,
+
+    /// Table contained in stdin when reading a single file with --data -
+    #[arg(long, value_enum)]
+    table: Option<Table>,
+
+    /// Only load these tables (comma-separated); the output's other tables
+    /// are left as they are
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with_all = ["table", "skip_tables"])]
+    only: Vec<Table>,
+
+    /// Don't load these tables (comma-separated)
+    #[arg(
+        long = "skip",
+        value_name = "TABLES",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with = "table"
+    )]
+    skip_tables: Vec<Table>,
+
+    /// Keep running and append new part files (<table>/*.parquet) as they appear
+    #[arg(long)]
+    watch: bool,
+
+    /// Seconds between directory polls in --watch mode
+    #[arg(long, default_value_t = 10, requires = "watch")]
+    poll_interval: u64,
+
+    /// Rows per Parquet record batch
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u64).range(1..))]
+    batch_size: u64,
+
+    /// Record batches decoded ahead of the inserts, across the reader
+    /// threads (0: read on the inserting thread)
+    #[arg(long, alias = "read-ahead", default_value_t = 4)]
+    queue_size: usize,
+
+    /// Threads decoding a file's row groups in parallel for the one thread
+    /// inserting them (local and stdin files with several row groups)
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    read_threads: u64,
+
+    /// Keep decoded batches within half of this (e.g. 4GiB), with smaller
+    /// batches, a shorter queue and fewer reader threads as needed
+    #[arg(long, value_name = "SIZE", value_parser = memory::parse_size)]
+    max_memory: Option<u64>,
+
+    /// Commit every N rows read instead of once per file, and record how far
+    /// each file got, so a rerun with --mode append resumes there
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    commit_every: Option<u64>,
+
+    /// Check column names, types and nulls and estimate the output size,
+    /// without writing a database
+    #[arg(long, conflicts_with = "watch")]
+    dry_run: bool,
+
+    /// Only load blocks at or above this number
+    #[arg(long)]
+    from_block: Option<i64>,
+
+    /// Only load blocks at or below this number
+    #[arg(long)]
+    to_block: Option<i64>,
+
+    /// Only load blocks with a timestamp at or after this date/time (ISO 8601,
+    /// e.g. 2023-03-13T00:00:00Z, or Unix seconds)
+    #[arg(long, value_parser = filter::parse_date, conflicts_with = "from_block")]
+    from_date: Option<i64>,
+
+    /// Only load blocks with a timestamp before this date/time
+    #[arg(long, value_parser = filter::parse_date, conflicts_with = "to_block")]
+    to_date: Option<i64>,
+
+    /// Only load logs emitted by these addresses, and the transactions that
+    /// emitted them or were sent from/to them (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    address: Vec<String>,
+
+    /// Don't read these nullable columns at all; they load as NULL
+    /// (comma-separated, e.g. input,data)
+    #[arg(long, value_delimiter = ',')]
+    skip_columns: Vec<String>,
+
+    /// Read the file's column FROM as the loader's TO, for datasets from
+    /// other exporters; TABLE. limits it to one table (repeatable)
+    #[arg(long, value_name = "[TABLE.]FROM=TO", value_parser = compat::parse)]
+    map: Vec<compat::Rename>,
+
+    /// The chain the dataset is from: op-stack also loads deposit and L1 fee
+    /// fields into transactions, arbitrum retryable ticket, L1 gas and L1
+    /// block number fields; a preset also checks the chain id and block
+    /// time (see chain.rs)
+    #[arg(long, value_enum, default_value_t = Chain::Ethereum)]
+    chain: Chain,
+
+    /// Shorthand for --skip-columns input,data: no calldata or log payloads
+    #[arg(long, conflicts_with = "skip_columns")]
+    light: bool,
+
+    /// How value, gas_price and base_fee are stored: decimal text, 0x-hex
+    /// padded to 64 digits, or 32-byte big-endian blobs
+    #[arg(long, value_enum, default_value_t = NumericEncoding::Decimal)]
+    numeric_encoding: NumericEncoding,
+
+    /// Rewrite from_addr, to_addr and log addresses (and --address filters)
+    /// as lowercase or EIP-55 checksummed hex
+    #[arg(long, value_enum)]
+    address_case: Option<AddressCase>,
+
+    /// Also store each log's topics as rows of log_topics(log_id, position,
+    /// topic), indexed for "any topic equals X" queries
+    #[arg(long)]
+    topics_table: bool,
+
+    /// Store calldata and log data zstd-compressed (read back transparently
+    /// by this tool's commands; other clients must decompress them)
+    #[arg(long)]
+    compress: bool,
+
+    /// Store hashes, addresses and topics as 32/20-byte BLOBs instead of
+    /// 0x-hex text: a smaller database and faster key lookups
+    #[arg(long, conflicts_with = "address_case")]
+    binary_keys: bool,
+
+    /// Columns kept unique in logs, so loading the same logs again doesn't
+    /// duplicate them: full (block_number, tx_hash, log_index), compact
+    /// (block_number, log_index) or none
+    #[arg(long, value_enum, default_value_t = LogKey::Full)]
+    log_key: LogKey,
+
+    /// Create STRICT tables, with transactions WITHOUT ROWID keyed by hash,
+    /// so mistyped values fail at insert
+    #[arg(long)]
+    strict: bool,
+
+    /// Also create this index, e.g. 'logs(address, topic0, block_number)'
+    /// (repeatable)
+    #[arg(long, value_name = "TABLE(COLUMNS)", value_parser = indexes::parse)]
+    index: Vec<indexes::Index>,
+
+    /// Leave out the default indexes on block_number, from_addr, to_addr,
+    /// address and topic0
+    #[arg(long)]
+    no_default_index: bool,
+
+    /// Install convenience views (v_tx_with_block, v_logs_named,
+    /// v_daily_activity), the event_signatures table they join and
+    /// function_signatures
+    #[arg(long)]
+    views: bool,
+
+    /// Instead of copying the data, make --out a DuckDB database of views
+    /// over the Parquet files (or, ending in .sql, the script creating one),
+    /// with the same table names and the --views helper views
+    #[arg(long, conflicts_with_all = ["watch", "dry_run", "shard_blocks", "vacuum_into", "prices"])]
+    views_only: bool,
+
+    /// CSV of token/USD prices (token, timestamp, price_usd[, decimals]) to
+    /// load into `prices`, with a v_transfers_usd view pricing each transfer
+    #[arg(long)]
+    prices: Option<PathBuf>,
+
+    /// SQLite settings for the load: bulk (fast, not crash-safe) or safe
+    /// (rollback journal, full fsync); default WAL with synchronous=NORMAL
+    #[arg(long, value_enum)]
+    preset: Option<pragmas::Preset>,
+
+    /// `PRAGMA name=value` to run on the output database, after the
+    /// preset's (repeatable)
+    #[arg(long = "pragma", value_name = "NAME=VALUE", value_parser = pragmas::parse)]
+    pragmas: Vec<(String, String)>,
+
+    /// Write per-table timings, throughput, bytes read, database growth and
+    /// the pragmas used to this JSON file when the load finishes
+    #[arg(long, conflicts_with = "dry_run")]
+    report: Option<PathBuf>,
+
+    /// Print the database's content hash on stdout when the load finishes,
+    /// to check that independently built databases match (see `hash`)
+    #[arg(long, conflicts_with_all = ["dry_run", "watch", "shard_blocks", "views_only"])]
+    emit_hash: bool,
+
+    /// Serve Prometheus metrics (rows read, current table, errors, rate) on
+    /// this address while loading, e.g. 0.0.0.0:9100
+    #[arg(long, value_name = "ADDR", conflicts_with = "dry_run")]
+    metrics_addr: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    /// Fail if the output database already exists
+    Create,
+    /// Add to an existing database (see --on-conflict for rows already present)
+    Append,
+    /// Delete an existing database and build it from scratch
+    Overwrite,
+}
 
 // ---------------------------------------------------------------------------
 // Helpers — extract typed columns from Arrow batches
 // ---------------------------------------------------------------------------
 
-fn col_str<'a>(batch: &'a arrow::record_batch::RecordBatch, name: &str) -> &'a StringArray {
-    batch
-        .column_by_name(name)
-        .unwrap_or_else(|| panic!("missing column: {name}"))
-        .as_any()
-        .downcast_ref::<StringArray>()
-        .unwrap_or_else(|| panic!("column {name} is not StringArray"))
+pub(crate) fn col_str<'a>(
+    batch: &'a arrow::record_batch::RecordBatch,
+    name: &str,
+) -> Result<&'a StringArray, DatasetError> {
+    typed(column(batch, name)?, name, DataType::Utf8)
 }
 
-fn col_i64<'a>(batch: &'a arrow::record_batch::RecordBatch, name: &str) -> &'a Int64Array {
-    batch
-        .column_by_name(name)
-        .unwrap_or_else(|| panic!("missing column: {name}"))
-        .as_any()
-        .downcast_ref::<Int64Array>()
-        .unwrap_or_else(|| panic!("column {name} is not Int64Array"))
+pub(crate) fn col_i64<'a>(
+    batch: &'a arrow::record_batch::RecordBatch,
+    name: &str,
+) -> Result<&'a Int64Array, DatasetError> {
+    typed(column(batch, name)?, name, DataType::Int64)
 }
 
-fn col_bin<'a>(batch: &'a arrow::record_batch::RecordBatch, name: &str) -> &'a BinaryArray {
+fn column<'a>(
+    batch: &'a arrow::record_batch::RecordBatch,
+    name: &str,
+) -> Result<&'a ArrayRef, DatasetError> {
     batch
         .column_by_name(name)
-        .unwrap_or_else(|| panic!("missing column: {name}"))
-        .as_any()
-        .downcast_ref::<BinaryArray>()
-        .unwrap_or_else(|| panic!("column {name} is not BinaryArray"))
+        .ok_or_else(|| DatasetError::MissingColumn { column: name.to_string() })
 }
 
-fn opt_str(arr: &StringArray, i: usize) -> Option<&str> {
-    if arr.is_null(i) { None } else { Some(arr.value(i)) }
+fn typed<'a, A: Array + 'static>(
+    column: &'a ArrayRef,
+    name: &str,
+    expected: DataType,
+) -> Result<&'a A, DatasetError> {
+    column.as_any().downcast_ref::<A>().ok_or_else(|| DatasetError::TypeMismatch {
+        column: name.to_string(),
+        expected,
+        found: column.data_type().clone(),
+    })
 }
 
-fn opt_bin(arr: &BinaryArray, i: usize) -> Option<&[u8]> {
-    if arr.is_null(i) { None } else { Some(arr.value(i)) }
+/// Open a database that must already exist (for subcommands that read one).
+pub(crate) fn open_existing(db: &std::path::Path) -> Result<Connection> {
+    if !db.is_file() {
+        eyre::bail!("{} does not exist", db.display());
+    }
+    Connection::open(db).with_context(|| format!("Cannot open {}", db.display()))
 }
 
-// ---------------------------------------------------------------------------
-// Progress bar
-// ---------------------------------------------------------------------------
+/// Nullable columns may be absent altogether (--skip-columns); every row of
+/// an absent column reads as NULL.
+fn col_str_opt<'a>(
+    batch: &'a arrow::record_batch::RecordBatch,
+    name: &str,
+) -> Result<Option<&'a StringArray>, DatasetError> {
+    batch.column_by_name(name).map(|c| typed(c, name, DataType::Utf8)).transpose()
+}
 
-fn progress_bar(total: u64, msg: &str) -> ProgressBar {
-    let pb = ProgressBar::new(total);
-    pb.set_style(
-        ProgressStyle::with_template(
-            "{msg:20} [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
-        )
-        .unwrap()
-        .progress_chars("=> "),
-    );
-    pb.set_message(msg.to_string());
-    pb
+fn col_bin_opt<'a>(
+    batch: &'a arrow::record_batch::RecordBatch,
+    name: &str,
+) -> Result<Option<&'a BinaryArray>, DatasetError> {
+    batch.column_by_name(name).map(|c| typed(c, name, DataType::Binary)).transpose()
+}
+
+fn col_i64_opt<'a>(
+    batch: &'a arrow::record_batch::RecordBatch,
+    name: &str,
+) -> Result<Option<&'a Int64Array>, DatasetError> {
+    batch.column_by_name(name).map(|c| typed(c, name, DataType::Int64)).transpose()
+}
+
+fn opt_str(arr: Option<&StringArray>, i: usize) -> Option<&str> {
+    arr.filter(|a| !a.is_null(i)).map(|a| a.value(i))
+}
+
+fn opt_bin(arr: Option<&BinaryArray>, i: usize) -> Option<&[u8]> {
+    arr.filter(|a| !a.is_null(i)).map(|a| a.value(i))
+}
+
+fn opt_i64(arr: Option<&Int64Array>, i: usize) -> Option<i64> {
+    arr.filter(|a| !a.is_null(i)).map(|a| a.value(i))
 }
 
 // ---------------------------------------------------------------------------
 // Load functions
 // ---------------------------------------------------------------------------
 
-fn load_blocks(conn: &Connection, path: &Path) -> Result<u64> {
-    let file = std::fs::File::open(path)
-        .with_context(|| format!("Cannot open {}", path.display()))?;
-    let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
-    let total_rows = builder.metadata().file_metadata().num_rows() as u64;
-    let reader = builder.build()?;
+/// With `--commit-every`, commits a file's rows every N rows read, recording
+/// in the same transaction how far the file got: `checkpoint.<location>` in
+/// _meta holds "<rows read> <rows written>", counted from the file's start.
+struct Commits {
+    every: Option<u64>,
+    key: String,
+    /// Rows read and written by earlier runs, from the checkpoint.
+    resumed: (u64, u64),
+    read: u64,
+    pending: u64,
+}
 
-    let pb = progress_bar(total_rows, "blocks");
+impl Commits {
+    /// Count a batch of `rows` read, with `written` rows written so far in
+    /// this run, and commit once enough have been read.
+    fn batch(&mut self, db: &mut Output, rows: usize, written: u64) -> Result<()> {
+        let Some(every) = self.every else { return Ok(()) };
+        self.read += rows as u64;
+        self.pending += rows as u64;
+        if self.pending < every {
+            return Ok(());
+        }
+        let (read, written) = (self.resumed.0 + self.read, self.resumed.1 + written);
+        if let Output::Single(conn) = &*db {
+            migrate::set_meta(conn, &self.key, &format!("{read} {written}"))?;
+        }
+        db.commit()?;
+        db.begin()?;
+        self.pending = 0;
+        debug!(key = self.key, read, written, "committed");
+        Ok(())
+    }
+}
+
+fn load_blocks(
+    db: &mut Output,
+    table: TableReader,
+    filter: &Filter,
+    norm: &Normalize,
+    commits: &mut Commits,
+) -> Result<u64> {
+    let mut pb = file_bar("blocks", &table);
     let mut count = 0u64;
+    // The first and last block loaded, as (number, timestamp).
+    let mut span: Option<((i64, i64), (i64, i64))> = None;
 
-    let mut stmt = conn.prepare_cached(
-        "INSERT OR IGNORE INTO blocks
-         (number, hash, parent_hash, timestamp, gas_used, gas_limit, base_fee, tx_count)
-         VALUES (?1,?2,?3,?4,?5,?6,?7,?8)",
-    )?;
+    // Blocks left out as not canonical (see reorg.rs).
+    let mut dropped = 0u64;
+
+    let mut columns = vec![
+        "number", "hash", "parent_hash", "timestamp", "gas_used", "gas_limit", "base_fee",
+        "tx_count", "is_canonical", "miner",
+    ];
+    columns.extend(norm.chain.column_names(Table::Blocks));
+    let sql = norm.on_conflict.insert(Table::Blocks, &columns);
 
-    for batch in reader {
+    for batch in table.batches {
         let batch = batch?;
-        let number      = col_i64(&batch, "number");
-        let hash        = col_str(&batch, "hash");
-        let parent_hash = col_str(&batch, "parent_hash");
-        let timestamp   = col_i64(&batch, "timestamp");
-        let gas_used    = col_i64(&batch, "gas_used");
-        let gas_limit   = col_i64(&batch, "gas_limit");
-        let base_fee    = col_str(&batch, "base_fee");
-        let tx_count    = col_i64(&batch, "tx_count");
+        let number      = col_i64(&batch, "number")?;
+        let hash        = col_str(&batch, "hash")?;
+        let parent_hash = col_str(&batch, "parent_hash")?;
+        let timestamp   = col_i64(&batch, "timestamp")?;
+        let gas_used    = col_i64(&batch, "gas_used")?;
+        let gas_limit   = col_i64(&batch, "gas_limit")?;
+        let base_fee    = col_str_opt(&batch, "base_fee")?;
+        let tx_count    = col_i64(&batch, "tx_count")?;
+        let canonical   = col_i64_opt(&batch, "is_canonical")?;
+        let miner       = col_str_opt(&batch, "miner")?;
+        let chain       = norm.chain.read(Table::Blocks, &batch)?;
 
         for i in 0..batch.num_rows() {
-            stmt.execute(params![
-                number.value(i),
-                hash.value(i),
-                parent_hash.value(i),
-                timestamp.value(i),
-                gas_used.value(i),
-                gas_limit.value(i),
-                opt_str(base_fee, i),
-                tx_count.value(i),
-            ])?;
+            if !filter.block(number.value(i)) {
+                continue;
+            }
+            let canonical = reorg::canonical(canonical, i);
+            if !canonical && norm.reorg == ReorgPolicy::Drop {
+                dropped += 1;
+                continue;
+            }
+            let key = norm.key("hash", hash.value(i))?;
+            if canonical {
+                norm.reorg.demote(db.conn(number.value(i))?, number.value(i), &key)?;
+            }
+            let row: [&dyn ToSql; 10] = [
+                &number.value(i),
+                &key,
+                &norm.key("parent_hash", parent_hash.value(i))?,
+                &timestamp.value(i),
+                &gas_used.value(i),
+                &gas_limit.value(i),
+                &opt_str(base_fee, i).map(|s| norm.amount("base_fee", s)).transpose()?,
+                &tx_count.value(i),
+                &canonical,
+                &opt_str(miner, i).map(|m| norm.key("miner", m)).transpose()?,
+            ];
+            let extra = chain.values(i, norm, None)?;
+            let conn = db.conn(number.value(i))?;
+            let stmt = &mut conn.prepare_cached(&sql)?;
+            if extra.is_empty() {
+                stmt.execute(&row[..])?;
+            } else {
+                let extra = extra.iter().map(|v| v as &dyn ToSql);
+                stmt.execute(params_from_iter(row.into_iter().chain(extra)))?;
+            }
+            if canonical {
+                norm.reorg.promote(conn, number.value(i), &key)?;
+            }
+            let block = (number.value(i), timestamp.value(i));
+            span = Some(match span {
+                None => (block, block),
+                Some((first, last)) => (first.min(block), last.max(block)),
+            });
             count += 1;
         }
         pb.inc(batch.num_rows() as u64);
+        commits.batch(db, batch.num_rows(), count)?;
+    }
+    if let Some(((first, first_time), (last, last_time))) = span {
+        norm.chain.check_block_time(last - first, last_time - first_time);
+    }
+    if dropped > 0 {
+        info!(blocks = dropped, "left out blocks that are not canonical");
     }
 
     pb.finish_with_message(format!("blocks ✓ ({count})"));
     Ok(count)
 }
 
-fn load_transactions(conn: &Connection, path: &Path) -> Result<u64> {
-    let file = std::fs::File::open(path)
-        .with_context(|| format!("Cannot open {}", path.display()))?;
-    let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
-    let total_rows = builder.metadata().file_metadata().num_rows() as u64;
-    let reader = builder.build()?;
-
-    let pb = progress_bar(total_rows, "transactions");
+fn load_transactions(
+    db: &mut Output,
+    table: TableReader,
+    filter: &Filter,
+    norm: &Normalize,
+    commits: &mut Commits,
+) -> Result<u64> {
+    let mut pb = file_bar("transactions", &table);
     let mut count = 0u64;
 
-    let mut stmt = conn.prepare_cached(
-        "INSERT OR IGNORE INTO transactions
-         (hash, block_number, tx_index, from_addr, to_addr, value,
-          gas_used, gas_price, input, status)
-         VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10)",
-    )?;
+    // log_count is counted by load_logs, so a replaced row keeps its own.
+    let mut columns = vec![
+        "hash", "block_number", "tx_index", "from_addr", "to_addr", "value", "gas_used",
+        "gas_price", "input", "status", "fee_wei", "logs_bloom", "nonce",
+    ];
+    columns.extend(norm.chain.column_names(Table::Transactions));
+    let sql = norm.on_conflict.insert(Table::Transactions, &columns);
 
-    for batch in reader {
+    for batch in table.batches {
         let batch = batch?;
-        let hash         = col_str(&batch, "hash");
-        let block_number = col_i64(&batch, "block_number");
-        let tx_index     = col_i64(&batch, "tx_index");
-        let from_addr    = col_str(&batch, "from_addr");
-        let to_addr      = col_str(&batch, "to_addr");
-        let value        = col_str(&batch, "value");
-        let gas_used     = col_i64(&batch, "gas_used");
-        let gas_price    = col_str(&batch, "gas_price");
-        let input        = col_bin(&batch, "input");
-        let status       = col_i64(&batch, "status");
+        let hash         = col_str(&batch, "hash")?;
+        let block_number = col_i64(&batch, "block_number")?;
+        let tx_index     = col_i64(&batch, "tx_index")?;
+        let from_addr    = col_str(&batch, "from_addr")?;
+        let to_addr      = col_str_opt(&batch, "to_addr")?;
+        let value        = col_str(&batch, "value")?;
+        let gas_used     = col_i64(&batch, "gas_used")?;
+        let gas_price    = col_str(&batch, "gas_price")?;
+        let input        = col_bin_opt(&batch, "input")?;
+        let status       = col_i64(&batch, "status")?;
+        let logs_bloom   = col_bin_opt(&batch, "logs_bloom")?;
+        let nonce        = col_i64_opt(&batch, "nonce")?;
+        let block_hash   = col_str_opt(&batch, "block_hash")?;
+        let chain        = norm.chain.read(Table::Transactions, &batch)?;
 
         for i in 0..batch.num_rows() {
-            stmt.execute(params![
-                hash.value(i),
+            if !filter.transaction(
                 block_number.value(i),
-                tx_index.value(i),
+                hash.value(i),
                 from_addr.value(i),
                 opt_str(to_addr, i),
-                value.value(i),
+            ) || !filter.canonical(opt_str(block_hash, i))
+            {
+                continue;
+            }
+            let row: [&dyn ToSql; 13] = [
+                &norm.key("hash", hash.value(i))?,
+                &block_number.value(i),
+                &tx_index.value(i),
+                &norm.key("from_addr", from_addr.value(i))?,
+                &opt_str(to_addr, i).map(|a| norm.key("to_addr", a)).transpose()?,
+                &norm.amount("value", value.value(i))?,
+                &gas_used.value(i),
+                &norm.amount("gas_price", gas_price.value(i))?,
+                &norm.blob(opt_bin(input, i).unwrap_or(&[]))?,
+                &status.value(i),
+                &norm.fee(gas_used.value(i), gas_price.value(i), chain.l1_fee(i))?,
+                &opt_bin(logs_bloom, i),
+                &opt_i64(nonce, i),
+            ];
+            let extra = chain.values(i, norm, Some(from_addr.value(i)))?;
+            let stmt = &mut db.conn(block_number.value(i))?.prepare_cached(&sql)?;
+            if extra.is_empty() {
+                stmt.execute(&row[..])?;
+            } else {
+                let extra = extra.iter().map(|v| v as &dyn ToSql);
+                stmt.execute(params_from_iter(row.into_iter().chain(extra)))?;
+            }
+            count += 1;
+        }
+        pb.inc(batch.num_rows() as u64);
+        commits.batch(db, batch.num_rows(), count)?;
+    }
+
+    pb.finish_with_message(format!("transactions ✓ ({count})"));
+    Ok(count)
+}
+
+fn load_logs(
+    db: &mut Output,
+    table: TableReader,
+    filter: &Filter,
+    norm: &Normalize,
+    commits: &mut Commits,
+) -> Result<u64> {
+    let mut pb = file_bar("logs", &table);
+    let mut count = 0u64;
+
+    let sql = norm.on_conflict.insert(
+        Table::Logs,
+        &[
+            "block_number", "tx_hash", "log_index", "address", "topic0", "topic1", "topic2",
+            "topic3", "data",
+        ],
+    );
+    const TOPIC_SQL: &str = "INSERT INTO log_topics (log_id, position, topic) VALUES (?1,?2,?3)";
+    const COUNT_SQL: &str = "UPDATE transactions SET log_count = log_count + 1 WHERE hash = ?1";
+    const UNCOUNT_SQL: &str = "UPDATE transactions SET log_count = log_count - 1
+        WHERE hash = (SELECT tx_hash FROM logs WHERE id = ?1)";
+    const CLEAR_TOPICS_SQL: &str = "DELETE FROM log_topics WHERE log_id = ?1";
+    // A replaced log keeps its id, so its topics and counts are moved by hand:
+    // the id of the log with the same key, and whether it has the same tx_hash.
+    let replace = norm.on_conflict.get(Table::Logs) == Conflict::Replace
+        && norm.log_key != LogKey::None;
+    let lookup = match norm.log_key {
+        LogKey::Compact => {
+            "SELECT id, tx_hash = ?2 FROM logs WHERE block_number = ?1 AND log_index = ?3"
+        }
+        LogKey::Full | LogKey::None => {
+            "SELECT id, 1 FROM logs WHERE block_number = ?1 AND tx_hash = ?2 AND log_index = ?3"
+        }
+    };
+
+    for batch in table.batches {
+        let batch = batch?;
+        let block_number = col_i64(&batch, "block_number")?;
+        let tx_hash      = col_str(&batch, "tx_hash")?;
+        let log_index    = col_i64(&batch, "log_index")?;
+        let address      = col_str(&batch, "address")?;
+        let topic0       = col_str_opt(&batch, "topic0")?;
+        let topic1       = col_str_opt(&batch, "topic1")?;
+        let topic2       = col_str_opt(&batch, "topic2")?;
+        let topic3       = col_str_opt(&batch, "topic3")?;
+        let data         = col_bin_opt(&batch, "data")?;
+        let block_hash   = col_str_opt(&batch, "block_hash")?;
+        let topic_columns =
+            [("topic0", topic0), ("topic1", topic1), ("topic2", topic2), ("topic3", topic3)];
+
+        for i in 0..batch.num_rows() {
+            if !filter.log(block_number.value(i), address.value(i))
+                || !filter.canonical(opt_str(block_hash, i))
+            {
+                continue;
+            }
+            let conn = db.conn(block_number.value(i))?;
+            let tx_hash = norm.key("tx_hash", tx_hash.value(i))?;
+            let mut topics = [None, None, None, None];
+            for (slot, (column, topic)) in topics.iter_mut().zip(topic_columns) {
+                *slot = opt_str(topic, i).map(|t| norm.key(column, t)).transpose()?;
+            }
+            let replaced: Option<(i64, bool)> = if replace {
+                conn.prepare_cached(lookup)?
+                    .query_row(params![block_number.value(i), tx_hash, log_index.value(i)], |r| {
+                        Ok((r.get(0)?, r.get(1)?))
+                    })
+                    .optional()?
+            } else {
+                None
+            };
+            if let Some((id, false)) = replaced {
+                conn.prepare_cached(UNCOUNT_SQL)?.execute([id])?;
+            }
+            let inserted = conn.prepare_cached(&sql)?.execute(params![
+                block_number.value(i),
+                tx_hash,
+                log_index.value(i),
+                norm.key("address", address.value(i))?,
+                topics[0],
+                topics[1],
+                topics[2],
+                topics[3],
+                opt_bin(data, i).map(|d| norm.blob(d)).transpose()?,
+            ])? > 0;
+            let id = match replaced {
+                Some((id, same_tx)) => {
+                    if !same_tx {
+                        conn.prepare_cached(COUNT_SQL)?.execute([&tx_hash])?;
+                    }
+                    if norm.topics_table {
+                        conn.prepare_cached(CLEAR_TOPICS_SQL)?.execute([id])?;
+                    }
+                    id
+                }
+                None if inserted => {
+                    conn.prepare_cached(COUNT_SQL)?.execute([&tx_hash])?;
+                    conn.last_insert_rowid()
+                }
+                // Ignored: the log is already there.
+                None => {
+                    count += 1;
+                    continue;
+                }
+            };
+            if norm.topics_table {
+                for (position, topic) in topics.iter().enumerate() {
+                    if let Some(topic) = topic {
+                        conn.prepare_cached(TOPIC_SQL)?.execute(params![id, position as i64, topic])?;
+                    }
+                }
+            }
+            count += 1;
+        }
+        pb.inc(batch.num_rows() as u64);
+        commits.batch(db, batch.num_rows(), count)?;
+    }
+
+    pb.finish_with_message(format!("logs ✓ ({count})"));
+    Ok(count)
+}
+
+fn load_ommers(
+    db: &mut Output,
+    table: TableReader,
+    filter: &Filter,
+    norm: &Normalize,
+    commits: &mut Commits,
+) -> Result<u64> {
+    let mut pb = file_bar("ommers", &table);
+    let mut count = 0u64;
+
+    let sql = norm.on_conflict.insert(
+        Table::Ommers,
+        &[
+            "block_number", "ommer_index", "hash", "number", "parent_hash", "miner", "timestamp",
+            "gas_used", "gas_limit", "reward",
+        ],
+    );
+
+    for batch in table.batches {
+        let batch = batch?;
+        let block_number = col_i64(&batch, "block_number")?;
+        let ommer_index  = col_i64(&batch, "ommer_index")?;
+        let hash         = col_str(&batch, "hash")?;
+        let number       = col_i64(&batch, "number")?;
+        let parent_hash  = col_str(&batch, "parent_hash")?;
+        let miner        = col_str(&batch, "miner")?;
+        let timestamp    = col_i64(&batch, "timestamp")?;
+        let gas_used     = col_i64(&batch, "gas_used")?;
+        let gas_limit    = col_i64(&batch, "gas_limit")?;
+        let reward       = col_str_opt(&batch, "reward")?;
+        let block_hash   = col_str_opt(&batch, "block_hash")?;
+
+        for i in 0..batch.num_rows() {
+            if !filter.block(block_number.value(i)) || !filter.canonical(opt_str(block_hash, i)) {
+                continue;
+            }
+            let reward = match opt_str(reward, i) {
+                Some(r) => Some(norm.amount("reward", r)?),
+                None => ommers::reward(norm.chain, block_number.value(i), number.value(i))
+                    .map(|r| norm.encode(r)),
+            };
+            let conn = db.conn(block_number.value(i))?;
+            conn.prepare_cached(&sql)?.execute(params![
+                block_number.value(i),
+                ommer_index.value(i),
+                norm.key("hash", hash.value(i))?,
+                number.value(i),
+                norm.key("parent_hash", parent_hash.value(i))?,
+                norm.key("miner", miner.value(i))?,
+                timestamp.value(i),
                 gas_used.value(i),
-                gas_price.value(i),
-                opt_bin(input, i).unwrap_or(&[]),
-                status.value(i),
+                gas_limit.value(i),
+                reward,
             ])?;
             count += 1;
         }
         pb.inc(batch.num_rows() as u64);
+        commits.batch(db, batch.num_rows(), count)?;
     }
 
-    pb.finish_with_message(format!("transactions ✓ ({count})"));
+    pb.finish_with_message(format!("ommers ✓ ({count})"));
     Ok(count)
 }
 
-fn load_logs(conn: &Connection, path: &Path) -> Result<u64> {
-    let file = std::fs::File::open(path)
-        .with_context(|| format!("Cannot open {}", path.display()))?;
-    let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
-    let total_rows = builder.metadata().file_metadata().num_rows() as u64;
-    let reader = builder.build()?;
+fn load_withdrawals(
+    db: &mut Output,
+    table: TableReader,
+    filter: &Filter,
+    norm: &Normalize,
+    commits: &mut Commits,
+) -> Result<u64> {
+    let mut pb = file_bar("withdrawals", &table);
+    let mut count = 0u64;
+
+    let sql = norm.on_conflict.insert(
+        Table::Withdrawals,
+        &["withdrawal_index", "block_number", "validator_index", "address", "amount"],
+    );
+
+    for batch in table.batches {
+        let batch = batch?;
+        let block_number     = col_i64(&batch, "block_number")?;
+        let withdrawal_index = col_i64(&batch, "withdrawal_index")?;
+        let validator_index  = col_i64(&batch, "validator_index")?;
+        let address          = col_str(&batch, "address")?;
+        let amount           = col_i64(&batch, "amount")?;
+        let block_hash       = col_str_opt(&batch, "block_hash")?;
+
+        for i in 0..batch.num_rows() {
+            if !filter.block(block_number.value(i)) || !filter.canonical(opt_str(block_hash, i)) {
+                continue;
+            }
+            let conn = db.conn(block_number.value(i))?;
+            conn.prepare_cached(&sql)?.execute(params![
+                withdrawal_index.value(i),
+                block_number.value(i),
+                validator_index.value(i),
+                norm.key("address", address.value(i))?,
+                amount.value(i),
+            ])?;
+            count += 1;
+        }
+        pb.inc(batch.num_rows() as u64);
+        commits.batch(db, batch.num_rows(), count)?;
+    }
+
+    pb.finish_with_message(format!("withdrawals ✓ ({count})"));
+    Ok(count)
+}
 
-    let pb = progress_bar(total_rows, "logs");
+fn load_blobs(
+    db: &mut Output,
+    table: TableReader,
+    filter: &Filter,
+    norm: &Normalize,
+    commits: &mut Commits,
+) -> Result<u64> {
+    let mut pb = file_bar("blobs", &table);
     let mut count = 0u64;
 
-    let mut stmt = conn.prepare_cached(
-        "INSERT INTO logs
-         (block_number, tx_hash, log_index, address,
-          topic0, topic1, topic2, topic3, data)
-         VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9)",
-    )?;
+    let sql = norm.on_conflict.insert(
+        Table::Blobs,
+        &[
+            "tx_hash", "blob_index", "block_number", "versioned_hash", "kzg_commitment",
+            "kzg_proof", "data", "data_ref",
+        ],
+    );
 
-    for batch in reader {
+    for batch in table.batches {
         let batch = batch?;
-        let block_number = col_i64(&batch, "block_number");
-        let tx_hash      = col_str(&batch, "tx_hash");
-        let log_index    = col_i64(&batch, "log_index");
-        let address      = col_str(&batch, "address");
-        let topic0       = col_str(&batch, "topic0");
-        let topic1       = col_str(&batch, "topic1");
-        let topic2       = col_str(&batch, "topic2");
-        let topic3       = col_str(&batch, "topic3");
-        let data         = col_bin(&batch, "data");
+        let block_number   = col_i64(&batch, "block_number")?;
+        let tx_hash        = col_str(&batch, "tx_hash")?;
+        let blob_index     = col_i64(&batch, "blob_index")?;
+        let versioned_hash = col_str_opt(&batch, "versioned_hash")?;
+        let kzg_commitment = col_str_opt(&batch, "kzg_commitment")?;
+        let kzg_proof      = col_str_opt(&batch, "kzg_proof")?;
+        let data           = col_bin_opt(&batch, "data")?;
+        let data_ref       = col_str_opt(&batch, "data_ref")?;
+        let block_hash     = col_str_opt(&batch, "block_hash")?;
 
         for i in 0..batch.num_rows() {
-            stmt.execute(params![
+            if !filter.block(block_number.value(i)) || !filter.canonical(opt_str(block_hash, i)) {
+                continue;
+            }
+            let commitment = opt_str(kzg_commitment, i);
+            let versioned =
+                blobs::checked(tx_hash.value(i), opt_str(versioned_hash, i), commitment)?;
+            let conn = db.conn(block_number.value(i))?;
+            conn.prepare_cached(&sql)?.execute(params![
+                norm.key("tx_hash", tx_hash.value(i))?,
+                blob_index.value(i),
                 block_number.value(i),
-                tx_hash.value(i),
-                log_index.value(i),
-                address.value(i),
-                opt_str(topic0, i),
-                opt_str(topic1, i),
-                opt_str(topic2, i),
-                opt_str(topic3, i),
-                opt_bin(data, i),
+                norm.key_value("versioned_hash", &versioned)?,
+                commitment.map(str::to_ascii_lowercase),
+                opt_str(kzg_proof, i).map(str::to_ascii_lowercase),
+                opt_bin(data, i).map(|d| norm.blob(d)).transpose()?,
+                opt_str(data_ref, i),
             ])?;
             count += 1;
         }
         pb.inc(batch.num_rows() as u64);
+        commits.batch(db, batch.num_rows(), count)?;
     }
 
-    pb.finish_with_message(format!("logs ✓ ({count})"));
+    pb.finish_with_message(format!("blobs ✓ ({count})"));
     Ok(count)
 }
 
@@ -306,43 +1085,968 @@ fn load_logs(conn: &Connection, path: &Path) -> Result<u64> {
 // Main
 // ---------------------------------------------------------------------------
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(error::exit_code(&e))
+        }
+    }
+}
+
+/// Parse the command line. The load flags only apply without a subcommand;
+/// the global ones (`--progress`, `-v`, `--quiet`, `--log-format`) go on
+/// either side of one.
+fn parse_cli(
+    argv: impl IntoIterator<Item = impl Into<std::ffi::OsString> + Clone>,
+) -> Result<(Cli, ArgMatches), clap::Error> {
+    let mut command = Cli::command();
+    let matches = command.try_get_matches_from_mut(argv)?;
+    if let Some((name, _)) = matches.subcommand() {
+        let load = command.get_arguments().filter(|a| !a.is_global_set()).find(|a| {
+            matches.value_source(a.get_id().as_str()) == Some(ValueSource::CommandLine)
+        });
+        if let Some(arg) = load {
+            let flag = arg.get_long().unwrap_or(arg.get_id().as_str());
+            return Err(command.error(
+                ErrorKind::ArgumentConflict,
+                format!("the subcommand '{name}' cannot be used with '--{flag}'"),
+            ));
+        }
+    }
+    let cli = Cli::from_arg_matches(&matches)?;
+    Ok((cli, matches))
+}
 
-    let blocks_path = args.data.join("blocks.parquet");
-    let txs_path    = args.data.join("transactions.parquet");
-    let logs_path   = args.data.join("logs.parquet");
+fn run() -> Result<()> {
+    let (mut cli, matches) = parse_cli(std::env::args_os()).unwrap_or_else(|e| e.exit());
+    logging::init(cli.verbose, cli.quiet, cli.log_format);
+    // Bench's loads draw no bars; its timings are the output.
+    let bench = matches!(cli.command, Some(Command::Bench(_)));
+    progress::set_mode(if cli.quiet || bench { ProgressMode::Hidden } else { cli.progress });
+    match cli.command {
+        Some(Command::Split(args)) => split::run(args),
+        Some(Command::Fetch(args)) => fetch::run(args),
+        Some(Command::Sample(args)) => sample::run(args),
+        Some(Command::Redact(args)) => redact::run(args),
+        Some(Command::Migrate(args)) => migrate::run(args),
+        Some(Command::Dedupe(args)) => dedupe::run(args),
+        Some(Command::Optimize(args)) => optimize::run(args),
+        Some(Command::Query(args)) => query::run(args),
+        Some(Command::Shell(args)) => shell::run(args),
+        Some(Command::Labels(args)) => labels::run(args),
+        Some(Command::Ens(args)) => ens::run(args),
+        Some(Command::Export(args)) => export::run(args),
+        Some(Command::Analyze(args)) => analyze::run(args),
+        Some(Command::Stats(args)) => stats::run(args),
+        Some(Command::Decode(args)) => protocols::run(args),
+        Some(Command::Hash(args)) => content_hash::run(args),
+        Some(Command::Inspect(args)) => inspect::run(args),
+        Some(Command::Call(args)) => call::run(args),
+        Some(Command::Verify(args)) => verify::run(args),
+        Some(Command::Generate(args)) => generate::run(args),
+        Some(Command::Bench(args)) => bench::run(args),
+        None => {
+            if let Some(path) = cli.load.config.clone() {
+                config::Job::read(&path)?.apply(&mut cli.load, &matches)?;
+            }
+            load(cli.load)
+        }
+    }
+}
 
-    for p in [&blocks_path, &txs_path, &logs_path] {
-        if !p.exists() {
-            eyre::bail!("Missing file: {}", p.display());
+/// `--from-date`/`--to-date` as `--from-block`/`--to-block`, from the
+/// timestamps in the dataset's blocks files.
+fn date_range(
+    args: &LoadArgs,
+    source: &DataSource,
+    files: &[(Table, String)],
+    read: &ReadOptions,
+) -> Result<(Option<i64>, Option<i64>)> {
+    if args.from_block.is_some() && args.from_date.is_some()
+        || args.to_block.is_some() && args.to_date.is_some()
+    {
+        // Set in a job file; clap only sees conflicts on the command line.
+        eyre::bail!("--from-date and --to-date replace --from-block and --to-block; give one");
+    }
+    if args.data.as_os_str() == "-" {
+        // Reading stdin for timestamps would leave nothing to load.
+        eyre::bail!("--from-date and --to-date need a dataset directory; use --from-block");
+    }
+    if args.watch {
+        // The blocks of a later part file aren't known yet.
+        eyre::bail!("--from-date and --to-date can't be combined with --watch");
+    }
+    if let (Some(from), Some(to)) = (args.from_date, args.to_date) {
+        if from >= to {
+            eyre::bail!("--from-date must be before --to-date");
         }
     }
+    let blocks: Vec<String> = if files.iter().any(|(t, _)| *t == Table::Blocks) {
+        files.iter().filter(|(t, _)| *t == Table::Blocks).map(|(_, f)| f.clone()).collect()
+    } else {
+        source.list(Table::Blocks.name())?
+    };
+    if blocks.is_empty() {
+        eyre::bail!(
+            "--from-date and --to-date need the dataset's blocks file for timestamps: {}",
+            source.display(Table::Blocks.file())
+        );
+    }
+    let tables = blocks.iter().map(|file| source.open_table(Table::Blocks, file, read));
+    let Some((first, last)) = filter::block_range(tables, args.from_date, args.to_date)? else {
+        eyre::bail!("No block in the dataset has a timestamp in the --from-date/--to-date range");
+    };
+    let from_block = args.from_date.map(|_| first);
+    let to_block = args.to_date.map(|_| last);
+    info!(?from_block, ?to_block, "resolved dates to blocks");
+    Ok((from_block, to_block))
+}
 
-    println!("Output: {}", args.out.display());
+/// The blocks whose transactions and logs aren't loaded (see reorg.rs),
+/// from a pre-pass over the blocks files; none are read unless they have an
+/// `is_canonical` column or --reorg-policy is prefer-latest.
+fn orphans(
+    args: &LoadArgs,
+    source: &DataSource,
+    files: &[(Table, String)],
+    read: &ReadOptions,
+) -> Result<HashSet<String>> {
+    let blocks: Vec<String> = if files.iter().any(|(t, _)| *t == Table::Blocks) {
+        files.iter().filter(|(t, _)| *t == Table::Blocks).map(|(_, f)| f.clone()).collect()
+    } else {
+        source.list(Table::Blocks.name())?
+    };
+    let mut marked = false;
+    for file in &blocks {
+        let schema = read.map.rename_schema(Table::Blocks, &source.meta(file)?.schema)?;
+        marked |= schema.field_with_name("is_canonical").is_ok();
+    }
+    if !marked && args.reorg_policy != ReorgPolicy::PreferLatest {
+        return Ok(HashSet::new());
+    }
+    let tables = blocks.iter().map(|file| source.open_table(Table::Blocks, file, read));
+    let orphans = reorg::orphans(tables, args.reorg_policy)?;
+    if !orphans.is_empty() {
+        info!(blocks = orphans.len(), "leaving out the transactions and logs of reorged blocks");
+    }
+    Ok(orphans)
+}
 
-    let conn = Connection::open(&args.out)
-        .with_context(|| format!("Cannot open {}", args.out.display()))?;
-    conn.execute_batch(SCHEMA)?;
+fn load(args: LoadArgs) -> Result<()> {
+    let from_stdin = args.data.as_os_str() == "-";
+    let to_stdout = args.out.as_os_str() == "-";
+    let in_memory = args.out.as_os_str() == ":memory:";
+    if args.watch && (from_stdin || to_stdout || in_memory) {
+        eyre::bail!("--watch needs a dataset directory and an output database file");
+    }
+    if args.emit_hash && (args.watch || to_stdout || args.shard_blocks.is_some()) {
+        // Set in a job file; clap only sees conflicts on the command line.
+        eyre::bail!("--emit-hash needs a single output database, not stdout, and no --watch");
+    }
+    if args.watch && args.vacuum_into.is_some() {
+        eyre::bail!("--vacuum-into runs after the load and can't be combined with --watch");
+    }
+
+    if from_stdin && !(args.only.is_empty() && args.skip_tables.is_empty()) {
+        // Set in a job file; clap only sees conflicts with --table on the command line.
+        eyre::bail!("--only and --skip select tables of a dataset directory; stdin has one");
+    }
+    let selected = |t: &Table| {
+        (args.only.is_empty() || args.only.contains(t)) && !args.skip_tables.contains(t)
+    };
+    let (source, tables) = match (from_stdin, args.table) {
+        (true, Some(table)) => (DataSource::stdin(table.file())?, vec![table]),
+        (false, None) => {
+            let source = DataSource::new(&args.data)?;
+            let mut tables: Vec<Table> = Vec::new();
+            for table in Table::ALL.into_iter().chain(Table::OPTIONAL).filter(selected) {
+                // An optional table loads when the dataset has it (or may, when watched).
+                let wanted = args.watch || args.only.contains(&table);
+                if !table.optional() || wanted || !source.list(table.name())?.is_empty() {
+                    tables.push(table);
+                }
+            }
+            if tables.is_empty() {
+                eyre::bail!("--skip leaves no tables to load");
+            }
+            (source, tables)
+        }
+        (false, Some(_)) => eyre::bail!("--table only applies when reading stdin (--data -)"),
+        (true, None) => eyre::bail!("--data - needs --table to say which table stdin contains"),
+    };
 
-    // Wrap all inserts in a single transaction per table for speed
-    conn.execute_batch("BEGIN;")?;
-    let blocks = load_blocks(&conn, &blocks_path)?;
-    conn.execute_batch("COMMIT;")?;
+    let skip: Vec<String> = if args.light {
+        schema::LIGHT_COLUMNS.iter().map(|c| c.to_string()).collect()
+    } else {
+        args.skip_columns.clone()
+    };
+    schema::check_skip_columns(&skip)?;
+    let read = ReadOptions {
+        skip: skip.clone(),
+        batch_size: args.batch_size as usize,
+        queue_size: args.queue_size,
+        read_threads: args.read_threads as usize,
+        max_memory: args.max_memory,
+        map: ColumnMap::new(args.map.clone(), args.chain),
+        commit_every: args.commit_every,
+    };
+    if args.commit_every.is_some() && (from_stdin || args.shard_blocks.is_some()) {
+        // Neither has a place to record how far a file got.
+        eyre::bail!("--commit-every needs a dataset directory and a single output database");
+    }
 
-    conn.execute_batch("BEGIN;")?;
-    let txs = load_transactions(&conn, &txs_path)?;
-    conn.execute_batch("COMMIT;")?;
+    if args.prices.is_some() {
+        if args.numeric_encoding != NumericEncoding::Decimal {
+            eyre::bail!(
+                "--prices needs --numeric-encoding decimal: the USD view reads amounts as numbers"
+            );
+        }
+        if args.shard_blocks.is_some() {
+            eyre::bail!("--prices can't be combined with --shard-blocks");
+        }
+        if args.binary_keys {
+            eyre::bail!("--prices can't be combined with --binary-keys");
+        }
+    }
+    if args.optimize.is_some() && args.shard_blocks.is_some() {
+        eyre::bail!("--optimize can't be combined with --shard-blocks; run `optimize` on each shard");
+    }
 
-    conn.execute_batch("BEGIN;")?;
-    let logs = load_logs(&conn, &logs_path)?;
-    conn.execute_batch("COMMIT;")?;
+    let indexes = Indexes { no_default: args.no_default_index, extra: args.index.clone() };
 
-    println!("\nDone.");
-    println!("  {blocks} blocks");
-    println!("  {txs} transactions");
-    println!("  {logs} logs");
-    println!("\nQuery with:  sqlite3 {}", args.out.display());
+    if args.dry_run {
+        return dry_run::run(&source, &tables, &read, &indexes, &mut std::io::stdout());
+    }
 
+    if let Some(addr) = &args.metrics_addr {
+        metrics::serve(addr)?;
+    }
+    let provenance = Provenance::read(&source)?;
+    if let Some(id) = provenance.chain_id {
+        args.chain.check_id(id, &source.display(manifest::MANIFEST_FILE))?;
+    }
+    let mut report = Report::new(
+        provenance.source.clone(),
+        provenance.manifest_sha256.clone(),
+        pragmas::settings(args.preset, &args.pragmas),
+    );
+
+    let segments = segments::discover(&source)?;
+    if segments.is_some() && (args.watch || args.shard_blocks.is_some() || args.views_only) {
+        eyre::bail!(
+            "A dataset with deltas can't be loaded with --watch, --shard-blocks or --views-only; \
+             rerun with --mode append to apply new deltas"
+        );
+    }
+
+    let mut files = Vec::new();
+    for &table in &tables {
+        let found = match &segments {
+            Some(segments) => segments.list(&source, table.name())?,
+            None => source.list(table.name())?,
+        };
+        if found.is_empty() && !args.watch {
+            eyre::bail!("Missing file: {}", source.display(table.file()));
+        }
+        files.extend(found.into_iter().map(|f| (table, f)));
+    }
+
+    let norm = Normalize {
+        numeric: args.numeric_encoding,
+        address: args.address_case,
+        topics_table: args.topics_table,
+        compress: args.compress,
+        binary_keys: args.binary_keys,
+        log_key: args.log_key,
+        chain: args.chain,
+        on_conflict: OnConflict::new(&args.on_conflict),
+        reorg: args.reorg_policy,
+    };
+    if norm.binary_keys && norm.address.is_some() {
+        // Set in a job file; clap only sees conflicts on the command line.
+        eyre::bail!("--address-case has no effect with --binary-keys");
+    }
+    let addresses = args
+        .address
+        .iter()
+        .map(|a| norm.address("--address", a).map(|a| a.into_owned()))
+        .collect::<Result<Vec<_>>>()?;
+    let (from_block, to_block) = if args.from_date.is_some() || args.to_date.is_some() {
+        date_range(&args, &source, &files, &read)?
+    } else {
+        (args.from_block, args.to_block)
+    };
+    let mut filter = Filter::new(from_block, to_block, &addresses);
+    if let (Some(from), Some(to)) = (args.from_block, args.to_block) {
+        if from > to {
+            eyre::bail!("--from-block ({from}) must not exceed --to-block ({to})");
+        }
+    }
+    if args.views_only {
+        if from_stdin || to_stdout || in_memory {
+            eyre::bail!("--views-only needs a dataset directory and an output file");
+        }
+        if args.watch || args.shard_blocks.is_some() || args.prices.is_some() {
+            // Set in a job file; clap only sees conflicts on the command line.
+            eyre::bail!("--views-only can't be combined with --watch, --shard-blocks or --prices");
+        }
+        if filter.addresses.is_some() {
+            eyre::bail!("--address filters rows as they're copied; --views-only copies none");
+        }
+        let sql = duckdb_views::script(&source, &tables, &read, from_block, to_block)?;
+        return duckdb_views::write(&sql, &args.out);
+    }
+    if filter.addresses.is_some() {
+        if args.watch {
+            // Transactions are kept based on logs that may not have arrived yet.
+            eyre::bail!("--address can't be combined with --watch");
+        }
+        // Even when logs aren't loaded, they say which transactions to keep.
+        let logs = if tables.contains(&Table::Logs) || from_stdin {
+            files.iter().filter(|(t, _)| *t == Table::Logs).map(|(_, f)| f.clone()).collect()
+        } else {
+            source.list(Table::Logs.name())?
+        };
+        for file in &logs {
+            filter.scan_logs(source.open_table(Table::Logs, file, &read)?)?;
+        }
+    }
+    if tables.iter().any(|&t| t != Table::Blocks) && !from_stdin {
+        filter.orphans = orphans(&args, &source, &files, &read)?;
+    }
+
+    if filter.from_block.is_some() || filter.to_block.is_some() || filter.addresses.is_some() {
+        info!(%filter, "filtering rows");
+    }
+
+    // Schema, provenance and pragmas, for the output database or every shard.
+    let (preset, pragmas) = (args.preset, args.pragmas.clone());
+    let with_views = args.views;
+    let strict = args.strict.then_some(norm);
+    let prices = args.prices.clone();
+    let partial = Table::ALL.iter().any(|t| !tables.contains(t));
+    let optional: Vec<Table> = tables.iter().copied().filter(|t| t.optional()).collect();
+    let setup = move |conn: &Connection| -> Result<()> {
+        pragmas::apply(conn, preset, &pragmas)?;
+        // One transaction, so an append refused by a check below leaves the
+        // database's _meta as it was.
+        let record = || -> Result<()> {
+            migrate::init(conn, strict)?;
+            indexes.create(conn)?;
+            provenance.record(conn)?;
+            norm.record(conn)?;
+            for &table in &optional {
+                let strict = migrate::is_strict(conn)?;
+                conn.execute_batch(&schema::optional_schema(table, &norm, strict))?;
+            }
+            Ok(())
+        };
+        conn.execute_batch("BEGIN;")?;
+        match record() {
+            Ok(()) => conn.execute_batch("COMMIT;")?,
+            Err(e) => {
+                conn.execute_batch("ROLLBACK;")?;
+                return Err(e);
+            }
+        }
+        if with_views {
+            views::install(conn)?;
+        }
+        if let Some(path) = &prices {
+            prices::import(conn, path)?;
+        }
+        if partial {
+            // A table on its own can't satisfy the cross-table REFERENCES.
+            conn.execute_batch("PRAGMA foreign_keys=OFF;")?;
+        }
+        Ok(())
+    };
+
+    let mode = args.mode.unwrap_or(if args.watch { Mode::Append } else { Mode::Create });
+    let mut db = if let Some(size) = args.shard_blocks {
+        if size == 0 {
+            eyre::bail!("--shard-blocks must be at least 1");
+        }
+        if args.watch || to_stdout || in_memory || args.vacuum_into.is_some() {
+            eyre::bail!(
+                "--shard-blocks writes a directory of databases; it can't be combined with \
+                 --watch, --vacuum-into or an --out of - or :memory:"
+            );
+        }
+        if args.out.join(output::SHARD_INDEX).exists() {
+            match mode {
+                Mode::Create => eyre::bail!(
+                    "{} already holds shards; use --mode append to add to them or \
+                     --mode overwrite to replace them",
+                    args.out.display()
+                ),
+                Mode::Append => info!(out = %args.out.display(), "appending to existing shards"),
+                Mode::Overwrite => {
+                    info!(out = %args.out.display(), "overwriting existing shards");
+                    Shards::remove(&args.out)?;
+                }
+            }
+        }
+        info!(out = %args.out.display(), shard_blocks = size, "writing sharded output");
+        let shards =
+            Shards::create(&args.out, size as i64, mode == Mode::Append, Box::new(setup))?;
+        Output::Sharded(shards)
+    } else {
+        let conn = if to_stdout || in_memory {
+            if in_memory && args.vacuum_into.is_none() {
+                warn!("--out :memory: without --vacuum-into: the database is discarded on exit");
+            }
+            Connection::open_in_memory()?
+        } else {
+            prepare_output(&args.out, mode)?;
+            info!(out = %args.out.display(), "opening output database");
+            Connection::open(&args.out)
+                .with_context(|| format!("Cannot open {}", args.out.display()))?
+        };
+        setup(&conn).with_context(|| format!("Cannot use {}", args.out.display()))?;
+        report.db_bytes_before(optimize::size(&conn)?.bytes);
+        Output::Single(conn)
+    };
+
+    let mut counts: Vec<(Table, u64)> = tables.iter().map(|&t| (t, 0)).collect();
+    let applied = match (&segments, &db) {
+        (Some(segments), Output::Single(conn)) => segments.applied(conn)?,
+        _ => None,
+    };
+    if let (Some(applied), Some(segments)) = (applied, &segments) {
+        info!(applied, last = segments.last().number, "applying the deltas after segment {applied}");
+    }
+    let mut todo = Vec::with_capacity(files.len());
+    for (table, file) in &files {
+        if applied.is_some_and(|n| segments::number(file) <= n) {
+            continue;
+        }
+        // With checkpoints, a rerun picks up where the last one stopped.
+        if (args.watch || args.commit_every.is_some() || segments.is_some())
+            && is_loaded(&db, &source, file)?
+        {
+            continue;
+        }
+        todo.push((*table, file));
+    }
+    if progress::enabled() {
+        let bytes = todo
+            .iter()
+            .map(|(table, file)| source.read_size(*table, file, &read))
+            .sum::<Result<u64>>()?;
+        progress::start_load(todo.len(), bytes);
+    }
+    for (table, file) in &todo {
+        let started = std::time::Instant::now();
+        let loaded = load_file(&mut db, &source, *table, file, &filter, &read, &norm)?;
+        report.file(*table, loaded.rows, loaded.bytes, started.elapsed());
+        if let Some(c) = counts.iter_mut().find(|(t, _)| t == table) {
+            c.1 += loaded.rows;
+        }
+    }
+    progress::finish_load();
+    if let (Some(segments), Output::Single(conn)) = (&segments, &db) {
+        segments.record(conn)?;
+        let last = segments.last();
+        info!(segment = last.number, to_block = last.block_range.end, "dataset segments applied");
+    }
+
+    for (table, rows) in counts {
+        info!(table = table.name(), rows, "loaded");
+    }
+
+    let conn = match db {
+        Output::Single(conn) => conn,
+        Output::Sharded(shards) => {
+            shards.finish()?;
+            if let Some(path) = &args.report {
+                report.write(path, &args.out, None)?;
+            }
+            return Ok(());
+        }
+    };
+
+    if let Some(level) = args.optimize {
+        optimize::optimize(&conn, level)?;
+    }
+    let db_bytes = optimize::size(&conn)?.bytes;
+    if let Some(path) = &args.vacuum_into {
+        vacuum_into(&conn, path, args.mode)?;
+    }
+
+    if to_stdout {
+        dump::dump(&conn, &mut BufWriter::new(std::io::stdout().lock()))?;
+        info!("SQL dump written to stdout");
+    } else if let Some(path) = args.vacuum_into.as_ref().filter(|_| in_memory) {
+        info!("done; query with: sqlite3 {}", path.display());
+    } else if !in_memory {
+        info!("done; query with: sqlite3 {}", args.out.display());
+    }
+    if let Some(path) = &args.report {
+        report.write(path, &args.out, Some(db_bytes))?;
+    }
+    if args.emit_hash {
+        println!("{}", content_hash::hash(&conn)?);
+    }
+
+    if args.watch {
+        let mut db = Output::Single(conn);
+        watch(&mut db, &source, &tables, &filter, &read, &norm, args.poll_interval)?;
+    }
+
+    Ok(())
+}
+
+/// Apply --mode to an existing output database.
+fn prepare_output(out: &std::path::Path, mode: Mode) -> Result<()> {
+    if !out.exists() {
+        return Ok(());
+    }
+    match mode {
+        Mode::Create => eyre::bail!(
+            "{} already exists; use --mode append to add to it or --mode overwrite to replace it",
+            out.display()
+        ),
+        Mode::Append => info!(out = %out.display(), "appending to existing database"),
+        Mode::Overwrite => {
+            info!(out = %out.display(), "overwriting existing database");
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = out.as_os_str().to_owned();
+                path.push(suffix);
+                match std::fs::remove_file(&path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("Cannot remove {}", out.display()))
+                    }
+                }
+            }
+        }
+    }
     Ok(())
 }
+
+/// Write a compacted copy of the database to `path`. VACUUM INTO needs a
+/// fresh target, so an existing file is only replaced with --mode overwrite.
+fn vacuum_into(conn: &Connection, path: &std::path::Path, mode: Option<Mode>) -> Result<()> {
+    if path.exists() && mode != Some(Mode::Overwrite) {
+        eyre::bail!("{} already exists; use --mode overwrite to replace it", path.display());
+    }
+    prepare_output(path, Mode::Overwrite)?;
+    conn.execute("VACUUM INTO ?1", [path.to_string_lossy()])
+        .with_context(|| format!("Cannot write {}", path.display()))?;
+    info!(path = %path.display(), "wrote compacted copy");
+    Ok(())
+}
+
+/// Where a load came from, recorded in _meta.
+struct Provenance {
+    source: String,
+    manifest_sha256: Option<String>,
+    /// The manifest's chain id, checked against --chain.
+    chain_id: Option<u64>,
+    loaded_at: u64,
+}
+
+impl Provenance {
+    fn read(source: &DataSource) -> Result<Self> {
+        let manifest = source.read(manifest::MANIFEST_FILE)?;
+        let chain_id = manifest.as_ref().and_then(|bytes| {
+            serde_json::from_slice::<serde_json::Value>(bytes).ok()?["chain_id"].as_u64()
+        });
+        Ok(Self {
+            source: source.display("").trim_end_matches('/').to_string(),
+            manifest_sha256: manifest.map(|bytes| format!("{:x}", Sha256::digest(&bytes))),
+            chain_id,
+            loaded_at: load_time()?,
+        })
+    }
+
+    fn record(&self, conn: &Connection) -> Result<()> {
+        migrate::set_meta(conn, "source", &self.source)?;
+        match &self.manifest_sha256 {
+            Some(hash) => migrate::set_meta(conn, "manifest_sha256", hash)?,
+            None => {
+                conn.execute("DELETE FROM _meta WHERE key = 'manifest_sha256'", [])?;
+            }
+        }
+        migrate::set_meta(conn, "loaded_at", &self.loaded_at.to_string())?;
+        Ok(())
+    }
+}
+
+/// When the load ran, in Unix seconds: SOURCE_DATE_EPOCH when set, so that a
+/// rebuild records the same time (see content_hash.rs).
+fn load_time() -> Result<u64> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => {
+            epoch.trim().parse().with_context(|| format!("Bad SOURCE_DATE_EPOCH {epoch:?}"))
+        }
+        Err(_) => Ok(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs()),
+    }
+}
+
+/// Rows written and compressed Parquet bytes read for one file.
+struct Loaded {
+    rows: u64,
+    bytes: u64,
+}
+
+/// Load one Parquet file inside its own transaction, so an interrupted or
+/// failed file leaves nothing behind, and record it in _loaded_files. With
+/// --commit-every the file is committed in steps instead, and a file an
+/// earlier run checkpointed continues after its last committed row.
+fn load_file(
+    db: &mut Output,
+    source: &DataSource,
+    table: Table,
+    file: &str,
+    filter: &Filter,
+    read: &ReadOptions,
+    norm: &Normalize,
+) -> Result<Loaded> {
+    let mut reader = source
+        .open_table(table, file, read)
+        .with_context(|| format!("Loading {}", source.display(file)))?;
+    let bytes = reader.bytes;
+    let name = table.name();
+    metrics::table(name);
+    // The rows of the batch being loaded, for errors.
+    let rows = Arc::new([AtomicU64::new(0), AtomicU64::new(0)]);
+    let seen = rows.clone();
+    reader.batches = Box::new(reader.batches.inspect(move |batch| {
+        if let Ok(batch) = batch {
+            metrics::rows(name, batch.num_rows());
+            let start = seen[1].load(Ordering::Relaxed);
+            seen[0].store(start, Ordering::Relaxed);
+            seen[1].store(start + batch.num_rows() as u64, Ordering::Relaxed);
+        }
+    }));
+    let key = format!("checkpoint.{}", source.key(file));
+    let resumed = checkpoint(db, &key)?.unwrap_or((0, 0));
+    if resumed.0 > 0 {
+        if resumed.0 > reader.total_rows {
+            eyre::bail!(
+                "{} has {} rows, but an earlier load committed {} of them; was it replaced?",
+                source.display(file),
+                reader.total_rows,
+                resumed.0
+            );
+        }
+        info!(file = %source.display(file), rows = resumed.0, "resuming after checkpoint");
+        reader.total_rows -= resumed.0;
+        reader.first_row = resumed.0;
+        reader.batches = skip_rows(reader.batches, resumed.0);
+    }
+    let mut commits = Commits { every: read.commit_every, key, resumed, read: 0, pending: 0 };
+    let row_groups = reader.row_groups.clone();
+    debug!(table = table.name(), file = %source.display(file), rows = reader.total_rows, "loading file");
+
+    db.begin()?;
+    let result = match table {
+        Table::Blocks => load_blocks(db, reader, filter, norm, &mut commits),
+        Table::Transactions => load_transactions(db, reader, filter, norm, &mut commits),
+        Table::Logs => load_logs(db, reader, filter, norm, &mut commits),
+        Table::Ommers => load_ommers(db, reader, filter, norm, &mut commits),
+        Table::Withdrawals => load_withdrawals(db, reader, filter, norm, &mut commits),
+        Table::Blobs => load_blobs(db, reader, filter, norm, &mut commits),
+    }
+    .and_then(|rows| {
+        // Sharded output has no single place to record files; it's never watched.
+        if let (Output::Single(conn), false) = (&*db, matches!(source, DataSource::Stdin { .. })) {
+            conn.execute(
+                "INSERT OR REPLACE INTO _loaded_files (location, table_name, rows, loaded_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![source.key(file), table.name(), (resumed.1 + rows) as i64, load_time()?],
+            )?;
+            conn.execute("DELETE FROM _meta WHERE key = ?1", [&commits.key])?;
+        }
+        Ok(rows)
+    });
+
+    match result {
+        Ok(rows) => {
+            db.commit()?;
+            metrics::file_loaded(name);
+            if let (Table::Logs, Output::Single(conn)) = (table, &*db) {
+                prices::refresh(conn)?;
+            }
+            Ok(Loaded { rows, bytes })
+        }
+        Err(e) => {
+            db.rollback()?;
+            let (start, end) = (rows[0].load(Ordering::Relaxed), rows[1].load(Ordering::Relaxed));
+            let e = if end > 0 {
+                e.wrap_err(format!("At rows {start}..{end}{}", row_group(&row_groups, start)))
+            } else {
+                e
+            };
+            Err(e.wrap_err(format!("Loading {}", source.display(file))))
+        }
+    }
+}
+
+/// " (row group N)" for the row group holding `row`.
+fn row_group(row_groups: &[u64], row: u64) -> String {
+    let mut end = 0;
+    for (i, rows) in row_groups.iter().enumerate() {
+        end += rows;
+        if row < end {
+            return format!(" (row group {i})");
+        }
+    }
+    String::new()
+}
+
+/// `batches` without their first `n` rows.
+fn skip_rows(batches: Batches, mut n: u64) -> Batches {
+    Box::new(batches.filter_map(move |batch| match batch {
+        Ok(batch) if n > 0 => {
+            let rows = batch.num_rows() as u64;
+            let skip = n.min(rows);
+            n -= skip;
+            (skip < rows).then(|| Ok(batch.slice(skip as usize, (rows - skip) as usize)))
+        }
+        other => Some(other),
+    }))
+}
+
+/// Rows read and written from the start of a file by earlier runs that
+/// committed part of it, from its `key` in _meta.
+fn checkpoint(db: &Output, key: &str) -> Result<Option<(u64, u64)>> {
+    let Output::Single(conn) = db else { return Ok(None) };
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM _meta WHERE key = ?1", [key], |r| r.get(0))
+        .optional()?;
+    let Some(value) = value else { return Ok(None) };
+    let parsed = value
+        .split_once(' ')
+        .and_then(|(read, written)| Some((read.parse().ok()?, written.parse().ok()?)));
+    parsed.map(Some).ok_or_else(|| eyre::eyre!("Invalid {key} in _meta: {value:?}"))
+}
+
+fn is_loaded(db: &Output, source: &DataSource, file: &str) -> Result<bool> {
+    let Output::Single(conn) = db else { return Ok(false) };
+    let n: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM _loaded_files WHERE location = ?1",
+        [source.key(file)],
+        |r| r.get(0),
+    )?;
+    Ok(n > 0)
+}
+
+/// Poll the dataset for part files that aren't in _loaded_files yet and append
+/// them, tables in dependency order. Runs until interrupted; since every file
+/// is its own transaction, Ctrl-C never leaves a half-loaded file behind.
+fn watch(
+    db: &mut Output,
+    source: &DataSource,
+    tables: &[Table],
+    filter: &Filter,
+    read: &ReadOptions,
+    norm: &Normalize,
+    interval: u64,
+) -> Result<()> {
+    info!(interval_secs = interval, "watching for new part files (Ctrl-C to stop)");
+    let mut not_ready = std::collections::HashSet::new();
+
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(interval));
+        for &table in tables {
+            for file in source.list(table.name())? {
+                if is_loaded(db, source, &file)? {
+                    continue;
+                }
+                match load_file(db, source, table, &file, filter, read, norm) {
+                    Ok(loaded) => {
+                        not_ready.remove(&file);
+                        info!(table = table.name(), file, rows = loaded.rows, "appended part file");
+                    }
+                    // Typically a part file still being written; retry next poll.
+                    Err(e) => {
+                        if not_ready.insert(file.clone()) {
+                            metrics::error();
+                            warn!(file, error = format!("{e:#}"), "part file not loadable yet");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &str) -> Result<Cli, clap::Error> {
+        parse_cli(argv.split_whitespace()).map(|(cli, _)| cli)
+    }
+
+    #[test]
+    fn global_flags_before_and_after_a_subcommand() {
+        for argv in [
+            "offline-replay -q --log-format json --progress json verify gas a.db",
+            "offline-replay verify gas a.db -q --log-format json --progress json",
+            "offline-replay --log-format json verify -q gas --progress json a.db",
+        ] {
+            let cli = parse(argv).unwrap_or_else(|e| panic!("{argv}: {e}"));
+            assert!(matches!(cli.command, Some(Command::Verify(_))), "{argv}");
+            assert!(cli.quiet, "{argv}");
+            assert_eq!(cli.log_format, LogFormat::Json, "{argv}");
+            assert_eq!(cli.progress, ProgressMode::Json, "{argv}");
+        }
+        let cli = parse("offline-replay -vv stats events a.db").unwrap();
+        assert_eq!(cli.verbose, 2);
+    }
+
+    #[test]
+    fn global_flags_with_a_load() {
+        let cli = parse("offline-replay --log-format json --data d --out o.db -q").unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.quiet);
+        assert_eq!(cli.log_format, LogFormat::Json);
+        assert_eq!(cli.load.out, PathBuf::from("o.db"));
+    }
+
+    #[test]
+    fn load_flags_conflict_with_a_subcommand() {
+        for argv in [
+            "offline-replay --views stats events a.db",
+            "offline-replay -q --out o.db verify gas a.db",
+        ] {
+            let e = parse(argv).err().unwrap_or_else(|| panic!("{argv} parsed"));
+            assert_eq!(e.kind(), ErrorKind::ArgumentConflict, "{argv}");
+        }
+    }
+
+    #[test]
+    fn an_interrupted_commit_every_load_resumes_at_its_checkpoint() {
+        use arrow::record_batch::RecordBatch;
+        use testing::{count, generate, load_into, rewrite, Scratch};
+
+        let dir = Scratch::new("resume");
+        let data = dir.join("data");
+        generate(&data, 20);
+        let flags = "--commit-every 500 --batch-size 100";
+        load_into(&data, &dir.join("whole.db"), flags).unwrap();
+
+        // A bad value two thirds of the way into the transactions stops the
+        // first run there, after some of its commits.
+        let file = data.join("transactions.parquet");
+        let original = std::fs::read(&file).unwrap();
+        let mut bad = 0;
+        rewrite(&file, |batch| {
+            bad = batch.num_rows() * 2 / 3;
+            let i = batch.schema().index_of("value").unwrap();
+            let values = batch.column(i).as_any().downcast_ref::<StringArray>().unwrap();
+            let values: StringArray = (0..values.len())
+                .map(|row| Some(if row == bad { "not a number" } else { values.value(row) }))
+                .collect();
+            let mut columns = batch.columns().to_vec();
+            columns[i] = Arc::new(values);
+            RecordBatch::try_new(batch.schema(), columns).unwrap()
+        });
+        let out = dir.join("resumed.db");
+        assert!(load_into(&data, &out, flags).is_err());
+        let conn = Connection::open(&out).unwrap();
+        let committed = count(&conn, "SELECT COUNT(*) FROM transactions");
+        assert!(committed > 0 && committed <= bad as i64, "{committed} of {bad} committed");
+        let checkpoint: String = conn
+            .query_row("SELECT value FROM _meta WHERE key LIKE 'checkpoint.%'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(checkpoint, format!("{committed} {committed}"));
+        drop(conn);
+
+        std::fs::write(&file, original).unwrap();
+        load_into(&data, &out, &format!("{flags} --mode append")).unwrap();
+        let whole = open_existing(&dir.join("whole.db")).unwrap();
+        let resumed = open_existing(&out).unwrap();
+        for table in ["blocks", "transactions", "logs"] {
+            let sql = format!("SELECT COUNT(*) FROM {table}");
+            assert_eq!(count(&whole, &sql), count(&resumed, &sql), "{table}");
+        }
+        assert_eq!(count(&resumed, "SELECT COUNT(*) FROM _meta WHERE key LIKE 'checkpoint.%'"), 0);
+        assert_eq!(content_hash::hash(&whole).unwrap(), content_hash::hash(&resumed).unwrap());
+    }
+
+    #[test]
+    fn reloading_overlapping_logs_under_each_conflict_action_and_log_key() {
+        use testing::{block, count, load_into, log, tx, write, Scratch};
+
+        let hash = |n: u64| format!("0x{n:064x}");
+        let (a, b) = (&hash(0xa), &hash(0xb));
+        // Logs as (tx, log_index, data): the second dataset corrects log 0,
+        // moves log 1 to the other transaction, and adds log 3.
+        let first: [(&String, i64, u8); 3] = [(a, 0, 1), (a, 1, 1), (b, 2, 1)];
+        let second = [(a, 0, 2u8), (b, 1, 2), (b, 2, 2), (b, 3, 2)];
+        let dir = Scratch::new("conflict");
+        for (name, logs) in [("first", &first[..]), ("second", &second[..])] {
+            let logs = logs
+                .iter()
+                .map(|&(tx, index, data)| {
+                    let mut l = log(tx, 1, index);
+                    l.topics[0] = Some(hash(data.into()));
+                    l.data = vec![data];
+                    l
+                })
+                .collect();
+            let txs = vec![tx(a, 1, 0), tx(b, 1, 1)];
+            write(&dir.join(name), vec![block(1, &hash(1), &hash(0))], txs, logs);
+        }
+
+        // (log key, action, whether the reload fails, the logs then stored).
+        type Logs<'a> = &'a [(&'a String, i64, u8)];
+        let cases: &[(&str, &str, bool, Logs)] = &[
+            ("full", "ignore", false, &[(a, 0, 1), (a, 1, 1), (b, 1, 2), (b, 2, 1), (b, 3, 2)]),
+            ("full", "replace", false, &[(a, 0, 2), (a, 1, 1), (b, 1, 2), (b, 2, 2), (b, 3, 2)]),
+            ("full", "fail", true, &first),
+            ("compact", "ignore", false, &[(a, 0, 1), (a, 1, 1), (b, 2, 1), (b, 3, 2)]),
+            ("compact", "replace", false, &[(a, 0, 2), (b, 1, 2), (b, 2, 2), (b, 3, 2)]),
+            ("compact", "fail", true, &first),
+            ("none", "replace", false, &[
+                (a, 0, 1), (a, 0, 2), (a, 1, 1), (b, 1, 2), (b, 2, 1), (b, 2, 2), (b, 3, 2),
+            ]),
+        ];
+        for &(key, action, fails, expected) in cases {
+            let case = format!("--log-key {key} --on-conflict logs={action}");
+            let db = dir.join(&format!("{key}-{action}.db"));
+            let flags = format!("--log-key {key} --topics-table");
+            load_into(&dir.join("first"), &db, &flags).unwrap();
+            let reload = load_into(
+                &dir.join("second"),
+                &db,
+                &format!("{flags} --mode append --on-conflict logs={action}"),
+            );
+            assert_eq!(reload.is_err(), fails, "{case}: {reload:?}");
+
+            let conn = open_existing(&db).unwrap();
+            let logs: Vec<(String, i64, u8)> = conn
+                .prepare("SELECT tx_hash, log_index, data FROM logs ORDER BY log_index, data")
+                .unwrap()
+                .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get::<_, Vec<u8>>(2)?[0])))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            let expected: Vec<(String, i64, u8)> =
+                expected.iter().map(|&(tx, i, d)| (tx.clone(), i, d)).collect();
+            assert_eq!(logs, expected, "{case}");
+            let miscounted = count(
+                &conn,
+                "SELECT COUNT(*) FROM transactions t
+                 WHERE log_count <> (SELECT COUNT(*) FROM logs WHERE tx_hash = t.hash)",
+            );
+            assert_eq!(miscounted, 0, "{case}: log_count");
+            // Each log has one topic, its topic0, and no other.
+            let topics = count(
+                &conn,
+                "SELECT COUNT(*) FROM log_topics JOIN logs ON logs.id = log_id
+                 WHERE position = 0 AND topic = logs.topic0",
+            );
+            assert_eq!(topics, expected.len() as i64, "{case}: log_topics");
+            assert_eq!(count(&conn, "SELECT COUNT(*) FROM log_topics"), topics, "{case}");
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Dataset manifests — a small JSON file describing what a dataset directory
+//! contains, so chunks can be distributed and loaded selectively.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TableEntry {
+    pub file: String,
+    pub rows: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub block_range: BlockRange,
+    /// The chain the blocks are from, where the writer knew it (`fetch`
+    /// asks the node); checked against a `--chain` preset by `load`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    pub tables: BTreeMap<String, TableEntry>,
+}
+
+impl Manifest {
+    pub fn new(block_range: BlockRange) -> Self {
+        Self { block_range, chain_id: None, tables: BTreeMap::new() }
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, json + "\n")
+            .with_context(|| format!("Cannot write {}", path.display()))
+    }
+}
@@ -0,0 +1,117 @@
+//! `--max-memory` — a ceiling on what a load holds in decoded record
+//! batches, so a file of huge log `data` blobs is loaded a few rows at a
+//! time instead of running the machine out of memory.
+//!
+//! Batches are held in up to `--queue-size` queue slots, one being filled
+//! by each `--read-threads` thread and one being inserted. For each file the
+//! loader estimates a row's size in memory from the footer — twice the
+//! uncompressed Parquet size of the columns read, for the Arrow arrays and
+//! the values the inserts make of them, in the row group with the largest
+//! rows — and gives the batches half the ceiling, leaving the rest to
+//! SQLite's page cache (see pragmas.rs) and the process. When the slots'
+//! batches don't fit, batches get fewer rows than `--batch-size`; when even
+//! one row per slot doesn't, threads and then queue slots are given up,
+//! down to one batch decoded on the inserting thread. The queues are
+//! bounded, so a writer that falls behind holds the readers back rather
+//! than letting batches pile up.
+//!
+//! It is an estimate: dictionary-encoded columns decode larger than their
+//! pages, and SQLite's cache is whatever the pragmas make it.
+
+use parquet::file::metadata::ParquetMetaData;
+use tracing::{debug, warn};
+
+use crate::source::ReadOptions;
+
+/// A size such as `16GiB`, `512M` or `1048576`: a whole number of bytes,
+/// optionally followed by K, M, G or T (powers of 1024), with or without a
+/// trailing `B` or `iB`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let t = s.trim();
+    let t = t.strip_suffix("iB").or_else(|| t.strip_suffix('B')).unwrap_or(t);
+    let digits = t.find(|c: char| !c.is_ascii_digit()).unwrap_or(t.len());
+    let (number, unit) = t.split_at(digits);
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("{s:?}: expected a size such as 512MiB or 16G")),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("{s:?}: expected a whole number"))?;
+    number
+        .checked_mul(1 << shift)
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("{s:?}: expected a size above 0 and below 16 EiB"))
+}
+
+/// `opts` with the batch size, queue and threads cut down so that the
+/// batches of a file with this footer fit into half of `max` bytes.
+/// `skip` names the (file) columns that aren't read.
+pub fn fit(
+    max: u64,
+    metadata: &ParquetMetaData,
+    skip: &[String],
+    opts: &ReadOptions,
+) -> ReadOptions {
+    let row = row_bytes(metadata, skip);
+    let budget = max / 2;
+    // Rows that fit at once, across every slot.
+    let rows = (budget / row.max(1)).max(1);
+    let slots = |queue: usize, threads: usize| if queue == 0 { 1 } else { queue + threads + 1 };
+    let (mut queue, mut threads) = (opts.queue_size, opts.read_threads);
+    while slots(queue, threads) as u64 > rows && (threads > 1 || queue > 0) {
+        if threads > 1 {
+            threads -= 1;
+        } else {
+            queue -= 1;
+        }
+    }
+    let batch_size = (rows / slots(queue, threads) as u64).clamp(1, opts.batch_size as u64);
+    if row > budget {
+        warn!(
+            row_bytes = row,
+            max_memory = max,
+            "a row alone is over half of --max-memory; loading one row at a time"
+        );
+    } else if (batch_size as usize, queue, threads)
+        != (opts.batch_size, opts.queue_size, opts.read_threads)
+    {
+        debug!(
+            row_bytes = row,
+            batch_size,
+            queue_size = queue,
+            read_threads = threads,
+            "fitted to --max-memory"
+        );
+    }
+    ReadOptions {
+        batch_size: batch_size as usize,
+        queue_size: queue,
+        read_threads: threads,
+        ..opts.clone()
+    }
+}
+
+/// Estimated bytes of a decoded row: twice the uncompressed size of the
+/// columns read, per row, in the row group where that is largest.
+fn row_bytes(metadata: &ParquetMetaData, skip: &[String]) -> u64 {
+    metadata
+        .row_groups()
+        .iter()
+        .filter(|rg| rg.num_rows() > 0)
+        .map(|rg| {
+            let bytes: i64 = rg
+                .columns()
+                .iter()
+                .filter(|c| {
+                    !c.column_path().parts().first().is_some_and(|root| skip.contains(root))
+                })
+                .map(|c| c.uncompressed_size())
+                .sum();
+            2 * bytes.max(0) as u64 / rg.num_rows() as u64
+        })
+        .max()
+        .unwrap_or(0)
+}
@@ -0,0 +1,184 @@
+//! `--metrics-addr 0.0.0.0:9100` — Prometheus metrics for long loads, served
+//! at `/metrics` from a thread of its own while the load (or --watch) runs:
+//!
+//!   offline_replay_rows_read_total{table}         rows read from Parquet
+//!   offline_replay_files_loaded_total{table}      files committed
+//!   offline_replay_errors_total                   files that failed to load
+//!   offline_replay_current_table{table}           1 for the table being loaded
+//!   offline_replay_rows_per_second                rows read since the last scrape,
+//!                                                 per second
+//!   offline_replay_last_progress_timestamp_seconds  when the last batch was read
+//!   offline_replay_start_time_seconds             when the load started
+//!
+//! A stalled load shows as `time() - offline_replay_last_progress_timestamp_seconds`
+//! growing; in --watch mode that is normal between part files. Errors only
+//! accumulate in --watch mode, where a file that fails is retried; anywhere
+//! else the first one ends the load.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use eyre::{Context, Result};
+use tracing::{debug, info};
+
+#[derive(Default)]
+struct State {
+    rows: BTreeMap<&'static str, u64>,
+    files: BTreeMap<&'static str, u64>,
+    errors: u64,
+    current: Option<&'static str>,
+    last_progress: f64,
+    /// Rows read and the time at the previous scrape, for the rate.
+    last_scrape: Option<(u64, Instant)>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    rows: BTreeMap::new(),
+    files: BTreeMap::new(),
+    errors: 0,
+    current: None,
+    last_progress: 0.0,
+    last_scrape: None,
+});
+
+/// Serve the metrics on `addr` until the process exits.
+pub fn serve(addr: &str) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("Cannot listen on {addr} for metrics"))?;
+    let started = (now(), Instant::now());
+    state().last_progress = started.0;
+    std::thread::Builder::new().name("metrics".into()).spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, started) {
+                debug!(error = %e, "metrics request failed");
+            }
+        }
+    })?;
+    info!(addr, "serving Prometheus metrics at /metrics");
+    Ok(())
+}
+
+/// `table` is being loaded.
+pub fn table(table: &'static str) {
+    state().current = Some(table);
+}
+
+pub fn rows(table: &'static str, n: usize) {
+    let mut state = state();
+    *state.rows.entry(table).or_default() += n as u64;
+    state.last_progress = now();
+}
+
+pub fn file_loaded(table: &'static str) {
+    let mut state = state();
+    *state.files.entry(table).or_default() += 1;
+    state.current = None;
+}
+
+pub fn error() {
+    let mut state = state();
+    state.errors += 1;
+    state.current = None;
+}
+
+fn state() -> std::sync::MutexGuard<'static, State> {
+    // The state stays consistent even if a holder panicked mid-update.
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn respond(stream: TcpStream, started: (f64, Instant)) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Read the headers, so closing the connection doesn't reset it.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/metrics" | "/" => ("200 OK", render(started)),
+        _ => ("404 Not Found", "not found; metrics are at /metrics\n".to_string()),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// The metrics in Prometheus' text exposition format.
+fn render(started: (f64, Instant)) -> String {
+    let mut state = state();
+    let total: u64 = state.rows.values().sum();
+    let rate = match state.last_scrape {
+        Some((rows, at)) => (total - rows) as f64 / at.elapsed().as_secs_f64().max(f64::EPSILON),
+        None => total as f64 / started.1.elapsed().as_secs_f64().max(f64::EPSILON),
+    };
+    state.last_scrape = Some((total, Instant::now()));
+
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{name}{labels} {value}");
+        }
+    };
+    let per_table = |counts: &BTreeMap<&'static str, u64>| -> Vec<(String, String)> {
+        counts.iter().map(|(t, n)| (format!("{{table=\"{t}\"}}"), n.to_string())).collect()
+    };
+    metric(
+        "offline_replay_rows_read_total",
+        "counter",
+        "Rows read from Parquet files.",
+        &per_table(&state.rows),
+    );
+    metric(
+        "offline_replay_files_loaded_total",
+        "counter",
+        "Parquet files loaded and committed.",
+        &per_table(&state.files),
+    );
+    metric(
+        "offline_replay_errors_total",
+        "counter",
+        "Files that failed to load.",
+        &[(String::new(), state.errors.to_string())],
+    );
+    let current: Vec<_> =
+        state.current.iter().map(|t| (format!("{{table=\"{t}\"}}"), "1".to_string())).collect();
+    metric("offline_replay_current_table", "gauge", "The table being loaded.", &current);
+    metric(
+        "offline_replay_rows_per_second",
+        "gauge",
+        "Rows read per second since the previous scrape.",
+        &[(String::new(), format!("{rate:.1}"))],
+    );
+    metric(
+        "offline_replay_last_progress_timestamp_seconds",
+        "gauge",
+        "Unix time the last record batch was read.",
+        &[(String::new(), format!("{:.3}", state.last_progress))],
+    );
+    metric(
+        "offline_replay_start_time_seconds",
+        "gauge",
+        "Unix time the load started.",
+        &[(String::new(), format!("{:.3}", started.0))],
+    );
+    out
+}
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
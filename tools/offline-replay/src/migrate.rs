@@ -0,0 +1,195 @@
+//! Schema versioning and the `migrate` subcommand.
+//!
+//! Every database records its schema version in `_meta`, next to the tool
+//! version, the source dataset and a hash of its manifest. Loading into a
+//! database with an older schema is refused; `offline-replay migrate <db>`
+//! brings it forward by applying the steps in [`MIGRATIONS`] in order.
+//!
+//! Adding to the schema: bump [`SCHEMA_VERSION`], update SCHEMA and
+//! STRICT_SCHEMA for fresh databases, and append the equivalent upgrade step
+//! here. Steps run on `--strict` databases too, so new columns need a type
+//! STRICT tables accept: INTEGER, REAL, TEXT, BLOB or ANY.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::info;
+
+use crate::normalize::Normalize;
+use crate::schema::{strict_schema, SCHEMA};
+
+pub const SCHEMA_VERSION: i64 = 7;
+
+/// `(version, sql)`: the statements that upgrade a database from
+/// `version - 1` to `version`. Version 0 is any database written before
+/// `_meta` existed.
+const MIGRATIONS: &[(i64, &str)] = &[
+    (
+        1,
+        "CREATE TABLE IF NOT EXISTS _loaded_files (
+             location   TEXT    PRIMARY KEY,
+             table_name TEXT    NOT NULL,
+             rows       INTEGER NOT NULL,
+             loaded_at  INTEGER NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS _meta (
+             key   TEXT PRIMARY KEY,
+             value TEXT NOT NULL
+         );",
+    ),
+    (
+        // fee_wei needs uint256 arithmetic and is left NULL for existing rows.
+        2,
+        "ALTER TABLE transactions ADD COLUMN fee_wei TEXT;
+         ALTER TABLE transactions ADD COLUMN log_count INTEGER NOT NULL DEFAULT 0;
+         UPDATE transactions SET log_count = c.n
+         FROM (SELECT tx_hash, COUNT(*) AS n FROM logs GROUP BY tx_hash) AS c
+         WHERE c.tx_hash = transactions.hash;",
+    ),
+    (3, "ALTER TABLE transactions ADD COLUMN logs_bloom BLOB;"),
+    (4, "ALTER TABLE transactions ADD COLUMN nonce INTEGER;"),
+    (
+        // Existing logs may hold duplicates, so they get no key; `dedupe`
+        // removes them and adds one.
+        5,
+        "INSERT OR REPLACE INTO _meta (key, value) VALUES ('log_key', 'none');",
+    ),
+    (
+        // The blocks table keeps its key on number, so only --reorg-policy
+        // drop loads into it (see reorg.rs).
+        6,
+        "ALTER TABLE blocks ADD COLUMN is_canonical INTEGER NOT NULL DEFAULT 1;
+         CREATE UNIQUE INDEX IF NOT EXISTS idx_blocks_canonical ON blocks(number)
+         WHERE is_canonical = 1;",
+    ),
+    (
+        // ANY, since a --strict database may store keys as BLOBs.
+        7,
+        "ALTER TABLE blocks ADD COLUMN miner ANY;",
+    ),
+];
+
+#[derive(Args, Debug)]
+pub struct MigrateArgs {
+    /// SQLite database to upgrade in place
+    db: PathBuf,
+}
+
+pub fn run(args: MigrateArgs) -> Result<()> {
+    if !args.db.is_file() {
+        eyre::bail!("{} does not exist", args.db.display());
+    }
+    let conn = Connection::open(&args.db)
+        .with_context(|| format!("Cannot open {}", args.db.display()))?;
+
+    let Some(from) = version(&conn)? else {
+        eyre::bail!("{} is not an offline-replay database", args.db.display());
+    };
+    if from > SCHEMA_VERSION {
+        eyre::bail!(
+            "{} has schema version {from}, newer than this build supports ({SCHEMA_VERSION})",
+            args.db.display()
+        );
+    }
+    if from == SCHEMA_VERSION {
+        info!(db = %args.db.display(), version = from, "already at the current schema version");
+        return Ok(());
+    }
+
+    for &(to, sql) in MIGRATIONS.iter().filter(|(v, _)| *v > from) {
+        conn.execute_batch("BEGIN;")?;
+        let step = conn
+            .execute_batch(sql)
+            .and_then(|()| set_meta(&conn, "schema_version", &to.to_string()));
+        match step {
+            Ok(()) => conn.execute_batch("COMMIT;")?,
+            Err(e) => {
+                conn.execute_batch("ROLLBACK;")?;
+                return Err(e).with_context(|| format!("Migrating to schema version {to}"));
+            }
+        }
+        info!(version = to, "applied migration");
+    }
+    set_meta(&conn, "tool_version", env!("CARGO_PKG_VERSION"))?;
+    info!(db = %args.db.display(), from, to = SCHEMA_VERSION, "migrated");
+    Ok(())
+}
+
+/// Schema version of `conn`: None for an empty database, 0 for one written
+/// before versioning.
+pub fn version(conn: &Connection) -> Result<Option<i64>> {
+    let has_table = |name: &str| -> rusqlite::Result<bool> {
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [name],
+            |r| r.get::<_, i64>(0),
+        )
+        .map(|n| n > 0)
+    };
+
+    if has_table("_meta")? {
+        let v: Option<String> = conn
+            .query_row("SELECT value FROM _meta WHERE key = 'schema_version'", [], |r| r.get(0))
+            .optional()?;
+        let v = v.unwrap_or_else(|| "0".to_string());
+        return Ok(Some(v.parse().with_context(|| format!("Bad schema_version in _meta: {v}"))?));
+    }
+    Ok(has_table("blocks")?.then_some(0))
+}
+
+/// Create the schema in an empty database, or check that an existing one is
+/// current and of the same kind.
+/// `strict` has the value encodings of a `--strict` schema, None for SCHEMA.
+pub fn init(conn: &Connection, strict: Option<Normalize>) -> Result<()> {
+    let schema = match strict {
+        Some(norm) => strict_schema(&norm).into(),
+        None => std::borrow::Cow::Borrowed(SCHEMA),
+    };
+    match version(conn)? {
+        None => {
+            conn.execute_batch(&schema)?;
+            set_meta(conn, "schema_version", &SCHEMA_VERSION.to_string())?;
+            if strict.is_some() {
+                set_meta(conn, "strict", "1")?;
+            }
+        }
+        Some(v) if v == SCHEMA_VERSION => {
+            match (is_strict(conn)?, strict.is_some()) {
+                (true, false) => {
+                    eyre::bail!("database was created with --strict; pass it to append to it")
+                }
+                (false, true) => {
+                    eyre::bail!("database was created without --strict; leave it out to append")
+                }
+                _ => conn.execute_batch(&schema)?,
+            }
+        }
+        Some(v) if v < SCHEMA_VERSION => eyre::bail!(
+            "database has schema version {v}; run `offline-replay migrate <db>` \
+             to upgrade it to {SCHEMA_VERSION}"
+        ),
+        Some(v) => eyre::bail!(
+            "database has schema version {v}, newer than this build supports ({SCHEMA_VERSION})"
+        ),
+    }
+    set_meta(conn, "tool_version", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
+
+/// Whether `conn` was created with `--strict`.
+pub fn is_strict(conn: &Connection) -> Result<bool> {
+    if version(conn)?.unwrap_or(0) == 0 {
+        return Ok(false);
+    }
+    Ok(conn
+        .query_row("SELECT value FROM _meta WHERE key = 'strict'", [], |r| r.get::<_, String>(0))
+        .optional()?
+        .is_some())
+}
+
+pub fn set_meta(conn: &Connection, key: &str, value: &str) -> rusqlite::Result<()> {
+    conn.execute("INSERT OR REPLACE INTO _meta (key, value) VALUES (?1, ?2)", params![key, value])?;
+    Ok(())
+}
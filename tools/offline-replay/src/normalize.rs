@@ -0,0 +1,373 @@
+//! Normalizing values as they are loaded, so datasets from different
+//! exporters end up in one consistent form.
+//!
+//! `--numeric-encoding` picks how uint256 amounts (`value`, `gas_price`,
+//! `base_fee`, `fee_wei`) are stored. Datasets carry them as decimal or 0x-hex text;
+//! either is accepted and re-encoded as:
+//!
+//!   decimal  decimal text, e.g. '1000000000000000' (the default)
+//!   hex      '0x' and 64 zero-padded hex digits, so text order is numeric order
+//!   blob32   32-byte big-endian BLOB, which compares and sorts numerically
+//!
+//! `--address-case` rewrites `from_addr`, `to_addr` and `logs.address` as
+//! lowercase or EIP-55 checksummed hex. Without it addresses are stored as the
+//! dataset has them, which breaks joins when exporters disagree on case.
+//!
+//! `--compress` stores calldata (`input`) and log `data`, most of a database's
+//! bytes, as zstd frames wherever that makes them smaller; a short blob, such
+//! as a single amount word, usually stays as it is. Stored blobs that start
+//! with the zstd magic number are frames (a raw blob that happens to start
+//! with it is always compressed). The commands here read them back
+//! transparently; other clients have to decompress them themselves.
+//!
+//! `--binary-keys` stores hashes, addresses and topics as their raw 32- or
+//! 20-byte BLOBs instead of 0x-hex text, which is less than half the size
+//! per value and makes the indexes on them smaller and faster to search.
+//! [`Normalize::key`] turns a 0x-hex value into the stored form and
+//! [`read_key`] reads either form back as 0x-hex; in SQL, `key('0x…')` and
+//! `hexkey(column)` do the same for `query` and `shell` (see [`functions`]).
+//!
+//! These settings are recorded in `_meta`; appending with different ones is
+//! refused, since a column mixing encodings can't be compared or joined.
+//!
+//! `--log-key` (see [`LogKey`]) is recorded the same way: an index is only
+//! unique for the rows it was built over. A database without a key gets one
+//! from `dedupe`.
+//!
+//! `--chain` (see chain.rs) is recorded too, since it adds columns to
+//! `transactions` and says which chain the rows are from; a database
+//! without it holds Ethereum transactions of an unnamed chain.
+//!
+//! `--topics-table` additionally writes each log's topics to `log_topics`
+//! (the topic columns of `logs` are kept). Turning it on for an existing
+//! database backfills the table; loading into one that has it without the
+//! flag is refused, since the table would silently fall behind.
+
+use std::borrow::Cow;
+
+use alloy_primitives::{hex, Address, U256};
+use clap::ValueEnum;
+use eyre::{eyre, Context, Result};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Deserialize;
+
+use crate::chain::Chain;
+use crate::conflict::OnConflict;
+use crate::migrate::set_meta;
+use crate::reorg::ReorgPolicy;
+use crate::schema::{LogKey, TOPICS_BACKFILL, TOPICS_SCHEMA};
+
+/// zstd's default level: most of the gain of higher levels at a fraction of
+/// the load time.
+const ZSTD_LEVEL: i32 = 3;
+
+/// The first four bytes of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Key columns holding a 20-byte address; the others hold 32-byte hashes.
+const ADDRESS_COLUMNS: [&str; 6] =
+    ["from_addr", "to_addr", "address", "refund_to", "beneficiary", "miner"];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumericEncoding {
+    /// 32-byte big-endian BLOB
+    Blob32,
+    /// 0x-prefixed, zero-padded to 64 hex digits
+    Hex,
+    /// Decimal text
+    #[default]
+    Decimal,
+}
+
+impl NumericEncoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            NumericEncoding::Blob32 => "blob32",
+            NumericEncoding::Hex => "hex",
+            NumericEncoding::Decimal => "decimal",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressCase {
+    /// 0x-prefixed lowercase hex
+    Lower,
+    /// EIP-55 mixed-case checksum
+    Checksum,
+}
+
+impl AddressCase {
+    pub fn name(self) -> &'static str {
+        match self {
+            AddressCase::Lower => "lower",
+            AddressCase::Checksum => "checksum",
+        }
+    }
+}
+
+/// How the loader rewrites values on their way into SQLite.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Normalize {
+    pub numeric: NumericEncoding,
+    /// None stores addresses unchanged.
+    pub address: Option<AddressCase>,
+    /// Also write topics to log_topics.
+    pub topics_table: bool,
+    /// zstd-compress `input` and `data`.
+    pub compress: bool,
+    /// Store hashes, addresses and topics as raw bytes.
+    pub binary_keys: bool,
+    /// The columns kept unique in logs.
+    pub log_key: LogKey,
+    /// Whose transaction fields are loaded (see chain.rs).
+    pub chain: Chain,
+    /// What inserts do with rows already in the table; not recorded.
+    pub on_conflict: OnConflict,
+    /// What happens to reorged blocks (see reorg.rs); not recorded.
+    pub reorg: ReorgPolicy,
+}
+
+impl Normalize {
+    /// The settings a database was loaded with, from its `_meta`.
+    pub fn from_meta(conn: &Connection) -> Result<Self> {
+        let get = |key: &str| -> Result<Option<String>> {
+            Ok(conn
+                .query_row("SELECT value FROM _meta WHERE key = ?1", [key], |r| r.get(0))
+                .optional()?)
+        };
+        let numeric = match get("numeric_encoding")? {
+            Some(v) => NumericEncoding::from_str(&v, false).map_err(|e| eyre!("_meta: {e}"))?,
+            None => NumericEncoding::default(),
+        };
+        let address = match get("address_case")? {
+            Some(v) => Some(AddressCase::from_str(&v, false).map_err(|e| eyre!("_meta: {e}"))?),
+            None => None,
+        };
+        // A database that predates the key has none.
+        let log_key = match get("log_key")? {
+            Some(v) => LogKey::from_str(&v, false).map_err(|e| eyre!("_meta: {e}"))?,
+            None => LogKey::None,
+        };
+        let chain = match get("chain")? {
+            Some(v) => Chain::from_str(&v, false).map_err(|e| eyre!("_meta: {e}"))?,
+            None => Chain::Ethereum,
+        };
+        Ok(Self {
+            numeric,
+            address,
+            topics_table: get("topics_table")?.is_some(),
+            compress: get("compression")?.is_some(),
+            binary_keys: get("key_encoding")?.as_deref() == Some("binary"),
+            log_key,
+            chain,
+            on_conflict: OnConflict::default(),
+            reorg: ReorgPolicy::default(),
+        })
+    }
+
+    /// Encode a uint256 amount read from `column`.
+    pub fn amount(&self, column: &str, s: &str) -> Result<Value> {
+        let n = parse_u256(s).with_context(|| format!("{column}: {s:?} is not a uint256"))?;
+        Ok(self.encode(n))
+    }
+
+    /// `gas_used * gas_price`, plus the L1 fee on a rollup (see chain.rs),
+    /// encoded like the other amounts.
+    pub fn fee(&self, gas_used: i64, gas_price: &str, l1_fee: Option<&str>) -> Result<Value> {
+        let price = parse_u256(gas_price)
+            .with_context(|| format!("gas_price: {gas_price:?} is not a uint256"))?;
+        let gas = U256::from(u64::try_from(gas_used).map_err(|_| eyre!("negative gas_used"))?);
+        let mut fee = price.checked_mul(gas).ok_or_else(|| eyre!("fee overflows uint256"))?;
+        if let Some(l1_fee) = l1_fee {
+            let l1_fee = parse_u256(l1_fee)
+                .with_context(|| format!("l1_fee: {l1_fee:?} is not a uint256"))?;
+            fee = fee.checked_add(l1_fee).ok_or_else(|| eyre!("fee overflows uint256"))?;
+        }
+        Ok(self.encode(fee))
+    }
+
+    pub fn encode(&self, n: U256) -> Value {
+        match self.numeric {
+            NumericEncoding::Decimal => Value::Text(n.to_string()),
+            NumericEncoding::Hex => Value::Text(format!("0x{}", hex::encode(n.to_be_bytes::<32>()))),
+            NumericEncoding::Blob32 => Value::Blob(n.to_be_bytes::<32>().to_vec()),
+        }
+    }
+
+    /// A calldata or log data blob as it is stored.
+    pub fn blob<'a>(&self, b: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if !self.compress || b.is_empty() {
+            return Ok(Cow::Borrowed(b));
+        }
+        let frame = zstd::bulk::compress(b, ZSTD_LEVEL)?;
+        Ok(if frame.len() < b.len() || b.starts_with(&ZSTD_MAGIC) {
+            Cow::Owned(frame)
+        } else {
+            Cow::Borrowed(b)
+        })
+    }
+
+    /// A stored calldata or log data blob, decompressed.
+    pub fn read_blob(&self, b: Vec<u8>) -> Result<Vec<u8>> {
+        if !self.compress || !b.starts_with(&ZSTD_MAGIC) {
+            return Ok(b);
+        }
+        zstd::stream::decode_all(b.as_slice()).context("corrupt zstd blob")
+    }
+
+    /// Normalize an address read from `column` (or given as a filter).
+    pub fn address<'a>(&self, column: &str, s: &'a str) -> Result<Cow<'a, str>> {
+        let Some(case) = self.address else { return Ok(Cow::Borrowed(s)) };
+        let addr: Address = s.parse().map_err(|_| eyre!("{column}: {s:?} is not an address"))?;
+        Ok(Cow::Owned(match case {
+            AddressCase::Lower => format!("0x{}", hex::encode(addr)),
+            AddressCase::Checksum => addr.to_checksum(None),
+        }))
+    }
+
+    /// A hash, address or topic read from `column` (or given as a filter),
+    /// as it is stored: its bytes with --binary-keys, otherwise the text,
+    /// with addresses normalized.
+    pub fn key<'a>(&self, column: &str, s: &'a str) -> Result<ToSqlOutput<'a>> {
+        if self.binary_keys {
+            let len = if ADDRESS_COLUMNS.contains(&column) { 20 } else { 32 };
+            let bytes = hex::decode(s).ok().filter(|b| b.len() == len);
+            let bytes = bytes.ok_or_else(|| eyre!("{column}: {s:?} is not {len} bytes of hex"))?;
+            return Ok(ToSqlOutput::Owned(Value::Blob(bytes)));
+        }
+        if !ADDRESS_COLUMNS.contains(&column) {
+            return Ok(ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes())));
+        }
+        Ok(match self.address(column, s)? {
+            Cow::Borrowed(s) => ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes())),
+            Cow::Owned(s) => ToSqlOutput::Owned(Value::Text(s)),
+        })
+    }
+
+    /// [`Normalize::key`] as an owned value, for a parameter list.
+    pub fn key_value(&self, column: &str, s: &str) -> Result<Value> {
+        Ok(match self.key(column, s)? {
+            ToSqlOutput::Owned(v) => v,
+            ToSqlOutput::Borrowed(v) => v.into(),
+            _ => Value::Text(s.to_string()),
+        })
+    }
+
+    /// Record the settings in `_meta`, refusing a database written with
+    /// different ones.
+    pub fn record(&self, conn: &Connection) -> Result<()> {
+        setting(conn, "numeric_encoding", "--numeric-encoding", Some(self.numeric.name()))?;
+        setting(conn, "address_case", "--address-case", self.address.map(AddressCase::name))?;
+        setting(conn, "compression", "--compress", self.compress.then_some("zstd"))?;
+        setting(conn, "key_encoding", "--binary-keys", self.binary_keys.then_some("binary"))?;
+        if self.log_key != LogKey::None && meta(conn, "log_key")?.as_deref() == Some("none") {
+            eyre::bail!(
+                "database has no log key, so it may hold duplicate logs; pass --log-key none \
+                 to append to it, or run `offline-replay dedupe` on it to add one"
+            );
+        }
+        setting(conn, "log_key", "--log-key", Some(self.log_key.name()))?;
+        self.log_key.create_index(conn)?;
+        let chain = (self.chain != Chain::Ethereum).then_some(self.chain.name());
+        let l2 = self.chain.kind() != Chain::Ethereum;
+        if let (Some(name), true, None) = (chain, l2, meta(conn, "chain")?) {
+            let empty: bool =
+                conn.query_row("SELECT NOT EXISTS (SELECT 1 FROM transactions)", [], |r| r.get(0))?;
+            if !empty {
+                eyre::bail!("database holds Ethereum transactions; load --chain {name} into a new one");
+            }
+        }
+        setting(conn, "chain", "--chain", chain)?;
+        self.chain.add_columns(conn, self)?;
+        self.reorg.check(conn)?;
+
+        let has_topics = conn
+            .query_row("SELECT value FROM _meta WHERE key = 'topics_table'", [], |r| {
+                r.get::<_, String>(0)
+            })
+            .optional()?
+            .is_some();
+        match (has_topics, self.topics_table) {
+            (true, false) => eyre::bail!(
+                "database has a log_topics table; pass --topics-table to keep it complete"
+            ),
+            (false, true) => {
+                conn.execute_batch(TOPICS_SCHEMA)?;
+                conn.execute_batch(TOPICS_BACKFILL)?;
+                set_meta(conn, "topics_table", "1")?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn meta(conn: &Connection, key: &str) -> Result<Option<String>> {
+    Ok(conn.query_row("SELECT value FROM _meta WHERE key = ?1", [key], |r| r.get(0)).optional()?)
+}
+
+fn setting(conn: &Connection, key: &str, flag: &str, value: Option<&str>) -> Result<()> {
+    match (meta(conn, key)?, value) {
+        (Some(e), v) if v != Some(e.as_str()) => {
+            eyre::bail!("database was loaded with {flag} {e}; pass the same to append to it")
+        }
+        (None, Some(v)) => set_meta(conn, key, v)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Read back an amount stored in any encoding.
+pub fn read_amount(v: ValueRef<'_>) -> Result<U256> {
+    match v {
+        ValueRef::Blob(b) if b.len() <= 32 => Ok(U256::from_be_slice(b)),
+        ValueRef::Text(t) => parse_u256(std::str::from_utf8(t)?),
+        ValueRef::Integer(i) if i >= 0 => Ok(U256::from(i as u64)),
+        other => Err(eyre!("not an amount: {other:?}")),
+    }
+}
+
+/// Read back a hash, address or topic stored either way, as 0x-hex text.
+pub fn read_key(v: ValueRef<'_>) -> Result<Option<String>> {
+    match v {
+        ValueRef::Null => Ok(None),
+        ValueRef::Blob(b) => Ok(Some(format!("0x{}", hex::encode(b)))),
+        ValueRef::Text(t) => Ok(Some(std::str::from_utf8(t)?.to_string())),
+        other => Err(eyre!("not a hash or address: {other:?}")),
+    }
+}
+
+/// SQL functions for querying keys in either form, so one query works on a
+/// database with or without --binary-keys:
+///
+///   key('0x…')     the value as `column = key(...)` compares it: its bytes
+///                  with --binary-keys (and a prefixed address as --address-case
+///                  stores it), otherwise the text
+///   hexkey(column) a stored key as 0x-hex text
+pub fn functions(conn: &Connection) -> Result<()> {
+    let norm = Normalize::from_meta(conn)?;
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("key", 1, flags, move |ctx| {
+        let Some(s) = ctx.get::<Option<String>>(0)? else { return Ok(Value::Null) };
+        let column = if s.len() == 42 { "address" } else { "hash" };
+        norm.key_value(column, &s).map_err(|e| rusqlite::Error::UserFunctionError(e.into()))
+    })?;
+    conn.create_scalar_function("hexkey", 1, flags, |ctx| {
+        read_key(ctx.get_raw(0)).map_err(|e| rusqlite::Error::UserFunctionError(e.into()))
+    })?;
+    Ok(())
+}
+
+/// Parse decimal or 0x-prefixed hex text.
+fn parse_u256(s: &str) -> Result<U256> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some("") => Ok(U256::ZERO),
+        Some(digits) => U256::from_str_radix(digits, 16).map_err(|e| eyre!("{e}")),
+        None => U256::from_str_radix(s, 10).map_err(|e| eyre!("{e}")),
+    }
+}
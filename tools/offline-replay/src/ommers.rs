@@ -0,0 +1,36 @@
+//! Uncle (ommer) blocks, for datasets covering pre-merge history where the
+//! rewards paid to uncle miners matter for miner accounting.
+//!
+//! A dataset may carry an `ommers.parquet` (or part files) next to its
+//! blocks, one row per uncle a block included; without one the table is
+//! not created. Each row is linked to its nephew by `block_number` (and
+//! `block_hash`, which leaves out the uncles of reorged blocks like their
+//! transactions, see reorg.rs) and keyed by its position `ommer_index` in
+//! the nephew's uncle list. `number` is the uncle's own height.
+//!
+//! `reward` is the uncle miner's reward in wei, as the file gives it. A
+//! file without one under `--chain mainnet` gets mainnet's:
+//! (8 + number - block_number) / 8 of the block reward of the nephew's era.
+//! The nephew's miner also earns 1/32 of a block reward per uncle; that is
+//! left to queries over `ommers` and `blocks`.
+
+use alloy_primitives::U256;
+
+use crate::chain::Chain;
+
+/// Mainnet's block reward in ether from each fork on: Frontier, Byzantium
+/// (EIP-649), Constantinople (EIP-1234). Uncles ended with the merge.
+const MAINNET_REWARDS: [(i64, u64); 3] = [(0, 5), (4_370_000, 3), (7_280_000, 2)];
+
+const WEI_PER_ETHER: u64 = 1_000_000_000_000_000_000;
+
+/// The reward of the miner of uncle `number` included by block `nephew`,
+/// where `chain` has a known schedule and the uncle is in reach.
+pub fn reward(chain: Chain, nephew: i64, number: i64) -> Option<U256> {
+    if chain != Chain::Mainnet || !(1..=6).contains(&(nephew - number)) {
+        return None;
+    }
+    let (_, ether) = MAINNET_REWARDS.iter().rev().find(|(from, _)| nephew >= *from)?;
+    let block = U256::from(*ether) * U256::from(WEI_PER_ETHER);
+    Some(block * U256::from(8 + number - nephew) / U256::from(8))
+}
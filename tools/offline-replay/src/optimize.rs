@@ -0,0 +1,81 @@
+//! `optimize` (and `--optimize` after a load) — get a database ready for
+//! ad-hoc queries.
+//!
+//! ANALYZE records per-index statistics in `sqlite_stat1`, so the query
+//! planner knows, say, that `idx_logs_topic0` is far more selective than a
+//! block range on a busy contract, instead of guessing. `vacuum` also
+//! rebuilds the file: free pages left by overwrites and deletes are dropped
+//! and tables are stored contiguously. VACUUM needs about the database's size
+//! again in free disk space while it runs.
+//!
+//! Both report the database's size before and after.
+
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use eyre::Result;
+use rusqlite::Connection;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::open_existing;
+
+#[derive(Args, Debug)]
+pub struct OptimizeArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Also VACUUM the database (rewrites the whole file)
+    #[arg(long)]
+    vacuum: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// ANALYZE only
+    Analyze,
+    /// ANALYZE, then VACUUM
+    Vacuum,
+}
+
+pub fn run(args: OptimizeArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    optimize(&conn, if args.vacuum { Level::Vacuum } else { Level::Analyze })?;
+    info!(db = %args.db.display(), "done");
+    Ok(())
+}
+
+pub fn optimize(conn: &Connection, level: Level) -> Result<()> {
+    let before = size(conn)?;
+    conn.execute_batch("ANALYZE;")?;
+    if level == Level::Vacuum {
+        conn.execute_batch("VACUUM;")?;
+    }
+    let after = size(conn)?;
+    info!(
+        vacuumed = level == Level::Vacuum,
+        before_bytes = before.bytes,
+        after_bytes = after.bytes,
+        free_pages_before = before.free_pages,
+        free_pages_after = after.free_pages,
+        "optimized"
+    );
+    Ok(())
+}
+
+pub(crate) struct Size {
+    pub bytes: i64,
+    pub free_pages: i64,
+}
+
+/// The database's size from its page counts (so it works for :memory: too).
+pub(crate) fn size(conn: &Connection) -> Result<Size> {
+    let pragma = |name: &str| -> Result<i64> {
+        Ok(conn.query_row(&format!("PRAGMA {name}"), [], |r| r.get(0))?)
+    };
+    Ok(Size {
+        bytes: pragma("page_count")? * pragma("page_size")?,
+        free_pages: pragma("freelist_count")?,
+    })
+}
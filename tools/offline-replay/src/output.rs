@@ -0,0 +1,204 @@
+//! Where loaded rows go: a single database, or with `--shard-blocks N` one
+//! database per N-block range.
+//!
+//! Sharded output is a directory of `<start>-<end>.db` files (ranges aligned to
+//! multiples of N, as with `split`) plus `shards.json`, which maps each block
+//! range to its file and row counts:
+//!
+//!   {"shard_blocks": 100000, "shards": [{"block_range": {...}, "tables": {...}}]}
+//!
+//! Rows are routed by block number, so a transaction and its logs always land
+//! in the same shard as their block.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use eyre::{Context, Result};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::manifest::{Manifest, TableEntry};
+use crate::schema::Table;
+use crate::split::chunk_range;
+
+pub const SHARD_INDEX: &str = "shards.json";
+
+/// Prepares a freshly opened database: schema, pragmas, _meta.
+pub type Setup = Box<dyn Fn(&Connection) -> Result<()>>;
+
+pub enum Output {
+    Single(Connection),
+    Sharded(Shards),
+}
+
+impl Output {
+    /// Connection that rows of `block` are written to.
+    pub fn conn(&mut self, block: i64) -> Result<&Connection> {
+        match self {
+            Output::Single(conn) => Ok(conn),
+            Output::Sharded(shards) => shards.conn(block),
+        }
+    }
+
+    /// Start a transaction. Shards open their own transactions lazily, when
+    /// their first row arrives.
+    pub fn begin(&mut self) -> Result<()> {
+        if let Output::Single(conn) = self {
+            conn.execute_batch("BEGIN;")?;
+        }
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        match self {
+            Output::Single(conn) => conn.execute_batch("COMMIT;")?,
+            Output::Sharded(shards) => shards.close(true)?,
+        }
+        Ok(())
+    }
+
+    pub fn rollback(&mut self) -> Result<()> {
+        match self {
+            Output::Single(conn) => conn.execute_batch("ROLLBACK;")?,
+            Output::Sharded(shards) => shards.close(false)?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShardIndex {
+    shard_blocks: i64,
+    shards: Vec<Manifest>,
+}
+
+pub struct Shards {
+    dir: PathBuf,
+    size: i64,
+    /// Runs on every shard as it is opened.
+    setup: Setup,
+    /// Shards with an open transaction, by range start.
+    open: BTreeMap<i64, Connection>,
+    /// Every shard in the index, by range start.
+    index: BTreeMap<i64, Manifest>,
+    /// Shards written to in this run, recounted on finish.
+    touched: Vec<i64>,
+}
+
+impl Shards {
+    /// Open (or with `append`, continue) a shard directory.
+    pub fn create(dir: &Path, size: i64, append: bool, setup: Setup) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create {}", dir.display()))?;
+
+        let mut index = BTreeMap::new();
+        let index_path = dir.join(SHARD_INDEX);
+        if append && index_path.exists() {
+            let text = std::fs::read_to_string(&index_path)
+                .with_context(|| format!("Cannot read {}", index_path.display()))?;
+            let existing: ShardIndex = serde_json::from_str(&text)
+                .with_context(|| format!("Invalid {}", index_path.display()))?;
+            if existing.shard_blocks != size {
+                eyre::bail!(
+                    "{} was sharded every {} blocks, not {size}",
+                    dir.display(),
+                    existing.shard_blocks
+                );
+            }
+            index = existing.shards.into_iter().map(|m| (m.block_range.start, m)).collect();
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            size,
+            setup,
+            open: BTreeMap::new(),
+            index,
+            touched: Vec::new(),
+        })
+    }
+
+    /// Remove the shard files listed in an existing index, and the index.
+    pub fn remove(dir: &Path) -> Result<()> {
+        let index_path = dir.join(SHARD_INDEX);
+        let text = std::fs::read_to_string(&index_path)
+            .with_context(|| format!("Cannot read {}", index_path.display()))?;
+        let existing: ShardIndex = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid {}", index_path.display()))?;
+        for m in &existing.shards {
+            let file = shard_file(m.block_range.start, m.block_range.end);
+            for suffix in ["", "-wal", "-shm"] {
+                let path = dir.join(format!("{file}{suffix}"));
+                if path.exists() {
+                    std::fs::remove_file(&path)
+                        .with_context(|| format!("Cannot remove {}", path.display()))?;
+                }
+            }
+        }
+        std::fs::remove_file(&index_path)
+            .with_context(|| format!("Cannot remove {}", index_path.display()))
+    }
+
+    fn conn(&mut self, block: i64) -> Result<&Connection> {
+        let range = chunk_range(block, self.size);
+        if !self.open.contains_key(&range.start) {
+            let path = self.dir.join(shard_file(range.start, range.end));
+            let conn = Connection::open(&path)
+                .with_context(|| format!("Cannot open {}", path.display()))?;
+            (self.setup)(&conn).with_context(|| format!("Cannot use {}", path.display()))?;
+            conn.execute_batch("BEGIN;")?;
+            self.open.insert(range.start, conn);
+            if !self.touched.contains(&range.start) {
+                self.touched.push(range.start);
+            }
+        }
+        Ok(&self.open[&range.start])
+    }
+
+    /// Commit (or roll back) every open shard and close it. Called after each
+    /// input file, so only the shards a file actually spans stay open.
+    fn close(&mut self, commit: bool) -> Result<()> {
+        for (_, conn) in std::mem::take(&mut self.open) {
+            conn.execute_batch(if commit { "COMMIT;" } else { "ROLLBACK;" })?;
+        }
+        Ok(())
+    }
+
+    /// Recount the shards written in this run and write shards.json.
+    pub fn finish(mut self) -> Result<()> {
+        self.close(true)?;
+        for &start in &self.touched {
+            let range = chunk_range(start, self.size);
+            let file = shard_file(range.start, range.end);
+            let conn = Connection::open(self.dir.join(&file))?;
+            let mut m = Manifest::new(range);
+            for table in Table::ALL {
+                let rows: i64 = conn.query_row(
+                    &format!("SELECT COUNT(*) FROM {}", table.name()),
+                    [],
+                    |r| r.get(0),
+                )?;
+                m.tables.insert(
+                    table.name().to_string(),
+                    TableEntry { file: file.clone(), rows: rows as u64 },
+                );
+            }
+            self.index.insert(start, m);
+        }
+
+        let index = ShardIndex {
+            shard_blocks: self.size,
+            shards: self.index.into_values().collect(),
+        };
+        let path = self.dir.join(SHARD_INDEX);
+        std::fs::write(&path, serde_json::to_string_pretty(&index)? + "\n")
+            .with_context(|| format!("Cannot write {}", path.display()))?;
+        info!(shards = index.shards.len(), index = %path.display(), "wrote shard index");
+        Ok(())
+    }
+}
+
+fn shard_file(start: i64, end: i64) -> String {
+    format!("{start}-{end}.db")
+}
@@ -0,0 +1,93 @@
+//! Connection pragmas for the output database: a `--preset`, then each
+//! `--pragma name=value` (and the config file's `[pragmas]`) in order, so a
+//! later setting overrides an earlier one.
+//!
+//!   (none)  journal_mode=WAL, synchronous=NORMAL, 64 MiB cache — readers can
+//!           query while a load or --watch is writing
+//!   bulk    no durability for speed: journal in memory, no fsync, 256 MiB
+//!           cache, temporary tables in memory. A crash or power loss
+//!           mid-load can leave the database corrupt; load again from scratch
+//!   safe    rollback journal, fsync on every commit. For network storage
+//!           (NFS, SMB), where WAL's shared-memory index doesn't work
+//!
+//! Pragmas are applied before the schema is created, so `page_size` and
+//! `auto_vacuum` take effect on a new database. `journal_mode` is stored in
+//! the file; the others last for the load only.
+
+use clap::ValueEnum;
+use eyre::{Context, Result};
+use rusqlite::Connection;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// Fastest; the database may be corrupt after a crash
+    Bulk,
+    /// Rollback journal and full fsync, for network filesystems
+    Safe,
+}
+
+const DEFAULT: &[(&str, &str)] =
+    &[("journal_mode", "WAL"), ("synchronous", "NORMAL"), ("cache_size", "-65536")];
+
+const BULK: &[(&str, &str)] = &[
+    ("journal_mode", "MEMORY"),
+    ("synchronous", "OFF"),
+    ("cache_size", "-262144"),
+    ("temp_store", "MEMORY"),
+];
+
+const SAFE: &[(&str, &str)] =
+    &[("journal_mode", "DELETE"), ("synchronous", "FULL"), ("cache_size", "-65536")];
+
+fn preset_pragmas(preset: Option<Preset>) -> &'static [(&'static str, &'static str)] {
+    match preset {
+        None => DEFAULT,
+        Some(Preset::Bulk) => BULK,
+        Some(Preset::Safe) => SAFE,
+    }
+}
+
+/// Apply `preset` (the default settings for None), then `extra` in order.
+pub fn apply(conn: &Connection, preset: Option<Preset>, extra: &[(String, String)]) -> Result<()> {
+    let extra = extra.iter().map(|(name, value)| (name.as_str(), value.as_str()));
+    for (name, value) in preset_pragmas(preset).iter().copied().chain(extra) {
+        conn.execute_batch(&format!("PRAGMA {name}={value};"))
+            .with_context(|| format!("PRAGMA {name}={value}"))?;
+    }
+    Ok(())
+}
+
+/// The value `apply` leaves each pragma at, in the order first set.
+pub fn settings(preset: Option<Preset>, extra: &[(String, String)]) -> Vec<(String, String)> {
+    let mut settings: Vec<(String, String)> = Vec::new();
+    let extra = extra.iter().map(|(name, value)| (name.as_str(), value.as_str()));
+    for (name, value) in preset_pragmas(preset).iter().copied().chain(extra) {
+        match settings.iter_mut().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+            Some(setting) => setting.1 = value.to_string(),
+            None => settings.push((name.to_string(), value.to_string())),
+        }
+    }
+    settings
+}
+
+/// Parse `--pragma name=value`. Numbers and bare words (`OFF`, `WAL`) are
+/// used as they are; anything else is quoted as a string.
+pub fn parse(s: &str) -> Result<(String, String), String> {
+    let (name, value) =
+        s.split_once('=').ok_or_else(|| format!("expected NAME=VALUE, got {s:?}"))?;
+    let (name, value) = (name.trim(), value.trim());
+    check_name(name)?;
+    let bare = !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    let value = if bare { value.to_string() } else { format!("'{}'", value.replace('\'', "''")) };
+    Ok((name.to_string(), value))
+}
+
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("Invalid pragma name: {name:?}"));
+    }
+    Ok(())
+}
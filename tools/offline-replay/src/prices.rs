@@ -0,0 +1,235 @@
+//! `--prices prices.csv` — token/USD prices loaded into a `prices` table, and
+//! a `v_transfers_usd` view pricing every transfer.
+//!
+//! The CSV has a header row with `token`, `timestamp` and `price_usd`, and
+//! optionally `decimals` (default 18):
+//!
+//!   token,timestamp,price_usd,decimals
+//!   eth,1678665600,1598.12,18
+//!   0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48,2023-03-13,1.0,6
+//!
+//! `token` is a token address, or `eth` for ether; `timestamp` is Unix seconds
+//! or an ISO 8601 date/time (UTC unless it has an offset). A transfer is
+//! priced at its token's latest price at or before its block's timestamp.
+//!
+//! The view covers ETH moved by successful transactions and `erc20_transfers`
+//! (which is why a load with --prices decodes the transfers as logs arrive);
+//! swaps show up as the transfers in and out of the pool.
+
+use std::path::Path;
+
+use eyre::{eyre, Context, Result};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::decode;
+use crate::schema::PRICES_SCHEMA;
+
+const VIEW: &str = "
+DROP VIEW IF EXISTS v_transfers_usd;
+
+CREATE VIEW v_transfers_usd AS
+WITH transfers AS (
+    SELECT t.block_number, t.hash AS tx_hash, NULL AS log_index, 'eth' AS token,
+           t.from_addr, t.to_addr, t.value AS amount
+    FROM transactions t
+    WHERE t.status = 1 AND t.to_addr IS NOT NULL AND t.value <> '0'
+    UNION ALL
+    SELECT block_number, tx_hash, log_index, lower(token), from_addr, to_addr, amount
+    FROM erc20_transfers
+)
+SELECT x.*,
+       b.timestamp,
+       p.price_usd,
+       CAST(x.amount AS REAL) * p.usd_per_unit AS amount_usd
+FROM transfers x
+JOIN blocks b ON b.number = x.block_number AND b.is_canonical = 1
+LEFT JOIN prices p ON p.token = x.token AND p.timestamp = (
+    SELECT MAX(timestamp) FROM prices WHERE token = x.token AND timestamp <= b.timestamp
+);
+";
+
+#[derive(Deserialize)]
+struct Price {
+    token: String,
+    timestamp: String,
+    price_usd: f64,
+    #[serde(default)]
+    decimals: Option<u8>,
+}
+
+/// Load a price file into `prices` (replacing prices already there for the
+/// same token and timestamp) and install the view.
+pub fn import(conn: &Connection, path: &Path) -> Result<()> {
+    conn.execute_batch(PRICES_SCHEMA)?;
+    decode::erc20_transfers(conn)?;
+
+    let mut reader =
+        csv::Reader::from_path(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    let mut stmt = conn.prepare(
+        "INSERT OR REPLACE INTO prices (token, timestamp, price_usd, decimals, usd_per_unit, source)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    let source = path.file_name().map(|n| n.to_string_lossy().into_owned());
+    let mut rows = 0;
+    for (i, price) in reader.deserialize().enumerate() {
+        let row = || format!("{} row {}", path.display(), i + 1);
+        let price: Price = price.map_err(|e| eyre!("{}: {e}", row()))?;
+        let token = price.token.trim().to_ascii_lowercase();
+        if token != "eth" && !(token.len() == 42 && token.starts_with("0x")) {
+            eyre::bail!("{}: {:?} is not a token address or \"eth\"", row(), price.token);
+        }
+        let timestamp = timestamp(price.timestamp.trim())
+            .ok_or_else(|| eyre!("{}: can't read {:?} as a timestamp", row(), price.timestamp))?;
+        let decimals = price.decimals.unwrap_or(18);
+        let usd_per_unit = price.price_usd / 10f64.powi(decimals.into());
+        stmt.execute(params![token, timestamp, price.price_usd, decimals, usd_per_unit, source])?;
+        rows += 1;
+    }
+
+    conn.execute_batch(VIEW)?;
+    info!(file = %path.display(), rows, "imported prices");
+    Ok(())
+}
+
+/// Catch `erc20_transfers` up with newly loaded logs, if the database has
+/// prices for the view to join.
+pub fn refresh(conn: &Connection) -> Result<()> {
+    let priced: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'prices'",
+        [],
+        |r| r.get(0),
+    )?;
+    if priced {
+        decode::erc20_transfers(conn)?;
+    }
+    Ok(())
+}
+
+/// Unix seconds, given as such or as an ISO 8601 / RFC 3339 date/time:
+/// `YYYY-MM-DD`, optionally followed by `T` or a space, `HH:MM[:SS[.f]]`
+/// and `Z` or an offset `+HH:MM`/`-HH:MM`, UTC without one. Fractions of
+/// a second are dropped.
+pub(crate) fn timestamp(s: &str) -> Option<i64> {
+    if let Ok(secs) = s.parse() {
+        return Some(secs);
+    }
+    if !s.is_ascii() {
+        return None;
+    }
+    let (date, time) = match s.find(['T', 't', ' ']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    if date.len() != 10 || &date[4..5] != "-" || &date[7..8] != "-" {
+        return None;
+    }
+    let (year, month, day) = (digits(&date[..4])?, digits(&date[5..7])?, digits(&date[8..])?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_days = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    if !(1..=12).contains(&month) || !(1..=month_days[month as usize - 1]).contains(&day) {
+        return None;
+    }
+    let mut secs = days_since_epoch(year, month, day) * 86_400;
+    let Some(time) = time else { return Some(secs) };
+
+    let (clock, offset) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else if let Some(i) = time.find(['+', '-']) {
+        let sign = if &time[i..i + 1] == "-" { -1 } else { 1 };
+        (&time[..i], sign * clock_minutes(&time[i + 1..])? * 60)
+    } else {
+        (time, 0)
+    };
+    let (clock, second) = match clock.len() {
+        5 => (clock, 0),
+        8.. if &clock[5..6] == ":" => {
+            let fraction = &clock[8..];
+            let digits_only = |f: &str| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit());
+            if !fraction.is_empty() && !fraction.strip_prefix('.').is_some_and(digits_only) {
+                return None;
+            }
+            (&clock[..5], digits(&clock[6..8]).filter(|&s| s < 60)?)
+        }
+        _ => return None,
+    };
+    secs += clock_minutes(clock)? * 60 + second - offset;
+    Some(secs)
+}
+
+/// A run of ASCII digits.
+fn digits(s: &str) -> Option<i64> {
+    (!s.is_empty() && s.len() <= 9 && s.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| s.parse().ok())?
+}
+
+/// `HH:MM` as minutes.
+fn clock_minutes(s: &str) -> Option<i64> {
+    if s.len() != 5 || &s[2..3] != ":" {
+        return None;
+    }
+    let (hours, minutes) = (digits(&s[..2])?, digits(&s[3..])?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+    // Howard Hinnant's days_from_civil: years start in March, so the leap
+    // day ends them.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        for (s, secs) in [
+            ("1678665600", 1_678_665_600),
+            ("2023-03-13", 1_678_665_600),
+            ("1970-01-01", 0),
+            ("1969-12-31", -86_400),
+            ("2024-02-29", 1_709_164_800),
+            ("2000-03-01", 951_868_800),
+            ("2023-03-13T00:00:00Z", 1_678_665_600),
+            ("2023-03-13t01:02z", 1_678_669_320),
+            ("2023-03-13 01:02:03", 1_678_669_323),
+            ("2023-03-13T01:02:03.999Z", 1_678_669_323),
+            ("2023-03-13T01:02:03.123456789012Z", 1_678_669_323),
+            ("2023-03-13T02:00:00+02:00", 1_678_665_600),
+            ("2023-03-12T19:30:00-04:30", 1_678_665_600),
+        ] {
+            assert_eq!(timestamp(s), Some(secs), "{s}");
+        }
+        for s in [
+            "",
+            "now",
+            "2023-3-13",
+            "2023/03/13",
+            "2023-13-01",
+            "2023-02-29",
+            "2023-04-31",
+            "2023-03-13T",
+            "2023-03-13T1:02",
+            "2023-03-13T24:00",
+            "2023-03-13T01:60",
+            "2023-03-13T01:02:60",
+            "2023-03-13T01:02:03.",
+            "2023-03-13T01:02:03,5",
+            "2023-03-13T01:02Zz",
+            "2023-03-13T01:02+0200",
+            "2023-03-13T01:02+",
+            "2023-03-13 01:02 UTC",
+            "2023-03-13T01:02:03.5é",
+        ] {
+            assert_eq!(timestamp(s), None, "{s}");
+        }
+    }
+}
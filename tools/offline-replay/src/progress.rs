@@ -0,0 +1,354 @@
+//! Progress reporting: the interactive indicatif bar, or with `--progress json`
+//! one JSON object per line on stderr for CI jobs and orchestrators:
+//!
+//!   {"event":"progress","table":"logs","rows_done":8192,"rows_total":1795000,"bytes_done":524288,"bytes_total":98610000,"rate":41230.5,"eta_secs":43.3}
+//!   {"event":"done","table":"logs","rows_done":1795000,"rows_total":1795000,"bytes_done":98610000,"bytes_total":98610000,"elapsed_secs":43.6}
+//!   {"event":"progress","table":"all","files_done":1,"files_total":3,"bytes_done":1048576,"bytes_total":120400000,"rate":2400000.0,"eta_secs":49.7}
+//!
+//! A loaded file's progress is measured in the compressed Parquet bytes read
+//! (see [`file_bar`]), row group by row group, as well as rows: rows say
+//! little about the time left when some logs carry megabytes of data and
+//! others none. Above the files' bars, a load shows one line for all of its
+//! files and tables (`"table":"all"` in JSON), from the bytes their footers
+//! say will be read. `rate` is rows/s for a file and bytes/s for the load.
+//!
+//! Progress events are emitted at most once per second per table.
+
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde_json::json;
+
+use crate::source::TableReader;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProgressMode {
+    #[default]
+    Bar,
+    Json,
+    /// Nothing at all (--quiet).
+    #[value(skip)]
+    Hidden,
+}
+
+static MODE: OnceLock<ProgressMode> = OnceLock::new();
+
+/// The bars of a load, under its overall line.
+static MULTI: OnceLock<MultiProgress> = OnceLock::new();
+
+/// The overall line of the load in progress, if any.
+static OVERALL: Mutex<Option<Overall>> = Mutex::new(None);
+
+const JSON_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Select how every progress reporter created afterwards is rendered.
+pub fn set_mode(mode: ProgressMode) {
+    let _ = MODE.set(mode);
+}
+
+fn mode() -> ProgressMode {
+    MODE.get().copied().unwrap_or_default()
+}
+
+/// Whether progress is shown at all, so a caller can skip working out totals.
+pub fn enabled() -> bool {
+    mode() != ProgressMode::Hidden
+}
+
+pub struct Progress {
+    view: View,
+    total: u64,
+    done: u64,
+    bytes: Option<ByteMeter>,
+}
+
+enum View {
+    Bar(ProgressBar),
+    Json(JsonProgress),
+}
+
+pub struct JsonProgress {
+    table: String,
+    started: Instant,
+    last: Instant,
+}
+
+/// Rows read to compressed bytes read, row group by row group; within a row
+/// group, in proportion to its rows.
+struct ByteMeter {
+    /// (rows, bytes) of each row group.
+    groups: Vec<(u64, u64)>,
+    /// Rows before the first one counted, when resuming.
+    first_row: u64,
+    total: u64,
+    /// Bytes at `first_row`.
+    start: u64,
+    done: u64,
+}
+
+impl ByteMeter {
+    fn at(&self, row: u64) -> u64 {
+        let (mut rows, mut bytes) = (0, 0);
+        for &(group_rows, group_bytes) in &self.groups {
+            if row < rows + group_rows {
+                return bytes + group_bytes * (row - rows) / group_rows;
+            }
+            rows += group_rows;
+            bytes += group_bytes;
+        }
+        bytes
+    }
+}
+
+pub fn progress_bar(total: u64, msg: &str) -> Progress {
+    let view = match mode() {
+        ProgressMode::Bar => {
+            let pb = ProgressBar::new(total);
+            pb.set_style(
+                ProgressStyle::with_template("{msg:20} [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
+                    .unwrap()
+                    .progress_chars("=> "),
+            );
+            pb.set_message(msg.to_string());
+            View::Bar(pb)
+        }
+        ProgressMode::Hidden => View::Bar(ProgressBar::hidden()),
+        ProgressMode::Json => View::Json(JsonProgress::new(msg)),
+    };
+    Progress { view, total, done: 0, bytes: None }
+}
+
+/// Progress through a file being loaded, by the compressed bytes of its row
+/// groups, with its rows alongside.
+pub fn file_bar(msg: &str, reader: &TableReader) -> Progress {
+    let groups: Vec<(u64, u64)> =
+        reader.row_groups.iter().copied().zip(reader.row_group_bytes.iter().copied()).collect();
+    let mut meter = ByteMeter {
+        groups,
+        first_row: reader.first_row,
+        total: reader.row_group_bytes.iter().sum(),
+        start: 0,
+        done: 0,
+    };
+    meter.start = meter.at(meter.first_row);
+    meter.done = meter.start;
+    add_overall(meter.start);
+
+    let view = match mode() {
+        ProgressMode::Bar => {
+            let pb = ProgressBar::new(meter.total);
+            pb.set_style(
+                ProgressStyle::with_template(
+                    "{msg:20} [{bar:40.cyan/blue}] {bytes}/{total_bytes}, {prefix} rows ({eta})",
+                )
+                .unwrap()
+                .progress_chars("=> "),
+            );
+            pb.set_message(msg.to_string());
+            pb.set_prefix(format!("0/{}", reader.total_rows));
+            pb.set_position(meter.start);
+            let pb = match (MULTI.get(), OVERALL.lock().unwrap().is_some()) {
+                (Some(multi), true) => multi.add(pb),
+                _ => pb,
+            };
+            View::Bar(pb)
+        }
+        ProgressMode::Hidden => View::Bar(ProgressBar::hidden()),
+        ProgressMode::Json => View::Json(JsonProgress::new(msg)),
+    };
+    Progress { view, total: reader.total_rows, done: 0, bytes: Some(meter) }
+}
+
+impl JsonProgress {
+    fn new(table: &str) -> Self {
+        let now = Instant::now();
+        Self { table: table.to_string(), started: now, last: now }
+    }
+
+    /// Whether a second has passed since the last event.
+    fn due(&mut self) -> bool {
+        let due = self.last.elapsed() >= JSON_INTERVAL;
+        if due {
+            self.last = Instant::now();
+        }
+        due
+    }
+}
+
+impl Progress {
+    pub fn inc(&mut self, n: u64) {
+        self.done += n;
+        let delta = self.bytes.as_mut().map(|meter| {
+            let now = meter.at(meter.first_row + self.done).max(meter.done);
+            let delta = now - meter.done;
+            meter.done = now;
+            delta
+        });
+        if let Some(delta) = delta {
+            add_overall(delta);
+        }
+        match &mut self.view {
+            View::Bar(pb) => match (&self.bytes, delta) {
+                (Some(meter), Some(_)) => {
+                    pb.set_position(meter.done);
+                    pb.set_prefix(format!("{}/{}", self.done, self.total));
+                }
+                _ => pb.inc(n),
+            },
+            View::Json(p) => {
+                if p.due() {
+                    let secs = p.started.elapsed().as_secs_f64();
+                    let rate = self.done as f64 / secs;
+                    let eta = match &self.bytes {
+                        Some(m) => eta(secs, m.done - m.start, m.total.saturating_sub(m.done)),
+                        None => eta(secs, self.done, self.total.saturating_sub(self.done)),
+                    };
+                    let mut event = json!({
+                        "event": "progress",
+                        "table": p.table,
+                        "rows_done": self.done,
+                        "rows_total": self.total,
+                    });
+                    if let Some(m) = &self.bytes {
+                        event["bytes_done"] = m.done.into();
+                        event["bytes_total"] = m.total.into();
+                    }
+                    event["rate"] = round(rate).into();
+                    event["eta_secs"] = eta.map(round).into();
+                    emit(event);
+                }
+            }
+        }
+    }
+
+    /// Finish the bar with `msg`; in JSON mode emit the final "done" event.
+    pub fn finish_with_message(&self, msg: impl Into<std::borrow::Cow<'static, str>>) {
+        if let Some(m) = &self.bytes {
+            // Rows filtered out or a last row group read short still count.
+            add_overall(m.total.saturating_sub(m.done));
+            if let Some(overall) = OVERALL.lock().unwrap().as_mut() {
+                overall.files_done += 1;
+            }
+        }
+        match &self.view {
+            View::Bar(pb) => {
+                if let Some(m) = &self.bytes {
+                    pb.set_position(m.total);
+                }
+                pb.finish_with_message(msg)
+            }
+            View::Json(p) => {
+                let mut event = json!({
+                    "event": "done",
+                    "table": p.table,
+                    "rows_done": self.done,
+                    "rows_total": self.total,
+                });
+                if let Some(m) = &self.bytes {
+                    event["bytes_done"] = m.total.into();
+                    event["bytes_total"] = m.total.into();
+                }
+                event["elapsed_secs"] = round(p.started.elapsed().as_secs_f64()).into();
+                emit(event);
+            }
+        }
+    }
+}
+
+/// The line for a whole load.
+struct Overall {
+    view: View,
+    files_total: usize,
+    files_done: usize,
+    bytes_total: u64,
+    bytes_done: u64,
+    started: Instant,
+}
+
+/// Show the overall line of a load of `files` files, `bytes` compressed
+/// bytes in all; the files' bars ([`file_bar`]) add to it.
+pub fn start_load(files: usize, bytes: u64) {
+    let view = match mode() {
+        ProgressMode::Hidden => return,
+        ProgressMode::Bar => {
+            let pb = ProgressBar::new(bytes);
+            pb.set_style(
+                ProgressStyle::with_template(
+                    "{msg:20} [{bar:40.green/blue}] {bytes}/{total_bytes}, {binary_bytes_per_sec} ({eta})",
+                )
+                .unwrap()
+                .progress_chars("=> "),
+            );
+            pb.set_message(format!("all ({files} files)"));
+            View::Bar(MULTI.get_or_init(MultiProgress::new).add(pb))
+        }
+        ProgressMode::Json => View::Json(JsonProgress::new("all")),
+    };
+    *OVERALL.lock().unwrap() = Some(Overall {
+        view,
+        files_total: files,
+        files_done: 0,
+        bytes_total: bytes,
+        bytes_done: 0,
+        started: Instant::now(),
+    });
+}
+
+/// Finish the overall line; files loaded afterwards (by --watch) get bars
+/// of their own.
+pub fn finish_load() {
+    let Some(overall) = OVERALL.lock().unwrap().take() else { return };
+    match &overall.view {
+        View::Bar(pb) => pb.finish_with_message(format!("all ✓ ({} files)", overall.files_done)),
+        View::Json(p) => emit(json!({
+            "event": "done",
+            "table": p.table,
+            "files_done": overall.files_done,
+            "files_total": overall.files_total,
+            "bytes_done": overall.bytes_done,
+            "bytes_total": overall.bytes_total,
+            "elapsed_secs": round(p.started.elapsed().as_secs_f64()),
+        })),
+    }
+}
+
+fn add_overall(bytes: u64) {
+    let mut overall = OVERALL.lock().unwrap();
+    let Some(o) = overall.as_mut() else { return };
+    o.bytes_done = (o.bytes_done + bytes).min(o.bytes_total);
+    match &mut o.view {
+        View::Bar(pb) => pb.set_position(o.bytes_done),
+        View::Json(p) => {
+            if p.due() {
+                let secs = o.started.elapsed().as_secs_f64();
+                emit(json!({
+                    "event": "progress",
+                    "table": p.table,
+                    "files_done": o.files_done,
+                    "files_total": o.files_total,
+                    "bytes_done": o.bytes_done,
+                    "bytes_total": o.bytes_total,
+                    "rate": round(o.bytes_done as f64 / secs),
+                    "eta_secs": eta(secs, o.bytes_done, o.bytes_total - o.bytes_done).map(round),
+                }));
+            }
+        }
+    }
+}
+
+/// Seconds left at the rate `done` took `secs`, if any was done.
+fn eta(secs: f64, done: u64, left: u64) -> Option<f64> {
+    (done > 0).then(|| secs * left as f64 / done as f64)
+}
+
+fn round(v: f64) -> f64 {
+    (v * 10.0).round() / 10.0
+}
+
+fn emit(event: serde_json::Value) {
+    let mut err = std::io::stderr().lock();
+    let _ = writeln!(err, "{event}");
+}
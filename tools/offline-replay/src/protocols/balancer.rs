@@ -0,0 +1,207 @@
+//! The Balancer V2 Vault, which holds every pool's tokens and so logs every
+//! pool's swaps, joins and exits, and its flash loans:
+//!
+//!   balancer_swaps       Swap, by `pool_id`
+//!   balancer_liquidity   PoolBalanceChanged (joins and exits), by `pool_id`
+//!   balancer_flashloans  FlashLoan
+//!
+//! PoolBalanceChanged logs arrays, which an event signature in the
+//! `--decoders` format can't describe: a `balancer_liquidity` row's `tokens`,
+//! `deltas` (signed; positive for a join) and `protocol_fee_amounts` are
+//! JSON arrays in the pool's token order, amounts as decimal strings.
+
+use alloy_primitives::{hex, I256, U256};
+use eyre::Result;
+use rusqlite::types::Value;
+
+use super::{Column, EventDecoder, Log, ProtocolDecoder};
+use crate::decode;
+use crate::normalize::Normalize;
+
+const VAULT: &str = "0xba12222222228d8ba445958a75a0704d566bf2c8";
+
+pub fn decoders() -> Result<Vec<Box<dyn ProtocolDecoder>>> {
+    let event = |table: &str, event: &str| {
+        EventDecoder::new("balancer".into(), table.into(), event, vec![VAULT.into()])
+    };
+    Ok(vec![
+        Box::new(
+            event(
+                "balancer_swaps",
+                "Swap(bytes32 indexed poolId, address indexed tokenIn, address indexed tokenOut, \
+                 uint256 amountIn, uint256 amountOut)",
+            )?
+            .with_indexes(vec!["pool_id".into()])?,
+        ),
+        Box::new(PoolBalanceChanged::new()),
+        Box::new(event(
+            "balancer_flashloans",
+            "FlashLoan(address indexed recipient, address indexed token, uint256 amount, \
+             uint256 feeAmount)",
+        )?),
+    ])
+}
+
+struct PoolBalanceChanged {
+    topic0: String,
+    columns: Vec<Column>,
+    indexes: Vec<String>,
+}
+
+impl PoolBalanceChanged {
+    fn new() -> Self {
+        let column = |name: &str| Column { name: name.into(), sql_type: "TEXT", nullable: false };
+        Self {
+            topic0: decode::topic(
+                "PoolBalanceChanged(bytes32,address,address[],int256[],uint256[])",
+            ),
+            columns: ["pool_id", "liquidity_provider", "tokens", "deltas", "protocol_fee_amounts"]
+                .into_iter()
+                .map(column)
+                .collect(),
+            indexes: vec!["pool_id".into()],
+        }
+    }
+}
+
+/// The `i`th dynamic array of ABI-encoded `data`, as its words; None if the
+/// offset or length points outside it.
+fn array(data: &[u8], i: usize) -> Option<Vec<[u8; 32]>> {
+    let word = |at: usize| -> Option<usize> {
+        let w = U256::from_be_slice(data.get(at..at.checked_add(32)?)?);
+        (w <= U256::from(data.len())).then(|| w.to::<usize>())
+    };
+    let offset = word(i * 32)?;
+    let len = word(offset)?;
+    (0..len)
+        .map(|k| {
+            let at = offset + 32 + k * 32;
+            data.get(at..at + 32).map(|w| w.try_into().expect("32 bytes"))
+        })
+        .collect()
+}
+
+impl ProtocolDecoder for PoolBalanceChanged {
+    fn protocol(&self) -> &str {
+        "balancer"
+    }
+
+    fn table(&self) -> &str {
+        "balancer_liquidity"
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn topics(&self) -> Vec<String> {
+        vec![self.topic0.clone()]
+    }
+
+    fn matches(&self, address: &str, topic0: &str) -> bool {
+        address == VAULT && topic0 == self.topic0
+    }
+
+    fn indexes(&self) -> &[String] {
+        &self.indexes
+    }
+
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>> {
+        let [pool_id, provider] = log.topics.as_slice() else { return Ok(None) };
+        let Some(provider) = decode::topic_address(&provider.to_ascii_lowercase()) else {
+            return Ok(None);
+        };
+        let (Some(tokens), Some(deltas), Some(fees)) =
+            (array(&log.data, 0), array(&log.data, 1), array(&log.data, 2))
+        else {
+            return Ok(None);
+        };
+        if deltas.len() != tokens.len() || fees.len() != tokens.len() {
+            return Ok(None);
+        }
+        let tokens = tokens
+            .iter()
+            .map(|w| {
+                let token = format!("0x{}", hex::encode(&w[12..]));
+                Ok(norm.address("tokens", &token)?.into_owned())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let deltas: Vec<String> =
+            deltas.iter().map(|w| I256::from_raw(U256::from_be_bytes(*w)).to_string()).collect();
+        let fees: Vec<String> = fees.iter().map(|w| U256::from_be_bytes(*w).to_string()).collect();
+        let json = |v: &[String]| Value::Text(serde_json::to_string(v).expect("strings serialize"));
+        Ok(Some(vec![
+            Value::Text(pool_id.to_ascii_lowercase()),
+            Value::Text(norm.address("topic2", &provider)?.into_owned()),
+            json(&tokens),
+            json(&deltas),
+            json(&fees),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::catch_up;
+    use crate::protocols::tests::{db, log, padded, row, rows, word};
+
+    const POOL_ID: &str = "0x5c6ee304399dbdb9c8ef030ab642b10820db8f56000200000000000000000014";
+    const BAL: &str = "0xba100000625a3754423978a60c9317c58a424e3d";
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+
+    /// ABI data of signed words.
+    fn data(words: &[i128]) -> Vec<u8> {
+        words.iter().flat_map(|&w| word(I256::try_from(w).unwrap().into_raw())).collect()
+    }
+
+    fn address_word(address: &str) -> Vec<u8> {
+        hex::decode(padded(address)).unwrap()
+    }
+
+    #[test]
+    fn swaps_and_flash_loans() {
+        let conn = db();
+        let swap = "0x2170c741c41531aec20e7c107c24eecfdd15e69c9bb0a8dd37b1840b9e0b207b";
+        let flash = "0x0d7d75e01ab95780d3cd1c8ec0dd6c2ce19e3a20427eec8bf53283b6fb8e95f0";
+        let eth = 10i128.pow(18);
+        log(&conn, VAULT, &[swap, POOL_ID, &padded(WETH), &padded(BAL)], &data(&[eth, 250 * eth]));
+        let recipient = "0x00000000000000000000000000000000000000f1";
+        log(&conn, VAULT, &[flash, &padded(recipient), &padded(WETH)], &data(&[1000 * eth, 0]));
+        // Not the Vault.
+        log(&conn, recipient, &[swap, POOL_ID, &padded(WETH), &padded(BAL)], &data(&[1, 1]));
+        catch_up(&conn, &["balancer"]).unwrap();
+        let (one, many) = (eth.to_string(), (250 * eth).to_string());
+        assert_eq!(rows(&conn, "balancer_swaps"), [row(&[POOL_ID, WETH, BAL, &one, &many])]);
+        let lent = (1000 * eth).to_string();
+        assert_eq!(rows(&conn, "balancer_flashloans"), [row(&[recipient, WETH, &lent, "0"])]);
+    }
+
+    #[test]
+    fn pool_balance_changes() {
+        let conn = db();
+        let changed = "0xe5ce249087ce04f05a957192435400fd97868dba0e6a4b4c049abf8af80dae78";
+        let provider = "0x00000000000000000000000000000000000000a1";
+        let eth = 10i128.pow(18);
+        // Offsets of the three arrays, then each as its length and words.
+        let changes = [
+            data(&[96, 192, 288, 2]),
+            address_word(BAL),
+            address_word(WETH),
+            data(&[2, -40 * eth, -eth / 10, 2, 0, 3]),
+        ]
+        .concat();
+        log(&conn, VAULT, &[changed, POOL_ID, &padded(provider)], &changes);
+        // Arrays of different lengths.
+        let mut uneven = changes.clone();
+        uneven[288 + 31] = 1;
+        log(&conn, VAULT, &[changed, POOL_ID, &padded(provider)], &uneven);
+        catch_up(&conn, &["balancer"]).unwrap();
+        let tokens = format!(r#"["{BAL}","{WETH}"]"#);
+        let deltas = format!(r#"["{}","{}"]"#, -40 * eth, -eth / 10);
+        assert_eq!(
+            rows(&conn, "balancer_liquidity"),
+            [row(&[POOL_ID, provider, &tokens, &deltas, r#"["0","3"]"#])]
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! The built-in decoders, in the `--decoders` format, each protocol behind a
+//! cargo feature of its name.
+//!
+//!   uniswap    V2 pair swaps, mints, burns and syncs; V3 pool swaps, mints,
+//!              burns, initializations and flash loans (from any pair or
+//!              pool, forks included; see analyze/v3_pool.rs)
+//!   aave       V2 LendingPool deposits, withdrawals, borrows, repays,
+//!              liquidations and flash loans
+//!   curve      see curve.rs
+//!   balancer   see balancer.rs
+//!   erc4337    see erc4337.rs
+//!   erc4626    see erc4626.rs
+//!   chainlink  price feed answers and rounds, from any aggregator (the
+//!              contract behind a feed's proxy, which is what logs them)
+//!   dydx       see dydx.rs
+//!   euler      deposits, withdrawals, borrows, repays and liquidations, and
+//!              the eToken mints and reserve donations of the March 2023
+//!              exploit (see analyze/euler.rs)
+//!   proxy      see proxy.rs
+
+#[cfg(feature = "uniswap")]
+const UNISWAP: &str = r#"
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v2_swaps"
+event = "Swap(address indexed sender, uint256 amount0In, uint256 amount1In, uint256 amount0Out, uint256 amount1Out, address indexed to)"
+
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v2_mints"
+event = "Mint(address indexed sender, uint256 amount0, uint256 amount1)"
+
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v2_burns"
+event = "Burn(address indexed sender, uint256 amount0, uint256 amount1, address indexed to)"
+
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v2_syncs"
+event = "Sync(uint112 reserve0, uint112 reserve1)"
+
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v3_swaps"
+event = "Swap(address indexed sender, address indexed recipient, int256 amount0, int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)"
+
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v3_mints"
+event = "Mint(address sender, address indexed owner, int24 indexed tickLower, int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)"
+
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v3_burns"
+event = "Burn(address indexed owner, int24 indexed tickLower, int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)"
+
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v3_initializations"
+event = "Initialize(uint160 sqrtPriceX96, int24 tick)"
+
+[[decoder]]
+protocol = "uniswap"
+table = "uniswap_v3_flashes"
+event = "Flash(address indexed sender, address indexed recipient, uint256 amount0, uint256 amount1, uint256 paid0, uint256 paid1)"
+"#;
+
+#[cfg(feature = "aave")]
+const AAVE: &str = r#"
+[[decoder]]
+protocol = "aave"
+table = "aave_v2_deposits"
+contracts = ["0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9"]
+event = "Deposit(address indexed reserve, address user, address indexed onBehalfOf, uint256 amount, uint16 indexed referral)"
+
+[[decoder]]
+protocol = "aave"
+table = "aave_v2_withdrawals"
+contracts = ["0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9"]
+event = "Withdraw(address indexed reserve, address indexed user, address indexed to, uint256 amount)"
+
+[[decoder]]
+protocol = "aave"
+table = "aave_v2_borrows"
+contracts = ["0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9"]
+event = "Borrow(address indexed reserve, address user, address indexed onBehalfOf, uint256 amount, uint256 borrowRateMode, uint256 borrowRate, uint16 indexed referral)"
+
+[[decoder]]
+protocol = "aave"
+table = "aave_v2_repays"
+contracts = ["0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9"]
+event = "Repay(address indexed reserve, address indexed user, address indexed repayer, uint256 amount)"
+
+[[decoder]]
+protocol = "aave"
+table = "aave_v2_liquidations"
+contracts = ["0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9"]
+event = "LiquidationCall(address indexed collateralAsset, address indexed debtAsset, address indexed user, uint256 debtToCover, uint256 liquidatedCollateralAmount, address liquidator, bool receiveAToken)"
+
+[[decoder]]
+protocol = "aave"
+table = "aave_v2_flash_loans"
+contracts = ["0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9"]
+event = "FlashLoan(address indexed target, address indexed initiator, address indexed asset, uint256 amount, uint256 premium, uint16 referralCode)"
+"#;
+
+/// `oracle_prices` is what a feed answered, and from which round on; its
+/// `contract` is the aggregator.
+#[cfg(feature = "chainlink")]
+const CHAINLINK: &str = r#"
+[[decoder]]
+protocol = "chainlink"
+table = "oracle_prices"
+event = "AnswerUpdated(int256 indexed answer, uint256 indexed roundId, uint256 updatedAt)"
+indexes = ["contract"]
+
+[[decoder]]
+protocol = "chainlink"
+table = "oracle_rounds"
+event = "NewRound(uint256 indexed roundId, address indexed startedBy, uint256 startedAt)"
+indexes = ["contract"]
+"#;
+
+/// Emitted by the Euler contract; the eToken and dToken proxies only emit
+/// their ERC-20 events.
+#[cfg(feature = "euler")]
+const EULER: &str = r#"
+[[decoder]]
+protocol = "euler"
+table = "euler_deposits"
+contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]
+event = "Deposit(address indexed underlying, address indexed account, uint256 amount)"
+
+[[decoder]]
+protocol = "euler"
+table = "euler_withdrawals"
+contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]
+event = "Withdraw(address indexed underlying, address indexed account, uint256 amount)"
+
+[[decoder]]
+protocol = "euler"
+table = "euler_borrows"
+contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]
+event = "Borrow(address indexed underlying, address indexed account, uint256 amount)"
+
+[[decoder]]
+protocol = "euler"
+table = "euler_repays"
+contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]
+event = "Repay(address indexed underlying, address indexed account, uint256 amount)"
+
+[[decoder]]
+protocol = "euler"
+table = "euler_liquidations"
+contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]
+event = "Liquidation(address indexed liquidator, address indexed violator, address indexed underlying, address collateral, uint256 repay, uint256 yield, uint256 healthScore, uint256 baseDiscount, uint256 discount)"
+
+[[decoder]]
+protocol = "euler"
+table = "euler_mints"
+contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]
+event = "RequestMint(address indexed account, uint256 amount)"
+
+[[decoder]]
+protocol = "euler"
+table = "euler_donations"
+contracts = ["0x27182842e098f60e3d576794a5bffb0777e025d3"]
+event = "RequestDonate(address indexed account, uint256 amount)"
+"#;
+
+/// (protocol, decoders) of every protocol compiled in.
+pub const PROTOCOLS: &[(&str, &str)] = &[
+    #[cfg(feature = "uniswap")]
+    ("uniswap", UNISWAP),
+    #[cfg(feature = "aave")]
+    ("aave", AAVE),
+    #[cfg(feature = "chainlink")]
+    ("chainlink", CHAINLINK),
+    #[cfg(feature = "euler")]
+    ("euler", EULER),
+];
+
+#[cfg(all(
+    test,
+    any(feature = "uniswap", feature = "aave", feature = "chainlink", feature = "euler")
+))]
+mod tests {
+    use alloy_primitives::{hex, I256};
+
+    use crate::decode::topic;
+    use crate::protocols::catch_up;
+    use crate::protocols::tests::{db, log, padded, row, rows, word};
+
+    /// ABI data of signed words.
+    fn data(words: &[i128]) -> Vec<u8> {
+        words.iter().flat_map(|&w| word(I256::try_from(w).unwrap().into_raw())).collect()
+    }
+
+    /// The address ending in `n`.
+    fn address(n: &str) -> String {
+        format!("0x{n:0>40}")
+    }
+
+    const ROUTER: &str = "0x7a250d5630b4cf539739df2c5dacb4c659f2488d";
+
+    #[cfg(feature = "uniswap")]
+    #[test]
+    fn uniswap_swaps() {
+        let conn = db();
+        let (router, to) = (padded(ROUTER), padded("0x0c"));
+        let pair = "0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc";
+        let v2 = "0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822";
+        let eth = 1_000_000_000_000_000_000;
+        log(&conn, pair, &[v2, &router, &to], &data(&[0, eth, 3_150_000_000, 0]));
+        let pool = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640";
+        let v3 = "0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67";
+        let sqrt_price = 1_350_174_849_792_634_181_862_360_983_626_536;
+        let swap = data(&[-3_150_000_000, eth, sqrt_price, 21 * eth, 195_285]);
+        log(&conn, pool, &[v3, &router, &to], &swap);
+        catch_up(&conn, &["uniswap"]).unwrap();
+        let (eth, to) = (eth.to_string(), address("c"));
+        assert_eq!(
+            rows(&conn, "uniswap_v2_swaps"),
+            [row(&[ROUTER, "0", &eth, "3150000000", "0", &to])]
+        );
+        let (sqrt_price, liquidity) = (sqrt_price.to_string(), (21 * 10u128.pow(18)).to_string());
+        assert_eq!(
+            rows(&conn, "uniswap_v3_swaps"),
+            [row(&[ROUTER, &to, "-3150000000", &eth, &sqrt_price, &liquidity, "195285"])]
+        );
+    }
+
+    #[cfg(feature = "aave")]
+    #[test]
+    fn aave_liquidations_from_the_lending_pool_only() {
+        let conn = db();
+        let liquidation = "0xe413a321e8681d831f4dbccbca790d2952b56f977908e45be37335533e005286";
+        let weth = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+        let usdc = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+        let topics = [liquidation, &padded(weth), &padded(usdc), &padded("0x0b")];
+        let liquidator = hex::decode(padded(&address("1c"))).unwrap();
+        let fields = [data(&[2_500_000_000, 10i128.pow(18)]), liquidator, data(&[0])].concat();
+        log(&conn, "0x7d2768de32b0b80b7a3454c06bdac94a69ddc7a9", &topics, &fields);
+        log(&conn, &address("ff"), &topics, &fields);
+        catch_up(&conn, &["aave"]).unwrap();
+        let (user, liquidator) = (address("b"), address("1c"));
+        assert_eq!(
+            rows(&conn, "aave_v2_liquidations"),
+            [row(&[weth, usdc, &user, "2500000000", "1000000000000000000", &liquidator, "0"])]
+        );
+    }
+
+    #[cfg(feature = "euler")]
+    #[test]
+    fn euler_deposits_and_donations() {
+        let conn = db();
+        let euler = "0x27182842e098f60e3d576794a5bffb0777e025d3";
+        let dai = "0x6b175474e89094c44da98b954eedeac495271d0f";
+        let exploiter = padded("0x583c21631c48d442b5c0e605d624f54a0b366c72");
+        let deposit = topic("Deposit(address,address,uint256)");
+        let donate = topic("RequestDonate(address,uint256)");
+        let amount = 30_000_000 * 10i128.pow(18);
+        log(&conn, euler, &[&deposit, &padded(dai), &exploiter], &data(&[amount]));
+        log(&conn, euler, &[&donate, &exploiter], &data(&[10 * amount]));
+        // Another contract's Deposit of the same shape.
+        log(&conn, &address("ff"), &[&deposit, &padded(dai), &exploiter], &data(&[amount]));
+        catch_up(&conn, &["euler"]).unwrap();
+        let exploiter = "0x583c21631c48d442b5c0e605d624f54a0b366c72";
+        let amount = amount.to_string();
+        assert_eq!(rows(&conn, "euler_deposits"), [row(&[dai, exploiter, &amount])]);
+        let donated = (300_000_000 * 10u128.pow(18)).to_string();
+        assert_eq!(rows(&conn, "euler_donations"), [row(&[exploiter, &donated])]);
+    }
+
+    #[cfg(feature = "chainlink")]
+    #[test]
+    fn chainlink_answers_and_rounds() {
+        let conn = db();
+        let aggregator = "0xe62b71cf983019bff55bc83b48601ce8419650cc";
+        let answer = "0x0559884fd3a460db3073b7fc896cc77986f16e378210ded43186175bf646fc5f";
+        let new_round = "0x0109fc6f55cf40689f02fbaad7af7fe7bbac8a3d2186600afc7d3e10cac60271";
+        let at = 1_700_000_000;
+        let signed = |n: i128| format!("0x{}", hex::encode(data(&[n])));
+        let (round, price) = (signed(27_911), signed(203_512_345_678));
+        log(&conn, aggregator, &[new_round, &round, &padded(&address("5"))], &data(&[at]));
+        log(&conn, aggregator, &[answer, &price, &round], &data(&[at]));
+        // Answers can be negative, as on rate feeds.
+        log(&conn, aggregator, &[answer, &signed(-42), &signed(27_912)], &data(&[at + 3600]));
+        catch_up(&conn, &["chainlink"]).unwrap();
+        let at = at.to_string();
+        assert_eq!(rows(&conn, "oracle_rounds"), [row(&["27911", &address("5"), &at])]);
+        assert_eq!(
+            rows(&conn, "oracle_prices"),
+            [row(&["203512345678", "27911", &at]), row(&["-42", "27912", "1700003600"])]
+        );
+    }
+
+    #[cfg(feature = "uniswap")]
+    #[test]
+    fn uniswap_v3_initializations_and_mints() {
+        let conn = db();
+        let pool = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640";
+        let initialize = "0x98636036cb66a9c19a37435efc1e90142190214e8abeb821bdba3f2990dd4c95";
+        let mint = "0x7a53080ba414158be7ec69b987b5fb7d07dee101fe85488f0853ae16239d0bde";
+        let sqrt_price = 1_350_174_849_792_634_181_862_360_983_626_536;
+        log(&conn, pool, &[initialize], &data(&[sqrt_price, -887_220]));
+        // Ticks are indexed, and sign-extended like any int24.
+        let tick = |n: i128| format!("0x{}", hex::encode(data(&[n])));
+        let manager = "0xc36442b4a4522e871399cd717abdd847ab11fe88";
+        let topics = [mint, &padded(manager), &tick(-887_220), &tick(887_220)];
+        let sender = hex::decode(padded(manager)).unwrap();
+        log(&conn, pool, &topics, &[sender, data(&[5_000, 7, 11])].concat());
+        catch_up(&conn, &["uniswap"]).unwrap();
+        assert_eq!(
+            rows(&conn, "uniswap_v3_initializations"),
+            [row(&[&sqrt_price.to_string(), "-887220"])]
+        );
+        assert_eq!(
+            rows(&conn, "uniswap_v3_mints"),
+            [row(&[manager, manager, "-887220", "887220", "5000", "7", "11"])]
+        );
+    }
+
+    #[cfg(feature = "uniswap")]
+    #[test]
+    fn uniswap_v3_flashes() {
+        let conn = db();
+        let pool = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640";
+        let flash = "0xbdbdb71d7860376ba52b25a5028beea23581364a40522f6bcfb86bb1f2dca633";
+        let (sender, recipient) = (address("f1"), address("f2"));
+        let topics = [flash, &padded(&sender), &padded(&recipient)];
+        log(&conn, pool, &topics, &data(&[5_000_000_000_000, 0, 2_500_000_000, 0]));
+        catch_up(&conn, &["uniswap"]).unwrap();
+        assert_eq!(
+            rows(&conn, "uniswap_v3_flashes"),
+            [row(&[&sender, &recipient, "5000000000000", "0", "2500000000", "0"])]
+        );
+    }
+}
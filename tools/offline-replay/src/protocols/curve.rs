@@ -0,0 +1,362 @@
+//! Curve pools, which one event signature per table can't describe: the same
+//! trade or liquidity change is logged under a different signature by each
+//! pool generation and pool size.
+//!
+//!   curve_trades     TokenExchange and TokenExchangeUnderlying, with the
+//!                    coin indices as INTEGER whether the pool logs them as
+//!                    int128 (stableswap) or uint256 (crypto pools)
+//!   curve_liquidity  AddLiquidity, RemoveLiquidity, RemoveLiquidityImbalance
+//!                    and RemoveLiquidityOne of 2-, 3- and 4-coin pools
+//!
+//! A liquidity row's `amounts` and `fees` are JSON arrays of decimal amounts,
+//! one per coin, as the pool logs them (crypto pools log a single fee); a
+//! `remove_one` row has the one coin's amount, and its `coin_index` when the
+//! pool logs it. `token_amount` is the LP tokens burned, where the event says,
+//! and `token_supply` the LP supply after the change.
+
+use alloy_primitives::U256;
+use eyre::Result;
+use rusqlite::types::Value;
+
+use super::{Column, Log, ProtocolDecoder};
+use crate::decode;
+use crate::normalize::Normalize;
+
+pub fn decoders() -> Vec<Box<dyn ProtocolDecoder>> {
+    vec![Box::new(Trades::new()), Box::new(Liquidity::new())]
+}
+
+fn column(name: &str, sql_type: &'static str, nullable: bool) -> Column {
+    Column { name: name.to_string(), sql_type, nullable }
+}
+
+/// A data word as a coin index; None if it isn't a small non-negative
+/// number (an int128 index is sign-extended, so negative ones are too large).
+fn index(word: &[u8]) -> Option<i64> {
+    let n = U256::from_be_slice(word);
+    (n < U256::from(256)).then(|| n.to::<i64>())
+}
+
+/// The provider or buyer topic as an address.
+fn address(log: &Log, norm: &Normalize) -> Result<Option<Value>> {
+    let [topic] = log.topics.as_slice() else { return Ok(None) };
+    let Some(address) = decode::topic_address(&topic.to_ascii_lowercase()) else {
+        return Ok(None);
+    };
+    Ok(Some(Value::Text(norm.address("topic1", &address)?.into_owned())))
+}
+
+struct Trades {
+    /// (topic0, kind)
+    events: Vec<(String, &'static str)>,
+    columns: Vec<Column>,
+}
+
+impl Trades {
+    fn new() -> Self {
+        let mut events = Vec::new();
+        for index in ["int128", "uint256"] {
+            for (event, kind) in
+                [("TokenExchange", "coins"), ("TokenExchangeUnderlying", "underlying")]
+            {
+                let signature = format!("{event}(address,{index},uint256,{index},uint256)");
+                events.push((decode::topic(&signature), kind));
+            }
+        }
+        let columns = vec![
+            column("kind", "TEXT", false),
+            column("buyer", "TEXT", false),
+            column("sold_id", "INTEGER", false),
+            column("tokens_sold", "TEXT", false),
+            column("bought_id", "INTEGER", false),
+            column("tokens_bought", "TEXT", false),
+        ];
+        Self { events, columns }
+    }
+}
+
+impl ProtocolDecoder for Trades {
+    fn protocol(&self) -> &str {
+        "curve"
+    }
+
+    fn table(&self) -> &str {
+        "curve_trades"
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.events.iter().map(|(topic0, _)| topic0.clone()).collect()
+    }
+
+    fn matches(&self, _address: &str, topic0: &str) -> bool {
+        self.events.iter().any(|(t, _)| t == topic0)
+    }
+
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>> {
+        let Some(&(_, kind)) = self.events.iter().find(|(topic0, _)| *topic0 == log.topic0) else {
+            return Ok(None);
+        };
+        let Some(buyer) = address(log, norm)? else { return Ok(None) };
+        if log.data.len() != 4 * 32 {
+            return Ok(None);
+        }
+        let word = |i: usize| &log.data[i * 32..(i + 1) * 32];
+        let (Some(sold_id), Some(bought_id)) = (index(word(0)), index(word(2))) else {
+            return Ok(None);
+        };
+        Ok(Some(vec![
+            Value::Text(kind.to_string()),
+            buyer,
+            Value::Integer(sold_id),
+            norm.encode(U256::from_be_slice(word(1))),
+            Value::Integer(bought_id),
+            norm.encode(U256::from_be_slice(word(3))),
+        ]))
+    }
+}
+
+/// A run of data words in a liquidity event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Part {
+    Amounts(usize),
+    Fees(usize),
+    Fee,
+    Invariant,
+    TokenSupply,
+    TokenAmount,
+    CoinIndex,
+    /// A coin index logged as int128.
+    CoinId,
+    CoinAmount,
+}
+
+impl Part {
+    fn words(self) -> usize {
+        match self {
+            Part::Amounts(n) | Part::Fees(n) => n,
+            _ => 1,
+        }
+    }
+
+    fn abi_type(self) -> String {
+        match self {
+            Part::Amounts(n) | Part::Fees(n) => format!("uint256[{n}]"),
+            Part::CoinId => "int128".to_string(),
+            _ => "uint256".to_string(),
+        }
+    }
+}
+
+struct Event {
+    topic0: String,
+    action: &'static str,
+    parts: Vec<Part>,
+}
+
+struct Liquidity {
+    events: Vec<Event>,
+    columns: Vec<Column>,
+}
+
+impl Liquidity {
+    fn new() -> Self {
+        use Part::*;
+        let mut layouts: Vec<(&str, &str, Vec<Part>)> = Vec::new();
+        for n in 2..=4 {
+            // Stableswap pools.
+            layouts.push((
+                "add",
+                "AddLiquidity",
+                vec![Amounts(n), Fees(n), Invariant, TokenSupply],
+            ));
+            layouts.push(("remove", "RemoveLiquidity", vec![Amounts(n), Fees(n), TokenSupply]));
+            layouts.push((
+                "remove_imbalance",
+                "RemoveLiquidityImbalance",
+                vec![Amounts(n), Fees(n), Invariant, TokenSupply],
+            ));
+            // Crypto pools.
+            layouts.push(("add", "AddLiquidity", vec![Amounts(n), Fee, TokenSupply]));
+            layouts.push(("remove", "RemoveLiquidity", vec![Amounts(n), TokenSupply]));
+        }
+        layouts.push(("remove_one", "RemoveLiquidityOne", vec![TokenAmount, CoinAmount]));
+        layouts.push((
+            "remove_one",
+            "RemoveLiquidityOne",
+            vec![TokenAmount, CoinIndex, CoinAmount],
+        ));
+        // Stableswap-NG pools.
+        layouts.push((
+            "remove_one",
+            "RemoveLiquidityOne",
+            vec![CoinId, TokenAmount, CoinAmount, TokenSupply],
+        ));
+
+        let events = layouts
+            .into_iter()
+            .map(|(action, name, parts)| {
+                let types: Vec<String> = parts.iter().map(|p| p.abi_type()).collect();
+                let topic0 = decode::topic(&format!("{name}(address,{})", types.join(",")));
+                Event { topic0, action, parts }
+            })
+            .collect();
+        let columns = vec![
+            column("action", "TEXT", false),
+            column("provider", "TEXT", false),
+            column("n_coins", "INTEGER", true),
+            column("amounts", "TEXT", false),
+            column("fees", "TEXT", true),
+            column("coin_index", "INTEGER", true),
+            column("token_amount", "TEXT", true),
+            column("token_supply", "TEXT", true),
+        ];
+        Self { events, columns }
+    }
+}
+
+impl ProtocolDecoder for Liquidity {
+    fn protocol(&self) -> &str {
+        "curve"
+    }
+
+    fn table(&self) -> &str {
+        "curve_liquidity"
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.events.iter().map(|e| e.topic0.clone()).collect()
+    }
+
+    fn matches(&self, _address: &str, topic0: &str) -> bool {
+        self.events.iter().any(|e| e.topic0 == topic0)
+    }
+
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>> {
+        let Some(event) = self.events.iter().find(|e| e.topic0 == log.topic0) else {
+            return Ok(None);
+        };
+        let Some(provider) = address(log, norm)? else { return Ok(None) };
+        if log.data.len() != 32 * event.parts.iter().map(|p| p.words()).sum::<usize>() {
+            return Ok(None);
+        }
+
+        let json = |words: &[&[u8]]| {
+            let amounts: Vec<String> =
+                words.iter().map(|w| U256::from_be_slice(w).to_string()).collect();
+            Value::Text(serde_json::to_string(&amounts).expect("strings serialize"))
+        };
+        let mut words = log.data.chunks_exact(32);
+        let (mut n_coins, mut amounts, mut fees) = (Value::Null, Value::Null, Value::Null);
+        let (mut coin_index, mut token_amount, mut token_supply) =
+            (Value::Null, Value::Null, Value::Null);
+        for &part in &event.parts {
+            let part_words: Vec<&[u8]> = words.by_ref().take(part.words()).collect();
+            match part {
+                Part::Amounts(n) => {
+                    n_coins = Value::Integer(n as i64);
+                    amounts = json(&part_words);
+                }
+                Part::Fees(_) | Part::Fee => fees = json(&part_words),
+                Part::Invariant => {}
+                Part::TokenSupply => token_supply = norm.encode(U256::from_be_slice(part_words[0])),
+                Part::TokenAmount => token_amount = norm.encode(U256::from_be_slice(part_words[0])),
+                Part::CoinIndex | Part::CoinId => match index(part_words[0]) {
+                    Some(i) => coin_index = Value::Integer(i),
+                    None => return Ok(None),
+                },
+                Part::CoinAmount => amounts = json(&part_words),
+            }
+        }
+        Ok(Some(vec![
+            Value::Text(event.action.to_string()),
+            provider,
+            n_coins,
+            amounts,
+            fees,
+            coin_index,
+            token_amount,
+            token_supply,
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::I256;
+
+    use super::*;
+    use crate::protocols::catch_up;
+    use crate::protocols::tests::{db, log, padded, row, rows, word};
+
+    const POOL: &str = "0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7";
+    const BUYER: &str = "0x00000000000000000000000000000000000000b0";
+
+    fn data(words: &[u128]) -> Vec<u8> {
+        words.iter().flat_map(|&w| word(U256::from(w))).collect()
+    }
+
+    #[test]
+    fn trades_of_stableswap_and_crypto_pools() {
+        let conn = db();
+        let stableswap = "0x8b3e96f2b889fa771c53c981b40daf005f63f637f1869f707052d15a3dd97140";
+        let crypto = "0xb2e76ae99761dc136e598d4a629bb347eccb9532a5f8bbd72e18467c3c34cc98";
+        let dai = 999_870_000_000_000_000_000;
+        log(&conn, POOL, &[stableswap, &padded(BUYER)], &data(&[1, 1_000_000_000, 0, dai]));
+        log(&conn, POOL, &[crypto, &padded(BUYER)], &data(&[2, 10u128.pow(18), 0, 3_150_000_000]));
+        // A negative int128 index isn't a coin.
+        let mut negative = data(&[1, 1, 0, 1]);
+        negative[..32].copy_from_slice(&word(I256::MINUS_ONE.into_raw()));
+        log(&conn, POOL, &[stableswap, &padded(BUYER)], &negative);
+        catch_up(&conn, &["curve"]).unwrap();
+        let dai = dai.to_string();
+        assert_eq!(
+            rows(&conn, "curve_trades"),
+            [
+                row(&["coins", BUYER, "1", "1000000000", "0", &dai]),
+                row(&["coins", BUYER, "2", "1000000000000000000", "0", "3150000000"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn liquidity_of_a_three_coin_pool() {
+        let conn = db();
+        let add = "0x423f6495a08fc652425cf4ed0d1f9e37e571d9b9529b1c1c23cce780b2e7df0d";
+        let remove_one = "0x9e96dd3b997a2a257eec4df9bb6eaf626e206df5f543bd963682d143300be310";
+        let eth = 10u128.pow(18);
+        let fees = [eth / 1000, 200, 300];
+        let supply = 3_000_000 * eth;
+        let added = [&[eth, 1_000_000, 0][..], &fees, &[4_000_000 * eth, supply]].concat();
+        log(&conn, POOL, &[add, &padded(BUYER)], &data(&added));
+        log(&conn, POOL, &[remove_one, &padded(BUYER)], &data(&[eth, 1_020_000]));
+        // An AddLiquidity of coins the pool doesn't have.
+        log(&conn, POOL, &[add, &padded(BUYER)], &data(&added[1..]));
+        catch_up(&conn, &["curve"]).unwrap();
+        let fees = format!(r#"["{}","200","300"]"#, eth / 1000);
+        let eth = eth.to_string();
+        assert_eq!(
+            rows(&conn, "curve_liquidity"),
+            [
+                row(&[
+                    "add",
+                    BUYER,
+                    "3",
+                    &format!(r#"["{eth}","1000000","0"]"#),
+                    &fees,
+                    "NULL",
+                    "NULL",
+                    &supply.to_string(),
+                ]),
+                row(&["remove_one", BUYER, "NULL", r#"["1020000"]"#, "NULL", "NULL", &eth, "NULL"]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! dYdX's SoloMargin, whose flash loans are a withdrawal and a deposit of
+//! the same market within one `operate` call:
+//!
+//!   dydx_transfers  LogDeposit and LogWithdraw
+//!
+//! Both events log a `BalanceUpdate` tuple, which an event signature in the
+//! `--decoders` format can't describe. A row has the `kind` (deposit or
+//! withdraw), the `account_owner` and `account_number`, the `market` id
+//! (0 WETH, 1 SAI, 2 USDC, 3 DAI), the `amount` moved and the
+//! `counterparty` the tokens came from or went to.
+
+use alloy_primitives::{hex, U256};
+use eyre::Result;
+use rusqlite::types::Value;
+
+use super::{Column, Log, ProtocolDecoder};
+use crate::decode;
+use crate::normalize::Normalize;
+
+const SOLO_MARGIN: &str = "0x1e0447b19bb6ecfdae1e4ae1694b0c3659614e4e";
+
+pub fn decoders() -> Vec<Box<dyn ProtocolDecoder>> {
+    vec![Box::new(Transfers::new())]
+}
+
+struct Transfers {
+    deposit: String,
+    withdraw: String,
+    columns: Vec<Column>,
+}
+
+impl Transfers {
+    fn new() -> Self {
+        let update = "((bool,uint256),(bool,uint128))";
+        let column = |name: &str, sql_type| Column { name: name.into(), sql_type, nullable: false };
+        Self {
+            deposit: decode::topic(&format!(
+                "LogDeposit(address,uint256,uint256,{update},address)"
+            )),
+            withdraw: decode::topic(&format!(
+                "LogWithdraw(address,uint256,uint256,{update},address)"
+            )),
+            columns: vec![
+                column("kind", "TEXT"),
+                column("account_owner", "TEXT"),
+                column("account_number", "TEXT"),
+                column("market", "INTEGER"),
+                column("amount", "TEXT"),
+                column("counterparty", "TEXT"),
+            ],
+        }
+    }
+}
+
+impl ProtocolDecoder for Transfers {
+    fn protocol(&self) -> &str {
+        "dydx"
+    }
+
+    fn table(&self) -> &str {
+        "dydx_transfers"
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn topics(&self) -> Vec<String> {
+        vec![self.deposit.clone(), self.withdraw.clone()]
+    }
+
+    fn matches(&self, address: &str, topic0: &str) -> bool {
+        address == SOLO_MARGIN && (topic0 == self.deposit || topic0 == self.withdraw)
+    }
+
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>> {
+        let kind = if log.topic0 == self.deposit { "deposit" } else { "withdraw" };
+        let [owner] = log.topics.as_slice() else { return Ok(None) };
+        let Some(owner) = decode::topic_address(&owner.to_ascii_lowercase()) else {
+            return Ok(None);
+        };
+        // accountNumber, market, deltaWei (sign, value), newPar (sign, value),
+        // from or to.
+        if log.data.len() != 7 * 32 {
+            return Ok(None);
+        }
+        let word = |i: usize| &log.data[i * 32..(i + 1) * 32];
+        let market = U256::from_be_slice(word(1));
+        if market > U256::from(i64::MAX) {
+            return Ok(None);
+        }
+        let counterparty = format!("0x{}", hex::encode(&word(6)[12..]));
+        Ok(Some(vec![
+            Value::Text(kind.to_string()),
+            Value::Text(norm.address("account_owner", &owner)?.into_owned()),
+            norm.encode(U256::from_be_slice(word(0))),
+            Value::Integer(market.to::<i64>()),
+            norm.encode(U256::from_be_slice(word(3))),
+            Value::Text(norm.address("counterparty", &counterparty)?.into_owned()),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::catch_up;
+    use crate::protocols::tests::{db, log, padded, row, rows, word};
+
+    #[test]
+    fn flash_loan_withdrawal_and_deposit() {
+        let conn = db();
+        let deposit = "0x2bad8bc95088af2c247b30fa2b2e6a0886f88625e0945cd3051008e0e270198f";
+        let withdraw = "0xbc83c08f0b269b1726990c8348ffdf1ae1696244a14868d766e542a2f18cd7d4";
+        let borrower = "0x00000000000000000000000000000000000000b1";
+        let eth = U256::from(10u64.pow(18));
+        // accountNumber, market, deltaWei (sign, value), newPar (sign,
+        // value), from or to.
+        let update = |positive: bool, amount: U256| {
+            let sign = U256::from(positive as u8);
+            [U256::ZERO, U256::ZERO, sign, amount, U256::from(1), U256::from(7)]
+                .into_iter()
+                .flat_map(word)
+                .chain(hex::decode(padded(borrower)).unwrap())
+                .collect::<Vec<u8>>()
+        };
+        let lent = eth * U256::from(10_000);
+        log(&conn, SOLO_MARGIN, &[withdraw, &padded(borrower)], &update(false, lent));
+        log(&conn, SOLO_MARGIN, &[deposit, &padded(borrower)], &update(true, lent + U256::from(2)));
+        // Not SoloMargin.
+        log(&conn, borrower, &[deposit, &padded(borrower)], &update(true, lent));
+        catch_up(&conn, &["dydx"]).unwrap();
+        let (lent, repaid) = (lent.to_string(), (lent + U256::from(2)).to_string());
+        assert_eq!(
+            rows(&conn, "dydx_transfers"),
+            [
+                row(&["withdraw", borrower, "0", "0", &lent, borrower]),
+                row(&["deposit", borrower, "0", "0", &repaid, borrower]),
+            ]
+        );
+    }
+}
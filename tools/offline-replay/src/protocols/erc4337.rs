@@ -0,0 +1,364 @@
+//! ERC-4337 account abstraction: the UserOperations a bundler's handleOps
+//! transaction carries, one row each in `user_operations`, so the accounts
+//! behind a bundle show up rather than only the bundler that sent it.
+//!
+//! A row is an EntryPoint's UserOperationEvent, from any emitter:
+//!
+//!   user_op_hash      the operation's hash
+//!   sender            the smart account
+//!   paymaster         who paid its gas; NULL when the account did
+//!   nonce             uint256, the key in the high 192 bits
+//!   success           whether its call succeeded (1) or reverted (0)
+//!   actual_gas_cost   wei charged, actual_gas_used
+//!
+//! and, when the transaction called handleOps directly (EntryPoint v0.6 or
+//! v0.7), what its calldata says of the operation with the same sender and
+//! nonce: `entry_point_version`, the `bundler` (the transaction's sender)
+//! and the `beneficiary` it paid, the `factory` of an account deployed by
+//! the operation, `call_selector` of the call the account made, and the
+//! gas limits and fees it signed for. Those are NULL for a bundle sent
+//! through another contract, or by handleAggregatedOps.
+
+use std::cell::RefCell;
+
+use alloy_primitives::{hex, Address, U256};
+use eyre::Result;
+use rusqlite::types::Value;
+
+use super::{Column, Log, ProtocolDecoder};
+use crate::decode;
+use crate::normalize::Normalize;
+
+/// handleOps(UserOperation[],address) of EntryPoint v0.6.
+const HANDLE_OPS_V06: [u8; 4] = [0x1f, 0xad, 0x94, 0x8c];
+/// handleOps(PackedUserOperation[],address) of EntryPoint v0.7.
+const HANDLE_OPS_V07: [u8; 4] = [0x76, 0x5e, 0x82, 0x7f];
+
+pub fn decoders() -> Vec<Box<dyn ProtocolDecoder>> {
+    vec![Box::new(UserOperations::new())]
+}
+
+struct UserOperations {
+    topic: String,
+    columns: Vec<Column>,
+    indexes: Vec<String>,
+    /// The operations of the last transaction decoded, by its hash: logs
+    /// of one transaction come one after another.
+    bundle: RefCell<Option<(String, Option<Bundle>)>>,
+}
+
+/// What a handleOps call says.
+struct Bundle {
+    version: &'static str,
+    bundler: String,
+    beneficiary: Address,
+    ops: Vec<Op>,
+}
+
+struct Op {
+    sender: Address,
+    nonce: U256,
+    factory: Option<Address>,
+    call_selector: Option<[u8; 4]>,
+    call_gas_limit: U256,
+    verification_gas_limit: U256,
+    pre_verification_gas: U256,
+    max_fee_per_gas: U256,
+    max_priority_fee_per_gas: U256,
+}
+
+impl UserOperations {
+    fn new() -> Self {
+        let column =
+            |name: &str, sql_type, nullable| Column { name: name.into(), sql_type, nullable };
+        let mut columns = vec![
+            column("user_op_hash", "TEXT", false),
+            column("sender", "TEXT", false),
+            column("paymaster", "TEXT", true),
+            column("nonce", "TEXT", false),
+            column("success", "INTEGER", false),
+            column("actual_gas_cost", "TEXT", false),
+            column("actual_gas_used", "TEXT", false),
+            column("entry_point_version", "TEXT", true),
+            column("bundler", "TEXT", true),
+            column("beneficiary", "TEXT", true),
+            column("factory", "TEXT", true),
+            column("call_selector", "TEXT", true),
+        ];
+        for name in [
+            "call_gas_limit",
+            "verification_gas_limit",
+            "pre_verification_gas",
+            "max_fee_per_gas",
+            "max_priority_fee_per_gas",
+        ] {
+            columns.push(column(name, "TEXT", true));
+        }
+        Self {
+            topic: decode::topic(
+                "UserOperationEvent(bytes32,address,address,uint256,bool,uint256,uint256)",
+            ),
+            columns,
+            indexes: vec!["sender".into(), "paymaster".into()],
+            bundle: RefCell::new(None),
+        }
+    }
+}
+
+impl ProtocolDecoder for UserOperations {
+    fn protocol(&self) -> &str {
+        "erc4337"
+    }
+
+    fn table(&self) -> &str {
+        "user_operations"
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn topics(&self) -> Vec<String> {
+        vec![self.topic.clone()]
+    }
+
+    fn reads_transaction(&self) -> bool {
+        true
+    }
+
+    fn indexes(&self) -> &[String] {
+        &self.indexes
+    }
+
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>> {
+        let [hash, sender, paymaster] = log.topics.as_slice() else { return Ok(None) };
+        let (Some(sender), Some(paymaster)) = (
+            decode::topic_address(&sender.to_ascii_lowercase()),
+            decode::topic_address(&paymaster.to_ascii_lowercase()),
+        ) else {
+            return Ok(None);
+        };
+        if log.data.len() != 4 * 32 {
+            return Ok(None);
+        }
+        let word = |i: usize| U256::from_be_slice(&log.data[i * 32..(i + 1) * 32]);
+        let nonce = word(0);
+        let address = |column: &str, a: &Address| -> Result<Value> {
+            Ok(Value::Text(norm.address(column, &format!("{a:#x}"))?.into_owned()))
+        };
+        let paymaster: Address = paymaster.parse()?;
+        let mut values = vec![
+            Value::Text(hash.to_ascii_lowercase()),
+            Value::Text(norm.address("sender", &sender)?.into_owned()),
+            if paymaster.is_zero() { Value::Null } else { address("paymaster", &paymaster)? },
+            norm.encode(nonce),
+            Value::Integer(!word(1).is_zero() as i64),
+            norm.encode(word(2)),
+            norm.encode(word(3)),
+        ];
+
+        let mut cache = self.bundle.borrow_mut();
+        if cache.as_ref().map_or(true, |(tx, _)| *tx != log.tx_hash) {
+            let bundle = log.tx.as_ref().and_then(|tx| {
+                let (version, beneficiary, ops) = handle_ops(&tx.input)?;
+                Some(Bundle { version, bundler: tx.from.clone(), beneficiary, ops })
+            });
+            *cache = Some((log.tx_hash.clone(), bundle));
+        }
+        let bundle = cache.as_ref().and_then(|(_, b)| b.as_ref());
+        let sender: Address = sender.parse()?;
+        let op =
+            bundle.and_then(|b| b.ops.iter().find(|op| op.sender == sender && op.nonce == nonce));
+        match (bundle, op) {
+            (Some(bundle), Some(op)) => {
+                values.push(Value::Text(bundle.version.to_string()));
+                values.push(Value::Text(norm.address("bundler", &bundle.bundler)?.into_owned()));
+                values.push(address("beneficiary", &bundle.beneficiary)?);
+                values.push(match &op.factory {
+                    Some(factory) => address("factory", factory)?,
+                    None => Value::Null,
+                });
+                values.push(match op.call_selector {
+                    Some(selector) => Value::Text(format!("0x{}", hex::encode(selector))),
+                    None => Value::Null,
+                });
+                for amount in [
+                    op.call_gas_limit,
+                    op.verification_gas_limit,
+                    op.pre_verification_gas,
+                    op.max_fee_per_gas,
+                    op.max_priority_fee_per_gas,
+                ] {
+                    values.push(norm.encode(amount));
+                }
+            }
+            _ => values.extend(std::iter::repeat(Value::Null).take(10)),
+        }
+        Ok(Some(values))
+    }
+}
+
+/// The EntryPoint version, beneficiary and operations of a handleOps call.
+fn handle_ops(input: &[u8]) -> Option<(&'static str, Address, Vec<Op>)> {
+    let (selector, args) = (input.get(..4)?, input.get(4..)?);
+    let version = match selector {
+        s if s == HANDLE_OPS_V06 => "0.6",
+        s if s == HANDLE_OPS_V07 => "0.7",
+        _ => return None,
+    };
+    let beneficiary = Address::from_word(word(args, 32)?.into());
+    let array = offset(args, 0)?;
+    let len = offset(args, array)?;
+    let elements = array + 32;
+    let mut ops = Vec::new();
+    for i in 0..len {
+        let op = elements + offset(args, elements + i * 32)?;
+        let field = |n: usize| word(args, op + n * 32);
+        let bytes = |n: usize| {
+            let at = op + offset(args, op + n * 32)?;
+            let len = offset(args, at)?;
+            args.get(at + 32..at + 32 + len)
+        };
+        let (init_code, call_data) = (bytes(2)?, bytes(3)?);
+        let high = |w: U256| w >> 128;
+        let low = |w: U256| w & (U256::MAX >> 128);
+        let (call_gas_limit, verification_gas_limit, pre_verification_gas, max_fee, max_priority) =
+            if version == "0.6" {
+                (field(4)?, field(5)?, field(6)?, field(7)?, field(8)?)
+            } else {
+                // accountGasLimits and gasFees pack two uint128s each.
+                let (limits, fees) = (field(4)?, field(6)?);
+                (low(limits), high(limits), field(5)?, low(fees), high(fees))
+            };
+        ops.push(Op {
+            sender: Address::from_word(field(0)?.into()),
+            nonce: field(1)?,
+            factory: init_code.get(..20).map(Address::from_slice),
+            call_selector: call_data.get(..4).map(|s| s.try_into().expect("4 bytes")),
+            call_gas_limit,
+            verification_gas_limit,
+            pre_verification_gas,
+            max_fee_per_gas: max_fee,
+            max_priority_fee_per_gas: max_priority,
+        });
+    }
+    Some((version, beneficiary, ops))
+}
+
+/// The ABI word at byte `at` of `data`.
+fn word(data: &[u8], at: usize) -> Option<U256> {
+    data.get(at..at.checked_add(32)?).map(U256::from_be_slice)
+}
+
+/// A word read as an offset or length, if it fits the data.
+fn offset(data: &[u8], at: usize) -> Option<usize> {
+    let n = word(data, at)?;
+    n.try_into().ok().filter(|&n: &usize| n <= data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::params;
+
+    use super::*;
+    use crate::protocols::catch_up;
+    use crate::protocols::tests::{db, log, padded, row, rows, word};
+
+    const USER_OPERATION_EVENT: &str =
+        "0x49628fd1471006c1482da88028e9ce4dbb080b815c9b0344d39e5a8e6ec1419f";
+
+    fn address(s: &str) -> [u8; 32] {
+        word(U256::from_be_slice(s.parse::<Address>().unwrap().as_slice()))
+    }
+
+    /// `b` as ABI bytes: its length, then its words.
+    fn bytes(b: &[u8]) -> Vec<u8> {
+        let mut out = word(U256::from(b.len())).to_vec();
+        out.extend(b);
+        out.resize(32 + b.len().div_ceil(32) * 32, 0);
+        out
+    }
+
+    /// A v0.6 handleOps call of one operation, deploying its account from
+    /// `factory` and calling its execute(address,uint256,bytes).
+    fn handle_ops_v06(beneficiary: &str, sender: &str, nonce: U256, factory: &str) -> Vec<u8> {
+        let n = |n: u64| word(U256::from(n));
+        let init_code = bytes(&[&address(factory)[12..], &[0xde, 0xad, 0xbe, 0xef]].concat());
+        let call_data = bytes(&[0xb6, 0x1d, 0x27, 0xf6, 0, 0, 0, 0]);
+        // sender, nonce, initCode, callData, callGasLimit,
+        // verificationGasLimit, preVerificationGas, maxFeePerGas,
+        // maxPriorityFeePerGas, paymasterAndData, signature.
+        let head = [
+            address(sender),
+            word(nonce),
+            n(11 * 32),
+            n(11 * 32 + 64),
+            n(100_000),
+            n(400_000),
+            n(50_000),
+            n(30_000_000_000),
+            n(1_000_000_000),
+            n(11 * 32 + 128),
+            n(11 * 32 + 160),
+        ];
+        let op = [head.concat(), init_code, call_data, bytes(&[]), bytes(&[])].concat();
+        [&HANDLE_OPS_V06[..], &n(64), &address(beneficiary), &n(1), &n(32), &op].concat()
+    }
+
+    #[test]
+    fn user_operations_of_a_handle_ops_bundle() {
+        let conn = db();
+        let entry_point = "0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789";
+        let (bundler, beneficiary, factory) = (
+            "0x00000000000000000000000000000000000000b1",
+            "0x00000000000000000000000000000000000000b2",
+            "0x00000000000000000000000000000000000000fa",
+        );
+        let (account, other, paymaster) = (
+            "0x00000000000000000000000000000000000000a1",
+            "0x00000000000000000000000000000000000000a2",
+            "0x00000000000000000000000000000000000000c1",
+        );
+        let nonce = (U256::from(1) << 64) | U256::from(3);
+        conn.execute(
+            "INSERT INTO transactions
+             (hash, block_number, tx_index, from_addr, value, gas_used, gas_price, input, status)
+             VALUES ('0x01', 1, 0, ?1, '0', 0, '0', ?2, 1)",
+            params![bundler, handle_ops_v06(beneficiary, account, nonce, factory)],
+        )
+        .unwrap();
+        let data = |nonce: U256, success: u64| {
+            [nonce, U256::from(success), U256::from(4_000_000_000_000_000u64), U256::from(140_000)]
+                .map(word)
+                .concat()
+        };
+        let hash = format!("0x{:064x}", 0xaa);
+        let zero = format!("0x{:040x}", 0);
+        let event = |sender: &str, paymaster: &str| {
+            [USER_OPERATION_EVENT.to_string(), hash.clone(), padded(sender), padded(paymaster)]
+        };
+        let topics = event(account, &zero);
+        log(&conn, entry_point, &topics.each_ref().map(String::as_str), &data(nonce, 1));
+        // An operation the calldata doesn't carry.
+        let topics = event(other, paymaster);
+        log(&conn, entry_point, &topics.each_ref().map(String::as_str), &data(U256::ZERO, 0));
+        catch_up(&conn, &["erc4337"]).unwrap();
+
+        let (nonce, cost) = (nonce.to_string(), "4000000000000000");
+        let null = "NULL";
+        assert_eq!(
+            rows(&conn, "user_operations"),
+            [
+                row(&[
+                    &hash, account, null, &nonce, "1", cost, "140000", "0.6", bundler,
+                    beneficiary, factory, "0xb61d27f6", "100000", "400000", "50000",
+                    "30000000000", "1000000000",
+                ]),
+                row(&[
+                    &hash, other, paymaster, "0", "0", cost, "140000", null, null, null, null,
+                    null, null, null, null, null, null,
+                ]),
+            ]
+        );
+    }
+}
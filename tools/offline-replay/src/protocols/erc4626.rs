@@ -0,0 +1,189 @@
+//! ERC-4626 tokenized vaults, from any emitter: the standard's Deposit and
+//! Withdraw events in one `vault_flows` table, indexed by `contract` (the
+//! vault), and a `v_vault_share_price` view over it.
+//!
+//!   kind      deposit or withdraw
+//!   sender    the caller
+//!   owner     who gets the shares (deposit) or whose shares are burned
+//!   receiver  who gets the assets; NULL for a deposit
+//!   assets    in base units of the vault's asset
+//!   shares    in base units of the vault's shares
+//!
+//! The view has a row per flow with shares, with `share_price` the assets
+//! paid or received per share, as a REAL in base units; following it by
+//! `contract` and `block_number` gives each vault's share price over time.
+//! It reads amounts as numbers, so it is only made in a database with
+//! --numeric-encoding decimal.
+//! Any event with the standard's signature is taken, so a contract that only
+//! borrows the name shows up too; filter by `contract` when that matters.
+
+use alloy_primitives::U256;
+use eyre::Result;
+use rusqlite::types::Value;
+
+use super::{Column, Log, ProtocolDecoder};
+use crate::decode;
+use crate::normalize::{Normalize, NumericEncoding};
+
+const VIEW: &str = "
+DROP VIEW IF EXISTS v_vault_share_price;
+
+CREATE VIEW v_vault_share_price AS
+SELECT f.contract,
+       f.block_number,
+       b.timestamp,
+       f.tx_hash,
+       f.log_index,
+       f.kind,
+       f.assets,
+       f.shares,
+       CAST(f.assets AS REAL) / CAST(f.shares AS REAL) AS share_price
+FROM vault_flows f
+JOIN blocks b ON b.number = f.block_number AND b.is_canonical = 1
+WHERE CAST(f.shares AS REAL) > 0;
+";
+
+pub fn decoders() -> Vec<Box<dyn ProtocolDecoder>> {
+    vec![Box::new(VaultFlows::new())]
+}
+
+struct VaultFlows {
+    deposit: String,
+    withdraw: String,
+    columns: Vec<Column>,
+    indexes: Vec<String>,
+}
+
+impl VaultFlows {
+    fn new() -> Self {
+        let column =
+            |name: &str, nullable| Column { name: name.into(), sql_type: "TEXT", nullable };
+        Self {
+            deposit: decode::topic("Deposit(address,address,uint256,uint256)"),
+            withdraw: decode::topic("Withdraw(address,address,address,uint256,uint256)"),
+            columns: vec![
+                column("kind", false),
+                column("sender", false),
+                column("owner", false),
+                column("receiver", true),
+                column("assets", false),
+                column("shares", false),
+            ],
+            indexes: vec!["contract".into()],
+        }
+    }
+}
+
+impl ProtocolDecoder for VaultFlows {
+    fn protocol(&self) -> &str {
+        "erc4626"
+    }
+
+    fn table(&self) -> &str {
+        "vault_flows"
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn topics(&self) -> Vec<String> {
+        vec![self.deposit.clone(), self.withdraw.clone()]
+    }
+
+    fn indexes(&self) -> &[String] {
+        &self.indexes
+    }
+
+    fn views(&self, norm: &Normalize) -> &str {
+        // The view reads amounts as numbers.
+        if norm.numeric == NumericEncoding::Decimal {
+            VIEW
+        } else {
+            ""
+        }
+    }
+
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>> {
+        let (kind, topics) = match log.topics.as_slice() {
+            [sender, owner] if log.topic0 == self.deposit => {
+                ("deposit", [Some(sender), Some(owner), None])
+            }
+            [sender, receiver, owner] if log.topic0 == self.withdraw => {
+                ("withdraw", [Some(sender), Some(owner), Some(receiver)])
+            }
+            _ => return Ok(None),
+        };
+        if log.data.len() != 2 * 32 {
+            return Ok(None);
+        }
+        let mut values = vec![Value::Text(kind.to_string())];
+        for (topic, column) in topics.into_iter().zip(["sender", "owner", "receiver"]) {
+            let Some(topic) = topic else {
+                values.push(Value::Null);
+                continue;
+            };
+            let Some(address) = decode::topic_address(&topic.to_ascii_lowercase()) else {
+                return Ok(None);
+            };
+            values.push(Value::Text(norm.address(column, &address)?.into_owned()));
+        }
+        values.push(norm.encode(U256::from_be_slice(&log.data[..32])));
+        values.push(norm.encode(U256::from_be_slice(&log.data[32..])));
+        Ok(Some(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::catch_up;
+    use crate::protocols::tests::{db, log, padded, row, rows, word};
+
+    #[test]
+    fn deposits_and_withdrawals() {
+        let conn = db();
+        conn.execute(
+            "INSERT INTO blocks
+             (number, hash, parent_hash, timestamp, gas_used, gas_limit, tx_count)
+             VALUES (1, '0x01', '0x00', 1700000000, 0, 30000000, 1)",
+            [],
+        )
+        .unwrap();
+        let deposit = "0xdcbc1c05240f31ff3ad067ef1ee35ce4997762752e3a095284754544f4c709d7";
+        let withdraw = "0xfbde797d201c681b91056529119e0b02407c7bb96a4a2c75c01fc9667232c8db";
+        let vault = "0x83f20f44975d03b1b09e64809b757c47f942beea";
+        let (sender, owner, receiver) = (
+            "0x00000000000000000000000000000000000000c1",
+            "0x00000000000000000000000000000000000000c2",
+            "0x00000000000000000000000000000000000000c3",
+        );
+        let amounts = |assets: u128, shares: u128| {
+            [word(U256::from(assets)), word(U256::from(shares))].concat()
+        };
+        let eth = 10u128.pow(18);
+        let topics = [deposit, &padded(sender), &padded(owner)];
+        log(&conn, vault, &topics, &amounts(105 * eth, 100 * eth));
+        let topics = [withdraw, &padded(sender), &padded(receiver), &padded(owner)];
+        log(&conn, vault, &topics, &amounts(21 * eth, 20 * eth));
+        // A Deposit with the assets indexed: another event of the name.
+        log(&conn, vault, &[deposit, &padded(sender), &padded(owner), &padded("0x05")], &[]);
+        catch_up(&conn, &["erc4626"]).unwrap();
+        let amount = |n: u128| (n * eth).to_string();
+        assert_eq!(
+            rows(&conn, "vault_flows"),
+            [
+                row(&["deposit", sender, owner, "NULL", &amount(105), &amount(100)]),
+                row(&["withdraw", sender, owner, receiver, &amount(21), &amount(20)]),
+            ]
+        );
+        let prices: Vec<f64> = conn
+            .prepare("SELECT share_price FROM v_vault_share_price ORDER BY log_index")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(prices, [1.05, 1.05]);
+    }
+}
@@ -0,0 +1,319 @@
+//! [`EventDecoder`] — a decoder described by an event signature with
+//! parameter names and `indexed` markers, as in the contract's source:
+//!
+//!   Swap(address indexed sender, uint256 amount0In, ..., address indexed to)
+//!
+//! Each parameter becomes a column named after it in snake_case
+//! (`amount0In` -> `amount0_in`; `arg<N>` when it has no name):
+//!
+//!   address             TEXT, following --address-case
+//!   bool                INTEGER 0 or 1
+//!   uint8..uint32       INTEGER
+//!   int8..int64         INTEGER
+//!   larger uints        TEXT (or BLOB), following --numeric-encoding
+//!   larger ints         TEXT, signed decimal
+//!   bytes1..bytes32     TEXT, 0x-hex
+//!
+//! Only events of static types can be described; `string`, `bytes`, arrays
+//! and tuples are refused. A log is decoded when it has as many topics as the
+//! event has indexed parameters and a data word for each of the others.
+
+use alloy_primitives::{hex, I256, U256};
+use eyre::{eyre, Result};
+use rusqlite::types::Value;
+
+use super::{Column, Log, ProtocolDecoder};
+use crate::decode;
+use crate::normalize::Normalize;
+
+/// Columns every decoder table starts with.
+const COMMON_COLUMNS: [&str; 5] = ["log_id", "block_number", "tx_hash", "log_index", "contract"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Address,
+    Bool,
+    Uint(u16),
+    Int(u16),
+    FixedBytes(usize),
+}
+
+impl Kind {
+    fn parse(s: &str) -> Result<Self> {
+        let bits = |n: &str| -> Result<u16> {
+            let bits =
+                if n.is_empty() { 256 } else { n.parse().map_err(|_| eyre!("bad type {s:?}"))? };
+            if bits == 0 || bits > 256 || bits % 8 != 0 {
+                eyre::bail!("bad type {s:?}");
+            }
+            Ok(bits)
+        };
+        Ok(match s {
+            "address" => Kind::Address,
+            "bool" => Kind::Bool,
+            _ if s.starts_with("uint") => Kind::Uint(bits(&s[4..])?),
+            _ if s.starts_with("int") => Kind::Int(bits(&s[3..])?),
+            _ if s.starts_with("bytes") && s.len() > 5 => match s[5..].parse() {
+                Ok(n @ 1..=32) => Kind::FixedBytes(n),
+                _ => eyre::bail!("bad type {s:?}"),
+            },
+            _ => eyre::bail!("{s:?} is not a static type; only static types can be decoded"),
+        })
+    }
+
+    /// The canonical name, as hashed into topic0.
+    fn canonical(self) -> String {
+        match self {
+            Kind::Address => "address".to_string(),
+            Kind::Bool => "bool".to_string(),
+            Kind::Uint(n) => format!("uint{n}"),
+            Kind::Int(n) => format!("int{n}"),
+            Kind::FixedBytes(n) => format!("bytes{n}"),
+        }
+    }
+
+    fn sql_type(self) -> &'static str {
+        match self {
+            Kind::Bool | Kind::Uint(..=32) | Kind::Int(..=64) => "INTEGER",
+            _ => "TEXT",
+        }
+    }
+
+    fn value(self, column: &str, word: &[u8; 32], norm: &Normalize) -> Result<Value> {
+        Ok(match self {
+            Kind::Address => {
+                let address = format!("0x{}", hex::encode(&word[12..]));
+                Value::Text(norm.address(column, &address)?.into_owned())
+            }
+            Kind::Bool => Value::Integer((word != &[0; 32]) as i64),
+            Kind::Uint(..=32) => {
+                Value::Integer(u32::from_be_bytes(word[28..].try_into().unwrap()).into())
+            }
+            Kind::Int(..=64) => Value::Integer(i64::from_be_bytes(word[24..].try_into().unwrap())),
+            Kind::Uint(_) => norm.encode(U256::from_be_bytes(*word)),
+            Kind::Int(_) => Value::Text(I256::from_raw(U256::from_be_bytes(*word)).to_string()),
+            Kind::FixedBytes(n) => Value::Text(format!("0x{}", hex::encode(&word[..n]))),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Param {
+    kind: Kind,
+    indexed: bool,
+}
+
+/// A decoder of one event into one table.
+#[derive(Debug)]
+pub struct EventDecoder {
+    protocol: String,
+    table: String,
+    topic0: String,
+    /// Lowercase; empty for any emitter.
+    contracts: Vec<String>,
+    params: Vec<Param>,
+    columns: Vec<Column>,
+    indexes: Vec<String>,
+}
+
+impl EventDecoder {
+    pub fn new(
+        protocol: String,
+        table: String,
+        event: &str,
+        contracts: Vec<String>,
+    ) -> Result<Self> {
+        let (name, list) = event
+            .trim()
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| eyre!("expected Name(type [indexed] name, ...), got {event:?}"))?;
+        let mut params = Vec::new();
+        let mut columns: Vec<Column> = Vec::new();
+        for (i, param) in list.split(',').map(str::trim).filter(|p| !p.is_empty()).enumerate() {
+            let words: Vec<&str> = param.split_whitespace().collect();
+            let (kind, indexed, name) = match words.as_slice() {
+                [kind] => (kind, false, None),
+                [kind, "indexed"] => (kind, true, None),
+                [kind, name] => (kind, false, Some(name)),
+                [kind, "indexed", name] => (kind, true, Some(name)),
+                _ => eyre::bail!("can't read parameter {param:?} of {event:?}"),
+            };
+            let kind = match *kind {
+                "uint" => Kind::Uint(256),
+                "int" => Kind::Int(256),
+                kind => Kind::parse(kind)?,
+            };
+            let column = name.map_or_else(|| format!("arg{i}"), |name| snake_case(name));
+            if column.is_empty() || !column.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                eyre::bail!("{column:?} can't be a column name, in {event:?}");
+            }
+            if COMMON_COLUMNS.contains(&column.as_str()) || columns.iter().any(|c| c.name == column)
+            {
+                eyre::bail!("two columns would be named {column}, in {event:?}");
+            }
+            columns.push(Column { name: column, sql_type: kind.sql_type(), nullable: false });
+            params.push(Param { kind, indexed });
+        }
+        if params.iter().filter(|p| p.indexed).count() > 3 {
+            eyre::bail!("{event:?} has more than 3 indexed parameters");
+        }
+        let types: Vec<String> = params.iter().map(|p| p.kind.canonical()).collect();
+        let topic0 = decode::topic(&format!("{}({})", name.trim(), types.join(",")));
+        let contracts = contracts.iter().map(|c| c.to_ascii_lowercase()).collect();
+        Ok(Self { protocol, table, topic0, contracts, params, columns, indexes: Vec::new() })
+    }
+
+    /// Also index the table by each of `columns`.
+    pub fn with_indexes(mut self, columns: Vec<String>) -> Result<Self> {
+        for column in &columns {
+            if !COMMON_COLUMNS.contains(&column.as_str())
+                && !self.columns.iter().any(|c| c.name == *column)
+            {
+                eyre::bail!("{}: no column {column:?} to index", self.table);
+            }
+        }
+        self.indexes = columns;
+        Ok(self)
+    }
+}
+
+impl ProtocolDecoder for EventDecoder {
+    fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    fn table(&self) -> &str {
+        &self.table
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn topics(&self) -> Vec<String> {
+        vec![self.topic0.clone()]
+    }
+
+    fn matches(&self, address: &str, topic0: &str) -> bool {
+        topic0 == self.topic0
+            && (self.contracts.is_empty() || self.contracts.iter().any(|c| c == address))
+    }
+
+    fn indexes(&self) -> &[String] {
+        &self.indexes
+    }
+
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>> {
+        let indexed = self.params.iter().filter(|p| p.indexed).count();
+        if log.topic0 != self.topic0
+            || log.topics.len() != indexed
+            || log.data.len() != 32 * (self.params.len() - indexed)
+        {
+            return Ok(None);
+        }
+        let (mut topics, mut data) = (log.topics.iter(), log.data.chunks_exact(32));
+        let mut values = Vec::with_capacity(self.params.len());
+        for (param, column) in self.params.iter().zip(&self.columns) {
+            let mut word = [0u8; 32];
+            if param.indexed {
+                let topic = topics.next().expect("counted above");
+                match hex::decode(topic) {
+                    Ok(bytes) if bytes.len() == 32 => word.copy_from_slice(&bytes),
+                    _ => return Ok(None),
+                }
+            } else {
+                word.copy_from_slice(data.next().expect("counted above"));
+            }
+            values.push(param.kind.value(&column.name, &word, norm)?);
+        }
+        Ok(Some(values))
+    }
+}
+
+/// `amount0In` -> `amount0_in`; leading underscores are dropped.
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for c in name.trim_start_matches('_').chars() {
+        if c.is_ascii_uppercase() {
+            if prev_lower {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+            prev_lower = false;
+        } else {
+            out.push(c);
+            prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoder(event: &str) -> Result<EventDecoder> {
+        EventDecoder::new("test".into(), "events".into(), event, Vec::new())
+    }
+
+    #[test]
+    fn columns_and_topics() {
+        let d = decoder(
+            "Swap(address indexed sender, uint256 amount0In, uint256 amount1In, \
+             uint256 amount0Out, uint256 amount1Out, address indexed to)",
+        )
+        .unwrap();
+        assert_eq!(d.topic0, "0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822");
+        let columns: Vec<(&str, &str)> =
+            d.columns.iter().map(|c| (c.name.as_str(), c.sql_type)).collect();
+        assert_eq!(
+            columns,
+            [
+                ("sender", "TEXT"),
+                ("amount0_in", "TEXT"),
+                ("amount1_in", "TEXT"),
+                ("amount0_out", "TEXT"),
+                ("amount1_out", "TEXT"),
+                ("to", "TEXT"),
+            ]
+        );
+        // `uint` is uint256; unnamed parameters are numbered.
+        let d = decoder("Fee(uint, int64 indexed, bool _on, uint32 tickSpacingX, bytes2)").unwrap();
+        assert_eq!(d.topic0, decode::topic("Fee(uint256,int64,bool,uint32,bytes2)"));
+        let columns: Vec<(&str, &str)> =
+            d.columns.iter().map(|c| (c.name.as_str(), c.sql_type)).collect();
+        assert_eq!(
+            columns,
+            [
+                ("arg0", "TEXT"),
+                ("arg1", "INTEGER"),
+                ("on", "INTEGER"),
+                ("tick_spacing_x", "INTEGER"),
+                ("arg4", "TEXT"),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_that_cant_be_described() {
+        for event in [
+            "Named(string name)",
+            "Bytes(bytes data)",
+            "Array(uint256[] amounts)",
+            "Odd(uint7 x)",
+            "Wide(uint264 x)",
+            "Long(bytes33 x)",
+            "Many(address indexed a, address indexed b, address indexed c, address indexed d)",
+            "Twice(uint8 amount, uint16 amount)",
+            "Common(uint8 block_number)",
+            "Words(uint8 too many words)",
+            "Unclosed(uint8 x",
+        ] {
+            assert!(decoder(event).is_err(), "{event}");
+        }
+        let d = decoder("Ping(address indexed who)").unwrap();
+        assert!(d.with_indexes(vec!["nope".into()]).is_err());
+    }
+}
@@ -0,0 +1,566 @@
+//! `decode <db>` — protocol events decoded from `logs` into a typed table per
+//! event, such as `uniswap_v3_swaps` or `aave_v2_liquidations`.
+//!
+//! A decoder is a [`ProtocolDecoder`]: it picks logs by topic0 and emitting
+//! contract and turns each into a row of its own table. Decoders are kept in
+//! a [`Registry`] under the name of their protocol, which `--protocol`
+//! selects by. The built-in ones are compiled in with a cargo feature per
+//! protocol (`uniswap`, `aave`, `curve`, `balancer`, `erc4337`, `erc4626`,
+//! `chainlink`, `dydx`, `euler` and `proxy`, all on by default; see
+//! builtin.rs and the protocol's own file for the others), and `--decoders`
+//! adds more from a TOML file:
+//!
+//! ```toml
+//! [[decoder]]
+//! protocol = "compound"
+//! table = "compound_liquidations"
+//! contracts = ["0x5d3a536e4d6dbd6114cc1ead35777bab948e3643"]  # optional
+//! indexes = ["borrower"]                                      # optional
+//! event = "LiquidateBorrow(address liquidator, address borrower, uint256 repayAmount, address cTokenCollateral, uint256 seizeTokens)"
+//! ```
+//!
+//! Every table starts with `log_id`, `block_number`, `tx_hash`, `log_index`
+//! and `contract` (the emitter), followed by a column per event parameter
+//! (see event.rs for the types). Like the tables of decode.rs, a table is
+//! caught up incrementally from its `decoded.<table>` mark in `_meta`;
+//! `--rebuild` drops it and decodes every log again, as after its event
+//! description changed.
+//!
+//! `--parquet DIR` then writes the selected tables, with decode.rs's
+//! `erc20_transfers` and `dex_swaps`, to `DIR/<table>.parquet` (see
+//! export.rs for the types), whole each time, for pipelines that read
+//! Parquet rather than SQLite.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::decode::{self, key};
+use crate::export::{self, TableFormat};
+use crate::migrate::set_meta;
+use crate::normalize::{read_key, Normalize};
+use crate::open_existing;
+
+#[cfg(feature = "balancer")]
+mod balancer;
+mod builtin;
+#[cfg(feature = "curve")]
+mod curve;
+#[cfg(feature = "dydx")]
+mod dydx;
+#[cfg(feature = "erc4337")]
+mod erc4337;
+#[cfg(feature = "erc4626")]
+mod erc4626;
+mod event;
+#[cfg(feature = "proxy")]
+mod proxy;
+
+pub use event::EventDecoder;
+
+/// Tables of the loader and of decode.rs, which a decoder can't write to.
+const RESERVED_TABLES: [&str; 7] =
+    ["blocks", "transactions", "logs", "log_topics", "erc20_transfers", "approvals", "dex_swaps"];
+
+/// A log as a decoder sees it: keys as 0x-hex text and data decompressed.
+pub struct Log {
+    pub id: i64,
+    pub block_number: i64,
+    pub tx_hash: String,
+    pub log_index: i64,
+    pub address: String,
+    pub topic0: String,
+    /// topic1.. up to the first NULL.
+    pub topics: Vec<String>,
+    pub data: Vec<u8>,
+    /// The transaction that emitted it, for a decoder that
+    /// [reads transactions](ProtocolDecoder::reads_transaction).
+    #[cfg_attr(not(feature = "erc4337"), allow(dead_code))]
+    pub tx: Option<Transaction>,
+}
+
+/// The sender and calldata of a log's transaction.
+#[cfg_attr(not(feature = "erc4337"), allow(dead_code))]
+pub struct Transaction {
+    /// Lowercase 0x-hex.
+    pub from: String,
+    /// Decompressed.
+    pub input: Vec<u8>,
+}
+
+/// A column of a decoder's table, after the common ones.
+#[derive(Clone, Debug)]
+pub struct Column {
+    pub name: String,
+    /// INTEGER or TEXT.
+    pub sql_type: &'static str,
+    pub nullable: bool,
+}
+
+pub trait ProtocolDecoder {
+    /// The protocol it belongs to, as `--protocol` names it.
+    fn protocol(&self) -> &str;
+
+    /// The table it writes.
+    fn table(&self) -> &str;
+
+    /// The columns of the table after `log_id`, `block_number`, `tx_hash`,
+    /// `log_index` and `contract`.
+    fn columns(&self) -> &[Column];
+
+    /// topic0 of each event it decodes; only those logs are read.
+    fn topics(&self) -> Vec<String>;
+
+    /// Whether a log of `address` (lowercase) with `topic0` is one of its
+    /// events.
+    fn matches(&self, address: &str, topic0: &str) -> bool {
+        let _ = address;
+        self.topics().iter().any(|t| t == topic0)
+    }
+
+    /// Whether [`decode`](ProtocolDecoder::decode) needs the log's
+    /// transaction, as a decoder of calldata does; only then is it read.
+    fn reads_transaction(&self) -> bool {
+        false
+    }
+
+    /// Columns to index the table by, besides `block_number`.
+    fn indexes(&self) -> &[String] {
+        &[]
+    }
+
+    /// SQL (re)creating views over the table, run whenever it is caught up.
+    fn views(&self, norm: &Normalize) -> &str {
+        let _ = norm;
+        ""
+    }
+
+    /// A value per column, or None when the log doesn't have the shape of
+    /// the event after all.
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>>;
+}
+
+/// Decoders by protocol.
+#[derive(Default)]
+pub struct Registry {
+    decoders: Vec<Box<dyn ProtocolDecoder>>,
+}
+
+/// A `--decoders` file.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct DecodersFile {
+    decoder: Vec<DecoderSpec>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct DecoderSpec {
+    protocol: String,
+    table: String,
+    event: String,
+    #[serde(default)]
+    contracts: Vec<String>,
+    #[serde(default)]
+    indexes: Vec<String>,
+}
+
+impl Registry {
+    /// The decoders compiled in.
+    pub fn builtin() -> Result<Self> {
+        let mut registry = Self::default();
+        for (protocol, toml) in builtin::PROTOCOLS {
+            registry.add_toml(toml).with_context(|| format!("Built-in decoders of {protocol}"))?;
+        }
+        #[cfg(feature = "curve")]
+        for decoder in curve::decoders() {
+            registry.register(decoder)?;
+        }
+        #[cfg(feature = "balancer")]
+        for decoder in balancer::decoders()? {
+            registry.register(decoder)?;
+        }
+        #[cfg(feature = "erc4337")]
+        for decoder in erc4337::decoders() {
+            registry.register(decoder)?;
+        }
+        #[cfg(feature = "erc4626")]
+        for decoder in erc4626::decoders() {
+            registry.register(decoder)?;
+        }
+        #[cfg(feature = "dydx")]
+        for decoder in dydx::decoders() {
+            registry.register(decoder)?;
+        }
+        #[cfg(feature = "proxy")]
+        for decoder in proxy::decoders()? {
+            registry.register(decoder)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, decoder: Box<dyn ProtocolDecoder>) -> Result<()> {
+        let table = decoder.table();
+        if table.is_empty()
+            || table.starts_with('_')
+            || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            eyre::bail!("{table:?} can't be a table name");
+        }
+        if RESERVED_TABLES.contains(&table) {
+            eyre::bail!("{table} is a table of offline-replay's own");
+        }
+        if self.decoders.iter().any(|d| d.table() == table) {
+            eyre::bail!("two decoders write {table}");
+        }
+        self.decoders.push(decoder);
+        Ok(())
+    }
+
+    /// Add the decoders of a `--decoders` file.
+    pub fn load(&mut self, path: &std::path::Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        self.add_toml(&text).with_context(|| format!("Invalid decoders {}", path.display()))
+    }
+
+    fn add_toml(&mut self, text: &str) -> Result<()> {
+        let file: DecodersFile = toml::from_str(text)?;
+        for spec in file.decoder {
+            let decoder =
+                EventDecoder::new(spec.protocol, spec.table, &spec.event, spec.contracts)?
+                    .with_indexes(spec.indexes)?;
+            self.register(Box::new(decoder))?;
+        }
+        Ok(())
+    }
+
+    pub fn protocols(&self) -> BTreeSet<&str> {
+        self.decoders.iter().map(|d| d.protocol()).collect()
+    }
+
+    /// The decoders of `protocols`, or every one when it is empty.
+    pub fn select(&self, protocols: &[String]) -> Result<Vec<&dyn ProtocolDecoder>> {
+        let known = self.protocols();
+        for protocol in protocols {
+            if !known.contains(protocol.as_str()) {
+                let known: Vec<&str> = known.into_iter().collect();
+                eyre::bail!("No decoders for {protocol:?}; known: {}", known.join(", "));
+            }
+        }
+        Ok(self
+            .decoders
+            .iter()
+            .filter(|d| protocols.is_empty() || protocols.iter().any(|p| p == d.protocol()))
+            .map(|d| d.as_ref())
+            .collect())
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct DecodeArgs {
+    /// SQLite database produced by offline-replay
+    #[arg(required_unless_present = "list")]
+    db: Option<PathBuf>,
+
+    /// Protocols to decode (comma-separated) [default: every one known]
+    #[arg(long, value_delimiter = ',')]
+    protocol: Vec<String>,
+
+    /// TOML file of more decoders (repeatable; see protocols/mod.rs for the format)
+    #[arg(long)]
+    decoders: Vec<PathBuf>,
+
+    /// Drop the selected tables and decode every log again
+    #[arg(long)]
+    rebuild: bool,
+
+    /// Print the selected decoders and their tables; decode nothing
+    #[arg(long)]
+    list: bool,
+
+    /// Also write the selected tables, erc20_transfers and dex_swaps to this
+    /// directory as <table>.parquet
+    #[arg(long, conflicts_with = "list")]
+    parquet: Option<PathBuf>,
+}
+
+pub fn run(args: DecodeArgs) -> Result<()> {
+    let mut registry = Registry::builtin()?;
+    for path in &args.decoders {
+        registry.load(path)?;
+    }
+    let decoders = registry.select(&args.protocol)?;
+    if args.list {
+        for d in &decoders {
+            let columns: Vec<&str> = d.columns().iter().map(|c| c.name.as_str()).collect();
+            println!("{}\t{}\t{}", d.protocol(), d.table(), columns.join(", "));
+        }
+        return Ok(());
+    }
+    if decoders.is_empty() {
+        eyre::bail!("No decoders; this build has no built-in ones, so give --decoders");
+    }
+
+    let db = args.db.expect("clap requires db without --list");
+    let conn = open_existing(&db)?;
+    if args.rebuild {
+        for d in &decoders {
+            conn.execute_batch(&format!("DROP TABLE IF EXISTS {};", d.table()))?;
+            set_meta(&conn, &format!("decoded.{}", d.table()), "0")?;
+        }
+    }
+    decode_all(&conn, &decoders)?;
+    if let Some(dir) = &args.parquet {
+        write_parquet(&conn, &decoders, dir)?;
+    }
+    Ok(())
+}
+
+/// Write the decoded tables out as a Parquet dataset, one file per table in
+/// the layout the loader reads, replacing what a previous run wrote.
+fn write_parquet(
+    conn: &Connection,
+    decoders: &[&dyn ProtocolDecoder],
+    dir: &std::path::Path,
+) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    decode::erc20_transfers(conn)?;
+    decode::dex_swaps(conn)?;
+    let norm = Normalize::from_meta(conn)?;
+    let tables =
+        ["erc20_transfers", "dex_swaps"].into_iter().chain(decoders.iter().map(|d| d.table()));
+    for table in tables {
+        let path = dir.join(format!("{table}.parquet"));
+        let sql = format!("SELECT * FROM {table} ORDER BY log_id");
+        let rows = export::write_query(conn, &norm, &sql, &path, TableFormat::Parquet)
+            .with_context(|| format!("Writing {}", path.display()))?;
+        info!(table, out = %path.display(), rows, "wrote parquet");
+    }
+    Ok(())
+}
+
+/// Bring the tables of the built-in decoders of `protocols` up to date, for
+/// an analysis that reads them.
+pub fn catch_up(conn: &Connection, protocols: &[&str]) -> Result<()> {
+    let registry = Registry::builtin()?;
+    let protocols: Vec<String> = protocols.iter().map(|p| p.to_string()).collect();
+    decode_all(conn, &registry.select(&protocols)?)
+}
+
+fn decode_all(conn: &Connection, decoders: &[&dyn ProtocolDecoder]) -> Result<()> {
+    let norm = Normalize::from_meta(conn)?;
+    for &d in decoders {
+        let added = decode_table(conn, &norm, d)
+            .with_context(|| format!("Decoding {} into {}", d.protocol(), d.table()))?;
+        info!(protocol = d.protocol(), table = d.table(), added, "decoded");
+    }
+    Ok(())
+}
+
+/// Bring `d`'s table up to date with `logs`. Returns the rows added.
+fn decode_table(conn: &Connection, norm: &Normalize, d: &dyn ProtocolDecoder) -> Result<u64> {
+    let table = d.table();
+    let mut columns = vec!["log_id", "block_number", "tx_hash", "log_index", "contract"];
+    let mut schema = format!(
+        "CREATE TABLE IF NOT EXISTS {table} (
+    log_id       INTEGER PRIMARY KEY REFERENCES logs(id),
+    block_number INTEGER NOT NULL,
+    tx_hash      TEXT    NOT NULL,
+    log_index    INTEGER NOT NULL,
+    contract     TEXT    NOT NULL"
+    );
+    for c in d.columns() {
+        // Quoted: parameters are often named `from` or `to`.
+        let not_null = if c.nullable { "" } else { " NOT NULL" };
+        schema.push_str(&format!(",\n    \"{}\" {}{not_null}", c.name, c.sql_type));
+        columns.push(&c.name);
+    }
+    schema.push_str(&format!(
+        "\n);\nCREATE INDEX IF NOT EXISTS idx_{table}_block ON {table}(block_number);\n"
+    ));
+    for column in d.indexes() {
+        schema.push_str(&format!(
+            "CREATE INDEX IF NOT EXISTS idx_{table}_{column} ON {table}(\"{column}\");\n"
+        ));
+    }
+    schema.push_str(d.views(norm));
+    conn.execute_batch(&schema)?;
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+    let columns: Vec<String> = columns.iter().map(|c| format!("\"{c}\"")).collect();
+    let insert = format!(
+        "INSERT OR IGNORE INTO {table} ({}) VALUES ({})",
+        columns.join(", "),
+        placeholders.join(",")
+    );
+
+    conn.execute_batch("BEGIN;")?;
+    let mut added = 0;
+    decode::catch_up(conn, table, |after| {
+        let mut stmt = conn.prepare(
+            "SELECT id, block_number, tx_hash, log_index, address, topic1, topic2, topic3, data
+             FROM logs
+             WHERE id > ?1 AND topic0 = ?2",
+        )?;
+        for topic0 in d.topics() {
+            let mut rows = stmt.query(params![after, norm.key("topic0", &topic0)?])?;
+            while let Some(r) = rows.next()? {
+                let address = key(r, 4)?;
+                if !d.matches(&address.to_ascii_lowercase(), &topic0) {
+                    continue;
+                }
+                let mut topics = Vec::new();
+                for i in 5..8 {
+                    let Some(topic) = read_key(r.get_ref(i)?)? else { break };
+                    topics.push(topic);
+                }
+                let data = norm.read_blob(r.get::<_, Option<Vec<u8>>>(8)?.unwrap_or_default())?;
+                let tx = if d.reads_transaction() {
+                    transaction(conn, norm, r.get(2)?)?
+                } else {
+                    None
+                };
+                let log = Log {
+                    id: r.get(0)?,
+                    block_number: r.get(1)?,
+                    tx_hash: key(r, 2)?,
+                    log_index: r.get(3)?,
+                    address,
+                    topic0: topic0.clone(),
+                    topics,
+                    data,
+                    tx,
+                };
+                let Some(values) = d.decode(&log, norm)? else { continue };
+                let mut row = vec![
+                    Value::Integer(log.id),
+                    Value::Integer(log.block_number),
+                    Value::Text(log.tx_hash),
+                    Value::Integer(log.log_index),
+                    Value::Text(norm.address("address", &log.address)?.into_owned()),
+                ];
+                row.extend(values);
+                conn.prepare_cached(&insert)?.execute(params_from_iter(row))?;
+                added += 1;
+            }
+        }
+        Ok(())
+    })?;
+    conn.execute_batch("COMMIT;")?;
+    Ok(added)
+}
+
+/// The transaction `hash` (as stored) names, if it was loaded.
+fn transaction(
+    conn: &Connection,
+    norm: &Normalize,
+    hash: Value,
+) -> Result<Option<Transaction>> {
+    let mut stmt =
+        conn.prepare_cached("SELECT from_addr, input FROM transactions WHERE hash = ?1")?;
+    let mut rows = stmt.query([hash])?;
+    let Some(r) = rows.next()? else { return Ok(None) };
+    Ok(Some(Transaction {
+        from: key(r, 0)?.to_ascii_lowercase(),
+        input: norm.read_blob(r.get(1)?)?,
+    }))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use alloy_primitives::{hex, U256};
+    use rusqlite::types::ValueRef;
+
+    use super::*;
+    pub(crate) use crate::decode::tests::{db, log, padded, word};
+
+    /// The rows of `table` after its common columns, as text, by log id.
+    pub(crate) fn rows(conn: &Connection, table: &str) -> Vec<Vec<Option<String>>> {
+        let mut stmt = conn.prepare(&format!("SELECT * FROM {table} ORDER BY log_id")).unwrap();
+        let width = stmt.column_count();
+        stmt.query_map([], |r| {
+            (5..width)
+                .map(|i| {
+                    Ok(match r.get_ref(i)? {
+                        ValueRef::Null => None,
+                        ValueRef::Integer(n) => Some(n.to_string()),
+                        ValueRef::Real(x) => Some(x.to_string()),
+                        ValueRef::Text(t) => Some(String::from_utf8_lossy(t).into_owned()),
+                        ValueRef::Blob(b) => Some(format!("0x{}", hex::encode(b))),
+                    })
+                })
+                .collect()
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap()
+    }
+
+    /// `row` as rows() gives it.
+    pub(crate) fn row(row: &[&str]) -> Vec<Option<String>> {
+        row.iter().map(|v| (*v != "NULL").then(|| v.to_string())).collect()
+    }
+
+    const PING: &str = r#"
+[[decoder]]
+protocol = "test"
+table = "pings"
+contracts = ["0x00000000000000000000000000000000000000AA"]
+indexes = ["who", "contract"]
+event = "Ping(address indexed who, uint256 amount, int24 tick, bool ok, bytes4 tag)"
+"#;
+
+    #[test]
+    fn toml_decoders_catch_up() {
+        let conn = db();
+        let mut registry = Registry::default();
+        registry.add_toml(PING).unwrap();
+        let decoders = registry.select(&[]).unwrap();
+        let ping = decode::topic("Ping(address,uint256,int24,bool,bytes4)");
+        let (pinger, who) = ("0x00000000000000000000000000000000000000aa", padded("0x0b"));
+        let data = |amount: u64, tick: i32| {
+            let tick = alloy_primitives::I256::try_from(tick).unwrap().into_raw();
+            let mut tag = [0u8; 32];
+            tag[..4].copy_from_slice(&[0xca, 0xfe, 0xba, 0xbe]);
+            [word(U256::from(amount)), word(tick), word(U256::from(1)), tag].concat()
+        };
+        log(&conn, pinger, &[&ping, &who], &data(7, -3));
+        // Another emitter, a topic too many, and a word short aren't pings.
+        log(&conn, "0x00000000000000000000000000000000000000bb", &[&ping, &who], &data(1, 1));
+        log(&conn, pinger, &[&ping, &who, &who], &data(1, 1));
+        log(&conn, pinger, &[&ping, &who], &data(1, 1)[32..]);
+        decode_all(&conn, &decoders).unwrap();
+        let who = format!("0x{:0>40}", "b");
+        assert_eq!(rows(&conn, "pings"), [row(&[&who, "7", "-3", "1", "0xcafebabe"])]);
+
+        // Only the logs added since are decoded next time.
+        log(&conn, pinger, &[&ping, &padded("0x0b")], &data(8, 3));
+        decode_all(&conn, &decoders).unwrap();
+        assert_eq!(
+            rows(&conn, "pings"),
+            [row(&[&who, "7", "-3", "1", "0xcafebabe"]), row(&[&who, "8", "3", "1", "0xcafebabe"])]
+        );
+        let indexes =
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND tbl_name = 'pings'";
+        assert_eq!(conn.query_row(indexes, [], |r| r.get::<_, i64>(0)).unwrap(), 3);
+    }
+
+    #[test]
+    fn registries_refuse_tables_they_cant_write() {
+        let toml = |table: &str| PING.replace("\"pings\"", &format!("{table:?}"));
+        for table in ["logs", "erc20_transfers", "_meta", "drop table", ""] {
+            assert!(Registry::default().add_toml(&toml(table)).is_err(), "{table:?}");
+        }
+        let mut registry = Registry::default();
+        registry.add_toml(PING).unwrap();
+        assert!(registry.add_toml(PING).unwrap_err().to_string().contains("two decoders"));
+        assert!(registry.select(&["nope".to_string()]).is_err());
+        assert_eq!(registry.select(&["test".to_string()]).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+//! EIP-1967 proxies, from any emitter:
+//!
+//!   proxy_upgrades       Upgraded and BeaconUpgraded: the `kind`
+//!                        (implementation or beacon) and the new
+//!                        `implementation`, which for a beacon proxy is the
+//!                        beacon
+//!   proxy_admin_changes  AdminChanged
+//!
+//! and a `proxy_implementations` view over the upgrades with a row per
+//! implementation each proxy (`contract`) ran, from the block of its upgrade
+//! to that of the next one (`to_block` NULL while it is current). Both ends
+//! are inclusive, since an upgrade can happen after other calls of its
+//! block; the upgrades' `log_index` tells them apart. A beacon proxy's
+//! implementation is the beacon's, which its own Upgraded events give.
+//! Proxies set up before the dataset starts, and those that don't log
+//! EIP-1967 events, are missing.
+
+use alloy_primitives::hex;
+use eyre::Result;
+use rusqlite::types::Value;
+
+use super::{Column, EventDecoder, Log, ProtocolDecoder};
+use crate::decode;
+use crate::normalize::Normalize;
+
+const VIEW: &str = "
+DROP VIEW IF EXISTS proxy_implementations;
+
+CREATE VIEW proxy_implementations AS
+SELECT contract AS proxy,
+       kind,
+       implementation,
+       block_number AS from_block,
+       log_index AS from_log_index,
+       LEAD(block_number) OVER upgrades AS to_block,
+       LEAD(log_index) OVER upgrades AS to_log_index,
+       tx_hash
+FROM proxy_upgrades
+WINDOW upgrades AS (PARTITION BY contract ORDER BY block_number, log_index);
+";
+
+pub fn decoders() -> Result<Vec<Box<dyn ProtocolDecoder>>> {
+    let admins = EventDecoder::new(
+        "proxy".into(),
+        "proxy_admin_changes".into(),
+        "AdminChanged(address previousAdmin, address newAdmin)",
+        Vec::new(),
+    )?
+    .with_indexes(vec!["contract".into()])?;
+    Ok(vec![Box::new(Upgrades::new()), Box::new(admins)])
+}
+
+struct Upgrades {
+    upgraded: String,
+    beacon_upgraded: String,
+    columns: Vec<Column>,
+    indexes: Vec<String>,
+}
+
+impl Upgrades {
+    fn new() -> Self {
+        let column = |name: &str| Column { name: name.into(), sql_type: "TEXT", nullable: false };
+        Self {
+            upgraded: decode::topic("Upgraded(address)"),
+            beacon_upgraded: decode::topic("BeaconUpgraded(address)"),
+            columns: vec![column("kind"), column("implementation")],
+            indexes: vec!["contract".into(), "implementation".into()],
+        }
+    }
+}
+
+impl ProtocolDecoder for Upgrades {
+    fn protocol(&self) -> &str {
+        "proxy"
+    }
+
+    fn table(&self) -> &str {
+        "proxy_upgrades"
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn topics(&self) -> Vec<String> {
+        vec![self.upgraded.clone(), self.beacon_upgraded.clone()]
+    }
+
+    fn indexes(&self) -> &[String] {
+        &self.indexes
+    }
+
+    fn views(&self, _norm: &Normalize) -> &str {
+        VIEW
+    }
+
+    fn decode(&self, log: &Log, norm: &Normalize) -> Result<Option<Vec<Value>>> {
+        let kind = if log.topic0 == self.upgraded { "implementation" } else { "beacon" };
+        // Indexed as EIP-1967 has it, or in the data as some proxies written
+        // before it log it.
+        let topic = match (log.topics.as_slice(), log.data.len()) {
+            ([address], 0) => address.to_ascii_lowercase(),
+            ([], 32) => format!("0x{}", hex::encode(&log.data)),
+            _ => return Ok(None),
+        };
+        let Some(implementation) = decode::topic_address(&topic) else { return Ok(None) };
+        Ok(Some(vec![
+            Value::Text(kind.to_string()),
+            Value::Text(norm.address("implementation", &implementation)?.into_owned()),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::catch_up;
+    use crate::protocols::tests::{db, log, padded, row, rows};
+
+    const UPGRADED: &str = "0xbc7cd75a20ee27fd9adebab32041f755214dbc6bffa90cc0225b39da2e5c2d3b";
+    const BEACON_UPGRADED: &str =
+        "0x1cf3b03a6cf19fa2baba4df148e9dcabedea7f8a5c07840e207e5c089be95d3e";
+    const ADMIN_CHANGED: &str =
+        "0x7e644d79422f17c01e4894b5f4f588d331ebfa28653d42ae832dc59e38c9798f";
+
+    #[test]
+    fn upgrades_indexed_in_the_data_and_of_beacons() {
+        let conn = db();
+        let (proxy, beacon_proxy) = (
+            "0x00000000000000000000000000000000000000a1",
+            "0x00000000000000000000000000000000000000a2",
+        );
+        let (v1, v2, beacon) = (
+            "0x00000000000000000000000000000000000000b1",
+            "0x00000000000000000000000000000000000000b2",
+            "0x00000000000000000000000000000000000000bc",
+        );
+        log(&conn, proxy, &[UPGRADED, &padded(v1)], &[]);
+        log(&conn, beacon_proxy, &[BEACON_UPGRADED, &padded(beacon)], &[]);
+        // Pre-EIP-1967, in the data.
+        log(&conn, proxy, &[UPGRADED], &hex::decode(padded(v2)).unwrap());
+        // Both, or neither.
+        log(&conn, proxy, &[UPGRADED, &padded(v1)], &hex::decode(padded(v2)).unwrap());
+        log(&conn, proxy, &[UPGRADED], &[]);
+        catch_up(&conn, &["proxy"]).unwrap();
+        assert_eq!(
+            rows(&conn, "proxy_upgrades"),
+            [
+                row(&["implementation", v1]),
+                row(&["beacon", beacon]),
+                row(&["implementation", v2]),
+            ]
+        );
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT proxy, kind, implementation, from_log_index, to_log_index
+                 FROM proxy_implementations ORDER BY proxy, from_log_index",
+            )
+            .unwrap();
+        let implementations: Vec<(String, String, String, i64, Option<i64>)> = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let text = |s: &str| s.to_string();
+        assert_eq!(
+            implementations,
+            [
+                (text(proxy), text("implementation"), text(v1), 0, Some(2)),
+                (text(proxy), text("implementation"), text(v2), 2, None),
+                (text(beacon_proxy), text("beacon"), text(beacon), 1, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn admin_changes() {
+        let conn = db();
+        let proxy = "0x00000000000000000000000000000000000000a1";
+        let (old, new) = (
+            "0x00000000000000000000000000000000000000d1",
+            "0x00000000000000000000000000000000000000d2",
+        );
+        let data = [padded(old), padded(new)].map(|w| hex::decode(w).unwrap()).concat();
+        log(&conn, proxy, &[ADMIN_CHANGED], &data);
+        catch_up(&conn, &["proxy"]).unwrap();
+        assert_eq!(rows(&conn, "proxy_admin_changes"), [row(&[old, new])]);
+    }
+}
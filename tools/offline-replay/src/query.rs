@@ -0,0 +1,204 @@
+//! `query` — run one SQL statement against a database, with other databases
+//! attached, and print the result.
+//!
+//!   offline-replay query post.db "SELECT ..." --attach pre.db AS prev
+//!
+//! Each `--attach FILE [AS ALIAS]` makes FILE's tables available as
+//! `ALIAS.table`, so two loaded datasets (say, the blocks before and after an
+//! incident) can be joined in one statement. Without AS, the alias is the
+//! file's name made into a plain identifier (`pre-incident.db` →
+//! `pre_incident`, a leading digit gets a `_`), with `_2`, `_3`, ... added if
+//! it is taken; `main` and `temp` are SQLite's own and are never used. The
+//! aliases are logged before the statement runs.
+//!
+//! The SQL follows the database, or is `-` to read it from stdin. An
+//! `--attach FILE` without AS would take the SQL for another file, so it goes
+//! after the SQL. Results print as an aligned table, CSV, or one JSON object per row.
+//! Blobs print as 0x-prefixed hex.
+//!
+//! `key('0x…')` and `hexkey(column)` compare and show hashes and addresses
+//! the same way whether or not the database was loaded with --binary-keys:
+//! `WHERE tx_hash = key('0x…')`.
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::hex;
+use clap::{Args, ValueEnum};
+use eyre::{eyre, Context, Result};
+use rusqlite::types::ValueRef;
+use tracing::info;
+
+use crate::{normalize, open_existing};
+
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// The statement to run, or - to read it from stdin
+    sql: String,
+
+    /// Attach another database: FILE, or FILE AS ALIAS (repeatable)
+    #[arg(long, num_args = 1..=3, value_names = ["FILE", "AS", "ALIAS"])]
+    attach: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    /// Columns aligned under a header
+    Table,
+    Csv,
+    /// One JSON object per row
+    Json,
+}
+
+pub fn run(args: QueryArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    normalize::functions(&conn)?;
+
+    let mut taken: HashSet<String> = ["main", "temp"].map(String::from).into();
+    let mut values = args.attach.iter().peekable();
+    while let Some(file) = values.next() {
+        let file = Path::new(file);
+        let alias = if values.next_if(|v| v.eq_ignore_ascii_case("as")).is_some() {
+            let alias = values
+                .next()
+                .ok_or_else(|| eyre!("--attach {} AS needs an alias", file.display()))?;
+            if taken.contains(&alias.to_ascii_lowercase()) {
+                eyre::bail!("--attach alias {alias:?} is already in use");
+            }
+            alias.clone()
+        } else {
+            alias_for(file, &taken)
+        };
+        if !file.is_file() {
+            eyre::bail!("{} does not exist", file.display());
+        }
+        conn.execute(&format!("ATTACH DATABASE ?1 AS {}", quote(&alias)), [file.to_string_lossy()])
+            .with_context(|| format!("Cannot attach {}", file.display()))?;
+        info!(file = %file.display(), alias, "attached");
+        taken.insert(alias.to_ascii_lowercase());
+    }
+
+    let sql = if args.sql == "-" {
+        let mut sql = String::new();
+        std::io::stdin().read_to_string(&mut sql)?;
+        sql
+    } else {
+        args.sql
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    let mut rows = stmt.query([])?;
+    let cells = |row: &rusqlite::Row| -> Result<Vec<String>> {
+        (0..columns.len()).map(|i| Ok(text(row.get_ref(i)?))).collect()
+    };
+    let mut n = 0u64;
+    match args.format {
+        Format::Json => {
+            while let Some(row) = rows.next()? {
+                let mut object = serde_json::Map::new();
+                for (i, name) in columns.iter().enumerate() {
+                    object.insert(name.clone(), json(row.get_ref(i)?));
+                }
+                writeln!(out, "{}", serde_json::Value::Object(object))?;
+                n += 1;
+            }
+        }
+        Format::Csv => {
+            let mut w = csv::Writer::from_writer(&mut out);
+            w.write_record(&columns)?;
+            while let Some(row) = rows.next()? {
+                w.write_record(cells(row)?)?;
+                n += 1;
+            }
+            w.flush()?;
+        }
+        Format::Table => {
+            // Aligning needs every row's widths first.
+            let mut table = Vec::new();
+            while let Some(row) = rows.next()? {
+                table.push(cells(row)?);
+            }
+            n = table.len() as u64;
+            print_table(&mut out, &columns, &table)?;
+        }
+    }
+    out.flush()?;
+    info!(rows = n, "done");
+    Ok(())
+}
+
+pub(crate) fn print_table(out: &mut dyn Write, columns: &[String], table: &[Vec<String>]) -> Result<()> {
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+    for cells in table {
+        for (w, cell) in widths.iter_mut().zip(cells) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let mut line = |cells: &[String]| -> Result<()> {
+        let padded: Vec<String> =
+            cells.iter().zip(&widths).map(|(c, &w)| format!("{c:<w$}")).collect();
+        writeln!(out, "{}", padded.join("  ").trim_end())?;
+        Ok(())
+    };
+    line(columns)?;
+    line(&widths.iter().map(|&w| "-".repeat(w)).collect::<Vec<_>>())?;
+    for cells in table {
+        line(cells)?;
+    }
+    Ok(())
+}
+
+/// An alias for `file` that is a plain identifier and not yet `taken`.
+fn alias_for(file: &Path, taken: &HashSet<String>) -> String {
+    let stem = file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut base: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if base.is_empty() {
+        base = "db".to_string();
+    } else if base.starts_with(|c: char| c.is_ascii_digit()) {
+        base.insert(0, '_');
+    }
+    let mut alias = base.clone();
+    let mut n = 2;
+    while taken.contains(&alias) {
+        alias = format!("{base}_{n}");
+        n += 1;
+    }
+    alias
+}
+
+/// `name` as a quoted SQL identifier.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+pub(crate) fn text(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into_owned(),
+        ValueRef::Blob(b) => format!("0x{}", hex::encode(b)),
+    }
+}
+
+fn json(value: ValueRef) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => f.into(),
+        ValueRef::Text(_) | ValueRef::Blob(_) => text(value).into(),
+    }
+}
@@ -0,0 +1,370 @@
+//! `redact` — rewrite a dataset so it can be shared outside the team:
+//! addresses and transaction hashes are pseudonymized, calldata is dropped.
+//!
+//! Each address is replaced by the first 20 bytes of HMAC-SHA256(key,
+//! address) — the same pseudonym wherever it appears (from/to, log emitter,
+//! indexed address topics, block and ommer miner, withdrawal recipient,
+//! Arbitrum's `refund_to` and `beneficiary`), so the transaction graph and token flows keep their
+//! shape. Transaction hashes are replaced the same way, so they can't be
+//! looked up on an explorer, and logs and Arbitrum redeems (`ticket_id`)
+//! still join to their transactions. Without the key the mapping can't be
+//! rebuilt or reversed.
+//!
+//! An indexed topic is treated as an address if it is a left-padded 20-byte
+//! value that appears as a sender, recipient or log emitter in the dataset,
+//! or is too large (≥ 2^128) to be a typical indexed amount or id. Addresses
+//! inside log data aren't recognised and stay as they are; --strip-log-data
+//! drops log data altogether. The zero address and --keep addresses (say,
+//! well-known tokens, so amounts stay interpretable) are left alone.
+//!
+//! Calldata is emptied (--keep-selectors keeps the 4-byte function
+//! selector), and `logs_bloom` is dropped since it encodes the original
+//! addresses. Blobs keep their data under pseudonymized transaction and
+//! versioned hashes; their KZG commitment and proof, which would give the
+//! real versioned hash away, and `data_ref` are dropped. Block numbers,
+//! timestamps, amounts and gas are kept, so this is pseudonymization, not
+//! anonymization: someone with the chain can still match transactions by
+//! block, index and value.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use alloy_primitives::hex;
+use arrow::array::{new_null_array, Array, ArrayRef, BinaryArray, StringArray};
+use arrow::datatypes::{DataType, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use clap::Args;
+use eyre::{eyre, Context, Result};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::error::DatasetError;
+use crate::{col_i64, col_str};
+use crate::manifest::{BlockRange, Manifest, TableEntry};
+use crate::progress::progress_bar;
+use crate::sample::{chain_id, files, tables};
+use crate::schema::Table;
+use crate::source::DataSource;
+
+#[derive(Args, Debug)]
+pub struct RedactArgs {
+    /// Dataset directory to redact
+    #[arg(short, long, default_value = ".")]
+    data: PathBuf,
+
+    /// Output dataset directory
+    #[arg(short, long)]
+    out: PathBuf,
+
+    /// Secret key for the pseudonyms; the same key gives the same pseudonyms
+    #[arg(long, env = "REDACT_KEY", hide_env_values = true)]
+    key: String,
+
+    /// Addresses to leave as they are (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    keep: Vec<String>,
+
+    /// Keep the 4-byte function selector of each transaction's calldata
+    #[arg(long)]
+    keep_selectors: bool,
+
+    /// Drop log data as well (it can hold non-indexed addresses)
+    #[arg(long)]
+    strip_log_data: bool,
+}
+
+type Addr = [u8; 20];
+
+struct Redactor {
+    key: Vec<u8>,
+    keep: HashSet<Addr>,
+    /// Senders, recipients, emitters, miners and withdrawal recipients:
+    /// topics holding these are addresses.
+    known: HashSet<Addr>,
+    keep_selectors: bool,
+    strip_log_data: bool,
+}
+
+pub fn run(args: RedactArgs) -> Result<()> {
+    if args.key.is_empty() {
+        eyre::bail!("--key must not be empty");
+    }
+    let source = DataSource::new(&args.data)?;
+    let mut keep = HashSet::from([[0u8; 20]]);
+    for a in &args.keep {
+        keep.insert(parse_address(a).ok_or_else(|| eyre!("--keep {a:?} is not an address"))?);
+    }
+
+    // First pass: every address that sends, receives, emits, mines or
+    // withdraws.
+    let mut known = HashSet::new();
+    let present = tables(&source)?;
+    for (table, columns) in [
+        (Table::Blocks, &["miner"][..]),
+        (Table::Ommers, &["miner"][..]),
+        (Table::Transactions, &["from_addr", "to_addr", "refund_to", "beneficiary"][..]),
+        (Table::Logs, &["address"][..]),
+        (Table::Withdrawals, &["address"][..]),
+    ]
+    .into_iter()
+    .filter(|(t, _)| present.contains(t))
+    {
+        let skip: Vec<String> = ["input", "data", "logs_bloom"].map(String::from).to_vec();
+        for file in files(&source, table)? {
+            for batch in source.open(&file, &skip)?.batches {
+                let batch = batch?;
+                // Older datasets have no miner, and most chains no refund_to.
+                for &column in columns.iter().filter(|&&c| batch.schema().index_of(c).is_ok()) {
+                    known.extend(col_str(&batch, column)?.iter().flatten().filter_map(parse_address));
+                }
+            }
+        }
+    }
+    info!(addresses = known.len(), "collected addresses");
+
+    let redactor = Redactor {
+        key: args.key.into_bytes(),
+        keep,
+        known,
+        keep_selectors: args.keep_selectors,
+        strip_log_data: args.strip_log_data,
+    };
+
+    std::fs::create_dir_all(&args.out)
+        .with_context(|| format!("Cannot create {}", args.out.display()))?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let mut range: Option<BlockRange> = None;
+    let mut m = Manifest::new(BlockRange { start: 0, end: 0 });
+    m.chain_id = chain_id(&source)?;
+
+    for table in present {
+        let files = files(&source, table)?;
+        let schema = source.meta(&files[0])?.schema;
+        if table == Table::Blobs && schema.index_of("versioned_hash").is_err() {
+            // The commitment it would be computed from is dropped.
+            eyre::bail!(
+                "{} has no versioned_hash column, which redact needs in place of kzg_commitment",
+                source.display(&files[0])
+            );
+        }
+        let path = args.out.join(table.file());
+        let f = std::fs::File::create(&path)
+            .with_context(|| format!("Cannot create {}", path.display()))?;
+        let mut writer = ArrowWriter::try_new(f, dropping(table, &schema), Some(props.clone()))?;
+
+        let mut rows = 0;
+        for file in &files {
+            let reader = source.open(file, &[])?;
+            let mut pb = progress_bar(reader.total_rows, table.name());
+            for batch in reader.batches {
+                let batch = redactor.batch(table, &batch?)?;
+                if table == Table::Blocks {
+                    for &n in col_i64(&batch, "number")?.values().iter() {
+                        let r = range.get_or_insert(BlockRange { start: n, end: n });
+                        r.start = r.start.min(n);
+                        r.end = r.end.max(n);
+                    }
+                }
+                writer.write(&batch)?;
+                rows += batch.num_rows() as u64;
+                pb.inc(batch.num_rows() as u64);
+            }
+            pb.finish_with_message(format!("{} ✓", table.name()));
+        }
+        writer.close()?;
+        info!(table = table.name(), rows, "redacted");
+        m.tables.insert(table.name().to_string(), TableEntry { file: table.file().to_string(), rows });
+    }
+
+    m.block_range = range.ok_or_else(|| eyre!("the dataset contains no blocks"))?;
+    m.write(&args.out)?;
+    info!(out = %args.out.display(), "done");
+    Ok(())
+}
+
+impl Redactor {
+    /// `batch` with its identifying columns rewritten.
+    fn batch(&self, table: Table, batch: &RecordBatch) -> Result<RecordBatch> {
+        let schema = batch.schema();
+        let mut columns: Vec<ArrayRef> = batch.columns().to_vec();
+        for (i, field) in schema.fields().iter().enumerate() {
+            let column = &batch.columns()[i];
+            let rewritten: Option<ArrayRef> = match (table, field.name().as_str()) {
+                (Table::Blocks | Table::Ommers, "miner")
+                | (Table::Transactions, "from_addr" | "to_addr" | "refund_to" | "beneficiary")
+                | (Table::Logs | Table::Withdrawals, "address") => {
+                    Some(self.strings(field.name(), column, |s| self.address(s))?)
+                }
+                (Table::Transactions, "hash" | "ticket_id")
+                | (Table::Logs, "tx_hash")
+                | (Table::Blobs, "tx_hash" | "versioned_hash") => {
+                    Some(self.strings(field.name(), column, |s| self.hash(s))?)
+                }
+                (Table::Logs, "topic1" | "topic2" | "topic3") => {
+                    Some(self.strings(field.name(), column, |s| Ok(self.topic(s)))?)
+                }
+                (Table::Transactions, "input") => Some(binaries(field.name(), column, |b| {
+                    if self.keep_selectors { &b[..b.len().min(4)] } else { &[] }
+                })?),
+                (Table::Logs, "data") if self.strip_log_data => {
+                    Some(binaries(field.name(), column, |_| &[])?)
+                }
+                (table, name) if dropped(table, name) => {
+                    Some(new_null_array(column.data_type(), column.len()))
+                }
+                _ => None,
+            };
+            if let Some(array) = rewritten {
+                columns[i] = array;
+            }
+        }
+        RecordBatch::try_new(dropping(table, &schema), columns)
+            .with_context(|| format!("Rewriting {}", table.name()))
+    }
+
+    fn strings(
+        &self,
+        name: &str,
+        column: &ArrayRef,
+        f: impl Fn(&str) -> Result<String>,
+    ) -> Result<ArrayRef> {
+        let column = column.as_any().downcast_ref::<StringArray>().ok_or_else(|| {
+            mismatch(name, DataType::Utf8, column)
+        })?;
+        let out: StringArray =
+            column.iter().map(|v| v.map(&f).transpose()).collect::<Result<_>>()?;
+        Ok(Arc::new(out))
+    }
+
+    fn pseudonym(&self, bytes: &[u8]) -> [u8; 32] {
+        hmac_sha256(&self.key, bytes)
+    }
+
+    fn address(&self, address: &str) -> Result<String> {
+        let addr = parse_address(address).ok_or_else(|| eyre!("{address:?} is not an address"))?;
+        if self.keep.contains(&addr) {
+            return Ok(format!("0x{}", hex::encode(addr)));
+        }
+        Ok(format!("0x{}", hex::encode(&self.pseudonym(&addr)[..20])))
+    }
+
+    fn hash(&self, hash: &str) -> Result<String> {
+        let bytes = hex::decode(hash).with_context(|| format!("{hash:?} is not a hash"))?;
+        Ok(format!("0x{}", hex::encode(self.pseudonym(&bytes))))
+    }
+
+    /// An indexed address topic is pseudonymized in place; other topics
+    /// are kept.
+    fn topic(&self, topic: &str) -> String {
+        let Ok(bytes) = hex::decode(topic) else { return topic.to_string() };
+        if bytes.len() != 32 || bytes[..12].iter().any(|&b| b != 0) {
+            return topic.to_string();
+        }
+        let addr: Addr = bytes[12..].try_into().expect("20 bytes");
+        let large = addr[..4].iter().any(|&b| b != 0);
+        if self.keep.contains(&addr) || !(large || self.known.contains(&addr)) {
+            return topic.to_string();
+        }
+        format!("0x{}{}", "0".repeat(24), hex::encode(&self.pseudonym(&addr)[..20]))
+    }
+}
+
+/// Columns emptied altogether: `logs_bloom` encodes the original addresses,
+/// a blob's commitment and proof would identify it as its versioned hash
+/// does, and `data_ref` is a path or URL of the exporter's.
+fn dropped(table: Table, column: &str) -> bool {
+    matches!(
+        (table, column),
+        (Table::Transactions, "logs_bloom")
+            | (Table::Blobs, "kzg_commitment" | "kzg_proof" | "data_ref")
+    )
+}
+
+/// `schema` with the [`dropped`] columns nullable, as they are written.
+fn dropping(table: Table, schema: &Schema) -> SchemaRef {
+    let fields: Vec<_> = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            if dropped(table, f.name()) {
+                Arc::new(f.as_ref().clone().with_nullable(true))
+            } else {
+                f.clone()
+            }
+        })
+        .collect();
+    Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
+}
+
+fn binaries<'a>(
+    name: &str,
+    column: &'a ArrayRef,
+    f: impl Fn(&'a [u8]) -> &'a [u8],
+) -> Result<ArrayRef> {
+    let column = column
+        .as_any()
+        .downcast_ref::<BinaryArray>()
+        .ok_or_else(|| mismatch(name, DataType::Binary, column))?;
+    Ok(Arc::new(column.iter().map(|v| v.map(&f)).collect::<BinaryArray>()))
+}
+
+fn mismatch(name: &str, expected: DataType, column: &ArrayRef) -> DatasetError {
+    DatasetError::TypeMismatch {
+        column: name.to_string(),
+        expected,
+        found: column.data_type().clone(),
+    }
+}
+
+fn parse_address(s: &str) -> Option<Addr> {
+    hex::decode(s.trim()).ok()?.try_into().ok()
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let inner = Sha256::new()
+        .chain_update(block.map(|b| b ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new().chain_update(block.map(|b| b ^ 0x5c)).chain_update(inner).finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 4231's test cases 1, 2 and 6 (a key longer than the block).
+    #[test]
+    fn hmac_sha256_vectors() {
+        for (key, message, mac) in [
+            (
+                vec![0x0b; 20],
+                &b"Hi There"[..],
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe".to_vec(),
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                vec![0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+        ] {
+            assert_eq!(hex::encode(hmac_sha256(&key, message)), mac);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! `--reorg-policy keep|drop|prefer-latest` — what to do with blocks that
+//! are no longer part of the chain, as in exports taken near the head that
+//! saw a reorg.
+//!
+//! Blocks are keyed by (number, hash), so two blocks of the same number can
+//! both be stored, and `is_canonical` says which one the chain kept; the
+//! `idx_blocks_canonical` index allows one canonical block per number. A
+//! blocks file may say which blocks are canonical in an `is_canonical`
+//! column (Boolean or 0/1); a block without one is canonical.
+//!
+//!   drop           non-canonical blocks are not loaded (the default)
+//!   keep           they are loaded with is_canonical = 0
+//!   prefer-latest  for exports that don't say: of the blocks with one
+//!                  number, the last one read is canonical, and the ones
+//!                  before it are kept with is_canonical = 0. That includes
+//!                  a block an earlier load stored: its transactions,
+//!                  logs and the optional tables' rows are deleted
+//!
+//! Transactions, logs and the optional tables (ommers, withdrawals, blobs)
+//! are only loaded for canonical blocks, since a transaction included again
+//! after a reorg has the same hash. The block a row is from is read from a
+//! `block_hash` column of their files; which blocks are not canonical, from
+//! a pre-pass over the blocks files ([`orphans`]). Rows of a file without
+//! `block_hash` are loaded whichever block they are from, keyed as
+//! `--on-conflict` says.
+//!
+//! A second canonical block for a number (a dataset without `is_canonical`
+//! under keep or drop) conflicts with the one stored, like a block loaded
+//! twice: `--on-conflict` decides which stays.
+//!
+//! With number no longer a key of blocks, `transactions.block_number` and
+//! `logs.block_number` have no foreign key to it. Databases created before
+//! schema version 6 keep blocks keyed by number alone, and their foreign
+//! keys, so only `drop` loads into them.
+
+use std::collections::{HashMap, HashSet};
+
+use arrow::array::Int64Array;
+use clap::ValueEnum;
+use eyre::Result;
+use rusqlite::{params, Connection, ToSql};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::schema::Table;
+use crate::source::TableReader;
+use crate::{col_i64, col_i64_opt, col_str, opt_i64};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReorgPolicy {
+    /// Load non-canonical blocks with is_canonical = 0
+    Keep,
+    /// Leave non-canonical blocks out
+    #[default]
+    Drop,
+    /// The last block read of a number is canonical; earlier ones are kept
+    /// with is_canonical = 0
+    PreferLatest,
+}
+
+/// Demote the canonical block of a number to make way for another.
+const DEMOTE_SQL: &str =
+    "UPDATE blocks SET is_canonical = 0 WHERE number = ?1 AND hash <> ?2 AND is_canonical = 1";
+/// A block read again under prefer-latest becomes canonical again.
+const PROMOTE_SQL: &str = "UPDATE blocks SET is_canonical = 1 WHERE number = ?1 AND hash = ?2";
+/// The rows of a demoted block.
+const DELETE_TOPICS_SQL: &str =
+    "DELETE FROM log_topics WHERE log_id IN (SELECT id FROM logs WHERE block_number = ?1)";
+const DELETE_LOGS_SQL: &str = "DELETE FROM logs WHERE block_number = ?1";
+const DELETE_TXS_SQL: &str = "DELETE FROM transactions WHERE block_number = ?1";
+
+impl ReorgPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            ReorgPolicy::Keep => "keep",
+            ReorgPolicy::Drop => "drop",
+            ReorgPolicy::PreferLatest => "prefer-latest",
+        }
+    }
+
+    /// Refuse a policy that stores a second block of a number in a database
+    /// whose blocks are keyed by number alone.
+    pub fn check(self, conn: &Connection) -> Result<()> {
+        if self == ReorgPolicy::Drop {
+            return Ok(());
+        }
+        let key: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('blocks') WHERE pk > 0",
+            [],
+            |r| r.get(0),
+        )?;
+        if key < 2 {
+            eyre::bail!(
+                "database keys blocks by number alone (it predates schema version 6); \
+                 load --reorg-policy {} into a new one",
+                self.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Under prefer-latest, make way for canonical block `hash` of `number`:
+    /// the block stored for it before is demoted and its transactions and
+    /// logs deleted. Returns whether there was one.
+    pub fn demote(self, conn: &Connection, number: i64, hash: &dyn ToSql) -> Result<bool> {
+        if self != ReorgPolicy::PreferLatest {
+            return Ok(false);
+        }
+        if conn.prepare_cached(DEMOTE_SQL)?.execute(params![number, hash])? == 0 {
+            return Ok(false);
+        }
+        // log_topics only exists with --topics-table, the optional tables
+        // with their files.
+        let exists = |table: &str| -> rusqlite::Result<bool> {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+                [table],
+                |r| r.get(0),
+            )
+        };
+        if exists("log_topics")? {
+            conn.prepare_cached(DELETE_TOPICS_SQL)?.execute([number])?;
+        }
+        for table in Table::OPTIONAL.map(Table::name) {
+            if exists(table)? {
+                conn.prepare_cached(&format!("DELETE FROM {table} WHERE block_number = ?1"))?
+                    .execute([number])?;
+            }
+        }
+        let logs = conn.prepare_cached(DELETE_LOGS_SQL)?.execute([number])?;
+        let txs = conn.prepare_cached(DELETE_TXS_SQL)?.execute([number])?;
+        info!(block = number, txs, logs, "reorg: replaced the canonical block");
+        Ok(true)
+    }
+
+    /// Under prefer-latest, mark block `hash` of `number` canonical again
+    /// if an earlier load had demoted it.
+    pub fn promote(self, conn: &Connection, number: i64, hash: &dyn ToSql) -> Result<()> {
+        if self == ReorgPolicy::PreferLatest {
+            conn.prepare_cached(PROMOTE_SQL)?.execute(params![number, hash])?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the block of a row with `is_canonical` (NULL or missing being
+/// canonical) is canonical.
+pub fn canonical(is_canonical: Option<&Int64Array>, i: usize) -> bool {
+    opt_i64(is_canonical, i) != Some(0)
+}
+
+/// The lowercased hashes of the blocks in `tables` that are not canonical
+/// under `policy`: those marked so, and under prefer-latest also every
+/// block followed by another of its number.
+pub fn orphans(
+    tables: impl IntoIterator<Item = Result<TableReader>>,
+    policy: ReorgPolicy,
+) -> Result<HashSet<String>> {
+    let mut orphans = HashSet::new();
+    // The last canonical block read of each number.
+    let mut latest: HashMap<i64, String> = HashMap::new();
+    for table in tables {
+        for batch in table?.batches {
+            let batch = batch?;
+            let number = col_i64(&batch, "number")?;
+            let hash = col_str(&batch, "hash")?;
+            let is_canonical = col_i64_opt(&batch, "is_canonical")?;
+            for i in 0..batch.num_rows() {
+                let hash = hash.value(i).to_ascii_lowercase();
+                if !canonical(is_canonical, i) {
+                    orphans.insert(hash);
+                    continue;
+                }
+                if policy != ReorgPolicy::PreferLatest {
+                    continue;
+                }
+                if let Some(before) = latest.insert(number.value(i), hash.clone()) {
+                    if before != hash {
+                        orphans.insert(before);
+                    }
+                }
+            }
+        }
+    }
+    // A block read again after another of its number is canonical again.
+    for hash in latest.values() {
+        orphans.remove(hash);
+    }
+    Ok(orphans)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use arrow::array::{Array, ArrayRef, StringArray};
+    use arrow::datatypes::{Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use super::*;
+    use crate::open_existing;
+    use crate::testing::{block, count, load_into, log, rewrite, tx, write, Scratch};
+
+    fn hash(n: u64) -> String {
+        format!("0x{n:064x}")
+    }
+
+    /// The same transaction, with value 1 in block 2a and 2 in block 2b.
+    const TX: u64 = 0x77;
+
+    /// Blocks `forks` of number 2 (a: hash 0x2a, b: 0x2b), after block 1
+    /// unless `after` is false, each with the transaction TX and a log; the
+    /// files of transactions and logs say which block they are from, the
+    /// blocks file which are canonical if `canonical` is given.
+    fn dataset(dir: &Path, forks: &[u64], after: bool, canonical: Option<&[i64]>) {
+        let mut blocks = Vec::new();
+        let (mut txs, mut logs, mut block_hashes) = (Vec::new(), Vec::new(), Vec::new());
+        if after {
+            blocks.push(block(1, &hash(1), &hash(0)));
+            txs.push(tx(&hash(0x11), 1, 0));
+            logs.push(log(&hash(0x11), 1, 0));
+            block_hashes.push(hash(1));
+        }
+        for &fork in forks {
+            blocks.push(block(2, &hash(fork), &hash(1)));
+            let mut t = tx(&hash(TX), 2, 0);
+            t.value = (fork - 0x29).to_string();
+            txs.push(t);
+            logs.push(log(&hash(TX), 2, 0));
+            block_hashes.push(hash(fork));
+        }
+        write(dir, blocks, txs, logs);
+
+        let add = |batch: RecordBatch, name: &str, column: ArrayRef| {
+            let mut fields = batch.schema().fields().to_vec();
+            fields.push(Arc::new(Field::new(name, column.data_type().clone(), true)));
+            let mut columns = batch.columns().to_vec();
+            columns.push(column);
+            RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap()
+        };
+        for file in ["transactions.parquet", "logs.parquet"] {
+            let column = Arc::new(StringArray::from(block_hashes.clone()));
+            rewrite(&dir.join(file), |b| add(b, "block_hash", column));
+        }
+        if let Some(canonical) = canonical {
+            let column = Arc::new(Int64Array::from(canonical.to_vec()));
+            rewrite(&dir.join("blocks.parquet"), |b| add(b, "is_canonical", column));
+        }
+    }
+
+    /// The blocks of number 2 by hash with is_canonical, the value of the
+    /// transaction stored, and the number of logs.
+    fn stored(db: &Path) -> (Vec<(String, i64)>, Vec<String>, i64) {
+        let conn = open_existing(db).unwrap();
+        let blocks = conn
+            .prepare("SELECT hash, is_canonical FROM blocks WHERE number = 2 ORDER BY hash")
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let values = conn
+            .prepare("SELECT value FROM transactions WHERE block_number = 2")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        (blocks, values, count(&conn, "SELECT COUNT(*) FROM logs"))
+    }
+
+    #[test]
+    fn drop_leaves_the_orphan_out() {
+        let dir = Scratch::new("reorg-drop");
+        dataset(&dir.join("data"), &[0x2a, 0x2b], true, Some(&[1, 0, 1]));
+        load_into(&dir.join("data"), &dir.join("out.db"), "").unwrap();
+        assert_eq!(stored(&dir.join("out.db")), (vec![(hash(0x2b), 1)], vec!["2".into()], 2));
+    }
+
+    #[test]
+    fn keep_stores_the_orphan_without_its_rows() {
+        let dir = Scratch::new("reorg-keep");
+        dataset(&dir.join("data"), &[0x2a, 0x2b], true, Some(&[1, 0, 1]));
+        load_into(&dir.join("data"), &dir.join("out.db"), "--reorg-policy keep").unwrap();
+        let blocks = vec![(hash(0x2a), 0), (hash(0x2b), 1)];
+        assert_eq!(stored(&dir.join("out.db")), (blocks, vec!["2".into()], 2));
+
+        // idx_blocks_canonical: one canonical block per number.
+        let conn = open_existing(&dir.join("out.db")).unwrap();
+        let promote = "UPDATE blocks SET is_canonical = 1 WHERE hash = ?1";
+        assert!(conn.execute(promote, [hash(0x2a)]).is_err());
+    }
+
+    #[test]
+    fn prefer_latest_takes_the_last_block_read() {
+        let dir = Scratch::new("reorg-latest");
+        dataset(&dir.join("data"), &[0x2a, 0x2b], true, None);
+        load_into(&dir.join("data"), &dir.join("out.db"), "--reorg-policy prefer-latest")
+            .unwrap();
+        let blocks = vec![(hash(0x2a), 0), (hash(0x2b), 1)];
+        assert_eq!(stored(&dir.join("out.db")), (blocks, vec!["2".into()], 2));
+    }
+
+    #[test]
+    fn prefer_latest_demotes_and_promotes_across_loads() {
+        let dir = Scratch::new("reorg-loads");
+        let (a, b, db) = (dir.join("a"), dir.join("b"), dir.join("out.db"));
+        dataset(&a, &[0x2a], true, None);
+        dataset(&b, &[0x2b], false, None);
+        let policy = "--reorg-policy prefer-latest";
+        load_into(&a, &db, policy).unwrap();
+        assert_eq!(stored(&db), (vec![(hash(0x2a), 1)], vec!["1".into()], 2));
+
+        // The stored block's transaction and log make way for the new one's.
+        load_into(&b, &db, &format!("{policy} --mode append")).unwrap();
+        let blocks = vec![(hash(0x2a), 0), (hash(0x2b), 1)];
+        assert_eq!(stored(&db), (blocks, vec!["2".into()], 2));
+
+        load_into(&a, &db, &format!("{policy} --mode append --on-conflict replace")).unwrap();
+        let blocks = vec![(hash(0x2a), 1), (hash(0x2b), 0)];
+        assert_eq!(stored(&db), (blocks, vec!["1".into()], 2));
+    }
+
+    #[test]
+    fn check_refuses_a_second_block_where_number_is_the_key() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE blocks (number INTEGER PRIMARY KEY, hash TEXT);").unwrap();
+        assert!(ReorgPolicy::Drop.check(&conn).is_ok());
+        assert!(ReorgPolicy::Keep.check(&conn).is_err());
+        assert!(ReorgPolicy::PreferLatest.check(&conn).is_err());
+    }
+}
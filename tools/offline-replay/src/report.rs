@@ -0,0 +1,150 @@
+//! `--report report.json` — timings and sizes of a load, written when it
+//! finishes, so loader performance can be compared across dataset versions
+//! and machines:
+//!
+//!   tables           per table: files, rows, compressed Parquet bytes read
+//!                    (after --skip-columns), seconds spent and rows/s
+//!   seconds          wall time of the whole load, and overall rows/s
+//!   db_bytes         database size before and after, and the difference
+//!                    (null for --shard-blocks output)
+//!   pragmas          the settings the preset and --pragma left in effect
+//!   manifest_sha256  which dataset version was loaded, with `source`
+//!   machine          os, arch, cpus and host name
+//!
+//! With --watch the report covers the initial load and is written before
+//! watching starts. Table seconds include reading, filtering and inserting;
+//! --optimize, --vacuum-into and a SQL dump count towards the total only.
+
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use eyre::{Context, Result};
+use serde_json::json;
+use tracing::info;
+
+use crate::schema::Table;
+
+pub struct Report {
+    started: Instant,
+    started_at: u64,
+    source: String,
+    manifest_sha256: Option<String>,
+    pragmas: Vec<(String, String)>,
+    tables: Vec<TableTiming>,
+    db_bytes_before: Option<i64>,
+}
+
+struct TableTiming {
+    table: Table,
+    files: u64,
+    rows: u64,
+    bytes_read: u64,
+    elapsed: Duration,
+}
+
+impl Report {
+    pub fn new(
+        source: String,
+        manifest_sha256: Option<String>,
+        pragmas: Vec<(String, String)>,
+    ) -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            source,
+            manifest_sha256,
+            pragmas,
+            tables: Vec::new(),
+            db_bytes_before: None,
+        }
+    }
+
+    pub fn db_bytes_before(&mut self, bytes: i64) {
+        self.db_bytes_before = Some(bytes);
+    }
+
+    /// Count one loaded file towards its table.
+    pub fn file(&mut self, table: Table, rows: u64, bytes_read: u64, elapsed: Duration) {
+        let timing = match self.tables.iter_mut().position(|t| t.table == table) {
+            Some(i) => &mut self.tables[i],
+            None => {
+                self.tables.push(TableTiming {
+                    table,
+                    files: 0,
+                    rows: 0,
+                    bytes_read: 0,
+                    elapsed: Duration::ZERO,
+                });
+                self.tables.last_mut().expect("just pushed")
+            }
+        };
+        timing.files += 1;
+        timing.rows += rows;
+        timing.bytes_read += bytes_read;
+        timing.elapsed += elapsed;
+    }
+
+    pub fn write(&self, path: &Path, out: &Path, db_bytes_after: Option<i64>) -> Result<()> {
+        let seconds = self.started.elapsed().as_secs_f64();
+        let rows: u64 = self.tables.iter().map(|t| t.rows).sum();
+        let tables: Vec<_> = self
+            .tables
+            .iter()
+            .map(|t| {
+                json!({
+                    "table": t.table.name(),
+                    "files": t.files,
+                    "rows": t.rows,
+                    "bytes_read": t.bytes_read,
+                    "seconds": t.elapsed.as_secs_f64(),
+                    "rows_per_sec": per_sec(t.rows, t.elapsed.as_secs_f64()),
+                })
+            })
+            .collect();
+        let pragmas: serde_json::Map<String, serde_json::Value> =
+            self.pragmas.iter().map(|(name, value)| (name.clone(), value.as_str().into())).collect();
+        let delta = self.db_bytes_before.zip(db_bytes_after).map(|(before, after)| after - before);
+
+        let report = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "started_at": self.started_at,
+            "source": self.source,
+            "manifest_sha256": self.manifest_sha256,
+            "out": out.display().to_string(),
+            "seconds": seconds,
+            "rows": rows,
+            "rows_per_sec": per_sec(rows, seconds),
+            "bytes_read": self.tables.iter().map(|t| t.bytes_read).sum::<u64>(),
+            "tables": tables,
+            "db_bytes": {
+                "before": self.db_bytes_before,
+                "after": db_bytes_after,
+                "delta": delta,
+            },
+            "pragmas": pragmas,
+            "machine": {
+                "os": std::env::consts::OS,
+                "arch": std::env::consts::ARCH,
+                "cpus": std::thread::available_parallelism().map_or(1, |n| n.get()),
+                "host": host(),
+            },
+        });
+        let mut text = serde_json::to_string_pretty(&report)?;
+        text.push('\n');
+        std::fs::write(path, text).with_context(|| format!("Cannot write {}", path.display()))?;
+        info!(path = %path.display(), "wrote load report");
+        Ok(())
+    }
+}
+
+fn per_sec(rows: u64, seconds: f64) -> f64 {
+    rows as f64 / seconds.max(f64::EPSILON)
+}
+
+fn host() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+}
@@ -0,0 +1,213 @@
+//! Revert data decoded into a reason: what a failed call returned, as the
+//! `traces` table's frames carry it in `output` and `call` gets it back.
+//!
+//!   error   `Error(string)`, what `require(cond, "...")` and `revert("...")`
+//!           return: the message
+//!   panic   `Panic(uint256)`, from failed asserts and checked arithmetic:
+//!           the code and what Solidity uses it for
+//!   custom  a custom error named in a `--abi` ABI: `Name(arg, ...)`, with
+//!           strings, bytes and static values decoded and other values
+//!           shown by type
+//!   empty   no data, as from `require(cond)` or a bare `revert()`
+//!   unknown anything else: a custom error no ABI names, or raw data
+//!
+//! Halts other than a revert (out of gas, an invalid opcode) return nothing;
+//! the trace's `error` says what happened to them.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use alloy_primitives::{hex, Address, I256, U256};
+
+use crate::abi::Entry;
+
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// What Solidity's Panic codes stand for.
+const PANIC_CODES: &[(u64, &str)] = &[
+    (0x00, "generic compiler panic"),
+    (0x01, "assert failed"),
+    (0x11, "arithmetic overflow or underflow"),
+    (0x12, "division or modulo by zero"),
+    (0x21, "invalid enum value"),
+    (0x22, "invalid storage byte array"),
+    (0x31, "pop on an empty array"),
+    (0x32, "array index out of bounds"),
+    (0x41, "out of memory"),
+    (0x51, "call to a zero function pointer"),
+];
+
+/// Revert data, decoded.
+pub struct Reason {
+    /// error, panic, custom, empty or unknown, as above.
+    pub kind: &'static str,
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.reason, self.kind) {
+            (Some(reason), _) => f.write_str(reason),
+            (None, "empty") => f.write_str("no reason given"),
+            (None, _) => f.write_str("undecoded revert data"),
+        }
+    }
+}
+
+/// Decode `output`, naming custom errors from `errors` (by selector).
+pub fn decode(output: &[u8], errors: &HashMap<String, Entry>) -> Reason {
+    let reason = |kind, reason| Reason { kind, reason: Some(reason) };
+    let Some(selector) = output.get(..4) else {
+        return Reason { kind: if output.is_empty() { "empty" } else { "unknown" }, reason: None };
+    };
+    let args = &output[4..];
+    if selector == ERROR_SELECTOR {
+        if let Some(message) = dynamic(args, 0).map(|b| String::from_utf8_lossy(b).into_owned()) {
+            return reason("error", message);
+        }
+    } else if selector == PANIC_SELECTOR {
+        if let Some(code) = word(args, 0).map(U256::from_be_slice) {
+            let meaning = PANIC_CODES
+                .iter()
+                .find(|(c, _)| U256::from(*c) == code)
+                .map_or("unknown panic code", |(_, m)| m);
+            return reason("panic", format!("0x{code:02x} {meaning}"));
+        }
+    } else if let Some(error) = errors.get(&hex::encode_prefixed(selector)) {
+        // A static tuple or fixed-size array takes more than one head word,
+        // so the values after one can't be found without a full decoder.
+        let mut lost = false;
+        let values: Vec<String> = error
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| {
+                let shown = if lost { format!("<{ty}>") } else { value(args, i, ty) };
+                lost |= ty.starts_with('(') || ty.ends_with(']') && !ty.ends_with("[]");
+                shown
+            })
+            .collect();
+        return reason("custom", format!("{}({})", error.name, values.join(", ")));
+    }
+    Reason { kind: "unknown", reason: None }
+}
+
+/// The `i`th head word of ABI-encoded `args`.
+fn word(args: &[u8], i: usize) -> Option<&[u8]> {
+    args.get(i * 32..(i + 1) * 32)
+}
+
+/// The bytes or string whose offset is in head word `i`.
+fn dynamic(args: &[u8], i: usize) -> Option<&[u8]> {
+    let offset = usize::try_from(U256::from_be_slice(word(args, i)?)).ok()?;
+    let len = U256::from_be_slice(args.get(offset..offset.checked_add(32)?)?);
+    let start = offset + 32;
+    args.get(start..start.checked_add(usize::try_from(len).ok()?)?)
+}
+
+/// Head word `i` of `args` as a value of type `ty`, as cast prints it.
+fn value(args: &[u8], i: usize, ty: &str) -> String {
+    let shown = match ty {
+        "string" => dynamic(args, i).map(|b| format!("{:?}", String::from_utf8_lossy(b))),
+        "bytes" => dynamic(args, i).map(hex::encode_prefixed),
+        _ => word(args, i).and_then(|w| match ty {
+            "address" => Some(Address::from_slice(&w[12..]).to_checksum(None)),
+            "bool" => Some((w[31] != 0).to_string()),
+            _ if ty.starts_with("uint") && !ty.contains('[') => {
+                Some(U256::from_be_slice(w).to_string())
+            }
+            _ if ty.starts_with("int") && !ty.contains('[') => {
+                Some(I256::from_raw(U256::from_be_slice(w)).to_string())
+            }
+            _ => {
+                let size: usize = ty.strip_prefix("bytes")?.parse().ok()?;
+                w.get(..size).map(hex::encode_prefixed)
+            }
+        }),
+    };
+    // Arrays and tuples are left undecoded.
+    shown.unwrap_or_else(|| format!("<{ty}>"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ABI-encoded words, from 0x-hex of up to 32 bytes each, right-aligned
+    /// unless they start with `<` (left-aligned, as bytes and strings are).
+    fn words(words: &[&str]) -> Vec<u8> {
+        words
+            .iter()
+            .flat_map(|w| {
+                let bytes = hex::decode(w.trim_start_matches('<')).unwrap();
+                let pad = vec![0u8; 32 - bytes.len()];
+                if w.starts_with('<') { [bytes, pad].concat() } else { [pad, bytes].concat() }
+            })
+            .collect()
+    }
+
+    fn output(selector: [u8; 4], args: &[&str]) -> Vec<u8> {
+        [&selector[..], &words(args)].concat()
+    }
+
+    #[test]
+    fn error_and_panic() {
+        let none = HashMap::new();
+        let r = decode(&output(ERROR_SELECTOR, &["20", "04", "<6e6f7065"]), &none);
+        assert_eq!((r.kind, r.reason.as_deref()), ("error", Some("nope")));
+        let r = decode(&output(PANIC_SELECTOR, &["11"]), &none);
+        assert_eq!(
+            (r.kind, r.reason.as_deref()),
+            ("panic", Some("0x11 arithmetic overflow or underflow"))
+        );
+        let r = decode(&output(PANIC_SELECTOR, &["99"]), &none);
+        assert_eq!(r.reason.as_deref(), Some("0x99 unknown panic code"));
+        // An offset past the end.
+        let r = decode(&output(ERROR_SELECTOR, &["40", "04"]), &none);
+        assert_eq!((r.kind, r.reason.as_deref()), ("unknown", None));
+    }
+
+    #[test]
+    fn empty_and_unknown() {
+        let none = HashMap::new();
+        assert_eq!(decode(&[], &none).kind, "empty");
+        assert_eq!(decode(&[0x08, 0xc3], &none).kind, "unknown");
+        assert_eq!(decode(&output([1, 2, 3, 4], &["01"]), &none).kind, "unknown");
+        assert_eq!(decode(&[], &none).to_string(), "no reason given");
+    }
+
+    #[test]
+    fn custom_errors() {
+        let error = Entry {
+            name: "Insufficient".into(),
+            inputs: ["address", "uint256", "int8", "bool", "bytes2", "string", "uint256[2]", "bool"]
+                .map(String::from)
+                .to_vec(),
+        };
+        let selector: [u8; 4] = hex::decode(error.selector()).unwrap().try_into().unwrap();
+        let errors = HashMap::from([(error.selector(), error)]);
+        let args = [
+            "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            "0de0b6b3a7640000",
+            &"ff".repeat(32),
+            "01",
+            "<beef",
+            "0120",
+            "01",
+            "02",
+            "01",
+            "05",
+            "<68656c6c6f",
+        ];
+        let r = decode(&output(selector, &args), &errors);
+        assert_eq!(r.kind, "custom");
+        assert_eq!(
+            r.reason.as_deref(),
+            Some(
+                "Insufficient(0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed, 1000000000000000000, \
+                 -1, true, 0xbeef, \"hello\", <uint256[2]>, <bool>)"
+            )
+        );
+    }
+}
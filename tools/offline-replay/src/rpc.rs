@@ -0,0 +1,133 @@
+//! Minimal blocking JSON-RPC client with the same retry policy as
+//! scripts/capture.py:
+//!   - 429 Too Many Requests: exponential backoff (delay, 2x, 4x, ...)
+//!   - 5xx / network errors / transient JSON-RPC errors: linear backoff
+//!   - other 4xx, method-not-found, invalid params: fail immediately
+
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
+use tracing::warn;
+
+/// JSON-RPC error codes that will never succeed on retry.
+const PERMANENT_RPC_ERRORS: [i64; 3] = [-32600, -32601, -32602];
+
+pub struct RpcClient {
+    url: String,
+    agent: ureq::Agent,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl RpcClient {
+    pub fn new(url: &str, retries: u32, retry_delay: Duration) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(60))
+            .build();
+        Self { url: url.to_string(), agent, retries: retries.max(1), retry_delay }
+    }
+
+    pub fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let last = attempt >= self.retries;
+
+            let wait = match self.agent.post(&self.url).send_json(&body) {
+                Ok(resp) => {
+                    let mut v: Value = resp.into_json()?;
+                    let Some(err) = v.get("error") else {
+                        return Ok(v["result"].take());
+                    };
+                    let code = err["code"].as_i64().unwrap_or(0);
+                    if last || PERMANENT_RPC_ERRORS.contains(&code) {
+                        return Err(eyre!("{method} failed: {err}"));
+                    }
+                    self.retry_delay * attempt
+                }
+                Err(ureq::Error::Status(429, _)) if !last => {
+                    self.retry_delay * 2u32.pow(attempt - 1)
+                }
+                Err(ureq::Error::Status(code, _)) if !last && code >= 500 => {
+                    self.retry_delay * attempt
+                }
+                Err(ureq::Error::Transport(_)) if !last => self.retry_delay * attempt,
+                Err(e) => return Err(eyre!("{method} failed: {e}")),
+            };
+            warn!(method, attempt, wait_ms = wait.as_millis() as u64, "RPC call failed, retrying");
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+impl RpcClient {
+    /// eth_call `calldata` against contract `to` at `block` (None for latest).
+    pub fn eth_call(&self, to: &str, calldata: &[u8], block: Option<i64>) -> Result<Vec<u8>> {
+        let block = block.map_or_else(|| "latest".to_string(), |b| format!("0x{b:x}"));
+        let input: String = calldata.iter().map(|b| format!("{b:02x}")).collect();
+        let result = self.call("eth_call", json!([{ "to": to, "data": format!("0x{input}") }, block]))?;
+        data(&result)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Quantity helpers
+// ---------------------------------------------------------------------------
+
+/// Parse a hex quantity ("0x1a") into an integer.
+pub fn quantity(v: &Value) -> Result<u128> {
+    let s = v.as_str().ok_or_else(|| eyre!("expected hex quantity, got {v}"))?;
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u128::from_str_radix(digits, 16).map_err(|e| eyre!("bad quantity {s}: {e}"))
+}
+
+pub fn quantity_i64(v: &Value) -> Result<i64> {
+    i64::try_from(quantity(v)?).map_err(|_| eyre!("quantity out of range: {v}"))
+}
+
+/// Decode hex data ("0xdeadbeef") into bytes.
+pub fn data(v: &Value) -> Result<Vec<u8>> {
+    let s = v.as_str().ok_or_else(|| eyre!("expected hex data, got {v}"))?;
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    if digits.len() % 2 != 0 {
+        return Err(eyre!("odd-length hex data: {s}"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|e| eyre!("bad hex {s}: {e}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn quantities() {
+        assert_eq!(quantity(&json!("0x1a")).unwrap(), 26);
+        assert_eq!(quantity(&json!("0x0")).unwrap(), 0);
+        assert_eq!(quantity(&json!("0x")).unwrap(), 0);
+        assert_eq!(quantity(&json!("ff")).unwrap(), 255);
+        assert_eq!(quantity(&json!(format!("0x{}", "f".repeat(32)))).unwrap(), u128::MAX);
+        assert!(quantity(&json!(format!("0x1{}", "0".repeat(32)))).is_err());
+        assert!(quantity(&json!("0xzz")).is_err());
+        assert!(quantity(&json!(26)).is_err());
+        assert!(quantity_i64(&json!("0x8000000000000000")).is_err());
+    }
+
+    #[test]
+    fn hex_data() {
+        assert_eq!(data(&json!("0xdeadBEEF")).unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+        assert!(data(&json!("0x")).unwrap().is_empty());
+        assert!(data(&json!("0xabc")).is_err());
+        assert!(data(&json!("0xgg")).is_err());
+    }
+}
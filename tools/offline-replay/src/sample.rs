@@ -0,0 +1,181 @@
+//! `sample` — cut a small dataset out of a large one, for iterating on
+//! analysis SQL before running it against the full archive.
+//!
+//! Whole blocks are kept, with every transaction and log in them, so the
+//! sample is as consistent as its source: each log's transaction and each
+//! transaction's block are in it too.
+//!
+//!   --every N    every Nth block present, starting with the first
+//!   --random N   N blocks picked at random (repeatable with --seed)
+//!
+//! The input is a dataset directory as the loader reads it (single files or
+//! <table>/*.parquet parts, or a URL with the `remote` feature); the output
+//! is a dataset directory with one file per table, the optional ones it has
+//! included, and a manifest.json whose block range spans the first to the
+//! last block kept.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use arrow::array::UInt32Array;
+use arrow::compute::take_record_batch;
+use clap::Args;
+use eyre::{Context, Result};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use tracing::info;
+
+use crate::col_i64;
+use crate::generate::Rng;
+use crate::manifest::{BlockRange, Manifest, TableEntry, MANIFEST_FILE};
+use crate::progress::progress_bar;
+use crate::schema::Table;
+use crate::source::DataSource;
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("pick").required(true).args(["every", "random"])))]
+pub struct SampleArgs {
+    /// Dataset directory to sample from
+    #[arg(short, long, default_value = ".")]
+    data: PathBuf,
+
+    /// Output dataset directory
+    #[arg(short, long)]
+    out: PathBuf,
+
+    /// Keep every Nth block
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    every: Option<u64>,
+
+    /// Keep this many blocks, picked at random
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    random: Option<u64>,
+
+    /// Seed for --random
+    #[arg(long, default_value_t = 1, requires = "random")]
+    seed: u64,
+}
+
+pub fn run(args: SampleArgs) -> Result<()> {
+    let source = DataSource::new(&args.data)?;
+
+    let mut numbers = Vec::new();
+    for file in files(&source, Table::Blocks)? {
+        for batch in source.open(&file, &[])?.batches {
+            numbers.extend(col_i64(&batch?, block_column(Table::Blocks))?.values());
+        }
+    }
+    numbers.sort_unstable();
+    numbers.dedup();
+    if numbers.is_empty() {
+        eyre::bail!("{} contains no blocks", source.display(Table::Blocks.file()));
+    }
+
+    let keep: Vec<i64> = match (args.every, args.random) {
+        (Some(n), _) => numbers.iter().copied().step_by(n as usize).collect(),
+        (None, Some(n)) => {
+            // Partial Fisher-Yates: the first n slots end up a uniform sample.
+            let n = (n as usize).min(numbers.len());
+            let mut rng = Rng(args.seed);
+            for i in 0..n {
+                let j = i + rng.below((numbers.len() - i) as u64) as usize;
+                numbers.swap(i, j);
+            }
+            numbers.truncate(n);
+            numbers.sort_unstable();
+            numbers
+        }
+        (None, None) => unreachable!("clap requires --every or --random"),
+    };
+    let range = BlockRange { start: keep[0], end: keep[keep.len() - 1] };
+    let keep: HashSet<i64> = keep.into_iter().collect();
+    info!(blocks = keep.len(), start = range.start, end = range.end, "sampling");
+
+    std::fs::create_dir_all(&args.out)
+        .with_context(|| format!("Cannot create {}", args.out.display()))?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let mut m = Manifest::new(range);
+    m.chain_id = chain_id(&source)?;
+
+    for table in tables(&source)? {
+        let files = files(&source, table)?;
+        let schema = source.meta(&files[0])?.schema;
+        let path = args.out.join(table.file());
+        let f = std::fs::File::create(&path)
+            .with_context(|| format!("Cannot create {}", path.display()))?;
+        let mut writer = ArrowWriter::try_new(f, schema, Some(props.clone()))?;
+
+        let mut rows = 0;
+        for file in &files {
+            let reader = source.open(file, &[])?;
+            let mut pb = progress_bar(reader.total_rows, table.name());
+            for batch in reader.batches {
+                let batch = batch?;
+                let blocks = col_i64(&batch, block_column(table))?;
+                let picked: Vec<u32> = (0..batch.num_rows())
+                    .filter(|&i| keep.contains(&blocks.value(i)))
+                    .map(|i| i as u32)
+                    .collect();
+                if !picked.is_empty() {
+                    let part = take_record_batch(&batch, &UInt32Array::from(picked))?;
+                    writer.write(&part)?;
+                    rows += part.num_rows() as u64;
+                }
+                pb.inc(batch.num_rows() as u64);
+            }
+            pb.finish_with_message(format!("{} ✓", table.name()));
+        }
+        writer.close()?;
+        info!(table = table.name(), rows, "sampled");
+        m.tables.insert(table.name().to_string(), TableEntry { file: table.file().to_string(), rows });
+    }
+
+    m.write(&args.out)?;
+    info!(out = %args.out.display(), "done");
+    Ok(())
+}
+
+/// The dataset's tables: the three every dataset has, then the optional
+/// ones it has files for.
+pub(crate) fn tables(source: &DataSource) -> Result<Vec<Table>> {
+    let mut tables = Table::ALL.to_vec();
+    for table in Table::OPTIONAL {
+        if !source.list(table.name())?.is_empty() {
+            tables.push(table);
+        }
+    }
+    Ok(tables)
+}
+
+/// The chain id of the dataset's manifest, for the manifests of datasets
+/// made from it, so `--chain` can still check them.
+pub(crate) fn chain_id(source: &DataSource) -> Result<Option<u64>> {
+    let Some(bytes) = source.read(MANIFEST_FILE)? else { return Ok(None) };
+    let manifest: serde_json::Value = serde_json::from_slice(&bytes)
+        .with_context(|| format!("Bad JSON in {}", source.display(MANIFEST_FILE)))?;
+    Ok(manifest["chain_id"].as_u64())
+}
+
+/// The table's files, which must exist: a dataset missing one is incomplete.
+pub(crate) fn files(source: &DataSource, table: Table) -> Result<Vec<String>> {
+    let files = source.list(table.name())?;
+    if files.is_empty() {
+        eyre::bail!("Missing file: {}", source.display(table.file()));
+    }
+    Ok(files)
+}
+
+/// The column holding the block number of a table's rows.
+pub(crate) fn block_column(table: Table) -> &'static str {
+    match table {
+        Table::Blocks => "number",
+        Table::Transactions
+        | Table::Logs
+        | Table::Ommers
+        | Table::Withdrawals
+        | Table::Blobs => "block_number",
+    }
+}
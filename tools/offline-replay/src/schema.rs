@@ -0,0 +1,568 @@
+//! Table definitions: the SQLite schema the loader writes, and the Parquet
+//! columns it expects to read for each table.
+
+use arrow::datatypes::DataType;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::chain::Chain;
+use crate::normalize::{Normalize, NumericEncoding};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Table {
+    Blocks,
+    Transactions,
+    Logs,
+    Ommers,
+    Withdrawals,
+    Blobs,
+}
+
+impl Table {
+    /// The tables every dataset has.
+    pub const ALL: [Table; 3] = [Table::Blocks, Table::Transactions, Table::Logs];
+
+    /// Tables loaded when the dataset has them (see [`optional_schema`]).
+    pub const OPTIONAL: [Table; 3] = [Table::Ommers, Table::Withdrawals, Table::Blobs];
+
+    pub fn optional(self) -> bool {
+        Table::OPTIONAL.contains(&self)
+    }
+
+    /// A table by its name, optional ones included.
+    pub fn named(name: &str) -> Option<Table> {
+        Table::ALL.into_iter().chain(Table::OPTIONAL).find(|t| t.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Table::Blocks => "blocks",
+            Table::Transactions => "transactions",
+            Table::Logs => "logs",
+            Table::Ommers => "ommers",
+            Table::Withdrawals => "withdrawals",
+            Table::Blobs => "blobs",
+        }
+    }
+
+    pub fn file(self) -> &'static str {
+        match self {
+            Table::Blocks => "blocks.parquet",
+            Table::Transactions => "transactions.parquet",
+            Table::Logs => "logs.parquet",
+            Table::Ommers => "ommers.parquet",
+            Table::Withdrawals => "withdrawals.parquet",
+            Table::Blobs => "blobs.parquet",
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Expected Parquet columns
+// ---------------------------------------------------------------------------
+
+/// Arrow physical type the loader reads a column as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Utf8,
+    Int64,
+    Binary,
+}
+
+impl Kind {
+    pub fn data_type(self) -> DataType {
+        match self {
+            Kind::Utf8 => DataType::Utf8,
+            Kind::Int64 => DataType::Int64,
+            Kind::Binary => DataType::Binary,
+        }
+    }
+}
+
+pub struct ColumnSpec {
+    pub name: &'static str,
+    pub kind: Kind,
+    /// Whether the loader accepts nulls in this column.
+    pub nullable: bool,
+}
+
+pub(crate) const fn col(name: &'static str, kind: Kind, nullable: bool) -> ColumnSpec {
+    ColumnSpec { name, kind, nullable }
+}
+
+const BLOCKS_COLUMNS: &[ColumnSpec] = &[
+    col("number", Kind::Int64, false),
+    col("hash", Kind::Utf8, false),
+    col("parent_hash", Kind::Utf8, false),
+    col("timestamp", Kind::Int64, false),
+    col("gas_used", Kind::Int64, false),
+    col("gas_limit", Kind::Int64, false),
+    col("base_fee", Kind::Utf8, true),
+    col("tx_count", Kind::Int64, false),
+    col("is_canonical", Kind::Int64, true),
+    col("miner", Kind::Utf8, true),
+];
+
+const TRANSACTIONS_COLUMNS: &[ColumnSpec] = &[
+    col("hash", Kind::Utf8, false),
+    col("block_number", Kind::Int64, false),
+    col("tx_index", Kind::Int64, false),
+    col("from_addr", Kind::Utf8, false),
+    col("to_addr", Kind::Utf8, true),
+    col("value", Kind::Utf8, false),
+    col("gas_used", Kind::Int64, false),
+    col("gas_price", Kind::Utf8, false),
+    col("input", Kind::Binary, true),
+    col("status", Kind::Int64, false),
+    col("logs_bloom", Kind::Binary, true),
+    col("nonce", Kind::Int64, true),
+    col("block_hash", Kind::Utf8, true),
+];
+
+const LOGS_COLUMNS: &[ColumnSpec] = &[
+    col("block_number", Kind::Int64, false),
+    col("tx_hash", Kind::Utf8, false),
+    col("log_index", Kind::Int64, false),
+    col("address", Kind::Utf8, false),
+    col("topic0", Kind::Utf8, true),
+    col("topic1", Kind::Utf8, true),
+    col("topic2", Kind::Utf8, true),
+    col("topic3", Kind::Utf8, true),
+    col("data", Kind::Binary, true),
+    col("block_hash", Kind::Utf8, true),
+];
+
+const OMMERS_COLUMNS: &[ColumnSpec] = &[
+    col("block_number", Kind::Int64, false),
+    col("ommer_index", Kind::Int64, false),
+    col("hash", Kind::Utf8, false),
+    col("number", Kind::Int64, false),
+    col("parent_hash", Kind::Utf8, false),
+    col("miner", Kind::Utf8, false),
+    col("timestamp", Kind::Int64, false),
+    col("gas_used", Kind::Int64, false),
+    col("gas_limit", Kind::Int64, false),
+    col("reward", Kind::Utf8, true),
+    col("block_hash", Kind::Utf8, true),
+];
+
+/// EIP-4895 withdrawals, as a block's `withdrawals` list has them; `amount`
+/// is in Gwei.
+const WITHDRAWALS_COLUMNS: &[ColumnSpec] = &[
+    col("block_number", Kind::Int64, false),
+    col("withdrawal_index", Kind::Int64, false),
+    col("validator_index", Kind::Int64, false),
+    col("address", Kind::Utf8, false),
+    col("amount", Kind::Int64, false),
+    col("block_hash", Kind::Utf8, true),
+];
+
+/// EIP-4844 blob sidecars, one per blob a transaction carried (see blobs.rs).
+const BLOBS_COLUMNS: &[ColumnSpec] = &[
+    col("block_number", Kind::Int64, false),
+    col("tx_hash", Kind::Utf8, false),
+    col("blob_index", Kind::Int64, false),
+    col("versioned_hash", Kind::Utf8, true),
+    col("kzg_commitment", Kind::Utf8, true),
+    col("kzg_proof", Kind::Utf8, true),
+    col("data", Kind::Binary, true),
+    col("data_ref", Kind::Utf8, true),
+    col("block_hash", Kind::Utf8, true),
+];
+
+impl Table {
+    pub fn columns(self) -> &'static [ColumnSpec] {
+        match self {
+            Table::Blocks => BLOCKS_COLUMNS,
+            Table::Transactions => TRANSACTIONS_COLUMNS,
+            Table::Logs => LOGS_COLUMNS,
+            Table::Ommers => OMMERS_COLUMNS,
+            Table::Withdrawals => WITHDRAWALS_COLUMNS,
+            Table::Blobs => BLOBS_COLUMNS,
+        }
+    }
+}
+
+/// Columns only read to tell canonical blocks from reorged ones (see
+/// reorg.rs); a file without them has none.
+pub const REORG_COLUMNS: [&str; 2] = ["is_canonical", "block_hash"];
+
+/// `--light`: transaction calldata and log data, which make up most of a
+/// dataset's bytes.
+pub const LIGHT_COLUMNS: [&str; 2] = ["input", "data"];
+
+/// Check `--skip-columns` names. Only nullable columns can be left out; they
+/// load as NULL (or an empty blob for `input`).
+pub fn check_skip_columns(names: &[String]) -> eyre::Result<()> {
+    let mut skippable: Vec<&str> = Vec::new();
+    for table in Table::ALL.into_iter().chain(Table::OPTIONAL) {
+        for spec in Chain::ALL.into_iter().flat_map(|c| c.columns(table)) {
+            if spec.nullable && !skippable.contains(&spec.name) {
+                skippable.push(spec.name);
+            }
+        }
+    }
+    for name in names {
+        if !skippable.contains(&name.as_str()) {
+            eyre::bail!(
+                "Column {name:?} can't be skipped; skippable columns are {}",
+                skippable.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// SQLite schema
+// ---------------------------------------------------------------------------
+
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS blocks (
+    number       INTEGER NOT NULL,
+    hash         TEXT    NOT NULL,
+    parent_hash  TEXT    NOT NULL,
+    timestamp    INTEGER NOT NULL,
+    gas_used     INTEGER NOT NULL,
+    gas_limit    INTEGER NOT NULL,
+    base_fee     TEXT,
+    tx_count     INTEGER NOT NULL,
+    is_canonical INTEGER NOT NULL DEFAULT 1,
+    miner        TEXT,
+    PRIMARY KEY (number, hash)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_blocks_canonical ON blocks(number) WHERE is_canonical = 1;
+
+CREATE TABLE IF NOT EXISTS transactions (
+    hash         TEXT    PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    tx_index     INTEGER NOT NULL,
+    from_addr    TEXT    NOT NULL,
+    to_addr      TEXT,
+    value        TEXT    NOT NULL,
+    gas_used     INTEGER NOT NULL,
+    gas_price    TEXT    NOT NULL,
+    input        BLOB    NOT NULL,
+    status       INTEGER NOT NULL,
+    fee_wei      TEXT,
+    log_count    INTEGER NOT NULL DEFAULT 0,
+    logs_bloom   BLOB,
+    nonce        INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS logs (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    block_number INTEGER NOT NULL,
+    tx_hash      TEXT    NOT NULL REFERENCES transactions(hash),
+    log_index    INTEGER NOT NULL,
+    address      TEXT    NOT NULL,
+    topic0       TEXT,
+    topic1       TEXT,
+    topic2       TEXT,
+    topic3       TEXT,
+    data         BLOB
+);
+
+CREATE TABLE IF NOT EXISTS _loaded_files (
+    location   TEXT    PRIMARY KEY,
+    table_name TEXT    NOT NULL,
+    rows       INTEGER NOT NULL,
+    loaded_at  INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS _meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+/// The secondary indexes every database gets unless `--no-default-index` is
+/// given (see indexes.rs): lookups by block, sender, recipient, emitting
+/// contract and event.
+pub const DEFAULT_INDEXES: &str = "
+CREATE INDEX IF NOT EXISTS idx_tx_block ON transactions(block_number);
+CREATE INDEX IF NOT EXISTS idx_tx_from  ON transactions(from_addr);
+CREATE INDEX IF NOT EXISTS idx_tx_to    ON transactions(to_addr);
+CREATE INDEX IF NOT EXISTS idx_log_block   ON logs(block_number);
+CREATE INDEX IF NOT EXISTS idx_log_address ON logs(address);
+CREATE INDEX IF NOT EXISTS idx_log_topic0  ON logs(topic0);
+";
+
+/// `--strict`: the tables of SCHEMA as STRICT tables, so a value of the wrong
+/// type fails its INSERT instead of being stored as whatever SQLite makes of
+/// it, and `transactions` as a WITHOUT ROWID table keyed by hash: rows live in
+/// the hash b-tree, so there is no separate hash index and a lookup by hash is
+/// one probe. Its secondary indexes hold the hash where they would hold an
+/// 8-byte rowid, which usually makes the file larger overall. Columns are in
+/// the same order, so rows copy between the two with `SELECT *`. AMOUNT is
+/// replaced by the type the --numeric-encoding stores, and HEX (hashes,
+/// addresses and topics) by the --binary-keys one (see [`strict_schema`]).
+const STRICT_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS blocks (
+    number       INTEGER NOT NULL,
+    hash         HEX     NOT NULL,
+    parent_hash  HEX     NOT NULL,
+    timestamp    INTEGER NOT NULL,
+    gas_used     INTEGER NOT NULL,
+    gas_limit    INTEGER NOT NULL,
+    base_fee     AMOUNT,
+    tx_count     INTEGER NOT NULL,
+    is_canonical INTEGER NOT NULL DEFAULT 1,
+    miner        HEX,
+    PRIMARY KEY (number, hash)
+) STRICT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_blocks_canonical ON blocks(number) WHERE is_canonical = 1;
+
+CREATE TABLE IF NOT EXISTS transactions (
+    hash         HEX     PRIMARY KEY NOT NULL,
+    block_number INTEGER NOT NULL,
+    tx_index     INTEGER NOT NULL,
+    from_addr    HEX     NOT NULL,
+    to_addr      HEX,
+    value        AMOUNT  NOT NULL,
+    gas_used     INTEGER NOT NULL,
+    gas_price    AMOUNT  NOT NULL,
+    input        BLOB    NOT NULL,
+    status       INTEGER NOT NULL,
+    fee_wei      AMOUNT,
+    log_count    INTEGER NOT NULL DEFAULT 0,
+    logs_bloom   BLOB,
+    nonce        INTEGER
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS logs (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    block_number INTEGER NOT NULL,
+    tx_hash      HEX     NOT NULL REFERENCES transactions(hash),
+    log_index    INTEGER NOT NULL,
+    address      HEX     NOT NULL,
+    topic0       HEX,
+    topic1       HEX,
+    topic2       HEX,
+    topic3       HEX,
+    data         BLOB
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS _loaded_files (
+    location   TEXT    PRIMARY KEY,
+    table_name TEXT    NOT NULL,
+    rows       INTEGER NOT NULL,
+    loaded_at  INTEGER NOT NULL
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS _meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+) STRICT;
+";
+
+/// STRICT_SCHEMA with amount columns typed for `norm.numeric` (BLOB for
+/// blob32, TEXT for the text encodings) and key columns for
+/// `norm.binary_keys`.
+pub fn strict_schema(norm: &Normalize) -> String {
+    strict_types(norm, STRICT_SCHEMA)
+}
+
+/// `sql` with AMOUNT and HEX replaced as in [`strict_schema`].
+pub(crate) fn strict_types(norm: &Normalize, sql: &str) -> String {
+    let amount = match norm.numeric {
+        NumericEncoding::Blob32 => "BLOB",
+        NumericEncoding::Hex | NumericEncoding::Decimal => "TEXT",
+    };
+    let key = if norm.binary_keys { "BLOB" } else { "TEXT" };
+    sql.replace("AMOUNT", amount).replace("HEX", key)
+}
+
+/// The uncles of pre-merge blocks, from an optional ommers.parquet (see
+/// ommers.rs), keyed by the nephew block that included them and their
+/// position in its uncle list. `number` is the uncle's own height.
+const OMMERS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS ommers (
+    block_number INTEGER NOT NULL,
+    ommer_index  INTEGER NOT NULL,
+    hash         HEX     NOT NULL,
+    number       INTEGER NOT NULL,
+    parent_hash  HEX     NOT NULL,
+    miner        HEX     NOT NULL,
+    timestamp    INTEGER NOT NULL,
+    gas_used     INTEGER NOT NULL,
+    gas_limit    INTEGER NOT NULL,
+    reward       AMOUNT,
+    PRIMARY KEY (block_number, ommer_index)
+) STRICT;
+CREATE INDEX IF NOT EXISTS idx_ommers_miner ON ommers(miner);
+";
+
+/// Withdrawals from the beacon chain to execution-layer addresses, from an
+/// optional withdrawals.parquet, keyed by their global index. `amount` is in
+/// Gwei, as the beacon chain counts it.
+const WITHDRAWALS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS withdrawals (
+    withdrawal_index INTEGER PRIMARY KEY,
+    block_number     INTEGER NOT NULL,
+    validator_index  INTEGER NOT NULL,
+    address          HEX     NOT NULL,
+    amount           INTEGER NOT NULL
+) STRICT;
+CREATE INDEX IF NOT EXISTS idx_withdrawals_block   ON withdrawals(block_number);
+CREATE INDEX IF NOT EXISTS idx_withdrawals_address ON withdrawals(address);
+";
+
+/// Blob sidecars from an optional blobs.parquet, keyed by their transaction
+/// and position in it (see blobs.rs). `data` is the blob, stored like
+/// calldata; `data_ref` says where it is kept instead.
+const BLOBS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS blobs (
+    tx_hash        HEX     NOT NULL,
+    blob_index     INTEGER NOT NULL,
+    block_number   INTEGER NOT NULL,
+    versioned_hash HEX     NOT NULL,
+    kzg_commitment TEXT,
+    kzg_proof      TEXT,
+    data           BLOB,
+    data_ref       TEXT,
+    PRIMARY KEY (tx_hash, blob_index)
+) STRICT;
+CREATE INDEX IF NOT EXISTS idx_blobs_block          ON blobs(block_number);
+CREATE INDEX IF NOT EXISTS idx_blobs_versioned_hash ON blobs(versioned_hash);
+";
+
+/// The schema of one of the [`Table::OPTIONAL`] tables, created when a
+/// load has its files: a STRICT table typed like [`strict_schema`] in a
+/// `--strict` database, and like SCHEMA otherwise.
+pub fn optional_schema(table: Table, norm: &Normalize, strict: bool) -> String {
+    let sql = match table {
+        Table::Ommers => OMMERS_SCHEMA,
+        Table::Withdrawals => WITHDRAWALS_SCHEMA,
+        Table::Blobs => BLOBS_SCHEMA,
+        Table::Blocks | Table::Transactions | Table::Logs => return String::new(),
+    };
+    if strict {
+        strict_types(norm, sql)
+    } else {
+        sql.replace(" STRICT", "").replace("HEX", "TEXT").replace("AMOUNT", "TEXT")
+    }
+}
+
+/// `--log-key`: the columns that identify a log, kept unique by the
+/// `idx_log_key` index so loading the same logs again doesn't store them
+/// twice. Logs keep their `id`, which log_topics and the decoded tables
+/// refer to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogKey {
+    /// (block_number, tx_hash, log_index)
+    #[default]
+    Full,
+    /// (block_number, log_index): enough where log_index counts the logs of
+    /// a block, as on Ethereum, with a smaller index
+    Compact,
+    /// No key: every load appends its logs
+    None,
+}
+
+impl LogKey {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogKey::Full => "full",
+            LogKey::Compact => "compact",
+            LogKey::None => "none",
+        }
+    }
+
+    pub fn columns(self) -> &'static [&'static str] {
+        match self {
+            LogKey::Full => &["block_number", "tx_hash", "log_index"],
+            LogKey::Compact => &["block_number", "log_index"],
+            LogKey::None => &[],
+        }
+    }
+
+    /// Create `idx_log_key` over this key's columns; fails if logs already
+    /// has duplicates of them.
+    pub fn create_index(self, conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+        if self == LogKey::None {
+            return Ok(());
+        }
+        conn.execute_batch(&format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_log_key ON logs({});",
+            self.columns().join(", ")
+        ))
+    }
+}
+
+/// `--topics-table`: every non-null topic of a log as its own row, so "any
+/// topic equals X" is one index lookup instead of four OR'd columns.
+pub const TOPICS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS log_topics (
+    log_id   INTEGER NOT NULL REFERENCES logs(id),
+    position INTEGER NOT NULL,
+    topic    TEXT    NOT NULL,
+    PRIMARY KEY (log_id, position)
+);
+CREATE INDEX IF NOT EXISTS idx_log_topics_topic ON log_topics(topic, log_id);
+";
+
+/// Address labels imported by the `labels` subcommand; addresses lowercase.
+pub const LABELS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS labels (
+    address  TEXT PRIMARY KEY,
+    name     TEXT NOT NULL,
+    category TEXT,
+    source   TEXT
+);
+";
+
+/// Primary ENS names found by the `ens` subcommand; NULL where an address has
+/// none, so reruns skip it.
+pub const ENS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS ens_names (
+    address     TEXT    PRIMARY KEY,
+    name        TEXT,
+    source      TEXT    NOT NULL,
+    resolved_at INTEGER NOT NULL
+);
+";
+
+/// Why failed calls failed, from `analyze revert-reasons` (see revert.rs);
+/// frame 0 is the transaction's own call, which `v_tx_with_block` shows.
+pub const REVERT_REASONS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS revert_reasons (
+    tx_hash      TEXT    NOT NULL,
+    block_number INTEGER NOT NULL,
+    frame        INTEGER NOT NULL,
+    depth        INTEGER NOT NULL,
+    address      TEXT,
+    caught       INTEGER NOT NULL,
+    kind         TEXT    NOT NULL,
+    reason       TEXT,
+    data         TEXT,
+    PRIMARY KEY (tx_hash, frame)
+);
+CREATE INDEX IF NOT EXISTS idx_revert_reasons_block ON revert_reasons(block_number);
+";
+
+/// Token/USD prices loaded with --prices; `usd_per_unit` is the price of one
+/// base unit (price_usd / 10^decimals), for the USD view to multiply by.
+pub const PRICES_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS prices (
+    token        TEXT    NOT NULL,
+    timestamp    INTEGER NOT NULL,
+    price_usd    REAL    NOT NULL,
+    decimals     INTEGER NOT NULL,
+    usd_per_unit REAL    NOT NULL,
+    source       TEXT,
+    PRIMARY KEY (token, timestamp)
+);
+";
+
+/// Fill log_topics from the topic columns of logs already in the database.
+/// Idempotent, so it can run over a table that is partly filled.
+pub const TOPICS_BACKFILL: &str = "
+INSERT OR IGNORE INTO log_topics (log_id, position, topic)
+          SELECT id, 0, topic0 FROM logs WHERE topic0 IS NOT NULL
+UNION ALL SELECT id, 1, topic1 FROM logs WHERE topic1 IS NOT NULL
+UNION ALL SELECT id, 2, topic2 FROM logs WHERE topic2 IS NOT NULL
+UNION ALL SELECT id, 3, topic3 FROM logs WHERE topic3 IS NOT NULL;
+";
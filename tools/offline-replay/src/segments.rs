@@ -0,0 +1,294 @@
+//! Segmented datasets — a base dataset extended by numbered deltas, so that
+//! publishing a longer block range means uploading the new blocks only:
+//!
+//!   manifest.json              the base: blocks start..=end
+//!   blocks.parquet, ...        (or <table>/*.parquet parts)
+//!   deltas/0001/manifest.json  blocks end+1..=end2
+//!   deltas/0001/blocks.parquet, ...
+//!   deltas/0002/...
+//!
+//! Each delta is a dataset directory of its own, as `fetch`, `sample` or
+//! `split` write them, with a manifest whose block range starts right after
+//! the previous segment's. Deltas are numbered from 1 with no gaps, and are
+//! found by their manifests (`deltas/0001/manifest.json`, `0002`, ... up to
+//! the first missing one), so a remote dataset needs no directory listing.
+//! A published segment never changes; anything published later goes into
+//! the next delta.
+//!
+//! A load of a segmented dataset loads the base and then each delta, and
+//! records in `_meta` the last segment it applied (`segment`) and the hash
+//! of the base manifest (`segment_base`). A later `--mode append` load of
+//! the same dataset into that database applies only the deltas after it,
+//! and stops if the base isn't the one the database was built from. Files
+//! are their own transactions and recorded in `_loaded_files`, so a load
+//! that stopped part of the way through a delta picks up at its next file.
+
+use eyre::{Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+
+use crate::manifest::{BlockRange, Manifest, MANIFEST_FILE};
+use crate::migrate::set_meta;
+use crate::source::DataSource;
+
+const DELTAS_DIR: &str = "deltas";
+
+/// The base dataset (number 0) or one of its deltas.
+pub struct Segment {
+    pub number: u64,
+    /// Where the segment's files are, relative to the dataset root.
+    pub prefix: String,
+    pub block_range: BlockRange,
+}
+
+pub struct Segments {
+    /// SHA-256 of the base manifest.
+    pub base: String,
+    pub list: Vec<Segment>,
+}
+
+/// The segments of `source`, if it has deltas.
+pub fn discover(source: &DataSource) -> Result<Option<Segments>> {
+    if matches!(source, DataSource::Stdin { .. }) || source.read(&manifest_of(1))?.is_none() {
+        return Ok(None);
+    }
+    let Some(bytes) = source.read(MANIFEST_FILE)? else {
+        eyre::bail!(
+            "{} has deltas but no base {MANIFEST_FILE}",
+            source.display(&format!("{DELTAS_DIR}/"))
+        );
+    };
+    let base = format!("{:x}", Sha256::digest(&bytes));
+    let manifest = parse(source, MANIFEST_FILE, &bytes)?;
+    let mut list =
+        vec![Segment { number: 0, prefix: String::new(), block_range: manifest.block_range }];
+    for number in 1.. {
+        let file = manifest_of(number);
+        let Some(bytes) = source.read(&file)? else { break };
+        let range = parse(source, &file, &bytes)?.block_range;
+        let previous = list.last().expect("the base").block_range;
+        if range.start != previous.end + 1 || range.end < range.start {
+            eyre::bail!(
+                "{} covers blocks {}..={}, but the segment before it ends at block {}; \
+                 each delta must start right after the previous one",
+                source.display(&file),
+                range.start,
+                range.end,
+                previous.end
+            );
+        }
+        list.push(Segment { number, prefix: format!("{}/", dir_of(number)), block_range: range });
+    }
+    Ok(Some(Segments { base, list }))
+}
+
+impl Segments {
+    /// The files of `table` in every segment, in segment order.
+    pub fn list(&self, source: &DataSource, table: &str) -> Result<Vec<String>> {
+        let mut files = Vec::new();
+        for segment in &self.list {
+            files.extend(source.list(&format!("{}{table}", segment.prefix))?);
+        }
+        Ok(files)
+    }
+
+    pub fn last(&self) -> &Segment {
+        self.list.last().expect("the base")
+    }
+
+    /// The last segment applied to the database, checking that it was
+    /// built from this base. None for a database with nothing loaded.
+    pub fn applied(&self, conn: &Connection) -> Result<Option<u64>> {
+        let meta = |key: &str| {
+            conn.query_row("SELECT value FROM _meta WHERE key = ?1", [key], |r| {
+                r.get::<_, String>(0)
+            })
+            .optional()
+        };
+        match (meta("segment")?, meta("segment_base")?) {
+            (Some(segment), Some(base)) => {
+                if base != self.base {
+                    eyre::bail!(
+                        "The database was built from another base dataset (manifest \
+                         {base}); load it into a new database"
+                    );
+                }
+                let segment: u64 =
+                    segment.parse().with_context(|| format!("Bad _meta segment {segment:?}"))?;
+                if segment > self.last().number {
+                    eyre::bail!(
+                        "The database has segment {segment} applied, but the dataset ends \
+                         at segment {}",
+                        self.last().number
+                    );
+                }
+                Ok(Some(segment))
+            }
+            _ => {
+                let loaded: bool =
+                    conn.query_row("SELECT EXISTS (SELECT 1 FROM blocks)", [], |r| r.get(0))?;
+                if loaded {
+                    eyre::bail!(
+                        "The database already holds blocks, but not from a segmented dataset; \
+                         load the dataset into a new database"
+                    );
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Record that every segment up to the last has been applied.
+    pub fn record(&self, conn: &Connection) -> Result<()> {
+        set_meta(conn, "segment", &self.last().number.to_string())?;
+        set_meta(conn, "segment_base", &self.base)?;
+        Ok(())
+    }
+}
+
+/// The segment `file` (relative to the dataset root) belongs to.
+pub fn number(file: &str) -> u64 {
+    file.strip_prefix(DELTAS_DIR)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .and_then(|rest| rest.split('/').next())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0)
+}
+
+fn dir_of(number: u64) -> String {
+    format!("{DELTAS_DIR}/{number:04}")
+}
+
+fn manifest_of(number: u64) -> String {
+    format!("{}/{MANIFEST_FILE}", dir_of(number))
+}
+
+fn parse(source: &DataSource, file: &str, bytes: &[u8]) -> Result<Manifest> {
+    serde_json::from_slice(bytes).with_context(|| format!("Cannot parse {}", source.display(file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::open_existing;
+    use crate::testing::{cli, count, generate, load_into, Scratch};
+    use crate::Command;
+
+    #[test]
+    fn numbers_of_files() {
+        for (file, expected) in [
+            ("blocks.parquet", 0),
+            ("logs/0.parquet", 0),
+            ("deltas/0002/logs.parquet", 2),
+            ("deltas/0010/transactions/3.parquet", 10),
+            ("deltas.parquet", 0),
+        ] {
+            assert_eq!(number(file), expected, "{file}");
+        }
+    }
+
+    /// A generated dataset of 20 blocks, split into its 4 segments of 7
+    /// blocks, in `chunks` (by block range, in order).
+    fn segments(dir: &Scratch) -> Vec<PathBuf> {
+        let (data, chunks) = (dir.join("data"), dir.join("chunks"));
+        generate(&data, 20);
+        let argv = format!(
+            "offline-replay split --data {} --out {} --blocks 7",
+            data.display(),
+            chunks.display()
+        );
+        let Some(Command::Split(args)) = cli(&argv).command else { unreachable!() };
+        crate::split::run(args).unwrap();
+        let mut list: Vec<PathBuf> =
+            std::fs::read_dir(&chunks).unwrap().map(|e| e.unwrap().path()).collect();
+        list.sort();
+        assert_eq!(list.len(), 4);
+        list
+    }
+
+    /// Copy the files of segment `from` into the dataset at `to`, as
+    /// delta `number` (or the base, for 0).
+    fn publish(from: &Path, to: &Path, number: u64) {
+        let to = if number == 0 { to.to_path_buf() } else { to.join(dir_of(number)) };
+        std::fs::create_dir_all(&to).unwrap();
+        for file in std::fs::read_dir(from).unwrap() {
+            let file = file.unwrap();
+            std::fs::copy(file.path(), to.join(file.file_name())).unwrap();
+        }
+    }
+
+    #[test]
+    fn appends_apply_the_deltas_published_since() {
+        let dir = Scratch::new("segments");
+        let chunks = segments(&dir);
+        let (dataset, db) = (dir.join("dataset"), dir.join("segments.db"));
+        publish(&chunks[0], &dataset, 0);
+        publish(&chunks[1], &dataset, 1);
+        load_into(&dataset, &db, "").unwrap();
+        let segment = "SELECT CAST(value AS INTEGER) FROM _meta WHERE key = 'segment'";
+        assert_eq!(count(&open_existing(&db).unwrap(), segment), 1);
+
+        publish(&chunks[2], &dataset, 2);
+        publish(&chunks[3], &dataset, 3);
+        let source = DataSource::new(&dataset).unwrap();
+        let found = discover(&source).unwrap().unwrap();
+        let numbers: Vec<u64> = found.list.iter().map(|s| s.number).collect();
+        assert_eq!(numbers, [0, 1, 2, 3]);
+        assert_eq!(found.applied(&open_existing(&db).unwrap()).unwrap(), Some(1));
+        load_into(&dataset, &db, "--mode append").unwrap();
+
+        // As one load of the whole dataset, with each block once.
+        load_into(&dir.join("data"), &dir.join("whole.db"), "").unwrap();
+        let conn = open_existing(&db).unwrap();
+        assert_eq!(count(&conn, segment), 3);
+        let whole = dir.join("whole.db");
+        conn.execute("ATTACH DATABASE ?1 AS whole", [whole.to_string_lossy()]).unwrap();
+        for table in ["blocks", "transactions", "logs"] {
+            let differ = format!(
+                "SELECT COUNT(*) FROM (SELECT * FROM main.{table}
+                                       EXCEPT SELECT * FROM whole.{table})"
+            );
+            assert_eq!(count(&conn, &differ), 0, "{table}");
+            let rows = format!("SELECT COUNT(*) FROM main.{table}");
+            assert_eq!(count(&conn, &rows), count(&conn, &rows.replace("main.", "whole.")));
+        }
+    }
+
+    #[test]
+    fn a_delta_must_start_after_the_segment_before_it() {
+        let dir = Scratch::new("segments-gap");
+        let chunks = segments(&dir);
+        let dataset = dir.join("dataset");
+        publish(&chunks[0], &dataset, 0);
+        publish(&chunks[2], &dataset, 1);
+        let e = discover(&DataSource::new(&dataset).unwrap()).err().unwrap();
+        assert!(e.to_string().contains("each delta must start right after"), "{e}");
+    }
+
+    #[test]
+    fn appends_refuse_another_base_or_an_unsegmented_database() {
+        let dir = Scratch::new("segments-base");
+        let chunks = segments(&dir);
+        let (dataset, db) = (dir.join("dataset"), dir.join("segments.db"));
+        publish(&chunks[0], &dataset, 0);
+        publish(&chunks[1], &dataset, 1);
+        load_into(&dataset, &db, "").unwrap();
+
+        // The same blocks, published from another base manifest.
+        let other = dir.join("other");
+        publish(&chunks[0], &other, 0);
+        publish(&chunks[1], &other, 1);
+        let manifest = other.join(MANIFEST_FILE);
+        let text = std::fs::read_to_string(&manifest).unwrap();
+        std::fs::write(&manifest, format!("{text}\n")).unwrap();
+        let e = load_into(&other, &db, "--mode append").unwrap_err();
+        assert!(e.to_string().contains("another base dataset"), "{e}");
+
+        let plain = dir.join("plain.db");
+        load_into(&chunks[0], &plain, "").unwrap();
+        let e = load_into(&dataset, &plain, "--mode append").unwrap_err();
+        assert!(e.to_string().contains("not from a segmented dataset"), "{e}");
+    }
+}
@@ -0,0 +1,460 @@
+//! `shell` — an interactive prompt on a database: SQL, plus shortcuts for
+//! the lookups that otherwise need a query written out each time.
+//!
+//!   .block N                      the block and its transactions
+//!   .tx HASH                      the transaction and its logs
+//!   .logs [key=value ...]         logs matching address=, topic0=..topic3=,
+//!                                 block=N or block=A..B, tx=HASH (limit=,
+//!                                 default 20)
+//!   .tables / .schema [NAME]      what's in the database
+//!   .history [N] / !N / !!        earlier entries, and running one again
+//!
+//! topic0= takes a hash, an event signature (`Transfer(address,address,
+//! uint256)`) or a name from `event_signatures` and the events `--views`
+//! seeds it with (`Transfer`). Addresses and hashes match in whatever case
+//! the database stores them, and address topics print as the address. SQL
+//! runs once a line ends with `;`, and prints as an aligned table.
+//!
+//! Entries typed at a terminal are appended to ~/.offline_replay_history
+//! (or --history), one per line. There is no line editing; with input piped
+//! in, the shell runs it as a script, without prompts or history.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use alloy_primitives::{hex, Address};
+use clap::Args;
+use eyre::{eyre, Context, Result};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+
+use crate::normalize::{self, read_key, Normalize};
+use crate::query::{print_table, text};
+use crate::{decode, open_existing, views};
+
+#[derive(Args, Debug)]
+pub struct ShellArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// History file (default: ~/.offline_replay_history)
+    #[arg(long)]
+    history: Option<PathBuf>,
+}
+
+const HELP: &str = "\
+SQL ending in ;          run it
+.block N                 a block and its transactions
+.tx HASH                 a transaction and its logs
+.logs [key=value ...]    logs by address=, topic0=..topic3=, block=N|A..B, tx=, limit=
+.tables                  tables and views
+.schema [NAME]           CREATE statements
+.history [N]             the last N entries (default 20)
+!N, !!                   run history entry N, or the last one
+.help, .quit";
+
+/// Rows `.logs` prints without limit=.
+const DEFAULT_LIMIT: i64 = 20;
+
+/// Calldata and log data longer than this print cut short.
+const SHOWN_BYTES: usize = 64;
+
+struct Shell {
+    conn: Connection,
+    norm: Normalize,
+    /// topic0 → event name, for the `event` column.
+    events: HashMap<String, String>,
+}
+
+pub fn run(args: ShellArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let norm = Normalize::from_meta(&conn)?;
+    normalize::functions(&conn)?;
+    let shell = Shell { events: views::event_names(&conn)?, conn, norm };
+
+    let interactive = std::io::stdin().is_terminal();
+    let history_file = args.history.or_else(|| {
+        std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".offline_replay_history"))
+    });
+    let mut history: Vec<String> = match &history_file {
+        Some(path) if interactive => std::fs::read_to_string(path)
+            .map(|s| s.lines().map(String::from).collect())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    let mut history_out = match &history_file {
+        Some(path) if interactive => OpenOptions::new().create(true).append(true).open(path).ok(),
+        _ => None,
+    };
+    if interactive {
+        println!("offline-replay shell on {} — .help for commands", args.db.display());
+    }
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut pending = String::new();
+    loop {
+        if interactive {
+            print!("{}", if pending.is_empty() { "replay> " } else { "   ...> " });
+            std::io::stdout().flush()?;
+        }
+        let Some(line) = lines.next() else { break };
+        let line = line?;
+        let trimmed = line.trim();
+        if pending.is_empty() && trimmed.is_empty() {
+            continue;
+        }
+
+        let entry = if pending.is_empty() && trimmed.starts_with('!') {
+            let n = &trimmed[1..];
+            let recalled = if n == "!" {
+                history.last()
+            } else {
+                n.parse::<usize>().ok().and_then(|n| history.get(n.wrapping_sub(1)))
+            };
+            match recalled {
+                Some(entry) => {
+                    println!("{entry}");
+                    entry.clone()
+                }
+                None => {
+                    eprintln!("error: no history entry {n}");
+                    continue;
+                }
+            }
+        } else if pending.is_empty() && trimmed.starts_with('.') {
+            trimmed.to_string()
+        } else {
+            if !pending.is_empty() {
+                pending.push(' ');
+            }
+            pending.push_str(trimmed);
+            if !pending.ends_with(';') {
+                continue;
+            }
+            std::mem::take(&mut pending)
+        };
+
+        if interactive && history.last() != Some(&entry) {
+            if let Some(out) = &mut history_out {
+                let _ = writeln!(out, "{entry}");
+            }
+            history.push(entry.clone());
+        }
+        if matches!(entry.as_str(), ".quit" | ".exit") {
+            break;
+        }
+        let result = if let Some(rest) = entry.strip_prefix(".history") {
+            print_history(&history, rest.trim())
+        } else {
+            shell.run(&entry)
+        };
+        if let Err(e) = result {
+            eprintln!("error: {e:#}");
+        }
+    }
+    if !pending.is_empty() {
+        shell.run(&pending)?;
+    }
+    Ok(())
+}
+
+fn print_history(history: &[String], n: &str) -> Result<()> {
+    let n = if n.is_empty() { 20 } else { n.parse().context(".history takes a count")? };
+    for (i, entry) in history.iter().enumerate().skip(history.len().saturating_sub(n)) {
+        println!("{:>5}  {entry}", i + 1);
+    }
+    Ok(())
+}
+
+impl Shell {
+    fn run(&self, entry: &str) -> Result<()> {
+        let (command, rest) = entry.split_once(char::is_whitespace).unwrap_or((entry, ""));
+        let rest = rest.trim();
+        match command {
+            ".help" => println!("{HELP}"),
+            ".block" => {
+                self.block(rest.parse().with_context(|| format!("{rest:?} is not a block"))?)?
+            }
+            ".tx" => self.tx(rest)?,
+            ".logs" => self.logs(rest)?,
+            ".tables" => self.sql(
+                "SELECT name, type FROM sqlite_master WHERE type IN ('table', 'view')
+                 AND name NOT LIKE 'sqlite_%' ORDER BY type, name",
+                Vec::new(),
+            )?,
+            ".schema" => {
+                let filter = if rest.is_empty() { "" } else { " AND name = ?1" };
+                let mut stmt = self.conn.prepare(&format!(
+                    "SELECT sql FROM sqlite_master WHERE sql IS NOT NULL{filter} ORDER BY name"
+                ))?;
+                let params = if rest.is_empty() { Vec::new() } else { vec![rest] };
+                let mut rows = stmt.query(params_from_iter(params))?;
+                while let Some(row) = rows.next()? {
+                    println!("{};", row.get::<_, String>(0)?);
+                }
+            }
+            _ if command.starts_with('.') => eyre::bail!("unknown command {command} (.help)"),
+            _ => self.sql(entry, Vec::new())?,
+        }
+        Ok(())
+    }
+
+    /// Run `sql` and print its rows as a table (or the rows changed).
+    fn sql(&self, sql: &str, params: Vec<Value>) -> Result<()> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let mut rows = stmt.query(params_from_iter(params))?;
+        let mut table = Vec::new();
+        while let Some(row) = rows.next()? {
+            let cells = (0..columns.len())
+                .map(|i| self.cell(&columns[i], row.get_ref(i)?))
+                .collect::<Result<Vec<_>>>()?;
+            table.push(cells);
+        }
+        drop(rows);
+        let mut out = std::io::stdout().lock();
+        if columns.is_empty() {
+            writeln!(out, "{} rows changed", self.conn.changes())?;
+        } else {
+            print_table(&mut out, &columns, &table)?;
+            writeln!(out, "{}", count(table.len()))?;
+        }
+        Ok(())
+    }
+
+    /// One row, one `column  value` line per column.
+    fn record(&self, sql: &str, params: Vec<Value>) -> Result<bool> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let mut rows = stmt.query(params_from_iter(params))?;
+        let Some(row) = rows.next()? else { return Ok(false) };
+        let width = columns.iter().map(|c| c.len()).max().unwrap_or(0);
+        for (i, name) in columns.iter().enumerate() {
+            let value = match (name.as_str(), row.get_ref(i)?) {
+                ("input", ValueRef::Blob(input)) => self.calldata(input)?,
+                (_, value) => self.cell(name, value)?,
+            };
+            println!("{name:<width$}  {value}");
+        }
+        Ok(true)
+    }
+
+    fn block(&self, number: i64) -> Result<()> {
+        let sql = "SELECT *, datetime(timestamp, 'unixepoch') AS time FROM blocks
+                   WHERE number = ?1 AND is_canonical = 1";
+        if !self.record(sql, vec![number.into()])? {
+            eyre::bail!("no block {number} in the database");
+        }
+        println!();
+        self.sql(
+            "SELECT tx_index, hash, from_addr, to_addr, value, status, log_count
+             FROM transactions WHERE block_number = ?1 ORDER BY tx_index",
+            vec![number.into()],
+        )
+    }
+
+    fn tx(&self, hash: &str) -> Result<()> {
+        let (clause, params) = self.any_of("hash", hash_forms(hash)?)?;
+        if !self.record(&format!("SELECT * FROM transactions WHERE {clause}"), params.clone())? {
+            eyre::bail!("no transaction {hash} in the database");
+        }
+        println!();
+        let clause = clause.replacen("hash", "tx_hash", 1);
+        self.logs_where(&[clause], params, None)
+    }
+
+    fn logs(&self, filters: &str) -> Result<()> {
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        let mut limit = DEFAULT_LIMIT;
+        for filter in filters.split_whitespace() {
+            let (key, value) = filter
+                .split_once('=')
+                .ok_or_else(|| eyre!("expected key=value, got {filter:?}"))?;
+            let (clause, values) = match key {
+                "address" => self.any_of("address", self.address_forms(value)?)?,
+                "topic0" => self.any_of("topic0", self.topic0(value)?)?,
+                "topic1" | "topic2" | "topic3" => self.any_of(key, topic_forms(value)?)?,
+                "tx" => self.any_of("tx_hash", hash_forms(value)?)?,
+                "block" => match value.split_once("..") {
+                    Some((a, b)) => (
+                        "block_number BETWEEN ? AND ?".to_string(),
+                        vec![block_number(a)?.into(), block_number(b)?.into()],
+                    ),
+                    None => ("block_number = ?".to_string(), vec![block_number(value)?.into()]),
+                },
+                "limit" => {
+                    limit = value.parse().with_context(|| format!("limit={value}"))?;
+                    continue;
+                }
+                _ => eyre::bail!(
+                    "unknown filter {key:?} (address, topic0..topic3, block, tx, limit)"
+                ),
+            };
+            clauses.push(clause);
+            params.extend(values);
+        }
+        self.logs_where(&clauses, params, Some(limit))
+    }
+
+    fn logs_where(
+        &self,
+        clauses: &[String],
+        mut params: Vec<Value>,
+        limit: Option<i64>,
+    ) -> Result<()> {
+        let filter = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+        let limit = match limit {
+            Some(n) => {
+                params.push(n.into());
+                "LIMIT ?"
+            }
+            None => "",
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT block_number, log_index, tx_hash, address, topic0, topic1, topic2, topic3, data
+             FROM logs {filter} ORDER BY block_number, log_index {limit}"
+        ))?;
+        let columns: Vec<String> = [
+            "block",
+            "log_index",
+            "tx_hash",
+            "address",
+            "event",
+            "topic1",
+            "topic2",
+            "topic3",
+            "data",
+        ]
+        .map(String::from)
+        .to_vec();
+        let mut rows = stmt.query(params_from_iter(params))?;
+        let mut table = Vec::new();
+        while let Some(row) = rows.next()? {
+            let topic0 = read_key(row.get_ref(4)?)?;
+            let event = match &topic0 {
+                Some(t) => {
+                    self.events.get(&t.to_ascii_lowercase()).cloned().unwrap_or_else(|| t.clone())
+                }
+                None => String::new(),
+            };
+            let mut cells: Vec<String> =
+                (0..4).map(|i| Ok(text(row.get_ref(i)?))).collect::<Result<_>>()?;
+            cells.push(event);
+            for i in 5..8 {
+                let topic = read_key(row.get_ref(i)?)?.unwrap_or_default();
+                cells.push(decode::topic_address(&topic).unwrap_or(topic));
+            }
+            cells.push(self.cell("data", row.get_ref(8)?)?);
+            table.push(cells);
+        }
+        let mut out = std::io::stdout().lock();
+        print_table(&mut out, &columns, &table)?;
+        writeln!(out, "{}", count(table.len()))?;
+        Ok(())
+    }
+
+    /// `column IN (?, ...)` over `values`, as the database stores them.
+    fn any_of(&self, column: &str, values: Vec<String>) -> Result<(String, Vec<Value>)> {
+        let mut params = Vec::new();
+        for value in &values {
+            let param = self.norm.key_value(column, value)?;
+            if !params.contains(&param) {
+                params.push(param);
+            }
+        }
+        let placeholders = vec!["?"; params.len()].join(", ");
+        Ok((format!("{column} IN ({placeholders})"), params))
+    }
+
+    /// The ways `address` may be stored: as the database normalizes it, or
+    /// (without --address-case) lower-case or checksummed.
+    fn address_forms(&self, address: &str) -> Result<Vec<String>> {
+        if self.norm.address.is_some() {
+            return Ok(vec![self.norm.address("address", address)?.into_owned()]);
+        }
+        let addr: Address = address.parse().map_err(|_| eyre!("{address:?} is not an address"))?;
+        Ok(dedup(vec![
+            address.to_string(),
+            format!("0x{}", hex::encode(addr)),
+            addr.to_checksum(None),
+        ]))
+    }
+
+    /// topic0 hashes for a hash, an event signature or an event name.
+    fn topic0(&self, value: &str) -> Result<Vec<String>> {
+        if value.starts_with("0x") {
+            return hash_forms(value);
+        }
+        if value.contains('(') {
+            return Ok(vec![decode::topic(value)]);
+        }
+        let topics: Vec<String> =
+            self.events.iter().filter(|(_, name)| *name == value).map(|(t, _)| t.clone()).collect();
+        if topics.is_empty() {
+            eyre::bail!("unknown event {value:?}; give its signature or topic0 hash");
+        }
+        Ok(topics)
+    }
+
+    /// Calldata, with its size.
+    fn calldata(&self, input: &[u8]) -> Result<String> {
+        let input = self.norm.read_blob(input.to_vec())?;
+        Ok(match input.len() {
+            0 => "(none)".to_string(),
+            n => format!("{} ({n} bytes)", shorten(&input)),
+        })
+    }
+
+    /// A value for display; calldata and log data decompressed and cut short.
+    fn cell(&self, column: &str, value: ValueRef) -> Result<String> {
+        Ok(match (column, value) {
+            ("input" | "data", ValueRef::Blob(b)) => shorten(&self.norm.read_blob(b.to_vec())?),
+            (_, value) => text(value),
+        })
+    }
+}
+
+/// A 32-byte hash as given and in lower case.
+fn hash_forms(hash: &str) -> Result<Vec<String>> {
+    let bytes = hex::decode(hash).ok().filter(|b| b.len() == 32);
+    if bytes.is_none() {
+        eyre::bail!("{hash:?} is not a 32-byte hash");
+    }
+    Ok(dedup(vec![hash.to_string(), hash.to_ascii_lowercase()]))
+}
+
+/// An indexed topic: a 32-byte hash, or an address left-padded into one.
+fn topic_forms(value: &str) -> Result<Vec<String>> {
+    let Ok(addr) = value.parse::<Address>() else { return hash_forms(value) };
+    Ok(vec![format!("0x{}{}", "0".repeat(24), hex::encode(addr))])
+}
+
+fn count(rows: usize) -> String {
+    if rows == 1 { "(1 row)".to_string() } else { format!("({rows} rows)") }
+}
+
+fn block_number(s: &str) -> Result<i64> {
+    s.trim().parse().with_context(|| format!("{s:?} is not a block number"))
+}
+
+fn dedup(mut values: Vec<String>) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    values.retain(|v| seen.insert(v.clone()));
+    values
+}
+
+fn shorten(bytes: &[u8]) -> String {
+    if bytes.len() <= SHOWN_BYTES {
+        format!("0x{}", hex::encode(bytes))
+    } else {
+        format!("0x{}…", hex::encode(&bytes[..SHOWN_BYTES]))
+    }
+}
@@ -0,0 +1,637 @@
+//! Where a dataset's Parquet files come from.
+//!
+//! `--data` is either a local directory or, with the `remote` feature, an
+//! object store URL (s3://, gs://, http(s)://). Remote files are read through
+//! the async Parquet reader, which fetches the footer and then only the byte
+//! ranges of each row group as it is decoded — nothing is downloaded up front.
+//!
+//! `--data -` reads a single table's Parquet file from stdin. Parquet keeps its
+//! footer at the end of the file, so stdin is buffered in memory first.
+//!
+//! With [`ReadOptions::queue_size`], a reader thread decodes row groups (and,
+//! remotely, fetches them) while the caller is still busy with earlier
+//! batches, keeping up to that many decoded batches queued; the loader uses it
+//! so Parquet IO and SQLite inserts overlap instead of taking turns. With
+//! [`ReadOptions::read_threads`] above 1, a local or stdin file's row groups
+//! are decoded by that many threads at once, each taking every Nth row group,
+//! and handed to the caller in file order (see [`pipeline`]).
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use bytes::Bytes;
+use eyre::{Context, Result};
+use parquet::arrow::arrow_reader::{
+    ArrowReaderBuilder, ArrowReaderMetadata, ParquetRecordBatchReaderBuilder,
+};
+use parquet::arrow::ProjectionMask;
+use parquet::file::metadata::ParquetMetaData;
+use parquet::file::reader::ChunkReader;
+
+use crate::compat::ColumnMap;
+use crate::memory;
+use crate::schema::Table;
+
+/// A stream of record batches plus the total row count from the footer.
+/// Record batches as a [`TableReader`] yields them.
+pub type Batches = Box<dyn Iterator<Item = Result<RecordBatch>> + Send>;
+
+pub struct TableReader {
+    pub total_rows: u64,
+    /// Compressed size of the column chunks the batches are decoded from.
+    pub bytes: u64,
+    /// Rows in each row group, in order.
+    pub row_groups: Vec<u64>,
+    /// Compressed bytes read for each row group, in order.
+    pub row_group_bytes: Vec<u64>,
+    /// Rows before the first batch, when a load resumes part way through.
+    pub first_row: u64,
+    /// The file's Arrow schema, before any columns were left out.
+    pub schema: SchemaRef,
+    pub batches: Batches,
+}
+
+impl TableReader {
+    /// Decode batches on a thread of their own, at most `depth` ahead of the
+    /// consumer. The thread stops when the reader is dropped.
+    fn read_ahead(self, depth: usize) -> Result<Self> {
+        if depth == 0 {
+            return Ok(self);
+        }
+        let (tx, rx) = sync_channel(depth);
+        let batches = self.batches;
+        std::thread::Builder::new().name("parquet-reader".into()).spawn(move || {
+            for batch in batches {
+                if tx.send(batch).is_err() {
+                    break;
+                }
+            }
+        })?;
+        Ok(Self { batches: Box::new(rx.into_iter()), ..self })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// Columns not to read (see [`DataSource::open`]).
+    pub skip: Vec<String>,
+    /// Rows per record batch.
+    pub batch_size: usize,
+    /// Batches decoded ahead of the caller; 0 decodes on the caller's thread.
+    pub queue_size: usize,
+    /// Threads decoding a file's row groups, when it has several.
+    pub read_threads: usize,
+    /// Bytes the batches of a file may take up (`--max-memory`), which
+    /// [`memory::fit`] cuts the three above down to.
+    pub max_memory: Option<u64>,
+    /// Column renames (`--map`) for [`DataSource::open_table`].
+    pub map: ColumnMap,
+    /// Rows read between commits within a file; None commits once per file.
+    pub commit_every: Option<u64>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        // The Parquet reader's own default batch size.
+        Self {
+            skip: Vec::new(),
+            batch_size: 1024,
+            queue_size: 0,
+            read_threads: 1,
+            max_memory: None,
+            map: ColumnMap::default(),
+            commit_every: None,
+        }
+    }
+}
+
+/// A file's Arrow schema and Parquet footer, read without decoding any rows.
+pub struct FileMeta {
+    pub schema: SchemaRef,
+    pub metadata: Arc<ParquetMetaData>,
+}
+
+pub enum DataSource {
+    Local(PathBuf),
+    /// One table file (by name, e.g. "logs.parquet") read from stdin.
+    Stdin { file: String, bytes: Bytes },
+    #[cfg(feature = "remote")]
+    Remote(remote::RemoteDir),
+}
+
+impl DataSource {
+    pub fn new(data: &Path) -> Result<Self> {
+        let s = data.to_string_lossy();
+        if s.contains("://") {
+            #[cfg(feature = "remote")]
+            return Ok(Self::Remote(remote::RemoteDir::new(&s)?));
+            #[cfg(not(feature = "remote"))]
+            eyre::bail!("{s}: remote datasets need a build with `--features remote`");
+        }
+        Ok(Self::Local(data.to_path_buf()))
+    }
+
+    pub fn stdin(file: &str) -> Result<Self> {
+        let mut buf = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut buf).context("Cannot read stdin")?;
+        Ok(Self::Stdin { file: file.to_string(), bytes: Bytes::from(buf) })
+    }
+
+    /// Human-readable location of `file`, for messages.
+    pub fn display(&self, file: &str) -> String {
+        match self {
+            Self::Local(dir) => dir.join(file).display().to_string(),
+            Self::Stdin { .. } => format!("<stdin> ({file})"),
+            #[cfg(feature = "remote")]
+            Self::Remote(r) => r.url(file),
+        }
+    }
+
+    /// Files holding `table`, relative to the dataset root: `<table>.parquet`
+    /// and/or part files `<table>/*.parquet` (sorted by name). Hidden and
+    /// `_`-prefixed files are skipped, as are files still being written
+    /// (those simply fail to open and are picked up on a later --watch poll).
+    pub fn list(&self, table: &str) -> Result<Vec<String>> {
+        match self {
+            Self::Local(dir) => list_local(dir, table),
+            Self::Stdin { file, .. } => Ok(vec![file.clone()]),
+            #[cfg(feature = "remote")]
+            Self::Remote(r) => r.list(table),
+        }
+    }
+
+    /// Stable identity of `file` for bookkeeping (canonical path or URL).
+    pub fn key(&self, file: &str) -> String {
+        match self {
+            Self::Local(dir) => std::fs::canonicalize(dir.join(file))
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| self.display(file)),
+            _ => self.display(file),
+        }
+    }
+
+    /// Open `file` for reading, leaving the columns in `skip` out entirely:
+    /// their pages are never fetched or decoded.
+    pub fn open(&self, file: &str, skip: &[String]) -> Result<TableReader> {
+        self.open_raw(file, skip, ReadOptions::default().batch_size)
+    }
+
+    /// Open one of `table`'s files for loading: columns renamed by
+    /// `opts.map` and checked against the loader's (see compat.rs), and
+    /// batches decoded ahead on reader threads.
+    pub fn open_table(&self, table: Table, file: &str, opts: &ReadOptions) -> Result<TableReader> {
+        let skip = opts.map.skip(table, &opts.skip);
+        let fitted;
+        let opts = match opts.max_memory {
+            Some(max) => {
+                fitted = memory::fit(max, &self.meta(file)?.metadata, &skip, opts);
+                &fitted
+            }
+            None => opts,
+        };
+        let mut reader = self.open_raw(file, &skip, opts.batch_size)?;
+        reader.schema = opts.map.rename_schema(table, &reader.schema)?;
+        opts.map.check(table, &reader.schema, &opts.skip)?;
+        let map = opts.map.clone();
+        let convert = move |b: Result<RecordBatch>| map.convert(table, b?);
+        let groups = reader.row_groups.len();
+        if opts.read_threads > 1 && opts.queue_size > 0 && groups > 1 {
+            if let Some(open) = self.row_groups(file, &skip, opts.batch_size)? {
+                let threads = opts.read_threads.min(groups);
+                reader.batches = pipeline(open, groups, threads, opts.queue_size, convert)?;
+                return Ok(reader);
+            }
+        }
+        reader.batches = Box::new(reader.batches.map(convert));
+        reader.read_ahead(opts.queue_size)
+    }
+
+    /// A way to open each of `file`'s row groups on its own, for
+    /// [`pipeline`]; None where a file can only be read front to back
+    /// (remotely, through the async reader).
+    fn row_groups(
+        &self,
+        file: &str,
+        skip: &[String],
+        batch_size: usize,
+    ) -> Result<Option<OpenGroup>> {
+        let skip = skip.to_vec();
+        match self {
+            Self::Local(dir) => {
+                let path = dir.join(file);
+                let open = |path: &Path| {
+                    std::fs::File::open(path)
+                        .with_context(|| format!("Cannot open {}", path.display()))
+                };
+                let metadata = ArrowReaderMetadata::load(&open(&path)?, Default::default())?;
+                Ok(Some(Arc::new(move |group| {
+                    // Each thread reads through its own handle: clones of
+                    // one would share its file offset.
+                    let builder = ParquetRecordBatchReaderBuilder::new_with_metadata(
+                        open(&path)?,
+                        metadata.clone(),
+                    );
+                    read_group(builder, &skip, batch_size, group)
+                })))
+            }
+            Self::Stdin { bytes, .. } => {
+                let metadata = ArrowReaderMetadata::load(bytes, Default::default())?;
+                let bytes = bytes.clone();
+                Ok(Some(Arc::new(move |group| {
+                    let builder = ParquetRecordBatchReaderBuilder::new_with_metadata(
+                        bytes.clone(),
+                        metadata.clone(),
+                    );
+                    read_group(builder, &skip, batch_size, group)
+                })))
+            }
+            #[cfg(feature = "remote")]
+            Self::Remote(_) => Ok(None),
+        }
+    }
+
+    fn open_raw(&self, file: &str, skip: &[String], batch_size: usize) -> Result<TableReader> {
+        match self {
+            Self::Local(dir) => open_local(&dir.join(file), skip, batch_size),
+            Self::Stdin { bytes, .. } => read_builder(
+                ParquetRecordBatchReaderBuilder::try_new(bytes.clone())
+                    .context("stdin is not a Parquet file")?,
+                skip,
+                batch_size,
+            ),
+            #[cfg(feature = "remote")]
+            Self::Remote(r) => r.open(file, skip, batch_size),
+        }
+    }
+
+    /// Compressed bytes `open_table` would read of `file`, from its footer.
+    pub fn read_size(&self, table: Table, file: &str, opts: &ReadOptions) -> Result<u64> {
+        let skip = opts.map.skip(table, &opts.skip);
+        Ok(read_bytes(&self.meta(file)?.metadata, &skip).iter().sum())
+    }
+
+    /// Contents of a small side file such as manifest.json, if present.
+    pub fn read(&self, file: &str) -> Result<Option<Bytes>> {
+        match self {
+            Self::Local(dir) => match std::fs::read(dir.join(file)) {
+                Ok(bytes) => Ok(Some(Bytes::from(bytes))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("Cannot read {}", self.display(file))),
+            },
+            Self::Stdin { .. } => Ok(None),
+            #[cfg(feature = "remote")]
+            Self::Remote(r) => r.read(file),
+        }
+    }
+
+    pub fn meta(&self, file: &str) -> Result<FileMeta> {
+        match self {
+            Self::Local(dir) => {
+                let path = dir.join(file);
+                let f = std::fs::File::open(&path)
+                    .with_context(|| format!("Cannot open {}", path.display()))?;
+                Ok(builder_meta(&ParquetRecordBatchReaderBuilder::try_new(f)?))
+            }
+            Self::Stdin { bytes, .. } => Ok(builder_meta(
+                &ParquetRecordBatchReaderBuilder::try_new(bytes.clone())
+                    .context("stdin is not a Parquet file")?,
+            )),
+            #[cfg(feature = "remote")]
+            Self::Remote(r) => r.meta(file),
+        }
+    }
+}
+
+/// Restrict a reader to the top-level columns not named in `skip`.
+fn project<T>(builder: ArrowReaderBuilder<T>, skip: &[String]) -> ArrowReaderBuilder<T> {
+    if skip.is_empty() {
+        return builder;
+    }
+    let keep: Vec<usize> = builder
+        .schema()
+        .fields()
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| !skip.contains(f.name()))
+        .map(|(i, _)| i)
+        .collect();
+    let mask = ProjectionMask::roots(builder.parquet_schema(), keep);
+    builder.with_projection(mask)
+}
+
+/// Compressed size of each row group's column chunks left after `skip`.
+fn read_bytes(metadata: &ParquetMetaData, skip: &[String]) -> Vec<u64> {
+    metadata
+        .row_groups()
+        .iter()
+        .map(|rg| {
+            rg.columns()
+                .iter()
+                .filter(|c| {
+                    !c.column_path().parts().first().is_some_and(|root| skip.contains(root))
+                })
+                .map(|c| c.compressed_size() as u64)
+                .sum()
+        })
+        .collect()
+}
+
+/// Opens one row group of a file, by index.
+type OpenGroup = Arc<dyn Fn(usize) -> Result<Batches> + Send + Sync>;
+
+fn read_group<T: ChunkReader + 'static>(
+    builder: ParquetRecordBatchReaderBuilder<T>,
+    skip: &[String],
+    batch_size: usize,
+    group: usize,
+) -> Result<Batches> {
+    let reader =
+        project(builder, skip).with_row_groups(vec![group]).with_batch_size(batch_size).build()?;
+    Ok(Box::new(reader.map(|b| b.map_err(Into::into))))
+}
+
+/// Decode `groups` row groups on `threads` threads, thread i taking row
+/// groups i, i + threads, ..., each converted by `convert` there too, and
+/// yield the batches in file order. Each thread queues its share of `depth`
+/// batches (at least one) and stops at its first error, which the caller
+/// gets in its place, or when the caller drops the batches.
+fn pipeline(
+    open: OpenGroup,
+    groups: usize,
+    threads: usize,
+    depth: usize,
+    convert: impl Fn(Result<RecordBatch>) -> Result<RecordBatch> + Send + Sync + 'static,
+) -> Result<Batches> {
+    let convert = Arc::new(convert);
+    let mut queues = Vec::with_capacity(threads);
+    for first in 0..threads {
+        // None marks the end of a row group.
+        let (tx, rx) = sync_channel::<Option<Result<RecordBatch>>>((depth / threads).max(1));
+        let (open, convert) = (open.clone(), convert.clone());
+        std::thread::Builder::new().name(format!("parquet-reader-{first}")).spawn(move || {
+            for group in (first..groups).step_by(threads) {
+                let batches = match open(group) {
+                    Ok(batches) => batches,
+                    Err(e) => {
+                        let _ = tx.send(Some(Err(e)));
+                        return;
+                    }
+                };
+                for batch in batches {
+                    let batch = convert(batch);
+                    let failed = batch.is_err();
+                    if tx.send(Some(batch)).is_err() || failed {
+                        return;
+                    }
+                }
+                if tx.send(None).is_err() {
+                    return;
+                }
+            }
+        })?;
+        queues.push(rx);
+    }
+    Ok(Box::new(InOrder { queues, group: 0, groups, failed: false }))
+}
+
+/// The batches of [`pipeline`]'s threads, row group by row group.
+struct InOrder {
+    queues: Vec<Receiver<Option<Result<RecordBatch>>>>,
+    group: usize,
+    groups: usize,
+    failed: bool,
+}
+
+impl Iterator for InOrder {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.group < self.groups && !self.failed {
+            match self.queues[self.group % self.queues.len()].recv() {
+                Ok(Some(batch)) => {
+                    self.failed = batch.is_err();
+                    return Some(batch);
+                }
+                Ok(None) => self.group += 1,
+                Err(_) => {
+                    self.failed = true;
+                    return Some(Err(eyre::eyre!(
+                        "reader thread for row group {} stopped",
+                        self.group
+                    )));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn row_groups(metadata: &ParquetMetaData) -> Vec<u64> {
+    metadata.row_groups().iter().map(|rg| rg.num_rows() as u64).collect()
+}
+
+fn builder_meta<T>(builder: &ArrowReaderBuilder<T>) -> FileMeta {
+    FileMeta { schema: builder.schema().clone(), metadata: builder.metadata().clone() }
+}
+
+fn is_part_file(name: &str) -> bool {
+    name.ends_with(".parquet") && !name.starts_with('.') && !name.starts_with('_')
+}
+
+fn list_local(dir: &Path, table: &str) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let single = format!("{table}.parquet");
+    if dir.join(&single).is_file() {
+        files.push(single);
+    }
+
+    let part_dir = dir.join(table);
+    if part_dir.is_dir() {
+        let mut parts = Vec::new();
+        for entry in std::fs::read_dir(&part_dir)
+            .with_context(|| format!("Cannot read {}", part_dir.display()))?
+        {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if is_part_file(&name) {
+                parts.push(format!("{table}/{name}"));
+            }
+        }
+        parts.sort();
+        files.extend(parts);
+    }
+    Ok(files)
+}
+
+fn open_local(path: &Path, skip: &[String], batch_size: usize) -> Result<TableReader> {
+    let f = std::fs::File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    read_builder(ParquetRecordBatchReaderBuilder::try_new(f)?, skip, batch_size)
+}
+
+fn read_builder<T: ChunkReader + 'static>(
+    builder: ParquetRecordBatchReaderBuilder<T>,
+    skip: &[String],
+    batch_size: usize,
+) -> Result<TableReader> {
+    let total_rows = builder.metadata().file_metadata().num_rows() as u64;
+    let row_group_bytes = read_bytes(builder.metadata(), skip);
+    let row_groups = row_groups(builder.metadata());
+    let schema = builder.schema().clone();
+    let reader = project(builder, skip).with_batch_size(batch_size).build()?;
+    Ok(TableReader {
+        total_rows,
+        bytes: row_group_bytes.iter().sum(),
+        row_groups,
+        row_group_bytes,
+        first_row: 0,
+        schema,
+        batches: Box::new(reader.map(|b| b.map_err(Into::into))),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Remote (object_store)
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "remote")]
+mod remote {
+    use std::sync::Arc;
+
+    use eyre::{Context, Result};
+    use futures::{StreamExt, TryStreamExt};
+    use object_store::path::Path as ObjectPath;
+    use object_store::ObjectStore;
+    use parquet::arrow::async_reader::{ParquetObjectReader, ParquetRecordBatchStreamBuilder};
+    use tokio::runtime::Runtime;
+    use url::Url;
+
+    use super::{FileMeta, TableReader};
+
+    pub struct RemoteDir {
+        url: Url,
+        store: Arc<dyn ObjectStore>,
+        prefix: ObjectPath,
+        rt: Arc<Runtime>,
+    }
+
+    impl RemoteDir {
+        /// Credentials and region come from the usual environment variables
+        /// (AWS_ACCESS_KEY_ID, AWS_REGION, GOOGLE_SERVICE_ACCOUNT, ...).
+        pub fn new(location: &str) -> Result<Self> {
+            let url = Url::parse(location).with_context(|| format!("Invalid URL: {location}"))?;
+            let mut opts: Vec<(String, String)> =
+                std::env::vars().map(|(k, v)| (k.to_ascii_lowercase(), v)).collect();
+            if url.scheme() == "http" {
+                opts.push(("allow_http".into(), "true".into()));
+            }
+            let (store, prefix) = object_store::parse_url_opts(&url, opts)
+                .with_context(|| format!("Unsupported object store URL: {location}"))?;
+            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            Ok(Self { url, store: Arc::from(store), prefix, rt: Arc::new(rt) })
+        }
+
+        fn path(&self, file: &str) -> ObjectPath {
+            self.prefix.child(file)
+        }
+
+        pub fn url(&self, file: &str) -> String {
+            format!("{}/{file}", self.url.as_str().trim_end_matches('/'))
+        }
+
+        fn is_http(&self) -> bool {
+            matches!(self.url.scheme(), "http" | "https")
+        }
+
+        fn exists(&self, file: &str) -> Result<bool> {
+            match self.rt.block_on(self.store.head(&self.path(file))) {
+                Ok(_) => Ok(true),
+                Err(object_store::Error::NotFound { .. }) => Ok(false),
+                Err(e) => Err(e).with_context(|| format!("Cannot stat {}", self.url(file))),
+            }
+        }
+
+        pub fn list(&self, table: &str) -> Result<Vec<String>> {
+            let mut files = Vec::new();
+            let single = format!("{table}.parquet");
+            if self.exists(&single)? {
+                files.push(single);
+            }
+
+            // Listing over HTTP needs WebDAV (PROPFIND), which a plain web
+            // server rejects after several retries; only try it when there
+            // is no single file to fall back on.
+            if self.is_http() && !files.is_empty() {
+                return Ok(files);
+            }
+
+            let part_dir = self.path(table);
+            let listed: Vec<_> = self
+                .rt
+                .block_on(self.store.list(Some(&part_dir)).try_collect())
+                .with_context(|| format!("Cannot list {}", self.url(table)))?;
+            let mut parts: Vec<String> = listed
+                .iter()
+                .filter_map(|meta| meta.location.filename())
+                .filter(|name| super::is_part_file(name))
+                .map(|name| format!("{table}/{name}"))
+                .collect();
+            parts.sort();
+            files.extend(parts);
+            Ok(files)
+        }
+
+        fn builder(
+            &self,
+            file: &str,
+        ) -> Result<ParquetRecordBatchStreamBuilder<ParquetObjectReader>> {
+            let meta = self
+                .rt
+                .block_on(self.store.head(&self.path(file)))
+                .with_context(|| format!("Cannot open {}", self.url(file)))?;
+            let reader = ParquetObjectReader::new(self.store.clone(), meta);
+            Ok(self.rt.block_on(ParquetRecordBatchStreamBuilder::new(reader))?)
+        }
+
+        pub fn read(&self, file: &str) -> Result<Option<bytes::Bytes>> {
+            let get = self.rt.block_on(async {
+                match self.store.get(&self.path(file)).await {
+                    Ok(result) => result.bytes().await.map(Some),
+                    Err(object_store::Error::NotFound { .. }) => Ok(None),
+                    Err(e) => Err(e),
+                }
+            });
+            get.with_context(|| format!("Cannot read {}", self.url(file)))
+        }
+
+        pub fn meta(&self, file: &str) -> Result<FileMeta> {
+            Ok(super::builder_meta(&self.builder(file)?))
+        }
+
+        pub fn open(&self, file: &str, skip: &[String], batch_size: usize) -> Result<TableReader> {
+            let rt = self.rt.clone();
+            let builder = self.builder(file)?;
+            let row_group_bytes = super::read_bytes(builder.metadata(), skip);
+            let row_groups = super::row_groups(builder.metadata());
+            let schema = builder.schema().clone();
+            let builder = super::project(builder, skip).with_batch_size(batch_size);
+            let total_rows = builder.metadata().file_metadata().num_rows() as u64;
+            let mut stream = builder.build()?;
+
+            let batches = std::iter::from_fn(move || {
+                rt.block_on(stream.next()).map(|b| b.map_err(Into::into))
+            });
+            Ok(TableReader {
+                total_rows,
+                bytes: row_group_bytes.iter().sum(),
+                row_groups,
+                row_group_bytes,
+                first_row: 0,
+                schema,
+                batches: Box::new(batches),
+            })
+        }
+    }
+}
@@ -0,0 +1,493 @@
+//! `split` — carve a dataset into fixed-size block-range chunks.
+//!
+//! Works on either a Parquet dataset directory (single files or
+//! <table>/*.parquet parts, as the loader reads it) or a loaded SQLite
+//! database. Chunks are aligned to multiples of `--blocks` so that
+//! splitting two overlapping datasets with the same size yields identically
+//! named chunks:
+//!
+//!   <out>/16817800-16817899/{blocks,transactions,logs}.parquet + manifest.json
+//!                            (and ommers, withdrawals, blobs where present)
+//!   <out>/16817800-16817899/ethereum.db + manifest.json      (DB input)
+
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::path::{Path, PathBuf};
+
+use arrow::array::{new_null_array, UInt32Array};
+use arrow::compute::take_record_batch;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use clap::Args;
+use eyre::{Context, Result};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::col_i64;
+use crate::manifest::{BlockRange, Manifest, TableEntry};
+use crate::migrate;
+use crate::normalize::Normalize;
+use crate::pragmas;
+use crate::progress::progress_bar;
+use crate::sample::{block_column, chain_id, files, tables};
+use crate::schema::{self, Table, TOPICS_BACKFILL, TOPICS_SCHEMA};
+use crate::source::DataSource;
+
+#[derive(Args, Debug)]
+pub struct SplitArgs {
+    /// Parquet dataset directory, or a SQLite database produced by offline-replay
+    #[arg(short, long, default_value = ".")]
+    data: PathBuf,
+
+    /// Output directory; one subdirectory is written per chunk
+    #[arg(short, long, default_value = "chunks")]
+    out: PathBuf,
+
+    /// Number of blocks per chunk
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    blocks: u64,
+}
+
+const CHUNK_DB: &str = "ethereum.db";
+
+pub fn run(args: SplitArgs) -> Result<()> {
+    std::fs::create_dir_all(&args.out)
+        .with_context(|| format!("Cannot create {}", args.out.display()))?;
+
+    let chunks = if args.data.is_file() {
+        split_db(&args.data, &args.out, args.blocks as i64)?
+    } else {
+        split_parquet(&args.data, &args.out, args.blocks as i64)?
+    };
+
+    for m in &chunks {
+        let rows: Vec<String> = m
+            .tables
+            .iter()
+            .map(|(name, t)| format!("{} {name}", t.rows))
+            .collect();
+        info!(start = m.block_range.start, end = m.block_range.end, rows = rows.join(", "), "chunk");
+    }
+    info!(chunks = chunks.len(), out = %args.out.display(), "done");
+    Ok(())
+}
+
+pub(crate) fn chunk_range(block: i64, size: i64) -> BlockRange {
+    let start = block - block.rem_euclid(size);
+    BlockRange { start, end: start + size - 1 }
+}
+
+fn chunk_dir(out: &Path, range: BlockRange) -> PathBuf {
+    out.join(format!("{}-{}", range.start, range.end))
+}
+
+// ---------------------------------------------------------------------------
+// Parquet input
+// ---------------------------------------------------------------------------
+
+fn split_parquet(data: &Path, out: &Path, size: i64) -> Result<Vec<Manifest>> {
+    let source = DataSource::new(data)?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+
+    let chain_id = chain_id(&source)?;
+    let mut manifests: BTreeMap<i64, Manifest> = BTreeMap::new();
+
+    for table in tables(&source)? {
+        let files = files(&source, table)?;
+        let schema = merged_schema(&source, &files)?;
+        let mut writers: BTreeMap<i64, (ArrowWriter<std::fs::File>, u64)> = BTreeMap::new();
+
+        for file in &files {
+            let reader = source.open(file, &[])?;
+            let mut pb = progress_bar(reader.total_rows, table.name());
+            for batch in reader.batches {
+                let batch = batch?;
+                let blocks = col_i64(&batch, block_column(table))?;
+
+                let mut rows_by_chunk: BTreeMap<i64, Vec<u32>> = BTreeMap::new();
+                for i in 0..batch.num_rows() {
+                    let range = chunk_range(blocks.value(i), size);
+                    rows_by_chunk.entry(range.start).or_default().push(i as u32);
+                }
+
+                for (start, rows) in rows_by_chunk {
+                    let (writer, count) = match writers.entry(start) {
+                        Entry::Occupied(e) => e.into_mut(),
+                        Entry::Vacant(e) => {
+                            let dir = chunk_dir(out, chunk_range(start, size));
+                            std::fs::create_dir_all(&dir)
+                                .with_context(|| format!("Cannot create {}", dir.display()))?;
+                            let f = std::fs::File::create(dir.join(table.file()))?;
+                            let w = ArrowWriter::try_new(f, schema.clone(), Some(props.clone()))?;
+                            e.insert((w, 0))
+                        }
+                    };
+                    let part = take_record_batch(&batch, &UInt32Array::from(rows))?;
+                    let part = conform(&part, &schema)?;
+                    writer.write(&part)?;
+                    *count += part.num_rows() as u64;
+                }
+                pb.inc(batch.num_rows() as u64);
+            }
+            pb.finish_with_message(format!("{} ✓", table.name()));
+        }
+
+        for (start, (writer, rows)) in writers {
+            writer.close()?;
+            manifests
+                .entry(start)
+                .or_insert_with(|| Manifest { chain_id, ..Manifest::new(chunk_range(start, size)) })
+                .tables
+                .insert(table.name().to_string(), TableEntry { file: table.file().to_string(), rows });
+        }
+    }
+
+    for m in manifests.values() {
+        m.write(&chunk_dir(out, m.block_range))?;
+    }
+    Ok(manifests.into_values().collect())
+}
+
+/// The schema of all of a table's files, so that parts written before a
+/// column was added (logs_bloom, nonce) split along with those after it:
+/// a column some files lack is nullable, and NULL in their rows.
+fn merged_schema(source: &DataSource, files: &[String]) -> Result<SchemaRef> {
+    let schemas = files
+        .iter()
+        .map(|f| Ok(source.meta(f)?.schema))
+        .collect::<Result<Vec<_>>>()?;
+    let mut merged = Schema::empty();
+    for (file, schema) in files.iter().zip(&schemas) {
+        merged = Schema::try_merge([merged, schema.as_ref().clone()]).with_context(|| {
+            format!("{file} does not match the schema of the files before it")
+        })?;
+    }
+    let fields: Vec<_> = merged
+        .fields()
+        .iter()
+        .map(|f| {
+            let everywhere = schemas.iter().all(|s| s.field_with_name(f.name()).is_ok());
+            f.as_ref().clone().with_nullable(f.is_nullable() || !everywhere)
+        })
+        .collect();
+    Ok(SchemaRef::new(Schema::new_with_metadata(fields, merged.metadata)))
+}
+
+/// `batch` with the columns of `schema`, in its order.
+fn conform(batch: &RecordBatch, schema: &SchemaRef) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|f| match batch.column_by_name(f.name()) {
+            Some(c) => c.clone(),
+            None => new_null_array(f.data_type(), batch.num_rows()),
+        })
+        .collect();
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+// ---------------------------------------------------------------------------
+// SQLite input
+// ---------------------------------------------------------------------------
+
+fn split_db(db: &Path, out: &Path, size: i64) -> Result<Vec<Manifest>> {
+    let src = Connection::open_with_flags(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Cannot open {}", db.display()))?;
+    if let Some(v) = migrate::version(&src)?.filter(|&v| v != migrate::SCHEMA_VERSION) {
+        eyre::bail!(
+            "{} has schema version {v}, this build writes {}; run `offline-replay migrate` on it first",
+            db.display(),
+            migrate::SCHEMA_VERSION
+        );
+    }
+    let norm = match migrate::version(&src)? {
+        Some(_) => Normalize::from_meta(&src)?,
+        None => Normalize::default(),
+    };
+    // Chunks of a --strict database are strict too, with the same value types.
+    let strict = migrate::is_strict(&src)?.then_some(norm);
+    // The optional tables the database has, copied chunk by chunk too.
+    let mut optional = Vec::new();
+    for table in Table::OPTIONAL {
+        let present: bool = src.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table.name()],
+            |r| r.get(0),
+        )?;
+        if present {
+            optional.push(table);
+        }
+    }
+    let (min, max): (Option<i64>, Option<i64>) =
+        src.query_row("SELECT MIN(number), MAX(number) FROM blocks", [], |r| {
+            Ok((r.get(0)?, r.get(1)?))
+        })?;
+    let (Some(min), Some(max)) = (min, max) else {
+        eyre::bail!("{} contains no blocks", db.display());
+    };
+
+    let first = chunk_range(min, size).start;
+    let total = ((max - first) / size + 1) as u64;
+    let mut pb = progress_bar(total, "chunks");
+    let mut manifests = Vec::new();
+
+    let mut start = first;
+    while start <= max {
+        let range = chunk_range(start, size);
+        start += size;
+        pb.inc(1);
+
+        let present: i64 = src.query_row(
+            "SELECT COUNT(*) FROM blocks WHERE number BETWEEN ?1 AND ?2",
+            [range.start, range.end],
+            |r| r.get(0),
+        )?;
+        if present == 0 {
+            continue;
+        }
+
+        let dir = chunk_dir(out, range);
+        let path = dir.join(CHUNK_DB);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Cannot create {}", dir.display()))?;
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+
+        let conn = Connection::open(&path)
+            .with_context(|| format!("Cannot open {}", path.display()))?;
+        pragmas::apply(&conn, None, &[])?;
+        migrate::init(&conn, strict)?;
+        // An L2's columns too, so transactions copy over column for column.
+        norm.chain.add_columns(&conn, &norm)?;
+        for &table in &optional {
+            conn.execute_batch(&schema::optional_schema(table, &norm, strict.is_some()))?;
+        }
+        conn.execute("ATTACH DATABASE ?1 AS src", [db.to_string_lossy()])?;
+
+        conn.execute_batch("BEGIN;")?;
+        let blocks = conn.execute(
+            "INSERT INTO blocks SELECT * FROM src.blocks WHERE number BETWEEN ?1 AND ?2",
+            [range.start, range.end],
+        )?;
+        let txs = conn.execute(
+            "INSERT INTO transactions SELECT * FROM src.transactions
+             WHERE block_number BETWEEN ?1 AND ?2",
+            [range.start, range.end],
+        )?;
+        let logs = conn.execute(
+            "INSERT INTO logs
+             (block_number, tx_hash, log_index, address, topic0, topic1, topic2, topic3, data)
+             SELECT block_number, tx_hash, log_index, address, topic0, topic1, topic2, topic3, data
+             FROM src.logs WHERE block_number BETWEEN ?1 AND ?2 ORDER BY id",
+            [range.start, range.end],
+        )?;
+        let mut copied = Vec::new();
+        for &table in &optional {
+            let rows = conn.execute(
+                &format!(
+                    "INSERT INTO {0} SELECT * FROM src.{0} WHERE block_number BETWEEN ?1 AND ?2",
+                    table.name()
+                ),
+                [range.start, range.end],
+            )?;
+            copied.push((table.name(), rows));
+        }
+        // The source's indexes, default or not, built once the rows are in;
+        // those of the schema are already there.
+        let tables: Vec<String> = ["blocks", "transactions", "logs"]
+            .into_iter()
+            .chain(optional.iter().map(|t| t.name()))
+            .map(|t| format!("'{t}'"))
+            .collect();
+        let indexes: Vec<String> = conn
+            .prepare(&format!(
+                "SELECT sql FROM src.sqlite_master WHERE type = 'index' AND sql IS NOT NULL
+                 AND tbl_name IN ({})
+                 AND name NOT IN (SELECT name FROM main.sqlite_master WHERE type = 'index')
+                 ORDER BY rowid",
+                tables.join(", ")
+            ))?
+            .query_map([], |r| r.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        for sql in &indexes {
+            conn.execute_batch(sql)?;
+        }
+        // Chunks keep the source's value encodings and layout, so appends to
+        // them match.
+        let has_meta: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM src.sqlite_master WHERE type = 'table' AND name = '_meta'",
+            [],
+            |r| r.get(0),
+        )?;
+        if has_meta {
+            conn.execute(
+                "INSERT OR REPLACE INTO _meta SELECT key, value FROM src._meta
+                 WHERE key IN ('numeric_encoding', 'address_case', 'topics_table', 'compression',
+                               'key_encoding', 'log_key', 'chain')",
+                [],
+            )?;
+            let topics: bool = conn.query_row(
+                "SELECT COUNT(*) > 0 FROM _meta WHERE key = 'topics_table'",
+                [],
+                |r| r.get(0),
+            )?;
+            if topics {
+                // Chunk logs get fresh ids, so rebuild rather than copy.
+                conn.execute_batch(TOPICS_SCHEMA)?;
+                conn.execute_batch(TOPICS_BACKFILL)?;
+            }
+        }
+        conn.execute_batch("COMMIT; DETACH DATABASE src;")?;
+        drop(conn);
+
+        // The database keeps the --chain it was loaded with, not a manifest.
+        let mut m = Manifest { chain_id: norm.chain.id(), ..Manifest::new(range) };
+        for (table, rows) in [("blocks", blocks), ("transactions", txs), ("logs", logs)]
+            .into_iter()
+            .chain(copied)
+        {
+            m.tables.insert(
+                table.to_string(),
+                TableEntry { file: CHUNK_DB.to_string(), rows: rows as u64 },
+            );
+        }
+        m.write(&dir)?;
+        manifests.push(m);
+    }
+
+    pb.finish_with_message("chunks ✓");
+    Ok(manifests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::compute::cast;
+    use arrow::datatypes::DataType;
+    use crate::open_existing;
+    use crate::testing::{count, generate, load_into, rewrite, Scratch};
+
+    /// A dataset whose transactions are in two parts, the first written
+    /// before logs_bloom and nonce were.
+    fn drifted(dir: &Scratch) -> PathBuf {
+        let data = dir.join("data");
+        generate(&data, 20);
+        std::fs::create_dir(data.join("transactions")).unwrap();
+        for part in ["0", "1"] {
+            let path = data.join(format!("transactions/{part}.parquet"));
+            std::fs::copy(data.join("transactions.parquet"), &path).unwrap();
+            rewrite(&path, |batch| {
+                let half = batch.num_rows() / 2;
+                if part == "1" {
+                    return batch.slice(half, batch.num_rows() - half);
+                }
+                let schema = batch.schema();
+                let old: Vec<usize> = (0..schema.fields().len())
+                    .filter(|&i| !matches!(schema.field(i).name().as_str(), "logs_bloom" | "nonce"))
+                    .collect();
+                batch.slice(0, half).project(&old).unwrap()
+            });
+        }
+        std::fs::remove_file(data.join("transactions.parquet")).unwrap();
+        data
+    }
+
+    #[test]
+    fn parquet_chunks_load_as_the_whole_dataset() {
+        let dir = Scratch::new("split-parquet");
+        let data = drifted(&dir);
+        let chunks = dir.join("chunks");
+        let manifests = split_parquet(&data, &chunks, 7).unwrap();
+        assert_eq!(manifests.len(), 4);
+
+        load_into(&data, &dir.join("whole.db"), "").unwrap();
+        for (i, m) in manifests.iter().enumerate() {
+            let mode = if i == 0 { "" } else { "--mode append" };
+            load_into(&chunk_dir(&chunks, m.block_range), &dir.join("split.db"), mode).unwrap();
+        }
+        // Row for row, rowids too; only _meta's source and manifest differ.
+        let split = open_existing(&dir.join("split.db")).unwrap();
+        let whole = dir.join("whole.db");
+        split.execute("ATTACH DATABASE ?1 AS whole", [whole.to_string_lossy()]).unwrap();
+        for table in ["blocks", "transactions", "logs"] {
+            let differ = format!(
+                "SELECT COUNT(*) FROM (SELECT rowid, * FROM main.{table}
+                                       EXCEPT SELECT rowid, * FROM whole.{table})"
+            );
+            assert_eq!(count(&split, &differ), 0, "{table}");
+            let rows = format!("SELECT COUNT(*) FROM main.{table}");
+            assert_eq!(count(&split, &rows), count(&split, &rows.replace("main.", "whole.")));
+        }
+        let older = "SELECT COUNT(*) FROM transactions WHERE nonce IS NULL";
+        assert!(count(&split, older) > 0);
+    }
+
+    #[test]
+    fn parts_of_conflicting_types_are_refused_up_front() {
+        let dir = Scratch::new("split-types");
+        let data = drifted(&dir);
+        rewrite(&data.join("transactions/1.parquet"), |batch| {
+            let schema = batch.schema();
+            let gas = schema.index_of("gas_used").unwrap();
+            let mut fields: Vec<_> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+            fields[gas] = fields[gas].clone().with_data_type(DataType::Utf8);
+            let mut columns = batch.columns().to_vec();
+            columns[gas] = cast(&columns[gas], &DataType::Utf8).unwrap();
+            RecordBatch::try_new(Schema::new(fields).into(), columns).unwrap()
+        });
+        let chunks = dir.join("chunks");
+        let e = split_parquet(&data, &chunks, 7).unwrap_err();
+        assert!(format!("{e:#}").contains("transactions/1.parquet"), "{e:#}");
+    }
+
+    #[test]
+    fn db_chunks_hold_the_rows_of_their_ranges() {
+        let dir = Scratch::new("split-db");
+        let data = dir.join("data");
+        generate(&data, 20);
+        let db = dir.join("whole.db");
+        load_into(&data, &db, "--topics-table").unwrap();
+        let chunks = dir.join("chunks");
+        let manifests = split_db(&db, &chunks, 7).unwrap();
+        assert_eq!(manifests.len(), 4);
+
+        let mut rows = BTreeMap::<&str, i64>::new();
+        for m in &manifests {
+            let (start, end) = (m.block_range.start, m.block_range.end);
+            let conn = open_existing(&chunk_dir(&chunks, m.block_range).join(CHUNK_DB)).unwrap();
+            conn.execute("ATTACH DATABASE ?1 AS whole", [db.to_string_lossy()]).unwrap();
+            for (table, column) in
+                [("blocks", "number"), ("transactions", "block_number"), ("logs", "block_number")]
+            {
+                let n = count(&conn, &format!("SELECT COUNT(*) FROM main.{table}"));
+                assert_eq!(m.tables[table].rows as i64, n, "{table} {start}-{end}");
+                let within = format!(
+                    "SELECT COUNT(*) FROM whole.{table} WHERE {column} BETWEEN {start} AND {end}"
+                );
+                assert_eq!(count(&conn, &within), n, "{table} {start}-{end}");
+                *rows.entry(table).or_default() += n;
+            }
+            // Chunk logs get ids of their own, and topics to match them.
+            let logs = "SELECT block_number, tx_hash, log_index, address, topic0, data FROM";
+            let differ =
+                format!("SELECT COUNT(*) FROM ({logs} main.logs EXCEPT {logs} whole.logs)");
+            assert_eq!(count(&conn, &differ), 0);
+            let topics = "SELECT COUNT(*) FROM log_topics JOIN logs ON logs.id = log_id
+                          WHERE position = 0 AND topic = topic0";
+            let first = "SELECT COUNT(*) FROM logs WHERE topic0 IS NOT NULL";
+            assert_eq!(count(&conn, topics), count(&conn, first));
+            let differ = "SELECT COUNT(*) FROM (SELECT * FROM main.transactions
+                                              EXCEPT SELECT * FROM whole.transactions)";
+            assert_eq!(count(&conn, differ), 0);
+        }
+        let whole = open_existing(&db).unwrap();
+        for (table, rows) in rows {
+            assert_eq!(rows, count(&whole, &format!("SELECT COUNT(*) FROM {table}")), "{table}");
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! `export state` — the accounts of `--addresses` as they stood after block
+//! `--at-block`, as the JSON state file `anvil --load-state` reads (and a
+//! revm `CacheDB` can be filled from), so a simulation starts from exactly
+//! the dataset's state:
+//!
+//!   {"best_block_number": "0x...",
+//!    "accounts": {"0x…": {"nonce": 7, "balance": "0x…", "code": "0x…",
+//!                          "storage": {"0x…": "0x…"}}}}
+//!
+//! The loader doesn't produce state; like `traces`, the tables come from
+//! the capture and are copied or attached into the database:
+//!
+//!   state_diffs  block_number, tx_index, address, field, slot, value: one
+//!                row per value a transaction changed, `field` being
+//!                balance, nonce, code or storage, `value` the value after
+//!                the transaction (decimal or 0x-hex amounts, 0x-hex code,
+//!                32-byte words for `slot` and storage values)
+//!   contracts    address, code and optionally block_number: deployed code
+//!
+//! A value is the last one written at or before the block. One that the
+//! tables don't have there — an account unchanged since before the dataset,
+//! or a slot first written after the block — is fetched with `--rpc` from
+//! an archive node at the block (eth_getBalance, eth_getTransactionCount,
+//! eth_getCode, eth_getStorageAt), or without it left at zero, with a
+//! warning. RPC can't list an account's storage, so the slots exported are
+//! those the dataset's transactions touched; zero slots are left out.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alloy_primitives::{hex, Address, U256};
+use clap::Args;
+use eyre::{eyre, Context, Result};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::Connection;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::incident_report;
+use crate::normalize::read_amount;
+use crate::open_existing;
+use crate::rpc::{self, RpcClient};
+
+const STATE_DIFF_COLUMNS: [&str; 6] =
+    ["block_number", "tx_index", "address", "field", "slot", "value"];
+
+#[derive(Args, Debug)]
+pub struct StateArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// Export the state after this block
+    #[arg(long)]
+    at_block: i64,
+
+    /// Accounts to export: one address per line, or a labels CSV or JSON file
+    #[arg(long)]
+    addresses: PathBuf,
+
+    /// Output JSON state file
+    #[arg(short, long)]
+    out: PathBuf,
+
+    /// Archive node JSON-RPC endpoint for the values the tables don't have
+    #[arg(long)]
+    rpc: Option<String>,
+
+    /// Attempts per RPC call before giving up
+    #[arg(long, default_value_t = 8, requires = "rpc")]
+    retries: u32,
+
+    /// Base delay between retries in milliseconds (doubles on HTTP 429)
+    #[arg(long, default_value_t = 2000, requires = "rpc")]
+    retry_delay_ms: u64,
+}
+
+/// One account at the block; None where the tables have no value for it.
+#[derive(Default)]
+pub(crate) struct Account {
+    pub(crate) balance: Option<U256>,
+    pub(crate) nonce: Option<u64>,
+    pub(crate) code: Option<Vec<u8>>,
+    pub(crate) storage: BTreeMap<U256, U256>,
+    /// Slots first written after the block, whose value at it is unknown.
+    pub(crate) unknown_slots: BTreeSet<U256>,
+}
+
+/// Which of the state tables a database has.
+pub(crate) struct Tables {
+    diffs: bool,
+    contracts: bool,
+    /// Whether contracts records the block of each deployment.
+    dated: bool,
+}
+
+pub fn run(args: StateArgs) -> Result<()> {
+    let conn = open_existing(&args.db)?;
+    let block = args.at_block;
+    check_block(&conn, &args.db, block)?;
+    let tables = Tables::open(&conn, args.rpc.is_some())?;
+    let client = args
+        .rpc
+        .as_deref()
+        .map(|url| RpcClient::new(url, args.retries, Duration::from_millis(args.retry_delay_ms)));
+
+    let addresses: BTreeSet<Address> = incident_report::addresses(&args.addresses)?
+        .into_iter()
+        .map(|(a, _)| a.parse().map_err(|_| eyre!("{a:?} is not an address")))
+        .collect::<Result<_>>()?;
+    let (mut fetched, mut unknown) = (0usize, 0usize);
+    let mut accounts = serde_json::Map::new();
+    for address in &addresses {
+        let mut account = tables.account(&conn, address, block)?;
+        let addr = format!("{address:#x}");
+        if let Some(client) = &client {
+            fetched += fetch(client, address, block, &mut account)?;
+        } else {
+            unknown += [account.balance.is_none(), account.nonce.is_none(), account.code.is_none()]
+                .iter()
+                .filter(|&&u| u)
+                .count()
+                + account.unknown_slots.len();
+        }
+        let storage: serde_json::Map<String, serde_json::Value> = account
+            .storage
+            .iter()
+            .filter(|(_, value)| !value.is_zero())
+            .map(|(slot, value)| (format!("{slot:#x}"), json!(format!("{value:#x}"))))
+            .collect();
+        accounts.insert(
+            addr,
+            json!({
+                "nonce": account.nonce.unwrap_or(0),
+                "balance": format!("{:#x}", account.balance.unwrap_or_default()),
+                "code": format!("0x{}", hex::encode(account.code.unwrap_or_default())),
+                "storage": storage,
+            }),
+        );
+    }
+    if unknown > 0 {
+        warn!(
+            values = unknown,
+            "values not in the tables at the block were left at zero; --rpc fetches them"
+        );
+    }
+
+    let state = json!({ "best_block_number": format!("0x{block:x}"), "accounts": accounts });
+    let json = serde_json::to_string_pretty(&state)?;
+    std::fs::write(&args.out, json + "\n")
+        .with_context(|| format!("Cannot write {}", args.out.display()))?;
+    info!(
+        out = %args.out.display(),
+        block,
+        accounts = addresses.len(),
+        fetched,
+        "wrote state snapshot"
+    );
+    Ok(())
+}
+
+/// Check that the database has blocks up to `block`.
+pub(crate) fn check_block(conn: &Connection, db: &Path, block: i64) -> Result<()> {
+    let last: Option<i64> = conn.query_row("SELECT MAX(number) FROM blocks", [], |r| r.get(0))?;
+    match last {
+        Some(last) if block > last => {
+            eyre::bail!("--at-block {block} is after the database's last block, {last}")
+        }
+        None => eyre::bail!("{} has no blocks", db.display()),
+        _ => Ok(()),
+    }
+}
+
+/// Fail with the expected layout if `state_diffs` is missing or lacks a
+/// column.
+pub(crate) fn check_diffs(conn: &Connection) -> Result<()> {
+    if columns(conn, "state_diffs")?.is_empty() {
+        eyre::bail!(
+            "no state_diffs table; copy one in (columns {})",
+            STATE_DIFF_COLUMNS.join(", ")
+        );
+    }
+    Tables::open(conn, true).map(|_| ())
+}
+
+impl Tables {
+    /// The state tables of the database, checking their columns. Without
+    /// `rpc` to fall back on, there must be at least one.
+    pub(crate) fn open(conn: &Connection, rpc: bool) -> Result<Self> {
+        let diffs = columns(conn, "state_diffs")?;
+        if !diffs.is_empty() {
+            let missing: Vec<&str> = STATE_DIFF_COLUMNS
+                .into_iter()
+                .filter(|c| !diffs.iter().any(|have| have == c))
+                .collect();
+            if !missing.is_empty() {
+                eyre::bail!("state_diffs table is missing columns: {}", missing.join(", "));
+            }
+        }
+        let contracts = columns(conn, "contracts")?;
+        if !contracts.is_empty()
+            && !["address", "code"].iter().all(|c| contracts.iter().any(|h| h == c))
+        {
+            eyre::bail!("contracts table needs address and code columns");
+        }
+        if diffs.is_empty() && contracts.is_empty() && !rpc {
+            eyre::bail!(
+                "no state_diffs or contracts table to read state from; copy them in (state_diffs \
+                 columns {}) or give --rpc",
+                STATE_DIFF_COLUMNS.join(", ")
+            );
+        }
+        Ok(Self {
+            diffs: !diffs.is_empty(),
+            dated: contracts.iter().any(|c| c == "block_number"),
+            contracts: !contracts.is_empty(),
+        })
+    }
+
+    /// `address` after `block`, as far as the tables know it.
+    pub(crate) fn account(
+        &self,
+        conn: &Connection,
+        address: &Address,
+        block: i64,
+    ) -> Result<Account> {
+        let mut account = Account::default();
+        if self.diffs {
+            read_diffs(conn, address, block, &mut account)?;
+        }
+        if account.code.is_none() && self.contracts {
+            account.code = read_code(conn, address, block, self.dated)?;
+        }
+        Ok(account)
+    }
+}
+
+/// Fetch what `account` is missing at `block` from an archive node: the
+/// balance, nonce and code the tables don't have and the unknown slots.
+/// Returns the number of values fetched.
+pub(crate) fn fetch(
+    client: &RpcClient,
+    address: &Address,
+    block: i64,
+    account: &mut Account,
+) -> Result<usize> {
+    let at = format!("0x{block:x}");
+    let addr = format!("{address:#x}");
+    let mut fetched = 0;
+    if account.balance.is_none() {
+        account.balance = Some(u256(&client.call("eth_getBalance", json!([addr, at]))?)?);
+        fetched += 1;
+    }
+    if account.nonce.is_none() {
+        let nonce = client.call("eth_getTransactionCount", json!([addr, at]))?;
+        account.nonce = Some(rpc::quantity_i64(&nonce)? as u64);
+        fetched += 1;
+    }
+    if account.code.is_none() {
+        account.code = Some(rpc::data(&client.call("eth_getCode", json!([addr, at]))?)?);
+        fetched += 1;
+    }
+    for slot in std::mem::take(&mut account.unknown_slots) {
+        account.storage.insert(slot, fetch_slot(client, address, slot, block)?);
+        fetched += 1;
+    }
+    Ok(fetched)
+}
+
+/// One storage slot of `address` at `block`, from an archive node.
+pub(crate) fn fetch_slot(
+    client: &RpcClient,
+    address: &Address,
+    slot: U256,
+    block: i64,
+) -> Result<U256> {
+    let params = json!([format!("{address:#x}"), format!("{slot:#x}"), format!("0x{block:x}")]);
+    Ok(U256::from_be_slice(&rpc::data(&client.call("eth_getStorageAt", params)?)?))
+}
+
+/// The columns of `table`, empty if there is no such table.
+fn columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    Ok(conn
+        .prepare("SELECT name FROM pragma_table_info(?1)")?
+        .query_map([table], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?)
+}
+
+/// `address` as it may be stored: lowercase, checksummed or as bytes.
+fn stored(address: &Address) -> [Value; 3] {
+    [
+        Value::Text(format!("{address:#x}")),
+        Value::Text(address.to_checksum(None)),
+        Value::Blob(address.to_vec()),
+    ]
+}
+
+/// Fill in `account` from its state_diffs rows, the last at or before
+/// `block` winning; slots only written later are noted as unknown.
+fn read_diffs(
+    conn: &Connection,
+    address: &Address,
+    block: i64,
+    account: &mut Account,
+) -> Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT field, slot, value, block_number <= ?4 FROM state_diffs
+         WHERE address IN (?1, ?2, ?3)
+         ORDER BY block_number, tx_index",
+    )?;
+    let [a, b, c] = stored(address);
+    let mut rows = stmt.query(rusqlite::params![a, b, c, block])?;
+    while let Some(r) = rows.next()? {
+        let field: String = r.get(0)?;
+        let before: bool = r.get(3)?;
+        let context = || format!("state_diffs {field} of {address:#x}");
+        match field.as_str() {
+            "storage" => {
+                let slot = read_amount(r.get_ref(1)?).with_context(context)?;
+                if before {
+                    account.unknown_slots.remove(&slot);
+                    account.storage.insert(slot, read_amount(r.get_ref(2)?).with_context(context)?);
+                } else if !account.storage.contains_key(&slot) {
+                    account.unknown_slots.insert(slot);
+                }
+            }
+            _ if !before => {}
+            "balance" => account.balance = Some(read_amount(r.get_ref(2)?).with_context(context)?),
+            "nonce" => {
+                let nonce = read_amount(r.get_ref(2)?).with_context(context)?;
+                account.nonce =
+                    Some(u64::try_from(nonce).map_err(|_| eyre!("{}: too large", context()))?);
+            }
+            "code" => account.code = Some(bytes(r.get_ref(2)?).with_context(context)?),
+            other => eyre::bail!(
+                "state_diffs: unknown field {other:?} (expected balance, nonce, code or storage)"
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// The code the contracts table has for `address` (deployed by `block`,
+/// when it records where).
+fn read_code(
+    conn: &Connection,
+    address: &Address,
+    block: i64,
+    dated: bool,
+) -> Result<Option<Vec<u8>>> {
+    let [a, b, c] = stored(address);
+    let mut stmt;
+    let mut rows = if dated {
+        stmt = conn.prepare_cached(
+            "SELECT code FROM contracts WHERE address IN (?1, ?2, ?3) AND block_number <= ?4
+             ORDER BY block_number DESC LIMIT 1",
+        )?;
+        stmt.query(rusqlite::params![a, b, c, block])?
+    } else {
+        stmt = conn.prepare_cached("SELECT code FROM contracts WHERE address IN (?1, ?2, ?3)")?;
+        stmt.query(rusqlite::params![a, b, c])?
+    };
+    match rows.next()? {
+        Some(r) => Ok(Some(
+            bytes(r.get_ref(0)?).with_context(|| format!("contracts code of {address:#x}"))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Code stored as a blob or as 0x-hex text.
+fn bytes(v: ValueRef<'_>) -> Result<Vec<u8>> {
+    match v {
+        ValueRef::Blob(b) => Ok(b.to_vec()),
+        ValueRef::Text(t) => Ok(hex::decode(std::str::from_utf8(t)?)?),
+        ValueRef::Null => Ok(Vec::new()),
+        other => Err(eyre!("not code: {other:?}")),
+    }
+}
+
+fn u256(v: &serde_json::Value) -> Result<U256> {
+    let s = v.as_str().ok_or_else(|| eyre!("expected hex quantity, got {v}"))?;
+    match s.strip_prefix("0x").unwrap_or(s) {
+        "" => Ok(U256::ZERO),
+        digits => U256::from_str_radix(digits, 16).map_err(|e| eyre!("bad quantity {s}: {e}")),
+    }
+}
@@ -0,0 +1,216 @@
+//! `stats` — what a dataset is made of, for a first look at an unfamiliar
+//! one:
+//!
+//!   stats selectors  transactions by the function selector they call
+//!   stats events     logs by topic0
+//!
+//! Each ranks by count, or with `--by gas` by gas used: a selector's is its
+//! transactions' gas, an event's that of the transactions logging it (each
+//! counted once per event, so an event's share can't be added up with
+//! another's). A transaction without calldata counts as `0x`, a deployment
+//! as `(create)`; a log without topics as `(anonymous)`. `--names` adds the
+//! name from the signature tables `--views` installs (`function_signatures`
+//! and `event_signatures`), or from the signatures they are seeded with
+//! when the database has none. Rows print as an aligned table, or CSV.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+
+use alloy_primitives::hex;
+use clap::{Args, Subcommand, ValueEnum};
+use eyre::Result;
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::normalize::{read_key, Normalize};
+use crate::{open_existing, query, views};
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    #[command(subcommand)]
+    command: StatsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum StatsCommand {
+    /// Function selectors by transactions and gas
+    Selectors(RankArgs),
+
+    /// Event topic0s by logs, emitters and the gas of their transactions
+    Events(RankArgs),
+}
+
+#[derive(Args, Debug)]
+struct RankArgs {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+
+    /// What to rank by
+    #[arg(long, value_enum, default_value_t = By::Count)]
+    by: By,
+
+    /// Print the top N (0 for all)
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+
+    /// Add each one's name, from the database's signature tables
+    #[arg(long)]
+    names: bool,
+
+    /// Print CSV instead of a table
+    #[arg(long)]
+    csv: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum By {
+    Count,
+    Gas,
+}
+
+/// One ranked selector or topic0.
+#[derive(Default)]
+struct Tally {
+    count: u64,
+    /// Failed transactions (selectors), or emitting contracts (events).
+    other: u64,
+    transactions: u64,
+    gas: u64,
+    contracts: HashSet<String>,
+}
+
+pub fn run(args: StatsArgs) -> Result<()> {
+    match args.command {
+        StatsCommand::Selectors(args) => {
+            let conn = open_existing(&args.db)?;
+            let tallies = selectors(&conn)?;
+            let names = if args.names { Some(views::function_names(&conn)?) } else { None };
+            let columns =
+                ["selector", "transactions", "failed", "contracts", "gas_used", "gas_pct"];
+            print(&args, &columns, tallies, names, |t| {
+                vec![t.count, t.other, t.contracts.len() as u64, t.gas]
+            })
+        }
+        StatsCommand::Events(args) => {
+            let conn = open_existing(&args.db)?;
+            let tallies = events(&conn)?;
+            let names = if args.names { Some(views::event_names(&conn)?) } else { None };
+            let columns = ["topic0", "logs", "emitters", "transactions", "gas_used", "gas_pct"];
+            print(&args, &columns, tallies, names, |t| {
+                vec![t.count, t.other, t.transactions, t.gas]
+            })
+        }
+    }
+}
+
+/// Transactions by selector.
+fn selectors(conn: &Connection) -> Result<HashMap<String, Tally>> {
+    let norm = Normalize::from_meta(conn)?;
+    let mut stmt = conn.prepare("SELECT to_addr, input, gas_used, status FROM transactions")?;
+    let mut rows = stmt.query([])?;
+    let mut tallies: HashMap<String, Tally> = HashMap::new();
+    while let Some(r) = rows.next()? {
+        let to = read_key(r.get_ref(0)?)?.map(|a| a.to_ascii_lowercase());
+        let input = norm.read_blob(r.get(1)?)?;
+        let selector = match (&to, input.get(..4)) {
+            (None, _) => "(create)".to_string(),
+            (Some(_), Some(selector)) => format!("0x{}", hex::encode(selector)),
+            (Some(_), None) => "0x".to_string(),
+        };
+        let t = tallies.entry(selector).or_default();
+        t.count += 1;
+        t.other += (r.get::<_, i64>(3)? == 0) as u64;
+        t.gas += r.get::<_, i64>(2)?.max(0) as u64;
+        t.contracts.extend(to);
+    }
+    Ok(tallies)
+}
+
+/// Logs by topic0.
+fn events(conn: &Connection) -> Result<HashMap<String, Tally>> {
+    let mut stmt = conn.prepare(
+        "SELECT c.topic0, c.logs, c.emitters, c.transactions, COALESCE(g.gas_used, 0)
+         FROM (SELECT topic0, COUNT(*) AS logs, COUNT(DISTINCT address) AS emitters,
+                      COUNT(DISTINCT tx_hash) AS transactions
+               FROM logs GROUP BY topic0) c
+         LEFT JOIN (SELECT d.topic0, SUM(t.gas_used) AS gas_used
+                    FROM (SELECT DISTINCT topic0, tx_hash FROM logs) d
+                    JOIN transactions t ON t.hash = d.tx_hash
+                    GROUP BY d.topic0) g ON g.topic0 IS c.topic0",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut tallies = HashMap::new();
+    while let Some(r) = rows.next()? {
+        let topic0 = read_key(r.get_ref(0)?)?.map(|t| t.to_ascii_lowercase());
+        let count = |i: usize| -> Result<u64> { Ok(r.get::<_, i64>(i)?.max(0) as u64) };
+        let tally = Tally {
+            count: count(1)?,
+            other: count(2)?,
+            transactions: count(3)?,
+            gas: count(4)?,
+            contracts: HashSet::new(),
+        };
+        tallies.insert(topic0.unwrap_or_else(|| "(anonymous)".to_string()), tally);
+    }
+    Ok(tallies)
+}
+
+/// Rank `tallies`, and print the top ones with `cells` and their share of
+/// all gas.
+fn print(
+    args: &RankArgs,
+    columns: &[&str],
+    tallies: HashMap<String, Tally>,
+    names: Option<HashMap<String, String>>,
+    cells: impl Fn(&Tally) -> Vec<u64>,
+) -> Result<()> {
+    let total_count: u64 = tallies.values().map(|t| t.count).sum();
+    let total_gas: u64 = tallies.values().map(|t| t.gas).sum();
+    let mut ranked: Vec<(String, Tally)> = tallies.into_iter().collect();
+    ranked.sort_by(|(a_key, a), (b_key, b)| {
+        let (a_rank, b_rank) = match args.by {
+            By::Count => ((a.count, a.gas), (b.count, b.gas)),
+            By::Gas => ((a.gas, a.count), (b.gas, b.count)),
+        };
+        b_rank.cmp(&a_rank).then_with(|| a_key.cmp(b_key))
+    });
+    let distinct = ranked.len();
+    if args.limit > 0 {
+        ranked.truncate(args.limit);
+    }
+
+    let mut header: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+    if names.is_some() {
+        header.insert(1, "name".into());
+    }
+    let table: Vec<Vec<String>> = ranked
+        .iter()
+        .map(|(key, t)| {
+            let mut row = vec![key.clone()];
+            if let Some(names) = &names {
+                row.push(names.get(key).cloned().unwrap_or_default());
+            }
+            row.extend(cells(t).iter().map(u64::to_string));
+            let share = if total_gas == 0 { 0.0 } else { t.gas as f64 * 100.0 / total_gas as f64 };
+            row.push(format!("{share:.1}"));
+            row
+        })
+        .collect();
+
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    if args.csv {
+        let mut w = csv::Writer::from_writer(&mut out);
+        w.write_record(&header)?;
+        for row in &table {
+            w.write_record(row)?;
+        }
+        w.flush()?;
+    } else {
+        query::print_table(&mut out, &header, &table)?;
+    }
+    out.flush()?;
+    info!(distinct, total = total_count, gas_used = total_gas, "ranked");
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Helpers for the tests that load datasets: scratch directories, small
+//! datasets written with DatasetWriter, and loads run from a command line
+//! as `run` would run them.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use arrow::compute::concat_batches;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use rusqlite::Connection;
+
+use crate::dataset::{BlockRow, DatasetWriter, LogRow, TxRow};
+use crate::manifest::BlockRange;
+use crate::{load, parse_cli, Cli, Command};
+
+/// A directory under the system's temporary one, removed on drop.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir();
+        let dir = dir.join(format!("offline-replay-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn join(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Parse a command line, split at whitespace.
+pub fn cli(argv: &str) -> Cli {
+    parse_cli(argv.split_whitespace()).unwrap_or_else(|e| panic!("{argv}: {e}")).0
+}
+
+/// Load `data` into `out`, with more flags.
+pub fn load_into(data: &Path, out: &Path, flags: &str) -> eyre::Result<()> {
+    let argv = format!("offline-replay --data {} --out {} {flags}", data.display(), out.display());
+    load(cli(&argv).load)
+}
+
+/// Write a synthetic dataset of `blocks` blocks to `dir` (see generate.rs).
+pub fn generate(dir: &Path, blocks: u64) {
+    let argv = format!("offline-replay generate --out {} --blocks {blocks}", dir.display());
+    let Some(Command::Generate(args)) = cli(&argv).command else { unreachable!() };
+    crate::generate::run(args).unwrap();
+}
+
+/// The single number a query returns.
+pub fn count(conn: &Connection, sql: &str) -> i64 {
+    conn.query_row(sql, [], |r| r.get(0)).unwrap_or_else(|e| panic!("{sql}: {e}"))
+}
+
+/// A block `hash` of `number` with one transaction: with `tx` and `log`,
+/// the rows of datasets written by hand.
+pub fn block(number: i64, hash: &str, parent_hash: &str) -> BlockRow {
+    BlockRow {
+        number,
+        hash: hash.to_string(),
+        parent_hash: parent_hash.to_string(),
+        timestamp: 1_700_000_000 + number * 12,
+        gas_used: 21_000,
+        gas_limit: 30_000_000,
+        base_fee: Some("1000000000".to_string()),
+        tx_count: 1,
+        miner: Some(format!("0x{:040x}", 0xfee)),
+    }
+}
+
+pub fn tx(hash: &str, block_number: i64, tx_index: i64) -> TxRow {
+    TxRow {
+        hash: hash.to_string(),
+        block_number,
+        tx_index,
+        from_addr: format!("0x{:040x}", 1),
+        to_addr: Some(format!("0x{:040x}", 2)),
+        value: "1".to_string(),
+        gas_used: 21_000,
+        gas_price: "1000000000".to_string(),
+        input: Vec::new(),
+        status: 1,
+        logs_bloom: None,
+        nonce: tx_index,
+    }
+}
+
+pub fn log(tx_hash: &str, block_number: i64, log_index: i64) -> LogRow {
+    LogRow {
+        block_number,
+        tx_hash: tx_hash.to_string(),
+        log_index,
+        address: format!("0x{:040x}", 3),
+        topics: [Some(format!("0x{:064x}", 4)), None, None, None],
+        data: vec![1],
+    }
+}
+
+/// Write `blocks`, `txs` and `logs` to a dataset in `dir`.
+pub fn write(dir: &Path, blocks: Vec<BlockRow>, txs: Vec<TxRow>, logs: Vec<LogRow>) {
+    let range = BlockRange {
+        start: blocks.iter().map(|b| b.number).min().unwrap(),
+        end: blocks.iter().map(|b| b.number).max().unwrap() + 1,
+    };
+    let mut w = DatasetWriter::create(dir, None).unwrap();
+    blocks.into_iter().try_for_each(|b| w.push_block(b)).unwrap();
+    txs.into_iter().try_for_each(|t| w.push_tx(t)).unwrap();
+    logs.into_iter().try_for_each(|l| w.push_log(l)).unwrap();
+    w.finish(range).unwrap();
+}
+
+/// Rewrite a Parquet file as `f` changes its rows, read as one batch.
+pub fn rewrite(path: &Path, f: impl FnOnce(RecordBatch) -> RecordBatch) {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+    let schema = reader.schema().clone();
+    let batches: Vec<RecordBatch> = reader.build().unwrap().map(Result::unwrap).collect();
+    let batch = f(concat_batches(&schema, &batches).unwrap());
+    let mut w = ArrowWriter::try_new(File::create(path).unwrap(), batch.schema(), None).unwrap();
+    w.write(&batch).unwrap();
+    w.close().unwrap();
+}
@@ -0,0 +1,82 @@
+//! Logs bloom: each transaction's receipt `logs_bloom` against a bloom
+//! rebuilt from the logs loaded for it (their addresses and topics).
+//!
+//! Bits set in the receipt but not by the loaded logs mean logs are missing
+//! or lost topics; bits set only by the loaded logs mean logs were altered or
+//! belong to another transaction. Transactions loaded without a bloom are
+//! skipped.
+
+use alloy_primitives::{hex, keccak256};
+use eyre::Result;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use tracing::{info, warn};
+
+use crate::normalize::read_key;
+
+const BLOOM_BYTES: usize = 256;
+
+pub fn check(conn: &Connection) -> Result<Vec<String>> {
+    let mut findings = Vec::new();
+    let mut blooms = conn.prepare(
+        "SELECT hash, block_number, logs_bloom FROM transactions
+         WHERE logs_bloom IS NOT NULL ORDER BY block_number, tx_index",
+    )?;
+    let mut logs = conn.prepare_cached(
+        "SELECT address, topic0, topic1, topic2, topic3 FROM logs WHERE tx_hash = ?1",
+    )?;
+    let mut rows = blooms.query([])?;
+    let mut checked = 0;
+    while let Some(r) = rows.next()? {
+        let (hash, block, stored): (Value, i64, Vec<u8>) = (r.get(0)?, r.get(1)?, r.get(2)?);
+        let tx = read_key(r.get_ref(0)?)?.unwrap_or_default();
+        if stored.len() != BLOOM_BYTES {
+            let len = stored.len();
+            findings.push(format!("tx {tx} (block {block}): logs_bloom is {len} bytes, not 256"));
+            continue;
+        }
+
+        let mut rebuilt = [0u8; BLOOM_BYTES];
+        let mut n = 0;
+        let mut log_rows = logs.query([&hash])?;
+        while let Some(l) = log_rows.next()? {
+            for i in 0..5 {
+                if let Some(item) = read_key(l.get_ref(i)?)? {
+                    accrue(&mut rebuilt, &hex::decode(&item)?);
+                }
+            }
+            n += 1;
+        }
+        checked += 1;
+
+        let missing = stored.iter().zip(&rebuilt).any(|(s, b)| s & !b != 0);
+        let extra = stored.iter().zip(&rebuilt).any(|(s, b)| b & !s != 0);
+        if missing {
+            findings.push(format!(
+                "tx {tx} (block {block}): logs_bloom has bits none of its {n} loaded logs set \
+                 (logs missing or truncated)"
+            ));
+        }
+        if extra {
+            findings.push(format!(
+                "tx {tx} (block {block}): its loaded logs set bits missing from logs_bloom \
+                 (logs altered or misattributed)"
+            ));
+        }
+    }
+    if checked == 0 {
+        warn!("no transactions with a logs_bloom; nothing to check");
+    }
+    info!(transactions = checked, "checked blooms");
+    Ok(findings)
+}
+
+/// Add `item` (an address or topic) to a 2048-bit bloom, as in the yellow
+/// paper: three 11-bit indexes from the first six bytes of its keccak hash.
+pub(crate) fn accrue(bloom: &mut [u8; BLOOM_BYTES], item: &[u8]) {
+    let hash = keccak256(item);
+    for i in 0..3 {
+        let bit = (usize::from(hash[2 * i]) << 8 | usize::from(hash[2 * i + 1])) & 2047;
+        bloom[BLOOM_BYTES - 1 - bit / 8] |= 1 << (bit % 8);
+    }
+}
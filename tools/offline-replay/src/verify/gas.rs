@@ -0,0 +1,48 @@
+//! Gas accounting: a block's gas_used is the sum of its transactions'
+//! gas_used, and neither a transaction nor the block uses more than the
+//! block's gas_limit.
+//!
+//! A block whose transaction count doesn't match tx_count is reported as
+//! such rather than by gas sum, since a load filtered with --address keeps
+//! only some of each block's transactions.
+
+use eyre::Result;
+use rusqlite::Connection;
+
+pub fn check(conn: &Connection) -> Result<Vec<String>> {
+    let mut findings = Vec::new();
+    let mut stmt = conn.prepare(
+        "SELECT b.number, b.gas_used, b.gas_limit, b.tx_count,
+                COUNT(t.hash), COALESCE(SUM(t.gas_used), 0), MAX(t.gas_used)
+         FROM blocks b
+         LEFT JOIN transactions t ON t.block_number = b.number
+         WHERE b.is_canonical = 1
+         GROUP BY b.number
+         ORDER BY b.number",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let (block, gas_used, gas_limit): (i64, i64, i64) = (r.get(0)?, r.get(1)?, r.get(2)?);
+        let (tx_count, loaded, sum): (i64, i64, i64) = (r.get(3)?, r.get(4)?, r.get(5)?);
+        let max: Option<i64> = r.get(6)?;
+
+        if gas_used > gas_limit {
+            findings.push(format!("block {block}: gas_used {gas_used} exceeds gas_limit {gas_limit}"));
+        }
+        if let Some(max) = max.filter(|&m| m > gas_limit) {
+            findings.push(format!(
+                "block {block}: a transaction uses {max} gas, over the gas_limit {gas_limit}"
+            ));
+        }
+        if loaded != tx_count {
+            findings.push(format!(
+                "block {block}: tx_count is {tx_count} but {loaded} transactions are loaded"
+            ));
+        } else if sum != gas_used {
+            findings.push(format!(
+                "block {block}: gas_used is {gas_used} but its transactions sum to {sum}"
+            ));
+        }
+    }
+    Ok(findings)
+}
@@ -0,0 +1,61 @@
+//! `verify` — internal consistency checks on a loaded database, for catching
+//! exports that dropped or mangled rows before they mislead an analysis.
+//!
+//!   verify gas      block gas_used against its transactions, and gas limits
+//!   verify bloom    receipt logs blooms against the logs loaded
+//!   verify nonces   tx_index order within blocks, nonce gaps per sender
+//!
+//! Each problem is printed on its own line on stdout; the command fails if
+//! there were any, so it can gate a CI job.
+
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use eyre::Result;
+use tracing::info;
+
+use crate::open_existing;
+
+pub(crate) mod bloom;
+mod gas;
+mod nonces;
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[command(subcommand)]
+    command: VerifyCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum VerifyCommand {
+    /// Per-block gas_used equals the sum over its transactions, within gas_limit
+    Gas(DbArg),
+
+    /// Receipt logs_bloom matches a bloom rebuilt from the loaded logs
+    Bloom(DbArg),
+
+    /// tx_index ordering within blocks, and nonce gaps or repeats per sender
+    Nonces(DbArg),
+}
+
+#[derive(Args, Debug)]
+struct DbArg {
+    /// SQLite database produced by offline-replay
+    db: PathBuf,
+}
+
+pub fn run(args: VerifyArgs) -> Result<()> {
+    let (check, findings) = match args.command {
+        VerifyCommand::Gas(a) => ("gas", gas::check(&open_existing(&a.db)?)?),
+        VerifyCommand::Bloom(a) => ("bloom", bloom::check(&open_existing(&a.db)?)?),
+        VerifyCommand::Nonces(a) => ("nonces", nonces::check(&open_existing(&a.db)?)?),
+    };
+    for finding in &findings {
+        println!("{finding}");
+    }
+    if !findings.is_empty() {
+        eyre::bail!("verify {check}: {} problem(s) found", findings.len());
+    }
+    info!(check, "no problems found");
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Transaction ordering and nonces.
+//!
+//! Within a block, tx_index must be unique, and run 0..tx_count-1 when all
+//! of the block's transactions are loaded. Per sender, in (block, tx_index)
+//! order, each nonce must be one more than the last: a repeated or lower
+//! nonce can't happen on chain, and a jump usually means the export dropped
+//! transactions in between. A load filtered with --address keeps only some
+//! of each sender's transactions, so jumps are expected there (and an
+//! EIP-7702 authorization also takes one of the authority's nonces).
+//! Transactions loaded without a nonce are skipped.
+
+use std::collections::HashMap;
+
+use eyre::Result;
+use rusqlite::Connection;
+use tracing::{info, warn};
+
+use crate::normalize::read_key;
+
+pub fn check(conn: &Connection) -> Result<Vec<String>> {
+    let mut findings = Vec::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT b.number, b.tx_count, COUNT(t.hash), COUNT(DISTINCT t.tx_index),
+                MIN(t.tx_index), MAX(t.tx_index)
+         FROM blocks b
+         JOIN transactions t ON t.block_number = b.number
+         WHERE b.is_canonical = 1
+         GROUP BY b.number
+         ORDER BY b.number",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let (block, tx_count, loaded, distinct): (i64, i64, i64, i64) =
+            (r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?);
+        let (min, max): (i64, i64) = (r.get(4)?, r.get(5)?);
+        if distinct < loaded {
+            let duplicates = loaded - distinct;
+            findings.push(format!("block {block}: {duplicates} duplicate tx_index value(s)"));
+        } else if loaded == tx_count && (min != 0 || max != tx_count - 1) {
+            findings.push(format!(
+                "block {block}: tx_index runs {min}..{max}, expected 0..{}",
+                tx_count - 1
+            ));
+        } else if max >= tx_count {
+            findings.push(format!("block {block}: tx_index {max} but tx_count is {tx_count}"));
+        }
+    }
+    drop(rows);
+
+    let mut stmt = conn.prepare(
+        "SELECT from_addr, nonce, block_number, hash FROM transactions
+         WHERE nonce IS NOT NULL
+         ORDER BY block_number, tx_index",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut last: HashMap<String, (i64, i64)> = HashMap::new();
+    let mut checked = 0;
+    while let Some(r) = rows.next()? {
+        let (nonce, block): (i64, i64) = (r.get(1)?, r.get(2)?);
+        let sender = read_key(r.get_ref(0)?)?.unwrap_or_default().to_ascii_lowercase();
+        let hash = read_key(r.get_ref(3)?)?.unwrap_or_default();
+        checked += 1;
+        if let Some(&(prev, prev_block)) = last.get(&sender) {
+            if nonce <= prev {
+                findings.push(format!(
+                    "sender {sender}: tx {hash} (block {block}) has nonce {nonce} after \
+                     nonce {prev} (block {prev_block})"
+                ));
+            } else if nonce > prev + 1 {
+                findings.push(format!(
+                    "sender {sender}: nonce jumps from {prev} (block {prev_block}) to {nonce} \
+                     (block {block}); {} transaction(s) missing?",
+                    nonce - prev - 1
+                ));
+            }
+        }
+        last.insert(sender, (nonce, block));
+    }
+    if checked == 0 {
+        warn!("no transactions with a nonce; only tx_index was checked");
+    }
+    info!(transactions = checked, senders = last.len(), "checked nonces");
+    Ok(findings)
+}
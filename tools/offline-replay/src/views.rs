@@ -0,0 +1,266 @@
+//! `--views` — convenience views installed alongside the data, for the joins
+//! everyone ends up writing by hand:
+//!
+//!   v_tx_with_block    transactions with their block's timestamp and base fee,
+//!                      names for from/to, and why they failed (from
+//!                      `revert_reasons`, once `analyze revert-reasons` ran)
+//!   v_logs_named       logs with the event name from `event_signatures` and
+//!                      the emitting address's name
+//!   v_daily_activity   blocks, transactions, logs and gas per UTC day
+//!
+//! `event_signatures` is seeded with common ERC-20/721, WETH and AMM events,
+//! and `function_signatures` with their functions; add rows to them (topic0
+//! or selector, name, signature) to name more. With the `event-db` feature
+//! (on by default) the events of data/event_signatures.txt, a few hundred
+//! from the openchain and 4byte signature databases, are seeded too, and
+//! `stats`, reports and `shell` name them even where no views are
+//! installed. Address names come
+//! from `labels`, falling back to `ens_names`. Views hold no data,
+//! so installing them again replaces them with the current definitions.
+//!
+//! `load --views-only` writes the same views for DuckDB (see
+//! duckdb_views.rs).
+//!
+//! With --binary-keys the views join the (text) signature, label and name
+//! tables on the keys' 0x-hex form, and show the keys themselves as BLOBs.
+
+use std::collections::HashMap;
+
+use eyre::Result;
+use rusqlite::{params, Connection};
+
+use crate::decode;
+use crate::normalize::Normalize;
+use crate::schema::{ENS_SCHEMA, LABELS_SCHEMA, REVERT_REASONS_SCHEMA};
+
+const VIEWS: &str = "
+DROP VIEW IF EXISTS v_tx_with_block;
+DROP VIEW IF EXISTS v_logs_named;
+DROP VIEW IF EXISTS v_daily_activity;
+
+CREATE TABLE IF NOT EXISTS event_signatures (
+    topic0    TEXT PRIMARY KEY,
+    name      TEXT NOT NULL,
+    signature TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS function_signatures (
+    selector  TEXT PRIMARY KEY,
+    name      TEXT NOT NULL,
+    signature TEXT NOT NULL
+);
+
+CREATE VIEW v_tx_with_block AS
+SELECT t.*,
+       b.timestamp,
+       datetime(b.timestamp, 'unixepoch') AS block_time,
+       b.base_fee,
+       COALESCE(fl.name, fe.name) AS from_label,
+       COALESCE(tl.name, te.name) AS to_label,
+       rr.kind   AS revert_kind,
+       rr.reason AS revert_reason
+FROM transactions t
+JOIN blocks b ON b.number = t.block_number AND b.is_canonical = 1
+LEFT JOIN revert_reasons rr ON rr.tx_hash = lower(t.hash) AND rr.frame = 0
+LEFT JOIN labels fl ON fl.address = lower(t.from_addr)
+LEFT JOIN labels tl ON tl.address = lower(t.to_addr)
+LEFT JOIN ens_names fe ON fe.address = lower(t.from_addr)
+LEFT JOIN ens_names te ON te.address = lower(t.to_addr);
+
+CREATE VIEW v_logs_named AS
+SELECT l.*,
+       s.name      AS event,
+       s.signature AS event_signature,
+       COALESCE(la.name, le.name) AS address_label
+FROM logs l
+LEFT JOIN event_signatures s ON s.topic0 = l.topic0
+LEFT JOIN labels la ON la.address = lower(l.address)
+LEFT JOIN ens_names le ON le.address = lower(l.address);
+
+CREATE VIEW v_daily_activity AS
+SELECT date(b.timestamp, 'unixepoch') AS day,
+       COUNT(*)                       AS blocks,
+       SUM(b.tx_count)                AS transactions,
+       SUM((SELECT COUNT(*) FROM logs l WHERE l.block_number = b.number)) AS logs,
+       SUM(b.gas_used)                AS gas_used
+FROM blocks b
+WHERE b.is_canonical = 1
+GROUP BY day;
+";
+
+/// VIEWS for how the database stores keys.
+fn views(norm: &Normalize) -> String {
+    if !norm.binary_keys {
+        return VIEWS.to_string();
+    }
+    let mut sql = VIEWS.to_string();
+    for key in ["t.hash", "t.from_addr", "t.to_addr", "l.address"] {
+        sql = sql.replace(&format!("lower({key})"), &format!("'0x' || lower(hex({key}))"));
+    }
+    sql.replace("s.topic0 = l.topic0", "s.topic0 = '0x' || lower(hex(l.topic0))")
+}
+
+/// The views and their tables in DuckDB's dialect, seeded, for
+/// `--views-only`.
+pub(crate) fn duckdb() -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let rows = |seeds: &[&str], hash: fn(&str) -> String| {
+        seeds
+            .iter()
+            .map(|s| format!("({}, {}, {})", quote(&hash(s)), quote(name(s)), quote(s)))
+            .collect::<Vec<_>>()
+            .join(",\n    ")
+    };
+    let views = VIEWS
+        .replace("CREATE VIEW", "CREATE OR REPLACE VIEW")
+        .replace("datetime(b.timestamp, 'unixepoch')", "make_timestamp(b.timestamp * 1000000)")
+        .replace("date(b.timestamp, 'unixepoch')", "CAST(make_timestamp(b.timestamp * 1000000) AS DATE)")
+        // Files need not have the column; views over them take every block.
+        .replace(" AND b.is_canonical = 1", "")
+        .replace("WHERE b.is_canonical = 1\n", "");
+    format!(
+        "{LABELS_SCHEMA}{ENS_SCHEMA}{REVERT_REASONS_SCHEMA}{views}
+INSERT OR IGNORE INTO event_signatures (topic0, name, signature) VALUES
+    {};
+
+INSERT OR IGNORE INTO function_signatures (selector, name, signature) VALUES
+    {};
+",
+        rows(&events(), decode::topic),
+        rows(FUNCTIONS, selector),
+    )
+}
+
+/// Events named out of the box in `v_logs_named`.
+pub(crate) const SIGNATURES: &[&str] = &[
+    "Transfer(address,address,uint256)",
+    "Approval(address,address,uint256)",
+    "ApprovalForAll(address,address,bool)",
+    "Deposit(address,uint256)",
+    "Withdrawal(address,uint256)",
+    "Sync(uint112,uint112)",
+    "Swap(address,uint256,uint256,uint256,uint256,address)",
+    "Swap(address,address,int256,int256,uint160,uint128,int24)",
+    "Mint(address,uint256,uint256)",
+    "Burn(address,uint256,uint256,address)",
+    "OwnershipTransferred(address,address)",
+    "Upgraded(address)",
+    "FlashLoan(address,address,address,uint256,uint256,uint16)",
+];
+
+/// The bundled event signatures, one per line, # for comments.
+#[cfg(feature = "event-db")]
+const EVENT_DB: &str = include_str!("../data/event_signatures.txt");
+
+/// The events seeded: `SIGNATURES`, then with `event-db` the bundled ones.
+fn events() -> Vec<&'static str> {
+    #[cfg(feature = "event-db")]
+    let bundled = EVENT_DB.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+    #[cfg(not(feature = "event-db"))]
+    let bundled = std::iter::empty();
+    SIGNATURES.iter().copied().chain(bundled).collect()
+}
+
+/// Functions named out of the box in `function_signatures`.
+pub(crate) const FUNCTIONS: &[&str] = &[
+    "transfer(address,uint256)",
+    "transferFrom(address,address,uint256)",
+    "approve(address,uint256)",
+    "setApprovalForAll(address,bool)",
+    "safeTransferFrom(address,address,uint256)",
+    "safeTransferFrom(address,address,uint256,bytes)",
+    "deposit()",
+    "withdraw(uint256)",
+    "multicall(bytes[])",
+    "multicall(uint256,bytes[])",
+    "execute(bytes,bytes[],uint256)",
+    "swapExactTokensForTokens(uint256,uint256,address[],address,uint256)",
+    "swapExactETHForTokens(uint256,address[],address,uint256)",
+    "swapExactTokensForETH(uint256,uint256,address[],address,uint256)",
+    "swap(uint256,uint256,address,bytes)",
+    "exactInputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))",
+    "exactInput((bytes,address,uint256,uint256,uint256))",
+    "flashLoan(address,address[],uint256[],uint256[],address,bytes,uint16)",
+    "upgradeTo(address)",
+    "upgradeToAndCall(address,bytes)",
+];
+
+/// Create (or replace) the views, and the tables they join if missing.
+pub fn install(conn: &Connection) -> Result<()> {
+    conn.execute_batch(LABELS_SCHEMA)?;
+    conn.execute_batch(ENS_SCHEMA)?;
+    conn.execute_batch(REVERT_REASONS_SCHEMA)?;
+    conn.execute_batch(&views(&Normalize::from_meta(conn)?))?;
+    let mut stmt = conn.prepare(
+        "INSERT OR IGNORE INTO event_signatures (topic0, name, signature) VALUES (?1, ?2, ?3)",
+    )?;
+    for signature in events() {
+        stmt.execute(params![decode::topic(signature), name(signature), signature])?;
+    }
+    let mut stmt = conn.prepare(
+        "INSERT OR IGNORE INTO function_signatures (selector, name, signature) VALUES (?1, ?2, ?3)",
+    )?;
+    for signature in FUNCTIONS {
+        stmt.execute(params![selector(signature), name(signature), signature])?;
+    }
+    Ok(())
+}
+
+/// Event names by topic0: the signatures `--views` seeds, then any in the
+/// database's `event_signatures`.
+pub(crate) fn event_names(conn: &Connection) -> Result<HashMap<String, String>> {
+    names(conn, &events(), decode::topic, "event_signatures", "topic0")
+}
+
+/// Function names by selector, likewise from `function_signatures`.
+pub(crate) fn function_names(conn: &Connection) -> Result<HashMap<String, String>> {
+    names(conn, FUNCTIONS, selector, "function_signatures", "selector")
+}
+
+fn names(
+    conn: &Connection,
+    seeds: &[&str],
+    hash: fn(&str) -> String,
+    table: &str,
+    key: &str,
+) -> Result<HashMap<String, String>> {
+    let mut names: HashMap<String, String> =
+        seeds.iter().map(|s| (hash(s), name(s).to_string())).collect();
+    let installed: bool =
+        conn.query_row("SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = ?1", [table], |r| {
+            r.get(0)
+        })?;
+    if installed {
+        let mut stmt = conn.prepare(&format!("SELECT {key}, name FROM {table}"))?;
+        let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (hash, name) = row?;
+            names.insert(hash.to_ascii_lowercase(), name);
+        }
+    }
+    Ok(names)
+}
+
+/// A signature's name, up to its parameters.
+fn name(signature: &str) -> &str {
+    &signature[..signature.find('(').unwrap_or(signature.len())]
+}
+
+/// The 4-byte selector of a function signature, as 0x-hex.
+fn selector(signature: &str) -> String {
+    decode::topic(signature)[..10].to_string()
+}
+
+/// Reinstall the views if the database has them, so they pick up tables
+/// added since they were created.
+pub fn refresh(conn: &Connection) -> Result<()> {
+    let installed: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'view' AND name = 'v_tx_with_block'",
+        [],
+        |r| r.get(0),
+    )?;
+    if installed {
+        install(conn)?;
+    }
+    Ok(())
+}